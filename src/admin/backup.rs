@@ -0,0 +1,260 @@
+//! Backup archives of proxy state.
+//!
+//! A backup is a `.tar.gz` holding everything needed to stand a proxy back up:
+//!
+//! - `manifest.json`: what the archive contains.
+//! - `prompts/...`: the prompts directory (workflows, tenant namespaces,
+//!   snippets, UI originals).
+//! - `config/negative_prompts.json`, `config/api_keys.json`: the files named
+//!   by `NEGATIVE_PROMPTS_FILE` / `API_KEYS_FILE`, when configured.
+//! - `config/seeds.json`: the seed journal (`SEEDS_FILE`), when it exists.
+//! - `jobs.db`: a snapshot of the job database (`JOBS_DB`), when configured.
+//! - `static/drive_index.json`: the static drive's output index, with the
+//!   metadata, thumbnails, and captions recorded for each file.
+//! - `history.json`: ComfyUI's `/history`, which the job store and output
+//!   index are rebuilt from via `POST /jobs/import`.
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use crate::config::Config;
+use crate::utils::static_drive_poller::INDEX_FILE;
+use crate::utils::time::now_ms;
+
+pub const MANIFEST_PATH: &str = "manifest.json";
+pub const HISTORY_PATH: &str = "history.json";
+const PROMPTS_PREFIX: &str = "prompts/";
+const NEGATIVE_PROMPTS_PATH: &str = "config/negative_prompts.json";
+const API_KEYS_PATH: &str = "config/api_keys.json";
+const SEEDS_PATH: &str = "config/seeds.json";
+const JOBS_DB_PATH: &str = "jobs.db";
+const DRIVE_INDEX_PATH: &str = "static/drive_index.json";
+const BACKUP_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Manifest {
+    pub version: u32,
+    pub created_at_ms: u64,
+    /// Files under the prompts directory, relative to it.
+    pub prompts: Vec<String>,
+    pub negative_prompts: bool,
+    pub api_keys: bool,
+    #[serde(default)]
+    pub seeds: bool,
+    #[serde(default)]
+    pub jobs_db: bool,
+    #[serde(default)]
+    pub drive_index: bool,
+    /// Number of ComfyUI history entries included, if history was backed up.
+    pub history_entries: Option<usize>,
+}
+
+/// Where the state lives on this instance.
+#[derive(Debug, Clone)]
+pub struct BackupSources {
+    pub prompts_dir: PathBuf,
+    pub negative_prompts_file: Option<PathBuf>,
+    pub api_keys_file: Option<PathBuf>,
+    pub seeds_file: Option<PathBuf>,
+    pub jobs_db: Option<PathBuf>,
+    pub drive_index: Option<PathBuf>,
+}
+
+impl BackupSources {
+    pub fn from_config(config: &Config) -> Self {
+        BackupSources {
+            prompts_dir: PathBuf::from(&config.prompts_dir),
+            negative_prompts_file: config.negative_prompts_file.as_ref().map(PathBuf::from),
+            api_keys_file: config.api_keys_file.as_ref().map(PathBuf::from),
+            seeds_file: Some(PathBuf::from(&config.seeds_file)),
+            jobs_db: config.jobs_db.as_ref().map(PathBuf::from),
+            drive_index: Some(Path::new(&config.static_drive_path).join(INDEX_FILE)),
+        }
+    }
+}
+
+/// Files under `dir`, relative to it, sorted.
+fn list_files(dir: &Path) -> Result<Vec<String>, String> {
+    let mut files = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(current) = stack.pop() {
+        let entries = std::fs::read_dir(&current).map_err(|e| format!("Failed to read {}: {}", current.display(), e))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                stack.push(path);
+            } else if let Ok(rel) = path.strip_prefix(dir) {
+                files.push(rel.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn append_bytes<W: std::io::Write>(tar: &mut tar::Builder<W>, path: &str, data: &[u8]) -> Result<(), String> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(now_ms() / 1000);
+    header.set_cksum();
+    tar.append_data(&mut header, path, data).map_err(|e| format!("Failed to add {} to backup: {}", path, e))
+}
+
+/// Write a backup of `sources` (and `history`, if given) to `out`.
+pub fn write_backup(out: &Path, sources: &BackupSources, history: Option<&Value>) -> Result<Manifest, String> {
+    let prompts = if sources.prompts_dir.is_dir() { list_files(&sources.prompts_dir)? } else { Vec::new() };
+    let existing = |p: &Option<PathBuf>| p.as_ref().filter(|p| p.is_file()).cloned();
+    let negative = existing(&sources.negative_prompts_file);
+    let api_keys = existing(&sources.api_keys_file);
+    let seeds = existing(&sources.seeds_file);
+    let jobs_db = existing(&sources.jobs_db);
+    let drive_index = existing(&sources.drive_index);
+    let manifest = Manifest {
+        version: BACKUP_VERSION,
+        created_at_ms: now_ms(),
+        prompts: prompts.clone(),
+        negative_prompts: negative.is_some(),
+        api_keys: api_keys.is_some(),
+        seeds: seeds.is_some(),
+        jobs_db: jobs_db.is_some(),
+        drive_index: drive_index.is_some(),
+        history_entries: history.map(|h| h.as_object().map(|m| m.len()).unwrap_or(0)),
+    };
+
+    let file = File::create(out).map_err(|e| format!("Failed to create {}: {}", out.display(), e))?;
+    let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    let manifest_json = serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?;
+    append_bytes(&mut tar, MANIFEST_PATH, &manifest_json)?;
+    for rel in &prompts {
+        let path = sources.prompts_dir.join(rel);
+        let data = std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        append_bytes(&mut tar, &format!("{}{}", PROMPTS_PREFIX, rel), &data)?;
+    }
+    for (path, name) in [(negative, NEGATIVE_PROMPTS_PATH), (api_keys, API_KEYS_PATH), (seeds, SEEDS_PATH), (drive_index, DRIVE_INDEX_PATH)] {
+        if let Some(path) = path {
+            let data = std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            append_bytes(&mut tar, name, &data)?;
+        }
+    }
+    if let Some(path) = jobs_db {
+        append_bytes(&mut tar, JOBS_DB_PATH, &snapshot_jobs_db(&path)?)?;
+    }
+    if let Some(history) = history {
+        let data = serde_json::to_vec(history).map_err(|e| e.to_string())?;
+        append_bytes(&mut tar, HISTORY_PATH, &data)?;
+    }
+    let encoder = tar.into_inner().map_err(|e| format!("Failed to write {}: {}", out.display(), e))?;
+    encoder.finish().map_err(|e| format!("Failed to write {}: {}", out.display(), e))?;
+    Ok(manifest)
+}
+
+/// The job database at `path` as one file. The proxy keeps it in WAL mode,
+/// so with the `sqlite` feature it's copied through SQLite (`VACUUM INTO`),
+/// which includes writes still in the log; otherwise the file is read as-is.
+fn snapshot_jobs_db(path: &Path) -> Result<Vec<u8>, String> {
+    #[cfg(feature = "sqlite")]
+    {
+        let copy = std::env::temp_dir().join(format!("jobs_backup_{}.db", uuid::Uuid::new_v4()));
+        crate::jobs::db::JobDb::snapshot(path, &copy)?;
+        let data = std::fs::read(&copy).map_err(|e| format!("Failed to read {}: {}", copy.display(), e));
+        std::fs::remove_file(&copy).ok();
+        data
+    }
+    #[cfg(not(feature = "sqlite"))]
+    std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+}
+
+/// Result of restoring a backup.
+#[derive(Debug)]
+pub struct Restored {
+    pub manifest: Manifest,
+    /// Files written, in archive order.
+    pub written: Vec<PathBuf>,
+    /// Archive entries with nowhere to go (e.g. API keys when `API_KEYS_FILE`
+    /// isn't set here).
+    pub skipped: Vec<String>,
+    /// ComfyUI history to feed to `POST /jobs/import`.
+    pub history: Option<Value>,
+}
+
+fn safe_relative(path: &str) -> bool {
+    !path.is_empty() && Path::new(path).components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Restore `archive` onto `sources`. Without `overwrite`, nothing is written
+/// if any target file already exists with different contents.
+pub fn restore_backup(archive: &Path, sources: &BackupSources, overwrite: bool) -> Result<Restored, String> {
+    let file = File::open(archive).map_err(|e| format!("Failed to open {}: {}", archive.display(), e))?;
+    let mut tar = tar::Archive::new(GzDecoder::new(file));
+    let mut manifest = None;
+    let mut history = None;
+    let mut targets: Vec<(PathBuf, Vec<u8>)> = Vec::new();
+    let mut skipped = Vec::new();
+    let entries = tar.entries().map_err(|e| format!("Failed to read {}: {}", archive.display(), e))?;
+    for entry in entries {
+        let mut entry = entry.map_err(|e| format!("Failed to read {}: {}", archive.display(), e))?;
+        let name = entry.path().map_err(|e| e.to_string())?.to_string_lossy().replace('\\', "/");
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).map_err(|e| format!("Failed to read {} from backup: {}", name, e))?;
+        let target = match name.as_str() {
+            MANIFEST_PATH => {
+                manifest = Some(serde_json::from_slice::<Manifest>(&data).map_err(|e| format!("Invalid manifest: {}", e))?);
+                continue;
+            }
+            HISTORY_PATH => {
+                history = Some(serde_json::from_slice::<Value>(&data).map_err(|e| format!("Invalid history.json: {}", e))?);
+                continue;
+            }
+            NEGATIVE_PROMPTS_PATH => sources.negative_prompts_file.clone(),
+            API_KEYS_PATH => sources.api_keys_file.clone(),
+            SEEDS_PATH => sources.seeds_file.clone(),
+            JOBS_DB_PATH => sources.jobs_db.clone(),
+            DRIVE_INDEX_PATH => sources.drive_index.clone(),
+            _ => match name.strip_prefix(PROMPTS_PREFIX) {
+                Some(rel) if safe_relative(rel) => Some(sources.prompts_dir.join(rel)),
+                Some(_) => return Err(format!("Refusing unsafe path '{}' in backup", name)),
+                None => None,
+            },
+        };
+        match target {
+            Some(target) => targets.push((target, data)),
+            None => skipped.push(name),
+        }
+    }
+    let manifest = manifest.ok_or_else(|| format!("{} has no {}; not a proxy backup", archive.display(), MANIFEST_PATH))?;
+    if manifest.version > BACKUP_VERSION {
+        return Err(format!("Backup version {} is newer than supported ({})", manifest.version, BACKUP_VERSION));
+    }
+
+    if !overwrite {
+        let conflicts: Vec<String> = targets.iter()
+            .filter(|(path, data)| std::fs::read(path).map(|existing| &existing != data).unwrap_or(false))
+            .map(|(path, _)| path.display().to_string())
+            .collect();
+        if !conflicts.is_empty() {
+            return Err(format!("Refusing to overwrite existing files (use --force): {}", conflicts.join(", ")));
+        }
+    }
+    let mut written = Vec::new();
+    for (path, data) in targets {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        std::fs::write(&path, data).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        if sources.jobs_db.as_ref() == Some(&path) {
+            // A leftover write-ahead log would be replayed over the restored
+            // database.
+            for suffix in ["-wal", "-shm"] {
+                let mut sidecar = path.clone().into_os_string();
+                sidecar.push(suffix);
+                std::fs::remove_file(&sidecar).ok();
+            }
+        }
+        written.push(path);
+    }
+    Ok(Restored { manifest, written, skipped, history })
+}
@@ -0,0 +1,36 @@
+//! Maintenance mode.
+//!
+//! While it's on, the proxy refuses new jobs with 503 and a `Retry-After`
+//! header but keeps serving reads and tracking jobs already queued, so the
+//! ComfyUI backend can be upgraded once in-flight work has drained.
+use serde::Serialize;
+
+use crate::error::AppError;
+
+/// `Retry-After` when the window has no expected end, or it has passed.
+pub const DEFAULT_RETRY_SECS: u64 = 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Maintenance {
+    pub since_ms: u64,
+    /// Shown to rejected clients instead of the default message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// When the operator expects to be done.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until_ms: Option<u64>,
+}
+
+impl Maintenance {
+    /// When rejected clients should try again.
+    pub fn retry_at_ms(&self, now: u64) -> u64 {
+        self.until_ms.filter(|&t| t > now).unwrap_or(now + DEFAULT_RETRY_SECS * 1000)
+    }
+
+    /// The error new submissions get while maintenance is on.
+    pub fn rejection(&self, now: u64) -> AppError {
+        let message = self.message.clone()
+            .unwrap_or_else(|| "The proxy is in maintenance mode and isn't accepting new jobs".to_string());
+        AppError::Maintenance { message, retry_at_ms: self.retry_at_ms(now) }
+    }
+}
@@ -0,0 +1,4 @@
+//! Administrative tasks: backups run from `comfyctl admin`, and maintenance
+//! mode toggled through `/admin/maintenance`.
+pub mod backup;
+pub mod maintenance;
@@ -0,0 +1,188 @@
+//! Range and conditional requests for served images and videos.
+//!
+//! Files fetched from ComfyUI are streamed. A `Range` is passed on to
+//! ComfyUI's `/view`, and its 206 sent through as-is; when ComfyUI sends
+//! the whole file instead, or the request has an `If-Range` that only the
+//! proxy's entity tag can settle, the range is cut from the stream here: the
+//! stream is read up to the range's start, and dropped once its end has been
+//! sent. One range per request is cut; a list of ranges gets the whole file,
+//! which HTTP allows. Static drive files are served by `ServeDir`, which
+//! handles their ranges and `If-Modified-Since`; they only get an `ETag`
+//! and 304s here.
+use axum::body::StreamBody;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures_util::StreamExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::api::middleware::if_none_match;
+use crate::comfyui::client::ImageStream;
+
+/// What a `Range` header asks of a body of known length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// No usable range: send everything.
+    Full,
+    /// Bytes `start..=end`.
+    Partial { start: u64, end: u64 },
+    /// Starts past the end of the body: 416.
+    Unsatisfiable,
+}
+
+/// Parse a `Range` value for a body of `length` bytes: `bytes=<a>-<b>`,
+/// `bytes=<a>-`, or `bytes=-<n>` (the last `n`). Malformed values and
+/// multiple ranges mean the whole body.
+pub fn parse_range(value: &str, length: u64) -> ByteRange {
+    let Some(spec) = value.trim().strip_prefix("bytes=").map(str::trim) else { return ByteRange::Full };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((first, last)) = spec.split_once('-').map(|(a, b)| (a.trim(), b.trim())) else { return ByteRange::Full };
+    let (start, end) = match (first.parse::<u64>(), last.parse::<u64>()) {
+        (Ok(start), Ok(end)) if start <= end => (start, end.min(length.saturating_sub(1))),
+        (Ok(start), Err(_)) if last.is_empty() => (start, length.saturating_sub(1)),
+        (Err(_), Ok(suffix)) if first.is_empty() => {
+            if suffix == 0 {
+                return ByteRange::Unsatisfiable;
+            }
+            (length.saturating_sub(suffix), length.saturating_sub(1))
+        }
+        _ => return ByteRange::Full,
+    };
+    if start >= length {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial { start, end }
+}
+
+/// Whether a `GET` with `headers` can be answered `304 Not Modified`.
+/// `If-None-Match` wins over `If-Modified-Since` when both are sent.
+pub fn is_not_modified(headers: &HeaderMap, etag: Option<&str>, last_modified: Option<SystemTime>) -> bool {
+    if headers.contains_key(header::IF_NONE_MATCH) {
+        return etag.map(|etag| if_none_match(headers, etag)).unwrap_or(false);
+    }
+    let since = headers.get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok());
+    match (since, last_modified) {
+        (Some(since), Some(modified)) => unix_secs(modified) <= unix_secs(since),
+        _ => false,
+    }
+}
+
+/// Whether a `Range` should be honoured given `If-Range`: only when the
+/// client's copy, named by a strong entity tag or a date, is still current.
+pub fn range_applies(headers: &HeaderMap, etag: Option<&str>, last_modified: Option<SystemTime>) -> bool {
+    let Some(value) = headers.get(header::IF_RANGE).and_then(|v| v.to_str().ok()).map(str::trim) else { return true };
+    if value.starts_with('"') {
+        return etag == Some(value);
+    }
+    match (httpdate::parse_http_date(value), last_modified) {
+        (Ok(date), Some(modified)) => unix_secs(date) == unix_secs(modified),
+        _ => false,
+    }
+}
+
+/// The `Range` to pass on to ComfyUI for `headers`. Not with `If-Range`,
+/// whose tag may be one the proxy made up, so the whole file is fetched and
+/// the range cut here once the tag has been checked.
+pub fn forwarded_range(headers: &HeaderMap) -> Option<&str> {
+    if headers.contains_key(header::IF_RANGE) {
+        return None;
+    }
+    headers.get(header::RANGE).and_then(|v| v.to_str().ok())
+}
+
+/// Entity tag for a file on disk, from its size and modification time.
+pub fn file_etag(meta: &std::fs::Metadata) -> Option<String> {
+    let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("\"{:x}-{:x}\"", modified.as_micros(), meta.len()))
+}
+
+/// Respond to `request` with `file`, honouring `Range`, `If-Range`,
+/// `If-None-Match`, and `If-Modified-Since`. `etag` and ComfyUI's
+/// `Last-Modified` are echoed on every response, including 304s.
+pub fn serve_stream(request: &HeaderMap, file: ImageStream, content_type: String, cache_control: &str, etag: Option<String>) -> Response {
+    let last_modified = file.last_modified.as_deref().and_then(|v| httpdate::parse_http_date(v).ok());
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_str(cache_control).unwrap_or(HeaderValue::from_static("no-cache")));
+    if let Some(value) = etag.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+        headers.insert(header::ETAG, value);
+    }
+    if let Some(value) = file.last_modified.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+        headers.insert(header::LAST_MODIFIED, value);
+    }
+    if is_not_modified(request, etag.as_deref(), last_modified) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+    if let Ok(value) = HeaderValue::from_str(&content_type) {
+        headers.insert(header::CONTENT_TYPE, value);
+    }
+    if let Some(value) = file.content_range.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+        // ComfyUI already cut the range.
+        headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        headers.insert(header::CONTENT_RANGE, value);
+        if let Some(length) = file.content_length {
+            headers.insert(header::CONTENT_LENGTH, length.into());
+        }
+        return (StatusCode::PARTIAL_CONTENT, headers, StreamBody::new(file.body)).into_response();
+    }
+    let Some(length) = file.content_length else {
+        // Without a length there's nothing to seek within.
+        return (headers, StreamBody::new(file.body)).into_response();
+    };
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    let range = request.get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|_| range_applies(request, etag.as_deref(), last_modified))
+        .map(|v| parse_range(v, length))
+        .unwrap_or(ByteRange::Full);
+    match range {
+        ByteRange::Full => {
+            headers.insert(header::CONTENT_LENGTH, length.into());
+            (headers, StreamBody::new(file.body)).into_response()
+        }
+        ByteRange::Partial { start, end } => {
+            headers.insert(header::CONTENT_LENGTH, (end - start + 1).into());
+            if let Ok(value) = HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, length)) {
+                headers.insert(header::CONTENT_RANGE, value);
+            }
+            (StatusCode::PARTIAL_CONTENT, headers, StreamBody::new(slice(file.body, start, end))).into_response()
+        }
+        ByteRange::Unsatisfiable => {
+            headers.remove(header::CONTENT_TYPE);
+            if let Ok(value) = HeaderValue::from_str(&format!("bytes */{}", length)) {
+                headers.insert(header::CONTENT_RANGE, value);
+            }
+            (StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response()
+        }
+    }
+}
+
+/// Bytes `start..=end` of `body`.
+fn slice<S>(body: S, start: u64, end: u64) -> impl futures_util::Stream<Item = reqwest::Result<hyper::body::Bytes>>
+where
+    S: futures_util::Stream<Item = reqwest::Result<hyper::body::Bytes>> + Unpin,
+{
+    futures_util::stream::unfold((body, 0u64), move |(mut body, mut offset)| async move {
+        while offset <= end {
+            let chunk = match body.next().await? {
+                Ok(chunk) => chunk,
+                Err(e) => return Some((Err(e), (body, u64::MAX))),
+            };
+            let chunk_start = offset;
+            offset += chunk.len() as u64;
+            if offset <= start {
+                continue;
+            }
+            let from = start.saturating_sub(chunk_start) as usize;
+            let to = ((end + 1).min(offset) - chunk_start) as usize;
+            return Some((Ok(chunk.slice(from..to)), (body, offset)));
+        }
+        None
+    })
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
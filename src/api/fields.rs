@@ -0,0 +1,118 @@
+//! `?fields=` response projection.
+//!
+//! List endpoints can return megabytes of JSON (history entries carry whole
+//! prompt graphs). `?fields=prompt_id,outputs.filename` keeps only the named
+//! paths of each item: segments are object keys, `*` matches every key, and
+//! arrays are projected element by element.
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+use crate::error::{AppError, AppResult};
+
+/// Parsed field paths, as a tree of keys. A node without children keeps the
+/// whole value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fields {
+    children: BTreeMap<String, Fields>,
+}
+
+impl Fields {
+    /// Parse a comma-separated list of dot-separated paths.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut fields = Fields::default();
+        for path in spec.split(',').map(str::trim) {
+            let segments: Vec<&str> = path.split('.').map(str::trim).collect();
+            if segments.iter().any(|s| s.is_empty()) {
+                return Err(format!("Invalid field path '{}'", path));
+            }
+            fields.insert(&segments);
+        }
+        Ok(fields)
+    }
+
+    fn insert(&mut self, path: &[&str]) {
+        let Some((first, rest)) = path.split_first() else { return };
+        match self.children.get_mut(*first) {
+            // Already kept whole.
+            Some(child) if child.children.is_empty() => {}
+            Some(child) if rest.is_empty() => child.children.clear(),
+            Some(child) => child.insert(rest),
+            None => {
+                let mut child = Fields::default();
+                child.insert(rest);
+                self.children.insert(first.to_string(), child);
+            }
+        }
+    }
+
+    /// The parts of `value` the paths name. Missing keys are left out;
+    /// scalars are kept as they are.
+    pub fn project(&self, value: &Value) -> Value {
+        if self.children.is_empty() {
+            return value.clone();
+        }
+        match value {
+            Value::Array(items) => Value::Array(items.iter().map(|v| self.project(v)).collect()),
+            Value::Object(map) => {
+                let mut out = Map::new();
+                if let Some(any) = self.children.get("*") {
+                    for (key, v) in map {
+                        out.insert(key.clone(), any.project(v));
+                    }
+                }
+                for (key, child) in self.children.iter().filter(|(k, _)| k.as_str() != "*") {
+                    if let Some(v) = map.get(key) {
+                        let projected = child.project(v);
+                        match out.get_mut(key) {
+                            Some(existing) => merge(existing, projected),
+                            None => {
+                                out.insert(key.clone(), projected);
+                            }
+                        }
+                    }
+                }
+                Value::Object(out)
+            }
+            other => other.clone(),
+        }
+    }
+}
+
+/// Fold `other` into `into`, where both are projections of the same value.
+fn merge(into: &mut Value, other: Value) {
+    match (into, other) {
+        (Value::Object(a), Value::Object(b)) => {
+            for (key, v) in b {
+                match a.get_mut(&key) {
+                    Some(existing) => merge(existing, v),
+                    None => {
+                        a.insert(key, v);
+                    }
+                }
+            }
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            for (existing, v) in a.iter_mut().zip(b) {
+                merge(existing, v);
+            }
+        }
+        (into, other) => *into = other,
+    }
+}
+
+/// The request's `?fields=`, if given.
+pub fn from_params(params: &std::collections::HashMap<String, String>) -> AppResult<Option<Fields>> {
+    params.get("fields")
+        .map(|spec| Fields::parse(spec).map_err(|message| AppError::InvalidField { field: "fields".to_string(), message }))
+        .transpose()
+}
+
+/// Project each item of the array at `body[key]`, leaving the rest of the
+/// body (totals and the like) alone.
+pub fn project_items(body: &mut Value, key: &str, fields: &Fields) {
+    if let Some(Value::Array(items)) = body.get_mut(key) {
+        for item in items.iter_mut() {
+            *item = fields.project(item);
+        }
+    }
+}
@@ -1,251 +1,3681 @@
 //! Axum request handlers for the HTTP API.
-use axum::{extract::{Query, State}, Json};
+use axum::{extract::{Extension, FromRequest, Multipart, Query, State}, Json};
+use base64::Engine;
 use axum::extract::Path;
-use axum::response::IntoResponse;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use futures_util::StreamExt;
+use tower::ServiceExt;
+use tower_http::services::ServeDir;
+use tokio::sync::broadcast::error::RecvError;
 use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 // use tokio::fs; // not needed in this module after refactor
 
+use crate::admin::maintenance::Maintenance;
+use crate::api::conditional;
+use crate::api::fields;
+use crate::api::middleware::{etag_for, ClientName};
+use crate::api::openapi;
 use crate::api::routes::AppState;
-use crate::utils::prompt_build::{resolve_prompt_root_from_payload, apply_overrides_from_payload, ensure_defaults_on_root, maybe_log_verbose};
+use crate::auth::ApiKey;
+use crate::error::{AppError, AppResult};
+use crate::events::{ProxyEvent, EVENT_TYPES};
+use crate::auth::quota::{Period, ANONYMOUS_USAGE};
+use crate::auth::rate::{RateLimited, QUEUED_RETRY_MS};
+use crate::jobs::events::{events_from_history_entry, JobEventKind};
+use crate::comfyui::client::ImageStream;
+use crate::comfyui::inputs;
+use crate::comfyui::object_info;
+use crate::comfyui::trash::OutputTrash;
+use crate::comfyui::types::{parse_history, HistoryEntry, NodeOutput, OutputImage, PromptResult, QueueStatus};
+use crate::comfyui::ws::ProgressEvent;
+use crate::config::Config;
+use crate::jobs::{history_records, FairQueue, Job, JobState, JobStore};
+use crate::jobs::limits::WorkflowLimits;
+use crate::prompt::validator::{validate_prompt_text, validate_text_params, TextValidationError};
+use crate::jobs::timing::{aggregate_node_stats, node_timings};
+use crate::workflow::bundle::{read_bundle, write_bundle};
+use crate::workflow::convert::{detect_format, to_api_graph, to_api_graph_with, validate_api_graph, WorkflowFormat};
+use crate::workflow::diff::{diff_inputs, InputChange};
+use crate::workflow::preflight::{self, PreflightReport};
+use crate::workflow::validate::validate_against_object_info;
+use crate::workflow::manager::{is_valid_workflow_name, resolve_workflow_dir, tenant_dir, WorkflowManager};
+use crate::auth::policy::enforce_filename_prefix;
+use crate::utils::animation::{assemble, AnimationFormat, AnimationOptions};
+use crate::utils::drive_actions::FileRecord;
+use crate::utils::harvest::{sidecar_for, OutputHarvester, HARVEST_DIR, SIDECAR_FILE};
+use crate::utils::grid_split::{cell_filename, GridSplit};
+use crate::utils::image_resize::{resize_upload, ResizeMode};
+use crate::utils::prompt_ops::{append_filename_suffix, latent_dimensions, load_image_ids, parse_value, set_latent_dimensions};
+use crate::utils::time::now_ms;
+use crate::utils::zip_stream::ZipStream;
+use crate::prompt::constructor::PromptConstructor;
+use crate::prompt::seeds::{primary_seed, seeds_in_graph, FavoriteSeed, FAVORITE_PREFIX};
+use crate::prompt::sweep::expand_sweep;
+use crate::prompt::variation::apply_variation;
+use crate::prompt::weights::apply_weight_normalization;
+use crate::utils::prompt_build::{apply_detail_faces, apply_disable_nodes, apply_group_toggles, expand_queue_batch, payload_has_overrides, split_combined_text, resolve_prompt_root_from_payload, apply_overrides_from_payload, apply_default_negative_from_payload, ensure_defaults_on_root, maybe_log_verbose};
 
 pub async fn root() -> &'static str {
     "ComfyUI API Proxy"
 }
 
+/// Queue a stored workflow or a prompt graph.
+///
+/// With `?dry_run=true` (or `"dry_run": true` in the payload), resolve and
+/// validate it and return the final graph and changed inputs without
+/// contacting ComfyUI.
+#[utoipa::path(
+    post, path = "/queue_prompt", tag = "prompts",
+    params(openapi::DryRunQuery, openapi::ClientHeader),
+    request_body = QueuePromptRequest,
+    responses(
+        (status = 200, description = "Queued, held (see held), or with dry_run, the prompt that would be queued", body = QueuePromptResult),
+        (status = 400, description = "Invalid params, workflow, or resolution", body = ErrorBody),
+        (status = 403, description = "Not allowed by the API key's policy", body = ErrorBody),
+        (status = 404, description = "Unknown workflow", body = ErrorBody),
+        (status = 429, description = "Quota or rate limit reached; see Retry-After", body = ErrorBody),
+        (status = 502, description = "ComfyUI refused the prompt or is unreachable", body = ErrorBody),
+        (status = 503, description = "Maintenance mode", body = ErrorBody),
+    )
+)]
 pub async fn queue_prompt(
     State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    client: Option<Extension<ClientName>>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
     Json(payload): Json<Value>,
-) -> Result<Json<Value>, String> {
-    // Resolve base {"prompt": {...}}
-    let mut root = resolve_prompt_root_from_payload(&payload, &state.prompts_dir).await?;
-    apply_overrides_from_payload(&mut root, &payload)?;
+) -> AppResult<Json<Value>> {
+    validate_text_params(&payload, &state.text_limits).map_err(text_rejection)?;
+    let dry_run_payload = match payload.get("dry_run") {
+        None => false,
+        Some(v) => v.as_bool().ok_or_else(|| AppError::InvalidField { field: "dry_run".to_string(), message: "'dry_run' must be a boolean".to_string() })?,
+    };
+    if dry_run_payload || params.get("dry_run").map(|v| v == "true" || v == "1").unwrap_or(false) {
+        return dry_run(&state, &payload, key.as_deref()).await.map(Json);
+    }
+    queue_job(&state, &payload, key.as_deref(), client_name(&client)).await.map(Json)
+}
+
+/// Queue several prompts at once, all or none.
+///
+/// See [`expand_queue_batch`] for the body. Every prompt is built and
+/// validated before any is queued, so one bad prompt rejects the whole batch;
+/// if queueing fails part-way, the prompts already queued are removed again.
+/// Returns the job ids in order, plus each prompt's `/queue_prompt` response.
+#[utoipa::path(
+    post, path = "/queue_batch", tag = "prompts",
+    params(openapi::DryRunQuery, openapi::ClientHeader),
+    request_body = QueueBatchRequest,
+    responses(
+        (status = 200, description = "Queued, in request order", body = QueueBatchResponse),
+        (status = 400, description = "A prompt is invalid; error.field starts with prompts[<index>]", body = ErrorBody),
+        (status = 429, description = "Quota or rate limit reached part-way; nothing stays queued", body = ErrorBody),
+        (status = 502, description = "ComfyUI refused a prompt or is unreachable", body = ErrorBody),
+        (status = 503, description = "Maintenance mode", body = ErrorBody),
+    )
+)]
+pub async fn queue_batch(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    client: Option<Extension<ClientName>>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+    Json(body): Json<Value>,
+) -> AppResult<Json<Value>> {
+    let specs = expand_queue_batch(&body).map_err(|message| AppError::InvalidField { field: "prompts".to_string(), message })?;
+    let dry_run = params.get("dry_run").map(|v| v == "true" || v == "1").unwrap_or(false);
+    queue_all(&state, &specs, key.as_deref(), client_name(&client), dry_run, "prompts").await.map(Json)
+}
+
+/// Queue one prompt per combination of swept params, all or none.
+///
+/// The sweep is expanded by [`expand_sweep`] and queued like `/queue_batch`,
+/// in order. The response adds each combination's swept `values` and filename
+/// `tag`.
+#[utoipa::path(
+    post, path = "/sweep", tag = "prompts",
+    params(openapi::DryRunQuery, openapi::ClientHeader),
+    request_body = SweepRequest,
+    responses(
+        (status = 200, description = "Queued, in combination order", body = SweepResponse),
+        (status = 400, description = "Nothing swept, too many combinations, or a combination is invalid (error.field starts with combinations[<index>])", body = ErrorBody),
+        (status = 429, description = "Quota or rate limit reached part-way; nothing stays queued", body = ErrorBody),
+        (status = 502, description = "ComfyUI refused a prompt or is unreachable", body = ErrorBody),
+        (status = 503, description = "Maintenance mode", body = ErrorBody),
+    )
+)]
+pub async fn sweep(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    client: Option<Extension<ClientName>>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+    Json(body): Json<Value>,
+) -> AppResult<Json<Value>> {
+    let points = expand_sweep(&body).map_err(AppError::BadRequest)?;
+    let specs: Vec<Value> = points.iter().map(|p| p.payload.clone()).collect();
+    let dry_run = params.get("dry_run").map(|v| v == "true" || v == "1").unwrap_or(false);
+    let mut response = queue_all(&state, &specs, key.as_deref(), client_name(&client), dry_run, "combinations").await?;
+    response["combinations"] = points.into_iter().map(|p| json!({"values": p.values, "tag": p.tag})).collect();
+    Ok(Json(response))
+}
+
+/// Build and validate every spec, then (unless `dry_run`) queue them in
+/// order, rolling back the ones already queued if one fails. Errors name
+/// the failing spec as `<label>[i]`.
+async fn queue_all(state: &AppState, specs: &[Value], key: Option<&ApiKey>, client: Option<&str>, dry_run_only: bool, label: &str) -> AppResult<Value> {
+    let mut dry_runs = Vec::with_capacity(specs.len());
+    for (i, spec) in specs.iter().enumerate() {
+        validate_text_params(spec, &state.text_limits).map_err(|e| in_batch(text_rejection(e), label, i))?;
+        dry_runs.push(dry_run(state, spec, key).await.map_err(|e| in_batch(e, label, i))?);
+    }
+    if dry_run_only {
+        return Ok(json!({"dry_run": true, "prompts": dry_runs}));
+    }
+    let mut queued: Vec<Value> = Vec::with_capacity(specs.len());
+    for (i, spec) in specs.iter().enumerate() {
+        match queue_job(state, spec, key, client).await {
+            Ok(response) => queued.push(response),
+            Err(e) => {
+                let job_ids: Vec<String> = queued.iter().filter_map(|r| r["job_id"].as_str().map(String::from)).collect();
+                let released = release_held(state, &job_ids).await;
+                // Held prompts may have been sent since they were queued.
+                let prompt_ids: Vec<String> = {
+                    let jobs = state.job_store.read().await;
+                    job_ids.iter().filter(|id| !released.contains(id))
+                        .filter_map(|id| jobs.get(id).and_then(|j| j.prompt_id.clone()))
+                        .collect()
+                };
+                roll_back_prompts(state, &prompt_ids).await;
+                return Err(in_batch(e, label, i));
+            }
+        }
+    }
+    let job_ids: Vec<Value> = queued.iter().map(|r| r["job_id"].clone()).collect();
+    Ok(json!({"status": "success", "total": queued.len(), "job_ids": job_ids, "jobs": queued}))
+}
+
+/// Point an error about the `index`th prompt of a batch at that prompt,
+/// e.g. `prompts[2]`.
+fn in_batch(e: AppError, label: &str, index: usize) -> AppError {
+    let at = format!("{}[{}]", label, index);
+    match e {
+        AppError::InvalidField { field, message } => AppError::InvalidField { field: format!("{}.{}", at, field), message },
+        AppError::BadRequest(message) => AppError::InvalidField { field: at, message },
+        AppError::NotFound(message) => AppError::NotFound(format!("{}: {}", at, message)),
+        AppError::Forbidden(message) => AppError::Forbidden(format!("{}: {}", at, message)),
+        other => other,
+    }
+}
+
+/// Take back prompts queued by a batch that failed part-way: pending ones
+/// are removed from the queue and running ones interrupted. Failures are
+/// logged; the batch's own error is what the caller sees.
+async fn roll_back_prompts(state: &AppState, prompt_ids: &[String]) {
+    if prompt_ids.is_empty() {
+        return;
+    }
+    let queue = match state.backends.get_queue().await {
+        Ok(queue) => queue,
+        Err(e) => {
+            tracing::warn!("Failed to roll back batch prompts {:?}: {}", prompt_ids, e);
+            return;
+        }
+    };
+    let pending: Vec<String> = prompt_ids.iter().filter(|id| queue.is_pending(id)).cloned().collect();
+    if !pending.is_empty() {
+        match state.backends.delete_from_queue(&pending).await {
+            Ok(_) => record_dequeued(state, &pending).await,
+            Err(e) => tracing::warn!("Failed to remove batch prompts {:?} from the queue: {}", pending, e),
+        }
+    }
+    for prompt_id in prompt_ids.iter().filter(|id| queue.is_running(id)) {
+        if let Err(e) = state.backends.interrupt(prompt_id).await {
+            tracing::warn!("Failed to interrupt batch prompt {}: {}", prompt_id, e);
+        }
+    }
+}
+
+fn text_rejection(e: TextValidationError) -> AppError {
+    AppError::InvalidField { message: e.to_string(), field: e.field.to_string() }
+}
+
+/// Workflow name a queue payload targets: its `workflow`, or
+/// `DEFAULT_WORKFLOW` when it carries no inline `prompt`. A `workflow` that
+/// isn't a plain name is rejected, so it can't reach another tenant's
+/// directory or leave `PROMPTS_DIR`.
+fn workflow_for(state: &AppState, payload: &Value) -> AppResult<Option<String>> {
+    let workflow = match payload.get("workflow").and_then(|v| v.as_str()) {
+        Some(name) => Some(name.to_string()),
+        None if payload.get("prompt").is_none() => state.default_workflow.clone(),
+        None => None,
+    };
+    if let Some(name) = payload.get("workflow").and_then(|v| v.as_str()).filter(|n| !is_valid_workflow_name(n)) {
+        return Err(AppError::InvalidField { field: "workflow".to_string(), message: format!("Invalid workflow name '{}'", name) });
+    }
+    Ok(workflow)
+}
+
+/// The `X-Client` a request named, if any.
+fn client_name(client: &Option<Extension<ClientName>>) -> Option<&str> {
+    client.as_ref().map(|c| c.0 .0.as_str())
+}
+
+/// Create a job for `payload`, submit it, and return ComfyUI's response with
+/// the proxy `job_id` added. `key` is the caller's API key, if auth is
+/// enabled, and `client` the app named by its `X-Client` header.
+async fn queue_job(state: &AppState, payload: &Value, key: Option<&ApiKey>, client: Option<&str>) -> AppResult<Value> {
+    if let Some(maintenance) = state.maintenance.read().await.as_ref() {
+        return Err(maintenance.rejection(now_ms()));
+    }
+    if let Some(key) = key.filter(|k| k.quota.is_limited()) {
+        sync_pending_usage(state, &key.id()).await;
+        key.quota.check(&*state.usage.read().await, &key.id(), now_ms())?;
+    }
+    let workflow = workflow_for(state, payload)?;
+    let split_grid = split_grid_from_payload(payload)?;
+    let key_id = key.map(ApiKey::id);
+    let usage_key = key_id.as_deref().unwrap_or(ANONYMOUS_USAGE);
+    let max_queued = key.map(|k| k.rate_limit.or(state.rate_limits)).unwrap_or(state.rate_limits).max_queued;
+    if let Some(max) = max_queued {
+        if queued_for(&*state.job_store.read().await, usage_key) >= max as usize {
+            // Jobs may have finished since they were last synced.
+            sync_pending_usage(state, usage_key).await;
+        }
+    }
+    let job_id = {
+        let mut jobs = state.job_store.write().await;
+        if let Some(max) = max_queued.filter(|max| queued_for(&jobs, usage_key) >= *max as usize) {
+            return Err(RateLimited {
+                message: format!("At most {} queued prompts are allowed per API key", max),
+                retry_at_ms: now_ms() + QUEUED_RETRY_MS,
+            }.into());
+        }
+        let id = jobs.create(workflow.clone());
+        if let Some(job) = jobs.get_mut(&id) {
+            job.key_id = key_id.clone();
+            job.tenant = key.and_then(|k| k.tenant()).map(String::from);
+            job.client = client.map(String::from);
+            job.split_grid = split_grid;
+            job.request = Some(payload.clone());
+        }
+        jobs.save(&id);
+        id
+    };
+    match (key, client) {
+        (Some(key), Some(client)) => tracing::info!("Queueing job {} for API key '{}' from client '{}'", job_id, key.label(), client),
+        (Some(key), None) => tracing::info!("Queueing job {} for API key '{}'", job_id, key.label()),
+        (None, Some(client)) => tracing::info!("Queueing job {} from client '{}'", job_id, client),
+        (None, None) => {}
+    }
+
+    let limited = workflow.as_deref().is_some_and(|w| state.workflow_limits.rule(w).is_some());
+    let result = async {
+        let built = build_prompt(state, payload, workflow.as_deref(), key).await?;
+        match &state.held_prompts {
+            Some(held) if held.depth.is_some() || limited => {
+                let prompt = HeldPrompt { built, workflow: workflow.clone() };
+                hold_prompt(state, prompt, &job_id, usage_key, key.and_then(|k| k.weight).unwrap_or(1)).await
+            }
+            _ => submit_prompt(state, built, &job_id).await,
+        }
+    }.await;
+    let mut jobs = state.job_store.write().await;
+    let mut response = match result {
+        Ok(mut response) => {
+            let expected = jobs.get(&job_id).map(|j| j.expected_outputs).unwrap_or(0);
+            let megapixel_steps = jobs.get(&job_id).map(|j| j.megapixel_steps).unwrap_or(0.0);
+            let estimated_cost = state.cost_model.estimate(megapixel_steps, workflow.as_deref(), jobs.iter());
+            if let Some(job) = jobs.get_mut(&job_id).filter(|_| estimated_cost.is_some()) {
+                job.estimated_cost = estimated_cost;
+                jobs.save(&job_id);
+            }
+            if let Some(prompt_id) = response.get("prompt_id").and_then(|v| v.as_str()) {
+                jobs.record(&job_id, JobEventKind::SentToBackend { prompt_id: prompt_id.to_string() });
+                state.metrics.prompt_queued();
+            }
+            if let Some(obj) = response.as_object_mut() {
+                if let Some(cost) = estimated_cost {
+                    obj.insert("estimated_cost".to_string(), json!({"amount": cost, "currency": state.cost_model.currency}));
+                }
+                let urls: Vec<String> = (0..expected).map(|i| output_url(&job_id, i)).collect();
+                obj.insert("outputs".to_string(), json!(urls));
+                obj.insert("job_id".to_string(), Value::String(job_id.clone()));
+            }
+            response
+        }
+        Err(e) => {
+            jobs.record(&job_id, JobEventKind::Failed { node: None, error: e.to_string() });
+            state.metrics.prompt_failed();
+            return Err(e);
+        }
+    };
+    drop(jobs);
+    if response.get("held").is_some() {
+        // Sent straight away when ComfyUI's queue and the workflow's limits allow.
+        dispatch_held_prompts(state).await;
+        let (prompt_id, busy) = {
+            let jobs = state.job_store.read().await;
+            let busy = workflow.as_deref().and_then(|w| state.workflow_limits.check(w, jobs.iter(), now_ms()).err());
+            (jobs.get(&job_id).and_then(|j| j.prompt_id.clone()), busy)
+        };
+        if let (Some(obj), Some(held)) = (response.as_object_mut(), &state.held_prompts) {
+            match prompt_id {
+                Some(prompt_id) => {
+                    obj.remove("held");
+                    obj.insert("prompt_id".to_string(), Value::String(prompt_id));
+                }
+                None => {
+                    let position = held.queue.lock().await.order().iter().position(|id| *id == job_id);
+                    obj.insert("position".to_string(), json!(position));
+                    if let Some(busy) = busy {
+                        obj.insert("reason".to_string(), Value::String(busy.message));
+                    }
+                }
+            }
+        }
+    }
+    Ok(response)
+}
+
+/// A prompt body ready to send, plus what building it changed.
+pub(crate) struct BuiltPrompt {
+    /// `{"prompt": graph}` body for ComfyUI.
+    root: Value,
+    /// The workflow graph as loaded, before params and overrides.
+    base: Value,
+    /// Inputs changed by the payload's params and `sets`, when it has any.
+    applied: Option<Vec<InputChange>>,
+    warnings: Vec<String>,
+}
+
+/// Resolve the workflow and apply params, overrides, defaults, and key
+/// policy, validating along the way. Does not contact ComfyUI.
+async fn build_prompt(state: &AppState, payload: &Value, workflow: Option<&str>, key: Option<&ApiKey>) -> AppResult<BuiltPrompt> {
+    // Resolve base {"prompt": {...}}; tenants load their own workflows first.
+    let tenant = key.and_then(|k| k.tenant());
+    let prompts_dir = resolve_workflow_dir(&state.prompts_dir, tenant, workflow);
+    let mut root = resolve_prompt_root_from_payload(payload, &prompts_dir, state.default_workflow.as_deref()).await?;
+    let mut warnings = apply_group_toggles(&mut root, payload).map_err(AppError::BadRequest)?;
+    let base = root.get("prompt").cloned().unwrap_or(Value::Null);
+    let mut payload = payload.clone();
+    state.seeds.read().await.resolve(&mut payload, tenant)
+        .map_err(|message| AppError::InvalidField { field: "seed".to_string(), message })?;
+    warnings.extend(state.resolution_rules.apply(&mut payload, root.get("prompt")).map_err(AppError::BadRequest)?);
+    let aspect = state.resolution_rules.aspect_dimensions(&payload, root.get("prompt")).map_err(AppError::BadRequest)?;
+    split_combined_text(&mut payload, &state.text_delimiter).map_err(AppError::BadRequest)?;
+    apply_weight_normalization(&mut payload).map_err(AppError::BadRequest)?;
+    let payload = &payload;
+    warnings.extend(apply_overrides_from_payload(&mut root, payload, &state.title_patterns).map_err(AppError::BadRequest)?);
+    if let (Some((w, h)), Some(graph)) = (aspect, root.get_mut("prompt")) {
+        set_latent_dimensions(graph, w, h);
+    }
+    if let Some(graph) = root.get_mut("prompt") {
+        apply_variation(payload, graph).map_err(AppError::BadRequest)?;
+    }
+    apply_detail_faces(&mut root, payload, &state.face_detailer_nodes).map_err(AppError::BadRequest)?;
+    warnings.extend(apply_disable_nodes(&mut root, payload).map_err(AppError::BadRequest)?);
+    let applied = payload_has_overrides(payload)
+        .then(|| diff_inputs(&base, root.get("prompt").unwrap_or(&Value::Null)));
+    apply_default_negative_from_payload(&mut root, payload, state.negative_prompts.for_workflow(workflow), &state.title_patterns);
     ensure_defaults_on_root(&mut root, payload.get("filename_prefix").and_then(|v| v.as_str()));
+    if let Some(suffix) = payload.get("filename_suffix") {
+        let suffix = suffix.as_str().filter(|s| !s.is_empty() && !s.contains(['/', '\\']))
+            .ok_or_else(|| AppError::InvalidField { field: "filename_suffix".to_string(), message: "'filename_suffix' must be a non-empty string without slashes".to_string() })?;
+        if let Some(graph) = root.get_mut("prompt") {
+            append_filename_suffix(graph, suffix);
+        }
+    }
+    if let (Some(key), Some(graph)) = (key, root.get_mut("prompt")) {
+        warnings.extend(key.policy.enforce(graph).map_err(AppError::Forbidden)?);
+        if let Some(tenant) = key.tenant() {
+            enforce_filename_prefix(graph, &format!("{}/", tenant))
+                .map_err(|message| AppError::InvalidField { field: "filename_prefix".to_string(), message })?;
+        }
+    }
     maybe_log_verbose(&root, payload.get("verbose").and_then(|v| v.as_bool()).unwrap_or(false));
+    if let Some(graph) = root.get("prompt") {
+        state.batch_limits.check(graph).map_err(AppError::BadRequest)?;
+    }
+    Ok(BuiltPrompt { root, base, applied, warnings })
+}
+
+async fn submit_prompt(state: &AppState, built: BuiltPrompt, job_id: &str) -> AppResult<Value> {
+    if let Some(graph) = built.root.get("prompt") {
+        state.job_store.write().await.set_node_classes(job_id, graph);
+    }
+
+    // Queue under the progress hub's client id so `/events/:prompt_id` sees it.
+    let mut root = built.root;
+    if let Some(obj) = root.as_object_mut() {
+        obj.entry("client_id").or_insert_with(|| Value::String(state.progress_hub.client_id().to_string()));
+    }
+    let mut response = state.backends.queue_prompt(root)
+        .await
+        .inspect_err(|e| tracing::error!("Failed to queue prompt: {:?}", e))?;
+    if let Some(applied) = built.applied {
+        response.extra.insert("applied".to_string(), json!(applied));
+    }
+    if !built.warnings.is_empty() {
+        response.extra.insert("warnings".to_string(), json!(built.warnings));
+    }
+    Ok(serde_json::to_value(response)?)
+}
+
+/// Prompts held for fair scheduling (`FAIR_QUEUE_DEPTH`) until ComfyUI's
+/// queue has room for them, or until their workflow's limits
+/// (`WORKFLOW_LIMITS`) allow them.
+pub(crate) struct HeldPrompts {
+    /// ComfyUI queue depth held prompts are sent into, if it's limited.
+    depth: Option<usize>,
+    queue: tokio::sync::Mutex<FairQueue<HeldPrompt>>,
+    /// Held for a whole dispatch, so two can't both fill the same free slots.
+    dispatching: tokio::sync::Mutex<()>,
+    /// Jobs taken off `queue` and being sent, with whether they were
+    /// released meanwhile.
+    sending: std::sync::Mutex<HashMap<String, bool>>,
+    /// When [`release_orphaned_prompts`] last ran.
+    orphans_checked_ms: AtomicU64,
+}
+
+/// A held prompt, and the workflow whose limits it waits on.
+pub(crate) struct HeldPrompt {
+    built: BuiltPrompt,
+    workflow: Option<String>,
+}
+
+impl HeldPrompts {
+    /// Held prompts when `FAIR_QUEUE_DEPTH` or any workflow limit is set.
+    pub(crate) fn from_config(config: &Config, limits: &WorkflowLimits) -> Option<Self> {
+        (config.fair_queue_depth.is_some() || !limits.is_empty()).then(|| HeldPrompts::new(config.fair_queue_depth))
+    }
+
+    pub(crate) fn new(depth: Option<usize>) -> Self {
+        HeldPrompts {
+            depth,
+            queue: tokio::sync::Mutex::new(FairQueue::new()),
+            dispatching: tokio::sync::Mutex::new(()),
+            sending: std::sync::Mutex::new(HashMap::new()),
+            orphans_checked_ms: AtomicU64::new(0),
+        }
+    }
+
+    fn sending(&self) -> std::sync::MutexGuard<'_, HashMap<String, bool>> {
+        self.sending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Held job ids in the order they'll be sent, after those being sent.
+    async fn job_ids(&self) -> Vec<String> {
+        let queue = self.queue.lock().await;
+        let mut ids: Vec<String> = self.sending().iter().filter(|(_, released)| !**released).map(|(id, _)| id.clone()).collect();
+        ids.extend(queue.order().into_iter().map(String::from));
+        ids
+    }
+}
+
+/// Hold a built prompt for fair scheduling or its workflow's limits
+/// instead of sending it. The response is `submit_prompt`'s without
+/// ComfyUI's fields, plus `held`.
+async fn hold_prompt(state: &AppState, prompt: HeldPrompt, job_id: &str, key: &str, weight: u32) -> AppResult<Value> {
+    let Some(held) = &state.held_prompts else { return submit_prompt(state, prompt.built, job_id).await };
+    let built = &prompt.built;
+    {
+        let mut jobs = state.job_store.write().await;
+        if let Some(graph) = built.root.get("prompt") {
+            jobs.set_node_classes(job_id, graph);
+        }
+        jobs.record(job_id, JobEventKind::Held { instance: state.instance_id.clone() });
+    }
+    let mut response = json!({"held": true});
+    if let Some(applied) = &built.applied {
+        response["applied"] = json!(applied);
+    }
+    if !built.warnings.is_empty() {
+        response["warnings"] = json!(built.warnings);
+    }
+    held.queue.lock().await.push(key, weight, job_id.to_string(), prompt);
+    Ok(response)
+}
+
+/// Send held prompts while ComfyUI has fewer than `FAIR_QUEUE_DEPTH`
+/// waiting and their workflows' limits allow, taking turns across keys.
+/// Runs after each prompt is held and, in the server, every second as
+/// ComfyUI's queue drains; embedders that set `FAIR_QUEUE_DEPTH` or
+/// `WORKFLOW_LIMITS` should call it periodically too.
+///
+/// The held queue is only locked to take the prompts to send, so holding,
+/// listing, and releasing prompts never wait on ComfyUI.
+pub async fn dispatch_held_prompts(state: &AppState) {
+    let Some(held) = &state.held_prompts else { return };
+    let _dispatching = held.dispatching.lock().await;
+    release_orphaned_prompts(state, held).await;
+    if held.queue.lock().await.is_empty() {
+        return;
+    }
+    let queried_ms = now_ms();
+    let comfy_queue = match state.backends.get_queue().await {
+        Ok(queue) => queue,
+        Err(e) => {
+            tracing::debug!("Not sending held prompts: {}", e);
+            return;
+        }
+    };
+    // Limited workflows' runs are counted from their jobs, so those ComfyUI has let go of must give up their slots first.
+    let limited = |job: &Job| job.workflow.as_deref().is_some_and(|w| state.workflow_limits.rule(w).is_some());
+    settle_unfinished(state, &comfy_queue, queried_ms, limited).await;
+    let mut runs: HashMap<String, (usize, Option<u64>)> = HashMap::new();
+    for job in state.job_store.read().await.iter().filter(|j| limited(j)) {
+        let (Some(workflow), Some(sent_at)) = (&job.workflow, job.sent_at_ms()) else { continue };
+        let run = runs.entry(workflow.clone()).or_default();
+        if !job.is_finished() {
+            run.0 += 1;
+        }
+        run.1 = run.1.max(Some(sent_at));
+    }
+    let room = held.depth.map(|depth| depth.saturating_sub(comfy_queue.pending.len()));
+    let now = now_ms();
+    let batch: Vec<(String, BuiltPrompt)> = {
+        let mut queue = held.queue.lock().await;
+        let mut batch = Vec::new();
+        while room.is_none_or(|room| batch.len() < room) {
+            let next = queue.pop_where(|prompt| prompt.workflow.as_deref().is_none_or(|w| {
+                let (active, last_sent_ms) = runs.get(w).copied().unwrap_or_default();
+                state.workflow_limits.allows(w, active, last_sent_ms, now).is_ok()
+            }));
+            let Some((job_id, prompt)) = next else { break };
+            if let Some(workflow) = prompt.workflow {
+                let run = runs.entry(workflow).or_default();
+                run.0 += 1;
+                run.1 = Some(now);
+            }
+            batch.push((job_id, prompt.built));
+        }
+        held.sending().extend(batch.iter().map(|(job_id, _)| (job_id.clone(), false)));
+        batch
+    };
+    for (job_id, built) in batch {
+        let result = submit_prompt(state, built, &job_id).await;
+        let released = held.sending().remove(&job_id).unwrap_or(false);
+        let mut jobs = state.job_store.write().await;
+        match result {
+            Ok(response) => {
+                let Some(prompt_id) = response.get("prompt_id").and_then(|v| v.as_str()) else { continue };
+                jobs.record(&job_id, JobEventKind::SentToBackend { prompt_id: prompt_id.to_string() });
+                state.metrics.prompt_queued();
+                if released {
+                    // Released while it was being sent; take it back.
+                    drop(jobs);
+                    roll_back_prompts(state, &[prompt_id.to_string()]).await;
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Failed to send held job {}: {}", job_id, e);
+                if !released {
+                    jobs.record(&job_id, JobEventKind::Failed { node: None, error: e.to_string() });
+                }
+                state.metrics.prompt_failed();
+            }
+        }
+    }
+}
+
+/// Why jobs whose held prompts were lost with their proxy instance failed.
+pub(crate) const LOST_HELD_PROMPT: &str = "The proxy holding the prompt stopped before sending it";
+
+/// Lease an instance renews while it may be holding prompts.
+fn holder_lease(instance: &str) -> String {
+    format!("held:{}", instance)
+}
+
+/// Renew this instance's holder lease, and fail the jobs held by instances
+/// whose lease ran out: they stopped, and their held prompts went with them.
+/// Runs at most every third of the lease TTL.
+async fn release_orphaned_prompts(state: &AppState, held: &HeldPrompts) {
+    let now = now_ms();
+    if now.saturating_sub(held.orphans_checked_ms.load(Ordering::Relaxed)) < state.lease_ttl_ms / 3 {
+        return;
+    }
+    held.orphans_checked_ms.store(now, Ordering::Relaxed);
+    let me = state.instance_id.as_str();
+    let (leases, holders) = {
+        let jobs = state.job_store.read().await;
+        (jobs.leases(), jobs.holders())
+    };
+    leases.claim(&holder_lease(me), me, state.lease_ttl_ms).await;
+    let mut gone = Vec::new();
+    for holder in holders {
+        // Taking a stopped instance's lease is harmless; it never comes back under that name.
+        if holder != me && leases.claim(&holder_lease(&holder), me, state.lease_ttl_ms).await {
+            gone.push(holder);
+        }
+    }
+    if gone.is_empty() {
+        return;
+    }
+    let gone: Vec<&str> = gone.iter().map(String::as_str).collect();
+    let failed = state.job_store.write().await.fail_held(&gone, LOST_HELD_PROMPT);
+    tracing::warn!("Failed {} job(s) held by stopped instances {:?}", failed, gone);
+}
+
+/// Stop holding the prompts of `job_ids`, marking those jobs failed.
+/// Returns the ids that were held. A prompt already being sent counts as
+/// released: it is taken back out of ComfyUI's queue once it arrives.
+async fn release_held(state: &AppState, job_ids: &[String]) -> Vec<String> {
+    let Some(held) = &state.held_prompts else { return Vec::new() };
+    let released: Vec<String> = {
+        let mut queue = held.queue.lock().await;
+        let mut sending = held.sending();
+        job_ids.iter()
+            .filter(|id| queue.remove(id).is_some() || sending.get_mut(id.as_str()).filter(|r| !**r).map(|r| *r = true).is_some())
+            .cloned()
+            .collect()
+    };
+    let mut jobs = state.job_store.write().await;
+    for id in &released {
+        jobs.record(id, JobEventKind::Failed { node: None, error: "Removed from queue".to_string() });
+    }
+    released
+}
+
+/// Build and validate `payload` exactly as queueing would, returning the
+/// final graph and the inputs that changed instead of sending it.
+async fn dry_run(state: &AppState, payload: &Value, key: Option<&ApiKey>) -> AppResult<Value> {
+    let workflow = workflow_for(state, payload)?;
+    split_grid_from_payload(payload)?;
+    let built = build_prompt(state, payload, workflow.as_deref(), key).await?;
+    dry_run_report(workflow, built)
+}
+
+/// The `dry_run` response for a built prompt.
+fn dry_run_report(workflow: Option<String>, built: BuiltPrompt) -> AppResult<Value> {
+    let graph = built.root.get("prompt").cloned().unwrap_or(Value::Null);
+    validate_api_graph(&graph).map_err(AppError::BadRequest)?;
+    Ok(json!({
+        "dry_run": true,
+        "workflow": workflow,
+        "prompt": graph,
+        "changes": diff_inputs(&built.base, &graph),
+        "applied": built.applied,
+        "warnings": built.warnings,
+    }))
+}
+
+fn split_grid_from_payload(payload: &Value) -> AppResult<Option<GridSplit>> {
+    GridSplit::from_payload(payload).map_err(|message| AppError::InvalidField { field: "split_grid".to_string(), message })
+}
+
+/// Fold the prompt's history entry into a job's timeline once ComfyUI has
+/// written it. Returns the history entry when the job has finished.
+async fn sync_job_history(state: &AppState, job_id: &str) -> Option<Value> {
+    let prompt_id = {
+        let jobs = state.job_store.read().await;
+        jobs.get(job_id)?.prompt_id.clone()?
+    };
+    match state.backends.get_prompt_history_raw(&prompt_id).await {
+        Ok(hist) => {
+            // History entries only appear once execution has ended.
+            let entry = hist.get(&prompt_id)?.clone();
+            fold_history_entry(state, job_id, &entry).await;
+            Some(entry)
+        }
+        Err(e) => {
+            tracing::warn!("Failed to sync history for job {}: {}", job_id, e);
+            None
+        }
+    }
+}
+
+/// Fold a finished prompt's history `entry` into job `job_id`'s timeline,
+/// once, and count its usage.
+async fn fold_history_entry(state: &AppState, job_id: &str, entry: &Value) {
+    let mut jobs = state.job_store.write().await;
+    // Saved once everything is set, so the completion hooks see it all.
+    let Some(job) = jobs.get_mut(job_id).filter(|j| !j.history_synced) else { return };
+    job.events.extend(events_from_history_entry(entry));
+    job.events.sort_by_key(|e| e.at_ms);
+    job.history_synced = true;
+    if job.state() == JobState::Failed {
+        state.metrics.prompt_failed();
+    }
+    let split_grid = job.split_grid.take().filter(|_| job.state() == JobState::Completed);
+    job.actual_cost = state.cost_model.actual(job);
+    let finished = job.clone();
+    jobs.save(job_id);
+    drop(jobs);
+    state.usage.write().await.record_job(&finished);
+    if let Some(split) = split_grid {
+        split_grid_outputs(state, job_id, split).await;
+    }
+}
+
+/// Fold in the history of every unsynced job matching `filter` that ComfyUI
+/// has finished, from one `/history` call per backend rather than one call
+/// per job.
+async fn sync_finished_jobs(state: &AppState, filter: impl Fn(&Job) -> bool) {
+    let pending: Vec<(String, String)> = state.job_store.read().await.iter()
+        .filter(|j| !j.history_synced && filter(j))
+        .filter_map(|j| j.prompt_id.clone().map(|prompt_id| (j.id.clone(), prompt_id)))
+        .collect();
+    if pending.is_empty() {
+        return;
+    }
+    let history = match state.backends.get_history_raw().await {
+        Ok(history) => history,
+        Err(e) => {
+            tracing::warn!("Failed to sync history for {} job(s): {}", pending.len(), e);
+            return;
+        }
+    };
+    for (job_id, prompt_id) in pending {
+        if let Some(entry) = history.get(&prompt_id) {
+            fold_history_entry(state, &job_id, entry).await;
+        }
+    }
+}
+
+/// Why jobs whose prompts ComfyUI dropped without running them failed.
+const LOST_PROMPT: &str = "ComfyUI no longer has the prompt queued or in its history";
+
+/// Settle unfinished jobs matching `filter` that ComfyUI has let go of:
+/// those sent before `queue` was fetched at `queried_ms` and no longer in
+/// it. Each one's history is folded in if ComfyUI has it; otherwise the
+/// prompt was lost (ComfyUI restarted, or its queue was cleared elsewhere)
+/// and the job is failed. Jobs whose history can't be fetched are left as
+/// they are.
+async fn settle_unfinished(state: &AppState, queue: &QueueStatus, queried_ms: u64, filter: impl Fn(&Job) -> bool) {
+    let gone: Vec<(String, String)> = state.job_store.read().await.iter()
+        .filter(|j| !j.is_finished() && !j.history_synced && filter(j))
+        .filter(|j| j.sent_at_ms().is_some_and(|at| at < queried_ms))
+        .filter_map(|j| j.prompt_id.clone().map(|prompt_id| (j.id.clone(), prompt_id)))
+        .filter(|(_, prompt_id)| !queue.is_running(prompt_id) && !queue.is_pending(prompt_id))
+        .collect();
+    for (job_id, prompt_id) in gone {
+        match state.backends.get_prompt_history_raw(&prompt_id).await {
+            Ok(history) => match history.get(&prompt_id) {
+                Some(entry) => fold_history_entry(state, &job_id, entry).await,
+                None => {
+                    tracing::warn!("Job {} failed: {}", job_id, LOST_PROMPT);
+                    state.job_store.write().await.record(&job_id, JobEventKind::Failed { node: None, error: LOST_PROMPT.to_string() });
+                    state.metrics.prompt_failed();
+                }
+            },
+            Err(e) => tracing::debug!("Not settling job {}: {}", job_id, e),
+        }
+    }
+}
+
+/// Follow ComfyUI's websocket and keep job timelines current while they run:
+/// node starts and finishes are recorded as ComfyUI reports them (history
+/// doesn't keep them), and a job's history is folded in as soon as its
+/// prompt is done. Runs until the progress hub closes; the server spawns it
+/// at startup.
+///
+/// Every replica follows the websocket for its own progress streams, but
+/// only the one holding the lease on the ComfyUI backend records, so shared
+/// jobs don't get each node twice. The others wait to take over.
+pub async fn follow_progress(state: &AppState) {
+    let lease = format!("progress:{}", state.comfyui_client.base_url());
+    loop {
+        let leases = state.job_store.read().await.leases();
+        if leases.hold(&lease, &state.instance_id, state.lease_ttl_ms, record_progress(state)).await.is_some() {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(state.lease_ttl_ms / 3)).await;
+    }
+}
+
+/// Record websocket events on job timelines until the hub closes.
+async fn record_progress(state: &AppState) {
+    let mut events = state.progress_hub.subscribe();
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                tracing::warn!("Job timelines missed {} websocket events", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        match event {
+            ProgressEvent::Executing { prompt_id: Some(prompt_id), node: Some(node) } => {
+                record_node_event(state, &prompt_id, JobEventKind::NodeStarted { node }).await;
+            }
+            ProgressEvent::Executed { prompt_id, node, .. } => {
+                record_node_event(state, &prompt_id, JobEventKind::NodeFinished { node }).await;
+            }
+            ProgressEvent::Executing { prompt_id: Some(prompt_id), node: None } => {
+                // The last node ends with the prompt, whatever history's clock says.
+                if let Some(node) = running_node(state, &prompt_id).await {
+                    record_node_event(state, &prompt_id, JobEventKind::NodeFinished { node }).await;
+                }
+                // ComfyUI writes the history entry before announcing the end.
+                let job_id = state.job_store.read().await.find_by_prompt_id(&prompt_id).map(|j| j.id.clone());
+                if let Some(job_id) = job_id {
+                    sync_job_history(state, &job_id).await;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Append a websocket-reported node event to the timeline of the job running
+/// `prompt_id`, unless its history has already been folded in.
+async fn record_node_event(state: &AppState, prompt_id: &str, kind: JobEventKind) {
+    let mut jobs = state.job_store.write().await;
+    let Some(job_id) = jobs.find_by_prompt_id(prompt_id).filter(|j| !j.history_synced).map(|j| j.id.clone()) else { return };
+    jobs.record(&job_id, kind);
+}
+
+/// The node `prompt_id`'s job last started and hasn't reported finishing.
+async fn running_node(state: &AppState, prompt_id: &str) -> Option<String> {
+    let jobs = state.job_store.read().await;
+    let job = jobs.find_by_prompt_id(prompt_id)?;
+    let (i, node) = job.events.iter().enumerate().rev().find_map(|(i, e)| match &e.kind {
+        JobEventKind::NodeStarted { node } => Some((i, node)),
+        _ => None,
+    })?;
+    let finished = job.events[i..].iter().any(|e| matches!(&e.kind, JobEventKind::NodeFinished { node: f } if f == node));
+    (!finished).then(|| node.clone())
+}
+
+/// `node` suffix recorded for the cells cut from a grid image.
+const GRID_CELL_SUFFIX: &str = ":cell";
+
+/// Cut each of a finished job's saved images (not temp previews) into grid
+/// cells, upload them next to the grid image, and record each as another
+/// output. Failures are logged; the grid images themselves stay available
+/// either way.
+async fn split_grid_outputs(state: &AppState, job_id: &str, split: GridSplit) {
+    let (prompt_id, grids): (Option<String>, Vec<crate::jobs::OutputFile>) = match state.job_store.read().await.get(job_id) {
+        Some(job) => (job.prompt_id.clone(), job.outputs().into_iter()
+            .filter(|o| o.folder_type.as_deref().unwrap_or("output") == "output" && content_type_for(&o.filename).starts_with("image/"))
+            .collect()),
+        None => return,
+    };
+    let prompt_id = prompt_id.as_deref();
+    for grid in grids {
+        let result: AppResult<()> = async {
+            let data = state.backends.get_image_in(prompt_id, &grid.filename, grid.subfolder.as_deref(), grid.folder_type.as_deref()).await?;
+            let cells = tokio::task::spawn_blocking(move || split.split(&data))
+                .await
+                .map_err(|e| AppError::Internal(e.to_string()))?
+                .map_err(AppError::Internal)?;
+            for (i, cell) in cells.into_iter().enumerate() {
+                let (row, col) = (i as u32 / split.cols, i as u32 % split.cols);
+                let filename = cell_filename(&grid.filename, row, col);
+                let uploaded = state.backends.upload_output(prompt_id, cell, &filename, grid.subfolder.as_deref()).await?;
+                state.job_store.write().await.record(job_id, JobEventKind::OutputSaved {
+                    node: format!("{}{}", grid.node, GRID_CELL_SUFFIX),
+                    filename: uploaded.name,
+                    subfolder: uploaded.subfolder,
+                    folder_type: Some("output".to_string()),
+                });
+            }
+            Ok(())
+        }.await;
+        if let Err(e) = result {
+            tracing::warn!("Failed to split grid image '{}' for job {}: {}", grid.filename, job_id, e);
+        }
+    }
+}
+
+/// Copy the outputs of jobs that have finished since the last pass onto the
+/// static drive (see [`crate::utils::harvest`]). Returns how many jobs were
+/// harvested. Stops early while ComfyUI is unreachable.
+///
+/// Replicas sharing a job backend take turns through a lease per ComfyUI
+/// backend, held for the whole pass (see [`crate::jobs::Leases::hold`]), so
+/// each job is downloaded once.
+pub async fn harvest_outputs(state: &AppState) -> usize {
+    let Some(harvester) = &state.harvester else { return 0 };
+    let lease = format!("harvest:{}", state.comfyui_client.base_url());
+    let leases = state.job_store.read().await.leases();
+    leases.hold(&lease, &state.instance_id, state.lease_ttl_ms, harvest_pass(state, harvester)).await.unwrap_or(0)
+}
+
+/// One pass of [`harvest_outputs`], run under its lease. Finished jobs'
+/// history comes from one `/history` call per backend, as in
+/// [`sync_finished_jobs`]; once no job before some point is left to check,
+/// the harvester's watermark moves up to it.
+async fn harvest_pass(state: &AppState, harvester: &OutputHarvester) -> usize {
+    // Jobs still to check, with when they were created. Unsent ones count
+    // until they fail, since they may yet be sent.
+    let waiting: Vec<(String, u64, bool)> = state.job_store.read().await.iter()
+        .filter(|j| j.created_at_ms >= harvester.since_ms() && !harvester.is_done(&j.id))
+        .filter(|j| j.prompt_id.is_some() || !j.is_finished())
+        .map(|j| (j.id.clone(), j.created_at_ms, j.prompt_id.is_some()))
+        .collect();
+    if waiting.is_empty() {
+        harvester.advance(u64::MAX, now_ms());
+        return 0;
+    }
+    let history = if waiting.iter().any(|(_, _, sent)| *sent) {
+        match state.backends.get_history_raw().await {
+            Ok(history) => history,
+            Err(e) => {
+                tracing::warn!("Output harvesting paused, ComfyUI history is unavailable: {}", e);
+                return 0;
+            }
+        }
+    } else {
+        Value::Null
+    };
+    let mut harvested = 0;
+    for (job_id, created_at_ms, _) in waiting.iter().filter(|(_, _, sent)| *sent) {
+        let (job_id, created_at_ms) = (job_id.as_str(), *created_at_ms);
+        let Some(prompt_id) = state.job_store.read().await.get(job_id).and_then(|j| j.prompt_id.clone()) else {
+            // Deleted since.
+            harvester.mark_done(job_id, created_at_ms);
+            continue;
+        };
+        let entry = history.get(&prompt_id).cloned();
+        if let Some(entry) = &entry {
+            fold_history_entry(state, job_id, entry).await;
+        }
+        let Some(job) = state.job_store.read().await.get(job_id).cloned() else { continue };
+        if state.output_trash.as_ref().is_some_and(|trash| trash.get(&prompt_id).is_some()) {
+            // Deleted before it was harvested.
+            harvester.mark_done(job_id, created_at_ms);
+            continue;
+        }
+        match job.state() {
+            JobState::Completed if !harvester.is_harvested(&prompt_id) => {}
+            JobState::Completed | JobState::Failed => {
+                harvester.mark_done(job_id, created_at_ms);
+                continue;
+            }
+            _ => continue,
+        }
+        let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+        let mut sources = Vec::new();
+        for output in job.outputs() {
+            let mut name = std::path::Path::new(&output.filename).file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| output.filename.clone());
+            if files.iter().any(|(existing, _)| *existing == name) {
+                name = format!("{}_{}", output.node.replace(':', "_"), name);
+            }
+            let mut source = json!({
+                "file": name,
+                "node": output.node,
+                "filename": output.filename,
+                "subfolder": output.subfolder,
+                "type": output.folder_type,
+            });
+            match state.backends.get_image_in(Some(&prompt_id), &output.filename, output.subfolder.as_deref(), output.folder_type.as_deref()).await {
+                Ok(data) => files.push((name, data)),
+                Err(AppError::HttpClient(e)) => {
+                    tracing::warn!("Output harvesting paused, ComfyUI is unreachable: {}", e);
+                    return harvested;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to harvest '{}' for job {}: {}", output.filename, job_id, e);
+                    source["file"] = Value::Null;
+                    source["error"] = json!(e.to_string());
+                }
+            }
+            sources.push(source);
+        }
+        let sidecar = json!({
+            "job_id": job.id,
+            "prompt_id": prompt_id,
+            "workflow": job.workflow,
+            "client": job.client,
+            "created_at_ms": job.created_at_ms,
+            "completed_at_ms": job.events.last().map(|e| e.at_ms),
+            "request": job.request,
+            "prompt": entry.as_ref().and_then(|e| e.get("prompt")).and_then(|p| p.get(2)),
+            "outputs": sources,
+        });
+        let root = harvester.dir_for(&prompt_id);
+        match harvester.store(&prompt_id, &files, &sidecar) {
+            Ok(written) => {
+                tracing::info!("Harvested {} output(s) of job {} into {}", written.len(), job_id, root.display());
+                harvester.mark_done(job_id, created_at_ms);
+                harvested += 1;
+            }
+            Err(e) => tracing::warn!("Failed to harvest job {}: {}", job_id, e),
+        }
+    }
+    let oldest_waiting = waiting.iter()
+        .filter(|(job_id, _, _)| !harvester.is_done(job_id))
+        .map(|(_, created_at_ms, _)| *created_at_ms)
+        .min();
+    harvester.advance(oldest_waiting.unwrap_or(u64::MAX), now_ms());
+    harvested
+}
+
+/// Pick up jobs other replicas saved to the shared job backend (see
+/// [`JobStore::refresh`]), counting the usage of those they finished. The
+/// server runs this every `JOBS_SYNC_SECS`.
+pub async fn refresh_jobs(state: &AppState) {
+    let refreshed = JobStore::refresh(&state.job_store).await;
+    if !refreshed.synced_elsewhere.is_empty() {
+        let mut usage = state.usage.write().await;
+        for job in &refreshed.synced_elsewhere {
+            usage.record_job(job);
+        }
+    }
+}
+
+/// Scan the static drive once (see
+/// [`crate::utils::static_drive_poller::StaticDrivePoller::poll_drive`]) and
+/// note on each harvested output's job where the `webhook` action delivered
+/// it and what the `caption` action captioned it, so the caption also shows
+/// on the job's output. The server runs this every `STATIC_DRIVE_POLL_SECS`. Replicas sharing
+/// a job backend (and, usually, the drive) take turns through a lease on
+/// the drive, so each file's actions run once.
+pub async fn poll_static_drive(state: &AppState) -> Vec<FileRecord> {
+    let poller = &state.static_drive_poller;
+    let lease = format!("drive:{}", poller.root().display());
+    let leases = state.job_store.read().await.leases();
+    let Some(processed) = leases.hold(&lease, &state.instance_id, state.lease_ttl_ms, poller.poll_drive()).await else {
+        return Vec::new();
+    };
+    for record in processed.iter().filter(|r| !r.path.ends_with(&format!("/{}", SIDECAR_FILE))) {
+        if record.webhook_url.is_none() && record.caption.is_none() {
+            continue;
+        }
+        let Some(sidecar) = sidecar_for(poller.root(), &record.path) else { continue };
+        let Some(job_id) = sidecar.get("job_id").and_then(|v| v.as_str()) else { continue };
+        let mut jobs = state.job_store.write().await;
+        if let Some(url) = &record.webhook_url {
+            jobs.record(job_id, JobEventKind::WebhookDelivered { url: url.clone() });
+        }
+        let name = record.path.rsplit('/').next().unwrap_or_default();
+        let source = sidecar.get("outputs").and_then(|o| o.as_array())
+            .and_then(|sources| sources.iter().find(|s| s.get("file").and_then(|f| f.as_str()) == Some(name)));
+        if let (Some(caption), Some(source)) = (&record.caption, source) {
+            let (Some(node), Some(filename)) = (source.get("node").and_then(|v| v.as_str()), source.get("filename").and_then(|v| v.as_str())) else { continue };
+            jobs.record(job_id, JobEventKind::Captioned { node: node.to_string(), filename: filename.to_string(), caption: caption.clone() });
+        }
+    }
+    processed
+}
+
+
+/// How many of `key`'s jobs are unfinished: held, or in ComfyUI as far as
+/// the proxy knows. [`sync_pending_usage`] settles the ones ComfyUI has let go of.
+fn queued_for(jobs: &JobStore, key: &str) -> usize {
+    jobs.iter().filter(|j| j.key_id.as_deref().unwrap_or(ANONYMOUS_USAGE) == key && !j.is_finished()).count()
+}
+
+/// Settle `key`'s jobs that are no longer in ComfyUI's queue (see
+/// [`settle_unfinished`]), so their usage is counted and they stop counting
+/// as queued. Jobs still queued or running aren't fetched.
+async fn sync_pending_usage(state: &AppState, key: &str) {
+    let queried_ms = now_ms();
+    match state.backends.get_queue().await {
+        Ok(queue) => {
+            settle_unfinished(state, &queue, queried_ms, |j| j.key_id.as_deref().unwrap_or(ANONYMOUS_USAGE) == key).await;
+        }
+        Err(e) => tracing::debug!("Not syncing usage for finished jobs: {}", e),
+    }
+}
+
+/// Usage and quota for the caller's API key in the current day and month.
+/// Without auth, reports usage for all requests.
+pub async fn usage(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+) -> Json<Value> {
+    let key_id = key.as_ref().map(|k| k.id());
+    let usage_key = key_id.as_deref().unwrap_or(ANONYMOUS_USAGE);
+    sync_pending_usage(&state, usage_key).await;
+    let now = now_ms();
+    let tracker = state.usage.read().await;
+    let period_json = |period: Period| {
+        let used = tracker.usage(usage_key, period, now);
+        let (start, end) = period.bounds(now);
+        let (images, gpu_seconds) = key.as_ref().map(|k| k.quota.limits(period)).unwrap_or((None, None));
+        json!({
+            "period_start_ms": start,
+            "resets_at_ms": end,
+            "images": used.images,
+            "gpu_seconds": used.gpu_ms as f64 / 1000.0,
+            "cost": state.cost_model.is_enabled().then_some(used.cost),
+            "limits": {"images": images, "gpu_seconds": gpu_seconds},
+        })
+    };
+    Json(json!({
+        "key": key.as_ref().map(|k| k.label()),
+        "currency": state.cost_model.is_enabled().then_some(&state.cost_model.currency),
+        "day": period_json(Period::Day),
+        "month": period_json(Period::Month),
+    }))
+}
+
+/// Event timeline for a job. Once ComfyUI has written the prompt's history
+/// entry, its execution milestones and outputs are folded into the timeline.
+pub async fn job_events(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(id): Path<String>,
+) -> AppResult<Json<Value>> {
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let synced = {
+        let jobs = state.job_store.read().await;
+        jobs.get(&id)
+            .filter(|j| j.visible_to(tenant))
+            .ok_or_else(|| AppError::NotFound(format!("Job '{}' not found", id)))?
+            .history_synced
+    };
+    if !synced {
+        sync_job_history(&state, &id).await;
+    }
+
+    let jobs = state.job_store.read().await;
+    let job = jobs.get(&id).ok_or_else(|| AppError::NotFound(format!("Job '{}' not found", id)))?;
+    Ok(Json(json!({
+        "job_id": job.id,
+        "prompt_id": job.prompt_id,
+        "events": job.events,
+        "outputs": {"expected": job.expected_outputs, "saved": job.saved_outputs()},
+        "node_timings": node_timings(job),
+    })))
+}
+
+/// Import a ComfyUI history dump (the `/history` object keyed by prompt id,
+/// or `{"history": {...}, "workflow": "<name>"}`) into the job store, so
+/// generations made outside the proxy get job ids and output URLs. Prompts
+/// that already have a job are skipped. Imported jobs belong to the caller's
+/// tenant.
+pub async fn import_jobs(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Json(body): Json<Value>,
+) -> AppResult<Json<Value>> {
+    let (history, workflow) = match body.get("history") {
+        Some(history) => (history, body.get("workflow").and_then(|v| v.as_str()).map(String::from)),
+        None => (&body, None),
+    };
+    let Some(entries) = history.as_object() else {
+        return Err(AppError::BadRequest("expected a ComfyUI history object keyed by prompt id".to_string()));
+    };
+    let tenant = key.as_ref().and_then(|k| k.tenant()).map(String::from);
+
+    let mut imported = serde_json::Map::new();
+    let mut skipped = Vec::new();
+    let mut invalid = Vec::new();
+    let mut jobs = state.job_store.write().await;
+    for (prompt_id, entry) in entries {
+        let looks_like_entry = entry.get("outputs").is_some() || entry.get("status").is_some() || entry.get("prompt").is_some();
+        if !looks_like_entry {
+            invalid.push(prompt_id.clone());
+            continue;
+        }
+        match jobs.import_history_entry(prompt_id, entry, workflow.clone(), tenant.clone()) {
+            Some(job_id) => { imported.insert(prompt_id.clone(), Value::String(job_id)); }
+            None => skipped.push(prompt_id.clone()),
+        }
+    }
+    tracing::info!("Imported {} history entries ({} already known, {} invalid)", imported.len(), skipped.len(), invalid.len());
+    Ok(Json(json!({
+        "imported": imported.len(),
+        "jobs": imported,
+        "skipped": skipped,
+        "invalid": invalid,
+    })))
+}
+
+/// One prompt's websocket events, turned into SSE events for its listeners.
+struct PromptProgress {
+    prompt_id: String,
+    job_id: Option<String>,
+    node_classes: std::collections::HashMap<String, String>,
+    filenames: Vec<String>,
+    done: bool,
+}
+
+impl PromptProgress {
+    fn sse(name: &str, data: Value) -> Event {
+        Event::default().event(name).data(data.to_string())
+    }
+
+    fn completed(&mut self) -> Event {
+        self.done = true;
+        Self::sse("completed", json!({"prompt_id": self.prompt_id, "job_id": self.job_id, "filenames": self.filenames}))
+    }
+
+    fn failed(&mut self, node: Option<String>, node_type: Option<String>, message: String) -> Event {
+        self.done = true;
+        Self::sse("error", json!({"prompt_id": self.prompt_id, "node": node, "node_type": node_type, "message": message}))
+    }
+
+    /// SSE event for a websocket event, if it belongs to this prompt and is
+    /// worth sending.
+    fn on_event(&mut self, event: ProgressEvent) -> Option<Event> {
+        if event.prompt_id() != Some(self.prompt_id.as_str()) {
+            return None;
+        }
+        match event {
+            ProgressEvent::Executing { node: Some(node), .. } => {
+                let class_type = self.node_classes.get(&node).cloned();
+                Some(Self::sse("executing", json!({"node": node, "class_type": class_type})))
+            }
+            ProgressEvent::Executing { node: None, .. } => Some(self.completed()),
+            ProgressEvent::Progress { node, value, max, .. } => {
+                let percent = (value * 100).checked_div(max).unwrap_or(0).min(100);
+                Some(Self::sse("progress", json!({"node": node, "value": value, "max": max, "percent": percent})))
+            }
+            ProgressEvent::Executed { output, .. } => {
+                let output: NodeOutput = serde_json::from_value(output).unwrap_or_default();
+                let files = output.images.iter().chain(&output.gifs).chain(&output.videos);
+                self.filenames.extend(files.map(|f| f.filename.clone()));
+                None
+            }
+            ProgressEvent::ExecutionError { node, node_type, message, .. } => Some(self.failed(node, node_type, message)),
+            ProgressEvent::Other { kind, .. } if kind == "execution_interrupted" => {
+                Some(self.failed(None, None, "interrupted".to_string()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// WebSocket stream of proxy-level events (job state changes, files indexed
+/// on the static drive, backend health), one JSON message per event, limited
+/// to what the caller's key may see. `?types=job_state,backend_health` keeps
+/// only those types.
+pub async fn proxy_events(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+    ws: WebSocketUpgrade,
+) -> AppResult<Response> {
+    let tenant = key.as_ref().and_then(|k| k.tenant()).map(String::from);
+    let types: Option<Vec<String>> = params.get("types")
+        .map(|t| t.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect());
+    if let Some(unknown) = types.iter().flatten().find(|t| !EVENT_TYPES.contains(&t.as_str())) {
+        return Err(AppError::InvalidField {
+            field: "types".to_string(),
+            message: format!("Unknown event type '{}'; expected one of {}", unknown, EVENT_TYPES.join(", ")),
+        });
+    }
+    let receiver = state.events.subscribe();
+    Ok(ws.on_upgrade(move |socket| forward_proxy_events(socket, receiver, tenant, types)))
+}
+
+async fn forward_proxy_events(
+    mut socket: WebSocket,
+    mut receiver: tokio::sync::broadcast::Receiver<ProxyEvent>,
+    tenant: Option<String>,
+    types: Option<Vec<String>>,
+) {
+    let wanted = |event: &ProxyEvent| {
+        event.visible_to(tenant.as_deref()) && types.as_ref().is_none_or(|t| t.iter().any(|t| t == event.kind.name()))
+    };
+    loop {
+        tokio::select! {
+            event = receiver.recv() => match event {
+                Ok(event) if wanted(&event) => {
+                    let Ok(text) = serde_json::to_string(&event) else { continue };
+                    if socket.send(Message::Text(text)).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => tracing::warn!("Proxy event listener missed {} events", missed),
+                Err(RecvError::Closed) => return,
+            },
+            // Client messages are ignored; a close or error ends the stream.
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+/// Server-Sent Events for a prompt's progress: `executing` (node and class),
+/// `progress` (steps and percent within a node), then `completed` with the
+/// output filenames or `error`. Prompts that already finished get their
+/// terminal event right away.
+pub async fn prompt_events(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(prompt_id): Path<String>,
+) -> Response {
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let (job_id, node_classes) = {
+        let jobs = state.job_store.read().await;
+        match jobs.find_by_prompt_id(&prompt_id) {
+            Some(job) if job.visible_to(tenant) => (Some(job.id.clone()), job.node_classes.clone()),
+            None if tenant.is_none() => (None, Default::default()),
+            _ => return AppError::NotFound(format!("Prompt '{}' not found", prompt_id)).into_response(),
+        }
+    };
+    // Subscribe before checking history so a prompt finishing in between
+    // isn't missed.
+    let receiver = state.progress_hub.subscribe();
+    let mut progress = PromptProgress { prompt_id: prompt_id.clone(), job_id, node_classes, filenames: Vec::new(), done: false };
+
+    let finished = match state.backends.get_prompt_history(&prompt_id).await {
+        Ok(entry) => entry.map(|entry| PromptResult::from_entry(&prompt_id, &entry)),
+        Err(e) => {
+            tracing::warn!("Failed to check history for prompt {}: {}", prompt_id, e);
+            None
+        }
+    };
+    let events: futures_util::stream::BoxStream<'static, Result<Event, std::convert::Infallible>> = match finished {
+        Some(result) if !result.success => {
+            let event = progress.failed(None, None, result.error.unwrap_or_default());
+            futures_util::stream::iter([Ok(event)]).boxed()
+        }
+        Some(result) => {
+            progress.filenames = result.images.into_iter().map(|o| o.filename).collect();
+            futures_util::stream::iter([Ok(progress.completed())]).boxed()
+        }
+        None => futures_util::stream::unfold((receiver, progress), |(mut receiver, mut progress)| async move {
+            if progress.done {
+                return None;
+            }
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => {
+                        if let Some(sse) = progress.on_event(event) {
+                            return Some((Ok(sse), (receiver, progress)));
+                        }
+                    }
+                    Err(RecvError::Lagged(missed)) => tracing::warn!("SSE listener for {} missed {} events", progress.prompt_id, missed),
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }).boxed(),
+    };
+    Sse::new(events).keep_alive(KeepAlive::default()).into_response()
+}
+
+/// Longest `?wait=` accepted by `GET /jobs/:id`.
+const MAX_LONG_POLL_SECS: u64 = 60;
+/// How often a long poll re-checks ComfyUI while waiting.
+const LONG_POLL_INTERVAL_MS: u64 = 1000;
+
+fn job_summary(job: &Job) -> Value {
+    let files: Vec<Value> = job.outputs().into_iter().enumerate()
+        .map(|(i, out)| json!({
+            "filename": out.filename,
+            "subfolder": out.subfolder,
+            "node": out.node,
+            "caption": out.caption,
+            "url": output_url(&job.id, i as u64),
+        }))
+        .collect();
+    json!({
+        "job_id": job.id,
+        "prompt_id": job.prompt_id,
+        "workflow": job.workflow,
+        "client": job.client,
+        "created_at_ms": job.created_at_ms,
+        "state": job.state(),
+        "outputs": {"expected": job.expected_outputs, "saved": job.saved_outputs(), "files": files},
+        "cost": {"estimated": job.estimated_cost, "actual": job.actual_cost},
+    })
+}
+
+/// Default and maximum page size for `GET /jobs`.
+const DEFAULT_JOB_LIST_LIMIT: usize = 50;
+const MAX_JOB_LIST_LIMIT: usize = 500;
+
+/// Jobs visible to the caller, newest first. `?state=` (or `?status=`)
+/// keeps jobs in one state; `?since=` keeps jobs created at or after a Unix
+/// time in milliseconds; `?client=` keeps jobs submitted with that
+/// `X-Client`; `?limit=` caps the count (default 50, max 500); `?fields=`
+/// trims each job to the named fields.
+pub async fn list_jobs(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+) -> AppResult<Json<Value>> {
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let fields = fields::from_params(&params)?;
+    let wanted = match params.get("state").or_else(|| params.get("status")) {
+        Some(s) => match JobState::parse(s) {
+            Some(s) => Some(s),
+            None => return Err(AppError::BadRequest(format!("Unknown job state '{}'", s))),
+        },
+        None => None,
+    };
+    let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+        None => DEFAULT_JOB_LIST_LIMIT,
+        Some(Ok(n)) => n.min(MAX_JOB_LIST_LIMIT),
+        Some(Err(_)) => return Err(AppError::BadRequest("'limit' must be a number".to_string())),
+    };
+    let since = match params.get("since").map(|s| s.parse::<u64>()) {
+        None => 0,
+        Some(Ok(ms)) => ms,
+        Some(Err(_)) => return Err(AppError::BadRequest("'since' must be a Unix time in milliseconds".to_string())),
+    };
+
+    sync_finished_jobs(&state, |j| j.visible_to(tenant)).await;
+
+    let jobs = state.job_store.read().await;
+    let mut visible: Vec<&Job> = jobs.iter()
+        .filter(|j| j.visible_to(tenant))
+        .filter(|j| wanted.map(|s| j.state() == s).unwrap_or(true))
+        .filter(|j| j.created_at_ms >= since)
+        .filter(|j| params.get("client").is_none_or(|c| j.client.as_ref() == Some(c)))
+        .collect();
+    visible.sort_by(|a, b| b.created_at_ms.cmp(&a.created_at_ms).then_with(|| a.id.cmp(&b.id)));
+    let total = visible.len();
+    let items: Vec<Value> = visible.into_iter().take(limit).map(job_summary).collect();
+    let mut body = json!({"total": total, "jobs": items});
+    if let Some(fields) = fields {
+        fields::project_items(&mut body, "jobs", &fields);
+    }
+    Ok(Json(body))
+}
+
+/// Job status, optionally long-polling for a change.
+///
+/// With `?wait=N` (seconds, up to 60), holds the request until the state
+/// differs from `?state=` (default: the state at request time) or the wait
+/// runs out; `changed` tells which happened.
+#[utoipa::path(
+    get, path = "/jobs/{id}", tag = "jobs",
+    params(
+        ("id" = String, Path, description = "Job id from the queue response"),
+        ("wait" = Option<u64>, Query, description = "Seconds (up to 60) to wait for the state to change", minimum = 0, maximum = 60),
+        ("state" = Option<JobState>, Query, description = "State to wait for a change from"),
+    ),
+    responses(
+        (status = 200, description = "Job status", body = JobStatus),
+        (status = 404, description = "Unknown job", body = ErrorBody),
+    )
+)]
+pub async fn job_status(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(id): Path<String>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+) -> AppResult<Json<Value>> {
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let not_found = || AppError::NotFound(format!("Job '{}' not found", id));
+    let wait_secs = match params.get("wait").map(|w| w.parse::<u64>()) {
+        None => 0,
+        Some(Ok(secs)) => secs.min(MAX_LONG_POLL_SECS),
+        Some(Err(_)) => return Err(AppError::BadRequest("'wait' must be a number of seconds".to_string())),
+    };
+    let current = |jobs: &JobStore| jobs.get(&id).filter(|j| j.visible_to(tenant)).map(|j| j.state());
+    let Some(initial) = current(&*state.job_store.read().await) else { return Err(not_found()) };
+    let since = match params.get("state") {
+        Some(s) => match JobState::parse(s) {
+            Some(s) => s,
+            None => return Err(AppError::BadRequest(format!("Unknown job state '{}'", s))),
+        },
+        None => initial,
+    };
+
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(wait_secs);
+    loop {
+        if !state.job_store.read().await.get(&id).map(|j| j.history_synced).unwrap_or(true) {
+            sync_job_history(&state, &id).await;
+        }
+        let now_state = current(&*state.job_store.read().await);
+        let changed = now_state.map(|s| s != since).unwrap_or(false);
+        if changed || wait_secs == 0 || tokio::time::Instant::now() >= deadline {
+            let jobs = state.job_store.read().await;
+            let job = jobs.get(&id).ok_or_else(not_found)?;
+            let mut body = job_summary(job);
+            body["changed"] = Value::Bool(changed);
+            return Ok(Json(body));
+        }
+        let next = tokio::time::Instant::now() + std::time::Duration::from_millis(LONG_POLL_INTERVAL_MS);
+        tokio::time::sleep_until(next.min(deadline)).await;
+    }
+}
+
+/// Remove a waiting job from ComfyUI's queue, or interrupt it if running.
+///
+/// Finished jobs can't be cancelled.
+#[utoipa::path(
+    post, path = "/jobs/{id}/cancel", tag = "jobs",
+    params(("id" = String, Path, description = "Job id")),
+    responses(
+        (status = 200, description = "Cancelled", body = JobCancelled),
+        (status = 404, description = "Unknown job", body = ErrorBody),
+        (status = 409, description = "The job has already finished", body = ErrorBody),
+    )
+)]
+pub async fn cancel_job(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(id): Path<String>,
+) -> AppResult<Json<Value>> {
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let finished = {
+        let jobs = state.job_store.read().await;
+        let job = jobs.get(&id).filter(|j| j.visible_to(tenant))
+            .ok_or_else(|| AppError::NotFound(format!("Job '{}' not found", id)))?;
+        job.is_finished()
+    };
+    if !finished && !release_held(&state, std::slice::from_ref(&id)).await.is_empty() {
+        return Ok(Json(json!({"status": "success", "job_id": id, "action": "dequeued"})));
+    }
+    let prompt_id = state.job_store.read().await.get(&id).and_then(|j| j.prompt_id.clone());
+    let Some(prompt_id) = prompt_id.filter(|_| !finished) else {
+        return Err(AppError::Conflict(format!("Job '{}' has already finished", id)));
+    };
+    let queue = state.backends.get_queue().await?;
+    let action = if queue.is_running(&prompt_id) {
+        state.backends.interrupt(&prompt_id).await?;
+        "interrupted"
+    } else if queue.is_pending(&prompt_id) {
+        let ids = vec![prompt_id];
+        state.backends.delete_from_queue(&ids).await?;
+        record_dequeued(&state, &ids).await;
+        "dequeued"
+    } else {
+        sync_job_history(&state, &id).await;
+        return Err(AppError::Conflict(format!("Job '{}' is no longer queued or running", id)));
+    };
+    Ok(Json(json!({"status": "success", "job_id": id, "action": action})))
+}
+
+/// Queue a job's original request again as a new job.
+///
+/// The new job is queued under the caller's key, and the request is checked
+/// against the current prompt text limits as `/queue_prompt` would. Jobs
+/// imported from history have no request to repeat.
+#[utoipa::path(
+    post, path = "/jobs/{id}/rerun", tag = "jobs",
+    params(("id" = String, Path, description = "Job id")),
+    responses(
+        (status = 200, description = "Queued; rerun_of names the original job", body = QueuePromptResponse),
+        (status = 400, description = "The request's prompt text exceeds the current limits", body = ErrorBody),
+        (status = 404, description = "Unknown job", body = ErrorBody),
+        (status = 410, description = "The job has no request to rerun (imported from history)", body = ErrorBody),
+        (status = 429, description = "Quota or rate limit reached; see Retry-After", body = ErrorBody),
+    )
+)]
+pub async fn rerun_job(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    client: Option<Extension<ClientName>>,
+    Path(id): Path<String>,
+) -> AppResult<Json<Value>> {
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let request = {
+        let jobs = state.job_store.read().await;
+        let job = jobs.get(&id).filter(|j| j.visible_to(tenant))
+            .ok_or_else(|| AppError::NotFound(format!("Job '{}' not found", id)))?;
+        job.request.clone().ok_or_else(|| AppError::Gone(format!("Job '{}' has no request to rerun", id)))?
+    };
+    validate_text_params(&request, &state.text_limits).map_err(text_rejection)?;
+    let mut response = queue_job(&state, &request, key.as_deref(), client_name(&client)).await?;
+    response["rerun_of"] = Value::String(id);
+    Ok(Json(response))
+}
+
+/// Save the seeds a finished job ran with as a named favorite.
+///
+/// Body: `{"name"?: "...", "output"?: <index>}`. The name defaults to the
+/// job id and must be alphanumeric, `_` or `-`; `output` picks which output
+/// was liked (the first by default). Seeds come from the graph ComfyUI
+/// recorded in its history, so imported jobs can be favorited too.
+#[utoipa::path(
+    post, path = "/jobs/{id}/favorite", tag = "jobs",
+    params(("id" = String, Path, description = "Job id")),
+    request_body(content = Option<FavoriteRequest>),
+    responses(
+        (status = 200, description = "Saved, replacing a favorite of the same name", body = FavoriteSaved),
+        (status = 400, description = "Invalid name or output index", body = ErrorBody),
+        (status = 404, description = "Unknown job", body = ErrorBody),
+        (status = 409, description = "The job hasn't finished or ran no node with a seed", body = ErrorBody),
+    )
+)]
+pub async fn favorite_job(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(id): Path<String>,
+    body: Option<Json<Value>>,
+) -> AppResult<Json<Value>> {
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let body = body.map(|Json(b)| b).unwrap_or(Value::Null);
+    let name = match body.get("name") {
+        None | Some(Value::Null) => id.clone(),
+        Some(Value::String(name)) if is_valid_workflow_name(name) => name.clone(),
+        Some(_) => return Err(AppError::InvalidField {
+            field: "name".to_string(),
+            message: "'name' must be non-empty and only contain alphanumerics, '_' or '-'".to_string(),
+        }),
+    };
+    let index = match body.get("output") {
+        None | Some(Value::Null) => 0,
+        Some(v) => v.as_u64().ok_or_else(|| AppError::InvalidField {
+            field: "output".to_string(),
+            message: "'output' must be a non-negative integer".to_string(),
+        })?,
+    };
+    let (prompt_id, workflow) = {
+        let jobs = state.job_store.read().await;
+        let job = jobs.get(&id).filter(|j| j.visible_to(tenant))
+            .ok_or_else(|| AppError::NotFound(format!("Job '{}' not found", id)))?;
+        (job.prompt_id.clone(), job.workflow.clone())
+    };
+    let prompt_id = prompt_id.ok_or_else(|| AppError::Conflict(format!("Job '{}' was never queued", id)))?;
+    let entry = sync_job_history(&state, &id).await
+        .ok_or_else(|| AppError::Conflict(format!("Job '{}' has not finished", id)))?;
+    let graph = entry.get("prompt").and_then(|p| p.get(2)).cloned().unwrap_or_default();
+    let seeds = seeds_in_graph(&graph);
+    let seed = primary_seed(&graph, &seeds).ok_or_else(|| AppError::Conflict(format!("Job '{}' ran no node with a seed", id)))?;
+    let output = {
+        let jobs = state.job_store.read().await;
+        let outputs = jobs.get(&id).map(|j| j.outputs()).unwrap_or_default();
+        if !outputs.is_empty() && index as usize >= outputs.len() {
+            return Err(AppError::InvalidField {
+                field: "output".to_string(),
+                message: format!("Job '{}' has {} output(s)", id, outputs.len()),
+            });
+        }
+        outputs.into_iter().nth(index as usize).map(|o| o.filename)
+    };
+    let favorite = FavoriteSeed {
+        name,
+        seed,
+        seeds,
+        job_id: id,
+        prompt_id: Some(prompt_id),
+        workflow,
+        output,
+        tenant: tenant.map(str::to_string),
+        saved_at_ms: now_ms(),
+    };
+    state.seeds.write().await.save(favorite.clone()).map_err(AppError::Internal)?;
+    Ok(Json(json!({"status": "success", "favorite": favorite})))
+}
+
+/// The caller's favorite seeds, newest first.
+#[utoipa::path(
+    get, path = "/seeds", tag = "jobs",
+    responses((status = 200, description = "Favorites", body = FavoriteList))
+)]
+pub async fn list_seeds(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+) -> Json<Value> {
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    Json(json!({"favorites": state.seeds.read().await.list(tenant)}))
+}
+
+/// Forget a favorite seed.
+#[utoipa::path(
+    delete, path = "/seeds/{name}", tag = "jobs",
+    params(("name" = String, Path, description = "Favorite name")),
+    responses(
+        (status = 200, description = "Deleted"),
+        (status = 404, description = "Unknown favorite", body = ErrorBody),
+    )
+)]
+pub async fn delete_seed(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(name): Path<String>,
+) -> AppResult<Json<Value>> {
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    if !state.seeds.write().await.remove(tenant, &name).map_err(AppError::Internal)? {
+        return Err(AppError::NotFound(format!("Favorite seed '{}' not found", name)));
+    }
+    Ok(Json(json!({"status": "success", "name": name})))
+}
+
+/// Stable URL for a job's `index`th output.
+fn output_url(job_id: &str, index: u64) -> String {
+    format!("/jobs/{}/outputs/{}", job_id, index)
+}
+
+fn content_type_for(filename: &str) -> &'static str {
+    match filename.rsplit('.').next().map(|e| e.to_ascii_lowercase()).as_deref() {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("webp") => "image/webp",
+        Some("gif") => "image/gif",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Serve a job's `index`th output once it exists. Returns 404 while the
+/// output is pending (or never will exist) and 410 once the job has failed
+/// without producing it, so clients can poll until 200.
+pub async fn job_output(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path((id, index)): Path<(String, u64)>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let not_found = || AppError::NotFound(format!("Job '{}' not found", id));
+    let synced = {
+        let jobs = state.job_store.read().await;
+        jobs.get(&id).filter(|j| j.visible_to(tenant)).ok_or_else(not_found)?.history_synced
+    };
+    if !synced {
+        sync_job_history(&state, &id).await;
+    }
+
+    let (output, prompt_id, failed, finished, expected) = {
+        let jobs = state.job_store.read().await;
+        let job = jobs.get(&id).ok_or_else(not_found)?;
+        (job.outputs().into_iter().nth(index as usize), job.prompt_id.clone(), job.is_failed(), job.is_finished(), job.expected_outputs)
+    };
+    let Some(output) = output else {
+        if failed {
+            return Err(AppError::Gone(format!("Job '{}' failed", id)));
+        }
+        if index >= expected || finished {
+            return Err(AppError::NotFound(format!("Job '{}' has no output {}", id, index)));
+        }
+        return Err(AppError::NotFound(format!("Output {} of job '{}' is not ready yet", index, id)));
+    };
+    if let Some(prompt_id) = &prompt_id {
+        check_not_trashed(&state, prompt_id)?;
+    }
+    let range = conditional::forwarded_range(&headers);
+    let file = state.backends.get_image_stream_in(prompt_id.as_deref(), &output.filename, output.subfolder.as_deref(), output.folder_type.as_deref(), range).await?;
+    let response = serve_comfyui_file(&headers, file, &output.filename, output.subfolder.as_deref(), output.folder_type.as_deref());
+    if response.status() == StatusCode::OK && content_type_for(&output.filename).starts_with("image/") {
+        state.metrics.image_served("job_output");
+    }
+    Ok(response)
+}
+
+/// `node` recorded for animations assembled from a job's frames.
+const ANIMATION_NODE: &str = "animation";
+const DEFAULT_ANIMATION_FPS: u32 = 8;
+const MAX_ANIMATION_FPS: u32 = 60;
+
+/// Every output file of a prompt as a ZIP archive.
+///
+/// The files are those of the prompt's ComfyUI history entry. Preview
+/// (`temp`) images are left out. Files are fetched and sent one at a time, so
+/// a backend error part-way ends the download with a truncated archive.
+#[utoipa::path(
+    get, path = "/outputs/{prompt_id}.zip", tag = "history",
+    params(("prompt_id" = String, Path, description = "ComfyUI prompt id")),
+    responses(
+        (status = 200, description = "Files named <subfolder>/<filename>, stored uncompressed; previews are left out", body = [u8], content_type = "application/zip"),
+        (status = 404, description = "Unknown prompt, or no outputs yet", body = ErrorBody),
+        (status = 410, description = "The prompt's outputs are in the trash", body = ErrorBody),
+        (status = 502, description = "ComfyUI is unreachable", body = ErrorBody),
+    )
+)]
+pub async fn outputs_zip(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(file): Path<String>,
+) -> AppResult<Response> {
+    let not_found = || AppError::NotFound(format!("'{}' not found", file));
+    let prompt_id = file.strip_suffix(".zip").filter(|id| !id.is_empty()).ok_or_else(not_found)?.to_string();
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    if !owns_prompt(&state, tenant, &prompt_id).await {
+        return Err(AppError::NotFound(format!("Prompt '{}' not found", prompt_id)));
+    }
+    check_not_trashed(&state, &prompt_id)?;
+    let entry = state.backends.get_prompt_history(&prompt_id).await?
+        .ok_or_else(|| AppError::NotFound(format!("Prompt '{}' not found", prompt_id)))?;
+    let outputs: Vec<OutputImage> = entry.images().into_iter().filter(|img| img.folder_type.as_deref() != Some("temp")).collect();
+    if outputs.is_empty() {
+        return Err(AppError::NotFound(format!("Prompt '{}' has no outputs", prompt_id)));
+    }
+
+    let disposition = format!("attachment; filename=\"{}.zip\"", prompt_id);
+    let zip = Some(ZipStream::new(now_ms()));
+    let chunks = futures_util::stream::unfold((state, prompt_id, outputs.into_iter(), zip), |(state, prompt_id, mut outputs, zip)| async move {
+        let mut zip = zip?;
+        let chunk = match outputs.next() {
+            Some(img) => match state.backends.get_image_in(Some(&prompt_id), &img.filename, img.subfolder.as_deref(), img.folder_type.as_deref()).await {
+                Ok(bytes) => {
+                    state.metrics.image_served("outputs_zip");
+                    let name = inputs::input_path(&img.filename, img.subfolder.as_deref());
+                    zip.entry(&name, &bytes).map(|out| (out, Some(zip)))
+                }
+                Err(e) => Err(format!("Failed to fetch {}: {}", img.filename, e)),
+            },
+            None => zip.finish().map(|out| (out, None)),
+        };
+        Some(match chunk {
+            Ok((out, zip)) => (Ok(axum::body::Bytes::from(out)), (state, prompt_id, outputs, zip)),
+            Err(e) => {
+                tracing::error!("Outputs archive for prompt {} cut short: {}", prompt_id, e);
+                (Err(std::io::Error::other(e)), (state, prompt_id, outputs, None))
+            }
+        })
+    });
+    Ok((
+        [(header::CONTENT_TYPE, "application/zip".to_string()), (header::CONTENT_DISPOSITION, disposition)],
+        axum::body::StreamBody::new(chunks),
+    ).into_response())
+}
+
+/// The trash, which deleting outputs requires.
+fn output_trash(state: &AppState) -> AppResult<&OutputTrash> {
+    state.output_trash.as_ref()
+        .ok_or_else(|| AppError::Unavailable("Deleting outputs requires COMFYUI_OUTPUT_DIR to be configured".to_string()))
+}
+
+/// Refuse to serve outputs of a prompt whose files are in the trash.
+fn check_not_trashed(state: &AppState, prompt_id: &str) -> AppResult<()> {
+    match state.output_trash.as_ref().and_then(|trash| trash.get(prompt_id)) {
+        Some(_) => Err(AppError::Gone(format!("Outputs of prompt '{}' are in the trash", prompt_id))),
+        None => Ok(()),
+    }
+}
+
+/// Move a prompt's output files to the trash.
+///
+/// Their copies harvested onto the static drive go too. Until the retention
+/// window passes, `POST /outputs/:prompt_id/restore` brings them back. The
+/// copies leave the drive index, and are indexed again if restored. Requires
+/// `COMFYUI_OUTPUT_DIR`.
+#[utoipa::path(
+    delete, path = "/outputs/{prompt_id}", tag = "history",
+    params(("prompt_id" = String, Path, description = "ComfyUI prompt id")),
+    responses(
+        (status = 200, description = "Moved; restorable until expires_at_ms", body = TrashedOutputs),
+        (status = 404, description = "Unknown prompt, or none of its files are in the output folder", body = ErrorBody),
+        (status = 409, description = "Already in the trash", body = ErrorBody),
+        (status = 503, description = "COMFYUI_OUTPUT_DIR isn't configured", body = ErrorBody),
+    )
+)]
+pub async fn trash_outputs(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(prompt_id): Path<String>,
+) -> AppResult<Json<Value>> {
+    let trash = output_trash(&state)?;
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    if !owns_prompt(&state, tenant, &prompt_id).await {
+        return Err(AppError::NotFound(format!("Prompt '{}' not found", prompt_id)));
+    }
+    let entry = state.backends.get_prompt_history(&prompt_id).await?
+        .ok_or_else(|| AppError::NotFound(format!("Prompt '{}' not found", prompt_id)))?;
+    // Only outputs live in the output folder; temp previews and inputs don't.
+    let files: Vec<String> = entry.images().into_iter()
+        .filter(|img| img.folder_type.as_deref().unwrap_or("output") == "output")
+        .map(|img| inputs::input_path(&img.filename, img.subfolder.as_deref()))
+        .collect();
+    let (job_id, job_tenant) = {
+        let jobs = state.job_store.read().await;
+        let job = jobs.find_by_prompt_id(&prompt_id);
+        (job.map(|j| j.id.clone()), job.and_then(|j| j.tenant.clone()))
+    };
+    let trashed = trash.trash(&prompt_id, state.backends.index_of(&prompt_id), &files, job_id, job_tenant, now_ms())?;
+    if !trashed.drive_files.is_empty() {
+        state.static_drive_poller.forget(&format!("{}/{}/", HARVEST_DIR, prompt_id)).await;
+    }
+    tracing::info!("Moved {} outputs of prompt {} to the trash", trashed.files.len() + trashed.drive_files.len(), prompt_id);
+    Ok(Json(json!({"status": "success", "trashed": trashed})))
+}
+
+/// Move a prompt's trashed outputs back into the output folder.
+#[utoipa::path(
+    post, path = "/outputs/{prompt_id}/restore", tag = "history",
+    params(("prompt_id" = String, Path, description = "ComfyUI prompt id")),
+    responses(
+        (status = 200, description = "Restored", body = RestoredOutputs),
+        (status = 404, description = "Not in the trash", body = ErrorBody),
+        (status = 409, description = "A file has since been written at one of the paths", body = ErrorBody),
+        (status = 410, description = "Purged: the retention window has passed", body = ErrorBody),
+        (status = 503, description = "COMFYUI_OUTPUT_DIR isn't configured", body = ErrorBody),
+    )
+)]
+pub async fn restore_outputs(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(prompt_id): Path<String>,
+) -> AppResult<Json<Value>> {
+    let trash = output_trash(&state)?;
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    if !trash.get(&prompt_id).is_some_and(|entry| entry.visible_to(tenant)) {
+        return Err(AppError::NotFound(format!("Outputs of prompt '{}' are not in the trash", prompt_id)));
+    }
+    let restored = trash.restore(&prompt_id, now_ms())?;
+    tracing::info!("Restored {} outputs of prompt {} from the trash", restored.files.len(), prompt_id);
+    Ok(Json(json!({"status": "success", "restored": restored})))
+}
+
+/// Trashed outputs, most recently deleted first.
+///
+/// Expired entries are purged first.
+#[utoipa::path(
+    get, path = "/outputs/trash", tag = "history",
+    responses(
+        (status = 200, description = "Trash", body = TrashList),
+        (status = 503, description = "COMFYUI_OUTPUT_DIR isn't configured", body = ErrorBody),
+    )
+)]
+pub async fn list_trash(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+) -> AppResult<Json<Value>> {
+    let trash = output_trash(&state)?;
+    trash.purge_expired(now_ms());
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let entries: Vec<_> = trash.list().into_iter().filter(|e| e.visible_to(tenant)).collect();
+    Ok(Json(json!({"total": entries.len(), "trash": entries})))
+}
+
+/// Purge trashed outputs whose retention has passed.
+pub fn purge_trash(state: &AppState) {
+    let Some(trash) = &state.output_trash else { return };
+    let purged = trash.purge_expired(now_ms());
+    if !purged.is_empty() {
+        tracing::info!("Purged trashed outputs of {} prompts", purged.len());
+    }
+}
+
+/// Assemble a finished job's image outputs into an animation and attach it
+/// to the job as another output.
+///
+/// Body (all optional): `{"format": "gif"|"apng"|"webp"|"mp4", "fps", "loop",
+/// "node"}`; `node` keeps only that SaveImage node's frames. The file is
+/// written to ComfyUI's output folder (the tenant's subfolder for scoped
+/// keys).
+pub async fn job_animation(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(id): Path<String>,
+    body: Option<Json<Value>>,
+) -> AppResult<Json<Value>> {
+    let body = body.map(|Json(b)| b).unwrap_or_else(|| json!({}));
+    let invalid = |field: &str, message: String| AppError::InvalidField { field: field.to_string(), message };
+    let format = match body.get("format") {
+        None => AnimationFormat::Gif,
+        Some(v) => v.as_str().and_then(AnimationFormat::parse)
+            .ok_or_else(|| invalid("format", "'format' must be one of gif, apng, webp, mp4".to_string()))?,
+    };
+    if !format.is_supported() {
+        return Err(AppError::Unavailable(format!("{} animations require the proxy to be built with the `ffmpeg` feature", format.extension())));
+    }
+    let fps = match body.get("fps") {
+        None => DEFAULT_ANIMATION_FPS,
+        Some(v) => v.as_u64().filter(|f| (1..=MAX_ANIMATION_FPS as u64).contains(f))
+            .ok_or_else(|| invalid("fps", format!("'fps' must be an integer from 1 to {}", MAX_ANIMATION_FPS)))? as u32,
+    };
+    let looped = match body.get("loop") {
+        None => true,
+        Some(v) => v.as_bool().ok_or_else(|| invalid("loop", "'loop' must be a boolean".to_string()))?,
+    };
+    let node = body.get("node").and_then(|v| v.as_str());
+
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let not_found = || AppError::NotFound(format!("Job '{}' not found", id));
+    let synced = state.job_store.read().await.get(&id).filter(|j| j.visible_to(tenant)).ok_or_else(not_found)?.history_synced;
+    if !synced {
+        sync_job_history(&state, &id).await;
+    }
+    let (prompt_id, frames): (Option<String>, Vec<crate::jobs::OutputFile>) = {
+        let jobs = state.job_store.read().await;
+        let job = jobs.get(&id).ok_or_else(not_found)?;
+        match job.state() {
+            JobState::Completed => {}
+            JobState::Failed => return Err(AppError::Gone(format!("Job '{}' failed", id))),
+            _ => return Err(AppError::Conflict(format!("Job '{}' has not finished", id))),
+        }
+        let frames = job.outputs().into_iter()
+            .filter(|o| o.node != ANIMATION_NODE && node.map(|n| o.node == n).unwrap_or(true))
+            .filter(|o| content_type_for(&o.filename).starts_with("image/"))
+            .collect();
+        (job.prompt_id.clone(), frames)
+    };
+    if frames.len() < 2 {
+        return Err(AppError::BadRequest(format!("Job '{}' has {} image output(s); an animation needs at least 2", id, frames.len())));
+    }
+
+    let mut data = Vec::with_capacity(frames.len());
+    for frame in &frames {
+        data.push(state.backends.get_image_in(prompt_id.as_deref(), &frame.filename, frame.subfolder.as_deref(), frame.folder_type.as_deref()).await?);
+    }
+    let options = AnimationOptions { format, fps, looped };
+    let animation = tokio::task::spawn_blocking(move || assemble(&data, options))
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?
+        .map_err(AppError::Internal)?;
+
+    let filename = format!("{}_animation.{}", id, format.extension());
+    let uploaded = state.backends.upload_output(prompt_id.as_deref(), animation, &filename, tenant).await?;
+    let index = {
+        let mut jobs = state.job_store.write().await;
+        let existing = |jobs: &JobStore| jobs.get(&id).and_then(|j| {
+            j.outputs().iter().position(|o| o.node == ANIMATION_NODE && o.filename == uploaded.name && o.subfolder == uploaded.subfolder)
+        });
+        // Re-assembling in the same format replaced the file; keep its index.
+        if existing(&jobs).is_none() {
+            jobs.record(&id, JobEventKind::OutputSaved {
+                node: ANIMATION_NODE.to_string(),
+                filename: uploaded.name.clone(),
+                subfolder: uploaded.subfolder.clone(),
+                folder_type: Some("output".to_string()),
+            });
+        }
+        existing(&jobs).unwrap_or_default() as u64
+    };
+    Ok(Json(json!({
+        "status": "success",
+        "format": format,
+        "frames": frames.len(),
+        "fps": fps,
+        "filename": uploaded.name,
+        "index": index,
+        "url": output_url(&id, index),
+    })))
+}
+
+/// Aggregate per-node execution time over completed jobs.
+/// `?workflow=<name>` narrows to one workflow and groups by node id.
+pub async fn node_stats(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+) -> Json<Value> {
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let jobs = state.job_store.read().await;
+    let stats = aggregate_node_stats(jobs.iter().filter(|j| j.visible_to(tenant)), params.get("workflow").map(|s| s.as_str()));
+    Json(json!({"nodes": stats}))
+}
+
+/// Minimal txt2img: drive the default workflow with a handful of fields,
+/// wait for it to finish, and return URLs for the generated images.
+///
+/// Body: `{"prompt": "...", "negative_prompt"?, "width"?, "height"?, "seed"?}`.
+/// A random seed is chosen when none is given; `"favorite:<name>"` reuses a
+/// favorite's.
+pub async fn generate(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    client: Option<Extension<ClientName>>,
+    Json(payload): Json<Value>,
+) -> AppResult<Json<Value>> {
+    let bad_request = |msg: &str| AppError::BadRequest(msg.to_string());
+    let prompt = payload.get("prompt").cloned().unwrap_or(Value::Null);
+    validate_prompt_text("prompt", &prompt, &state.text_limits).map_err(text_rejection)?;
+    if let Some(neg) = payload.get("negative_prompt") {
+        validate_prompt_text("negative_prompt", neg, &state.text_limits).map_err(text_rejection)?;
+    }
+    let text = prompt.as_str().unwrap_or_default();
+    let workflow = state.default_workflow.clone()
+        .ok_or_else(|| AppError::Unavailable("POST /generate requires DEFAULT_WORKFLOW to be configured".to_string()))?;
+    let seed = match payload.get("seed") {
+        Some(Value::String(s)) if s.starts_with(FAVORITE_PREFIX) => {
+            let mut seed = json!({"seed": s});
+            let tenant = key.as_ref().and_then(|k| k.tenant());
+            state.seeds.read().await.resolve(&mut seed, tenant)
+                .map_err(|message| AppError::InvalidField { field: "seed".to_string(), message })?;
+            seed["seed"].as_u64().unwrap_or_default()
+        }
+        Some(v) => v.as_u64().ok_or_else(|| bad_request("'seed' must be a non-negative integer or \"favorite:<name>\""))?,
+        None => (uuid::Uuid::new_v4().as_u128() as u64) >> 14,
+    };
+
+    let mut body = json!({"workflow": workflow, "text_positive": text, "seed": seed});
+    if let Some(neg) = payload.get("negative_prompt") {
+        let neg = neg.as_str().ok_or_else(|| bad_request("'negative_prompt' must be a string"))?;
+        body["text_negative"] = json!(neg);
+    }
+    for key in ["width", "height"] {
+        if let Some(v) = payload.get(key) {
+            let n = v.as_u64().ok_or_else(|| bad_request(&format!("'{}' must be a positive integer", key)))?;
+            body[key] = json!(n);
+        }
+    }
+
+    let queued = queue_job(&state, &body, key.as_deref(), client_name(&client)).await?;
+    let job_id = queued.get("job_id").and_then(|v| v.as_str()).unwrap_or_default().to_string();
+
+    // A prompt held for fair scheduling gets its prompt id once it's sent.
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(state.generate_timeout_secs);
+    let entry = loop {
+        if let Some(entry) = sync_job_history(&state, &job_id).await {
+            break entry;
+        }
+        let (prompt_id, failure) = match state.job_store.read().await.get(&job_id) {
+            Some(job) => (job.prompt_id.clone(), job.events.iter().find_map(|e| match &e.kind {
+                JobEventKind::Failed { error, .. } => Some(error.clone()),
+                _ => None,
+            })),
+            None => (None, None),
+        };
+        if let (None, Some(error)) = (&prompt_id, failure) {
+            return Err(AppError::ComfyUI(format!("Job {} was never sent: {}", job_id, error)));
+        }
+        if tokio::time::Instant::now() >= deadline {
+            let prompt_id = prompt_id.unwrap_or_else(|| "not sent yet".to_string());
+            return Err(AppError::Timeout(format!("waiting for job {} (prompt {})", job_id, prompt_id)));
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    };
+    let prompt_id = state.job_store.read().await.get(&job_id).and_then(|j| j.prompt_id.clone()).unwrap_or_default();
+
+    let entry: HistoryEntry = serde_json::from_value(entry)
+        .map_err(|e| AppError::ComfyUI(format!("Unexpected history entry for job {}: {}", job_id, e)))?;
+    if entry.is_error() {
+        return Err(AppError::ComfyUI(format!("Generation failed for job {}", job_id)));
+    }
+    let images: Vec<Value> = entry.outputs.values()
+        .flat_map(|out| out.images.iter())
+        .map(|img| json!({
+            "filename": img.filename,
+            "subfolder": img.subfolder.clone().unwrap_or_default(),
+            "type": img.folder_type,
+            "url": image_url(&img.filename, img.subfolder.as_deref()),
+        }))
+        .collect();
+    Ok(Json(json!({
+        "job_id": job_id,
+        "prompt_id": prompt_id,
+        "seed": seed,
+        "images": images,
+    })))
+}
+
+/// Proxy-relative URL for fetching an output image via `/get_image`.
+fn image_url(filename: &str, subfolder: Option<&str>) -> String {
+    let mut url = reqwest::Url::parse("http://proxy/get_image").expect("static URL is valid");
+    url.query_pairs_mut().append_pair("filename", filename);
+    if let Some(subfolder) = subfolder.filter(|s| !s.is_empty()) {
+        url.query_pairs_mut().append_pair("subfolder", subfolder);
+    }
+    format!("{}?{}", url.path(), url.query().unwrap_or_default())
+}
+
+pub async fn get_name(Query(params): Query<std::collections::HashMap<String, String>>) -> String {
+    let default = String::from("sdxl");
+    let name = params.get("name").ok_or(&default).unwrap_or(&default);
+    name.to_string()
+}
+
+/// Fetch an output image. Optional `subfolder` and `type` are passed to
+/// ComfyUI's `/view`; tenant-scoped keys may only read their own subfolder.
+/// The file is streamed through as ComfyUI sends it, with a `Content-Type`
+/// browsers can render and cache headers.
+pub async fn get_image(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let filename = params.get("filename").ok_or_else(|| AppError::InvalidField { field: "filename".to_string(), message: "Filename is required".to_string() })?;
+    let subfolder = params.get("subfolder").map(|s| s.as_str());
+    if let Some(tenant) = key.as_ref().and_then(|k| k.tenant()) {
+        let own = subfolder.map(|s| s == tenant || s.starts_with(&format!("{}/", tenant))).unwrap_or(false);
+        if !own || filename.contains("..") || subfolder.unwrap_or_default().contains("..") {
+            return Err(AppError::NotFound(format!("Image '{}' not found", filename)));
+        }
+    }
+    let prompt_id = params.get("prompt_id").map(|s| s.as_str());
+    let folder_type = params.get("type").map(|s| s.as_str());
+    let image = state.backends.get_image_stream_in(prompt_id, filename, subfolder, folder_type, conditional::forwarded_range(&headers)).await?;
+    let response = serve_comfyui_file(&headers, image, filename, subfolder, folder_type);
+    if response.status() == StatusCode::OK {
+        state.metrics.image_served("comfyui");
+    }
+    Ok(response)
+}
+
+/// Respond with a file streamed from ComfyUI's `/view`, supporting range
+/// and conditional requests.
+fn serve_comfyui_file(request: &HeaderMap, file: ImageStream, filename: &str, subfolder: Option<&str>, folder_type: Option<&str>) -> Response {
+    // ComfyUI guesses types from the extension too, but falls back to a
+    // generic type that browsers won't render inline.
+    let content_type = file.content_type.clone()
+        .filter(|t| t.starts_with("image/") || t.starts_with("video/"))
+        .unwrap_or_else(|| content_type_for(filename).to_string());
+    // An output's name can come back with other contents once the file is
+    // deleted, so caches revalidate; its name, size, and modified time
+    // stand in for a tag ComfyUI didn't send.
+    let folder_type = folder_type.unwrap_or("output");
+    let etag = match folder_type {
+        "output" => {
+            let identity = file.file_length().map(|length| {
+                let modified = file.last_modified.as_deref().unwrap_or_default();
+                etag_for(format!("{}/{}:{}:{}", subfolder.unwrap_or_default(), filename, length, modified).as_bytes())
+            });
+            file.etag.clone().or(identity)
+        }
+        _ => file.etag.clone(),
+    };
+    let cache_control = "private, no-cache";
+    conditional::serve_stream(request, file, content_type, cache_control, etag)
+}
+
+/// Upload an input image (multipart field `image`) to ComfyUI for img2img
+/// and inpainting workflows. An `overwrite` field of `true` replaces an
+/// existing file of the same name; tenant-scoped keys can't overwrite, since
+/// ComfyUI's input folder is shared. The image can be resized first, see
+/// [`upload_resize`].
+pub async fn upload_image(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    multipart: Multipart,
+) -> AppResult<Json<Value>> {
+    let (mut filename, mut bytes, fields) = read_image_upload(multipart).await?;
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let overwrite = fields.get("overwrite").map(|v| v == "true" || v == "1").unwrap_or(false) && tenant.is_none();
+
+    let mut resized = None;
+    if let Some((mode, (width, height))) = upload_resize(&state, key.as_deref(), &fields).await? {
+        let source = std::mem::take(&mut bytes);
+        let name = filename.clone();
+        let result = tokio::task::spawn_blocking(move || resize_upload(&source, &name, width, height, mode))
+            .await
+            .map_err(|e| AppError::Internal(e.to_string()))?
+            .map_err(|e| AppError::InvalidField { field: "image".to_string(), message: e })?;
+        resized = Some(json!({"mode": mode, "from": [result.from.0, result.from.1], "to": [width, height]}));
+        filename = result.filename;
+        bytes = result.bytes;
+    }
+
+    let size = bytes.len() as u64;
+    let uploaded = state.backends.upload_image(bytes, &filename, overwrite).await?;
+    state.inputs.write().await.record(&uploaded, size, now_ms(), tenant.map(String::from));
+    Ok(Json(json!({
+        "status": "success",
+        "name": uploaded.name,
+        "subfolder": uploaded.subfolder,
+        "type": uploaded.folder_type,
+        "resized": resized,
+    })))
+}
+
+/// The `image` file and the text fields of a multipart image upload.
+async fn read_image_upload(mut multipart: Multipart) -> AppResult<(String, Vec<u8>, std::collections::HashMap<String, String>)> {
+    let mut image: Option<(String, Vec<u8>)> = None;
+    let mut fields = std::collections::HashMap::new();
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return Err(AppError::BadRequest(e.to_string())),
+        };
+        match field.name().map(String::from) {
+            Some(name) if name == "image" => {
+                let Some(filename) = field.file_name().map(String::from) else {
+                    return Err(AppError::InvalidField { field: "image".to_string(), message: "The 'image' field needs a filename".to_string() });
+                };
+                let bytes = field.bytes().await.map_err(|e| AppError::BadRequest(e.to_string()))?;
+                image = Some((filename, bytes.to_vec()));
+            }
+            Some(name) => {
+                fields.insert(name, field.text().await.unwrap_or_default());
+            }
+            None => {}
+        }
+    }
+    let Some((filename, bytes)) = image else {
+        return Err(AppError::InvalidField { field: "image".to_string(), message: "No 'image' file found in upload".to_string() });
+    };
+    check_image_filename(&filename, "image")?;
+    Ok((filename, bytes, fields))
+}
+
+fn check_image_filename(filename: &str, field: &str) -> AppResult<()> {
+    if filename.is_empty() || filename.contains(['/', '\\']) || filename.contains("..") {
+        return Err(AppError::InvalidField { field: field.to_string(), message: format!("Invalid image filename '{}'", filename) });
+    }
+    Ok(())
+}
+
+/// Upload an init image and queue an img2img prompt using it.
+///
+/// The image is uploaded to ComfyUI like `/upload_image` and set as the
+/// `image` param, which points the workflow's LoadImage nodes at it.
+///
+/// The body is either multipart, with the `image` file, an optional
+/// `payload` field holding a `/queue_prompt` body, and other fields as
+/// top-level params (e.g. `denoise=0.55`), or a `/queue_prompt` JSON body
+/// with the image base64-encoded in `init_image` (a `data:` URL works too)
+/// and optionally named by `init_image_name`. `?dry_run=true` builds the
+/// prompt without uploading anything.
+#[utoipa::path(
+    post, path = "/img2img", tag = "prompts",
+    params(openapi::DryRunQuery, openapi::ClientHeader),
+    request_body = Img2ImgRequest,
+    responses(
+        (status = 200, description = "Uploaded and queued", body = Img2ImgResponse),
+        (status = 400, description = "Invalid image or params, or no init image LoadImage node", body = ErrorBody),
+        (status = 403, description = "Not allowed by the API key's policy", body = ErrorBody),
+        (status = 404, description = "Unknown workflow", body = ErrorBody),
+        (status = 429, description = "Quota or rate limit reached; see Retry-After", body = ErrorBody),
+        (status = 502, description = "ComfyUI refused the upload or prompt, or is unreachable", body = ErrorBody),
+        (status = 503, description = "Maintenance mode", body = ErrorBody),
+    )
+)]
+pub async fn img2img(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    client: Option<Extension<ClientName>>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+    request: axum::http::Request<axum::body::Body>,
+) -> AppResult<Json<Value>> {
+    let is_multipart = request.headers().get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("multipart/form-data"))
+        .unwrap_or(false);
+    let (filename, bytes, mut payload) = if is_multipart {
+        let multipart = Multipart::from_request(request, &state).await.map_err(|e| AppError::BadRequest(e.body_text()))?;
+        let (filename, bytes, fields) = read_image_upload(multipart).await?;
+        (filename, bytes, img2img_multipart_payload(fields)?)
+    } else {
+        let Json(body) = Json::<Value>::from_request(request, &state).await.map_err(|e| AppError::BadRequest(e.body_text()))?;
+        img2img_json_image(body)?
+    };
+    if payload.get("image").is_some() || payload.get("params").and_then(|p| p.get("image")).is_some() {
+        return Err(AppError::InvalidField { field: "image".to_string(), message: "Send the init image as the upload, not an 'image' param".to_string() });
+    }
+    validate_text_params(&payload, &state.text_limits).map_err(text_rejection)?;
+
+    // Check the workflow can take the image before uploading it.
+    payload["image"] = Value::String(filename.clone());
+    let workflow = workflow_for(&state, &payload)?;
+    split_grid_from_payload(&payload)?;
+    let built = build_prompt(&state, &payload, workflow.as_deref(), key.as_deref()).await?;
+    if load_image_ids(&built.base).is_empty() {
+        return Err(AppError::InvalidField { field: "workflow".to_string(), message: "The workflow has no LoadImage node to take the init image".to_string() });
+    }
+    if params.get("dry_run").map(|v| v == "true" || v == "1").unwrap_or(false) {
+        return dry_run_report(workflow, built).map(Json);
+    }
 
-    // Use the constructed body for the request
-    state.comfyui_client.queue_prompt(root)
-        .await
-        .map(Json)
-        .map_err(|e| {
-            tracing::error!("Failed to queue prompt: {:?}", e);
-            e.to_string()
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let size = bytes.len() as u64;
+    let uploaded = state.backends.upload_image(bytes, &filename, false).await?;
+    state.inputs.write().await.record(&uploaded, size, now_ms(), tenant.map(String::from));
+    payload["image"] = Value::String(inputs::input_path(&uploaded.name, uploaded.subfolder.as_deref()));
+    let mut response = queue_job(&state, &payload, key.as_deref(), client_name(&client)).await?;
+    response["image"] = json!({"name": uploaded.name, "subfolder": uploaded.subfolder, "type": uploaded.folder_type});
+    Ok(Json(response))
+}
+
+/// The queue payload of a multipart `/img2img` request: the `payload` field,
+/// with the other fields set over it as top-level keys.
+fn img2img_multipart_payload(mut fields: std::collections::HashMap<String, String>) -> AppResult<Value> {
+    let mut payload = match fields.remove("payload") {
+        None => json!({}),
+        Some(text) => match serde_json::from_str::<Value>(&text) {
+            Ok(payload) if payload.is_object() => payload,
+            _ => return Err(AppError::InvalidField { field: "payload".to_string(), message: "'payload' must be a JSON object".to_string() }),
+        },
+    };
+    for (name, value) in fields {
+        // Prompt text and names stay text even when they look like numbers.
+        let text = name.starts_with("text") || name.starts_with("filename_") || name == "workflow";
+        payload[&name] = if text { Value::String(value) } else { parse_value(&value) };
+    }
+    Ok(payload)
+}
+
+/// Take the base64 `init_image` (and `init_image_name`) out of a JSON
+/// `/img2img` body, returning the image and the remaining queue payload.
+fn img2img_json_image(mut body: Value) -> AppResult<(String, Vec<u8>, Value)> {
+    let invalid = |message: &str| AppError::InvalidField { field: "init_image".to_string(), message: message.to_string() };
+    let obj = body.as_object_mut().ok_or_else(|| AppError::BadRequest("Body must be a JSON object".to_string()))?;
+    let data = match obj.remove("init_image") {
+        Some(Value::String(data)) => data,
+        Some(_) => return Err(invalid("'init_image' must be a base64 string")),
+        None => return Err(invalid("Missing 'init_image': send the image base64-encoded, or upload it as multipart")),
+    };
+    // Accept data URLs, e.g. `data:image/png;base64,...`.
+    let encoded = match data.split_once(',') {
+        Some((prefix, rest)) if prefix.starts_with("data:") => rest,
+        _ => data.as_str(),
+    };
+    let bytes = base64::engine::general_purpose::STANDARD.decode(encoded.trim())
+        .map_err(|_| invalid("'init_image' is not valid base64"))?;
+    let format = image::guess_format(&bytes).map_err(|_| invalid("'init_image' is not a PNG, JPEG, WebP, or GIF image"))?;
+    let filename = match obj.remove("init_image_name") {
+        None => format!("img2img.{}", format.extensions_str().first().copied().unwrap_or("png")),
+        Some(Value::String(name)) => name,
+        Some(_) => return Err(AppError::InvalidField { field: "init_image_name".to_string(), message: "'init_image_name' must be a string".to_string() }),
+    };
+    check_image_filename(&filename, "init_image_name")?;
+    Ok((filename, bytes, body))
+}
+
+/// How to resize an upload, if at all. The `resize` field picks the mode
+/// (`crop`, `pad`, `stretch`; `true` for the default, `false` to skip);
+/// without it, `UPLOAD_RESIZE` decides. The target size is the `width` and
+/// `height` fields, else the empty-latent size of the `workflow` field's
+/// workflow, else `UPLOAD_RESIZE_WIDTH`/`UPLOAD_RESIZE_HEIGHT`.
+async fn upload_resize(
+    state: &AppState,
+    key: Option<&ApiKey>,
+    fields: &std::collections::HashMap<String, String>,
+) -> AppResult<Option<(ResizeMode, (u32, u32))>> {
+    let invalid = |field: &str, message: String| AppError::InvalidField { field: field.to_string(), message };
+    let defaults = state.upload_resize;
+    let mode = match fields.get("resize").map(|v| v.trim()) {
+        None => defaults.mode,
+        Some("false" | "0" | "none" | "") => None,
+        Some("true" | "1") => Some(defaults.mode.unwrap_or(ResizeMode::Crop)),
+        Some(other) => Some(ResizeMode::parse(other)
+            .ok_or_else(|| invalid("resize", format!("Unknown resize mode '{}'; expected crop, pad, or stretch", other)))?),
+    };
+    let Some(mode) = mode else { return Ok(None) };
+
+    let dimension = |field: &str| -> AppResult<Option<u32>> {
+        fields.get(field)
+            .map(|v| v.trim().parse::<u32>().map_err(|_| invalid(field, format!("'{}' must be a positive integer", field))))
+            .transpose()
+    };
+    let size = match (dimension("width")?, dimension("height")?) {
+        (Some(width), Some(height)) => (width, height),
+        (None, None) => match fields.get("workflow") {
+            Some(name) => {
+                let (dir, mut doc) = read_stored_workflow(state, key, name).await?;
+                let graph = resolve_stored_workflow(&dir, name, &mut doc)?;
+                let (width, height) = latent_dimensions(&graph)
+                    .ok_or_else(|| invalid("workflow", format!("Workflow '{}' has no empty-latent node with a fixed size", name)))?;
+                (width as u32, height as u32)
+            }
+            None => defaults.size.ok_or_else(|| invalid(
+                "resize",
+                "Resizing needs 'width' and 'height', a 'workflow', or UPLOAD_RESIZE_WIDTH/UPLOAD_RESIZE_HEIGHT".to_string(),
+            ))?,
+        },
+        _ => return Err(invalid("width", "'width' and 'height' must be given together".to_string())),
+    };
+    let rules = &state.resolution_rules;
+    for (field, value) in [("width", size.0), ("height", size.1)] {
+        if (value as u64) < rules.min || (value as u64) > rules.max {
+            return Err(invalid(field, format!("'{}' = {} must be between {} and {}", field, value, rules.min, rules.max)));
+        }
+    }
+    Ok(Some((mode, size)))
+}
+
+/// Input images: uploads tracked by the proxy, plus everything in
+/// `COMFYUI_INPUT_DIR` when configured. Tenant-scoped keys only see their
+/// own uploads.
+pub async fn list_inputs(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+) -> Json<Value> {
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let input_dir = state.comfyui_input_dir.as_deref().map(std::path::Path::new);
+    let images: Vec<Value> = inputs::list_inputs(&*state.inputs.read().await, input_dir)
+        .into_iter()
+        .filter(|image| image.visible_to(tenant))
+        .map(|image| {
+            let mut item = json!(image);
+            item["path"] = json!(image.path());
+            item
         })
+        .collect();
+    Json(json!({"total": images.len(), "inputs": images}))
 }
-    
 
+/// `COMFYUI_INPUT_DIR`, which deleting inputs requires.
+fn input_dir(state: &AppState) -> AppResult<&std::path::Path> {
+    state.comfyui_input_dir.as_deref().map(std::path::Path::new)
+        .ok_or_else(|| AppError::Unavailable("Deleting inputs requires COMFYUI_INPUT_DIR to be configured".to_string()))
+}
 
-pub async fn get_name(Query(params): Query<std::collections::HashMap<String, String>>) -> String {
-    let default = String::from("sdxl");
-    let name = params.get("name").ok_or(&default).unwrap_or(&default);
-    name.to_string()
+/// Delete input image `path` from `COMFYUI_INPUT_DIR` and stop tracking it.
+async fn remove_input(state: &AppState, input_dir: &std::path::Path, path: &str) -> AppResult<()> {
+    match tokio::fs::remove_file(input_dir.join(path)).await {
+        Ok(()) => {}
+        // Already gone from ComfyUI's side; just forget it.
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(AppError::Internal(format!("Failed to delete {}: {}", path, e))),
+    }
+    state.inputs.write().await.remove(path);
+    Ok(())
 }
 
-pub async fn get_image(
+/// Delete one input image (`?subfolder=` if it's in one). Requires
+/// `COMFYUI_INPUT_DIR`; tenant-scoped keys may only delete their own uploads.
+pub async fn delete_input(
     State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(name): Path<String>,
     Query(params): Query<std::collections::HashMap<String, String>>,
-) -> Result<Vec<u8>, String> {
-    let filename = params.get("filename").ok_or("Filename is required")?;
-    state.comfyui_client.get_image(filename)
-        .await
-        .map_err(|e| e.to_string())
+) -> AppResult<Json<Value>> {
+    let input_dir = input_dir(&state)?;
+    let path = inputs::input_path(&name, params.get("subfolder").map(|s| s.as_str()));
+    if !inputs::is_safe_input_path(&path) {
+        return Err(AppError::BadRequest(format!("Invalid input path '{}'", path)));
+    }
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let tracked = state.inputs.read().await.get(&path).cloned();
+    let visible = match &tracked {
+        Some(image) => image.visible_to(tenant),
+        None => tenant.is_none() && input_dir.join(&path).is_file(),
+    };
+    if !visible {
+        return Err(AppError::NotFound(format!("Input '{}' not found", path)));
+    }
+    remove_input(&state, input_dir, &path).await?;
+    Ok(Json(json!({"status": "success", "deleted": [path]})))
+}
+
+/// Delete input images older than `?older_than_secs=N`, to keep the input
+/// folder from growing without bound. Requires `COMFYUI_INPUT_DIR`;
+/// tenant-scoped keys only prune their own uploads.
+pub async fn prune_inputs(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+) -> AppResult<Json<Value>> {
+    let input_dir = input_dir(&state)?;
+    let older_than_secs = params.get("older_than_secs").and_then(|v| v.parse::<u64>().ok()).ok_or_else(|| AppError::InvalidField {
+        field: "older_than_secs".to_string(),
+        message: "'older_than_secs' is required and must be a number".to_string(),
+    })?;
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let cutoff = now_ms().saturating_sub(older_than_secs.saturating_mul(1000));
+    let stale: Vec<String> = inputs::list_inputs(&*state.inputs.read().await, Some(input_dir))
+        .into_iter()
+        .filter(|image| image.visible_to(tenant) && image.modified_ms < cutoff)
+        .map(|image| image.path())
+        .collect();
+    let mut deleted = Vec::new();
+    for path in stale {
+        match remove_input(&state, input_dir, &path).await {
+            Ok(()) => deleted.push(path),
+            Err(e) => tracing::warn!("{}", e),
+        }
+    }
+    Ok(Json(json!({"status": "success", "deleted": deleted})))
+}
+
+/// Whether a caller scoped to `tenant` owns `prompt_id`. Unscoped callers
+/// own every prompt; tenants only those submitted as their jobs.
+async fn owns_prompt(state: &AppState, tenant: Option<&str>, prompt_id: &str) -> bool {
+    tenant.is_none() || state.job_store.read().await
+        .find_by_prompt_id(prompt_id)
+        .map(|job| job.visible_to(tenant))
+        .unwrap_or(false)
+}
+
+/// Mark the jobs for prompts removed from ComfyUI's queue as failed, since
+/// they'll never get a history entry.
+async fn record_dequeued(state: &AppState, prompt_ids: &[String]) {
+    let mut jobs = state.job_store.write().await;
+    for prompt_id in prompt_ids {
+        let Some(id) = jobs.find_by_prompt_id(prompt_id).map(|j| j.id.clone()) else { continue };
+        jobs.record(&id, JobEventKind::Failed { node: None, error: "Removed from queue".to_string() });
+    }
+}
+
+/// Held jobs among `ids` that a caller scoped to `tenant` can see.
+async fn held_among(state: &AppState, tenant: Option<&str>, ids: &[String]) -> Vec<String> {
+    let Some(held) = &state.held_prompts else { return Vec::new() };
+    let held = held.job_ids().await;
+    let jobs = state.job_store.read().await;
+    ids.iter()
+        .filter(|id| held.contains(id) && jobs.get(id).is_some_and(|j| j.visible_to(tenant)))
+        .cloned()
+        .collect()
+}
+
+/// Interrupt the running prompt: the one named by `{"prompt_id"}` in the
+/// body, or otherwise whatever is running (for tenant-scoped keys, their own
+/// running prompt). A `prompt_id` naming a job held for fair scheduling
+/// (by its job id) releases it instead, since it never reached ComfyUI.
+pub async fn interrupt(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    body: Option<Json<Value>>,
+) -> AppResult<Json<Value>> {
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let requested = body.as_ref().and_then(|b| b.get("prompt_id")).and_then(|v| v.as_str()).map(String::from);
+    if let Some(id) = &requested {
+        if !release_held(&state, &held_among(&state, tenant, std::slice::from_ref(id)).await).await.is_empty() {
+            return Ok(Json(json!({"status": "success", "released": id})));
+        }
+    }
+    let queue = state.backends.get_queue().await?;
+    let target = match requested {
+        Some(prompt_id) => {
+            if !owns_prompt(&state, tenant, &prompt_id).await {
+                return Err(AppError::NotFound(format!("Prompt '{}' not found", prompt_id)));
+            }
+            if !queue.is_running(&prompt_id) {
+                return Err(AppError::Conflict(format!("Prompt '{}' is not running", prompt_id)));
+            }
+            prompt_id
+        }
+        None => {
+            let mut target = None;
+            for item in &queue.running {
+                if owns_prompt(&state, tenant, &item.prompt_id).await {
+                    target = Some(item.prompt_id.clone());
+                    break;
+                }
+            }
+            target.ok_or_else(|| AppError::Conflict("Nothing is running".to_string()))?
+        }
+    };
+    state.backends.interrupt(&target).await?;
+    Ok(Json(json!({"status": "success", "interrupted": target})))
+}
+
+/// ComfyUI's running and pending prompts, with the proxy job each belongs
+/// to. Tenant-scoped keys only see their own prompts.
+pub async fn get_queue(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+) -> AppResult<Json<Value>> {
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let queue = state.backends.get_queue().await?;
+    // Taken before the job store, the same order dispatching locks them.
+    let held = match &state.held_prompts {
+        Some(held) => Some(held.job_ids().await),
+        None => None,
+    };
+    let jobs = state.job_store.read().await;
+    let summarize = |items: &[crate::comfyui::types::QueueItem]| -> Vec<Value> {
+        items.iter()
+            .filter_map(|item| {
+                let job = jobs.find_by_prompt_id(&item.prompt_id);
+                if tenant.is_some() && !job.map(|j| j.visible_to(tenant)).unwrap_or(false) {
+                    return None;
+                }
+                Some(json!({
+                    "prompt_id": item.prompt_id,
+                    "number": item.number,
+                    "job_id": job.map(|j| j.id.clone()),
+                    "workflow": job.and_then(|j| j.workflow.clone()),
+                }))
+            })
+            .collect()
+    };
+    let mut body = json!({
+        "running": summarize(&queue.running),
+        "pending": summarize(&queue.pending),
+    });
+    if let Some(held) = held {
+        let held: Vec<Value> = held.iter().enumerate()
+            .filter_map(|(position, id)| jobs.get(id).filter(|j| j.visible_to(tenant)).map(|j| (position, j)))
+            .map(|(position, job)| json!({"job_id": job.id, "workflow": job.workflow, "position": position}))
+            .collect();
+        body["held"] = json!(held);
+    }
+    Ok(Json(body))
+}
+
+/// Remove a pending prompt from the queue, or a prompt held for fair
+/// scheduling by its job id. Running prompts need `POST /interrupt`
+/// instead.
+pub async fn delete_queued(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(prompt_id): Path<String>,
+) -> AppResult<Json<Value>> {
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    if !release_held(&state, &held_among(&state, tenant, std::slice::from_ref(&prompt_id)).await).await.is_empty() {
+        return Ok(Json(json!({"status": "success", "deleted": prompt_id})));
+    }
+    if !owns_prompt(&state, tenant, &prompt_id).await {
+        return Err(AppError::NotFound(format!("Prompt '{}' not found", prompt_id)));
+    }
+    let queue = state.backends.get_queue().await?;
+    if queue.is_running(&prompt_id) {
+        return Err(AppError::Conflict(format!("Prompt '{}' is running; use POST /interrupt", prompt_id)));
+    }
+    if !queue.is_pending(&prompt_id) {
+        return Err(AppError::NotFound(format!("Prompt '{}' is not queued", prompt_id)));
+    }
+    let ids = vec![prompt_id.clone()];
+    state.backends.delete_from_queue(&ids).await?;
+    record_dequeued(&state, &ids).await;
+    Ok(Json(json!({"status": "success", "deleted": prompt_id})))
 }
 
+/// Remove every pending prompt from the queue, and release every prompt
+/// held for fair scheduling; for tenant-scoped keys, only their own.
+pub async fn clear_queue(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+) -> AppResult<Json<Value>> {
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let released = match &state.held_prompts {
+        Some(held) => {
+            let held = held.job_ids().await;
+            release_held(&state, &held_among(&state, tenant, &held).await).await
+        }
+        None => Vec::new(),
+    };
+    let queue = state.backends.get_queue().await?;
+    let mut ids = Vec::new();
+    for item in &queue.pending {
+        if owns_prompt(&state, tenant, &item.prompt_id).await {
+            ids.push(item.prompt_id.clone());
+        }
+    }
+    let failed = match tenant {
+        None => state.backends.clear_queue().await?,
+        Some(_) if ids.is_empty() => Vec::new(),
+        Some(_) => {
+            state.backends.delete_from_queue(&ids).await?;
+            Vec::new()
+        }
+    };
+    // Prompts on backends that couldn't be cleared are still queued.
+    ids.retain(|id| state.backends.url_for(id).is_none_or(|url| failed.iter().all(|(backend, _)| *backend != url)));
+    record_dequeued(&state, &ids).await;
+    let mut body = json!({"status": "success", "cleared": ids, "released": released});
+    if !failed.is_empty() {
+        body["failed"] = failed.iter().map(|(backend, e)| json!({"backend": backend, "error": e.to_string()})).collect();
+    }
+    Ok(Json(body))
+}
+
+/// ComfyUI history, narrowed to the caller's own prompts for tenant-scoped keys.
+async fn history_for(state: &AppState, key: Option<&ApiKey>) -> AppResult<Value> {
+    let hist = state.backends.get_history_raw().await?;
+    let Some(tenant) = key.and_then(|k| k.tenant()) else { return Ok(hist) };
+    let jobs = state.job_store.read().await;
+    let own: std::collections::HashSet<&str> = jobs.iter()
+        .filter(|j| j.visible_to(Some(tenant)))
+        .filter_map(|j| j.prompt_id.as_deref())
+        .collect();
+    let filtered = hist.as_object()
+        .map(|m| m.iter().filter(|(pid, _)| own.contains(pid.as_str())).map(|(k, v)| (k.clone(), v.clone())).collect())
+        .unwrap_or_default();
+    Ok(Value::Object(filtered))
+}
+
+/// ComfyUI's /history as JSON.
+#[utoipa::path(
+    get, path = "/get_history", tag = "history",
+    responses((status = 200, description = "History keyed by prompt id", body = HistoryEntries))
+)]
 pub async fn get_history(
     State(state): State<Arc<AppState>>,
-) -> Result<Json<Value>, String> {
-    state.comfyui_client.get_history()
-        .await
-        .map(Json)
-        .map_err(|e| e.to_string())
+    key: Option<Extension<ApiKey>>,
+) -> AppResult<Json<Value>> {
+    history_for(&state, key.as_deref()).await.map(Json)
 }
 
-// Friendly history endpoint: defaults to human-readable lines; add ?json=true for raw JSON
+/// ComfyUI history, as text lines or JSON.
+///
+/// Defaults to one prompt id per line; `?json=true` returns the raw JSON.
+/// `?fields=` implies JSON and trims each entry (keyed by prompt id) to the
+/// named fields.
+#[utoipa::path(
+    get, path = "/history", tag = "history",
+    params(
+        openapi::JsonFlag,
+        ("prompt_id" = Option<String>, Query, description = "List this prompt's output filenames"),
+        openapi::FieldsQuery,
+    ),
+    responses(
+        (status = 200, description = "Prompt ids in queue order (or a prompt's filenames), or the JSON history", content(
+            ("text/plain" = String),
+            ("application/json" = HistoryEntries),
+        )),
+    )
+)]
 pub async fn history_friendly(
     State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
     Query(params): Query<std::collections::HashMap<String, String>>,
-) -> Result<impl IntoResponse, String> {
+) -> AppResult<Response> {
     let json_flag = params.get("json").map(|v| v == "true" || v == "1").unwrap_or(false);
-    let hist = state.comfyui_client.get_history().await.map_err(|e| e.to_string())?;
+    let fields = fields::from_params(&params)?;
+    let mut hist = history_for(&state, key.as_deref()).await?;
+    if let Some(fields) = fields {
+        if let Some(entries) = hist.as_object_mut() {
+            for entry in entries.values_mut() {
+                *entry = fields.project(entry);
+            }
+        }
+        return Ok(Json(hist).into_response());
+    }
     if json_flag {
         return Ok(Json(hist).into_response());
     }
-    if let Some(pid) = params.get("prompt_id") {
-        let mut files = Vec::new();
-        collect_filenames_for_id(&hist, pid, &mut files);
-        let body = if files.is_empty() { String::new() } else { files.join("\n") };
-        Ok(body.into_response())
+    let history = parse_history(hist);
+    let lines: Vec<String> = match params.get("prompt_id") {
+        Some(pid) => history.get(pid).map(|e| e.images()).unwrap_or_default().into_iter().map(|img| img.filename).collect(),
+        None => {
+            let mut entries: Vec<(&String, Option<u64>)> = history.iter().map(|(id, e)| (id, e.number())).collect();
+            entries.sort_by_key(|(_, number)| *number);
+            entries.into_iter().map(|(id, _)| id.clone()).collect()
+        }
+    };
+    Ok(lines.join("\n").into_response())
+}
+
+/// Gzip `lines` one at a time, yielding whatever compressed bytes each line
+/// produced and the gzip trailer at the end.
+fn gzip_lines(lines: Vec<String>) -> impl futures_util::Stream<Item = std::io::Result<axum::body::Bytes>> {
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    let encoder = Some(GzEncoder::new(Vec::new(), Compression::default()));
+    futures_util::stream::unfold((lines.into_iter(), encoder), |(mut lines, encoder)| async move {
+        let mut encoder = encoder?;
+        let chunk = match lines.next() {
+            Some(line) => encoder.write_all(line.as_bytes()).map(|_| {
+                let out = std::mem::take(encoder.get_mut());
+                (out, Some(encoder))
+            }),
+            None => encoder.finish().map(|out| (out, None)),
+        };
+        Some(match chunk {
+            Ok((out, encoder)) => (Ok(axum::body::Bytes::from(out)), (lines, encoder)),
+            Err(e) => (Err(e), (lines, None)),
+        })
+    })
+}
+
+/// History as NDJSON, one prompt per line.
+///
+/// Entries are streamed oldest first, parsed, with the proxy job id and
+/// workflow when the prompt went through the proxy. `?gzip=true` compresses
+/// the stream (`Content-Encoding: gzip`).
+#[utoipa::path(
+    get, path = "/history/export", tag = "history",
+    params(("gzip" = Option<bool>, Query, description = "Compress with Content-Encoding: gzip")),
+    responses((status = 200, description = "One record per line, in queue order", body = HistoryRecord, content_type = "application/x-ndjson"))
+)]
+pub async fn history_export(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+) -> AppResult<Response> {
+    let gzip = params.get("gzip").map(|v| v == "true" || v == "1").unwrap_or(false);
+    let hist = history_for(&state, key.as_deref()).await?;
+    let mut records = history_records(&hist);
+    {
+        let jobs = state.job_store.read().await;
+        for record in records.iter_mut() {
+            if let Some(job) = jobs.find_by_prompt_id(&record.prompt_id) {
+                record.job_id = Some(job.id.clone());
+                record.workflow = job.workflow.clone();
+            }
+        }
+    }
+    let lines: Vec<String> = records.iter()
+        .filter_map(|r| serde_json::to_string(r).ok())
+        .map(|line| line + "\n")
+        .collect();
+
+    let content_type = [(header::CONTENT_TYPE, "application/x-ndjson")];
+    if gzip {
+        let body = axum::body::StreamBody::new(gzip_lines(lines));
+        Ok((content_type, [(header::CONTENT_ENCODING, "gzip")], body).into_response())
     } else {
-        let mut ids = Vec::new();
-        collect_prompt_ids(&hist, &mut ids);
-        let body = if ids.is_empty() { String::new() } else { ids.join("\n") };
-        Ok(body.into_response())
+        let chunks = lines.into_iter().map(|line| Ok::<_, std::io::Error>(axum::body::Bytes::from(line)));
+        Ok((content_type, axum::body::StreamBody::new(futures_util::stream::iter(chunks))).into_response())
     }
 }
 
+/// A manager writing into `tenant`'s namespace, created on demand.
+async fn tenant_manager(state: &AppState, tenant: &str) -> AppResult<WorkflowManager> {
+    let dir = tenant_dir(&state.prompts_dir, Some(tenant));
+    tokio::fs::create_dir_all(&dir).await.map_err(|e| AppError::Internal(format!("Failed to create {}: {}", dir, e)))?;
+    Ok(WorkflowManager::with_dir(dir))
+}
+
 pub async fn add_workflow(
     State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
     Json(payload): Json<Value>,
-) -> Result<Json<Value>, String> {
+) -> AppResult<Json<Value>> {
     let workflow_name = payload.get("name").and_then(|v| v.as_str()).map(String::from);
     let workflow = payload.get("workflow").cloned();
 
-    if workflow_name.is_none() && workflow.is_none() {
-        return Err("Either 'name' or 'workflow' must be provided".to_string());
+    match (&workflow_name, &workflow) {
+        (None, None) => return Err(AppError::BadRequest("Either 'name' or 'workflow' must be provided".to_string())),
+        (None, Some(_)) => return Err(AppError::InvalidField { field: "name".to_string(), message: "A name must be provided when adding a new workflow".to_string() }),
+        _ => {}
     }
 
-    let mut workflow_manager = state.workflow_manager.write().await;
+    let mut scoped;
+    let mut shared;
+    let workflow_manager = match key.as_ref().and_then(|k| k.tenant()) {
+        Some(tenant) => {
+            if let Some(name) = workflow_name.as_deref().filter(|n| !is_valid_workflow_name(n)) {
+                return Err(AppError::InvalidField { field: "name".to_string(), message: format!("Invalid workflow name '{}'", name) });
+            }
+            scoped = tenant_manager(&state, tenant).await?;
+            &mut scoped
+        }
+        None => { shared = state.workflow_manager.write().await; &mut *shared }
+    };
+    if let (Some(name), None) = (&workflow_name, &workflow) {
+        if !workflow_manager.has_workflow(name) {
+            return Err(AppError::NotFound(format!("Workflow '{}' not found", name)));
+        }
+    }
     workflow_manager
         .add_workflow(workflow_name, workflow)
         .await
-        .map(|_| Json(json!({"status": "success"})))
-        .map_err(|e| e.to_string())
+        .map_err(AppError::WorkflowManagement)?;
+    Ok(Json(json!({"status": "success"})))
+}
+
+/// Upload workflow files (API graphs or UI exports).
+///
+/// The body is `multipart/form-data` with one or more files. Each file part
+/// may be an API graph or a UI export; UI exports are converted before
+/// saving. The workflow name comes from an optional `name` text field
+/// (single-file uploads only) or the uploaded file's stem.
+#[utoipa::path(
+    post, path = "/workflows/upload", tag = "workflows",
+    request_body(content = WorkflowUpload, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Saved workflows", body = WorkflowsUploaded),
+        (status = 400, description = "Invalid workflow file", body = ErrorBody),
+    )
+)]
+pub async fn upload_workflow(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    mut multipart: Multipart,
+) -> AppResult<Json<Value>> {
+    let bad_request = |e: axum::extract::multipart::MultipartError| AppError::BadRequest(e.to_string());
+    let mut explicit_name: Option<String> = None;
+    let mut files: Vec<(Option<String>, Vec<u8>)> = Vec::new();
+    while let Some(field) = multipart.next_field().await.map_err(bad_request)? {
+        if field.name() == Some("name") {
+            explicit_name = Some(field.text().await.map_err(bad_request)?);
+            continue;
+        }
+        let file_name = field.file_name().map(String::from);
+        let bytes = field.bytes().await.map_err(bad_request)?;
+        files.push((file_name, bytes.to_vec()));
+    }
+    if files.is_empty() {
+        return Err(AppError::BadRequest("No workflow file found in upload".to_string()));
+    }
+    if explicit_name.is_some() && files.len() > 1 {
+        return Err(AppError::InvalidField { field: "name".to_string(), message: "'name' can only be used when uploading a single file".to_string() });
+    }
+
+    let mut saved = Vec::new();
+    let mut scoped;
+    let mut shared;
+    let workflow_manager = match key.as_ref().and_then(|k| k.tenant()) {
+        Some(tenant) => { scoped = tenant_manager(&state, tenant).await?; &mut scoped }
+        None => { shared = state.workflow_manager.write().await; &mut *shared }
+    };
+    for (file_name, bytes) in files {
+        let name = explicit_name.clone()
+            .or_else(|| file_name.as_deref().map(|f| f.trim_end_matches(".json").to_string()))
+            .ok_or_else(|| AppError::BadRequest("Uploaded file has no filename; provide a 'name' field".to_string()))?;
+        if !is_valid_workflow_name(&name) {
+            return Err(AppError::BadRequest(format!("Invalid workflow name '{}'", name)));
+        }
+        let doc: Value = serde_json::from_slice(&bytes)
+            .map_err(|e| AppError::BadRequest(format!("Failed to parse '{}' as JSON: {}", name, e)))?;
+        let (graph, format) = to_api_graph(&doc).map_err(|e| AppError::BadRequest(format!("{}: {}", name, e)))?;
+        validate_api_graph(&graph).map_err(|e| AppError::BadRequest(format!("{}: {}", name, e)))?;
+        let node_count = graph.as_object().map(|o| o.len()).unwrap_or(0);
+        workflow_manager.add_workflow(Some(name.clone()), Some(graph)).await.map_err(AppError::WorkflowManagement)?;
+        saved.push(json!({"name": name, "format": format.as_str(), "nodes": node_count}));
+    }
+    Ok(Json(json!({"status": "success", "workflows": saved})))
+}
+
+/// Download workflow `name` as a bundle archive (see
+/// [`crate::workflow::bundle`]) for importing into another proxy.
+pub async fn workflow_bundle(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(name): Path<String>,
+) -> AppResult<Response> {
+    let (dir, mut doc) = read_stored_workflow(&state, key.as_deref(), &name).await?;
+    let graph = resolve_stored_workflow(&dir, &name, &mut doc)?;
+    let (_, bytes) = write_bundle(&name, &graph).map_err(AppError::Internal)?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/gzip".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.bundle.tar.gz\"", name)),
+        ],
+        bytes,
+    ).into_response())
+}
+
+/// The directory holding stored workflow `name` for the caller, and the
+/// workflow as saved.
+async fn read_stored_workflow(state: &AppState, key: Option<&ApiKey>, name: &str) -> AppResult<(String, Value)> {
+    if !is_valid_workflow_name(name) {
+        return Err(AppError::BadRequest(format!("Invalid workflow name '{}'", name)));
+    }
+    let tenant = key.and_then(|k| k.tenant());
+    let dir = resolve_workflow_dir(&state.prompts_dir, tenant, Some(name));
+    let path = format!("{}/{}.json", dir.trim_end_matches('/'), name);
+    let data = tokio::fs::read_to_string(&path).await
+        .map_err(|_| AppError::NotFound(format!("Workflow '{}' not found", name)))?;
+    let doc = serde_json::from_str(&data)
+        .map_err(|e| AppError::WorkflowManagement(format!("Failed to parse {}: {}", path, e)))?;
+    Ok((dir, doc))
+}
+
+/// Expand `$include`s in a stored workflow and convert UI exports.
+fn resolve_stored_workflow(dir: &str, name: &str, doc: &mut Value) -> AppResult<Value> {
+    WorkflowManager::with_dir(dir).expand_includes(doc)
+        .and_then(|_| to_api_graph(doc).map(|(graph, _)| graph))
+        .map_err(|e| AppError::WorkflowManagement(format!("{}: {}", name, e)))
+}
+
+/// List stored workflows.
+///
+/// Tenant-scoped keys see the shared workflows plus their own, with `scope`
+/// telling which; their own shadow shared ones of the same name. `?fields=`
+/// trims each workflow to the named fields.
+#[utoipa::path(
+    get, path = "/workflows", tag = "workflows",
+    params(openapi::FieldsQuery),
+    responses((status = 200, description = "Workflows, sorted by name", body = WorkflowList))
+)]
+pub async fn list_workflows(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+) -> AppResult<Json<Value>> {
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let fields = fields::from_params(&params)?;
+    let mut workflows: std::collections::BTreeMap<String, Value> = std::collections::BTreeMap::new();
+    for info in state.workflow_manager.read().await.list_workflows().await.map_err(AppError::WorkflowManagement)? {
+        workflows.insert(info.name.clone(), json!({"name": info.name, "size": info.size, "modified_ms": info.modified_ms, "scope": "shared"}));
+    }
+    if let Some(tenant) = tenant {
+        let own = WorkflowManager::with_dir(tenant_dir(&state.prompts_dir, Some(tenant)));
+        for info in own.list_workflows().await.map_err(AppError::WorkflowManagement)? {
+            workflows.insert(info.name.clone(), json!({"name": info.name, "size": info.size, "modified_ms": info.modified_ms, "scope": "tenant"}));
+        }
+    }
+    let workflows: Vec<Value> = workflows.into_values().collect();
+    let mut body = json!({"total": workflows.len(), "workflows": workflows});
+    if let Some(fields) = fields {
+        fields::project_items(&mut body, "workflows", &fields);
+    }
+    Ok(Json(body))
+}
+
+/// A stored workflow as saved. With `?resolved=true`, `$include`s are
+/// expanded and UI exports converted, giving the graph that would be queued.
+#[utoipa::path(
+    get, path = "/workflows/{name}", tag = "workflows",
+    params(
+        ("name" = String, Path, description = "Workflow name"),
+        ("resolved" = Option<bool>, Query, description = "Expand $include snippets and convert UI exports"),
+    ),
+    responses(
+        (status = 200, description = "The workflow document", body = Graph),
+        (status = 304, description = "Not modified (If-None-Match)"),
+        (status = 404, description = "Unknown workflow", body = ErrorBody),
+    )
+)]
+pub async fn get_workflow(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(name): Path<String>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+) -> AppResult<Json<Value>> {
+    let (dir, mut doc) = read_stored_workflow(&state, key.as_deref(), &name).await?;
+    if params.get("resolved").map(|v| v == "true" || v == "1").unwrap_or(false) {
+        doc = resolve_stored_workflow(&dir, &name, &mut doc)?;
+    }
+    Ok(Json(doc))
+}
+
+/// Convert a UI export to the API prompt format.
+///
+/// The UI export (`nodes`/`links`) in the body isn't stored. API graphs are
+/// returned as they are. ComfyUI's node definitions fill in widget layouts
+/// for nodes the converter doesn't know, when ComfyUI is reachable.
+#[utoipa::path(
+    post, path = "/workflows/convert", tag = "workflows",
+    request_body = Graph,
+    responses(
+        (status = 200, description = "The converted graph", body = ConvertResponse),
+        (status = 400, description = "Widgets can't be mapped or a link is broken", body = ErrorBody),
+    )
+)]
+pub async fn convert_workflow(
+    State(state): State<Arc<AppState>>,
+    Json(doc): Json<Value>,
+) -> AppResult<Json<Value>> {
+    let info = match detect_format(&doc) {
+        WorkflowFormat::Ui => match state.object_info.get(&state.comfyui_client, false).await {
+            Ok(info) => Some(info),
+            Err(e) => {
+                tracing::warn!("Converting without node definitions: {}", e);
+                None
+            }
+        },
+        _ => None,
+    };
+    let (graph, format) = to_api_graph_with(&doc, info.as_deref())
+        .and_then(|(graph, format)| validate_api_graph(&graph).map(|_| (graph, format)))
+        .map_err(AppError::BadRequest)?;
+    let node_count = graph.as_object().map(|m| m.len()).unwrap_or(0);
+    Ok(Json(json!({"format": format.as_str(), "nodes": node_count, "prompt": graph})))
+}
+
+/// The last preflight of the shared workflows against ComfyUI, run now if
+/// there is none yet or `?refresh=true` is given.
+pub async fn workflow_preflight(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+) -> AppResult<Json<Value>> {
+    let refresh = params.get("refresh").map(|v| v == "true" || v == "1").unwrap_or(false);
+    let cached = state.preflight.read().await.clone();
+    let report = match cached {
+        Some(report) if !refresh => report,
+        _ => run_preflight(&state, refresh).await?,
+    };
+    Ok(Json(serde_json::to_value(&*report)?))
+}
+
+/// Validate every shared workflow against ComfyUI's node definitions
+/// (re-fetched when `refresh_object_info`), log the failures, and keep the
+/// report for `GET /workflows/preflight`.
+pub async fn run_preflight(state: &AppState, refresh_object_info: bool) -> AppResult<Arc<PreflightReport>> {
+    let info = state.object_info.get(&state.comfyui_client, refresh_object_info).await?;
+    let report = preflight::run_preflight(&state.prompts_dir, &info, now_ms()).await
+        .map_err(AppError::WorkflowManagement)?;
+    for workflow in report.workflows.iter().filter(|w| !w.valid) {
+        let first = workflow.errors.first().map(|e| e.message.as_str()).unwrap_or_default();
+        tracing::warn!("Workflow '{}' failed preflight with {} error(s): {}", workflow.name, workflow.errors.len(), first);
+    }
+    tracing::info!("Preflight: {} of {} workflows ready", report.total - report.failing.len(), report.total);
+    let report = Arc::new(report);
+    *state.preflight.write().await = Some(report.clone());
+    Ok(report)
+}
+
+/// Check a workflow against ComfyUI's node definitions.
+///
+/// Catches what ComfyUI would reject before the workflow is queued. The body
+/// names a stored workflow (`{"workflow": name}`), wraps a graph (`{"prompt":
+/// graph}`), or is the graph or UI export itself.
+#[utoipa::path(
+    post, path = "/workflows/validate", tag = "workflows",
+    params(("refresh" = Option<bool>, Query, description = "Re-fetch /object_info first")),
+    request_body = ValidateRequest,
+    responses(
+        (status = 200, description = "Validation result", body = ValidateResponse),
+        (status = 502, description = "ComfyUI is unreachable", body = ErrorBody),
+    )
+)]
+pub async fn validate_workflow(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+    Json(payload): Json<Value>,
+) -> AppResult<Json<Value>> {
+    let (name, graph) = match payload.get("workflow").and_then(|v| v.as_str()) {
+        Some(name) => {
+            let (dir, mut doc) = read_stored_workflow(&state, key.as_deref(), name).await?;
+            (Some(name.to_string()), resolve_stored_workflow(&dir, name, &mut doc)?)
+        }
+        None => {
+            let doc = payload.get("prompt").unwrap_or(&payload);
+            let (graph, _) = to_api_graph(doc).map_err(|e| AppError::InvalidField { field: "prompt".to_string(), message: e })?;
+            (None, graph)
+        }
+    };
+    let refresh = params.get("refresh").map(|v| v == "true" || v == "1").unwrap_or(false);
+    let info = state.object_info.get(&state.comfyui_client, refresh).await?;
+    let errors = validate_against_object_info(&graph, &info);
+    Ok(Json(json!({"workflow": name, "valid": errors.is_empty(), "errors": errors})))
+}
+
+/// Create or replace a workflow.
+///
+/// The body is an API graph or a UI export, which is converted. Tenant-scoped
+/// keys write to their own namespace.
+#[utoipa::path(
+    put, path = "/workflows/{name}", tag = "workflows",
+    params(("name" = String, Path, description = "Workflow name")),
+    request_body = Graph,
+    responses(
+        (status = 200, description = "Replaced", body = WorkflowSaved),
+        (status = 201, description = "Created", body = WorkflowSaved),
+        (status = 400, description = "Invalid graph or reserved name", body = ErrorBody),
+    )
+)]
+pub async fn put_workflow(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(name): Path<String>,
+    Json(doc): Json<Value>,
+) -> AppResult<Response> {
+    if !is_valid_workflow_name(&name) {
+        return Err(AppError::BadRequest(format!("Invalid workflow name '{}'", name)));
+    }
+    let (graph, format) = to_api_graph(&doc)
+        .and_then(|(graph, format)| validate_api_graph(&graph).map(|_| (graph, format)))
+        .map_err(|e| AppError::BadRequest(format!("{}: {}", name, e)))?;
+    let node_count = graph.as_object().map(|o| o.len()).unwrap_or(0);
+    let mut scoped;
+    let mut shared;
+    let manager = match key.as_ref().and_then(|k| k.tenant()) {
+        Some(tenant) => { scoped = tenant_manager(&state, tenant).await?; &mut scoped }
+        None => { shared = state.workflow_manager.write().await; &mut *shared }
+    };
+    let created = !manager.has_workflow(&name);
+    manager.add_workflow(Some(name.clone()), Some(graph)).await.map_err(AppError::WorkflowManagement)?;
+    let status = if created { StatusCode::CREATED } else { StatusCode::OK };
+    Ok((status, Json(json!({"status": "success", "name": name, "format": format.as_str(), "nodes": node_count, "created": created}))).into_response())
+}
+
+/// Delete a workflow.
+///
+/// Tenant-scoped keys can only delete their own workflows, not shared ones.
+#[utoipa::path(
+    delete, path = "/workflows/{name}", tag = "workflows",
+    params(("name" = String, Path, description = "Workflow name")),
+    responses(
+        (status = 200, description = "Deleted", body = WorkflowDeleted),
+        (status = 404, description = "Unknown workflow", body = ErrorBody),
+    )
+)]
+pub async fn delete_workflow(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(name): Path<String>,
+) -> AppResult<Json<Value>> {
+    if !is_valid_workflow_name(&name) {
+        return Err(AppError::BadRequest(format!("Invalid workflow name '{}'", name)));
+    }
+    let deleted = match key.as_ref().and_then(|k| k.tenant()) {
+        Some(tenant) => WorkflowManager::with_dir(tenant_dir(&state.prompts_dir, Some(tenant))).delete_workflow(&name).await,
+        None => state.workflow_manager.write().await.delete_workflow(&name).await,
+    };
+    if !deleted.map_err(AppError::WorkflowManagement)? {
+        return Err(AppError::NotFound(format!("Workflow '{}' not found", name)));
+    }
+    Ok(Json(json!({"status": "success", "deleted": name})))
+}
+
+/// Import a workflow bundle (the raw archive as the request body). The
+/// workflow is saved under the bundle's name, or `?name=`; an existing
+/// workflow is only replaced with `?overwrite=true`.
+pub async fn import_workflow_bundle(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+    body: axum::body::Bytes,
+) -> AppResult<Json<Value>> {
+    let bundle = read_bundle(&body).map_err(AppError::BadRequest)?;
+    let name = params.get("name").cloned().unwrap_or_else(|| bundle.manifest.name.clone());
+    if !is_valid_workflow_name(&name) {
+        return Err(AppError::BadRequest(format!("Invalid workflow name '{}'", name)));
+    }
+    validate_api_graph(&bundle.workflow).map_err(|e| AppError::BadRequest(format!("{}: {}", name, e)))?;
+    let tenant = key.as_ref().and_then(|k| k.tenant());
+    let dir = tenant_dir(&state.prompts_dir, tenant);
+    let overwrite = params.get("overwrite").map(|v| v == "true" || v == "1").unwrap_or(false);
+    if !overwrite && std::path::Path::new(&format!("{}/{}.json", dir.trim_end_matches('/'), name)).exists() {
+        return Err(AppError::Conflict(format!("Workflow '{}' already exists; pass overwrite=true to replace it", name)));
+    }
+
+    let saved = match tenant {
+        Some(tenant) => tenant_manager(&state, tenant).await?.add_workflow(Some(name.clone()), Some(bundle.workflow)).await,
+        None => state.workflow_manager.write().await.add_workflow(Some(name.clone()), Some(bundle.workflow)).await,
+    };
+    saved.map_err(AppError::WorkflowManagement)?;
+    Ok(Json(json!({
+        "status": "success",
+        "name": name,
+        "node_count": bundle.manifest.node_count,
+        "nodes": bundle.manifest.nodes,
+        "models": bundle.manifest.models,
+        "defaults": bundle.defaults,
+    })))
+}
+
+/// Pull the latest workflows from `WORKFLOWS_GIT_URL` now, instead of
+/// waiting for the next scheduled sync.
+pub async fn sync_workflows(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+) -> AppResult<Json<Value>> {
+    // The checkout is the shared prompts directory, not a tenant namespace.
+    if key.as_ref().and_then(|k| k.tenant()).is_some() {
+        return Err(AppError::Forbidden("Tenant-scoped keys can't sync shared workflows".to_string()));
+    }
+    let sync = state.workflow_sync.as_ref()
+        .ok_or_else(|| AppError::Unavailable("POST /workflows/sync requires WORKFLOWS_GIT_URL to be configured".to_string()))?;
+    // Git failures are the remote's, like ComfyUI failures are ComfyUI's.
+    let outcome = sync.sync().await.map_err(AppError::ComfyUI)?;
+    Ok(Json(json!({
+        "status": "success",
+        "action": outcome.action,
+        "revision": outcome.revision,
+        "previous": outcome.previous,
+        "changed": outcome.changed,
+    })))
+}
+
+/// Maintenance mode and the jobs still in flight, so an operator can wait
+/// for them to drain before taking ComfyUI down.
+pub async fn maintenance_status(State(state): State<Arc<AppState>>) -> Json<Value> {
+    Json(maintenance_body(&state).await)
+}
+
+/// Turn maintenance mode on or off. Body: `{"enabled": bool}`, plus an
+/// optional `message` for rejected clients and `retry_after_secs`, the
+/// expected length of the window, when turning it on.
+pub async fn set_maintenance(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Json(payload): Json<Value>,
+) -> AppResult<Json<Value>> {
+    if key.as_ref().and_then(|k| k.tenant()).is_some() {
+        return Err(AppError::Forbidden("Tenant-scoped keys can't change maintenance mode".to_string()));
+    }
+    let invalid = |field: &str, message: &str| AppError::InvalidField { field: field.to_string(), message: message.to_string() };
+    let enabled = payload.get("enabled").and_then(|v| v.as_bool())
+        .ok_or_else(|| invalid("enabled", "'enabled' must be a boolean"))?;
+    let message = match payload.get("message") {
+        None | Some(Value::Null) => None,
+        Some(v) => Some(v.as_str().ok_or_else(|| invalid("message", "'message' must be a string"))?.to_string()),
+    };
+    let retry_after_secs = match payload.get("retry_after_secs") {
+        None | Some(Value::Null) => None,
+        Some(v) => Some(v.as_u64().ok_or_else(|| invalid("retry_after_secs", "'retry_after_secs' must be a non-negative integer"))?),
+    };
+    {
+        let mut maintenance = state.maintenance.write().await;
+        if enabled {
+            let now = now_ms();
+            let since_ms = maintenance.as_ref().map(|m| m.since_ms).unwrap_or(now);
+            *maintenance = Some(Maintenance { since_ms, message, until_ms: retry_after_secs.map(|s| now + s * 1000) });
+            tracing::warn!("Maintenance mode on; new jobs are refused");
+        } else if maintenance.take().is_some() {
+            tracing::warn!("Maintenance mode off");
+        }
+    }
+    Ok(Json(maintenance_body(&state).await))
+}
+
+/// Read from the job store alone, which [`follow_progress`] keeps current as
+/// prompts finish, so polling it never waits on ComfyUI.
+async fn maintenance_body(state: &AppState) -> Value {
+    let in_flight = state.job_store.read().await.iter().filter(|j| !j.is_finished()).count();
+    let maintenance = state.maintenance.read().await.clone();
+    json!({
+        "enabled": maintenance.is_some(),
+        "since_ms": maintenance.as_ref().map(|m| m.since_ms),
+        "message": maintenance.as_ref().and_then(|m| m.message.clone()),
+        "retry_at_ms": maintenance.as_ref().map(|m| m.retry_at_ms(now_ms())),
+        "in_flight": in_flight,
+    })
 }
 
+/// A node class's input schema (types, defaults, ranges, combo values) and
+/// outputs from ComfyUI's cached `/object_info`; `?refresh=true` re-fetches
+/// it first. Node info added through `WorkflowManager::add_node` is the
+/// fallback for classes ComfyUI doesn't report, or when it's unreachable.
 pub async fn get_node_info(
     State(state): State<Arc<AppState>>,
     Query(params): Query<std::collections::HashMap<String, String>>,
-) -> Result<Json<Value>, String> {
-    let node_type = params.get("node_type").ok_or("Node type is required")?;
-    state.workflow_manager.read().await.get_node_info(node_type)
-        .map(Json)
-        .ok_or_else(|| "Node type not found".to_string())
+) -> AppResult<Json<Value>> {
+    let node_type = params.get("node_type")
+        .ok_or_else(|| AppError::InvalidField { field: "node_type".to_string(), message: "Node type is required".to_string() })?;
+    let refresh = params.get("refresh").map(|v| v == "true" || v == "1").unwrap_or(false);
+    let manual = state.workflow_manager.read().await.get_node_info(node_type);
+    match state.object_info.get(&state.comfyui_client, refresh).await {
+        Ok(info) => object_info::node_schema(&info, node_type).or(manual),
+        Err(e) if manual.is_some() => {
+            tracing::warn!("Falling back to stored node info for '{}': {}", node_type, e);
+            manual
+        }
+        Err(e) => return Err(e),
+    }
+    .map(Json)
+    .ok_or_else(|| AppError::NotFound(format!("Node type '{}' not found", node_type)))
 }
 
 pub async fn construct_prompt(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<Value>,
-) -> Result<Json<Value>, String> {
-    let template = payload.get("template").ok_or("Template is required")?;
-    let inputs = payload.get("inputs").ok_or("Inputs are required")?;
+) -> AppResult<Json<Value>> {
+    let missing = |field: &str, message: &str| AppError::InvalidField { field: field.to_string(), message: message.to_string() };
+    let template = payload.get("template").ok_or_else(|| missing("template", "Template is required"))?;
+    let inputs = payload.get("inputs").ok_or_else(|| missing("inputs", "Inputs are required"))?;
     println!("Constructing prompt with template: {}", template);
     println!("Inputs: {}", inputs);
     state.prompt_constructor.read().await
         .construct_prompt(template, inputs)
         .map(Json)
-        .map_err(|e| e.to_string())
 }
 
-// Models: list categories
-pub async fn models_categories(
+/// The placeholders a stored template expects, including those of the
+/// templates it `extends`, with their defaults, so clients can build input
+/// forms for it.
+pub async fn template_params(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(name): Path<String>,
+) -> AppResult<Json<Value>> {
+    let (dir, doc) = read_stored_workflow(&state, key.as_deref(), &name).await?;
+    let params = PromptConstructor::with_dir(dir).extract_placeholders(&doc)
+        .map_err(|e| AppError::WorkflowManagement(format!("{}: {}", name, e)))?;
+    Ok(Json(json!({"template": name, "params": params})))
+}
+
+/// ComfyUI backends behind the proxy, with their detected version, devices,
+/// and the API features the proxy adapts to. `?refresh=true` re-detects.
+pub async fn backends(
     State(state): State<Arc<AppState>>,
     Query(params): Query<std::collections::HashMap<String, String>>,
-) -> Result<impl IntoResponse, String> {
-    let json_flag = params.get("json").map(|v| v == "true" || v == "1").unwrap_or(false);
-    let v = state.comfyui_client.get_model_categories().await.map_err(|e| e.to_string())?;
-    if json_flag {
-        Ok(Json(v).into_response())
-    } else if let Some(arr) = v.as_array() {
-        let mut lines = String::new();
-        for it in arr {
-            match it {
-                Value::String(s) => { lines.push_str(s); lines.push('\n'); }
-                _ => { lines.push_str(&it.to_string()); lines.push('\n'); }
-            }
-        }
-        Ok(lines.into_response())
-    } else {
-        Ok(serde_json::to_string_pretty(&v).unwrap_or_default().into_response())
-    }
+) -> Json<Value> {
+    let refresh = params.get("refresh").map(|v| v == "true" || v == "1").unwrap_or(false);
+    let infos = state.backends.infos(refresh).await;
+    let backends: Vec<Value> = infos.iter().zip(state.backends.loads())
+        .map(|(info, load)| {
+            let mut backend = json!(&**info);
+            backend["healthy"] = json!(load.healthy);
+            backend["queue_depth"] = json!(load.queue_depth);
+            backend
+        })
+        .collect();
+    Json(json!({"backends": backends}))
 }
 
-// Models: list for a category (e.g., checkpoints)
-pub async fn models_in_category(
+/// Files indexed under the static drive, oldest first: `?since=` keeps
+/// files modified at or after a Unix time in milliseconds, `?prefix=` those
+/// whose path starts with it, and `?fields=` trims each record. The drive
+/// holds every tenant's outputs, so tenant-scoped keys can't list it.
+pub async fn static_index(
     State(state): State<Arc<AppState>>,
-    Path(category): Path<String>,
+    key: Option<Extension<ApiKey>>,
     Query(params): Query<std::collections::HashMap<String, String>>,
-) -> Result<impl IntoResponse, String> {
-    let json_flag = params.get("json").map(|v| v == "true" || v == "1").unwrap_or(false);
-    let v = state.comfyui_client.get_models_in_category(&category).await.map_err(|e| e.to_string())?;
-    if json_flag {
-        Ok(Json(v).into_response())
-    } else if let Some(arr) = v.as_array() {
-        let mut lines = String::new();
-        for item in arr {
-            match item {
-                Value::String(s) => {
-                    lines.push_str(s);
-                    lines.push('\n');
-                }
-                Value::Object(o) => {
-                    if let Some(name) = o.get("name").and_then(|x| x.as_str()) {
-                        lines.push_str(name);
-                        lines.push('\n');
-                    } else {
-                        lines.push_str(&serde_json::to_string_pretty(item).unwrap_or_default());
-                        lines.push('\n');
-                    }
-                }
-                _ => {
-                    lines.push_str(&item.to_string());
-                    lines.push('\n');
-                }
-            }
+) -> AppResult<Json<Value>> {
+    if key.as_ref().and_then(|k| k.tenant()).is_some() {
+        return Err(AppError::Forbidden("Tenant-scoped keys can't list the static drive".to_string()));
+    }
+    let fields = fields::from_params(&params)?;
+    let since = match params.get("since").map(|s| s.parse::<u64>()) {
+        None => 0,
+        Some(Ok(ms)) => ms,
+        Some(Err(_)) => return Err(AppError::BadRequest("'since' must be a Unix time in milliseconds".to_string())),
+    };
+    let files = state.static_drive_poller.list(since, params.get("prefix").map(String::as_str)).await;
+    let mut body = json!({"total": files.len(), "files": files});
+    if let Some(fields) = fields {
+        fields::project_items(&mut body, "files", &fields);
+    }
+    Ok(Json(body))
+}
+
+/// `GET /static/*path`: a file from the static drive, so clients can fetch
+/// outputs without going through ComfyUI's `/view`. `ServeDir` handles
+/// content types, ranges, and `If-Modified-Since`. Hidden files (including
+/// the drive index) aren't served.
+pub async fn static_file(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+    Path(path): Path<String>,
+    req: axum::http::Request<axum::body::Body>,
+) -> AppResult<Response> {
+    if key.as_ref().and_then(|k| k.tenant()).is_some() {
+        return Err(AppError::Forbidden("Tenant-scoped keys can't read the static drive".to_string()));
+    }
+    let safe = std::path::Path::new(&path).components()
+        .all(|c| matches!(c, std::path::Component::Normal(part) if !part.to_string_lossy().starts_with('.')));
+    let meta = std::fs::metadata(state.static_drive_poller.root().join(&path)).ok().filter(|m| m.is_file());
+    let Some(meta) = meta.filter(|_| !path.is_empty() && safe) else {
+        return Err(AppError::NotFound(format!("File '{}' not found", path)));
+    };
+    // ServeDir handles ranges and dates but not entity tags. Files on the
+    // drive can be replaced under the same name, so caches revalidate.
+    let etag = conditional::file_etag(&meta);
+    let etag_header = etag.as_deref().and_then(|e| HeaderValue::from_str(e).ok());
+    let cache_control = HeaderValue::from_static("private, no-cache");
+    let (mut parts, body) = req.into_parts();
+    if conditional::is_not_modified(&parts.headers, etag.as_deref(), meta.modified().ok()) {
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        let headers = response.headers_mut();
+        headers.insert(header::CACHE_CONTROL, cache_control);
+        if let Some(value) = etag_header {
+            headers.insert(header::ETAG, value);
+        }
+        if let Some(value) = meta.modified().ok().and_then(|m| HeaderValue::from_str(&httpdate::fmt_http_date(m)).ok()) {
+            headers.insert(header::LAST_MODIFIED, value);
+        }
+        return Ok(response);
+    }
+    // Having checked If-None-Match, keep ServeDir from answering by date.
+    if parts.headers.contains_key(header::IF_NONE_MATCH) {
+        parts.headers.remove(header::IF_MODIFIED_SINCE);
+    }
+    if !conditional::range_applies(&parts.headers, etag.as_deref(), meta.modified().ok()) {
+        parts.headers.remove(header::RANGE);
+    }
+    // ServeDir resolves the request path against the drive root, so drop
+    // the `/static` prefix and keep the rest as sent (still percent-encoded).
+    let rest = parts.uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/")
+        .strip_prefix("/static").unwrap_or("/").to_string();
+    parts.uri = rest.parse().map_err(|_| AppError::BadRequest(format!("Invalid path '{}'", path)))?;
+    let response = ServeDir::new(state.static_drive_poller.root())
+        .oneshot(axum::http::Request::from_parts(parts, body))
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to read '{}': {}", path, e)))?;
+    if response.status() == StatusCode::OK && content_type_for(&path).starts_with("image/") {
+        state.metrics.image_served("static");
+    }
+    let mut response = response.map(axum::body::boxed);
+    if response.status().is_success() {
+        response.headers_mut().insert(header::CACHE_CONTROL, cache_control);
+        if let Some(value) = etag_header {
+            response.headers_mut().insert(header::ETAG, value);
         }
-        Ok(lines.into_response())
-    } else {
-        Ok(serde_json::to_string_pretty(&v).unwrap_or_default().into_response())
     }
+    Ok(response)
 }
 
-// Models: checkpoints convenience
-pub async fn models_checkpoints(
+/// Request, prompt, image, and upstream counters in the Prometheus text
+/// format. Tenant-scoped keys get 403, since the counts cover every tenant.
+pub async fn metrics(
+    State(state): State<Arc<AppState>>,
+    key: Option<Extension<ApiKey>>,
+) -> AppResult<Response> {
+    if key.as_ref().and_then(|k| k.tenant()).is_some() {
+        return Err(AppError::Forbidden("Tenant-scoped keys can't read proxy metrics".to_string()));
+    }
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], state.metrics.render()).into_response())
+}
+
+/// The OpenAPI document for the main endpoints.
+pub async fn openapi_json() -> Json<Value> {
+    Json(openapi::spec())
+}
+
+/// Swagger UI for `/openapi.json`.
+pub async fn swagger_ui() -> axum::response::Html<&'static str> {
+    axum::response::Html(openapi::SWAGGER_UI_HTML)
+}
+
+/// Swagger UI's scripts, stylesheet, and icons, from the vendored copy.
+pub async fn swagger_ui_asset(Path(file): Path<String>) -> AppResult<Response> {
+    let (content_type, data) = openapi::swagger_ui_asset(&file)
+        .ok_or_else(|| AppError::NotFound(format!("No Swagger UI file '{}'", file)))?;
+    Ok(([(header::CONTENT_TYPE, content_type), (header::CACHE_CONTROL, "public, max-age=86400")], data).into_response())
+}
+
+/// `/object_info`, when the backend has no `/models` endpoint and model
+/// lists have to be read off its loader nodes instead.
+async fn object_info_for_models(state: &AppState) -> AppResult<Option<Arc<Value>>> {
+    let backend = state.backends.primary_info(false).await;
+    if !backend.reachable || backend.features.models {
+        return Ok(None);
+    }
+    state.object_info.get(&state.comfyui_client, false).await.map(Some)
+}
+
+/// Model categories.
+#[utoipa::path(
+    get, path = "/models", tag = "models",
+    params(openapi::JsonFlag),
+    responses((status = 200, description = "One category per line, or a JSON array", content(("text/plain" = String), ("application/json" = Vec<String>))),)
+)]
+pub async fn models_categories(
     State(state): State<Arc<AppState>>,
     Query(params): Query<std::collections::HashMap<String, String>>,
-) -> Result<impl IntoResponse, String> {
+) -> AppResult<Response> {
     let json_flag = params.get("json").map(|v| v == "true" || v == "1").unwrap_or(false);
-    let v = state.comfyui_client.get_checkpoints().await.map_err(|e| e.to_string())?;
+    if let Some(info) = object_info_for_models(&state).await? {
+        let categories = object_info::model_categories_from_object_info(&info);
+        return Ok(if json_flag { Json(json!(categories)).into_response() } else { name_lines(&categories).into_response() });
+    }
     if json_flag {
-        Ok(Json(v).into_response())
-    } else if let Some(arr) = v.as_array() {
-        let mut lines = String::new();
-        for it in arr {
-            match it {
-                Value::String(s) => { lines.push_str(s); lines.push('\n'); }
-                _ => { lines.push_str(&it.to_string()); lines.push('\n'); }
-            }
-        }
-        Ok(lines.into_response())
-    } else {
-        Ok(serde_json::to_string_pretty(&v).unwrap_or_default().into_response())
+        let v = state.comfyui_client.get_model_categories_raw().await?;
+        return Ok(Json(v).into_response());
     }
+    let categories = state.comfyui_client.get_model_categories().await?;
+    Ok(name_lines(&categories).into_response())
 }
 
-// Helpers (duplicated from CLI to avoid coupling)
-fn collect_filenames_for_id(v: &Value, prompt_id: &str, out: &mut Vec<String>) {
-    match v {
-        Value::Object(map) => {
-            if let Some(entry) = map.get(prompt_id) { collect_any_filenames(entry, out); }
-            if let Some(hist) = map.get("history") { collect_filenames_for_id(hist, prompt_id, out); }
-            for (_k, vv) in map.iter() { collect_filenames_for_id(vv, prompt_id, out); }
-        }
-        Value::Array(arr) => { for vv in arr { collect_filenames_for_id(vv, prompt_id, out); } }
-        _ => {}
+/// Models in a category, e.g. loras.
+#[utoipa::path(
+    get, path = "/models/{category}", tag = "models",
+    params(("category" = String, Path, description = "Model category"), openapi::JsonFlag),
+    responses(
+        (status = 200, description = "One model per line, or a JSON array", content(("text/plain" = String), ("application/json" = Vec<String>))),
+        (status = 404, description = "Category can't be listed on this ComfyUI version", body = ErrorBody),
+    )
+)]
+pub async fn models_in_category(
+    State(state): State<Arc<AppState>>,
+    Path(category): Path<String>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+) -> AppResult<Response> {
+    let json_flag = params.get("json").map(|v| v == "true" || v == "1").unwrap_or(false);
+    if let Some(info) = object_info_for_models(&state).await? {
+        let names = object_info::models_from_object_info(&info, &category)
+            .ok_or_else(|| AppError::NotFound(format!("Model category '{}' can't be listed on this ComfyUI version", category)))?;
+        return Ok(if json_flag { Json(json!(names)).into_response() } else { name_lines(&names).into_response() });
+    }
+    if json_flag {
+        let v = state.comfyui_client.get_models_in_category_raw(&category).await?;
+        return Ok(Json(v).into_response());
     }
+    let models = state.comfyui_client.get_models_in_category(&category).await?;
+    Ok(name_lines(&models.names).into_response())
 }
 
-fn collect_any_filenames(v: &Value, out: &mut Vec<String>) {
-    match v {
-        Value::Object(map) => {
-            for (k, vv) in map.iter() {
-                if k == "filename" { if let Value::String(s) = vv { out.push(s.clone()); } }
-                collect_any_filenames(vv, out);
-            }
-        }
-        Value::Array(arr) => { for vv in arr { collect_any_filenames(vv, out); } }
-        _ => {}
-    }
+/// Checkpoint names (values for ckpt_name).
+#[utoipa::path(
+    get, path = "/models/checkpoints", tag = "models",
+    params(openapi::JsonFlag),
+    responses((status = 200, description = "One checkpoint per line, or a JSON array", content(("text/plain" = String), ("application/json" = Vec<String>))),)
+)]
+pub async fn models_checkpoints(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+) -> AppResult<Response> {
+    models_in_category(State(state), Path("checkpoints".to_string()), Query(params)).await
 }
 
-fn collect_prompt_ids(v: &Value, out: &mut Vec<String>) {
-    match v {
-        Value::Object(map) => {
-            for (k, vv) in map.iter() {
-                if k.len() >= 8 && vv.is_object() { out.push(k.clone()); }
-                if k == "history" { collect_prompt_ids(vv, out); }
-            }
-        }
-        Value::Array(arr) => { for vv in arr { collect_prompt_ids(vv, out); } }
-        _ => {}
-    }
+/// One name per line, for the friendly (non-JSON) listings.
+fn name_lines(names: &[String]) -> String {
+    names.iter().map(|n| format!("{}\n", n)).collect()
 }
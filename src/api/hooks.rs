@@ -0,0 +1,98 @@
+//! Lifecycle hooks for embedders.
+//!
+//! Library users who build the router themselves can run their own code
+//! when a job is queued, when it finishes, and for each output it saves,
+//! without forking the handlers. Register on the state before building the
+//! router:
+//!
+//! ```no_run
+//! # use comfyui_api_proxy::{api::routes, Config, ComfyUIClient};
+//! # let config = Config::new().unwrap();
+//! let state = routes::build_state(&config, ComfyUIClient::from_config(&config));
+//! state.hooks.on_job_completed(|job| async move {
+//!     println!("job {} is {:?}", job.id, job.state());
+//! });
+//! let app = routes::build_router(state);
+//! ```
+//!
+//! Hooks get a snapshot of the job and run on their own task, so a slow
+//! hook never holds up a response and a panicking one only ends its task.
+//! The job store runs them as it records each change (see
+//! [`JobStore::set_hooks`](crate::jobs::JobStore::set_hooks)), whichever
+//! path made it.
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+
+use crate::jobs::{Job, OutputFile};
+
+pub type HookFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+type JobHook = Arc<dyn Fn(Job) -> HookFuture + Send + Sync>;
+type OutputHook = Arc<dyn Fn(Job, OutputFile) -> HookFuture + Send + Sync>;
+
+#[derive(Default)]
+pub struct LifecycleHooks {
+    job_queued: RwLock<Vec<JobHook>>,
+    job_completed: RwLock<Vec<JobHook>>,
+    output_saved: RwLock<Vec<OutputHook>>,
+}
+
+impl LifecycleHooks {
+    /// Run `hook` once ComfyUI has accepted a job's prompt.
+    pub fn on_job_queued<F, Fut>(&self, hook: F)
+    where
+        F: Fn(Job) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        write(&self.job_queued).push(Arc::new(move |job| Box::pin(hook(job))));
+    }
+
+    /// Run `hook` when a job finishes, however it ends (see [`Job::state`]):
+    /// completed, failed in ComfyUI, cancelled, removed from the queue, or
+    /// never sent. Runs after its [`on_output_saved`](Self::on_output_saved)
+    /// hooks.
+    pub fn on_job_completed<F, Fut>(&self, hook: F)
+    where
+        F: Fn(Job) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        write(&self.job_completed).push(Arc::new(move |job| Box::pin(hook(job))));
+    }
+
+    /// Run `hook` for each output a job saves: the images in its history
+    /// once it finishes, and files added later such as grid cells and
+    /// animations.
+    pub fn on_output_saved<F, Fut>(&self, hook: F)
+    where
+        F: Fn(Job, OutputFile) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        write(&self.output_saved).push(Arc::new(move |job, output| Box::pin(hook(job, output))));
+    }
+
+    pub(crate) fn job_queued(&self, job: &Job) {
+        for hook in read(&self.job_queued).iter() {
+            tokio::spawn(hook(job.clone()));
+        }
+    }
+
+    pub(crate) fn job_completed(&self, job: &Job) {
+        for hook in read(&self.job_completed).iter() {
+            tokio::spawn(hook(job.clone()));
+        }
+    }
+
+    pub(crate) fn output_saved(&self, job: &Job, output: &OutputFile) {
+        for hook in read(&self.output_saved).iter() {
+            tokio::spawn(hook(job.clone(), output.clone()));
+        }
+    }
+}
+
+fn read<T>(lock: &RwLock<T>) -> std::sync::RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|e| e.into_inner())
+}
+
+fn write<T>(lock: &RwLock<T>) -> std::sync::RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|e| e.into_inner())
+}
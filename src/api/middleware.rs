@@ -0,0 +1,146 @@
+//! Request middleware for the HTTP API.
+use axum::body::{boxed, Full};
+use axum::extract::{MatchedPath, State};
+use axum::http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::api::routes::AppState;
+use crate::auth::keys::key_from_headers;
+use crate::auth::ApiKey;
+use crate::error::AppError;
+use crate::metrics::UNMATCHED_ROUTE;
+use crate::reporting::{self, RequestContext};
+use crate::utils::time::now_ms;
+
+/// Reject requests without a known API key (when keys are configured) and
+/// make the matching [`ApiKey`](crate::auth::ApiKey) available to handlers
+/// as a request extension.
+pub async fn require_api_key<B>(State(state): State<Arc<AppState>>, mut req: Request<B>, next: Next<B>) -> Response {
+    if !state.api_keys.is_enabled() {
+        return next.run(req).await;
+    }
+    let key = key_from_headers(req.headers()).and_then(|k| state.api_keys.lookup(k)).cloned();
+    match key {
+        Some(key) => {
+            req.extensions_mut().insert(key);
+            next.run(req).await
+        }
+        None => AppError::Unauthorized("Missing or invalid API key".to_string()).into_response(),
+    }
+}
+
+/// Header naming the client app behind a request, recorded on the jobs it
+/// submits.
+pub const CLIENT_HEADER: &str = "x-client";
+/// Longest `X-Client` value accepted.
+const MAX_CLIENT_LEN: usize = 64;
+
+/// The client app named by a request's `X-Client` header, e.g.
+/// `storyboard-ui/2.1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientName(pub String);
+
+/// Make a request's `X-Client` header available to handlers as a
+/// [`ClientName`] extension. Values that aren't printable ASCII or are longer
+/// than 64 characters are refused with 400, so job listings stay readable.
+pub async fn identify_client<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let Some(value) = req.headers().get(CLIENT_HEADER) else { return next.run(req).await };
+    let name = value.to_str().ok()
+        .map(str::trim)
+        .filter(|v| !v.is_empty() && v.len() <= MAX_CLIENT_LEN && v.chars().all(|c| c.is_ascii_graphic() || c == ' '));
+    match name {
+        Some(name) => {
+            let name = ClientName(name.to_string());
+            req.extensions_mut().insert(name);
+            next.run(req).await
+        }
+        None => AppError::InvalidField {
+            field: "X-Client".to_string(),
+            message: format!("X-Client must be 1 to {} printable ASCII characters", MAX_CLIENT_LEN),
+        }.into_response(),
+    }
+}
+
+/// Refuse requests over the caller's per-minute rate with 429 and
+/// `Retry-After`. Runs after [`require_api_key`]; requests without a key
+/// share one bucket.
+pub async fn rate_limit<B>(State(state): State<Arc<AppState>>, req: Request<B>, next: Next<B>) -> Response {
+    let key = req.extensions().get::<ApiKey>();
+    let limit = key.map(|k| k.rate_limit.or(state.rate_limits)).unwrap_or(state.rate_limits);
+    let bucket = key.map(|k| k.key.as_str()).unwrap_or("");
+    match state.rate_limiter.check(bucket, &limit, now_ms()) {
+        Ok(()) => next.run(req).await,
+        Err(limited) => AppError::from(limited).into_response(),
+    }
+}
+
+/// Attach the request's method, route, API key, and client to error
+/// reports (see [`crate::reporting`]) for its panics and 5xx errors. Runs
+/// inside [`require_api_key`] and [`identify_client`] so both are known.
+pub async fn report_errors<B>(req: Request<B>, next: Next<B>) -> Response {
+    if !reporting::is_enabled() {
+        return next.run(req).await;
+    }
+    let key = req.extensions().get::<ApiKey>();
+    let context = RequestContext {
+        method: req.method().to_string(),
+        route: req.extensions().get::<MatchedPath>().map(|p| p.as_str().to_string()).unwrap_or_else(|| UNMATCHED_ROUTE.to_string()),
+        key_name: key.and_then(|k| k.name.clone()),
+        tenant: key.and_then(|k| k.tenant.clone()),
+        client: req.extensions().get::<ClientName>().map(|c| c.0.clone()),
+    };
+    reporting::reported(context, next.run(req)).await
+}
+
+/// Count each request and time it for `/metrics`, labelled by the route
+/// that matched rather than the raw path.
+pub async fn track_metrics<B>(State(state): State<Arc<AppState>>, req: Request<B>, next: Next<B>) -> Response {
+    let route = req.extensions().get::<MatchedPath>().map(|p| p.as_str().to_string()).unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+    let method = req.method().to_string();
+    let started = Instant::now();
+    let response = next.run(req).await;
+    state.metrics.observe_request(&method, &route, response.status().as_u16(), started.elapsed());
+    response
+}
+
+/// Strong entity tag for a response body.
+pub fn etag_for(body: &[u8]) -> String {
+    format!("\"{}\"", hex::encode(&Sha256::digest(body)[..16]))
+}
+
+/// Whether the request's `If-None-Match` lists `etag` (or is `*`).
+pub(crate) fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers.get_all(header::IF_NONE_MATCH).iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+/// Tag successful `GET` responses with an `ETag` and answer `304 Not
+/// Modified` when the client already has that version, so pollers don't
+/// re-download unchanged resources.
+pub async fn etag<B>(req: Request<B>, next: Next<B>) -> Response {
+    let conditional = matches!(*req.method(), Method::GET | Method::HEAD);
+    let request_headers = req.headers().clone();
+    let response = next.run(req).await;
+    if !conditional || response.status() != StatusCode::OK {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => return AppError::Internal(format!("Failed to buffer response: {}", e)).into_response(),
+    };
+    let etag = etag_for(&bytes);
+    let Ok(value) = HeaderValue::from_str(&etag) else { return Response::from_parts(parts, boxed(Full::from(bytes))) };
+    if if_none_match(&request_headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, value)]).into_response();
+    }
+    parts.headers.insert(header::ETAG, value);
+    Response::from_parts(parts, boxed(Full::from(bytes)))
+}
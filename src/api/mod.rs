@@ -1,2 +1,7 @@
+pub mod conditional;
+pub mod fields;
 pub mod handlers;
-pub mod routes;
\ No newline at end of file
+pub mod hooks;
+pub mod middleware;
+pub mod openapi;
+pub mod routes;
@@ -0,0 +1,628 @@
+//! OpenAPI 3.0 description of the main endpoints, served at
+//! `GET /openapi.json`, and a Swagger UI page for it at `GET /docs`.
+//!
+//! The spec is generated with utoipa from the `#[utoipa::path]` annotations
+//! on the handlers. Most handlers build their JSON with `json!`, so the
+//! schemas of those bodies are declared here; types the API serializes as
+//! they are (job state, input changes, trash entries, favorites, history
+//! records) derive theirs where they're defined. Covers queueing, workflows,
+//! models, history, and job status; the other endpoints are described in the
+//! README. Payloads the proxy passes through from ComfyUI (prompt graphs,
+//! history entries) are open objects.
+//!
+//! Swagger UI is served from the copy vendored in
+//! `utoipa-swagger-ui-vendored`, so the docs work without internet access.
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Cursor, Read};
+use std::sync::OnceLock;
+use utoipa::openapi::path::PathItemType;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{Content, Ref};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+
+use crate::api::handlers;
+use crate::comfyui::trash::TrashEntry;
+use crate::jobs::history::HistoryRecord;
+use crate::jobs::store::{JobState, OutputFile};
+use crate::prompt::seeds::FavoriteSeed;
+use crate::workflow::diff::InputChange;
+use crate::workflow::validate::NodeIssue;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "ComfyUI API Proxy",
+        description = "Queue ComfyUI workflows by name with typed params, manage stored workflows, and read models, history, and job status.",
+    ),
+    paths(
+        handlers::queue_prompt,
+        handlers::queue_batch,
+        handlers::sweep,
+        handlers::img2img,
+        handlers::job_status,
+        handlers::cancel_job,
+        handlers::rerun_job,
+        handlers::favorite_job,
+        handlers::list_seeds,
+        handlers::delete_seed,
+        handlers::list_workflows,
+        handlers::get_workflow,
+        handlers::put_workflow,
+        handlers::delete_workflow,
+        handlers::upload_workflow,
+        handlers::convert_workflow,
+        handlers::validate_workflow,
+        handlers::models_categories,
+        handlers::models_checkpoints,
+        handlers::models_in_category,
+        handlers::history_friendly,
+        handlers::get_history,
+        handlers::history_export,
+        handlers::outputs_zip,
+        handlers::trash_outputs,
+        handlers::restore_outputs,
+        handlers::list_trash,
+    ),
+    components(schemas(
+        ErrorBody, ErrorDetail,
+        Graph, Params, SeedParam, NormalizeWeights, WeightStyle, Variation, DisableMode, GridSize,
+        QueuePromptRequest, QueuePromptResponse, EstimatedCost, DryRunResponse, QueuePromptResult, InputChange,
+        QueueBatchRequest, QueueBatchResponse, SweepRequest, SweepResponse, SweepCombination,
+        Img2ImgRequest, Img2ImgUpload, Img2ImgResponse, UploadedImage,
+        JobState, JobStatus, JobOutputs, JobOutputLink, JobCost, JobCancelled, CancelAction,
+        FavoriteRequest, FavoriteSeed, FavoriteSaved, FavoriteList,
+        WorkflowList, WorkflowSummary, WorkflowScope, GraphFormat, WorkflowSaved, WorkflowDeleted,
+        WorkflowUpload, WorkflowsUploaded, UploadedWorkflow, ConvertResponse,
+        ValidateRequest, StoredWorkflow, NodeIssue, ValidateResponse,
+        HistoryEntries, HistoryRecord, OutputFile,
+        TrashEntry, TrashedOutputs, RestoredOutputs, TrashList,
+    )),
+    modifiers(&Extras),
+    security(("apiKey" = []), ("bearer" = [])),
+    tags((name = "prompts"), (name = "jobs"), (name = "workflows"), (name = "models"), (name = "history")),
+)]
+pub struct ApiDoc;
+
+/// What the annotations can't express: the API key schemes, and
+/// `/img2img`'s multipart body next to its JSON one.
+struct Extras;
+
+impl Modify for Extras {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("apiKey", SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))));
+        components.add_security_scheme("bearer", SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()));
+
+        let img2img = openapi.paths.paths.get_mut("/img2img")
+            .and_then(|item| item.operations.get_mut(&PathItemType::Post))
+            .and_then(|operation| operation.request_body.as_mut());
+        if let Some(body) = img2img {
+            body.content.insert("multipart/form-data".to_string(), Content::new(Ref::from_schema_name("Img2ImgUpload")));
+        }
+    }
+}
+
+/// The proxy's OpenAPI document.
+pub fn spec() -> Value {
+    serde_json::to_value(ApiDoc::openapi()).unwrap_or_default()
+}
+
+/// `?dry_run=true` on the queueing endpoints.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DryRunQuery {
+    /// Build and validate without uploading or queueing anything
+    pub dry_run: Option<bool>,
+}
+
+/// The optional `X-Client` header recorded on queued jobs.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Header)]
+pub struct ClientHeader {
+    /// Name of the client app, recorded on the job (printable ASCII, at most 64 characters)
+    #[serde(rename = "X-Client")]
+    #[param(example = "storyboard-ui/2.1")]
+    pub client: Option<String>,
+}
+
+/// `?json=true` on the friendly listings.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct JsonFlag {
+    /// Return JSON instead of one name per line
+    pub json: Option<bool>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FieldsQuery {
+    /// Comma-separated dot paths to keep in each item (see Field selection in the README)
+    pub fields: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    pub error: ErrorDetail,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorDetail {
+    #[schema(example = "invalid_field")]
+    pub code: String,
+    pub message: String,
+    /// The request field at fault
+    pub field: Option<String>,
+    /// When a quota resets
+    pub reset_at_ms: Option<u64>,
+    /// When a retry can succeed
+    pub retry_at_ms: Option<u64>,
+}
+
+/// A ComfyUI prompt graph keyed by node id, a UI export, or a {"prompt": graph} document
+#[derive(Serialize, ToSchema)]
+pub struct Graph(pub HashMap<String, Value>);
+
+/// Values applied to every node input of the same name
+#[derive(Serialize, ToSchema)]
+pub struct Params {
+    /// A seed, or "favorite:<name>" for a favorite's
+    pub seed: Option<SeedParam>,
+    pub steps: Option<u32>,
+    pub cfg: Option<f64>,
+    pub sampler_name: Option<String>,
+    pub scheduler: Option<String>,
+    pub denoise: Option<f64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub batch_size: Option<u32>,
+    pub ckpt_name: Option<String>,
+    pub vae_name: Option<String>,
+    pub clip_skip: Option<i32>,
+    pub upscale_method: Option<String>,
+    pub upscale_by: Option<f64>,
+    /// Input image for the init image LoadImage node (the only one, one titled Init Image, or the one feeding a VAEEncode), e.g. an /upload_image name
+    pub image: Option<String>,
+    /// Applied to every text node, or split on TEXT_DELIMITER
+    pub text: Option<String>,
+    pub text_positive: Option<String>,
+    pub text_negative: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum SeedParam {
+    Seed(u64),
+    Favorite(String),
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum NormalizeWeights {
+    Enabled(bool),
+    Style(WeightStyle),
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WeightStyle {
+    A1111,
+    Comfy,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Variation {
+    Subtle,
+    Moderate,
+    Strong,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DisableMode {
+    Bypass,
+    Mute,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct GridSize {
+    #[schema(minimum = 1, maximum = 16)]
+    pub rows: u32,
+    #[schema(minimum = 1, maximum = 16)]
+    pub cols: u32,
+}
+
+/// Params may also be given at the top level.
+#[derive(Serialize, ToSchema)]
+pub struct QueuePromptRequest {
+    /// Stored workflow name; DEFAULT_WORKFLOW when neither this nor prompt is given
+    pub workflow: Option<String>,
+    pub prompt: Option<Graph>,
+    pub params: Option<Params>,
+    #[schema(example = json!(["2.inputs.seed=123"]))]
+    pub sets: Option<Vec<String>>,
+    pub filename_prefix: Option<String>,
+    /// Appended to every SaveImage filename_prefix as <prefix>_<suffix>
+    pub filename_suffix: Option<String>,
+    pub dry_run: Option<bool>,
+    #[schema(example = "16:9")]
+    pub aspect_ratio: Option<String>,
+    pub base_size: Option<u32>,
+    pub snap_resolution: Option<bool>,
+    pub normalize_weights: Option<NormalizeWeights>,
+    pub variation: Option<Variation>,
+    pub detail_faces: Option<bool>,
+    pub groups: Option<BTreeMap<String, bool>>,
+    #[schema(example = json!(["12", "title:Upscale", "class:PreviewImage"]))]
+    pub disable_nodes: Option<Vec<String>>,
+    pub disable_mode: Option<DisableMode>,
+    pub inject_vae: Option<bool>,
+    pub split_grid: Option<GridSize>,
+    pub text_delimiter: Option<String>,
+    /// Values for a stored workflow's {{placeholders}}, over its defaults
+    pub inputs: Option<HashMap<String, Value>>,
+    #[serde(flatten)]
+    pub top_level: Params,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct QueuePromptResponse {
+    /// Unset while the prompt is held (FAIR_QUEUE_DEPTH, WORKFLOW_LIMITS)
+    pub prompt_id: Option<String>,
+    pub number: Option<u64>,
+    pub node_errors: Option<HashMap<String, Value>>,
+    pub job_id: String,
+    /// Predicted /jobs/<job_id>/outputs/<index> URLs
+    pub outputs: Vec<String>,
+    pub estimated_cost: Option<EstimatedCost>,
+    pub applied: Option<Vec<InputChange>>,
+    pub warnings: Option<Vec<String>>,
+    /// Held by the proxy until ComfyUI's queue has room
+    pub held: Option<bool>,
+    /// Held prompts ahead of this one
+    pub position: Option<usize>,
+    /// Why the prompt is held, when a workflow limit holds it
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct EstimatedCost {
+    pub amount: f64,
+    pub currency: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DryRunResponse {
+    pub dry_run: bool,
+    pub workflow: Option<String>,
+    pub prompt: Graph,
+    pub changes: Vec<InputChange>,
+    pub applied: Option<Vec<InputChange>>,
+    pub warnings: Option<Vec<String>>,
+}
+
+/// Queued, or with dry_run, the prompt that would be queued
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum QueuePromptResult {
+    Queued(QueuePromptResponse),
+    DryRun(DryRunResponse),
+}
+
+/// Shared settings, each prompt merged over them (params and groups key by
+/// key, sets appended). A bare array of prompts is accepted too.
+#[derive(Serialize, ToSchema)]
+#[schema(example = json!({"workflow": "sdxlapi", "steps": 30, "prompts": [{"seed": 1}, {"seed": 2}]}))]
+pub struct QueueBatchRequest {
+    #[schema(min_items = 1, max_items = 100)]
+    pub prompts: Vec<QueuePromptRequest>,
+    #[serde(flatten)]
+    pub shared: QueuePromptRequest,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct QueueBatchResponse {
+    pub status: String,
+    pub total: usize,
+    pub job_ids: Vec<String>,
+    pub jobs: Vec<QueuePromptResponse>,
+}
+
+/// A queue_prompt body whose params may be lists of values, or
+/// {"count": n, "start"?: s} for seed. One prompt is queued per combination.
+#[derive(Serialize, ToSchema)]
+#[schema(example = json!({"workflow": "sdxlapi", "cfg": [5, 7, 9], "seed": {"count": 4}}))]
+pub struct SweepRequest {
+    #[serde(flatten)]
+    pub request: QueuePromptRequest,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SweepResponse {
+    pub combinations: Vec<SweepCombination>,
+    #[serde(flatten)]
+    pub batch: QueueBatchResponse,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SweepCombination {
+    pub values: HashMap<String, Value>,
+    #[schema(example = "cfg-7_seed-1234")]
+    pub tag: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[schema(example = json!({"workflow": "img2img", "denoise": 0.55, "init_image": "data:image/png;base64,iVBORw0KGgo..."}))]
+pub struct Img2ImgRequest {
+    /// The image, base64-encoded or as a data: URL
+    pub init_image: String,
+    /// Filename to upload it as; defaults to img2img.<ext>
+    pub init_image_name: Option<String>,
+    #[serde(flatten)]
+    pub request: QueuePromptRequest,
+}
+
+/// Fields other than image and payload are set as top-level params, e.g. denoise=0.55.
+#[derive(Serialize, ToSchema)]
+pub struct Img2ImgUpload {
+    #[schema(value_type = String, format = Binary)]
+    pub image: Vec<u8>,
+    /// A queue_prompt body as JSON
+    pub payload: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Img2ImgResponse {
+    pub image: UploadedImage,
+    #[serde(flatten)]
+    pub queued: QueuePromptResponse,
+}
+
+/// Where ComfyUI stored the init image
+#[derive(Serialize, ToSchema)]
+pub struct UploadedImage {
+    pub name: String,
+    pub subfolder: Option<String>,
+    #[serde(rename = "type")]
+    pub folder_type: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct JobStatus {
+    pub job_id: String,
+    pub prompt_id: Option<String>,
+    pub workflow: Option<String>,
+    /// X-Client of the request that queued the job
+    pub client: Option<String>,
+    pub created_at_ms: u64,
+    pub state: JobState,
+    pub outputs: JobOutputs,
+    pub cost: JobCost,
+    /// With wait, whether the state changed
+    pub changed: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct JobOutputs {
+    pub expected: u64,
+    pub saved: u64,
+    pub files: Vec<JobOutputLink>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct JobOutputLink {
+    pub filename: String,
+    pub subfolder: Option<String>,
+    pub node: String,
+    /// Caption from the static drive's `caption` action, once it has run on
+    /// the harvested copy.
+    pub caption: Option<String>,
+    pub url: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct JobCost {
+    pub estimated: Option<f64>,
+    pub actual: Option<f64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct JobCancelled {
+    pub status: String,
+    pub job_id: String,
+    pub action: CancelAction,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelAction {
+    Dequeued,
+    Interrupted,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FavoriteRequest {
+    /// Defaults to the job id
+    pub name: Option<String>,
+    /// Index of the liked output; defaults to 0
+    pub output: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FavoriteSaved {
+    pub status: String,
+    pub favorite: FavoriteSeed,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FavoriteList {
+    pub favorites: Vec<FavoriteSeed>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WorkflowList {
+    pub total: usize,
+    pub workflows: Vec<WorkflowSummary>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WorkflowSummary {
+    pub name: String,
+    pub size: u64,
+    pub modified_ms: u64,
+    pub scope: Option<WorkflowScope>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkflowScope {
+    Shared,
+    Tenant,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GraphFormat {
+    Api,
+    Ui,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WorkflowSaved {
+    pub status: String,
+    pub name: String,
+    pub format: GraphFormat,
+    pub nodes: usize,
+    pub created: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WorkflowDeleted {
+    pub status: String,
+    pub deleted: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WorkflowUpload {
+    #[schema(value_type = Vec<String>, format = Binary)]
+    pub file: Vec<Vec<u8>>,
+    /// Name for a single uploaded file
+    pub name: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WorkflowsUploaded {
+    pub status: String,
+    pub workflows: Vec<UploadedWorkflow>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UploadedWorkflow {
+    pub name: String,
+    pub format: GraphFormat,
+    pub nodes: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ConvertResponse {
+    pub format: GraphFormat,
+    pub nodes: usize,
+    pub prompt: Graph,
+}
+
+/// A stored workflow's name, a {"prompt": graph} document, or the graph itself
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum ValidateRequest {
+    Stored(StoredWorkflow),
+    Graph(Graph),
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct StoredWorkflow {
+    pub workflow: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ValidateResponse {
+    pub workflow: Option<String>,
+    pub valid: bool,
+    pub errors: Vec<NodeIssue>,
+}
+
+/// ComfyUI history entries keyed by prompt id
+#[derive(Serialize, ToSchema)]
+pub struct HistoryEntries(pub HashMap<String, HashMap<String, Value>>);
+
+#[derive(Serialize, ToSchema)]
+pub struct TrashedOutputs {
+    pub status: String,
+    pub trashed: TrashEntry,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RestoredOutputs {
+    pub status: String,
+    pub restored: TrashEntry,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TrashList {
+    pub total: usize,
+    pub trash: Vec<TrashEntry>,
+}
+
+/// Swagger UI pointed at `/openapi.json`; its assets are under `/docs/`.
+pub const SWAGGER_UI_HTML: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>ComfyUI API Proxy</title>
+  <link rel="stylesheet" href="/docs/swagger-ui.css">
+  <link rel="icon" type="image/png" href="/docs/favicon-32x32.png">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="/docs/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+/// Swagger UI files served under `/docs/`, with their content types.
+const SWAGGER_UI_ASSETS: &[(&str, &str)] = &[
+    ("swagger-ui.css", "text/css"),
+    ("swagger-ui-bundle.js", "text/javascript"),
+    ("favicon-16x16.png", "image/png"),
+    ("favicon-32x32.png", "image/png"),
+];
+
+/// A Swagger UI asset by file name, and its content type. The vendored
+/// archive is unpacked once, on first use.
+pub fn swagger_ui_asset(name: &str) -> Option<(&'static str, &'static [u8])> {
+    static ASSETS: OnceLock<HashMap<&'static str, Vec<u8>>> = OnceLock::new();
+    let assets = ASSETS.get_or_init(|| {
+        let mut assets = HashMap::new();
+        let Ok(mut archive) = zip::ZipArchive::new(Cursor::new(utoipa_swagger_ui_vendored::SWAGGER_UI_VENDORED)) else {
+            tracing::warn!("Vendored Swagger UI archive is unreadable");
+            return assets;
+        };
+        for i in 0..archive.len() {
+            let Ok(mut file) = archive.by_index(i) else { continue };
+            // Entries are named `swagger-ui-<version>/dist/<file>`.
+            let name = file.name().split_once('/').and_then(|(_, rest)| rest.strip_prefix("dist/"));
+            let Some(asset) = name.and_then(|n| SWAGGER_UI_ASSETS.iter().find(|(a, _)| *a == n)) else { continue };
+            let mut data = Vec::new();
+            if file.read_to_end(&mut data).is_ok() {
+                assets.insert(asset.0, data);
+            }
+        }
+        assets
+    });
+    let (file, content_type) = SWAGGER_UI_ASSETS.iter().find(|(a, _)| *a == name)?;
+    assets.get(file).map(|data| (*content_type, data.as_slice()))
+}
@@ -1,44 +1,315 @@
 //! HTTP router setup for the Axum server.
 use axum::{
-    routing::{get, post},
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{delete, get, post},
     Router,
 };
 use std::sync::Arc;
+use tower_http::catch_panic::CatchPanicLayer;
 use tokio::sync::RwLock;
 
+use crate::admin::maintenance::Maintenance;
+use crate::comfyui::backend::BackendPool;
 use crate::comfyui::client::ComfyUIClient;
+use crate::comfyui::inputs::InputLibrary;
+use crate::comfyui::object_info::ObjectInfoCache;
+use crate::comfyui::trash::OutputTrash;
+use crate::comfyui::ws::ProgressHub;
+use crate::events::EventBus;
+use crate::metrics::Metrics;
 use crate::prompt::constructor::PromptConstructor;
+use crate::prompt::negative::NegativePrompts;
+use crate::prompt::resolution::ResolutionRules;
+use crate::prompt::seeds::SeedJournal;
+use crate::prompt::validator::TextLimits;
 use crate::workflow::manager::WorkflowManager;
-use crate::api::handlers;  // Import the handlers
+use crate::workflow::preflight::PreflightReport;
+use crate::workflow::sync::WorkflowSync;
+use crate::api::handlers::{self, HeldPrompts, LOST_HELD_PROMPT};  // Import the handlers
+use crate::api::hooks::LifecycleHooks;
+use crate::api::middleware::{etag, identify_client, rate_limit, report_errors, require_api_key, track_metrics};
+use crate::reporting;
+use crate::auth::{ApiKeys, RateLimit, RateLimiter, UsageTracker};
+use crate::utils::image_resize::ResizeDefaults;
+use crate::utils::prompt_ops::TitlePatterns;
+use crate::utils::harvest::OutputHarvester;
 use crate::utils::static_drive_poller::StaticDrivePoller;
+use crate::utils::time::now_ms;
 use crate::config::Config;
+use crate::jobs::JobStore;
+use crate::jobs::batch::BatchLimits;
+use crate::jobs::cost::CostModel;
+use crate::jobs::limits::WorkflowLimits;
 
+/// Largest history dump `POST /jobs/import` accepts.
+const MAX_IMPORT_BYTES: usize = 64 * 1024 * 1024;
+/// Largest input image `POST /upload_image` accepts.
+const MAX_IMAGE_UPLOAD_BYTES: usize = 32 * 1024 * 1024;
+/// Largest `POST /img2img` body: the image limit, base64-encoded, plus room
+/// for the payload.
+const MAX_IMG2IMG_BYTES: usize = MAX_IMAGE_UPLOAD_BYTES / 3 * 4 + 1024 * 1024;
 
+/// State shared by every handler: ComfyUI clients, job and workflow
+/// stores, limits, and settings, built from a [`Config`] by
+/// [`AppState::new`]. Handlers, including an embedder's own routes mounted
+/// with [`build_router_with`], receive it as `State<Arc<AppState>>`.
 pub struct AppState {
     pub prompt_constructor: RwLock<PromptConstructor>,
+    /// The primary backend, for calls that don't depend on where a prompt runs.
     pub comfyui_client: ComfyUIClient,
+    /// Every backend, for queueing prompts and following them up.
+    pub backends: BackendPool,
+    /// Shared websocket for live progress; prompts are queued under its client id.
+    pub progress_hub: ProgressHub,
     pub workflow_manager: RwLock<WorkflowManager>,
     pub static_drive_poller: Arc<StaticDrivePoller>,
     pub prompts_dir: String,
+    pub job_store: RwLock<JobStore>,
+    /// Images uploaded through `/upload_image`.
+    pub inputs: RwLock<InputLibrary>,
+    pub comfyui_input_dir: Option<String>,
+    /// Trash for deleted outputs; set with `COMFYUI_OUTPUT_DIR`.
+    pub output_trash: Option<OutputTrash>,
+    /// ComfyUI's node definitions, backing `/get_node_info`.
+    pub object_info: ObjectInfoCache,
+    pub upload_resize: ResizeDefaults,
+    pub default_workflow: Option<String>,
+    pub generate_timeout_secs: u64,
+    pub text_limits: TextLimits,
+    pub negative_prompts: NegativePrompts,
+    /// Seeds of favorited outputs, for `"seed": "favorite:<name>"`.
+    pub seeds: RwLock<SeedJournal>,
+    pub batch_limits: BatchLimits,
+    pub workflow_limits: WorkflowLimits,
+    /// Prompts held for fair scheduling (`FAIR_QUEUE_DEPTH`) or until their
+    /// workflow's limits allow them (`WORKFLOW_LIMITS`).
+    pub(crate) held_prompts: Option<HeldPrompts>,
+    pub cost_model: CostModel,
+    pub resolution_rules: ResolutionRules,
+    pub title_patterns: TitlePatterns,
+    pub text_delimiter: String,
+    /// Selectors for the nodes `detail_faces` toggles.
+    pub face_detailer_nodes: Vec<String>,
+    pub api_keys: ApiKeys,
+    pub usage: RwLock<UsageTracker>,
+    /// Limits for keys that don't set their own, and for requests without a key.
+    pub rate_limits: RateLimit,
+    pub rate_limiter: RateLimiter,
+    /// Git sync for the prompts directory, when `WORKFLOWS_GIT_URL` is set.
+    pub workflow_sync: Option<Arc<WorkflowSync>>,
+    /// The last workflow preflight, served by `/workflows/preflight`.
+    pub preflight: RwLock<Option<Arc<PreflightReport>>>,
+    /// Set while maintenance mode refuses new jobs.
+    pub maintenance: RwLock<Option<Maintenance>>,
+    /// Proxy-level events streamed by `/events`.
+    pub events: EventBus,
+    /// Copies finished jobs' outputs to the static drive, with `HARVEST_OUTPUTS`.
+    pub harvester: Option<OutputHarvester>,
+    /// This replica's lease owner name; see [`crate::jobs::Leases::claim`].
+    pub instance_id: String,
+    pub lease_ttl_ms: u64,
+    /// Counters served by `/metrics`; shared with `comfyui_client`.
+    pub metrics: Arc<Metrics>,
+    /// Embedder callbacks for job and output events.
+    pub hooks: Arc<LifecycleHooks>,
 }
 
-pub fn setup_routes(comfyui_client: ComfyUIClient) -> Router {
-    let config = Config::new().expect("Failed to load configuration");
-    let state = Arc::new(AppState {
-        comfyui_client,
-        prompt_constructor: RwLock::new(PromptConstructor::new()),
-        workflow_manager: RwLock::new(WorkflowManager::new()),
-        static_drive_poller: Arc::new(StaticDrivePoller::new(config.static_drive_path.clone())),
-        prompts_dir: config.prompts_dir.clone(),
-    });
+/// Build the shared state from configuration and an existing client; the
+/// same as [`AppState::new`].
+pub fn build_state(config: &Config, comfyui_client: ComfyUIClient) -> Arc<AppState> {
+    AppState::new(config, comfyui_client)
+}
+
+impl AppState {
+    /// Build the shared state from configuration and an existing client.
+    /// Nothing is started: the caller spawns background tasks (pollers,
+    /// harvesting) as `main` does.
+    pub fn new(config: &Config, comfyui_client: ComfyUIClient) -> Arc<AppState> {
+        let metrics = Arc::new(Metrics::new());
+        let comfyui_client = comfyui_client.with_metrics(metrics.clone());
+        let events = EventBus::new();
+        let mut static_drive_poller = StaticDrivePoller::from_config(config);
+        static_drive_poller.set_event_bus(events.clone());
+        let mut job_store = JobStore::from_config(config);
+        // Prompts this instance held before a restart are gone; other
+        // instances' are given up once their lease runs out.
+        let lost = job_store.fail_held(&[&config.instance_id], LOST_HELD_PROMPT);
+        if lost > 0 {
+            tracing::warn!("Failed {} job(s) whose held prompts were lost in a restart", lost);
+        }
+        job_store.set_event_bus(events.clone());
+        // Quota usage so far this month, from the jobs that outlived a restart.
+        let mut usage = UsageTracker::new();
+        for job in job_store.iter().filter(|j| j.history_synced) {
+            usage.record_job(job);
+        }
+        let hooks = Arc::new(LifecycleHooks::default());
+        let workflow_limits = WorkflowLimits::from_config(config);
+        job_store.set_hooks(hooks.clone());
+        let others = config.comfyui_urls.iter().skip(1)
+            .map(|url| ComfyUIClient::for_url(config, url).with_metrics(metrics.clone()))
+            .collect();
+        let mut backends = BackendPool::new(comfyui_client.clone(), others);
+        backends.set_event_bus(events.clone());
+        let harvester = config.harvest_outputs.then(|| {
+            // Jobs still running from before a restart are harvested too.
+            let since = job_store.iter().filter(|j| !j.is_finished()).map(|j| j.created_at_ms).fold(now_ms(), u64::min);
+            OutputHarvester::new(&config.static_drive_path, since)
+        });
+        Arc::new(AppState {
+            progress_hub: ProgressHub::for_backends(&backends.urls()),
+            comfyui_client,
+            backends,
+            prompt_constructor: RwLock::new(PromptConstructor::with_dir(config.prompts_dir.clone())),
+            workflow_manager: RwLock::new(WorkflowManager::with_dir(config.prompts_dir.clone())),
+            static_drive_poller: Arc::new(static_drive_poller),
+            prompts_dir: config.prompts_dir.clone(),
+            job_store: RwLock::new(job_store),
+            inputs: RwLock::new(InputLibrary::from_config(config)),
+            comfyui_input_dir: config.comfyui_input_dir.clone(),
+            output_trash: OutputTrash::from_config(config),
+            object_info: ObjectInfoCache::new(std::time::Duration::from_secs(config.object_info_ttl_secs)),
+            upload_resize: ResizeDefaults::from_config(config),
+            default_workflow: config.default_workflow.clone(),
+            generate_timeout_secs: config.generate_timeout_secs,
+            negative_prompts: NegativePrompts::from_config(config),
+            seeds: RwLock::new(SeedJournal::from_config(config)),
+            resolution_rules: ResolutionRules {
+                min: config.min_resolution,
+                max: config.max_resolution,
+                snap: config.snap_resolution,
+            },
+            title_patterns: TitlePatterns::from_config(config),
+            text_delimiter: config.text_delimiter.clone(),
+            face_detailer_nodes: config.face_detailer_nodes.clone(),
+            batch_limits: BatchLimits {
+                max_batch_size: config.max_batch_size,
+                vram_gb: config.gpu_vram_gb,
+                gb_per_megapixel: config.vram_gb_per_megapixel,
+            },
+            held_prompts: HeldPrompts::from_config(config, &workflow_limits),
+            workflow_limits,
+            cost_model: CostModel::from_config(config),
+            text_limits: TextLimits { max_chars: config.max_prompt_chars, max_tokens: config.max_prompt_tokens },
+            api_keys: ApiKeys::from_config(config),
+            usage: RwLock::new(usage),
+            rate_limits: RateLimit::from_config(config),
+            rate_limiter: RateLimiter::new(),
+            workflow_sync: WorkflowSync::from_config(config).map(Arc::new),
+            preflight: RwLock::new(None),
+            maintenance: RwLock::new(None),
+            events,
+            harvester,
+            instance_id: config.instance_id.clone(),
+            lease_ttl_ms: config.lease_ttl_secs.max(1) * 1000,
+            metrics,
+            hooks,
+        })
+    }
+}
+
+/// All API routes wired to `state`. Everything except the `/` health check
+/// and the API docs (`/openapi.json`, `/docs` and its assets) requires an API
+/// key when keys are configured.
+pub fn build_router(state: Arc<AppState>) -> Router {
+    build_router_with(state, Router::new())
+}
 
+/// [`build_router`] plus `plugins`, an embedder's own routes. They sit
+/// behind the same API key check, rate limit, `X-Client` handling, panic
+/// catching, and metrics as the built-in API, and their handlers see the
+/// caller's [`ApiKey`](crate::auth::ApiKey) and
+/// [`ClientName`](crate::api::middleware::ClientName) as request
+/// extensions. Paths must not clash with built-in routes; axum panics if
+/// they do.
+///
+/// ```no_run
+/// # use comfyui_api_proxy::{api::routes::{self, AppState}, Config, ComfyUIClient};
+/// use axum::{extract::State, routing::get, Router};
+/// use std::sync::Arc;
+///
+/// async fn job_count(State(state): State<Arc<AppState>>) -> String {
+///     state.job_store.read().await.iter().count().to_string()
+/// }
+///
+/// # let config = Config::new().unwrap();
+/// let state = AppState::new(&config, ComfyUIClient::from_config(&config));
+/// let app = routes::build_router_with(state, Router::new().route("/plugins/job_count", get(job_count)));
+/// ```
+pub fn build_router_with(state: Arc<AppState>, plugins: Router<Arc<AppState>>) -> Router {
     Router::new()
-        .route("/", get(handlers::root))
         .route("/queue_prompt", post(handlers::queue_prompt))
+        .route("/queue_batch", post(handlers::queue_batch))
+        .route("/sweep", post(handlers::sweep))
+        .route("/generate", post(handlers::generate))
         .route("/get_image", get(handlers::get_image))
-        .route("/history", get(handlers::get_history))
+        .route("/outputs/trash", get(handlers::list_trash))
+        .route("/outputs/:file", get(handlers::outputs_zip).delete(handlers::trash_outputs))
+        .route("/outputs/:file/restore", post(handlers::restore_outputs))
+        .route("/inputs", get(handlers::list_inputs).delete(handlers::prune_inputs))
+        .route("/inputs/:name", delete(handlers::delete_input))
+        .route("/upload_image", post(handlers::upload_image).layer(DefaultBodyLimit::max(MAX_IMAGE_UPLOAD_BYTES)))
+        .route("/img2img", post(handlers::img2img).layer(DefaultBodyLimit::max(MAX_IMG2IMG_BYTES)))
+        .route("/get_history", get(handlers::get_history))
+        .route("/interrupt", post(handlers::interrupt))
+        .route("/queue", get(handlers::get_queue))
+        .route("/queue/clear", post(handlers::clear_queue))
+        .route("/queue/:prompt_id", delete(handlers::delete_queued))
+        .route("/history", get(handlers::history_friendly))
+        .route("/history/export", get(handlers::history_export))
         .route("/add_workflow", post(handlers::add_workflow))
-        .route("/get_node_info", get(handlers::get_node_info))
+        .route("/workflows", get(handlers::list_workflows))
+        .route("/workflows/:name", get(handlers::get_workflow).put(handlers::put_workflow).delete(handlers::delete_workflow)
+            .layer(middleware::from_fn(etag)))
+        .route("/workflows/upload", post(handlers::upload_workflow))
+        .route("/workflows/sync", post(handlers::sync_workflows))
+        .route("/workflows/convert", post(handlers::convert_workflow))
+        .route("/workflows/validate", post(handlers::validate_workflow))
+        .route("/workflows/preflight", get(handlers::workflow_preflight).layer(middleware::from_fn(etag)))
+        .route("/workflows/bundle", post(handlers::import_workflow_bundle))
+        .route("/workflows/:name/bundle", get(handlers::workflow_bundle))
+        .route("/jobs", get(handlers::list_jobs))
+        .route("/jobs/import", post(handlers::import_jobs).layer(DefaultBodyLimit::max(MAX_IMPORT_BYTES)))
+        .route("/jobs/:id", get(handlers::job_status))
+        .route("/jobs/:id/events", get(handlers::job_events))
+        .route("/jobs/:id/cancel", post(handlers::cancel_job))
+        .route("/jobs/:id/rerun", post(handlers::rerun_job))
+        .route("/jobs/:id/favorite", post(handlers::favorite_job))
+        .route("/seeds", get(handlers::list_seeds))
+        .route("/seeds/:name", delete(handlers::delete_seed))
+        .route("/events", get(handlers::proxy_events))
+        .route("/events/:prompt_id", get(handlers::prompt_events))
+        .route("/jobs/:id/animation", post(handlers::job_animation))
+        .route("/jobs/:id/outputs/:index", get(handlers::job_output))
+        .route("/stats/nodes", get(handlers::node_stats))
+        .route("/usage", get(handlers::usage))
+        .route("/get_node_info", get(handlers::get_node_info).layer(middleware::from_fn(etag)))
         .route("/construct_prompt", post(handlers::construct_prompt))
+        .route("/templates/:name/params", get(handlers::template_params))
+        .route("/models", get(handlers::models_categories).layer(middleware::from_fn(etag)))
+        .route("/models/checkpoints", get(handlers::models_checkpoints).layer(middleware::from_fn(etag)))
+        .route("/models/:category", get(handlers::models_in_category).layer(middleware::from_fn(etag)))
+        .route("/backends", get(handlers::backends))
+        .route("/static/index", get(handlers::static_index))
+        .route("/static/*path", get(handlers::static_file))
+        .route("/admin/maintenance", get(handlers::maintenance_status).post(handlers::set_maintenance))
+        .route("/metrics", get(handlers::metrics))
+        .merge(plugins)
+        .route_layer(CatchPanicLayer::custom(reporting::panic_response))
+        .route_layer(middleware::from_fn(report_errors))
+        .route_layer(middleware::from_fn(identify_client))
+        .route_layer(middleware::from_fn_with_state(state.clone(), rate_limit))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_api_key))
+        .route("/", get(handlers::root))
+        .route("/openapi.json", get(handlers::openapi_json))
+        .route("/docs", get(handlers::swagger_ui))
+        .route("/docs/:file", get(handlers::swagger_ui_asset))
+        .layer(middleware::from_fn_with_state(state.clone(), track_metrics))
         .with_state(state)
 }
+
+pub fn setup_routes(comfyui_client: ComfyUIClient) -> Router {
+    let config = Config::new().expect("Failed to load configuration");
+    build_router(build_state(&config, comfyui_client))
+}
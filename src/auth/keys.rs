@@ -0,0 +1,128 @@
+//! API key registry.
+//!
+//! `API_KEYS_FILE` points at JSON of the form
+//! `{"keys": [{"key": "...", "name": "alice", "policy": {...}}]}`. Without a
+//! file, authentication is disabled and every request is served as before.
+//! Each key may also carry a `quota` (see [`Quota`]) and a `rate_limit`
+//! (see [`RateLimit`]).
+use axum::http::HeaderMap;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+use crate::auth::policy::KeyPolicy;
+use crate::auth::quota::Quota;
+use crate::auth::rate::RateLimit;
+use crate::config::Config;
+use crate::workflow::manager::is_valid_workflow_name;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKey {
+    pub key: String,
+    /// Human-readable owner, used in logs.
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub policy: KeyPolicy,
+    #[serde(default)]
+    pub quota: Quota,
+    /// Request rate and queued-prompt limits; unset fields use the defaults.
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+    /// Prompts sent per turn when `FAIR_QUEUE_DEPTH` holds prompts; unset
+    /// means 1.
+    #[serde(default)]
+    pub weight: Option<u32>,
+    /// Tenant namespace for workflows, jobs, and outputs. Keys without a
+    /// tenant are unscoped and see everything.
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+impl ApiKey {
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or("<unnamed>")
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    /// Identifier stored with the key's jobs and usage instead of the key.
+    pub fn id(&self) -> String {
+        key_id(&self.key)
+    }
+}
+
+/// SHA-256 of `key`, hex-encoded: identifies a key's jobs and usage without
+/// persisting the secret itself.
+pub fn key_id(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct KeysFile {
+    #[serde(default)]
+    keys: Vec<ApiKey>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeys {
+    enabled: bool,
+    keys: HashMap<String, ApiKey>,
+}
+
+impl ApiKeys {
+    /// An enabled registry holding `keys`. Keys whose tenant id isn't a
+    /// valid directory name are dropped.
+    pub fn from_keys(keys: Vec<ApiKey>) -> Self {
+        let keys = keys.into_iter()
+            .filter(|k| match k.tenant() {
+                Some(t) if !is_valid_workflow_name(t) => {
+                    tracing::error!("Ignoring API key '{}': invalid tenant id '{}'", k.label(), t);
+                    false
+                }
+                _ => true,
+            })
+            .map(|k| (k.key.clone(), k))
+            .collect();
+        ApiKeys { enabled: true, keys }
+    }
+
+    /// Build from configuration. Unlike other config files, an unreadable or
+    /// malformed keys file fails closed: auth stays enabled with no valid keys.
+    pub fn from_config(config: &Config) -> Self {
+        let Some(path) = config.api_keys_file.as_deref() else { return ApiKeys::default() };
+        let parsed = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| serde_json::from_str::<KeysFile>(&data).map_err(|e| e.to_string()));
+        match parsed {
+            Ok(file) => ApiKeys::from_keys(file.keys),
+            Err(e) => {
+                tracing::error!("Failed to load API keys file {}: {}; rejecting all keys", path, e);
+                ApiKeys::from_keys(Vec::new())
+            }
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn lookup(&self, key: &str) -> Option<&ApiKey> {
+        self.keys.get(key)
+    }
+}
+
+/// Key presented in `X-API-Key` or `Authorization: Bearer <key>`.
+pub fn key_from_headers(headers: &HeaderMap) -> Option<&str> {
+    headers.get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| {
+            headers.get("authorization")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+        })
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+}
@@ -0,0 +1,10 @@
+//! API keys and the per-key policies attached to them.
+pub mod keys;
+pub mod policy;
+pub mod quota;
+pub mod rate;
+
+pub use keys::{ApiKey, ApiKeys};
+pub use policy::{KeyPolicy, OnExceed};
+pub use quota::{Quota, UsageTracker};
+pub use rate::{RateLimit, RateLimiter};
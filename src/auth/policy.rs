@@ -0,0 +1,116 @@
+//! Per-key limits on what a submitted graph may do.
+//!
+//! Policies are checked against the final graph, after overrides and
+//! defaults, so they hold no matter how a value got there (request params,
+//! `--set`-style overrides, or the stored workflow itself).
+use serde::Deserialize;
+use serde_json::Value;
+
+/// What to do with a value over the limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnExceed {
+    #[default]
+    Reject,
+    Clamp,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct KeyPolicy {
+    pub max_steps: Option<u64>,
+    /// Upper bound for every `width`/`height` input.
+    pub max_resolution: Option<u64>,
+    /// Checkpoint/UNet file names the key may load. Always enforced by rejection.
+    pub allowed_checkpoints: Option<Vec<String>>,
+    /// Prefix every SaveImage `filename_prefix` must start with; prepended when missing.
+    pub filename_prefix: Option<String>,
+    pub on_exceed: OnExceed,
+}
+
+impl KeyPolicy {
+    /// Check `value` against `max`; when clamping, the replacement is `clamp_to`.
+    fn limit(&self, node: &str, field: &str, value: u64, max: u64, clamp_to: u64, warnings: &mut Vec<String>) -> Result<u64, String> {
+        if value <= max {
+            return Ok(value);
+        }
+        match self.on_exceed {
+            OnExceed::Reject => Err(format!("node {}: {} {} exceeds this key's limit of {}", node, field, value, max)),
+            OnExceed::Clamp => {
+                warnings.push(format!("node {}: {} {} clamped to {}", node, field, value, clamp_to));
+                Ok(clamp_to)
+            }
+        }
+    }
+
+    /// Enforce the policy on an API-format graph in place. Returns warnings
+    /// for clamped values, or an error describing the first violation.
+    pub fn enforce(&self, graph: &mut Value) -> Result<Vec<String>, String> {
+        let mut warnings = Vec::new();
+        let Some(nodes) = graph.as_object_mut() else { return Ok(warnings) };
+        let mut ids: Vec<String> = nodes.keys().cloned().collect();
+        ids.sort();
+        for id in ids {
+            let node = &mut nodes[&id];
+            let Some(inputs) = node.get_mut("inputs").and_then(|v| v.as_object_mut()) else { continue };
+
+            if let Some(max) = self.max_steps {
+                if let Some(steps) = inputs.get("steps").and_then(|v| v.as_u64()) {
+                    let steps = self.limit(&id, "steps", steps, max, max, &mut warnings)?;
+                    inputs.insert("steps".to_string(), Value::from(steps));
+                }
+            }
+            if let Some(max) = self.max_resolution {
+                // Clamp to a multiple of 8 so the result is still a valid latent size.
+                let clamp_to = (max / 8 * 8).max(8);
+                for field in ["width", "height"] {
+                    if let Some(v) = inputs.get(field).and_then(|v| v.as_u64()) {
+                        let v = self.limit(&id, field, v, max, clamp_to, &mut warnings)?;
+                        inputs.insert(field.to_string(), Value::from(v));
+                    }
+                }
+            }
+            if let Some(allowed) = &self.allowed_checkpoints {
+                for field in ["ckpt_name", "unet_name"] {
+                    if let Some(name) = inputs.get(field).and_then(|v| v.as_str()) {
+                        if !allowed.iter().any(|a| a == name) {
+                            return Err(format!("node {}: {} '{}' is not allowed for this key", id, field, name));
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(prefix) = &self.filename_prefix {
+            enforce_filename_prefix(graph, prefix)?;
+        }
+        Ok(warnings)
+    }
+}
+
+/// Make every `filename_prefix` input (of SaveImage and any other saving
+/// node) start with `prefix`, prepending it where missing; SaveImage nodes
+/// without one get `prefix` itself. Fails if a prefix could still leave the
+/// directory `prefix` names: a `..` segment after it, or a prefix linked
+/// from another node, which can't be checked.
+pub fn enforce_filename_prefix(graph: &mut Value, prefix: &str) -> Result<(), String> {
+    let Some(nodes) = graph.as_object_mut() else { return Ok(()) };
+    let mut ids: Vec<String> = nodes.keys().cloned().collect();
+    ids.sort();
+    for id in ids {
+        let Some(node) = nodes.get_mut(&id) else { continue };
+        let is_save_image = node.get("class_type").and_then(|v| v.as_str()) == Some("SaveImage");
+        let Some(inputs) = node.get_mut("inputs").and_then(|v| v.as_object_mut()) else { continue };
+        let current = match inputs.get("filename_prefix") {
+            Some(Value::String(s)) => s.clone(),
+            Some(_) => return Err(format!("node {}: filename_prefix must be a string", id)),
+            None if is_save_image => String::new(),
+            None => continue,
+        };
+        let scoped = if current.starts_with(prefix) { current } else { format!("{}{}", prefix, current) };
+        if scoped[prefix.len()..].split(['/', '\\']).any(|segment| segment == "..") {
+            return Err(format!("node {}: filename_prefix '{}' leaves '{}'", id, scoped, prefix));
+        }
+        inputs.insert("filename_prefix".to_string(), Value::String(scoped));
+    }
+    Ok(())
+}
@@ -0,0 +1,158 @@
+//! Per-key usage accounting and quotas.
+//!
+//! Usage is counted when a finished job's history is synced: one image per
+//! `output_saved` event, GPU time from execution start to finish, and the
+//! job's cost when a cost model is configured. Quota
+//! periods are UTC calendar days and months. Jobs still running when a quota
+//! is checked are not counted yet.
+//!
+//! Counters live in memory. At startup they are rebuilt from the finished
+//! jobs in the job store (so `JOBS_DB` or `JOBS_REDIS_URL` carry them across
+//! restarts), and replicas sharing a job backend count the jobs the others
+//! finish as they pick them up.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::error::AppError;
+use crate::jobs::store::Job;
+use crate::jobs::timing::execution_ms;
+use crate::utils::time::{day_bounds, month_bounds, now_ms};
+
+/// Usage bucket for requests made without an API key (auth disabled).
+pub const ANONYMOUS_USAGE: &str = "";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Usage {
+    pub images: u64,
+    pub gpu_ms: u64,
+    /// Actual cost under the configured cost model (0 without one).
+    pub cost: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Period {
+    Day,
+    Month,
+}
+
+impl Period {
+    /// Start and end (exclusive) of the period containing `ms`.
+    pub fn bounds(&self, ms: u64) -> (u64, u64) {
+        match self {
+            Period::Day => day_bounds(ms),
+            Period::Month => month_bounds(ms),
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Period::Day => "daily",
+            Period::Month => "monthly",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Quota {
+    pub daily_images: Option<u64>,
+    pub monthly_images: Option<u64>,
+    pub daily_gpu_seconds: Option<u64>,
+    pub monthly_gpu_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub message: String,
+    /// When the exhausted period rolls over.
+    pub reset_at_ms: u64,
+}
+
+impl From<QuotaExceeded> for AppError {
+    fn from(q: QuotaExceeded) -> Self {
+        AppError::QuotaExceeded { message: q.message, reset_at_ms: q.reset_at_ms }
+    }
+}
+
+impl Quota {
+    pub fn is_limited(&self) -> bool {
+        self.daily_images.is_some() || self.monthly_images.is_some()
+            || self.daily_gpu_seconds.is_some() || self.monthly_gpu_seconds.is_some()
+    }
+
+    /// `(images, gpu_seconds)` limits for a period.
+    pub fn limits(&self, period: Period) -> (Option<u64>, Option<u64>) {
+        match period {
+            Period::Day => (self.daily_images, self.daily_gpu_seconds),
+            Period::Month => (self.monthly_images, self.monthly_gpu_seconds),
+        }
+    }
+
+    /// Fail if `key` has used up any of its limits at `now_ms`.
+    pub fn check(&self, tracker: &UsageTracker, key: &str, now_ms: u64) -> Result<(), QuotaExceeded> {
+        for period in [Period::Day, Period::Month] {
+            let usage = tracker.usage(key, period, now_ms);
+            let (images, gpu_seconds) = self.limits(period);
+            let exhausted = match (images, gpu_seconds) {
+                (Some(max), _) if usage.images >= max => Some(format!("{} image quota of {} exhausted", period.label(), max)),
+                (_, Some(max)) if usage.gpu_ms >= max * 1000 => Some(format!("{} GPU quota of {}s exhausted", period.label(), max)),
+                _ => None,
+            };
+            if let Some(message) = exhausted {
+                return Err(QuotaExceeded { message, reset_at_ms: period.bounds(now_ms).1 });
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Window {
+    start_ms: u64,
+    usage: Usage,
+}
+
+/// Usage counters for the current day and month, per key.
+#[derive(Default)]
+pub struct UsageTracker {
+    windows: HashMap<(String, Period), Window>,
+}
+
+impl UsageTracker {
+    pub fn new() -> Self {
+        UsageTracker { windows: HashMap::new() }
+    }
+
+    /// Add usage for `key` at `at_ms`. Usage from a period older than the
+    /// one currently tracked is dropped.
+    pub fn record(&mut self, key: &str, at_ms: u64, usage: Usage) {
+        for period in [Period::Day, Period::Month] {
+            let start_ms = period.bounds(at_ms).0;
+            let window = self.windows.entry((key.to_string(), period))
+                .or_insert(Window { start_ms, usage: Usage::default() });
+            if start_ms > window.start_ms {
+                *window = Window { start_ms, usage: Usage::default() };
+            }
+            if start_ms == window.start_ms {
+                window.usage.images += usage.images;
+                window.usage.gpu_ms += usage.gpu_ms;
+                window.usage.cost += usage.cost;
+            }
+        }
+    }
+
+    /// Add a finished job's usage for the key it was queued with, at the
+    /// time it finished.
+    pub fn record_job(&mut self, job: &Job) {
+        let usage = Usage { images: job.saved_outputs(), gpu_ms: execution_ms(job), cost: job.actual_cost.unwrap_or(0.0) };
+        let at = job.events.last().map(|e| e.at_ms).unwrap_or_else(now_ms);
+        self.record(job.key_id.as_deref().unwrap_or(ANONYMOUS_USAGE), at, usage);
+    }
+
+    /// Usage for `key` in the period containing `now_ms`.
+    pub fn usage(&self, key: &str, period: Period, now_ms: u64) -> Usage {
+        match self.windows.get(&(key.to_string(), period)) {
+            Some(w) if w.start_ms == period.bounds(now_ms).0 => w.usage,
+            _ => Usage::default(),
+        }
+    }
+}
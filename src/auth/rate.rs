@@ -0,0 +1,98 @@
+//! Per-key request rate limits and caps on queued prompts.
+//!
+//! Requests are metered with a token bucket per API key (one shared bucket
+//! when auth is disabled): `requests_per_minute` tokens refill evenly, and up
+//! to `burst` (default: one minute's worth) can be spent at once.
+//! `max_queued` caps how many of a key's jobs may be unfinished at a time.
+//! Keys take `RATE_LIMIT_PER_MINUTE`, `RATE_LIMIT_BURST`, and
+//! `MAX_QUEUED_PER_KEY` for anything they don't set themselves.
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::config::Config;
+use crate::error::AppError;
+
+/// How soon a client over its queued-prompt cap is told to retry.
+pub const QUEUED_RETRY_MS: u64 = 5000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RateLimit {
+    pub requests_per_minute: Option<u32>,
+    pub burst: Option<u32>,
+    pub max_queued: Option<u32>,
+}
+
+impl RateLimit {
+    /// The defaults for every key.
+    pub fn from_config(config: &Config) -> Self {
+        RateLimit {
+            requests_per_minute: config.rate_limit_per_minute,
+            burst: config.rate_limit_burst,
+            max_queued: config.max_queued_per_key,
+        }
+    }
+
+    /// `self`, with unset limits taken from `defaults`.
+    pub fn or(self, defaults: RateLimit) -> RateLimit {
+        RateLimit {
+            requests_per_minute: self.requests_per_minute.or(defaults.requests_per_minute),
+            burst: self.burst.or(defaults.burst),
+            max_queued: self.max_queued.or(defaults.max_queued),
+        }
+    }
+}
+
+/// A request refused by a rate limit or queued-prompt cap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimited {
+    pub message: String,
+    /// When a retry can succeed.
+    pub retry_at_ms: u64,
+}
+
+impl From<RateLimited> for AppError {
+    fn from(r: RateLimited) -> Self {
+        AppError::RateLimited { message: r.message, retry_at_ms: r.retry_at_ms }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_ms: u64,
+}
+
+/// Token buckets, per key.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        RateLimiter::default()
+    }
+
+    /// Spend one request from `key`'s bucket at `now_ms`. Keys without a
+    /// `requests_per_minute` are never limited.
+    pub fn check(&self, key: &str, limit: &RateLimit, now_ms: u64) -> Result<(), RateLimited> {
+        let Some(per_minute) = limit.requests_per_minute.filter(|n| *n > 0) else { return Ok(()) };
+        let capacity = limit.burst.unwrap_or(per_minute).max(1) as f64;
+        let per_ms = per_minute as f64 / 60_000.0;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket { tokens: capacity, updated_ms: now_ms });
+        let elapsed = now_ms.saturating_sub(bucket.updated_ms) as f64;
+        bucket.tokens = (bucket.tokens + elapsed * per_ms).min(capacity);
+        bucket.updated_ms = now_ms.max(bucket.updated_ms);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        let wait_ms = ((1.0 - bucket.tokens) / per_ms).ceil() as u64;
+        Err(RateLimited {
+            message: format!("Rate limit of {} requests per minute exceeded", per_minute),
+            retry_at_ms: now_ms + wait_ms.max(1),
+        })
+    }
+}
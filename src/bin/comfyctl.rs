@@ -1,9 +1,32 @@
-use clap::{Parser, Subcommand};
-use comfyui_api_proxy::{Config, ComfyUIClient};
-use serde_json::{json, Value};
-use std::path::PathBuf;
-use comfyui_api_proxy::utils::prompt_ops::{apply_set_path, ensure_filename_prefix, parse_set_pairs, apply_params_map};
-use comfyui_api_proxy::utils::prompt_build::{apply_overrides_from_payload, ensure_defaults_on_root, is_probably_graph};
+use clap::{Parser, Subcommand, ValueEnum};
+use comfyui_api_proxy::{Config, ComfyUIClient, PromptConstructor};
+use comfyui_api_proxy::comfyui::ws::{ProgressEvent, ProgressStream};
+use comfyui_api_proxy::error::AppError;
+use futures_util::StreamExt;
+use std::io::Write;
+use comfyui_api_proxy::admin::backup::{restore_backup, write_backup, BackupSources};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use comfyui_api_proxy::prompt::negative::NegativePrompts;
+use comfyui_api_proxy::prompt::seeds::{SeedJournal, FAVORITE_PREFIX};
+use comfyui_api_proxy::prompt::testing::{diff_json, load_cases};
+use comfyui_api_proxy::prompt::variation::apply_variation;
+use comfyui_api_proxy::workflow::convert::{detect_format, to_api_graph, to_api_graph_with, validate_api_graph, WorkflowFormat};
+use comfyui_api_proxy::workflow::manager::WorkflowManager;
+use comfyui_api_proxy::workflow::validate::validate_against_object_info;
+use comfyui_api_proxy::workflow::diff::diff_inputs;
+use comfyui_api_proxy::utils::batch_file::{read_batch_rows, BatchFormat};
+use comfyui_api_proxy::utils::dataset::{caption_for, export_dataset, has_tags, is_training_image, DatasetFormat, DatasetItem, DatasetLayout};
+use comfyui_api_proxy::utils::drive_actions::is_media;
+use comfyui_api_proxy::utils::harvest::sidecar_for;
+use comfyui_api_proxy::utils::static_drive_poller::{StaticDrivePoller, INDEX_FILE};
+use comfyui_api_proxy::utils::time::{civil_date, now_ms, parse_age};
+use comfyui_api_proxy::utils::logging::{init_logging, Console};
+use comfyui_api_proxy::utils::prompt_ops::{apply_init_image, apply_set_path, load_image_ids, parse_set_pairs, TitlePatterns};
+use comfyui_api_proxy::comfyui::inputs::input_path;
+use comfyui_api_proxy::utils::prompt_build::{apply_detail_faces, apply_disable_nodes, apply_group_toggles, apply_overrides_from_payload, apply_default_negative_from_payload, ensure_defaults_on_root, is_probably_graph};
 
 #[derive(Parser, Debug)]
 #[command(name = "comfyctl", about = "CLI for ComfyUI API Proxy", version)]
@@ -12,17 +35,74 @@ struct Cli {
     #[arg(global = true, long)]
     comfyui_url: Option<String>,
 
+    /// Log which nodes params and prompt text are routed to (stderr)
+    #[arg(global = true, long)]
+    debug: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)]
 enum Commands {
     /// Prompt-related commands
     Prompt {
         #[command(subcommand)]
         cmd: PromptCmd,
     },
+    /// Upload an init image to ComfyUI and queue an img2img workflow that
+    /// loads it (its init image LoadImage node is pointed at the upload)
+    Img2img {
+        /// Workflow name under prompts/<name>.json
+        #[arg(long, conflicts_with = "file")]
+        workflow: Option<String>,
+        /// Explicit file path to a workflow JSON
+        #[arg(long, value_name = "PATH")]
+        file: Option<String>,
+        /// The init image to upload
+        #[arg(long, value_name = "PATH")]
+        image: PathBuf,
+        /// Denoise strength: how far to move away from the init image
+        #[arg(long)]
+        denoise: Option<f64>,
+        /// Positive prompt text
+        #[arg(long, value_name = "TEXT")]
+        text_positive: Option<String>,
+        /// Negative prompt text
+        #[arg(long, value_name = "TEXT")]
+        text_negative: Option<String>,
+        /// Seed, or `favorite:<name>` to reuse a favorite's
+        #[arg(long, value_name = "SEED")]
+        seed: Option<String>,
+        /// Overrides as key=value (repeatable), as for `prompt queue`
+        #[arg(long = "set", value_name = "KEY=VALUE")]
+        sets: Vec<String>,
+        /// Default filename prefix to apply if present and not overridden
+        #[arg(long, default_value = "Derivata")]
+        filename_prefix: String,
+        /// Treat failed --set applications as errors (exit non-zero)
+        #[arg(long)]
+        strict_set: bool,
+        /// Treat params that match no node input as errors (exit non-zero)
+        #[arg(long)]
+        strict_params: bool,
+        /// Build and validate the prompt without uploading or queueing
+        #[arg(long)]
+        dry_run: bool,
+        /// Print `{"prompt_id", "number", "image"}` as JSON
+        #[arg(long)]
+        json: bool,
+        /// Follow the prompt until it finishes, then download its outputs
+        #[arg(long, conflicts_with = "dry_run")]
+        wait: bool,
+        /// Where --wait saves outputs (defaults to <STATIC_DRIVE_PATH>/images)
+        #[arg(long, value_name = "PATH", requires = "wait")]
+        out_dir: Option<PathBuf>,
+        /// Seconds --wait waits for the prompt to finish
+        #[arg(long, value_name = "SECS", default_value_t = 600, requires = "wait")]
+        timeout: u64,
+    },
     /// Fetch ComfyUI execution history
     History {
         /// Filter by prompt ID to list output filenames
@@ -37,14 +117,217 @@ enum Commands {
         #[command(subcommand)]
         cmd: ImageCmd,
     },
+    /// Workflow checks
+    Workflow {
+        #[command(subcommand)]
+        cmd: WorkflowCmd,
+    },
     /// Model listing utilities
     Models {
         #[command(subcommand)]
         cmd: ModelsCmd,
     },
+    /// Proxy administration
+    Admin {
+        #[command(subcommand)]
+        cmd: AdminCmd,
+    },
+    /// Prompt template checks
+    Template {
+        #[command(subcommand)]
+        cmd: TemplateCmd,
+    },
+    /// Recent outputs from the static drive index, newest first
+    Gallery {
+        /// Only outputs modified within this long, e.g. 30m, 12h, 1d, 2w
+        #[arg(long, default_value = "1d")]
+        since: String,
+        /// Only outputs harvested from jobs of this workflow (see HARVEST_OUTPUTS)
+        #[arg(long)]
+        workflow: Option<String>,
+        /// Most outputs to list
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+        /// Print proxy URLs (GET /static/<path>) instead of local paths
+        #[arg(long)]
+        urls: bool,
+        /// Proxy for --urls (defaults to http://<API_HOST>:<API_PORT>)
+        #[arg(long, value_name = "URL")]
+        proxy_url: Option<String>,
+        /// Open the listed files with the system viewer
+        #[arg(long)]
+        open: bool,
+        /// Output JSON instead of one line per file
+        #[arg(long)]
+        json: bool,
+    },
+    /// Outputs on the static drive
+    Outputs {
+        #[command(subcommand)]
+        cmd: OutputsCmd,
+    },
+    /// Jobs on a running proxy (GET /jobs, /jobs/:id, ...)
+    Jobs {
+        /// Proxy to talk to (defaults to http://<API_HOST>:<API_PORT>)
+        #[arg(long, value_name = "URL")]
+        proxy_url: Option<String>,
+        /// API key for the proxy
+        #[arg(long)]
+        api_key: Option<String>,
+        #[command(subcommand)]
+        cmd: JobsCmd,
+    },
+    /// Favorite seeds saved with POST /jobs/:id/favorite (SEEDS_FILE)
+    Seeds {
+        #[command(subcommand)]
+        cmd: SeedsCmd,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum OutputsCmd {
+    /// Copy captioned outputs into a training dataset for LoRA tools
+    Export {
+        /// Directory to write the dataset to
+        #[arg(long, value_name = "PATH")]
+        out: PathBuf,
+        /// Only outputs whose caption has this tag or phrase (repeatable; all must match)
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+        /// Layout: kohya (<repeats>_<concept>/ folders), flat, or imagefolder (metadata.jsonl)
+        #[arg(long, default_value = "kohya")]
+        format: String,
+        /// Only outputs modified within this long, e.g. 12h, 1d, 2w
+        #[arg(long)]
+        since: Option<String>,
+        /// Only outputs harvested from jobs of this workflow (see HARVEST_OUTPUTS)
+        #[arg(long)]
+        workflow: Option<String>,
+        /// Only outputs whose drive path starts with this, e.g. images/
+        #[arg(long)]
+        prefix: Option<String>,
+        /// Times each image is repeated per epoch (kohya)
+        #[arg(long, default_value_t = 10)]
+        repeats: u32,
+        /// Concept folder name (kohya); defaults to the first --tag
+        #[arg(long)]
+        concept: Option<String>,
+        /// Token to put at the start of every caption, e.g. a trigger word
+        #[arg(long)]
+        trigger: Option<String>,
+        /// Export into --out even if it isn't empty
+        #[arg(long)]
+        force: bool,
+        /// Print the exported files as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SeedsCmd {
+    /// List favorite seeds, newest first
+    List {
+        /// Favorites of this tenant instead of unscoped ones
+        #[arg(long)]
+        tenant: Option<String>,
+        /// Only favorites of jobs of this workflow
+        #[arg(long)]
+        workflow: Option<String>,
+        /// Output JSON instead of one line per favorite
+        #[arg(long)]
+        json: bool,
+    },
 }
 
 #[derive(Subcommand, Debug)]
+enum JobsCmd {
+    /// List jobs, newest first
+    List {
+        /// Only jobs in this state: submitted, queued, running, completed, failed
+        #[arg(long)]
+        state: Option<String>,
+        /// Only jobs created at or after this Unix time in milliseconds
+        #[arg(long)]
+        since: Option<u64>,
+        /// Only jobs submitted by this client app (its X-Client header)
+        #[arg(long)]
+        client: Option<String>,
+        /// Most jobs to list (the proxy caps this at 500)
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Output raw JSON instead of one line per job
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show a job's state, outputs, and cost
+    Show {
+        id: String,
+        /// Output raw JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Block until a job completes or fails, showing its progress on stderr
+    Wait {
+        id: String,
+        /// Give up after this many seconds
+        #[arg(long, default_value_t = 600)]
+        timeout: u64,
+        /// Print the final job as JSON instead of its output URLs
+        #[arg(long)]
+        json: bool,
+    },
+    /// Remove a waiting job from ComfyUI's queue, or interrupt it if running
+    Cancel {
+        id: String,
+    },
+    /// Queue a job's original request again as a new job
+    Rerun {
+        id: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum TemplateCmd {
+    /// Render golden-test fixtures and diff them against their expected output
+    Test {
+        /// Directory of fixtures (defaults to <PROMPTS_DIR>/tests)
+        dir: Option<PathBuf>,
+        /// Rewrite each fixture's expected output with the current rendering
+        #[arg(long)]
+        update: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AdminCmd {
+    /// Archive workflows, config files, the job database, the drive index, and ComfyUI history
+    Backup {
+        /// Archive to write
+        #[arg(long, value_name = "PATH", default_value = "backup.tar.gz")]
+        out: PathBuf,
+        /// Skip fetching ComfyUI history
+        #[arg(long)]
+        no_history: bool,
+    },
+    /// Restore a backup written by `admin backup`
+    Restore {
+        /// Archive to restore
+        archive: PathBuf,
+        /// Overwrite existing files that differ from the backup
+        #[arg(long)]
+        force: bool,
+        /// Running proxy to re-import the backed-up history into (POST /jobs/import)
+        #[arg(long, value_name = "URL")]
+        proxy_url: Option<String>,
+        /// API key for --proxy-url
+        #[arg(long, requires = "proxy_url")]
+        api_key: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)]
 enum PromptCmd {
     /// Queue a workflow prompt to ComfyUI
     Queue {
@@ -67,9 +350,9 @@ enum PromptCmd {
         /// Negative prompt text; auto-routed via KSampler links when possible
         #[arg(long, value_name = "TEXT")]
         text_negative: Option<String>,
-        /// Seed
-        #[arg(long)]
-        seed: Option<i64>,
+        /// Seed, or `favorite:<name>` to reuse a favorite's (see `seeds list`)
+        #[arg(long, value_name = "SEED")]
+        seed: Option<String>,
         /// Steps
         #[arg(long)]
         steps: Option<i64>,
@@ -85,6 +368,22 @@ enum PromptCmd {
         /// Denoise strength
         #[arg(long)]
         denoise: Option<f64>,
+        /// Img2img denoise preset: subtle, moderate, or strong
+        #[arg(long, conflicts_with = "denoise")]
+        variation: Option<String>,
+        /// Turn the face-detailer stage on or off (see FACE_DETAILER_NODES)
+        #[arg(long, value_name = "BOOL")]
+        detail_faces: Option<bool>,
+        /// Bypass nodes before queueing (repeatable): a node id,
+        /// `title:<title>`, or `class:<class_type>`
+        #[arg(long = "disable", value_name = "SELECTOR")]
+        disable_nodes: Vec<String>,
+        /// Mute --disable nodes (removing everything downstream) instead of bypassing them
+        #[arg(long, requires = "disable_nodes")]
+        mute: bool,
+        /// Enable or disable a UI group (repeatable), e.g. "Hires Fix=off"
+        #[arg(long = "group", value_name = "NAME=on|off")]
+        groups: Vec<String>,
         /// Width
         #[arg(long)]
         width: Option<i64>,
@@ -94,18 +393,167 @@ enum PromptCmd {
         /// Batch size
         #[arg(long, alias = "batchsize")]
         batch_size: Option<i64>,
+        /// Upscale method (e.g. nearest-exact, bilinear)
+        #[arg(long)]
+        upscale_method: Option<String>,
+        /// Overall upscale factor
+        #[arg(long)]
+        upscale_by: Option<f64>,
         /// Checkpoint name
         #[arg(long)]
         ckpt_name: Option<String>,
+        /// CLIP skip (e.g. 2); adds a CLIPSetLastLayer when the graph has none
+        #[arg(long)]
+        clip_skip: Option<i64>,
+        /// VAE name, set on the graph's VAELoader
+        #[arg(long)]
+        vae_name: Option<String>,
+        /// Add a VAELoader for --vae-name when the graph has none
+        #[arg(long, requires = "vae_name")]
+        inject_vae: bool,
         /// Verbose: print constructed prompt body before sending
         #[arg(short, long)]
         verbose: bool,
-        /// Output raw JSON response instead of a friendly line
+        /// Output format: `text` prints `prompt_id=<id> number=<n>`, `json`
+        /// prints `{"prompt_id", "number", "node_errors"}`
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+        /// Shorthand for --output json
         #[arg(long)]
         json: bool,
+        /// Print only the prompt id
+        #[arg(short, long, conflicts_with_all = ["json", "output", "verbose", "dry_run"])]
+        quiet: bool,
         /// Treat failed --set applications as errors (exit non-zero)
         #[arg(long)]
         strict_set: bool,
+        /// Treat params that match no node input as errors (exit non-zero)
+        #[arg(long)]
+        strict_params: bool,
+        /// Resolve and validate the prompt, then print the changed inputs
+        /// (or, with --json, the final graph too) instead of queueing it
+        #[arg(long)]
+        dry_run: bool,
+        /// Follow the prompt's progress until it finishes, then download its
+        /// output images
+        #[arg(long, conflicts_with = "dry_run")]
+        wait: bool,
+        /// Where --wait saves outputs (defaults to <STATIC_DRIVE_PATH>/images)
+        #[arg(long, value_name = "PATH", requires = "wait")]
+        out_dir: Option<PathBuf>,
+        /// Seconds --wait waits for the prompt to finish
+        #[arg(long, value_name = "SECS", default_value_t = 600, requires = "wait")]
+        timeout: u64,
+    },
+    /// Queue one prompt per row of a CSV or JSONL file, whose columns are
+    /// params (e.g. seed, text_positive) or `--set` paths (e.g. 2.inputs.seed)
+    Batch {
+        /// Workflow name under prompts/<name>.json
+        #[arg(long, conflicts_with = "file")]
+        workflow: Option<String>,
+        /// Explicit file path to a workflow JSON
+        #[arg(long, value_name = "PATH")]
+        file: Option<String>,
+        /// Rows to queue: a .csv file with a header row, or a .jsonl file
+        #[arg(long, value_name = "PATH")]
+        input: PathBuf,
+        /// Overrides applied to every row (repeatable), as for `prompt queue`
+        #[arg(long = "set", value_name = "KEY=VALUE")]
+        sets: Vec<String>,
+        /// Filename prefix for rows without a filename_prefix column
+        #[arg(long, default_value = "Derivata")]
+        filename_prefix: String,
+        /// Queue at most this many prompts per minute
+        #[arg(long, value_name = "PER_MINUTE")]
+        rate: Option<u32>,
+        /// Stop at the first row that fails instead of carrying on
+        #[arg(long)]
+        stop_on_error: bool,
+        /// Treat failed --set applications as errors for that row
+        #[arg(long)]
+        strict_set: bool,
+        /// Treat params that match no node input as errors for that row
+        #[arg(long)]
+        strict_params: bool,
+        /// Build and validate every row without queueing
+        #[arg(long)]
+        dry_run: bool,
+        /// Print the summary as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Queue one prompt per combination of swept params on a running proxy
+    /// (POST /sweep); outputs are tagged with their combination
+    Sweep {
+        /// Workflow name on the proxy
+        #[arg(long)]
+        workflow: Option<String>,
+        /// A param and its values (repeatable), e.g. `cfg=5,7,9`. Values are
+        /// split on commas, except for text params; repeat a key to add more.
+        #[arg(long = "param", value_name = "KEY=V1,V2,...")]
+        params: Vec<String>,
+        /// Sweep this many consecutive seeds
+        #[arg(long, value_name = "N")]
+        seeds: Option<u64>,
+        /// First seed for --seeds (random by default)
+        #[arg(long, value_name = "SEED", requires = "seeds")]
+        seed_start: Option<u64>,
+        /// Filename prefix the combination tags are appended to
+        #[arg(long)]
+        filename_prefix: Option<String>,
+        /// Proxy to talk to (defaults to http://<API_HOST>:<API_PORT>)
+        #[arg(long, value_name = "URL")]
+        proxy_url: Option<String>,
+        /// API key for the proxy
+        #[arg(long)]
+        api_key: Option<String>,
+        /// Validate every combination without queueing
+        #[arg(long)]
+        dry_run: bool,
+        /// Output the proxy's JSON response
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// What `prompt queue` prints with `--output json`.
+#[derive(Serialize, Debug)]
+struct QueuedPrompt<'a> {
+    prompt_id: &'a str,
+    number: Option<u64>,
+    node_errors: &'a Map<String, Value>,
+}
+
+#[derive(Subcommand, Debug)]
+enum WorkflowCmd {
+    /// Convert a UI export (nodes/links) into an API prompt graph
+    Convert {
+        /// UI export to convert
+        file: PathBuf,
+        /// Where to write the graph (defaults to stdout)
+        #[arg(long, value_name = "PATH")]
+        out: Option<PathBuf>,
+        /// Don't ask ComfyUI for widget layouts of unfamiliar nodes
+        #[arg(long)]
+        offline: bool,
+    },
+    /// Check a workflow against ComfyUI's installed node definitions
+    Validate {
+        /// Workflow name under prompts/<name>.json
+        #[arg(long, conflicts_with = "file")]
+        workflow: Option<String>,
+        /// Explicit file path to a workflow JSON (API graph or UI export)
+        #[arg(long, value_name = "PATH")]
+        file: Option<String>,
+        /// Output the errors as JSON
+        #[arg(long)]
+        json: bool,
     },
 }
 
@@ -147,174 +595,351 @@ enum ModelsCmd {
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Load env and parse CLI
+async fn main() {
+    // Load env and parse CLI; clap exits with `Exit::Usage` on bad arguments.
     Config::dotenv_load();
     let cli = Cli::parse();
 
-    let mut conf = Config::new().expect("Failed to load config");
+    let mut conf = match Config::new() {
+        Ok(conf) => conf,
+        Err(e) => fail(CliError::new(Exit::Failure, format!("Failed to load config: {}", e))),
+    };
+    if let Err(e) = init_logging(&conf, "comfyctl", Console::Stderr, cli.debug || conf.trace_prompt_ops) {
+        fail(CliError::new(Exit::Failure, e));
+    }
     if let Some(url) = cli.comfyui_url {
         conf.comfyui_url = url;
     }
+    if let Err(e) = run(cli.command, conf).await {
+        fail(e);
+    }
+}
 
-    match cli.command {
+/// Print `error` and exit with its code.
+fn fail(error: CliError) -> ! {
+    eprintln!("Error: {}", error.message);
+    std::process::exit(error.exit as i32);
+}
+
+async fn run(command: Commands, conf: Config) -> CliResult {
+    match command {
         Commands::Prompt { cmd } => match cmd {
             PromptCmd::Queue {
                 workflow, file, sets, filename_prefix,
                 text_positive, text_negative,
-                seed, steps, cfg, sampler_name, scheduler, denoise,
-                width, height, batch_size, ckpt_name,
-                verbose, json, strict_set,
+                seed, steps, cfg, sampler_name, scheduler, denoise, variation, detail_faces, disable_nodes, mute, groups,
+                width, height, batch_size, upscale_method, upscale_by, ckpt_name, clip_skip, vae_name, inject_vae,
+                verbose, output, json, quiet, strict_set, strict_params, dry_run, wait, out_dir, timeout,
             } => {
-                let path = match (workflow, file) {
-                    (Some(name), None) => {
-                        let mut p = std::path::PathBuf::from(conf.prompts_dir.clone());
-                        p.push(format!("{}.json", name));
-                        p.to_string_lossy().to_string()
-                    }
-                    (None, Some(p)) => p,
-                    _ => {
-                        eprintln!("Must provide either --workflow <name> or --file <path>");
-                        std::process::exit(2);
-                    }
-                };
-                let data = tokio::fs::read_to_string(&path).await?;
-                let mut raw: Value = serde_json::from_str(&data)?;
-
-                // Extract graph whether already wrapped or not
-                let mut graph = if let Some(p) = raw.get("prompt").cloned() { p } else { raw.clone() };
-
-                if !is_probably_graph(&graph) {
-                    return Err(format!("Workflow at '{}' does not look like a valid ComfyUI graph", path).into());
-                }
+                let json = json || output == OutputFormat::Json;
+                let graph = load_workflow_graph(&conf, workflow.as_deref(), file.as_deref()).await?;
 
                 // Construct payload from flags for shared override application
-                let mut payload_obj = serde_json::Map::new();
-                payload_obj.insert("prompt".into(), graph.clone());
                 let mut params = serde_json::Map::new();
                 if let Some(t) = text_positive { params.insert("text_positive".into(), Value::String(t)); }
                 if let Some(t) = text_negative { params.insert("text_negative".into(), Value::String(t)); }
-                if let Some(v) = seed { params.insert("seed".into(), Value::from(v)); }
+                if let Some(v) = seed { params.insert("seed".into(), seed_value(v)?); }
                 if let Some(v) = steps { params.insert("steps".into(), Value::from(v)); }
                 if let Some(v) = cfg { params.insert("cfg".into(), json!(v)); }
                 if let Some(v) = sampler_name { params.insert("sampler_name".into(), Value::String(v)); }
                 if let Some(v) = scheduler { params.insert("scheduler".into(), Value::String(v)); }
                 if let Some(v) = denoise { params.insert("denoise".into(), json!(v)); }
+                if let Some(v) = variation { params.insert("variation".into(), Value::String(v)); }
                 if let Some(v) = width { params.insert("width".into(), Value::from(v)); }
                 if let Some(v) = height { params.insert("height".into(), Value::from(v)); }
                 if let Some(v) = batch_size { params.insert("batch_size".into(), Value::from(v)); }
+                if let Some(v) = upscale_method { params.insert("upscale_method".into(), Value::String(v)); }
+                if let Some(v) = upscale_by { params.insert("upscale_by".into(), json!(v)); }
                 if let Some(v) = ckpt_name { params.insert("ckpt_name".into(), Value::String(v)); }
-                if !params.is_empty() { payload_obj.insert("params".into(), Value::Object(params.clone())); }
-                if !sets.is_empty() { payload_obj.insert("sets".into(), Value::Array(sets.iter().map(|s| Value::String(s.clone())).collect())); }
-                let mut body = json!({"prompt": graph});
-                apply_overrides_from_payload(&mut body, &Value::Object(payload_obj.clone()))?;
-                ensure_defaults_on_root(&mut body, Some(&filename_prefix));
-                if verbose { eprintln!("[verbose] Request body to ComfyUI:
-{}", serde_json::to_string_pretty(&body)?); }
-
-                if !params.is_empty() {
-                    apply_params_map(&mut graph, &Value::Object(params.clone()));
-                }
-
-                // Apply dynamic overrides
-                if !sets.is_empty() {
-                    let pairs = parse_set_pairs(&sets).map_err(|e| {
-                        let boxed: Box<dyn std::error::Error> = e.into();
-                        boxed
-                    })?;
-                    for (path, new_val) in pairs {
-                        if !apply_set_path(&mut graph, &path, new_val.clone()) {
-                            // If graph was originally wrapped, user may have provided a full path starting with `prompt.`
-                            if !apply_set_path(&mut raw, &path, new_val.clone()) {
-                                eprintln!("Warning: could not apply --set to path: {}", path.join("."));
-                            }
-                        }
+                if let Some(v) = clip_skip { params.insert("clip_skip".into(), Value::from(v)); }
+                if let Some(v) = vae_name { params.insert("vae_name".into(), Value::String(v)); }
+                let mut payload = json!({"params": params, "inject_vae": inject_vae});
+                if let Some(v) = detail_faces { payload["detail_faces"] = Value::Bool(v); }
+                if !disable_nodes.is_empty() {
+                    payload["disable_nodes"] = json!(disable_nodes);
+                    payload["disable_mode"] = json!(if mute { "mute" } else { "bypass" });
+                }
+                if !groups.is_empty() {
+                    let mut toggles = serde_json::Map::new();
+                    for item in &groups {
+                        let parsed = item.rsplit_once('=').and_then(|(name, state)| match state.to_ascii_lowercase().as_str() {
+                            "on" | "true" | "1" => Some((name, true)),
+                            "off" | "false" | "0" => Some((name, false)),
+                            _ => None,
+                        });
+                        let Some((name, on)) = parsed else {
+                            return Err(CliError::new(Exit::Usage, format!("Invalid --group '{}', expected NAME=on|off", item)));
+                        };
+                        toggles.insert(name.to_string(), Value::Bool(on));
                     }
+                    payload["groups"] = Value::Object(toggles);
                 }
 
-                // If not overridden, set filename_prefix defaults
-                ensure_filename_prefix(&mut graph, &filename_prefix);
-
-                // Construct request body
-                let body = json!({"prompt": graph});
-
-                if verbose {
-                    eprintln!("[verbose] Request body to ComfyUI:\n{}", serde_json::to_string_pretty(&body)?);
+                let options = BuildOptions { workflow: workflow.as_deref(), sets: &sets, filename_prefix: &filename_prefix, strict_params, strict_set };
+                let BuiltPrompt { mut body, base, warnings } = build_prompt_body(&conf, &graph, &payload, &options)?;
+                for warning in warnings {
+                    eprintln!("Warning: {}", warning);
                 }
-                if !params.is_empty() { payload_obj.insert("params".into(), Value::Object(params.clone())); }
-                if !sets.is_empty() { payload_obj.insert("sets".into(), Value::Array(sets.iter().map(|s| Value::String(s.clone())).collect())); }
-                let mut body = json!({"prompt": graph});
-                apply_overrides_from_payload(&mut body, &Value::Object(payload_obj.clone()))?;
-                ensure_defaults_on_root(&mut body, Some(&filename_prefix));
                 if verbose { eprintln!("[verbose] Request body to ComfyUI:\n{}", serde_json::to_string_pretty(&body)?); }
 
-                let client = ComfyUIClient::new(conf.comfyui_url.clone());
+                if dry_run {
+                    validate_api_graph(&body["prompt"]).map_err(CliError::invalid)?;
+                    let changes = diff_inputs(&base, &body["prompt"]);
+                    if json {
+                        println!("{}", serde_json::to_string(&json!({"prompt": body["prompt"], "changes": changes}))?);
+                    } else {
+                        for c in &changes {
+                            let show = |v: &Option<Value>| v.as_ref().map(|v| v.to_string()).unwrap_or_else(|| "<none>".to_string());
+                            println!("{} ({}).{}: {} -> {}", c.node, c.class_type.as_deref().unwrap_or("?"), c.input, show(&c.old), show(&c.new));
+                        }
+                        println!("dry run: graph is valid, {} input(s) changed", changes.len());
+                    }
+                    return Ok(());
+                }
+
+                let client = ComfyUIClient::from_config(&conf);
+                // Subscribe before queueing so no early events are missed.
+                let events = if wait {
+                    let client_id = uuid::Uuid::new_v4().to_string();
+                    body["client_id"] = Value::String(client_id.clone());
+                    match client.progress_events(&client_id).await {
+                        Ok(events) => Some(events),
+                        Err(e) => {
+                            eprintln!("Warning: no live progress ({}); polling history instead", e);
+                            None
+                        }
+                    }
+                } else {
+                    None
+                };
                 let res = client.queue_prompt(body).await;
                 match res {
                     Ok(v) => {
-                        if json {
-                            println!("{}", serde_json::to_string(&v)?);
-                        } else if let Some(obj) = v.as_object() {
-                            if let Some(pid) = obj.get("prompt_id").and_then(|x| x.as_str()) {
-                                if let Some(num) = obj.get("number").and_then(|x| x.as_i64()) {
-                                    println!("queued {} – prompt_id: {}", num, pid);
-                                } else {
-                                    println!("prompt_id: {}", pid);
-                                }
-                            } else {
-                                println!("{}", serde_json::to_string_pretty(&v)?);
-                            }
+                        if quiet {
+                            println!("{}", v.prompt_id);
+                        } else if json {
+                            let queued = QueuedPrompt { prompt_id: &v.prompt_id, number: v.number, node_errors: &v.node_errors };
+                            println!("{}", serde_json::to_string(&queued)?);
+                        } else if let Some(num) = v.number {
+                            println!("prompt_id={} number={}", v.prompt_id, num);
                         } else {
-                            println!("{}", serde_json::to_string_pretty(&v)?);
+                            println!("prompt_id={}", v.prompt_id);
+                        }
+                        if wait {
+                            let out_dir = out_dir.unwrap_or_else(|| PathBuf::from(&conf.static_drive_path).join("images"));
+                            watch_prompt(&client, events, &v.prompt_id, Duration::from_secs(timeout), &out_dir, quiet).await?;
                         }
                         Ok(())
                     }
-                    Err(e) => {
-                        eprintln!("Error: {}", e);
-                        std::process::exit(1);
+                    // ComfyUI answered but refused the prompt (node_errors), or failed.
+                    Err(e) => Err(CliError::rejected(e)),
+                }
+            }
+            PromptCmd::Batch { workflow, file, input, sets, filename_prefix, rate, stop_on_error, strict_set, strict_params, dry_run, json } => {
+                let format = BatchFormat::from_path(&input)
+                    .ok_or_else(|| CliError::new(Exit::Usage, format!("Can't tell the format of {}; use a .csv or .jsonl file", input.display())))?;
+                let data = tokio::fs::read_to_string(&input).await.map_err(|e| format!("{}: {}", input.display(), e))?;
+                let rows = read_batch_rows(&data, format).map_err(CliError::invalid)?;
+                let graph = load_workflow_graph(&conf, workflow.as_deref(), file.as_deref()).await?;
+                let options = BuildOptions { workflow: workflow.as_deref(), sets: &sets, filename_prefix: &filename_prefix, strict_params, strict_set };
+                let client = ComfyUIClient::from_config(&conf);
+                let interval = rate.filter(|r| *r > 0).map(|r| Duration::from_secs_f64(60.0 / r as f64));
+                let mut next_at = tokio::time::Instant::now();
+                let mut report = Vec::with_capacity(rows.len());
+                let (mut failed, mut exit) = (0, Exit::Invalid);
+                for (i, row) in rows.iter().enumerate() {
+                    let n = i + 1;
+                    let outcome = match build_prompt_body(&conf, &graph, row, &options) {
+                        Ok(BuiltPrompt { body, warnings, .. }) => {
+                            for warning in warnings {
+                                eprintln!("Warning: row {}: {}", n, warning);
+                            }
+                            if dry_run {
+                                validate_api_graph(&body["prompt"]).map(|_| None).map_err(CliError::invalid)
+                            } else {
+                                if let Some(interval) = interval {
+                                    tokio::time::sleep_until(next_at).await;
+                                    next_at = tokio::time::Instant::now() + interval;
+                                }
+                                match client.queue_prompt(body).await {
+                                    Ok(v) => Ok(Some(v.prompt_id)),
+                                    Err(e) => Err(CliError::rejected(e)),
+                                }
+                            }
+                        }
+                        Err(e) => Err(e),
+                    };
+                    match outcome {
+                        Ok(prompt_id) => {
+                            if !json {
+                                println!("row {}: {}", n, prompt_id.as_deref().map(|id| format!("prompt_id={}", id)).unwrap_or_else(|| "ok".to_string()));
+                            }
+                            report.push(json!({"row": n, "prompt_id": prompt_id}));
+                        }
+                        Err(e) => {
+                            failed += 1;
+                            if !json {
+                                println!("row {}: error: {}", n, e.message);
+                            }
+                            report.push(json!({"row": n, "error": e.message}));
+                            // Later rows would fail the same way.
+                            if e.exit == Exit::Unreachable {
+                                exit = Exit::Unreachable;
+                                break;
+                            }
+                            if e.exit == Exit::Upstream {
+                                exit = Exit::Upstream;
+                            }
+                            if stop_on_error {
+                                break;
+                            }
+                        }
                     }
                 }
+                let done = report.len() - failed;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&json!({"total": rows.len(), "ok": done, "failed": failed, "dry_run": dry_run, "rows": report}))?);
+                } else {
+                    println!("{} {} of {} prompts", if dry_run { "validated" } else { "queued" }, done, rows.len());
+                }
+                if failed > 0 {
+                    return Err(CliError::new(exit, format!("{} of {} rows failed", failed, rows.len())));
+                }
+                Ok(())
+            }
+            PromptCmd::Sweep { workflow, params, seeds, seed_start, filename_prefix, proxy_url, api_key, dry_run, json } => {
+                let mut body = sweep_params(&params)?;
+                if let Some(count) = seeds {
+                    if body.contains_key("seed") {
+                        return Err(CliError::invalid("Use either --seeds or --param seed=..., not both"));
+                    }
+                    let mut spec = json!({"count": count});
+                    if let Some(start) = seed_start {
+                        spec["start"] = json!(start);
+                    }
+                    body.insert("seed".to_string(), spec);
+                }
+                if let Some(workflow) = workflow {
+                    body.insert("workflow".to_string(), json!(workflow));
+                }
+                if let Some(prefix) = filename_prefix {
+                    body.insert("filename_prefix".to_string(), json!(prefix));
+                }
+                let proxy = ProxyClient::new(proxy_url.unwrap_or_else(|| default_proxy_url(&conf)), api_key);
+                let query = if dry_run { vec![("dry_run", "true".to_string())] } else { Vec::new() };
+                let response = proxy.post_json("/sweep", &query, &Value::Object(body)).await?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&response)?);
+                    return Ok(());
+                }
+                let combinations = response["combinations"].as_array().cloned().unwrap_or_default();
+                if dry_run {
+                    for combination in &combinations {
+                        println!("{}", combination["tag"].as_str().unwrap_or_default());
+                    }
+                    println!("{} prompts would be queued", combinations.len());
+                } else {
+                    let job_ids = response["job_ids"].as_array().cloned().unwrap_or_default();
+                    for (job_id, combination) in job_ids.iter().zip(&combinations) {
+                        println!("{}  {}", job_id.as_str().unwrap_or_default(), combination["tag"].as_str().unwrap_or_default());
+                    }
+                }
+                Ok(())
             }
         },
-        Commands::History { prompt_id, json } => {
-            let client = ComfyUIClient::new(conf.comfyui_url.clone());
-            let hist = client.get_history().await.map_err(|e| {
-                eprintln!("Error: {}", e);
-                e
-            })?;
+        Commands::Img2img {
+            workflow, file, image, denoise, text_positive, text_negative, seed, sets, filename_prefix,
+            strict_set, strict_params, dry_run, json, wait, out_dir, timeout,
+        } => {
+            let bytes = tokio::fs::read(&image).await.map_err(|e| format!("{}: {}", image.display(), e))?;
+            let name = image.file_name().and_then(|n| n.to_str()).map(String::from)
+                .ok_or_else(|| CliError::new(Exit::Usage, format!("Invalid --image path {}", image.display())))?;
+            let graph = load_workflow_graph(&conf, workflow.as_deref(), file.as_deref()).await?;
+
+            let mut params = serde_json::Map::new();
+            // Stands in for the uploaded name until the upload is done.
+            params.insert("image".into(), Value::String(name.clone()));
+            if let Some(v) = denoise { params.insert("denoise".into(), json!(v)); }
+            if let Some(t) = text_positive { params.insert("text_positive".into(), Value::String(t)); }
+            if let Some(t) = text_negative { params.insert("text_negative".into(), Value::String(t)); }
+            if let Some(v) = seed { params.insert("seed".into(), seed_value(v)?); }
+            let payload = json!({"params": params});
+            let options = BuildOptions { workflow: workflow.as_deref(), sets: &sets, filename_prefix: &filename_prefix, strict_params, strict_set };
+            let BuiltPrompt { mut body, base, warnings } = build_prompt_body(&conf, &graph, &payload, &options)?;
+            if load_image_ids(&base).is_empty() {
+                return Err(CliError::invalid("The workflow has no LoadImage node to take the init image"));
+            }
+            for warning in warnings {
+                eprintln!("Warning: {}", warning);
+            }
+            if dry_run {
+                validate_api_graph(&body["prompt"]).map_err(CliError::invalid)?;
+                let changes = diff_inputs(&base, &body["prompt"]);
+                if json {
+                    println!("{}", serde_json::to_string(&json!({"prompt": body["prompt"], "changes": changes}))?);
+                } else {
+                    println!("dry run: graph is valid, {} input(s) changed", changes.len());
+                }
+                return Ok(());
+            }
 
+            let client = ComfyUIClient::from_config(&conf);
+            let uploaded = client.upload_image(bytes, &name, false).await?;
+            let path = input_path(&uploaded.name, uploaded.subfolder.as_deref());
+            apply_init_image(&mut body["prompt"], &path).map_err(CliError::invalid)?;
+            let events = if wait {
+                let client_id = uuid::Uuid::new_v4().to_string();
+                body["client_id"] = Value::String(client_id.clone());
+                client.progress_events(&client_id).await
+                    .inspect_err(|e| eprintln!("Warning: no live progress ({}); polling history instead", e))
+                    .ok()
+            } else {
+                None
+            };
+            let queued = match client.queue_prompt(body).await {
+                Ok(v) => v,
+                Err(e) => return Err(CliError::rejected(e)),
+            };
+            if json {
+                println!("{}", serde_json::to_string(&json!({"prompt_id": queued.prompt_id, "number": queued.number, "image": path}))?);
+            } else {
+                println!("uploaded {}", path);
+                println!("prompt_id={}", queued.prompt_id);
+            }
+            if wait {
+                let out_dir = out_dir.unwrap_or_else(|| PathBuf::from(&conf.static_drive_path).join("images"));
+                watch_prompt(&client, events, &queued.prompt_id, Duration::from_secs(timeout), &out_dir, json).await?;
+            }
+            Ok(())
+        }
+        Commands::History { prompt_id, json } => {
+            let client = ComfyUIClient::from_config(&conf);
             if json {
+                let hist = client.get_history_raw().await?;
                 println!("{}", serde_json::to_string(&hist)?);
                 return Ok(());
             }
+            let hist = client.get_history().await?;
 
             if let Some(id) = prompt_id {
-                let mut files: Vec<String> = Vec::new();
-                collect_filenames_for_id(&hist, &id, &mut files);
+                let files: Vec<String> = hist.get(&id).map(|e| e.images()).unwrap_or_default()
+                    .into_iter().map(|img| img.filename).collect();
                 if files.is_empty() {
                     eprintln!("No filenames found for prompt_id={}", id);
                 } else {
                     for f in files { println!("{}", f); }
                 }
-                Ok(())
             } else {
-                let mut ids: Vec<String> = Vec::new();
-                collect_prompt_ids(&hist, &mut ids);
-                if ids.is_empty() {
-                    println!("{}", serde_json::to_string_pretty(&hist)?);
-                } else {
-                    for id in ids { println!("{}", id); }
-                }
-                Ok(())
+                let mut entries: Vec<(&String, Option<u64>)> = hist.iter().map(|(id, e)| (id, e.number())).collect();
+                entries.sort_by_key(|(_, number)| *number);
+                for (id, _) in entries { println!("{}", id); }
             }
+            Ok(())
         }
         Commands::Image { cmd } => match cmd {
             ImageCmd::Get { filename, out } => {
-                let client = ComfyUIClient::new(conf.comfyui_url.clone());
-                let bytes = client.get_image(&filename).await.map_err(|e| {
-                    eprintln!("Error: {}", e);
-                    e
-                })?;
+                let client = ComfyUIClient::from_config(&conf);
+                let bytes = client.get_image(&filename).await?;
                 // Default to <STATIC_DRIVE_PATH>/images/<filename>
                 let default_dir = PathBuf::from(conf.static_drive_path).join("images");
                 tokio::fs::create_dir_all(&default_dir).await?;
@@ -324,58 +949,371 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 Ok(())
             }
         },
-        Commands::Models { cmd } => match cmd {
-            ModelsCmd::Categories { json } => {
-                let client = ComfyUIClient::new(conf.comfyui_url.clone());
-                let v = client.get_model_categories().await?;
-                if json {
-                    println!("{}", serde_json::to_string(&v)?);
+        Commands::Admin { cmd } => match cmd {
+            AdminCmd::Backup { out, no_history } => {
+                let history = if no_history {
+                    None
                 } else {
-                    if let Some(arr) = v.as_array() {
-                        for item in arr {
-                            if let Some(s) = item.as_str() { println!("{}", s); } else { println!("{}", item); }
+                    let client = ComfyUIClient::from_config(&conf);
+                    match client.get_history_raw().await {
+                        Ok(h) => Some(h),
+                        Err(e) => {
+                            eprintln!("Warning: skipping history ({}); the job index won't be in the backup", e);
+                            None
                         }
-                    } else {
-                        println!("{}", serde_json::to_string_pretty(&v)?);
                     }
+                };
+                let manifest = write_backup(&out, &BackupSources::from_config(&conf), history.as_ref())?;
+                println!(
+                    "Wrote {}: {} prompt files, negative prompts: {}, API keys: {}, job database: {}, drive index: {}, history entries: {}",
+                    out.display(),
+                    manifest.prompts.len(),
+                    manifest.negative_prompts,
+                    manifest.api_keys,
+                    manifest.jobs_db,
+                    manifest.drive_index,
+                    manifest.history_entries.map(|n| n.to_string()).unwrap_or_else(|| "none".to_string()),
+                );
+                Ok(())
+            }
+            AdminCmd::Restore { archive, force, proxy_url, api_key } => {
+                let restored = restore_backup(&archive, &BackupSources::from_config(&conf), force)?;
+                for path in &restored.written {
+                    println!("Restored {}", path.display());
+                }
+                for name in &restored.skipped {
+                    eprintln!("Skipped {} (not configured on this instance)", name);
+                }
+                match (restored.history, proxy_url) {
+                    (Some(history), Some(url)) => {
+                        let mut request = reqwest::Client::new()
+                            .post(format!("{}/jobs/import", url.trim_end_matches('/')))
+                            .json(&json!({"history": history}));
+                        if let Some(key) = api_key {
+                            request = request.header("X-API-Key", key);
+                        }
+                        let response = request.send().await?.error_for_status()?;
+                        let body: Value = response.json().await?;
+                        println!("Imported {} jobs into {}", body["imported"], url);
+                    }
+                    (Some(_), None) => eprintln!("Backup includes history; pass --proxy-url to rebuild the job index"),
+                    (None, _) => {}
                 }
                 Ok(())
             }
-            ModelsCmd::List { category, json } => {
-                let client = ComfyUIClient::new(conf.comfyui_url.clone());
-                let v = client.get_models_in_category(&category).await?;
-                if json {
-                    println!("{}", serde_json::to_string(&v)?);
-                } else if let Some(arr) = v.as_array() {
-                    for item in arr {
-                        match item {
-                            serde_json::Value::String(s) => println!("{}", s),
-                            serde_json::Value::Object(o) => {
-                                if let Some(name) = o.get("name").and_then(|x| x.as_str()) {
-                                    println!("{}", name);
-                                } else {
-                                    println!("{}", serde_json::to_string_pretty(item).unwrap_or_default());
-                                }
+        },
+        Commands::Template { cmd } => match cmd {
+            TemplateCmd::Test { dir, update } => {
+                let dir = dir.unwrap_or_else(|| Path::new(&conf.prompts_dir).join("tests"));
+                let constructor = PromptConstructor::with_dir(conf.prompts_dir.clone());
+                let (mut passed, mut failed) = (0, 0);
+                for case in load_cases(&dir)? {
+                    let case = match case {
+                        Ok(case) => case,
+                        Err(e) => {
+                            failed += 1;
+                            println!("FAIL {}", e);
+                            continue;
+                        }
+                    };
+                    let outcome = match (case.render(&constructor), &case.expected) {
+                        (Err(e), _) => Err(format!("  render failed: {}", e)),
+                        (Ok(rendered), _) if update => case.write_expected(&rendered).map(|_| "updated"),
+                        (Ok(_), None) => Err("  no expected output; run with --update".to_string()),
+                        (Ok(rendered), Some(expected)) => {
+                            let mismatches = diff_json(expected, &rendered);
+                            if mismatches.is_empty() {
+                                Ok("ok")
+                            } else {
+                                Err(mismatches.iter().map(|m| format!("  {}", m)).collect::<Vec<_>>().join("\n"))
                             }
-                            _ => println!("{}", item),
+                        }
+                    };
+                    match outcome {
+                        Ok(status) => {
+                            passed += 1;
+                            println!("{} {}", status, case.name());
+                        }
+                        Err(details) => {
+                            failed += 1;
+                            println!("FAIL {}\n{}", case.name(), details);
+                        }
+                    }
+                }
+                println!("{} passed, {} failed", passed, failed);
+                if failed > 0 {
+                    return Err(CliError::invalid(format!("{} template test(s) failed", failed)));
+                }
+                Ok(())
+            }
+        },
+        Commands::Gallery { since, workflow, limit, urls, proxy_url, open, json } => {
+            let age = parse_age(&since).ok_or_else(|| CliError::new(Exit::Usage, format!("Invalid --since '{}'; expected e.g. 30m, 12h, 1d, 2w", since)))?;
+            let root = PathBuf::from(&conf.static_drive_path);
+            if !root.join(INDEX_FILE).is_file() {
+                eprintln!("Nothing indexed under {} yet; the proxy indexes STATIC_DRIVE_PATH while it runs", root.display());
+            }
+            let base = proxy_url.unwrap_or_else(|| default_proxy_url(&conf));
+            let records = StaticDrivePoller::new(conf.static_drive_path.clone()).list(now_ms().saturating_sub(age), None).await;
+            let mut entries = Vec::new();
+            for record in records.into_iter().rev().filter(|r| is_media(&r.path)) {
+                if entries.len() >= limit {
+                    break;
+                }
+                let sidecar = sidecar_for(&root, &record.path);
+                let job_workflow = sidecar.as_ref().and_then(|s| s["workflow"].as_str()).map(String::from);
+                if workflow.is_some() && job_workflow != workflow {
+                    continue;
+                }
+                entries.push(json!({
+                    "path": record.path,
+                    "local_path": root.join(&record.path),
+                    "url": format!("{}/static/{}", base.trim_end_matches('/'), record.path),
+                    "workflow": job_workflow,
+                    "job_id": sidecar.as_ref().and_then(|s| s["job_id"].as_str()),
+                    "size": record.size,
+                    "modified_ms": record.modified_ms,
+                }));
+            }
+            if json {
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+            } else {
+                for entry in &entries {
+                    let location = if urls { &entry["url"] } else { &entry["local_path"] };
+                    println!(
+                        "{}  {}  {}",
+                        format_timestamp(entry["modified_ms"].as_u64().unwrap_or(0)),
+                        entry["workflow"].as_str().unwrap_or("-"),
+                        location.as_str().unwrap_or_default(),
+                    );
+                }
+            }
+            if open {
+                for entry in &entries {
+                    let path = Path::new(entry["local_path"].as_str().unwrap_or_default());
+                    if let Err(e) = open_in_viewer(path) {
+                        return Err(format!("Failed to open {}: {}", path.display(), e).into());
+                    }
+                }
+            }
+            Ok(())
+        }
+        Commands::Outputs { cmd: OutputsCmd::Export { out, tags, format, since, workflow, prefix, repeats, concept, trigger, force, json } } => {
+            let format = DatasetFormat::parse(&format)
+                .ok_or_else(|| CliError::new(Exit::Usage, format!("Unknown --format '{}'; expected kohya, flat, or imagefolder", format)))?;
+            let since_ms = match since {
+                Some(since) => {
+                    let age = parse_age(&since).ok_or_else(|| CliError::new(Exit::Usage, format!("Invalid --since '{}'; expected e.g. 30m, 12h, 1d, 2w", since)))?;
+                    now_ms().saturating_sub(age)
+                }
+                None => 0,
+            };
+            let root = PathBuf::from(&conf.static_drive_path);
+            if !root.join(INDEX_FILE).is_file() {
+                return Err(format!("Nothing indexed under {} yet; the proxy indexes STATIC_DRIVE_PATH while it runs", root.display()).into());
+            }
+            if !force && out.read_dir().map(|mut entries| entries.next().is_some()).unwrap_or(false) {
+                return Err(CliError::new(Exit::Usage, format!("{} isn't empty; pass --force to export into it anyway", out.display())));
+            }
+            let records = StaticDrivePoller::new(conf.static_drive_path.clone()).list(since_ms, prefix.as_deref()).await;
+            let mut items = Vec::new();
+            let mut uncaptioned = 0;
+            for record in records.iter().filter(|r| is_training_image(&r.path)) {
+                if workflow.is_some() && sidecar_for(&root, &record.path).and_then(|s| s["workflow"].as_str().map(String::from)) != workflow {
+                    continue;
+                }
+                let Some(caption) = caption_for(&root, record) else {
+                    uncaptioned += 1;
+                    continue;
+                };
+                if has_tags(&caption, &tags) {
+                    items.push(DatasetItem { path: record.path.clone(), caption });
+                }
+            }
+            if uncaptioned > 0 {
+                eprintln!("Skipped {} outputs without a caption (see the caption drive action)", uncaptioned);
+            }
+            if items.is_empty() {
+                return Err("No captioned outputs match".to_string().into());
+            }
+            let concept = concept.or_else(|| tags.first().cloned()).unwrap_or_else(|| "dataset".to_string());
+            let layout = DatasetLayout { format, repeats, concept: concept.trim().replace(['/', '\\', ' '], "_"), trigger };
+            let written = export_dataset(&root, &items, &out, &layout)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&json!({"out": out, "images": written.len(), "files": written}))?);
+            } else {
+                println!("Exported {} images to {}", written.len(), out.display());
+            }
+            Ok(())
+        }
+        Commands::Seeds { cmd: SeedsCmd::List { tenant, workflow, json } } => {
+            let journal = SeedJournal::from_config(&conf);
+            let favorites: Vec<_> = journal.list(tenant.as_deref()).into_iter()
+                .filter(|f| workflow.is_none() || f.workflow == workflow)
+                .collect();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&favorites)?);
+            } else if favorites.is_empty() {
+                eprintln!("No favorite seeds in {}", conf.seeds_file);
+            } else {
+                for f in favorites {
+                    println!(
+                        "{}  {}  {}  {}  job {}{}",
+                        format_timestamp(f.saved_at_ms),
+                        f.name,
+                        f.seed,
+                        f.workflow.as_deref().unwrap_or("-"),
+                        f.job_id,
+                        f.output.as_deref().map(|o| format!("  {}", o)).unwrap_or_default(),
+                    );
+                }
+            }
+            Ok(())
+        }
+        Commands::Jobs { proxy_url, api_key, cmd } => {
+            let proxy = ProxyClient::new(proxy_url.unwrap_or_else(|| default_proxy_url(&conf)), api_key);
+            match cmd {
+                JobsCmd::List { state, since, client, limit, json } => {
+                    let mut query = Vec::new();
+                    if let Some(state) = state {
+                        query.push(("state", state));
+                    }
+                    if let Some(since) = since {
+                        query.push(("since", since.to_string()));
+                    }
+                    if let Some(client) = client {
+                        query.push(("client", client));
+                    }
+                    if let Some(limit) = limit {
+                        query.push(("limit", limit.to_string()));
+                    }
+                    let body = proxy.get("/jobs", &query).await?;
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&body)?);
+                    } else {
+                        for job in body["jobs"].as_array().into_iter().flatten() {
+                            println!("{}", job_line(job));
                         }
                     }
+                    Ok(())
+                }
+                JobsCmd::Show { id, json } => {
+                    let job = proxy.get(&format!("/jobs/{}", id), &[]).await?;
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&job)?);
+                    } else {
+                        print_job(&job);
+                    }
+                    Ok(())
+                }
+                JobsCmd::Wait { id, timeout, json } => {
+                    let job = wait_for_job(&proxy, &id, Duration::from_secs(timeout)).await?;
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&job)?);
+                    } else {
+                        for file in job["outputs"]["files"].as_array().into_iter().flatten() {
+                            println!("{}{}", proxy.base, file["url"].as_str().unwrap_or_default());
+                        }
+                    }
+                    if job["state"] == "failed" {
+                        return Err(CliError::new(Exit::JobFailed, format!("job {} failed", id)));
+                    }
+                    Ok(())
+                }
+                JobsCmd::Cancel { id } => {
+                    let body = proxy.post(&format!("/jobs/{}/cancel", id)).await?;
+                    println!("{} {}", id, body["action"].as_str().unwrap_or("cancelled"));
+                    Ok(())
+                }
+                JobsCmd::Rerun { id } => {
+                    let body = proxy.post(&format!("/jobs/{}/rerun", id)).await?;
+                    println!("job_id={} prompt_id={}", body["job_id"].as_str().unwrap_or_default(), body["prompt_id"].as_str().unwrap_or_default());
+                    Ok(())
+                }
+            }
+        }
+        Commands::Workflow { cmd } => match cmd {
+            WorkflowCmd::Convert { file, out, offline } => {
+                let doc: Value = serde_json::from_str(&tokio::fs::read_to_string(&file).await?)?;
+                let info = if offline || detect_format(&doc) != WorkflowFormat::Ui {
+                    None
                 } else {
-                    println!("{}", serde_json::to_string_pretty(&v)?);
+                    match ComfyUIClient::from_config(&conf).get_object_info_raw().await {
+                        Ok(info) => Some(info),
+                        Err(e) => {
+                            eprintln!("Warning: converting without node definitions ({})", e);
+                            None
+                        }
+                    }
+                };
+                let (graph, format) = to_api_graph_with(&doc, info.as_ref()).map_err(|e| CliError::invalid(format!("{}: {}", file.display(), e)))?;
+                validate_api_graph(&graph).map_err(|e| CliError::invalid(format!("{}: {}", file.display(), e)))?;
+                let text = serde_json::to_string_pretty(&graph)?;
+                match out {
+                    Some(path) => {
+                        tokio::fs::write(&path, text).await?;
+                        let nodes = graph.as_object().map(|m| m.len()).unwrap_or(0);
+                        eprintln!("Wrote {} ({} nodes, from {} format)", path.display(), nodes, format.as_str());
+                    }
+                    None => println!("{}", text),
                 }
                 Ok(())
             }
-            ModelsCmd::Checkpoints { json } => {
-                let client = ComfyUIClient::new(conf.comfyui_url.clone());
-                let v = client.get_checkpoints().await?;
+            WorkflowCmd::Validate { workflow, file, json } => {
+                let path = match (workflow, file) {
+                    (Some(name), None) => format!("{}/{}.json", conf.prompts_dir.trim_end_matches('/'), name),
+                    (None, Some(p)) => p,
+                    _ => return Err(CliError::new(Exit::Usage, "Must provide either --workflow <name> or --file <path>")),
+                };
+                let data = tokio::fs::read_to_string(&path).await.map_err(|e| format!("{}: {}", path, e))?;
+                let mut doc: Value = serde_json::from_str(&data)?;
+                WorkflowManager::with_dir(conf.prompts_dir.clone()).expand_includes(&mut doc).map_err(CliError::invalid)?;
+                let doc = doc.get("prompt").cloned().unwrap_or(doc);
+                let (graph, _) = to_api_graph(&doc).map_err(|e| CliError::invalid(format!("{}: {}", path, e)))?;
+                let client = ComfyUIClient::from_config(&conf);
+                let errors = validate_against_object_info(&graph, &client.get_object_info_raw().await?);
                 if json {
-                    println!("{}", serde_json::to_string(&v)?);
-                } else if let Some(arr) = v.as_array() {
-                    for item in arr {
-                        if let Some(s) = item.as_str() { println!("{}", s); } else { println!("{}", item); }
+                    println!("{}", serde_json::to_string_pretty(&json!({"valid": errors.is_empty(), "errors": errors}))?);
+                } else if errors.is_empty() {
+                    println!("{}: OK", path);
+                } else {
+                    for issue in &errors {
+                        let class_type = issue.class_type.as_deref().map(|c| format!(" ({})", c)).unwrap_or_default();
+                        let input = issue.input.as_deref().map(|i| format!(".{}", i)).unwrap_or_default();
+                        println!("node {}{}{}: [{}] {}", issue.node, class_type, input, issue.code, issue.message);
                     }
+                }
+                if !errors.is_empty() {
+                    return Err(CliError::invalid(format!("{}: {} validation error(s)", path, errors.len())));
+                }
+                Ok(())
+            }
+        },
+        Commands::Models { cmd } => match cmd {
+            ModelsCmd::Categories { json } => {
+                let client = ComfyUIClient::from_config(&conf);
+                if json {
+                    println!("{}", serde_json::to_string(&client.get_model_categories_raw().await?)?);
+                } else {
+                    for name in client.get_model_categories().await? { println!("{}", name); }
+                }
+                Ok(())
+            }
+            ModelsCmd::List { category, json } => {
+                let client = ComfyUIClient::from_config(&conf);
+                if json {
+                    println!("{}", serde_json::to_string(&client.get_models_in_category_raw(&category).await?)?);
                 } else {
-                    println!("{}", serde_json::to_string_pretty(&v)?);
+                    for name in client.get_models_in_category(&category).await?.names { println!("{}", name); }
+                }
+                Ok(())
+            }
+            ModelsCmd::Checkpoints { json } => {
+                let client = ComfyUIClient::from_config(&conf);
+                if json {
+                    println!("{}", serde_json::to_string(&client.get_checkpoints_raw().await?)?);
+                } else {
+                    for name in client.get_checkpoints().await?.names { println!("{}", name); }
                 }
                 Ok(())
             }
@@ -383,63 +1321,449 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 }
 
-fn collect_filenames_for_id(v: &Value, prompt_id: &str, out: &mut Vec<String>) {
-    // Expected shapes vary by ComfyUI version. Try common cases.
-    match v {
-        Value::Object(map) => {
-            // Direct match on key == prompt_id
-            if let Some(entry) = map.get(prompt_id) {
-                collect_any_filenames(entry, out);
-            }
-            // Some servers wrap under "history"
-            if let Some(hist) = map.get("history") {
-                collect_filenames_for_id(hist, prompt_id, out);
+/// Exit codes, documented in the README's CLI section. Usage errors from
+/// argument parsing also exit with 2 (clap's default).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Exit {
+    /// Anything not covered below, e.g. local I/O errors.
+    Failure = 1,
+    /// Bad or missing arguments.
+    Usage = 2,
+    /// ComfyUI (or the proxy) couldn't be reached.
+    Unreachable = 3,
+    /// A workflow, template, or prompt failed validation, locally or in ComfyUI.
+    Invalid = 4,
+    /// A queued prompt failed or didn't finish in time.
+    JobFailed = 5,
+    /// ComfyUI (or the proxy) answered with a server error (5xx).
+    Upstream = 6,
+}
+
+#[derive(Debug)]
+struct CliError {
+    exit: Exit,
+    message: String,
+}
+
+type CliResult<T = ()> = Result<T, CliError>;
+
+impl CliError {
+    fn new(exit: Exit, message: impl Into<String>) -> Self {
+        CliError { exit, message: message.into() }
+    }
+
+    fn invalid(message: impl Into<String>) -> Self {
+        CliError::new(Exit::Invalid, message)
+    }
+
+    /// ComfyUI answered a prompt with an error: a rejected prompt is
+    /// invalid, while a server error is ComfyUI's own.
+    fn rejected(e: AppError) -> Self {
+        match e {
+            AppError::ComfyUI(message) if comfyui_status(&message).is_none_or(|s| s < 500) => CliError::invalid(message),
+            e => e.into(),
+        }
+    }
+}
+
+/// The HTTP status in a `ComfyUIClient` error message, which ends in it
+/// (`...: 500`) or names it (`Status: 500 Internal Server Error, Body: ...`).
+fn comfyui_status(message: &str) -> Option<u16> {
+    let status = match message.split_once("Status: ") {
+        Some((_, rest)) => rest.split(|c: char| !c.is_ascii_digit()).next()?,
+        None => message.rsplit(": ").next()?,
+    };
+    status.parse().ok().filter(|s| (100..600).contains(s))
+}
+
+impl From<AppError> for CliError {
+    fn from(e: AppError) -> Self {
+        let exit = match e {
+            AppError::HttpClient(ref e) if !e.is_decode() => Exit::Unreachable,
+            AppError::BadRequest(_) | AppError::InvalidField { .. } | AppError::PromptConstruction(_) => Exit::Invalid,
+            AppError::ComfyUI(ref message) if comfyui_status(message).is_some_and(|s| s >= 500) => Exit::Upstream,
+            _ => Exit::Failure,
+        };
+        CliError::new(exit, e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for CliError {
+    fn from(e: reqwest::Error) -> Self {
+        let exit = if e.is_connect() || e.is_timeout() { Exit::Unreachable } else { Exit::Failure };
+        CliError::new(exit, e.to_string())
+    }
+}
+
+/// Malformed JSON input (workflow files, fixtures).
+impl From<serde_json::Error> for CliError {
+    fn from(e: serde_json::Error) -> Self {
+        CliError::invalid(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for CliError {
+    fn from(e: std::io::Error) -> Self {
+        CliError::new(Exit::Failure, e.to_string())
+    }
+}
+
+impl From<String> for CliError {
+    fn from(message: String) -> Self {
+        CliError::new(Exit::Failure, message)
+    }
+}
+
+/// Width of the `--wait` progress bar, in characters.
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+/// Print progress for `prompt_id` from `events` (when available) until it
+/// finishes, then download its output images into `out_dir`. With `quiet`,
+/// saved files are reported on stderr so stdout holds only the prompt id.
+/// A prompt that fails or runs past `timeout` is an [`Exit::JobFailed`].
+async fn watch_prompt(
+    client: &ComfyUIClient,
+    events: Option<ProgressStream>,
+    prompt_id: &str,
+    timeout: Duration,
+    out_dir: &Path,
+    quiet: bool,
+) -> CliResult {
+    let deadline = tokio::time::Instant::now() + timeout;
+    if let Some(events) = events {
+        let followed = tokio::time::timeout_at(deadline, follow_progress(events, prompt_id)).await;
+        eprintln!();
+        match followed {
+            Ok(Err(e)) => eprintln!("Warning: lost live progress ({}); polling history instead", e),
+            Err(_) => return Err(CliError::new(Exit::JobFailed, format!("prompt {} did not finish within {:?}", prompt_id, timeout))),
+            Ok(Ok(())) => {}
+        }
+    }
+    // The history entry is the source of truth for outputs and errors.
+    let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+    let result = match client.wait_for_completion(prompt_id, remaining).await {
+        Err(AppError::Timeout(message)) => return Err(CliError::new(Exit::JobFailed, message)),
+        result => result?,
+    };
+    if !result.success {
+        let error = result.error.as_deref().unwrap_or("unknown error");
+        return Err(CliError::new(Exit::JobFailed, format!("prompt {} failed: {}", prompt_id, error)));
+    }
+
+    tokio::fs::create_dir_all(out_dir).await?;
+    let outputs: Vec<_> = result.images.iter().filter(|img| img.folder_type.as_deref() != Some("temp")).collect();
+    for img in &outputs {
+        let bytes = client.get_image_in(&img.filename, img.subfolder.as_deref(), img.folder_type.as_deref()).await?;
+        let path = out_dir.join(&img.filename);
+        tokio::fs::write(&path, &bytes).await?;
+        if quiet {
+            eprintln!("Saved {} ({} bytes)", path.display(), bytes.len());
+        } else {
+            println!("Saved {} ({} bytes)", path.display(), bytes.len());
+        }
+    }
+    if outputs.is_empty() {
+        eprintln!("prompt {} finished without output images", prompt_id);
+    }
+    Ok(())
+}
+
+/// Draw a progress line on stderr for each event of `prompt_id`. Returns once
+/// ComfyUI reports the prompt done (or failed); errors if the socket drops.
+async fn follow_progress(mut events: ProgressStream, prompt_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut current: Option<String> = None;
+    while let Some(event) = events.next().await {
+        let event = event?;
+        if event.prompt_id().is_some_and(|id| id != prompt_id) {
+            continue;
+        }
+        match event {
+            ProgressEvent::ExecutionStart { .. } => eprint!("\rstarted"),
+            ProgressEvent::Executing { node: Some(node), .. } => {
+                eprint!("\r\x1b[Knode {}", node);
+                current = Some(node);
             }
-            // Recurse into all values
-            for (_k, vv) in map.iter() {
-                collect_filenames_for_id(vv, prompt_id, out);
+            ProgressEvent::Executing { node: None, prompt_id: Some(_) } => return Ok(()),
+            ProgressEvent::Progress { value, max, node, .. } => {
+                let node = node.or_else(|| current.clone()).unwrap_or_default();
+                eprint!("\r\x1b[Knode {} {}", node, progress_bar(value, max));
             }
+            ProgressEvent::ExecutionError { .. } => return Ok(()),
+            _ => continue,
         }
-        Value::Array(arr) => {
-            for vv in arr { collect_filenames_for_id(vv, prompt_id, out); }
+        std::io::stderr().flush().ok();
+    }
+    Err("websocket closed".into())
+}
+
+/// `[#####.....] 5/10`
+fn progress_bar(value: u64, max: u64) -> String {
+    let filled = if max == 0 { 0 } else { (value.min(max) as usize * PROGRESS_BAR_WIDTH) / max as usize };
+    format!("[{}{}] {}/{}", "#".repeat(filled), ".".repeat(PROGRESS_BAR_WIDTH - filled), value, max)
+}
+
+/// `2026-10-16 09:30Z`
+fn format_timestamp(ms: u64) -> String {
+    let (year, month, day) = civil_date(ms);
+    let minutes = ms / 60_000 % (24 * 60);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}Z", year, month, day, minutes / 60, minutes % 60)
+}
+
+/// Open `path` with the desktop's default application, without waiting.
+fn open_in_viewer(path: &Path) -> std::io::Result<()> {
+    #[cfg(target_os = "macos")]
+    let mut command = std::process::Command::new("open");
+    #[cfg(target_os = "windows")]
+    let mut command = {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    };
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    let mut command = std::process::Command::new("xdg-open");
+    command.arg(path)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .map(|_| ())
+}
+
+/// Seconds each `jobs wait` long-poll asks the proxy to hold the request.
+const JOB_POLL_SECS: u64 = 10;
+
+/// The proxy this machine's config describes, for `jobs` without `--proxy-url`.
+fn default_proxy_url(conf: &Config) -> String {
+    let host = match conf.api_host.as_str() {
+        "0.0.0.0" | "" => "127.0.0.1",
+        host => host,
+    };
+    format!("http://{}:{}", host, conf.api_port)
+}
+
+/// JSON calls to a running proxy, with its `{"error": ...}` bodies turned
+/// into [`CliError`]s.
+struct ProxyClient {
+    http: reqwest::Client,
+    base: String,
+    api_key: Option<String>,
+}
+
+/// Sent as `User-Agent` and `X-Client`, so jobs show they came from here.
+const CLIENT_NAME: &str = concat!("comfyctl/", env!("CARGO_PKG_VERSION"));
+
+impl ProxyClient {
+    fn new(base: String, api_key: Option<String>) -> Self {
+        let http = reqwest::Client::builder().user_agent(CLIENT_NAME).build().unwrap_or_default();
+        ProxyClient { http, base: base.trim_end_matches('/').to_string(), api_key }
+    }
+
+    async fn get(&self, path: &str, query: &[(&str, String)]) -> CliResult<Value> {
+        self.send(self.http.get(format!("{}{}", self.base, path)).query(query)).await
+    }
+
+    async fn post(&self, path: &str) -> CliResult<Value> {
+        self.send(self.http.post(format!("{}{}", self.base, path))).await
+    }
+
+    async fn post_json(&self, path: &str, query: &[(&str, String)], body: &Value) -> CliResult<Value> {
+        self.send(self.http.post(format!("{}{}", self.base, path)).query(query).json(body)).await
+    }
+
+    async fn send(&self, mut request: reqwest::RequestBuilder) -> CliResult<Value> {
+        request = request.header("X-Client", CLIENT_NAME);
+        if let Some(key) = &self.api_key {
+            request = request.header("X-API-Key", key);
         }
-        _ => {}
+        let response = request.send().await?;
+        let status = response.status();
+        let body: Value = response.json().await.unwrap_or(Value::Null);
+        if status.is_success() {
+            return Ok(body);
+        }
+        let message = body["error"]["message"].as_str().map(String::from).unwrap_or_else(|| status.to_string());
+        let exit = match status.as_u16() {
+            400 | 422 => Exit::Invalid,
+            502..=504 => Exit::Unreachable,
+            500..=599 => Exit::Upstream,
+            _ => Exit::Failure,
+        };
+        Err(CliError::new(exit, message))
+    }
+}
+
+/// The API graph of `--workflow <name>` (under PROMPTS_DIR) or `--file <path>`.
+async fn load_workflow_graph(conf: &Config, workflow: Option<&str>, file: Option<&str>) -> CliResult<Value> {
+    let path = match (workflow, file) {
+        (Some(name), None) => PathBuf::from(&conf.prompts_dir).join(format!("{}.json", name)).to_string_lossy().to_string(),
+        (None, Some(p)) => p.to_string(),
+        _ => return Err(CliError::new(Exit::Usage, "Must provide either --workflow <name> or --file <path>")),
+    };
+    let data = tokio::fs::read_to_string(&path).await.map_err(|e| format!("{}: {}", path, e))?;
+    let raw: Value = serde_json::from_str(&data)?;
+
+    // Extract graph whether already wrapped or not
+    let graph = if let Some(p) = raw.get("prompt").cloned() { p } else { raw };
+    if !is_probably_graph(&graph) {
+        return Err(CliError::new(Exit::Invalid, format!("Workflow at '{}' does not look like a valid ComfyUI graph", path)));
     }
+    Ok(graph)
 }
 
-fn collect_any_filenames(v: &Value, out: &mut Vec<String>) {
-    match v {
-        Value::Object(map) => {
-            for (k, vv) in map.iter() {
-                if k == "filename" {
-                    if let Value::String(s) = vv { out.push(s.clone()); }
+/// How `prompt queue`, `prompt batch`, and `img2img` apply a payload to a graph.
+struct BuildOptions<'a> {
+    workflow: Option<&'a str>,
+    sets: &'a [String],
+    filename_prefix: &'a str,
+    strict_params: bool,
+    strict_set: bool,
+}
+
+struct BuiltPrompt {
+    /// `{"prompt": <graph>}` ready to queue.
+    body: Value,
+    /// The graph before params were applied, for `--dry-run` diffs.
+    base: Value,
+    warnings: Vec<String>,
+}
+
+/// Apply `payload` (params, groups, variation, `detail_faces`,
+/// `disable_nodes`, `sets`, `filename_prefix`) and `options.sets` to
+/// `graph` the way the proxy's `/queue_prompt` does. Favorite seeds are
+/// resolved from SEEDS_FILE.
+fn build_prompt_body(conf: &Config, graph: &Value, payload: &Value, options: &BuildOptions) -> CliResult<BuiltPrompt> {
+    let mut payload = payload.clone();
+    if payload["seed"].is_string() || payload["params"]["seed"].is_string() {
+        SeedJournal::from_config(conf).resolve(&mut payload, None).map_err(CliError::invalid)?;
+    }
+    let mut body = json!({"prompt": graph});
+    let mut warnings = apply_group_toggles(&mut body, &payload).map_err(CliError::invalid)?;
+    let base = body["prompt"].clone();
+    let title_patterns = TitlePatterns::from_config(conf);
+    for warning in apply_overrides_from_payload(&mut body, &payload, &title_patterns).map_err(CliError::invalid)? {
+        if options.strict_params {
+            return Err(CliError::invalid(warning));
+        }
+        warnings.push(warning);
+    }
+    apply_variation(&payload, &mut body["prompt"]).map_err(CliError::invalid)?;
+    apply_detail_faces(&mut body, &payload, &conf.face_detailer_nodes).map_err(CliError::invalid)?;
+    warnings.extend(apply_disable_nodes(&mut body, &payload).map_err(CliError::invalid)?);
+
+    // Apply dynamic overrides; paths may address the graph or the
+    // body (`prompt.2.inputs.seed`)
+    let mut sets = options.sets.to_vec();
+    sets.extend(payload["sets"].as_array().into_iter().flatten().filter_map(|v| v.as_str().map(String::from)));
+    if !sets.is_empty() {
+        let pairs = parse_set_pairs(&sets).map_err(|e| CliError::new(Exit::Usage, e))?;
+        for (path, new_val) in pairs {
+            let applied = apply_set_path(&mut body["prompt"], &path, new_val.clone())
+                || apply_set_path(&mut body, &path, new_val);
+            if !applied {
+                if options.strict_set {
+                    return Err(CliError::invalid(format!("could not apply --set to path: {}", path.join("."))));
                 }
-                collect_any_filenames(vv, out);
+                warnings.push(format!("could not apply --set to path: {}", path.join(".")));
             }
         }
-        Value::Array(arr) => {
-            for vv in arr { collect_any_filenames(vv, out); }
-        }
-        _ => {}
     }
+
+    let negatives = NegativePrompts::from_config(conf);
+    apply_default_negative_from_payload(&mut body, &payload, negatives.for_workflow(options.workflow), &title_patterns);
+    let filename_prefix = payload["filename_prefix"].as_str().unwrap_or(options.filename_prefix);
+    ensure_defaults_on_root(&mut body, Some(filename_prefix));
+    Ok(BuiltPrompt { body, base, warnings })
 }
 
-// (moved to utils::prompt_build)
+/// A `--seed` flag: an integer, or `favorite:<name>`.
+fn seed_value(seed: String) -> CliResult<Value> {
+    match seed.parse::<i64>() {
+        Ok(n) => Ok(Value::from(n)),
+        Err(_) if seed.starts_with(FAVORITE_PREFIX) => Ok(Value::String(seed)),
+        Err(_) => Err(CliError::new(Exit::Usage, format!("Invalid --seed '{}'; expected an integer or favorite:<name>", seed))),
+    }
+}
 
-fn collect_prompt_ids(v: &Value, out: &mut Vec<String>) {
-    match v {
-        Value::Object(map) => {
-            // Keys that look like UUIDs or known prompt ids
-            for (k, vv) in map.iter() {
-                if k.len() >= 8 && vv.is_object() { out.push(k.clone()); }
-                if k == "history" { collect_prompt_ids(vv, out); }
+/// `prompt sweep --param KEY=V1,V2,...` flags as a `/sweep` body. Values
+/// that parse as JSON numbers or booleans are sent as such; text params
+/// aren't split on commas, and a key given one value is fixed, not swept.
+fn sweep_params(params: &[String]) -> CliResult<Map<String, Value>> {
+    let mut body = Map::new();
+    for param in params {
+        let (key, raw) = param.split_once('=').ok_or_else(|| CliError::invalid(format!("Expected KEY=VALUES, got '{}'", param)))?;
+        let key = key.trim();
+        let raws: Vec<&str> = if key.starts_with("text") { vec![raw] } else { raw.split(',').collect() };
+        let entry = body.entry(key.to_string()).or_insert_with(|| Value::Array(Vec::new()));
+        for raw in raws {
+            let value = match serde_json::from_str::<Value>(raw.trim()) {
+                Ok(v @ (Value::Number(_) | Value::Bool(_))) => v,
+                _ => Value::String(raw.to_string()),
+            };
+            if let Some(values) = entry.as_array_mut() {
+                values.push(value);
             }
         }
-        Value::Array(arr) => {
-            for vv in arr { collect_prompt_ids(vv, out); }
+    }
+    // A single value is a fixed param rather than a one-value sweep.
+    for value in body.values_mut() {
+        if let Some([only]) = value.as_array().map(|v| v.as_slice()) {
+            *value = only.clone();
         }
-        _ => {}
     }
+    Ok(body)
 }
 
+/// `<job_id>  <state>  <workflow>  <saved>/<expected>`
+fn job_line(job: &Value) -> String {
+    format!(
+        "{}  {:<9}  {}  {}/{}",
+        job["job_id"].as_str().unwrap_or_default(),
+        job["state"].as_str().unwrap_or_default(),
+        job["workflow"].as_str().unwrap_or("-"),
+        job["outputs"]["saved"],
+        job["outputs"]["expected"],
+    )
+}
+
+fn print_job(job: &Value) {
+    println!("job_id:    {}", job["job_id"].as_str().unwrap_or_default());
+    println!("prompt_id: {}", job["prompt_id"].as_str().unwrap_or("-"));
+    println!("workflow:  {}", job["workflow"].as_str().unwrap_or("-"));
+    println!("client:    {}", job["client"].as_str().unwrap_or("-"));
+    println!("state:     {}", job["state"].as_str().unwrap_or_default());
+    println!("outputs:   {}/{}", job["outputs"]["saved"], job["outputs"]["expected"]);
+    if let Some(cost) = job["cost"]["actual"].as_f64().or_else(|| job["cost"]["estimated"].as_f64()) {
+        println!("cost:      {}", cost);
+    }
+    for file in job["outputs"]["files"].as_array().into_iter().flatten() {
+        println!("  {}", file["url"].as_str().unwrap_or_default());
+    }
+}
+
+/// Long-poll the proxy until job `id` completes or fails, redrawing its
+/// state and saved outputs on stderr. Running past `timeout` is an
+/// [`Exit::JobFailed`].
+async fn wait_for_job(proxy: &ProxyClient, id: &str, timeout: Duration) -> CliResult<Value> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut job = proxy.get(&format!("/jobs/{}", id), &[]).await?;
+    loop {
+        let state = job["state"].as_str().unwrap_or_default().to_string();
+        let (saved, expected) = (job["outputs"]["saved"].as_u64().unwrap_or(0), job["outputs"]["expected"].as_u64().unwrap_or(0));
+        eprint!("\r\x1b[K{} {}", state, progress_bar(saved, expected));
+        std::io::stderr().flush().ok();
+        if state == "completed" || state == "failed" {
+            eprintln!();
+            return Ok(job);
+        }
+        let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+        if remaining.is_zero() {
+            eprintln!();
+            return Err(CliError::new(Exit::JobFailed, format!("job {} did not finish within {:?}", id, timeout)));
+        }
+        let wait = remaining.as_secs().clamp(1, JOB_POLL_SECS);
+        job = proxy.get(&format!("/jobs/{}", id), &[("wait", wait.to_string()), ("state", state)]).await?;
+    }
+}
+
+// (moved to utils::prompt_build)
+
 // helper functions moved to utils::prompt_ops
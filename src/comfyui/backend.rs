@@ -0,0 +1,585 @@
+//! What the ComfyUI backends are, which endpoints they have, and which one
+//! a prompt goes to.
+//!
+//! ComfyUI changes its HTTP API without versioning it: `/system_stats` only
+//! reports `comfyui_version` on recent releases, `/models` is missing from
+//! older ones, and old history entries carry no `status`. [`BackendDetector`]
+//! reads `/system_stats` and probes the endpoints the proxy relies on, so
+//! handlers can adapt to what's there instead of guessing.
+//!
+//! With several instances in `COMFYUI_URLS`, [`BackendPool`] sends each new
+//! prompt to the healthy backend with the shortest queue, as of the last
+//! `/queue` check plus prompts sent since. It remembers which backend has
+//! each prompt, so history, images, interrupts, and queue deletes for it go
+//! to the same place; prompts it doesn't know (from before a restart) are
+//! looked for on every backend. Listings (`/history`, `/queue`) are merged
+//! across backends, and input images are uploaded to all of them, since any
+//! backend may run the prompt that uses them.
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+use crate::comfyui::client::{ComfyUIClient, ImageStream};
+use crate::events::{Audience, EventBus, ProxyEvent, ProxyEventKind};
+use crate::comfyui::types::{HistoryEntry, QueuePromptResponse, QueueStatus, UploadedImage};
+use crate::error::{AppError, AppResult};
+use crate::utils::time::now_ms;
+
+/// How long a detection is reused before the backend is asked again.
+const DETECTION_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Device {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub device_type: Option<String>,
+    pub vram_total: Option<u64>,
+    pub vram_free: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BackendFeatures {
+    /// `/system_stats` exists.
+    pub system_stats: bool,
+    /// `/models` lists model folders; otherwise the proxy reads model files
+    /// off loader nodes in `/object_info`.
+    pub models: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BackendInfo {
+    pub url: String,
+    pub reachable: bool,
+    /// ComfyUI's release, when `/system_stats` reports it.
+    pub version: Option<String>,
+    pub python_version: Option<String>,
+    pub pytorch_version: Option<String>,
+    pub os: Option<String>,
+    pub devices: Vec<Device>,
+    pub features: BackendFeatures,
+    pub detected_at_ms: u64,
+    /// Why the backend couldn't be reached.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BackendInfo {
+    /// Detection result from the probe responses: `/system_stats` (`None`
+    /// when the endpoint is missing) and whether `/models` exists.
+    pub fn from_probes(url: &str, stats: Option<&Value>, models: bool, at_ms: u64) -> Self {
+        let system = stats.and_then(|s| s.get("system"));
+        let text = |key: &str| system.and_then(|s| s.get(key)).and_then(|v| v.as_str()).map(String::from);
+        let devices = stats.and_then(|s| s.get("devices")).and_then(|d| d.as_array()).into_iter().flatten()
+            .map(|d| Device {
+                name: d.get("name").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+                device_type: d.get("type").and_then(|v| v.as_str()).map(String::from),
+                vram_total: d.get("vram_total").and_then(|v| v.as_u64()),
+                vram_free: d.get("vram_free").and_then(|v| v.as_u64()),
+            })
+            .collect();
+        BackendInfo {
+            url: url.to_string(),
+            reachable: true,
+            version: text("comfyui_version"),
+            python_version: text("python_version"),
+            pytorch_version: text("pytorch_version"),
+            os: text("os"),
+            devices,
+            features: BackendFeatures { system_stats: stats.is_some(), models },
+            detected_at_ms: at_ms,
+            error: None,
+        }
+    }
+
+    fn unreachable(url: &str, error: String, at_ms: u64) -> Self {
+        BackendInfo {
+            url: url.to_string(),
+            reachable: false,
+            version: None,
+            python_version: None,
+            pytorch_version: None,
+            os: None,
+            devices: Vec::new(),
+            features: BackendFeatures::default(),
+            detected_at_ms: at_ms,
+            error: Some(error),
+        }
+    }
+}
+
+/// Probe `client`'s backend.
+pub async fn detect(client: &ComfyUIClient) -> BackendInfo {
+    let url = client.base_url();
+    let probes = async {
+        let stats = client.get_system_stats_raw().await?;
+        let models = client.probe("/models").await?.is_some();
+        Ok::<_, crate::error::AppError>((stats, models))
+    };
+    match probes.await {
+        Ok((stats, models)) => BackendInfo::from_probes(url, stats.as_ref(), models, now_ms()),
+        Err(e) => BackendInfo::unreachable(url, e.to_string(), now_ms()),
+    }
+}
+
+/// The last detection, reused for [`DETECTION_TTL`]. Failed detections
+/// aren't kept, so the next request tries again.
+#[derive(Default)]
+pub struct BackendDetector {
+    cached: RwLock<Option<(Instant, Arc<BackendInfo>)>>,
+    /// Reachability as of the last detection, to spot transitions.
+    reachable: RwLock<Option<bool>>,
+    events: Option<EventBus>,
+}
+
+impl BackendDetector {
+    pub fn new() -> Self {
+        BackendDetector::default()
+    }
+
+    /// Announce the backend going up or down on `bus`.
+    pub fn set_event_bus(&mut self, bus: EventBus) {
+        self.events = Some(bus);
+    }
+
+    pub async fn get(&self, client: &ComfyUIClient, refresh: bool) -> Arc<BackendInfo> {
+        if let Some((at, info)) = self.cached.read().await.as_ref() {
+            if !refresh && at.elapsed() < DETECTION_TTL {
+                return info.clone();
+            }
+        }
+        let info = Arc::new(detect(client).await);
+        let was_reachable = self.reachable.write().await.replace(info.reachable);
+        if was_reachable != Some(info.reachable) {
+            if let Some(bus) = &self.events {
+                let kind = ProxyEventKind::BackendHealth { url: info.url.clone(), reachable: info.reachable, error: info.error.clone() };
+                bus.publish(ProxyEvent::now(kind, Audience::Everyone));
+            }
+        }
+        if info.reachable {
+            let previous = self.cached.write().await.replace((Instant::now(), info.clone()));
+            if previous.map(|(_, p)| p.version != info.version).unwrap_or(true) {
+                tracing::info!("ComfyUI backend at {}: version {}", info.url, info.version.as_deref().unwrap_or("unknown"));
+            }
+        }
+        info
+    }
+}
+
+struct Backend {
+    client: ComfyUIClient,
+    detector: BackendDetector,
+    /// Whether the last `/queue` check (or prompt) reached it.
+    healthy: AtomicBool,
+    /// Running and pending prompts as of the last check, plus prompts sent since.
+    queue_depth: AtomicUsize,
+}
+
+/// Load and health of one backend, for `/backends`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BackendLoad {
+    pub url: String,
+    pub healthy: bool,
+    pub queue_depth: usize,
+}
+
+/// Finished prompts whose backend is remembered, so their images and
+/// history are still fetched from where they ran; older ones are forgotten.
+const FINISHED_PROMPTS_KEPT: usize = 1000;
+
+/// Which backend each prompt was sent to.
+#[derive(Default)]
+struct Placements {
+    /// Prompt id -> (backend index, finished).
+    backend_of: HashMap<String, (usize, bool)>,
+    /// Finished prompts, oldest first.
+    finished: VecDeque<String>,
+}
+
+impl Placements {
+    fn place(&mut self, prompt_id: &str, index: usize) {
+        match self.backend_of.get_mut(prompt_id) {
+            Some(placed) => placed.0 = index,
+            None => {
+                self.backend_of.insert(prompt_id.to_string(), (index, false));
+            }
+        }
+    }
+
+    fn finish(&mut self, prompt_id: &str, index: usize) {
+        match self.backend_of.get_mut(prompt_id) {
+            Some((_, true)) => return,
+            Some(placed) => *placed = (index, true),
+            None => {
+                self.backend_of.insert(prompt_id.to_string(), (index, true));
+            }
+        }
+        self.finished.push_back(prompt_id.to_string());
+        while self.finished.len() > FINISHED_PROMPTS_KEPT {
+            if let Some(oldest) = self.finished.pop_front() {
+                self.backend_of.remove(&oldest);
+            }
+        }
+    }
+
+    /// Prompts on backend `index` not yet known to have finished.
+    fn unfinished_on(&self, index: usize) -> Vec<String> {
+        self.backend_of.iter()
+            .filter(|(_, &(i, finished))| i == index && !finished)
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+}
+
+/// The ComfyUI instances behind the proxy. The first is the primary, used
+/// for everything that doesn't depend on where a prompt runs (node
+/// definitions, model lists).
+pub struct BackendPool {
+    backends: Vec<Backend>,
+    /// Where prompts were sent. With a single backend there is nothing to
+    /// remember.
+    prompts: Mutex<Placements>,
+}
+
+impl BackendPool {
+    /// A pool of `primary` followed by `others`.
+    pub fn new(primary: ComfyUIClient, others: Vec<ComfyUIClient>) -> Self {
+        let backends = std::iter::once(primary).chain(others)
+            .map(|client| Backend { client, detector: BackendDetector::new(), healthy: AtomicBool::new(true), queue_depth: AtomicUsize::new(0) })
+            .collect();
+        BackendPool { backends, prompts: Mutex::new(Placements::default()) }
+    }
+
+    /// Announce backends going up or down on `bus`.
+    pub fn set_event_bus(&mut self, bus: EventBus) {
+        for backend in &mut self.backends {
+            backend.detector.set_event_bus(bus.clone());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.backends.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.backends.is_empty()
+    }
+
+    pub fn primary(&self) -> &ComfyUIClient {
+        &self.backends[0].client
+    }
+
+    pub fn urls(&self) -> Vec<String> {
+        self.backends.iter().map(|b| b.client.base_url().to_string()).collect()
+    }
+
+    /// The primary backend's detection; see [`BackendDetector::get`].
+    pub async fn primary_info(&self, refresh: bool) -> Arc<BackendInfo> {
+        self.backends[0].detector.get(&self.backends[0].client, refresh).await
+    }
+
+    /// Every backend's detection, in order.
+    pub async fn infos(&self, refresh: bool) -> Vec<Arc<BackendInfo>> {
+        let mut infos = Vec::with_capacity(self.backends.len());
+        for backend in &self.backends {
+            infos.push(backend.detector.get(&backend.client, refresh).await);
+        }
+        infos
+    }
+
+    pub fn loads(&self) -> Vec<BackendLoad> {
+        self.backends.iter()
+            .map(|b| BackendLoad {
+                url: b.client.base_url().to_string(),
+                healthy: b.healthy.load(Ordering::Relaxed),
+                queue_depth: b.queue_depth.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Check every backend's `/queue`: whether it answers, how deep its
+    /// queue is, and which prompts it holds.
+    pub async fn check_health(&self) {
+        for (index, backend) in self.backends.iter().enumerate() {
+            let unfinished = self.unfinished_on(index);
+            match backend.client.get_queue().await {
+                Ok(queue) => {
+                    self.set_healthy(index, true);
+                    self.observe_queue(index, &queue, unfinished);
+                }
+                Err(e) => {
+                    tracing::debug!("ComfyUI at {} failed its health check: {}", backend.client.base_url(), e);
+                    self.set_healthy(index, false);
+                }
+            }
+        }
+    }
+
+    fn set_healthy(&self, index: usize, healthy: bool) {
+        let backend = &self.backends[index];
+        if backend.healthy.swap(healthy, Ordering::Relaxed) != healthy && self.backends.len() > 1 {
+            let state = if healthy { "back in rotation" } else { "out of rotation" };
+            tracing::warn!("ComfyUI at {} is {}", backend.client.base_url(), state);
+        }
+    }
+
+    fn placements(&self) -> std::sync::MutexGuard<'_, Placements> {
+        self.prompts.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Remember that backend `index` has `prompt_ids` queued or running.
+    fn remember<'a>(&self, index: usize, prompt_ids: impl IntoIterator<Item = &'a str>) {
+        if self.backends.len() < 2 {
+            return;
+        }
+        let mut placements = self.placements();
+        for prompt_id in prompt_ids {
+            placements.place(prompt_id, index);
+        }
+    }
+
+    /// Remember that `prompt_ids` finished on backend `index`; only the most
+    /// recent [`FINISHED_PROMPTS_KEPT`] finished prompts are kept.
+    fn remember_finished<'a>(&self, index: usize, prompt_ids: impl IntoIterator<Item = &'a str>) {
+        if self.backends.len() < 2 {
+            return;
+        }
+        let mut placements = self.placements();
+        for prompt_id in prompt_ids {
+            placements.finish(prompt_id, index);
+        }
+    }
+
+    fn unfinished_on(&self, index: usize) -> Vec<String> {
+        self.placements().unfinished_on(index)
+    }
+
+    /// Record backend `index`'s queue. Of the prompts it had unfinished
+    /// before the queue was fetched, those no longer in it have finished
+    /// (or were removed).
+    fn observe_queue(&self, index: usize, queue: &QueueStatus, unfinished_before: Vec<String>) {
+        self.backends[index].queue_depth.store(queue.running.len() + queue.pending.len(), Ordering::Relaxed);
+        let queued: HashSet<&str> = queue.running.iter().chain(&queue.pending).map(|item| item.prompt_id.as_str()).collect();
+        self.remember(index, queued.iter().copied());
+        self.remember_finished(index, unfinished_before.iter().map(|id| id.as_str()).filter(|id| !queued.contains(id)));
+    }
+
+    /// Index of the backend known to have `prompt_id`.
+    fn backend_of(&self, prompt_id: &str) -> Option<usize> {
+        self.placements().backend_of.get(prompt_id).map(|&(index, _)| index)
+    }
+
+    /// Index (in `COMFYUI_URLS` order) of the backend that has `prompt_id`,
+    /// if known.
+    pub fn index_of(&self, prompt_id: &str) -> Option<usize> {
+        self.backend_of(prompt_id)
+    }
+
+    /// URL of the backend that has `prompt_id`, if known.
+    pub fn url_for(&self, prompt_id: &str) -> Option<String> {
+        self.backend_of(prompt_id).map(|i| self.backends[i].client.base_url().to_string())
+    }
+
+    /// Backend indexes to try for `prompt_id`: its own first, when known,
+    /// then the rest in order.
+    fn candidates(&self, prompt_id: Option<&str>) -> Vec<usize> {
+        let known = prompt_id.and_then(|id| self.backend_of(id));
+        known.into_iter().chain((0..self.backends.len()).filter(|i| Some(*i) != known)).collect()
+    }
+
+    /// Queue `prompt` on the healthy backend with the shortest queue. A
+    /// backend that refuses the connection is taken out of rotation and the
+    /// next one tried; any other failure is returned, since the prompt may
+    /// have been queued.
+    pub async fn queue_prompt(&self, prompt: Value) -> AppResult<QueuePromptResponse> {
+        let mut order: Vec<usize> = (0..self.backends.len()).collect();
+        order.sort_by_key(|&i| {
+            let backend = &self.backends[i];
+            (!backend.healthy.load(Ordering::Relaxed), backend.queue_depth.load(Ordering::Relaxed), i)
+        });
+        let mut last_error = None;
+        for index in order {
+            let backend = &self.backends[index];
+            match backend.client.queue_prompt(prompt.clone()).await {
+                Ok(response) => {
+                    self.set_healthy(index, true);
+                    backend.queue_depth.fetch_add(1, Ordering::Relaxed);
+                    self.remember(index, [response.prompt_id.as_str()]);
+                    if self.backends.len() > 1 {
+                        tracing::info!("Queued prompt {} on {}", response.prompt_id, backend.client.base_url());
+                    }
+                    return Ok(response);
+                }
+                Err(AppError::HttpClient(e)) if e.is_connect() => {
+                    self.set_healthy(index, false);
+                    last_error = Some(AppError::HttpClient(e));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| AppError::ComfyUI("No ComfyUI backends configured".to_string())))
+    }
+
+    /// `/history/{prompt_id}` from the backend that has the prompt.
+    pub async fn get_prompt_history_raw(&self, prompt_id: &str) -> AppResult<Value> {
+        let mut result = None;
+        for index in self.candidates(Some(prompt_id)) {
+            match self.backends[index].client.get_prompt_history_raw(prompt_id).await {
+                Ok(history) if history.get(prompt_id).is_some() => {
+                    self.remember_finished(index, [prompt_id]);
+                    return Ok(history);
+                }
+                // Still queued or running there.
+                Ok(history) if self.backend_of(prompt_id) == Some(index) => return Ok(history),
+                other => {
+                    result.get_or_insert(other);
+                }
+            }
+        }
+        result.unwrap_or_else(|| Ok(Value::Object(Map::new())))
+    }
+
+    pub async fn get_prompt_history(&self, prompt_id: &str) -> AppResult<Option<HistoryEntry>> {
+        let history = self.get_prompt_history_raw(prompt_id).await?;
+        Ok(history.get(prompt_id).map(|entry| serde_json::from_value(entry.clone())).transpose()?)
+    }
+
+    /// Every backend's `/history`, merged. Backends that can't be reached
+    /// are left out unless none can.
+    pub async fn get_history_raw(&self) -> AppResult<Value> {
+        let mut merged = Map::new();
+        let mut first_error = None;
+        let mut reached = false;
+        for (index, backend) in self.backends.iter().enumerate() {
+            match backend.client.get_history_raw().await {
+                Ok(Value::Object(history)) => {
+                    reached = true;
+                    self.remember_finished(index, history.keys().map(|k| k.as_str()));
+                    merged.extend(history);
+                }
+                Ok(_) => reached = true,
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match first_error {
+            Some(e) if !reached => Err(e),
+            _ => Ok(Value::Object(merged)),
+        }
+    }
+
+    /// Every backend's `/queue`, merged. Backends that can't be reached are
+    /// left out unless none can.
+    pub async fn get_queue(&self) -> AppResult<QueueStatus> {
+        let mut merged = QueueStatus::default();
+        let mut first_error = None;
+        let mut reached = false;
+        for (index, backend) in self.backends.iter().enumerate() {
+            let unfinished = self.unfinished_on(index);
+            match backend.client.get_queue().await {
+                Ok(queue) => {
+                    reached = true;
+                    self.observe_queue(index, &queue, unfinished);
+                    merged.running.extend(queue.running);
+                    merged.pending.extend(queue.pending);
+                }
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match first_error {
+            Some(e) if !reached => Err(e),
+            _ => Ok(merged),
+        }
+    }
+
+    /// Interrupt `prompt_id` on the backend running it.
+    pub async fn interrupt(&self, prompt_id: &str) -> AppResult<()> {
+        let index = self.backend_of(prompt_id).unwrap_or(0);
+        self.backends[index].client.interrupt(Some(prompt_id)).await
+    }
+
+    /// Remove pending prompts from whichever backends hold them.
+    pub async fn delete_from_queue(&self, prompt_ids: &[String]) -> AppResult<()> {
+        let mut by_backend: HashMap<usize, Vec<String>> = HashMap::new();
+        for prompt_id in prompt_ids {
+            by_backend.entry(self.backend_of(prompt_id).unwrap_or(0)).or_default().push(prompt_id.clone());
+        }
+        for (index, ids) in by_backend {
+            self.backends[index].client.delete_from_queue(&ids).await?;
+        }
+        Ok(())
+    }
+
+    /// Remove every pending prompt from every backend. Backends that fail
+    /// don't stop the rest from being cleared; their errors are returned by
+    /// URL, or the first one if none could be cleared.
+    pub async fn clear_queue(&self) -> AppResult<Vec<(String, AppError)>> {
+        let mut failed = Vec::new();
+        for backend in &self.backends {
+            if let Err(e) = backend.client.clear_queue().await {
+                tracing::warn!("Failed to clear the queue of ComfyUI at {}: {}", backend.client.base_url(), e);
+                failed.push((backend.client.base_url().to_string(), e));
+            }
+        }
+        if !failed.is_empty() && failed.len() == self.backends.len() {
+            return Err(failed.swap_remove(0).1);
+        }
+        Ok(failed)
+    }
+
+    /// An image from the backend that ran `prompt_id` (when given and
+    /// known), or from the first backend that has it.
+    pub async fn get_image_in(&self, prompt_id: Option<&str>, filename: &str, subfolder: Option<&str>, folder_type: Option<&str>) -> AppResult<Vec<u8>> {
+        let mut first_error = None;
+        for index in self.candidates(prompt_id) {
+            match self.backends[index].client.get_image_in(filename, subfolder, folder_type).await {
+                Ok(bytes) => return Ok(bytes),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        Err(first_error.unwrap_or_else(|| AppError::ComfyUI("No ComfyUI backends configured".to_string())))
+    }
+
+    /// [`ComfyUIClient::get_image_stream`] from the backend that ran
+    /// `prompt_id`, or the first backend that has the file.
+    pub async fn get_image_stream_in(&self, prompt_id: Option<&str>, filename: &str, subfolder: Option<&str>, folder_type: Option<&str>, range: Option<&str>) -> AppResult<ImageStream> {
+        let mut first_error = None;
+        for index in self.candidates(prompt_id) {
+            match self.backends[index].client.get_image_stream(filename, subfolder, folder_type, range).await {
+                Ok(image) => return Ok(image),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        Err(first_error.unwrap_or_else(|| AppError::ComfyUI("No ComfyUI backends configured".to_string())))
+    }
+
+    /// Upload an input image to every backend, under the name the first one
+    /// stored it as, so prompts can use it wherever they run. Backends other
+    /// than the primary that can't be reached are skipped with a warning.
+    pub async fn upload_image(&self, bytes: Vec<u8>, filename: &str, overwrite: bool) -> AppResult<UploadedImage> {
+        let uploaded = self.backends[0].client.upload_image(bytes.clone(), filename, overwrite).await?;
+        for backend in &self.backends[1..] {
+            if let Err(e) = backend.client.upload_image(bytes.clone(), &uploaded.name, true).await {
+                tracing::warn!("Failed to copy input {} to {}: {}", uploaded.name, backend.client.base_url(), e);
+            }
+        }
+        Ok(uploaded)
+    }
+
+    /// Write a file into the output folder of the backend that ran
+    /// `prompt_id`, so it's served next to the prompt's other outputs.
+    pub async fn upload_output(&self, prompt_id: Option<&str>, bytes: Vec<u8>, filename: &str, subfolder: Option<&str>) -> AppResult<UploadedImage> {
+        let index = prompt_id.and_then(|id| self.backend_of(id)).unwrap_or(0);
+        self.backends[index].client.upload_output(bytes, filename, subfolder).await
+    }
+}
@@ -1,42 +1,313 @@
 //! Thin HTTP client for ComfyUI endpoints.
 //!
+//! Methods return the typed structs in [`crate::comfyui::types`]; each has a
+//! `_raw` variant returning ComfyUI's JSON as-is.
+//!
 //! - `queue_prompt` posts a prompt JSON to `/prompt`.
-//! - `get_image` proxies to `/view?filename=...` and returns raw bytes.
+//! - `get_image` proxies to `/view?filename=...` and returns raw bytes;
+//!   `get_image_stream` returns the file's headers and a byte stream instead.
+//! - `upload_image` posts an input image to `/upload/image` for img2img and
+//!   inpainting workflows.
 //! - `get_history` fetches `/history` as JSON.
+//! - `get_prompt_history` fetches `/history/{prompt_id}` for a single prompt.
+//! - `wait_for_completion` polls `/history/{prompt_id}` until the prompt finishes.
+//! - `interrupt`, `get_queue`, `delete_from_queue`, and `clear_queue` manage
+//!   what ComfyUI is running and has queued.
+//! - `get_object_info_raw` fetches `/object_info`, the definitions of every
+//!   node class (cached by [`crate::comfyui::object_info`]).
+//! - `get_system_stats_raw` and `probe` tell what the backend is and which
+//!   endpoints it has (see [`crate::comfyui::backend`]).
+//!
+//! Requests time out after `COMFYUI_TIMEOUT_SECS`, so a hung ComfyUI can't
+//! stall callers. GETs that couldn't connect, timed out, or got a
+//! 502/503/504 are retried up to `COMFYUI_RETRIES` times with exponential
+//! backoff, since repeating them is harmless. POSTs are sent once unless
+//! `COMFYUI_RETRY_POSTS` opts in to retrying the ones that couldn't connect.
+//! See [`ComfyUIClientBuilder`]. Every request carries a
+//! `User-Agent` naming the proxy and its version (`PROXY_USER_AGENT`).
+//!
+//! Live progress over `/ws` lives in [`crate::comfyui::ws`]. With the
+//! `fixtures` feature, responses can be recorded and replayed; see
+//! `comfyui::fixtures`.
 use reqwest::Client;
 use serde_json::Value;
+#[cfg(feature = "fixtures")]
+use crate::comfyui::fixtures::{FixtureMode, Fixtures};
+use crate::config::{Config, DEFAULT_USER_AGENT};
+use crate::comfyui::types::{parse_history, unwrap_history, History, HistoryEntry, ModelList, PromptResult, QueuePromptResponse, QueueStatus, UploadedImage};
 use crate::error::{AppResult, AppError};
+use crate::metrics::Metrics;
+use std::pin::Pin;
+use std::sync::Arc;
 use std::time::Duration;
 
+/// How often `wait_for_completion` re-checks history.
+const COMPLETION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Builder defaults; `COMFYUI_TIMEOUT_SECS` and `COMFYUI_RETRIES` override
+/// the timeout and retry count.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60 * 25);
+const DEFAULT_RETRIES: u32 = 2;
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(250);
+/// Longest wait between retries, however many there have been.
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Configures a [`ComfyUIClient`]: timeouts, and how transient failures are
+/// retried. Every clone of the built client shares one connection pool.
+#[derive(Debug, Clone)]
+pub struct ComfyUIClientBuilder {
+    base_url: String,
+    connect_timeout: Duration,
+    timeout: Duration,
+    retries: u32,
+    retry_posts: bool,
+    backoff: Duration,
+    user_agent: String,
+}
+
+impl ComfyUIClientBuilder {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        ComfyUIClientBuilder {
+            base_url: base_url.into(),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+            retry_posts: false,
+            backoff: DEFAULT_BACKOFF,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+        }
+    }
+
+    /// Longest wait for a connection to ComfyUI.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Longest wait for a whole request, from connecting to reading the
+    /// last byte of the response.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many times a failed request is retried; 0 disables retries.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Also retry POSTs that couldn't connect. Off by default: a POST such
+    /// as `/prompt` that did reach ComfyUI before the connection broke would
+    /// be queued twice.
+    pub fn retry_posts(mut self, retry_posts: bool) -> Self {
+        self.retry_posts = retry_posts;
+        self
+    }
+
+    /// Wait before the first retry; doubled for each one after.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// `User-Agent` header for every request; defaults to
+    /// `comfyui-api-proxy/<version>`.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    pub fn build(self) -> ComfyUIClient {
+        let client = Client::builder()
+            .user_agent(self.user_agent)
+            .connect_timeout(self.connect_timeout.min(self.timeout))
+            .timeout(self.timeout)
+            .build()
+            .expect("failed to build reqwest client");
+        ComfyUIClient {
+            client,
+            base_url: self.base_url.trim_end_matches('/').to_string(),
+            retries: self.retries,
+            retry_posts: self.retry_posts,
+            backoff: self.backoff,
+            #[cfg(feature = "fixtures")]
+            fixtures: None,
+            metrics: None,
+        }
+    }
+}
+
+/// A file from `/view` whose body hasn't been read yet.
+pub struct ImageStream {
+    /// ComfyUI's `Content-Type`, if it sent one.
+    pub content_type: Option<String>,
+    pub content_length: Option<u64>,
+    /// ComfyUI's `ETag` and `Last-Modified`, if it sent them.
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// ComfyUI's `Content-Range` when it answered a forwarded `Range` with
+    /// 206; `content_length` is then the length of the part sent.
+    pub content_range: Option<String>,
+    pub body: Pin<Box<dyn futures_util::Stream<Item = reqwest::Result<hyper::body::Bytes>> + Send>>,
+}
+
+impl ImageStream {
+    /// Length of the whole file, even when only part of it was sent.
+    pub fn file_length(&self) -> Option<u64> {
+        match &self.content_range {
+            Some(range) => range.rsplit_once('/').and_then(|(_, total)| total.trim().parse().ok()),
+            None => self.content_length,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct ComfyUIClient {
     client: Client,
     base_url: String,
+    retries: u32,
+    /// Whether POSTs that couldn't connect are retried.
+    retry_posts: bool,
+    /// Wait before the first retry.
+    backoff: Duration,
+    /// Where responses are recorded to or replayed from.
+    #[cfg(feature = "fixtures")]
+    fixtures: Option<Arc<Fixtures>>,
+    /// Counts requests and errors for `/metrics`.
+    metrics: Option<Arc<Metrics>>,
 }
 
 impl ComfyUIClient {
+    /// A client with the builder's default timeouts and retries.
     pub fn new(base_url: String) -> Self {
-        let base = base_url.trim_end_matches('/').to_string();
-        let client = Client::builder()
-            // Allow long-running generations (e.g., 20 minutes)
-            .timeout(Duration::from_secs(60 * 25))
-            .build()
-            .expect("failed to build reqwest client");
-        ComfyUIClient { client, base_url: base }
+        ComfyUIClient::builder(base_url).build()
+    }
+
+    pub fn builder(base_url: impl Into<String>) -> ComfyUIClientBuilder {
+        ComfyUIClientBuilder::new(base_url)
+    }
+
+    /// A client for `COMFYUI_URL` with `COMFYUI_TIMEOUT_SECS`,
+    /// `COMFYUI_RETRIES`, `COMFYUI_RETRY_POSTS`, and `PROXY_USER_AGENT`, that records or replays its responses when
+    /// `COMFYUI_FIXTURES_DIR` is set (requires the `fixtures` feature).
+    pub fn from_config(config: &Config) -> Self {
+        ComfyUIClient::for_url(config, &config.comfyui_url)
+    }
+
+    /// Like [`ComfyUIClient::from_config`], for another backend at `url`.
+    pub fn for_url(config: &Config, url: &str) -> Self {
+        let client = ComfyUIClient::builder(url)
+            .timeout(Duration::from_secs(config.comfyui_timeout_secs.max(1)))
+            .retries(config.comfyui_retries)
+            .retry_posts(config.comfyui_retry_posts)
+            .user_agent(config.user_agent.clone())
+            .build();
+        let Some(dir) = &config.comfyui_fixtures_dir else { return client };
+        #[cfg(feature = "fixtures")]
+        {
+            let mode = FixtureMode::parse(&config.comfyui_fixtures_mode).unwrap_or_else(|| {
+                tracing::warn!("Unknown COMFYUI_FIXTURES_MODE '{}', replaying", config.comfyui_fixtures_mode);
+                FixtureMode::Replay
+            });
+            tracing::info!("ComfyUI responses: {:?} with fixtures in {}", mode, dir);
+            client.with_fixtures(Fixtures::new(dir, mode))
+        }
+        #[cfg(not(feature = "fixtures"))]
+        {
+            tracing::error!("COMFYUI_FIXTURES_DIR={} needs a build with --features fixtures; ignoring it", dir);
+            client
+        }
+    }
+
+    /// Record responses to, or replay them from, `fixtures`.
+    #[cfg(feature = "fixtures")]
+    pub fn with_fixtures(mut self, fixtures: Fixtures) -> Self {
+        self.fixtures = Some(Arc::new(fixtures));
+        self
+    }
+
+    /// Count requests to ComfyUI, and those that fail, in `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Send `request`, retrying transient failures. POSTs are sent once
+    /// unless `retry_posts` is set, and requests whose body can't be
+    /// replayed (multipart uploads) always are.
+    async fn send(&self, request: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+        let mut request = request.build()?;
+        let idempotent = request.method() == reqwest::Method::GET;
+        let retryable = idempotent || self.retry_posts;
+        let mut attempt = 0;
+        loop {
+            let next = if retryable && attempt < self.retries { request.try_clone() } else { None };
+            let (method, path) = (request.method().clone(), request.url().path().to_string());
+            let result = self.send_once(request).await;
+            let Some(next) = next else { return result };
+            let transient = match &result {
+                Err(e) => e.is_connect() || (idempotent && e.is_timeout()),
+                Ok(response) => idempotent && matches!(response.status().as_u16(), 502..=504),
+            };
+            if !transient {
+                return result;
+            }
+            let delay = self.backoff.saturating_mul(1 << attempt.min(16)).min(MAX_BACKOFF);
+            match &result {
+                Ok(response) => tracing::warn!("ComfyUI {} {} returned {}; retrying in {:?}", method, path, response.status(), delay),
+                Err(e) => tracing::warn!("ComfyUI {} {} failed ({}); retrying in {:?}", method, path, e, delay),
+            }
+            tokio::time::sleep(delay).await;
+            request = next;
+            attempt += 1;
+        }
+    }
+
+    /// Send `request` once, through the fixtures when there are any.
+    async fn send_once(&self, request: reqwest::Request) -> reqwest::Result<reqwest::Response> {
+        let path = request.url().path().to_string();
+        #[cfg(feature = "fixtures")]
+        if let Some(fixtures) = &self.fixtures {
+            if fixtures.mode() == FixtureMode::Replay {
+                return fixtures.send(&self.client, request).await;
+            }
+            let result = fixtures.send(&self.client, request).await;
+            self.observe(&path, &result);
+            return result;
+        }
+        let result = self.client.execute(request).await;
+        self.observe(&path, &result);
+        result
+    }
+
+    fn observe(&self, path: &str, result: &reqwest::Result<reqwest::Response>) {
+        if let Some(metrics) = &self.metrics {
+            let failed = result.as_ref().map(|r| r.status().is_server_error()).unwrap_or(true);
+            metrics.observe_upstream(path, failed);
+        }
+    }
+
+    /// Base URL of the ComfyUI server, without a trailing slash.
+    pub fn base_url(&self) -> &str {
+        &self.base_url
     }
 
     /// Queue a prompt with ComfyUI.
     ///
     /// Expects a JSON document compatible with ComfyUI's `/prompt` endpoint.
-    /// Returns the JSON response from ComfyUI on success.
-    pub async fn queue_prompt(&self, prompt: Value) -> AppResult<Value> {
+    pub async fn queue_prompt(&self, prompt: Value) -> AppResult<QueuePromptResponse> {
+        Ok(serde_json::from_value(self.queue_prompt_raw(prompt).await?)?)
+    }
+
+    /// Queue a prompt, returning ComfyUI's JSON response as-is.
+    pub async fn queue_prompt_raw(&self, prompt: Value) -> AppResult<Value> {
         let url = format!("{}/prompt", self.base_url);
         tracing::info!("Sending prompt to ComfyUI at URL: {}", url);
         tracing::debug!("Prompt payload: {:?}", prompt);
     
-        let response = self.client.post(&url)
-            .json(&prompt)
-            .send()
+        let response = self.send(self.client.post(&url).json(&prompt))
             .await
             .map_err(AppError::HttpClient)?;
     
@@ -55,42 +326,265 @@ impl ComfyUIClient {
 
     /// Fetch image bytes by filename via ComfyUI's `/view` endpoint.
     pub async fn get_image(&self, filename: &str) -> AppResult<Vec<u8>> {
+        self.get_image_in(filename, None, None).await
+    }
+
+    /// Fetch an output image from a `subfolder` of ComfyUI's `output` (or
+    /// another `folder_type` such as `temp`/`input`).
+    pub async fn get_image_in(&self, filename: &str, subfolder: Option<&str>, folder_type: Option<&str>) -> AppResult<Vec<u8>> {
+        let response = self.view(filename, subfolder, folder_type, None).await?;
+        response.bytes().await.map(|b| b.to_vec()).map_err(AppError::HttpClient)
+    }
+
+    /// Like [`get_image_in`](Self::get_image_in), but returns as soon as
+    /// ComfyUI answers, with the body still to be read, so large files can
+    /// be passed on without holding them in memory. A `range` (a `Range`
+    /// header value) is passed on to `/view`; see
+    /// [`ImageStream::content_range`] for whether ComfyUI honoured it. A
+    /// range past the end of the file gets the whole file.
+    pub async fn get_image_stream(&self, filename: &str, subfolder: Option<&str>, folder_type: Option<&str>, range: Option<&str>) -> AppResult<ImageStream> {
+        let mut response = self.view(filename, subfolder, folder_type, range).await?;
+        if response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+            response = self.view(filename, subfolder, folder_type, None).await?;
+        }
+        let header = |name: reqwest::header::HeaderName| response.headers().get(name).and_then(|v| v.to_str().ok()).map(String::from);
+        let partial = response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+        Ok(ImageStream {
+            content_type: header(reqwest::header::CONTENT_TYPE),
+            content_length: response.content_length(),
+            etag: header(reqwest::header::ETAG),
+            last_modified: header(reqwest::header::LAST_MODIFIED),
+            content_range: header(reqwest::header::CONTENT_RANGE).filter(|_| partial),
+            body: Box::pin(response.bytes_stream()),
+        })
+    }
+
+    /// A successful `/view` response for the file, or part of it with a
+    /// `range` (which may also get a 416).
+    async fn view(&self, filename: &str, subfolder: Option<&str>, folder_type: Option<&str>, range: Option<&str>) -> AppResult<reqwest::Response> {
         let url = format!("{}/view", self.base_url);
-        let response = self.client.get(&url)
-            .query(&[("filename", filename)])
-            .send()
+        let mut query = vec![("filename", filename)];
+        if let Some(subfolder) = subfolder { query.push(("subfolder", subfolder)); }
+        if let Some(folder_type) = folder_type { query.push(("type", folder_type)); }
+        let mut request = self.client.get(&url).query(&query);
+        if let Some(range) = range {
+            request = request.header(reqwest::header::RANGE, range);
+        }
+        let response = self.send(request)
             .await
             .map_err(AppError::HttpClient)?;
 
-        if response.status().is_success() {
-            response.bytes().await.map(|b| b.to_vec()).map_err(AppError::HttpClient)
+        let unsatisfiable = range.is_some() && response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE;
+        if response.status().is_success() || unsatisfiable {
+            Ok(response)
         } else {
             Err(AppError::ComfyUI(format!("Failed to get image: {:?}", response.status())))
         }
     }
 
+    /// Upload an image into ComfyUI's `input` folder. Without `overwrite`,
+    /// ComfyUI renames the file if the name is taken; the returned
+    /// [`UploadedImage::name`] is the one to reference from `LoadImage`.
+    pub async fn upload_image(&self, bytes: Vec<u8>, filename: &str, overwrite: bool) -> AppResult<UploadedImage> {
+        Ok(serde_json::from_value(self.upload_image_raw(bytes, filename, overwrite).await?)?)
+    }
+
+    /// Upload an image, returning ComfyUI's JSON response as-is.
+    pub async fn upload_image_raw(&self, bytes: Vec<u8>, filename: &str, overwrite: bool) -> AppResult<Value> {
+        let form = reqwest::multipart::Form::new()
+            .part("image", reqwest::multipart::Part::bytes(bytes).file_name(filename.to_string()))
+            .text("overwrite", overwrite.to_string());
+        self.post_upload(form).await
+    }
+
+    /// Write a file into ComfyUI's `output` folder (under `subfolder`), so it
+    /// can be served by `/view` like a generated image. Replaces an existing
+    /// file of the same name.
+    pub async fn upload_output(&self, bytes: Vec<u8>, filename: &str, subfolder: Option<&str>) -> AppResult<UploadedImage> {
+        let mut form = reqwest::multipart::Form::new()
+            .part("image", reqwest::multipart::Part::bytes(bytes).file_name(filename.to_string()))
+            .text("type", "output")
+            .text("overwrite", "true");
+        if let Some(subfolder) = subfolder {
+            form = form.text("subfolder", subfolder.to_string());
+        }
+        Ok(serde_json::from_value(self.post_upload(form).await?)?)
+    }
+
+    async fn post_upload(&self, form: reqwest::multipart::Form) -> AppResult<Value> {
+        let url = format!("{}/upload/image", self.base_url);
+        let response = self.send(self.client.post(&url).multipart(form))
+            .await
+            .map_err(AppError::HttpClient)?;
+
+        if response.status().is_success() {
+            response.json().await.map_err(AppError::HttpClient)
+        } else {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            Err(AppError::ComfyUI(format!("Failed to upload image. Status: {}, Body: {}", status, body)))
+        }
+    }
+
+    /// Retrieve ComfyUI execution history, keyed by prompt id.
+    pub async fn get_history(&self) -> AppResult<History> {
+        Ok(parse_history(self.get_history_raw().await?))
+    }
+
     /// Retrieve ComfyUI execution history as JSON.
-    pub async fn get_history(&self) -> AppResult<Value> {
+    pub async fn get_history_raw(&self) -> AppResult<Value> {
         let url = format!("{}/history", self.base_url);
-        let response = self.client.get(&url)
-            .send()
+        let response = self.send(self.client.get(&url))
             .await
             .map_err(AppError::HttpClient)?;
 
         if response.status().is_success() {
-            response.json().await.map_err(AppError::HttpClient)
+            response.json().await.map(unwrap_history).map_err(AppError::HttpClient)
         } else {
             Err(AppError::ComfyUI(format!("Failed to get history: {:?}", response.status())))
         }
     }
 
+    /// Retrieve the history entry for a single prompt from
+    /// `/history/{prompt_id}`. `None` while the prompt is still queued or
+    /// running.
+    pub async fn get_prompt_history(&self, prompt_id: &str) -> AppResult<Option<HistoryEntry>> {
+        let mut history = parse_history(self.get_prompt_history_raw(prompt_id).await?);
+        Ok(history.remove(prompt_id))
+    }
+
+    /// Retrieve `/history/{prompt_id}` as JSON: the object keyed by prompt
+    /// id, empty while the prompt is still queued or running.
+    pub async fn get_prompt_history_raw(&self, prompt_id: &str) -> AppResult<Value> {
+        let url = format!("{}/history/{}", self.base_url, prompt_id);
+        let response = self.send(self.client.get(&url))
+            .await
+            .map_err(AppError::HttpClient)?;
+
+        if response.status().is_success() {
+            response.json().await.map(unwrap_history).map_err(AppError::HttpClient)
+        } else {
+            Err(AppError::ComfyUI(format!("Failed to get history for {}: {:?}", prompt_id, response.status())))
+        }
+    }
+
+    /// Wait for `prompt_id` to finish, polling its history entry, and return
+    /// its outcome and output images. A prompt that fails in ComfyUI is still
+    /// `Ok`, with `success: false`; `AppError::Timeout` is returned if it
+    /// hasn't finished within `timeout`.
+    pub async fn wait_for_completion(&self, prompt_id: &str, timeout: Duration) -> AppResult<PromptResult> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            if let Some(entry) = self.get_prompt_history(prompt_id).await? {
+                return Ok(PromptResult::from_entry(prompt_id, &entry));
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(AppError::Timeout(format!("prompt {} did not finish within {:?}", prompt_id, timeout)));
+            }
+            tokio::time::sleep_until((tokio::time::Instant::now() + COMPLETION_POLL_INTERVAL).min(deadline)).await;
+        }
+    }
+
+    /// POST `body` to `path`, discarding the response.
+    async fn post_ok(&self, path: &str, body: Value, what: &str) -> AppResult<()> {
+        let url = format!("{}{}", self.base_url, path);
+        let response = self.send(self.client.post(&url).json(&body))
+            .await
+            .map_err(AppError::HttpClient)?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            Err(AppError::ComfyUI(format!("Failed to {}. Status: {}, Body: {}", what, status, body)))
+        }
+    }
+
+    /// Stop the running prompt. ComfyUI versions that accept a `prompt_id`
+    /// only interrupt it if it's the one running; older ones interrupt
+    /// whatever is running.
+    pub async fn interrupt(&self, prompt_id: Option<&str>) -> AppResult<()> {
+        let body = match prompt_id {
+            Some(id) => serde_json::json!({"prompt_id": id}),
+            None => serde_json::json!({}),
+        };
+        self.post_ok("/interrupt", body, "interrupt").await
+    }
+
+    /// Running and pending prompts from `/queue`.
+    pub async fn get_queue(&self) -> AppResult<QueueStatus> {
+        Ok(serde_json::from_value(self.get_queue_raw().await?)?)
+    }
+
+    /// `/queue` as JSON.
+    pub async fn get_queue_raw(&self) -> AppResult<Value> {
+        let url = format!("{}/queue", self.base_url);
+        let response = self.send(self.client.get(&url))
+            .await
+            .map_err(AppError::HttpClient)?;
+        if response.status().is_success() {
+            response.json().await.map_err(AppError::HttpClient)
+        } else {
+            Err(AppError::ComfyUI(format!("Failed to get queue: {:?}", response.status())))
+        }
+    }
+
+    /// Remove pending prompts from the queue. Running prompts are unaffected;
+    /// use [`ComfyUIClient::interrupt`] for those.
+    pub async fn delete_from_queue(&self, prompt_ids: &[String]) -> AppResult<()> {
+        self.post_ok("/queue", serde_json::json!({"delete": prompt_ids}), "delete from queue").await
+    }
+
+    /// Remove every pending prompt from the queue.
+    pub async fn clear_queue(&self) -> AppResult<()> {
+        self.post_ok("/queue", serde_json::json!({"clear": true}), "clear queue").await
+    }
+
+    /// `/object_info` as JSON: every node class's inputs and outputs, keyed
+    /// by class type.
+    pub async fn get_object_info_raw(&self) -> AppResult<Value> {
+        let url = format!("{}/object_info", self.base_url);
+        let response = self.send(self.client.get(&url))
+            .await
+            .map_err(AppError::HttpClient)?;
+        if response.status().is_success() {
+            response.json().await.map_err(AppError::HttpClient)
+        } else {
+            Err(AppError::ComfyUI(format!("Failed to get object info: {:?}", response.status())))
+        }
+    }
+
+    /// `/system_stats` as JSON: ComfyUI and Python versions, OS, and devices.
+    /// `None` on versions without the endpoint.
+    pub async fn get_system_stats_raw(&self) -> AppResult<Option<Value>> {
+        self.probe("/system_stats").await
+    }
+
+    /// GET `path`, returning its JSON body (`Null` if it isn't JSON), or
+    /// `None` when this ComfyUI doesn't have the endpoint.
+    pub async fn probe(&self, path: &str) -> AppResult<Option<Value>> {
+        let url = format!("{}{}", self.base_url, path);
+        let response = self.send(self.client.get(&url))
+            .await
+            .map_err(AppError::HttpClient)?;
+        match response.status() {
+            status if status.is_success() => Ok(Some(response.json().await.unwrap_or(Value::Null))),
+            reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::METHOD_NOT_ALLOWED => Ok(None),
+            status => Err(AppError::ComfyUI(format!("Failed to probe {}: {:?}", path, status))),
+        }
+    }
+
     // Add more methods for other ComfyUI API endpoints here
 
     /// List model categories available from ComfyUI `/models` endpoint.
-    pub async fn get_model_categories(&self) -> AppResult<Value> {
+    pub async fn get_model_categories(&self) -> AppResult<Vec<String>> {
+        Ok(serde_json::from_value(self.get_model_categories_raw().await?)?)
+    }
+
+    /// `/models` as JSON.
+    pub async fn get_model_categories_raw(&self) -> AppResult<Value> {
         let url = format!("{}/models", self.base_url);
-        let response = self.client.get(&url)
-            .send()
+        let response = self.send(self.client.get(&url))
             .await
             .map_err(AppError::HttpClient)?;
         if response.status().is_success() {
@@ -101,14 +595,18 @@ impl ComfyUIClient {
     }
 
     /// List models within a category from `/models/<category>`.
-    pub async fn get_models_in_category(&self, category: &str) -> AppResult<Value> {
+    pub async fn get_models_in_category(&self, category: &str) -> AppResult<ModelList> {
+        Ok(serde_json::from_value(self.get_models_in_category_raw(category).await?)?)
+    }
+
+    /// `/models/<category>` as JSON.
+    pub async fn get_models_in_category_raw(&self, category: &str) -> AppResult<Value> {
         // Basic validation: allow alphanumeric, underscore, and hyphen only
         if !category.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
             return Err(AppError::ComfyUI("Invalid model category".to_string()));
         }
         let url = format!("{}/models/{}", self.base_url, category);
-        let response = self.client.get(&url)
-            .send()
+        let response = self.send(self.client.get(&url))
             .await
             .map_err(AppError::HttpClient)?;
         if response.status().is_success() {
@@ -119,7 +617,12 @@ impl ComfyUIClient {
     }
 
     /// Convenience for `/models/checkpoints` which is the source for `ckpt_name` values.
-    pub async fn get_checkpoints(&self) -> AppResult<Value> {
+    pub async fn get_checkpoints(&self) -> AppResult<ModelList> {
         self.get_models_in_category("checkpoints").await
     }
+
+    /// `/models/checkpoints` as JSON.
+    pub async fn get_checkpoints_raw(&self) -> AppResult<Value> {
+        self.get_models_in_category_raw("checkpoints").await
+    }
 }
@@ -0,0 +1,194 @@
+//! Record and replay ComfyUI responses (the `fixtures` feature).
+//!
+//! In [`FixtureMode::Record`] the client talks to ComfyUI as usual and also
+//! writes each response under the fixture directory; in
+//! [`FixtureMode::Replay`] it answers from those files without contacting
+//! ComfyUI, for tests and offline work. Requests are matched by method,
+//! path, and query; bodies are ignored, since prompts carry random client
+//! ids and seeds. A request recorded several times (history polled while a
+//! prompt runs) replays its responses in order, then repeats the last one.
+//!
+//! Each request gets `<dir>/<METHOD>_<path>_<hash>.json`:
+//! `{"method", "path", "responses": [{"status", "content_type", "json" | "text" | "file"}]}`.
+//! Binary bodies such as images are written beside it as `.bin` files.
+//! The `/ws` progress socket isn't recorded.
+use reqwest::{Client, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Longest path part of a fixture file name.
+const MAX_SLUG_LEN: usize = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixtureMode {
+    Record,
+    Replay,
+}
+
+impl FixtureMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "record" => Some(FixtureMode::Record),
+            "replay" => Some(FixtureMode::Replay),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FixtureFile {
+    method: String,
+    path: String,
+    responses: Vec<Recorded>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Recorded {
+    status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    json: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    /// Body file, relative to the fixture directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+}
+
+pub struct Fixtures {
+    dir: PathBuf,
+    mode: FixtureMode,
+    /// Replay: responses served so far, per fixture file.
+    served: Mutex<HashMap<String, usize>>,
+    /// Record: fixture files started this run. Files from earlier runs are
+    /// replaced rather than appended to.
+    recorded: Mutex<HashSet<String>>,
+}
+
+impl Fixtures {
+    pub fn new(dir: impl Into<PathBuf>, mode: FixtureMode) -> Self {
+        Fixtures { dir: dir.into(), mode, served: Mutex::new(HashMap::new()), recorded: Mutex::new(HashSet::new()) }
+    }
+
+    pub fn mode(&self) -> FixtureMode {
+        self.mode
+    }
+
+    /// Send `request` (recording the response) or answer it from the
+    /// fixtures. Replaying a request that was never recorded returns a 404.
+    pub async fn send(&self, client: &Client, request: Request) -> reqwest::Result<Response> {
+        let (method, path) = request_key(&request);
+        let stem = file_stem(&method, &path);
+        match self.mode {
+            FixtureMode::Replay => Ok(self.replay(&stem, &method, &path)),
+            FixtureMode::Record => {
+                let response = client.execute(request).await?;
+                let status = response.status();
+                let content_type = response.headers().get(reqwest::header::CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .map(String::from);
+                let body = response.bytes().await?.to_vec();
+                if let Err(e) = self.record(&stem, &method, &path, status, content_type.as_deref(), &body) {
+                    tracing::warn!("Failed to record {} {}: {}", method, path, e);
+                }
+                Ok(build_response(status, content_type.as_deref(), body))
+            }
+        }
+    }
+
+    fn replay(&self, stem: &str, method: &str, path: &str) -> Response {
+        let index = {
+            let mut served = self.served.lock().unwrap_or_else(|e| e.into_inner());
+            let count = served.entry(stem.to_string()).or_insert(0);
+            *count += 1;
+            *count - 1
+        };
+        let loaded = read_fixture(&self.dir.join(format!("{}.json", stem))).and_then(|fixture| {
+            let recorded = fixture.responses.get(index).or(fixture.responses.last())
+                .ok_or_else(|| "no responses recorded".to_string())?;
+            let body = match (&recorded.json, &recorded.text, &recorded.file) {
+                (Some(json), _, _) => serde_json::to_vec(json).map_err(|e| e.to_string())?,
+                (_, Some(text), _) => text.clone().into_bytes(),
+                (_, _, Some(file)) => std::fs::read(self.dir.join(file)).map_err(|e| format!("{}: {}", file, e))?,
+                _ => Vec::new(),
+            };
+            let status = StatusCode::from_u16(recorded.status).map_err(|e| e.to_string())?;
+            Ok(build_response(status, recorded.content_type.as_deref(), body))
+        });
+        loaded.unwrap_or_else(|e| {
+            tracing::warn!("No recorded response for {} {} ({})", method, path, e);
+            build_response(StatusCode::NOT_FOUND, Some("text/plain"), format!("No recorded response for {} {}", method, path).into_bytes())
+        })
+    }
+
+    fn record(&self, stem: &str, method: &str, path: &str, status: StatusCode, content_type: Option<&str>, body: &[u8]) -> Result<(), String> {
+        let mut recorded = self.recorded.lock().unwrap_or_else(|e| e.into_inner());
+        std::fs::create_dir_all(&self.dir).map_err(|e| format!("Failed to create {}: {}", self.dir.display(), e))?;
+        let json_path = self.dir.join(format!("{}.json", stem));
+        let mut fixture = if recorded.insert(stem.to_string()) {
+            FixtureFile { method: method.to_string(), path: path.to_string(), responses: Vec::new() }
+        } else {
+            read_fixture(&json_path)?
+        };
+        let mut entry = Recorded { status: status.as_u16(), content_type: content_type.map(String::from), json: None, text: None, file: None };
+        let is_json = content_type.is_some_and(|t| t.contains("json"));
+        let is_text = content_type.is_some_and(|t| t.starts_with("text/"));
+        match (serde_json::from_slice::<Value>(body), std::str::from_utf8(body)) {
+            (Ok(json), _) if is_json => entry.json = Some(json),
+            (_, Ok(text)) if is_json || is_text || body.is_empty() => entry.text = Some(text.to_string()),
+            _ => {
+                let file = format!("{}.{}.bin", stem, fixture.responses.len());
+                std::fs::write(self.dir.join(&file), body).map_err(|e| format!("Failed to write {}: {}", file, e))?;
+                entry.file = Some(file);
+            }
+        }
+        fixture.responses.push(entry);
+        let text = serde_json::to_string_pretty(&fixture).map_err(|e| e.to_string())?;
+        std::fs::write(&json_path, text).map_err(|e| format!("Failed to write {}: {}", json_path.display(), e))
+    }
+}
+
+/// Method and path (with query) that fixtures are matched on.
+fn request_key(request: &Request) -> (String, String) {
+    let url = request.url();
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    (request.method().to_string(), path)
+}
+
+/// `GET_history_abc_1a2b3c4d`: readable, with a hash so paths that slug
+/// the same don't collide.
+fn file_stem(method: &str, path: &str) -> String {
+    let mut slug = String::new();
+    for c in path.chars() {
+        if c.is_ascii_alphanumeric() || c == '-' {
+            slug.push(c);
+        } else if !slug.ends_with('_') {
+            slug.push('_');
+        }
+    }
+    let slug: String = slug.trim_matches('_').chars().take(MAX_SLUG_LEN).collect();
+    let hash = hex::encode(&Sha256::digest(format!("{} {}", method, path))[..4]);
+    format!("{}_{}_{}", method, slug, hash)
+}
+
+fn read_fixture(path: &Path) -> Result<FixtureFile, String> {
+    let data = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    serde_json::from_str(&data).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn build_response(status: StatusCode, content_type: Option<&str>, body: Vec<u8>) -> Response {
+    let mut response = hyper::Response::new(body);
+    *response.status_mut() = status;
+    if let Some(value) = content_type.and_then(|t| t.parse().ok()) {
+        response.headers_mut().insert(reqwest::header::CONTENT_TYPE, value);
+    }
+    Response::from(response)
+}
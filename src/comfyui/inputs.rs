@@ -0,0 +1,210 @@
+//! Images uploaded to ComfyUI's input folder.
+//!
+//! ComfyUI has no API to list or delete input images, so the proxy records
+//! each upload it forwards (who uploaded it, when, how big), in
+//! `INPUTS_FILE` so the record survives restarts. When `COMFYUI_INPUT_DIR`
+//! points at ComfyUI's input folder, the folder itself is listed too and
+//! files can be deleted from it.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::comfyui::types::UploadedImage;
+use crate::config::Config;
+
+/// Name of the library file kept in `COMFYUI_INPUT_DIR` when `INPUTS_FILE`
+/// isn't set. Hidden, so it isn't listed as an input.
+pub const DEFAULT_INPUTS_FILE: &str = ".proxy_inputs.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputImage {
+    pub name: String,
+    pub subfolder: Option<String>,
+    pub size: u64,
+    /// Upload time for tracked images, file modification time otherwise.
+    pub modified_ms: u64,
+    /// Whether the upload went through this proxy.
+    pub tracked: bool,
+    /// Tenant that uploaded the image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+impl InputImage {
+    /// Path relative to the input folder, as `LoadImage` expects it.
+    pub fn path(&self) -> String {
+        input_path(&self.name, self.subfolder.as_deref())
+    }
+
+    pub fn visible_to(&self, tenant: Option<&str>) -> bool {
+        tenant.is_none() || self.tenant.as_deref() == tenant
+    }
+}
+
+/// `subfolder/name`, or just `name`.
+pub fn input_path(name: &str, subfolder: Option<&str>) -> String {
+    match subfolder.filter(|s| !s.is_empty()) {
+        Some(subfolder) => format!("{}/{}", subfolder.trim_end_matches('/'), name),
+        None => name.to_string(),
+    }
+}
+
+/// Whether `path` stays inside the folder it's relative to.
+pub fn is_safe_input_path(path: &str) -> bool {
+    !path.is_empty() && Path::new(path).components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct InputsFile {
+    #[serde(default)]
+    images: Vec<InputImage>,
+}
+
+/// Uploads seen by this proxy, keyed by [`InputImage::path`].
+#[derive(Default)]
+pub struct InputLibrary {
+    path: Option<PathBuf>,
+    images: BTreeMap<String, InputImage>,
+}
+
+impl InputLibrary {
+    /// A library kept in memory only.
+    pub fn new() -> Self {
+        InputLibrary::default()
+    }
+
+    /// Load the library at `path`, which every change is written back to. A
+    /// missing file starts an empty library; an unreadable or malformed one
+    /// is logged and ignored.
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let images = match std::fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str::<InputsFile>(&data).map(|f| f.images).unwrap_or_else(|e| {
+                tracing::warn!("Ignoring invalid inputs file {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                tracing::warn!("Failed to read inputs file {}: {}", path.display(), e);
+                Vec::new()
+            }
+        };
+        InputLibrary { path: Some(path), images: images.into_iter().map(|image| (image.path(), image)).collect() }
+    }
+
+    /// The library in `INPUTS_FILE`, or in `COMFYUI_INPUT_DIR` when only that
+    /// is set; in memory without either.
+    pub fn from_config(config: &Config) -> Self {
+        let path = config.inputs_file.as_ref().map(PathBuf::from)
+            .or_else(|| config.comfyui_input_dir.as_ref().map(|dir| Path::new(dir).join(DEFAULT_INPUTS_FILE)));
+        match path {
+            Some(path) => Self::open(path),
+            None => Self::new(),
+        }
+    }
+
+    /// Remember an upload ComfyUI accepted.
+    pub fn record(&mut self, uploaded: &UploadedImage, size: u64, at_ms: u64, tenant: Option<String>) {
+        let image = InputImage {
+            name: uploaded.name.clone(),
+            subfolder: uploaded.subfolder.clone(),
+            size,
+            modified_ms: at_ms,
+            tracked: true,
+            tenant,
+        };
+        self.images.insert(image.path(), image);
+        self.write();
+    }
+
+    pub fn get(&self, path: &str) -> Option<&InputImage> {
+        self.images.get(path)
+    }
+
+    pub fn remove(&mut self, path: &str) -> Option<InputImage> {
+        let removed = self.images.remove(path);
+        if removed.is_some() {
+            self.write();
+        }
+        removed
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &InputImage> {
+        self.images.values()
+    }
+
+    /// Save the library to its file. The upload or delete it follows has
+    /// already happened, so failures are logged rather than returned.
+    fn write(&self) {
+        let Some(path) = &self.path else { return };
+        let file = InputsFile { images: self.images.values().cloned().collect() };
+        let result = serde_json::to_string_pretty(&file).map_err(|e| e.to_string()).and_then(|data| {
+            if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+            }
+            // Write then rename so a crash never leaves a truncated file.
+            let tmp = path.with_extension("json.tmp");
+            std::fs::write(&tmp, data).map_err(|e| format!("Failed to write {}: {}", tmp.display(), e))?;
+            std::fs::rename(&tmp, path).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+        });
+        if let Err(e) = result {
+            tracing::warn!("Failed to save inputs file: {}", e);
+        }
+    }
+}
+
+/// Every non-hidden file under `dir`, untracked.
+pub fn scan_input_dir(dir: &Path) -> Vec<InputImage> {
+    let mut images = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(current) = stack.pop() {
+        let Ok(entries) = std::fs::read_dir(&current) else { continue };
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            let Ok(meta) = entry.metadata() else { continue };
+            if meta.is_dir() {
+                stack.push(path);
+                continue;
+            }
+            let Ok(rel) = path.strip_prefix(dir) else { continue };
+            let subfolder = rel.parent().map(|p| p.to_string_lossy().replace('\\', "/")).filter(|s| !s.is_empty());
+            images.push(InputImage {
+                name: entry.file_name().to_string_lossy().to_string(),
+                subfolder,
+                size: meta.len(),
+                modified_ms: meta.modified().ok()
+                    .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|d| d.as_millis() as u64)
+                    .unwrap_or(0),
+                tracked: false,
+                tenant: None,
+            });
+        }
+    }
+    images
+}
+
+/// Tracked uploads merged with the files in `input_dir` (if given); tracked
+/// details win for files in both. Newest first.
+pub fn list_inputs(library: &InputLibrary, input_dir: Option<&Path>) -> Vec<InputImage> {
+    let mut merged: BTreeMap<String, InputImage> = BTreeMap::new();
+    if let Some(dir) = input_dir {
+        for image in scan_input_dir(dir) {
+            merged.insert(image.path(), image);
+        }
+    }
+    for image in library.iter() {
+        // With the folder available, uploads deleted outside the proxy drop out.
+        if input_dir.is_some() && !merged.contains_key(&image.path()) {
+            continue;
+        }
+        merged.insert(image.path(), image.clone());
+    }
+    let mut images: Vec<InputImage> = merged.into_values().collect();
+    images.sort_by(|a, b| b.modified_ms.cmp(&a.modified_ms).then_with(|| a.path().cmp(&b.path())));
+    images
+}
+
@@ -1,2 +1,9 @@
+pub mod backend;
 pub mod client;
-pub mod models;
+#[cfg(feature = "fixtures")]
+pub mod fixtures;
+pub mod inputs;
+pub mod object_info;
+pub mod trash;
+pub mod types;
+pub mod ws;
@@ -0,0 +1,140 @@
+//! ComfyUI's node definitions from `/object_info`, cached.
+//!
+//! `/object_info` describes every installed node class: its inputs (type,
+//! default, range, or the allowed values of a combo), outputs, and category.
+//! The document is large and only changes when nodes or models are added,
+//! so it is fetched once and reused until [`ObjectInfoCache`]'s TTL expires.
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+use crate::comfyui::client::ComfyUIClient;
+use crate::error::AppResult;
+
+pub struct ObjectInfoCache {
+    ttl: Duration,
+    cached: RwLock<Option<(Instant, Arc<Value>)>>,
+}
+
+impl ObjectInfoCache {
+    pub fn new(ttl: Duration) -> Self {
+        ObjectInfoCache { ttl, cached: RwLock::new(None) }
+    }
+
+    /// The cached document, re-fetched once it is older than the TTL (or
+    /// always, with `refresh`). A stale copy is served if re-fetching fails.
+    pub async fn get(&self, client: &ComfyUIClient, refresh: bool) -> AppResult<Arc<Value>> {
+        let stale = {
+            let cached = self.cached.read().await;
+            match cached.as_ref() {
+                Some((at, info)) if !refresh && at.elapsed() < self.ttl => return Ok(info.clone()),
+                other => other.map(|(_, info)| info.clone()),
+            }
+        };
+        match client.get_object_info_raw().await {
+            Ok(info) => {
+                let info = Arc::new(info);
+                *self.cached.write().await = Some((Instant::now(), info.clone()));
+                Ok(info)
+            }
+            Err(e) => match stale {
+                Some(info) => {
+                    tracing::warn!("Serving cached /object_info; refresh failed: {}", e);
+                    Ok(info)
+                }
+                None => Err(e),
+            },
+        }
+    }
+}
+
+/// One input of a node class, flattened from ComfyUI's
+/// `[type_or_options, {config}]` pairs.
+fn input_schema(name: &str, spec: &Value, required: bool) -> Value {
+    let (kind, config) = match spec {
+        Value::Array(parts) => (parts.first().cloned().unwrap_or(Value::Null), parts.get(1).cloned().unwrap_or(Value::Null)),
+        other => (other.clone(), Value::Null),
+    };
+    let mut input = Map::new();
+    input.insert("name".to_string(), json!(name));
+    input.insert("required".to_string(), json!(required));
+    match kind {
+        // Older ComfyUI lists a combo's values in place of its type.
+        Value::Array(options) => {
+            input.insert("type".to_string(), json!("COMBO"));
+            input.insert("options".to_string(), Value::Array(options));
+        }
+        kind => {
+            input.insert("type".to_string(), kind);
+        }
+    }
+    if let Value::Object(config) = config {
+        for (key, value) in config {
+            // Newer ComfyUI: ["COMBO", {"options": [...]}].
+            input.entry(key).or_insert(value);
+        }
+    }
+    Value::Object(input)
+}
+
+/// `node_type`'s definition from an `/object_info` document, with inputs
+/// flattened into `{name, required, type, default, min, max, options, ...}`
+/// and outputs paired with their names. The original is kept under `raw`.
+pub fn node_schema(info: &Value, node_type: &str) -> Option<Value> {
+    let raw = info.get(node_type)?;
+    let mut inputs = Vec::new();
+    for (section, required) in [("required", true), ("optional", false)] {
+        if let Some(section) = raw.pointer(&format!("/input/{}", section)).and_then(|v| v.as_object()) {
+            inputs.extend(section.iter().map(|(name, spec)| input_schema(name, spec, required)));
+        }
+    }
+    let names = raw.get("output_name").and_then(|v| v.as_array());
+    let outputs: Vec<Value> = raw.get("output").and_then(|v| v.as_array()).into_iter().flatten().enumerate()
+        .map(|(i, kind)| json!({"type": kind, "name": names.and_then(|n| n.get(i)).cloned().unwrap_or_else(|| kind.clone())}))
+        .collect();
+    Some(json!({
+        "node_type": node_type,
+        "display_name": raw.get("display_name"),
+        "category": raw.get("category"),
+        "description": raw.get("description"),
+        "inputs": inputs,
+        "outputs": outputs,
+        "raw": raw,
+    }))
+}
+
+/// Model categories whose files can be read off a loader node's combo, for
+/// backends without `/models`: `(category, node class, input)`.
+const MODEL_LOADERS: &[(&str, &str, &str)] = &[
+    ("checkpoints", "CheckpointLoaderSimple", "ckpt_name"),
+    ("loras", "LoraLoader", "lora_name"),
+    ("vae", "VAELoader", "vae_name"),
+    ("upscale_models", "UpscaleModelLoader", "model_name"),
+    ("controlnet", "ControlNetLoader", "control_net_name"),
+    ("clip", "CLIPLoader", "clip_name"),
+    ("clip_vision", "CLIPVisionLoader", "clip_name"),
+    ("unet", "UNETLoader", "unet_name"),
+    ("style_models", "StyleModelLoader", "style_model_name"),
+];
+
+/// Files in model `category`, from the combo of the loader node that reads
+/// them. `None` for categories without a known loader, or when the loader
+/// isn't installed.
+pub fn models_from_object_info(info: &Value, category: &str) -> Option<Vec<String>> {
+    let (_, class_type, input) = MODEL_LOADERS.iter().find(|(c, _, _)| *c == category)?;
+    let spec = info.pointer(&format!("/{}/input/required/{}", class_type, input))?;
+    let options = match spec.get(0)? {
+        Value::Array(options) => options,
+        _ => spec.pointer("/1/options")?.as_array()?,
+    };
+    Some(options.iter().filter_map(|o| o.as_str()).map(String::from).collect())
+}
+
+/// Categories [`models_from_object_info`] can list from `info`.
+pub fn model_categories_from_object_info(info: &Value) -> Vec<String> {
+    MODEL_LOADERS.iter()
+        .filter(|(category, _, _)| models_from_object_info(info, category).is_some())
+        .map(|(category, _, _)| category.to_string())
+        .collect()
+}
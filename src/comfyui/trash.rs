@@ -0,0 +1,285 @@
+//! Soft-deleted outputs.
+//!
+//! Deleting a prompt's outputs moves its files from ComfyUI's output folder
+//! (`COMFYUI_OUTPUT_DIR`) into `.trash/<prompt_id>/` inside it, next to a
+//! `trash.json` manifest, instead of removing them. Copies harvested onto
+//! the static drive (`HARVEST_OUTPUTS`) go into the drive's own `.trash/`
+//! alongside. They can be moved back until the retention window
+//! (`OUTPUT_TRASH_RETENTION_SECS`) passes, after which they're purged for
+//! good. Everything lives on disk, so the trash survives restarts.
+//!
+//! With several backends, each has its own output folder, in `COMFYUI_URLS`
+//! order; a prompt's files are trashed in the folder of the backend that
+//! ran it.
+//!
+//! The manifest is written before any file moves, and restoring skips files
+//! already back in place, so a move that fails part-way (or a crash) can be
+//! finished by retrying the restore.
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::comfyui::inputs::is_safe_input_path;
+use crate::error::{AppError, AppResult};
+use crate::utils::harvest::HARVEST_DIR;
+
+const TRASH_DIR: &str = ".trash";
+const MANIFEST: &str = "trash.json";
+
+/// One prompt's trashed outputs, as recorded in its `trash.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct TrashEntry {
+    pub prompt_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+    /// Tenant of the job the outputs belong to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub deleted_at_ms: u64,
+    /// When the files are purged.
+    pub expires_at_ms: u64,
+    /// Paths relative to the output folder, as `subfolder/filename`.
+    pub files: Vec<String>,
+    /// Harvested copies, relative to the static drive root.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub drive_files: Vec<String>,
+}
+
+impl TrashEntry {
+    pub fn visible_to(&self, tenant: Option<&str>) -> bool {
+        tenant.is_none() || self.tenant.as_deref() == tenant
+    }
+}
+
+pub struct OutputTrash {
+    /// Each backend's output folder, in `COMFYUI_URLS` order. A single
+    /// folder is shared by every backend.
+    output_dirs: Vec<PathBuf>,
+    /// Static drive root, when outputs are harvested onto it.
+    drive_dir: Option<PathBuf>,
+    retention_ms: u64,
+}
+
+impl OutputTrash {
+    pub fn new(output_dir: impl Into<PathBuf>, retention_ms: u64) -> Self {
+        OutputTrash { output_dirs: vec![output_dir.into()], drive_dir: None, retention_ms }
+    }
+
+    /// One output folder per backend, in `COMFYUI_URLS` order.
+    pub fn for_backends(output_dirs: Vec<PathBuf>, retention_ms: u64) -> Self {
+        OutputTrash { output_dirs, drive_dir: None, retention_ms }
+    }
+
+    /// Also trash harvested copies under `drive_dir`'s `images/<prompt_id>/`.
+    pub fn with_drive(mut self, drive_dir: impl Into<PathBuf>) -> Self {
+        self.drive_dir = Some(drive_dir.into());
+        self
+    }
+
+    /// The trash for `COMFYUI_OUTPUT_DIR` (comma-separated with several
+    /// backends), if it's set.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let dirs: Vec<PathBuf> = config.comfyui_output_dir.as_deref()?
+            .split(',').map(str::trim).filter(|d| !d.is_empty()).map(PathBuf::from)
+            .collect();
+        if dirs.is_empty() {
+            return None;
+        }
+        let trash = OutputTrash::for_backends(dirs, config.output_trash_retention_secs.saturating_mul(1000));
+        Some(match config.harvest_outputs {
+            true => trash.with_drive(&config.static_drive_path),
+            false => trash,
+        })
+    }
+
+    /// Output folder of backend `backend` (by `COMFYUI_URLS` index; the
+    /// primary when unknown).
+    fn output_dir(&self, backend: Option<usize>) -> AppResult<&Path> {
+        match (self.output_dirs.len(), backend.unwrap_or(0)) {
+            (1, _) => Ok(&self.output_dirs[0]),
+            (_, index) => self.output_dirs.get(index).map(PathBuf::as_path)
+                .ok_or_else(|| AppError::Unavailable(format!("COMFYUI_OUTPUT_DIR has no output folder for backend {}", index + 1))),
+        }
+    }
+
+    fn entry_dir(output_dir: &Path, prompt_id: &str) -> PathBuf {
+        output_dir.join(TRASH_DIR).join(prompt_id)
+    }
+
+    fn drive_entry_dir(&self, prompt_id: &str) -> Option<PathBuf> {
+        self.drive_dir.as_ref().map(|drive| drive.join(TRASH_DIR).join(prompt_id))
+    }
+
+    /// `prompt_id`'s entry and the output folder it's in.
+    fn find(&self, prompt_id: &str) -> Option<(&Path, TrashEntry)> {
+        if !is_prompt_id(prompt_id) {
+            return None;
+        }
+        self.output_dirs.iter().find_map(|dir| {
+            let data = std::fs::read(Self::entry_dir(dir, prompt_id).join(MANIFEST)).ok()?;
+            serde_json::from_slice(&data).ok().map(|entry| (dir.as_path(), entry))
+        })
+    }
+
+    /// `prompt_id`'s entry, if its outputs are in the trash.
+    pub fn get(&self, prompt_id: &str) -> Option<TrashEntry> {
+        self.find(prompt_id).map(|(_, entry)| entry)
+    }
+
+    /// Every entry, most recently deleted first.
+    pub fn list(&self) -> Vec<TrashEntry> {
+        let mut entries: Vec<TrashEntry> = self.output_dirs.iter()
+            .filter_map(|dir| std::fs::read_dir(dir.join(TRASH_DIR)).ok())
+            .flat_map(|dirs| dirs.filter_map(|d| d.ok()))
+            .filter_map(|d| d.file_name().to_str().and_then(|id| self.get(id)))
+            .collect();
+        entries.sort_by(|a, b| b.deleted_at_ms.cmp(&a.deleted_at_ms).then_with(|| a.prompt_id.cmp(&b.prompt_id)));
+        entries.dedup_by(|a, b| a.prompt_id == b.prompt_id);
+        entries
+    }
+
+    /// Move `files` (relative to backend `backend`'s output folder) into
+    /// the trash as `prompt_id`'s, along with its harvested copies. Files
+    /// already gone are left out; if nothing is left the prompt is reported
+    /// not found. If a move fails, everything moved so far is put back.
+    pub fn trash(&self, prompt_id: &str, backend: Option<usize>, files: &[String], job_id: Option<String>, tenant: Option<String>, now_ms: u64) -> AppResult<TrashEntry> {
+        if !is_prompt_id(prompt_id) {
+            return Err(AppError::BadRequest(format!("Invalid prompt id '{}'", prompt_id)));
+        }
+        if self.get(prompt_id).is_some() {
+            return Err(AppError::Conflict(format!("Outputs of prompt '{}' are already in the trash", prompt_id)));
+        }
+        let output_dir = self.output_dir(backend)?;
+        let files: Vec<String> = files.iter()
+            .filter(|p| is_safe_input_path(p) && output_dir.join(p).is_file())
+            .cloned()
+            .collect();
+        let drive_files = self.harvested_files(prompt_id);
+        if files.is_empty() && drive_files.is_empty() {
+            return Err(AppError::NotFound(format!("Prompt '{}' has no output files in {}", prompt_id, output_dir.display())));
+        }
+        let entry = TrashEntry {
+            prompt_id: prompt_id.to_string(),
+            job_id,
+            tenant,
+            deleted_at_ms: now_ms,
+            expires_at_ms: now_ms.saturating_add(self.retention_ms),
+            files,
+            drive_files,
+        };
+
+        // Written first, so files are never in the trash without a record of where they came from.
+        let dir = Self::entry_dir(output_dir, prompt_id);
+        let manifest = serde_json::to_vec_pretty(&entry)?;
+        std::fs::create_dir_all(&dir)
+            .and_then(|_| std::fs::write(dir.join(MANIFEST), manifest))
+            .map_err(|e| {
+                let _ = std::fs::remove_dir_all(&dir);
+                AppError::Internal(format!("Failed to write the trash manifest for '{}': {}", prompt_id, e))
+            })?;
+        let moves = self.moves(output_dir, &entry);
+        for (i, (from, to)) in moves.iter().enumerate() {
+            if let Err(e) = move_file(from, to) {
+                // Put back what was moved so the prompt isn't half-deleted.
+                for (from, to) in &moves[..i] {
+                    let _ = move_file(to, from);
+                }
+                self.purge_from(output_dir, prompt_id);
+                return Err(AppError::Internal(format!("Failed to move {} to the trash: {}", from.display(), e)));
+            }
+        }
+        if let Some(drive) = self.drive_dir.as_ref().filter(|_| !entry.drive_files.is_empty()) {
+            let _ = std::fs::remove_dir(drive.join(HARVEST_DIR).join(prompt_id));
+        }
+        Ok(entry)
+    }
+
+    /// Move `prompt_id`'s files back where they were. Files already back in
+    /// place (from a restore that failed part-way) are skipped; one whose
+    /// place has since been taken by another file refuses the restore,
+    /// moving nothing.
+    pub fn restore(&self, prompt_id: &str, now_ms: u64) -> AppResult<TrashEntry> {
+        let (output_dir, entry) = self.find(prompt_id)
+            .ok_or_else(|| AppError::NotFound(format!("Outputs of prompt '{}' are not in the trash", prompt_id)))?;
+        if entry.expires_at_ms <= now_ms {
+            self.purge_from(output_dir, prompt_id);
+            return Err(AppError::Gone(format!("Outputs of prompt '{}' were purged from the trash", prompt_id)));
+        }
+        let moves: Vec<(PathBuf, PathBuf)> = self.moves(output_dir, &entry).into_iter()
+            .filter(|(_, trashed)| trashed.is_file())
+            .collect();
+        if let Some((taken, _)) = moves.iter().find(|(original, _)| original.exists()) {
+            return Err(AppError::Conflict(format!("'{}' already exists", taken.display())));
+        }
+        for (original, trashed) in &moves {
+            move_file(trashed, original)
+                .map_err(|e| AppError::Internal(format!("Failed to restore {}: {}", original.display(), e)))?;
+        }
+        self.purge_from(output_dir, prompt_id);
+        Ok(entry)
+    }
+
+    /// Remove entries whose retention has passed, returning their prompt ids.
+    pub fn purge_expired(&self, now_ms: u64) -> Vec<String> {
+        let expired: Vec<String> = self.list().into_iter()
+            .filter(|e| e.expires_at_ms <= now_ms)
+            .map(|e| e.prompt_id)
+            .collect();
+        for prompt_id in &expired {
+            if let Some((output_dir, _)) = self.find(prompt_id) {
+                self.purge_from(output_dir, prompt_id);
+            }
+        }
+        expired
+    }
+
+    /// `(original, trashed)` paths of every file in `entry`.
+    fn moves(&self, output_dir: &Path, entry: &TrashEntry) -> Vec<(PathBuf, PathBuf)> {
+        let dir = Self::entry_dir(output_dir, &entry.prompt_id).join("files");
+        let mut moves: Vec<(PathBuf, PathBuf)> = entry.files.iter()
+            .filter(|p| is_safe_input_path(p))
+            .map(|p| (output_dir.join(p), dir.join(p)))
+            .collect();
+        if let (Some(drive), Some(drive_trash)) = (&self.drive_dir, self.drive_entry_dir(&entry.prompt_id)) {
+            moves.extend(entry.drive_files.iter()
+                .filter(|p| is_safe_input_path(p))
+                .map(|p| (drive.join(p), drive_trash.join(p))));
+        }
+        moves
+    }
+
+    /// Harvested files of `prompt_id`, relative to the drive root.
+    fn harvested_files(&self, prompt_id: &str) -> Vec<String> {
+        let Some(drive) = &self.drive_dir else { return Vec::new() };
+        let Ok(entries) = std::fs::read_dir(drive.join(HARVEST_DIR).join(prompt_id)) else { return Vec::new() };
+        let mut files: Vec<String> = entries.flatten()
+            .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+            .filter_map(|e| e.file_name().to_str().map(|name| format!("{}/{}/{}", HARVEST_DIR, prompt_id, name)))
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn purge_from(&self, output_dir: &Path, prompt_id: &str) {
+        for dir in std::iter::once(Self::entry_dir(output_dir, prompt_id)).chain(self.drive_entry_dir(prompt_id)) {
+            match std::fs::remove_dir_all(&dir) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    tracing::warn!("Failed to purge trashed outputs of prompt {}: {}", prompt_id, e);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A prompt id usable as a single, non-hidden directory name.
+fn is_prompt_id(prompt_id: &str) -> bool {
+    is_safe_input_path(prompt_id) && !prompt_id.contains(['/', '\\']) && !prompt_id.starts_with('.')
+}
+
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::rename(from, to)
+}
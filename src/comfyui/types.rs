@@ -0,0 +1,258 @@
+//! Typed views of ComfyUI responses.
+//!
+//! The client's typed methods return these; each also has a `_raw` variant
+//! returning the untyped JSON for fields not modelled here.
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// Response from `POST /prompt`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueuePromptResponse {
+    pub prompt_id: String,
+    /// Position ComfyUI assigned in its queue.
+    #[serde(default)]
+    pub number: Option<u64>,
+    /// Per-node validation errors, keyed by node id.
+    #[serde(default)]
+    pub node_errors: Map<String, Value>,
+    /// Any other fields ComfyUI returned.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+fn empty_as_none<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    let value = Option::<String>::deserialize(deserializer)?;
+    Ok(value.filter(|s| !s.is_empty()))
+}
+
+/// An image (or other file) written by a prompt.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputImage {
+    /// Node that wrote the file; not part of ComfyUI's item, filled in by
+    /// [`HistoryEntry::images`].
+    #[serde(default)]
+    pub node: String,
+    pub filename: String,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub subfolder: Option<String>,
+    /// ComfyUI folder type (`output`, `temp`).
+    #[serde(default, rename = "type", deserialize_with = "empty_as_none")]
+    pub folder_type: Option<String>,
+}
+
+/// UI output of one node in a history entry.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NodeOutput {
+    #[serde(default)]
+    pub images: Vec<OutputImage>,
+    #[serde(default)]
+    pub gifs: Vec<OutputImage>,
+    #[serde(default)]
+    pub videos: Vec<OutputImage>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HistoryStatus {
+    /// `success` or `error`.
+    #[serde(default)]
+    pub status_str: Option<String>,
+    #[serde(default)]
+    pub completed: bool,
+    /// `[message type, data]` pairs, e.g. `execution_start`, `execution_error`.
+    #[serde(default)]
+    pub messages: Vec<(String, Value)>,
+}
+
+/// One prompt's entry in `/history`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// `[number, prompt_id, graph, extra_data, outputs_to_execute]`.
+    #[serde(default)]
+    pub prompt: Value,
+    #[serde(default)]
+    pub outputs: BTreeMap<String, NodeOutput>,
+    #[serde(default)]
+    pub status: Option<HistoryStatus>,
+}
+
+impl HistoryEntry {
+    /// Queue number the prompt ran as.
+    pub fn number(&self) -> Option<u64> {
+        self.prompt.get(0).and_then(|v| v.as_u64())
+    }
+
+    /// The submitted API graph.
+    pub fn graph(&self) -> Option<&Value> {
+        self.prompt.get(2)
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.status.as_ref().and_then(|s| s.status_str.as_deref()) == Some("error")
+    }
+
+    /// ComfyUI's exception message for a failed prompt.
+    pub fn error_message(&self) -> Option<String> {
+        let messages = self.status.as_ref().map(|s| s.messages.as_slice()).unwrap_or_default();
+        messages.iter()
+            .find(|(kind, _)| kind == "execution_error")
+            .and_then(|(_, data)| data.get("exception_message"))
+            .and_then(|v| v.as_str())
+            .map(|s| s.trim().to_string())
+            .or_else(|| self.is_error().then(|| "execution failed".to_string()))
+    }
+
+    /// Files written by every node, ordered by node id, with `node` set.
+    pub fn images(&self) -> Vec<OutputImage> {
+        self.outputs.iter()
+            .flat_map(|(node, out)| {
+                out.images.iter().chain(&out.gifs).chain(&out.videos)
+                    .map(move |img| OutputImage { node: node.clone(), ..img.clone() })
+            })
+            .collect()
+    }
+}
+
+/// `/history` (or `/history/{prompt_id}`), keyed by prompt id.
+pub type History = BTreeMap<String, HistoryEntry>;
+
+/// History keyed by prompt id. Some ComfyUI builds and forks wrap it as
+/// `{"history": {...}}`; the client unwraps that so callers see one shape.
+pub fn unwrap_history(history: Value) -> Value {
+    match history {
+        Value::Object(mut map) if is_wrapped_history(&map) => map.remove("history").unwrap_or_default(),
+        other => other,
+    }
+}
+
+/// History keyed by prompt id, parsed entry by entry: an entry that doesn't
+/// parse (e.g. one a custom node wrote odd outputs into) is logged and left
+/// out instead of failing the whole history.
+pub fn parse_history(history: Value) -> History {
+    let Value::Object(entries) = history else { return History::new() };
+    entries.into_iter()
+        .filter_map(|(prompt_id, entry)| match serde_json::from_value(entry) {
+            Ok(entry) => Some((prompt_id, entry)),
+            Err(e) => {
+                tracing::warn!("Skipping unreadable history entry {}: {}", prompt_id, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Whether `map` is `{"history": {...}}` rather than history keyed by prompt id.
+pub fn is_wrapped_history(map: &Map<String, Value>) -> bool {
+    map.len() == 1 && map.get("history").map(|h| h.is_object() && h.get("outputs").is_none()).unwrap_or(false)
+}
+
+/// Response from `POST /upload/image`: where ComfyUI stored the file. Pass
+/// `name` (prefixed with `subfolder/` if set) as a `LoadImage` node's `image`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UploadedImage {
+    /// Stored file name; differs from the uploaded one when ComfyUI renamed it
+    /// to avoid overwriting an existing file.
+    pub name: String,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub subfolder: Option<String>,
+    /// ComfyUI folder type, normally `input`.
+    #[serde(default, rename = "type", deserialize_with = "empty_as_none")]
+    pub folder_type: Option<String>,
+}
+
+/// A prompt in ComfyUI's queue. ComfyUI sends these as
+/// `[number, prompt_id, graph, extra_data, outputs_to_execute]` arrays.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueueItem {
+    pub number: Option<u64>,
+    pub prompt_id: String,
+    pub graph: Value,
+}
+
+impl<'de> Deserialize<'de> for QueueItem {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let items = Vec::<Value>::deserialize(deserializer)?;
+        let prompt_id = items.get(1).and_then(|v| v.as_str())
+            .ok_or_else(|| serde::de::Error::custom("queue item has no prompt id"))?;
+        Ok(QueueItem {
+            number: items.first().and_then(|v| v.as_u64()),
+            prompt_id: prompt_id.to_string(),
+            graph: items.get(2).cloned().unwrap_or(Value::Null),
+        })
+    }
+}
+
+/// Response from `GET /queue`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct QueueStatus {
+    #[serde(default, rename = "queue_running")]
+    pub running: Vec<QueueItem>,
+    #[serde(default, rename = "queue_pending")]
+    pub pending: Vec<QueueItem>,
+}
+
+impl QueueStatus {
+    pub fn is_running(&self, prompt_id: &str) -> bool {
+        self.running.iter().any(|item| item.prompt_id == prompt_id)
+    }
+
+    pub fn is_pending(&self, prompt_id: &str) -> bool {
+        self.pending.iter().any(|item| item.prompt_id == prompt_id)
+    }
+}
+
+/// Model file names from `/models/{category}`. Older ComfyUI versions list
+/// plain names; newer ones list `{"name", "pathIndex"}` objects.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ModelList {
+    pub names: Vec<String>,
+}
+
+impl<'de> Deserialize<'de> for ModelList {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Item {
+            Name(String),
+            Entry { name: String },
+        }
+        let items = Vec::<Item>::deserialize(deserializer)?;
+        let names = items.into_iter()
+            .map(|item| match item {
+                Item::Name(name) | Item::Entry { name } => name,
+            })
+            .collect();
+        Ok(ModelList { names })
+    }
+}
+
+/// Outcome of a prompt once ComfyUI has written its history entry.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PromptResult {
+    pub prompt_id: String,
+    pub success: bool,
+    /// ComfyUI's exception message when the prompt failed.
+    pub error: Option<String>,
+    /// Files written, including any from before a failure.
+    pub images: Vec<OutputImage>,
+}
+
+impl PromptResult {
+    pub fn from_entry(prompt_id: &str, entry: &HistoryEntry) -> Self {
+        PromptResult {
+            prompt_id: prompt_id.to_string(),
+            success: !entry.is_error(),
+            error: entry.error_message(),
+            images: entry.images(),
+        }
+    }
+
+    /// Read the raw history entry stored under `prompt_id`. An entry that
+    /// isn't shaped like one is an error, rather than an empty success.
+    pub fn from_history_entry(prompt_id: &str, entry: &Value) -> serde_json::Result<Self> {
+        let entry: HistoryEntry = serde_json::from_value(entry.clone())?;
+        Ok(Self::from_entry(prompt_id, &entry))
+    }
+}
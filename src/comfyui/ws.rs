@@ -0,0 +1,219 @@
+//! WebSocket client for ComfyUI's live execution progress.
+//!
+//! ComfyUI pushes `{"type": ..., "data": ...}` messages on
+//! `/ws?clientId=<id>` for prompts queued with the same `client_id`. Text
+//! messages are parsed into [`ProgressEvent`]s; binary frames (latent
+//! previews) are skipped.
+//!
+//! ComfyUI keeps one socket per client id, so the proxy shares a single
+//! connection through [`ProgressHub`] instead of opening one per listener.
+use futures_util::{Stream, StreamExt};
+use serde_json::Value;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::comfyui::client::ComfyUIClient;
+use crate::error::{AppError, AppResult};
+
+/// Stream of progress events from one websocket connection. Ends when
+/// ComfyUI closes the socket.
+pub type ProgressStream = Pin<Box<dyn Stream<Item = AppResult<ProgressEvent>> + Send>>;
+
+/// A typed ComfyUI websocket message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgressEvent {
+    /// Queue status broadcast.
+    Status { queue_remaining: Option<u64> },
+    ExecutionStart { prompt_id: String },
+    /// Nodes whose outputs were reused from cache.
+    ExecutionCached { prompt_id: String, nodes: Vec<String> },
+    /// A node started executing; `node` is `None` once the prompt is done.
+    Executing { prompt_id: Option<String>, node: Option<String> },
+    /// Step progress within a node (e.g. sampler steps).
+    Progress { prompt_id: Option<String>, node: Option<String>, value: u64, max: u64 },
+    /// A node finished and produced UI output (e.g. saved images).
+    Executed { prompt_id: String, node: String, output: Value },
+    ExecutionError { prompt_id: String, node: Option<String>, node_type: Option<String>, message: String },
+    /// Any other message type, kept as-is.
+    Other { kind: String, data: Value },
+}
+
+fn str_field(data: &Value, key: &str) -> Option<String> {
+    match data.get(key)? {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+impl ProgressEvent {
+    /// Parse a text message from ComfyUI's websocket. Returns `None` for
+    /// messages that aren't `{type, data}` objects or lack required fields.
+    pub fn parse(text: &str) -> Option<Self> {
+        let msg: Value = serde_json::from_str(text).ok()?;
+        let kind = msg.get("type")?.as_str()?.to_string();
+        let data = msg.get("data").cloned().unwrap_or(Value::Null);
+        let event = match kind.as_str() {
+            "status" => ProgressEvent::Status {
+                queue_remaining: data.pointer("/status/exec_info/queue_remaining").and_then(|v| v.as_u64()),
+            },
+            "execution_start" => ProgressEvent::ExecutionStart { prompt_id: str_field(&data, "prompt_id")? },
+            "execution_cached" => ProgressEvent::ExecutionCached {
+                prompt_id: str_field(&data, "prompt_id")?,
+                nodes: data.get("nodes").and_then(|n| n.as_array())
+                    .map(|a| a.iter().filter_map(|v| v.as_str().map(String::from)).collect())
+                    .unwrap_or_default(),
+            },
+            "executing" => ProgressEvent::Executing {
+                prompt_id: str_field(&data, "prompt_id"),
+                node: str_field(&data, "node"),
+            },
+            "progress" => ProgressEvent::Progress {
+                prompt_id: str_field(&data, "prompt_id"),
+                node: str_field(&data, "node"),
+                value: data.get("value")?.as_u64()?,
+                max: data.get("max")?.as_u64()?,
+            },
+            "executed" => ProgressEvent::Executed {
+                prompt_id: str_field(&data, "prompt_id")?,
+                node: str_field(&data, "node")?,
+                output: data.get("output").cloned().unwrap_or(Value::Null),
+            },
+            "execution_error" => ProgressEvent::ExecutionError {
+                prompt_id: str_field(&data, "prompt_id")?,
+                node: str_field(&data, "node_id"),
+                node_type: str_field(&data, "node_type"),
+                message: str_field(&data, "exception_message").unwrap_or_default(),
+            },
+            _ => ProgressEvent::Other { kind, data },
+        };
+        Some(event)
+    }
+
+    /// Prompt the event belongs to, when it carries one.
+    pub fn prompt_id(&self) -> Option<&str> {
+        match self {
+            ProgressEvent::ExecutionStart { prompt_id }
+            | ProgressEvent::ExecutionCached { prompt_id, .. }
+            | ProgressEvent::Executed { prompt_id, .. }
+            | ProgressEvent::ExecutionError { prompt_id, .. } => Some(prompt_id),
+            ProgressEvent::Executing { prompt_id, .. } | ProgressEvent::Progress { prompt_id, .. } => prompt_id.as_deref(),
+            ProgressEvent::Status { .. } | ProgressEvent::Other { .. } => None,
+        }
+    }
+}
+
+/// Websocket URL for `base_url` (http -> ws, https -> wss).
+pub fn ws_url(base_url: &str, client_id: &str) -> String {
+    let base = base_url.trim_end_matches('/');
+    let base = if let Some(rest) = base.strip_prefix("https://") {
+        format!("wss://{}", rest)
+    } else if let Some(rest) = base.strip_prefix("http://") {
+        format!("ws://{}", rest)
+    } else {
+        base.to_string()
+    };
+    format!("{}/ws?clientId={}", base, client_id)
+}
+
+/// Connect to ComfyUI's websocket as `client_id` and stream its events.
+pub async fn connect(base_url: &str, client_id: &str) -> AppResult<ProgressStream> {
+    let url = ws_url(base_url, client_id);
+    tracing::info!("Connecting to ComfyUI websocket at {}", url);
+    let (socket, _) = tokio_tungstenite::connect_async(&url)
+        .await
+        .map_err(|e| AppError::ComfyUI(format!("Failed to connect to websocket {}: {}", url, e)))?;
+    let events = socket.filter_map(|msg| async move {
+        match msg {
+            Ok(Message::Text(text)) => {
+                let event = ProgressEvent::parse(&text);
+                if event.is_none() {
+                    tracing::debug!("Ignoring websocket message: {}", text);
+                }
+                event.map(Ok)
+            }
+            Ok(_) => None,
+            Err(e) => Some(Err(AppError::ComfyUI(format!("Websocket error: {}", e)))),
+        }
+    });
+    Ok(Box::pin(events))
+}
+
+impl ComfyUIClient {
+    /// Open a websocket for progress of prompts queued with `client_id`.
+    pub async fn progress_events(&self, client_id: &str) -> AppResult<ProgressStream> {
+        connect(self.base_url(), client_id).await
+    }
+}
+
+/// Events buffered per subscriber before slow ones start missing events.
+const HUB_CAPACITY: usize = 1024;
+/// Pause before reconnecting after the websocket drops or fails to connect.
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// One websocket connection to each ComfyUI backend, fanned out to any
+/// number of subscribers. Prompts must be queued with
+/// [`ProgressHub::client_id`] for their events to arrive. The connections
+/// are opened on first subscribe and re-opened if they drop.
+pub struct ProgressHub {
+    base_urls: Vec<String>,
+    client_id: String,
+    sender: broadcast::Sender<ProgressEvent>,
+    started: AtomicBool,
+}
+
+impl ProgressHub {
+    pub fn new(base_url: &str) -> Self {
+        ProgressHub::for_backends(&[base_url.to_string()])
+    }
+
+    /// A hub following every backend in `base_urls`.
+    pub fn for_backends(base_urls: &[String]) -> Self {
+        let (sender, _) = broadcast::channel(HUB_CAPACITY);
+        ProgressHub {
+            base_urls: base_urls.iter().map(|url| url.trim_end_matches('/').to_string()).collect(),
+            client_id: uuid::Uuid::new_v4().to_string(),
+            sender,
+            started: AtomicBool::new(false),
+        }
+    }
+
+    /// Client id the proxy queues prompts under.
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// Receive every event from now on, connecting if this is the first
+    /// subscriber.
+    pub fn subscribe(&self) -> broadcast::Receiver<ProgressEvent> {
+        let receiver = self.sender.subscribe();
+        if self.started.swap(true, Ordering::SeqCst) {
+            return receiver;
+        }
+        for base_url in &self.base_urls {
+            let (base_url, client_id, sender) = (base_url.clone(), self.client_id.clone(), self.sender.clone());
+            tokio::spawn(async move {
+                loop {
+                    match connect(&base_url, &client_id).await {
+                        Ok(mut events) => {
+                            while let Some(event) = events.next().await {
+                                match event {
+                                    // No subscribers is fine; the event is dropped.
+                                    Ok(event) => { let _ = sender.send(event); }
+                                    Err(e) => { tracing::warn!("{}", e); break; }
+                                }
+                            }
+                            tracing::warn!("ComfyUI websocket closed; reconnecting");
+                        }
+                        Err(e) => tracing::warn!("{}", e),
+                    }
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            });
+        }
+        receiver
+    }
+}
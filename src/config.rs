@@ -5,13 +5,166 @@
 use std::env;
 use dotenv;
 
+/// `User-Agent` sent on outgoing requests unless `PROXY_USER_AGENT` is set.
+pub const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
 
 pub struct Config {
+    /// The primary ComfyUI instance: the first of `COMFYUI_URLS` when set.
     pub comfyui_url: String,
+    /// Every ComfyUI instance new prompts are balanced across, primary first.
+    pub comfyui_urls: Vec<String>,
+    /// Directory ComfyUI responses are recorded to or replayed from (`fixtures` feature).
+    pub comfyui_fixtures_dir: Option<String>,
+    /// `record` or `replay`.
+    pub comfyui_fixtures_mode: String,
+    /// Longest a request to ComfyUI may take, in seconds.
+    pub comfyui_timeout_secs: u64,
+    /// How many times transient ComfyUI failures are retried.
+    pub comfyui_retries: u32,
+    /// Retry POSTs (such as `/prompt`) that couldn't connect, too.
+    pub comfyui_retry_posts: bool,
+    /// `User-Agent` for requests to ComfyUI and drive webhooks.
+    pub user_agent: String,
     pub static_drive_path: String,
+    /// ComfyUI's input folder, when it's reachable from the proxy; enables
+    /// listing all input images and deleting them.
+    pub comfyui_input_dir: Option<String>,
+    /// JSON file uploads made through the proxy are recorded in, so they
+    /// keep their details (and tenant) across restarts. Defaults to
+    /// `.proxy_inputs.json` in `comfyui_input_dir` when that is set.
+    pub inputs_file: Option<String>,
+    /// ComfyUI's output folder, when it's reachable from the proxy (one per
+    /// backend, comma-separated); enables deleting outputs to the trash and
+    /// restoring them.
+    pub comfyui_output_dir: Option<String>,
+    /// How long trashed outputs can be restored, in seconds.
+    pub output_trash_retention_secs: u64,
+    /// How long ComfyUI's `/object_info` is cached, in seconds.
+    pub object_info_ttl_secs: u64,
+    /// Seconds between backend health checks for `/events`; 0 disables them.
+    pub backend_health_interval_secs: u64,
+    /// Resize uploads to an exact size by default (`crop`, `pad`, `stretch`).
+    pub upload_resize: Option<String>,
+    /// Upload target size when the request names neither a size nor a workflow.
+    pub upload_resize_width: Option<u32>,
+    pub upload_resize_height: Option<u32>,
+    /// Seconds between static drive scans.
+    pub static_drive_poll_secs: u64,
+    /// Ordered actions run on new static drive files (`thumbnail`,
+    /// `metadata`, `caption`, `s3`, `webhook`).
+    pub static_drive_actions: Vec<String>,
+    /// Longest side of generated thumbnails, in pixels.
+    pub thumbnail_size: u32,
+    /// Copy finished jobs' outputs into `images/<prompt_id>/` on the static drive.
+    pub harvest_outputs: bool,
+    /// Receives `file_added` events from the `webhook` action.
+    pub static_drive_webhook_url: Option<String>,
+    /// Vision/caption endpoint used by the `caption` action.
+    pub caption_url: Option<String>,
+    /// Bearer token sent to `caption_url`.
+    pub caption_api_key: Option<String>,
+    /// Also write each caption to a `.txt` file next to the image.
+    pub caption_sidecar: bool,
+    /// Destination of the `s3` action.
+    pub s3_bucket: Option<String>,
+    pub s3_region: String,
+    /// S3-compatible endpoint (MinIO, R2); AWS when unset.
+    pub s3_endpoint: Option<String>,
+    pub s3_prefix: Option<String>,
+    pub aws_access_key_id: Option<String>,
+    pub aws_secret_access_key: Option<String>,
+    pub aws_session_token: Option<String>,
     pub prompts_dir: String,
+    /// SQLite database jobs are persisted to (`sqlite` feature).
+    pub jobs_db: Option<String>,
+    /// Redis server jobs are shared through (`redis` feature); takes precedence over `jobs_db`.
+    pub jobs_redis_url: Option<String>,
+    /// Prefix for the Redis keys jobs are stored under.
+    pub jobs_redis_prefix: String,
+    /// Seconds between pulls of jobs other replicas saved.
+    pub jobs_sync_secs: u64,
+    /// This replica's name in leases; random per process unless set.
+    pub instance_id: String,
+    /// How long a replica keeps one-replica work (like output harvesting)
+    /// after it last renewed its lease.
+    pub lease_ttl_secs: u64,
     pub api_host: String,
     pub api_port: String,
+    /// Workflow used when a queue payload names neither `prompt` nor `workflow`.
+    pub default_workflow: Option<String>,
+    /// How long `POST /generate` waits for a result before giving up.
+    pub generate_timeout_secs: u64,
+    /// Character budget for each prompt text param.
+    pub max_prompt_chars: usize,
+    /// Optional approximate token budget for each prompt text param.
+    pub max_prompt_tokens: Option<usize>,
+    /// Global default negative prompt.
+    pub default_negative_prompt: Option<String>,
+    /// JSON file with global and per-workflow default negative prompts.
+    pub negative_prompts_file: Option<String>,
+    /// JSON file holding the seeds of favorited outputs.
+    pub seeds_file: String,
+    /// Per-workflow concurrency and cooldown rules, e.g.
+    /// `video_animatediff:concurrency=1,train_lora:cooldown=30`.
+    pub workflow_limits: Option<String>,
+    /// Cost model rates; either enables per-job cost tracking.
+    pub cost_per_megapixel_step: Option<f64>,
+    pub cost_per_gpu_second: Option<f64>,
+    /// Unit the cost rates are in.
+    pub cost_currency: String,
+    /// Hard cap on `batch_size`.
+    pub max_batch_size: Option<u64>,
+    /// GPU memory in GB, used to estimate a batch cap when `max_batch_size` is unset.
+    pub gpu_vram_gb: Option<f64>,
+    /// Approximate VRAM (GB) needed per output megapixel.
+    pub vram_gb_per_megapixel: f64,
+    /// Allowed width/height range.
+    pub min_resolution: u64,
+    pub max_resolution: u64,
+    /// Snap invalid width/height to the nearest valid size instead of rejecting.
+    pub snap_resolution: bool,
+    /// JSON file listing API keys and their policies; unset disables auth.
+    pub api_keys_file: Option<String>,
+    /// Default requests per minute per API key; unset means unlimited.
+    pub rate_limit_per_minute: Option<u32>,
+    /// Default burst size per API key; unset means one minute's worth.
+    pub rate_limit_burst: Option<u32>,
+    /// Default cap on a key's unfinished jobs; unset means no cap.
+    pub max_queued_per_key: Option<u32>,
+    /// Most prompts kept waiting in ComfyUI's queue; the rest are held by
+    /// the proxy and sent fairly across keys. Unset sends prompts at once.
+    pub fair_queue_depth: Option<usize>,
+    /// Log which nodes params and prompt text were routed to.
+    pub trace_prompt_ops: bool,
+    /// Log line format: `text` or `json`.
+    pub log_format: String,
+    /// Filter directives (e.g. `info,comfyui_api_proxy=debug`) used when
+    /// `RUST_LOG` is unset.
+    pub log_level: Option<String>,
+    /// Directory for rolling log files; unset logs to the console only.
+    pub log_dir: Option<String>,
+    /// How often log files roll over: `hourly`, `daily`, or `never`.
+    pub log_rotation: String,
+    /// Sentry DSN for reporting panics and 5xx errors (feature `sentry`).
+    pub sentry_dsn: Option<String>,
+    /// Environment name attached to Sentry reports, e.g. `production`.
+    pub sentry_environment: Option<String>,
+    /// Comma-separated `_meta.title` substrings marking positive/negative text nodes.
+    pub positive_title_patterns: Option<String>,
+    pub negative_title_patterns: Option<String>,
+    /// Splits a combined `text` param into positive and negative halves.
+    pub text_delimiter: String,
+    /// Node selectors (`<id>`, `title:<title>`, `class:<class_type>`) making up
+    /// the face-detailer stage toggled by `detail_faces`.
+    pub face_detailer_nodes: Vec<String>,
+    /// Git repository mirrored into `prompts_dir`; unset disables sync.
+    pub workflows_git_url: Option<String>,
+    /// Branch to check out; the remote's default branch when unset.
+    pub workflows_git_branch: Option<String>,
+    /// Seconds between scheduled syncs; 0 syncs only at startup and on request.
+    pub workflows_sync_interval_secs: u64,
+    /// Validate every workflow against ComfyUI at startup.
+    pub workflow_preflight: bool,
 }
 
 impl Config {
@@ -19,20 +172,175 @@ impl Config {
         dotenv::dotenv().ok();
     }
     pub fn new() -> Result<Self, env::VarError> {
+        let comfyui_urls: Vec<String> = env::var("COMFYUI_URLS").unwrap_or_default()
+            .split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+        let comfyui_url = comfyui_urls.first().cloned()
+            .unwrap_or_else(|| env::var("COMFYUI_URL").unwrap_or_else(|_| "http://localhost:8188".to_string()));
         Ok(Config {
-            comfyui_url: env::var("COMFYUI_URL").unwrap_or_else(|_| "http://localhost:8188".to_string()),
+            comfyui_urls: if comfyui_urls.is_empty() { vec![comfyui_url.clone()] } else { comfyui_urls },
+            comfyui_url,
+            comfyui_fixtures_dir: env::var("COMFYUI_FIXTURES_DIR").ok().filter(|s| !s.trim().is_empty()),
+            comfyui_fixtures_mode: env::var("COMFYUI_FIXTURES_MODE").unwrap_or_else(|_| "replay".to_string()),
+            comfyui_timeout_secs: env::var("COMFYUI_TIMEOUT_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(1500),
+            comfyui_retries: env::var("COMFYUI_RETRIES").ok().and_then(|s| s.parse().ok()).unwrap_or(2),
+            comfyui_retry_posts: env::var("COMFYUI_RETRY_POSTS").map(|v| v == "true" || v == "1").unwrap_or(false),
+            user_agent: env::var("PROXY_USER_AGENT").ok().filter(|s| !s.trim().is_empty()).unwrap_or_else(|| DEFAULT_USER_AGENT.to_string()),
             static_drive_path: env::var("STATIC_DRIVE_PATH").unwrap_or_else(|_| "./static".to_string()),
+            comfyui_input_dir: env::var("COMFYUI_INPUT_DIR").ok().filter(|s| !s.trim().is_empty()),
+            inputs_file: env::var("INPUTS_FILE").ok().filter(|s| !s.trim().is_empty()),
+            comfyui_output_dir: env::var("COMFYUI_OUTPUT_DIR").ok().filter(|s| !s.trim().is_empty()),
+            output_trash_retention_secs: env::var("OUTPUT_TRASH_RETENTION_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(604_800),
+            object_info_ttl_secs: env::var("OBJECT_INFO_TTL_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(300),
+            backend_health_interval_secs: env::var("BACKEND_HEALTH_INTERVAL_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(30),
+            upload_resize: env::var("UPLOAD_RESIZE").ok().filter(|s| !s.trim().is_empty()),
+            upload_resize_width: env::var("UPLOAD_RESIZE_WIDTH").ok().and_then(|s| s.parse().ok()),
+            upload_resize_height: env::var("UPLOAD_RESIZE_HEIGHT").ok().and_then(|s| s.parse().ok()),
+            static_drive_poll_secs: env::var("STATIC_DRIVE_POLL_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(5),
+            static_drive_actions: env::var("STATIC_DRIVE_ACTIONS").unwrap_or_default()
+                .split(',').map(|s| s.trim().to_ascii_lowercase()).filter(|s| !s.is_empty()).collect(),
+            thumbnail_size: env::var("THUMBNAIL_SIZE").ok().and_then(|s| s.parse().ok()).unwrap_or(256),
+            harvest_outputs: env::var("HARVEST_OUTPUTS").map(|v| v == "true" || v == "1").unwrap_or(false),
+            static_drive_webhook_url: env::var("STATIC_DRIVE_WEBHOOK_URL").ok().filter(|s| !s.trim().is_empty()),
+            caption_url: env::var("CAPTION_URL").ok().filter(|s| !s.trim().is_empty()),
+            caption_api_key: env::var("CAPTION_API_KEY").ok().filter(|s| !s.trim().is_empty()),
+            caption_sidecar: env::var("CAPTION_SIDECAR").map(|v| v == "true" || v == "1").unwrap_or(false),
+            s3_bucket: env::var("S3_BUCKET").ok().filter(|s| !s.trim().is_empty()),
+            s3_region: env::var("S3_REGION").ok().filter(|s| !s.trim().is_empty()).unwrap_or_else(|| "us-east-1".to_string()),
+            s3_endpoint: env::var("S3_ENDPOINT").ok().filter(|s| !s.trim().is_empty()),
+            s3_prefix: env::var("S3_PREFIX").ok().filter(|s| !s.trim().is_empty()),
+            aws_access_key_id: env::var("AWS_ACCESS_KEY_ID").ok().filter(|s| !s.trim().is_empty()),
+            aws_secret_access_key: env::var("AWS_SECRET_ACCESS_KEY").ok().filter(|s| !s.trim().is_empty()),
+            aws_session_token: env::var("AWS_SESSION_TOKEN").ok().filter(|s| !s.trim().is_empty()),
             prompts_dir: env::var("PROMPTS_DIR").unwrap_or_else(|_| "./prompts".to_string()),
+            jobs_db: env::var("JOBS_DB").ok().filter(|s| !s.trim().is_empty()),
+            jobs_redis_url: env::var("JOBS_REDIS_URL").ok().filter(|s| !s.trim().is_empty()),
+            jobs_redis_prefix: env::var("JOBS_REDIS_PREFIX").ok().filter(|s| !s.trim().is_empty()).unwrap_or_else(|| "comfyui-proxy".to_string()),
+            jobs_sync_secs: env::var("JOBS_SYNC_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(2),
+            instance_id: env::var("INSTANCE_ID").ok().filter(|s| !s.trim().is_empty())
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+            lease_ttl_secs: env::var("LEASE_TTL_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(30),
             api_host: env::var("API_HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
             api_port: env::var("API_PORT").unwrap_or_else(|_| "8189".to_string()),
-            
+            default_workflow: env::var("DEFAULT_WORKFLOW").ok().filter(|s| !s.trim().is_empty()),
+            generate_timeout_secs: env::var("GENERATE_TIMEOUT_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(600),
+            max_prompt_chars: env::var("MAX_PROMPT_CHARS").ok().and_then(|s| s.parse().ok()).unwrap_or(4000),
+            max_prompt_tokens: env::var("MAX_PROMPT_TOKENS").ok().and_then(|s| s.parse().ok()),
+            default_negative_prompt: env::var("DEFAULT_NEGATIVE_PROMPT").ok().filter(|s| !s.trim().is_empty()),
+            negative_prompts_file: env::var("NEGATIVE_PROMPTS_FILE").ok().filter(|s| !s.trim().is_empty()),
+            seeds_file: env::var("SEEDS_FILE").ok().filter(|s| !s.trim().is_empty()).unwrap_or_else(|| "./seeds.json".to_string()),
+            workflow_limits: env::var("WORKFLOW_LIMITS").ok().filter(|s| !s.trim().is_empty()),
+            cost_per_megapixel_step: env::var("COST_PER_MEGAPIXEL_STEP").ok().and_then(|s| s.parse().ok()),
+            cost_per_gpu_second: env::var("COST_PER_GPU_SECOND").ok().and_then(|s| s.parse().ok()),
+            cost_currency: env::var("COST_CURRENCY").ok().filter(|s| !s.trim().is_empty()).unwrap_or_else(|| "USD".to_string()),
+            max_batch_size: env::var("MAX_BATCH_SIZE").ok().and_then(|s| s.parse().ok()),
+            gpu_vram_gb: env::var("GPU_VRAM_GB").ok().and_then(|s| s.parse().ok()),
+            vram_gb_per_megapixel: env::var("VRAM_GB_PER_MEGAPIXEL").ok().and_then(|s| s.parse().ok()).unwrap_or(2.0),
+            min_resolution: env::var("MIN_RESOLUTION").ok().and_then(|s| s.parse().ok()).unwrap_or(64),
+            max_resolution: env::var("MAX_RESOLUTION").ok().and_then(|s| s.parse().ok()).unwrap_or(4096),
+            snap_resolution: env::var("SNAP_RESOLUTION").map(|v| v == "true" || v == "1").unwrap_or(false),
+            api_keys_file: env::var("API_KEYS_FILE").ok().filter(|s| !s.trim().is_empty()),
+            rate_limit_per_minute: env::var("RATE_LIMIT_PER_MINUTE").ok().and_then(|s| s.parse().ok()),
+            rate_limit_burst: env::var("RATE_LIMIT_BURST").ok().and_then(|s| s.parse().ok()),
+            max_queued_per_key: env::var("MAX_QUEUED_PER_KEY").ok().and_then(|s| s.parse().ok()),
+            fair_queue_depth: env::var("FAIR_QUEUE_DEPTH").ok().and_then(|s| s.parse().ok()).filter(|n| *n > 0),
+            trace_prompt_ops: env::var("TRACE_PROMPT_OPS").map(|v| v == "true" || v == "1").unwrap_or(false),
+            log_format: env::var("LOG_FORMAT").ok().filter(|s| !s.trim().is_empty()).unwrap_or_else(|| "text".to_string()),
+            log_level: env::var("LOG_LEVEL").ok().filter(|s| !s.trim().is_empty()),
+            log_dir: env::var("LOG_DIR").ok().filter(|s| !s.trim().is_empty()),
+            log_rotation: env::var("LOG_ROTATION").ok().filter(|s| !s.trim().is_empty()).unwrap_or_else(|| "daily".to_string()),
+            sentry_dsn: env::var("SENTRY_DSN").ok().filter(|s| !s.trim().is_empty()),
+            sentry_environment: env::var("SENTRY_ENVIRONMENT").ok().filter(|s| !s.trim().is_empty()),
+            positive_title_patterns: env::var("POSITIVE_TITLE_PATTERNS").ok().filter(|s| !s.trim().is_empty()),
+            negative_title_patterns: env::var("NEGATIVE_TITLE_PATTERNS").ok().filter(|s| !s.trim().is_empty()),
+            face_detailer_nodes: env::var("FACE_DETAILER_NODES").ok().filter(|s| !s.trim().is_empty())
+                .unwrap_or_else(|| "class:FaceDetailer,class:FaceDetailerPipe".to_string())
+                .split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+            workflows_git_url: env::var("WORKFLOWS_GIT_URL").ok().filter(|s| !s.trim().is_empty()),
+            workflows_git_branch: env::var("WORKFLOWS_GIT_BRANCH").ok().filter(|s| !s.trim().is_empty()),
+            workflows_sync_interval_secs: env::var("WORKFLOWS_SYNC_INTERVAL_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(0),
+            workflow_preflight: env::var("WORKFLOW_PREFLIGHT").map(|v| v == "true" || v == "1").unwrap_or(false),
+            text_delimiter: env::var("TEXT_DELIMITER").ok().filter(|s| !s.trim().is_empty()).unwrap_or_else(|| "###".to_string()),
         })
     }
     pub fn print_env_vars() {
         println!("COMFYUI_URL: {}", env::var("COMFYUI_URL").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("COMFYUI_URLS: {}", env::var("COMFYUI_URLS").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("COMFYUI_FIXTURES_DIR: {}", env::var("COMFYUI_FIXTURES_DIR").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("COMFYUI_FIXTURES_MODE: {}", env::var("COMFYUI_FIXTURES_MODE").unwrap_or_else(|_| "replay".to_string()));
+        println!("COMFYUI_TIMEOUT_SECS: {}", env::var("COMFYUI_TIMEOUT_SECS").unwrap_or_else(|_| "1500".to_string()));
+        println!("COMFYUI_RETRIES: {}", env::var("COMFYUI_RETRIES").unwrap_or_else(|_| "2".to_string()));
+        println!("COMFYUI_RETRY_POSTS: {}", env::var("COMFYUI_RETRY_POSTS").unwrap_or_else(|_| "false".to_string()));
+        println!("PROXY_USER_AGENT: {}", env::var("PROXY_USER_AGENT").unwrap_or_else(|_| "<unset>".to_string()));
         println!("STATIC_DRIVE_PATH: {}", env::var("STATIC_DRIVE_PATH").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("COMFYUI_INPUT_DIR: {}", env::var("COMFYUI_INPUT_DIR").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("INPUTS_FILE: {}", env::var("INPUTS_FILE").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("COMFYUI_OUTPUT_DIR: {}", env::var("COMFYUI_OUTPUT_DIR").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("OUTPUT_TRASH_RETENTION_SECS: {}", env::var("OUTPUT_TRASH_RETENTION_SECS").unwrap_or_else(|_| "604800".to_string()));
+        println!("OBJECT_INFO_TTL_SECS: {}", env::var("OBJECT_INFO_TTL_SECS").unwrap_or_else(|_| "300".to_string()));
+        println!("BACKEND_HEALTH_INTERVAL_SECS: {}", env::var("BACKEND_HEALTH_INTERVAL_SECS").unwrap_or_else(|_| "30".to_string()));
+        println!("UPLOAD_RESIZE: {}", env::var("UPLOAD_RESIZE").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("UPLOAD_RESIZE_WIDTH: {}", env::var("UPLOAD_RESIZE_WIDTH").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("UPLOAD_RESIZE_HEIGHT: {}", env::var("UPLOAD_RESIZE_HEIGHT").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("STATIC_DRIVE_POLL_SECS: {}", env::var("STATIC_DRIVE_POLL_SECS").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("STATIC_DRIVE_ACTIONS: {}", env::var("STATIC_DRIVE_ACTIONS").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("THUMBNAIL_SIZE: {}", env::var("THUMBNAIL_SIZE").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("HARVEST_OUTPUTS: {}", env::var("HARVEST_OUTPUTS").unwrap_or_else(|_| "false".to_string()));
+        println!("STATIC_DRIVE_WEBHOOK_URL: {}", env::var("STATIC_DRIVE_WEBHOOK_URL").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("CAPTION_URL: {}", env::var("CAPTION_URL").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("CAPTION_API_KEY: {}", if env::var("CAPTION_API_KEY").is_ok() { "<set>" } else { "<unset>" });
+        println!("CAPTION_SIDECAR: {}", env::var("CAPTION_SIDECAR").unwrap_or_else(|_| "false".to_string()));
+        println!("S3_BUCKET: {}", env::var("S3_BUCKET").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("S3_REGION: {}", env::var("S3_REGION").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("S3_ENDPOINT: {}", env::var("S3_ENDPOINT").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("S3_PREFIX: {}", env::var("S3_PREFIX").unwrap_or_else(|_| "<unset>".to_string()));
+        // Credentials are reported as set/unset only.
+        println!("AWS_ACCESS_KEY_ID: {}", if env::var("AWS_ACCESS_KEY_ID").is_ok() { "<set>" } else { "<unset>" });
+        println!("AWS_SECRET_ACCESS_KEY: {}", if env::var("AWS_SECRET_ACCESS_KEY").is_ok() { "<set>" } else { "<unset>" });
         println!("PROMPTS_DIR: {}", env::var("PROMPTS_DIR").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("JOBS_DB: {}", env::var("JOBS_DB").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("JOBS_REDIS_URL: {}", if env::var("JOBS_REDIS_URL").is_ok() { "<set>" } else { "<unset>" });
+        println!("JOBS_REDIS_PREFIX: {}", env::var("JOBS_REDIS_PREFIX").unwrap_or_else(|_| "comfyui-proxy".to_string()));
+        println!("JOBS_SYNC_SECS: {}", env::var("JOBS_SYNC_SECS").unwrap_or_else(|_| "2".to_string()));
+        println!("INSTANCE_ID: {}", env::var("INSTANCE_ID").unwrap_or_else(|_| "<random>".to_string()));
+        println!("LEASE_TTL_SECS: {}", env::var("LEASE_TTL_SECS").unwrap_or_else(|_| "30".to_string()));
         println!("API_HOST: {}", env::var("API_HOST").unwrap_or_else(|_| "<unset>".to_string()));
         println!("API_PORT: {}", env::var("API_PORT").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("DEFAULT_WORKFLOW: {}", env::var("DEFAULT_WORKFLOW").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("GENERATE_TIMEOUT_SECS: {}", env::var("GENERATE_TIMEOUT_SECS").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("MAX_PROMPT_CHARS: {}", env::var("MAX_PROMPT_CHARS").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("MAX_PROMPT_TOKENS: {}", env::var("MAX_PROMPT_TOKENS").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("DEFAULT_NEGATIVE_PROMPT: {}", env::var("DEFAULT_NEGATIVE_PROMPT").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("NEGATIVE_PROMPTS_FILE: {}", env::var("NEGATIVE_PROMPTS_FILE").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("SEEDS_FILE: {}", env::var("SEEDS_FILE").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("WORKFLOW_LIMITS: {}", env::var("WORKFLOW_LIMITS").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("COST_PER_MEGAPIXEL_STEP: {}", env::var("COST_PER_MEGAPIXEL_STEP").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("COST_PER_GPU_SECOND: {}", env::var("COST_PER_GPU_SECOND").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("COST_CURRENCY: {}", env::var("COST_CURRENCY").unwrap_or_else(|_| "USD".to_string()));
+        println!("MAX_BATCH_SIZE: {}", env::var("MAX_BATCH_SIZE").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("GPU_VRAM_GB: {}", env::var("GPU_VRAM_GB").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("VRAM_GB_PER_MEGAPIXEL: {}", env::var("VRAM_GB_PER_MEGAPIXEL").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("MIN_RESOLUTION: {}", env::var("MIN_RESOLUTION").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("MAX_RESOLUTION: {}", env::var("MAX_RESOLUTION").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("SNAP_RESOLUTION: {}", env::var("SNAP_RESOLUTION").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("API_KEYS_FILE: {}", env::var("API_KEYS_FILE").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("RATE_LIMIT_PER_MINUTE: {}", env::var("RATE_LIMIT_PER_MINUTE").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("RATE_LIMIT_BURST: {}", env::var("RATE_LIMIT_BURST").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("MAX_QUEUED_PER_KEY: {}", env::var("MAX_QUEUED_PER_KEY").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("FAIR_QUEUE_DEPTH: {}", env::var("FAIR_QUEUE_DEPTH").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("TRACE_PROMPT_OPS: {}", env::var("TRACE_PROMPT_OPS").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("LOG_FORMAT: {}", env::var("LOG_FORMAT").unwrap_or_else(|_| "text".to_string()));
+        println!("LOG_LEVEL: {}", env::var("LOG_LEVEL").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("LOG_DIR: {}", env::var("LOG_DIR").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("LOG_ROTATION: {}", env::var("LOG_ROTATION").unwrap_or_else(|_| "daily".to_string()));
+        println!("SENTRY_DSN: {}", if env::var("SENTRY_DSN").is_ok() { "<set>" } else { "<unset>" });
+        println!("SENTRY_ENVIRONMENT: {}", env::var("SENTRY_ENVIRONMENT").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("POSITIVE_TITLE_PATTERNS: {}", env::var("POSITIVE_TITLE_PATTERNS").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("NEGATIVE_TITLE_PATTERNS: {}", env::var("NEGATIVE_TITLE_PATTERNS").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("TEXT_DELIMITER: {}", env::var("TEXT_DELIMITER").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("FACE_DETAILER_NODES: {}", env::var("FACE_DETAILER_NODES").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("WORKFLOWS_GIT_URL: {}", if env::var("WORKFLOWS_GIT_URL").is_ok() { "<set>" } else { "<unset>" });
+        println!("WORKFLOWS_GIT_BRANCH: {}", env::var("WORKFLOWS_GIT_BRANCH").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("WORKFLOWS_SYNC_INTERVAL_SECS: {}", env::var("WORKFLOWS_SYNC_INTERVAL_SECS").unwrap_or_else(|_| "<unset>".to_string()));
+        println!("WORKFLOW_PREFLIGHT: {}", env::var("WORKFLOW_PREFLIGHT").unwrap_or_else(|_| "false".to_string()));
     }
 }
@@ -1,6 +1,16 @@
 //! Central error definitions used across the crate.
+//!
+//! `AppError` doubles as the HTTP error type: handlers return it and it
+//! renders as `{"error": {"code", "message"}}` with a matching status code.
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::{json, Value};
 use thiserror::Error;
 
+use crate::reporting::ReportableError;
+use crate::utils::time::now_ms;
+
 #[derive(Error, Debug)]
 pub enum AppError {
     #[error("HTTP client error: {0}")]
@@ -15,6 +25,9 @@ pub enum AppError {
     #[error("ComfyUI error: {0}")]
     ComfyUI(String),
 
+    #[error("Timed out: {0}")]
+    Timeout(String),
+
     #[error("Configuration error: {0}")]
     Config(String),
 
@@ -23,6 +36,138 @@ pub enum AppError {
 
     #[error("Static drive polling error: {0}")]
     StaticDrivePolling(String),
+
+    /// The request itself is malformed or fails validation.
+    #[error("{0}")]
+    BadRequest(String),
+
+    /// A request param failed validation; `field` names it.
+    #[error("{message}")]
+    InvalidField { field: String, message: String },
+
+    #[error("{0}")]
+    Unauthorized(String),
+
+    /// Not allowed for the caller's API key.
+    #[error("{0}")]
+    Forbidden(String),
+
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error("{0}")]
+    Conflict(String),
+
+    /// The resource existed but can no longer be produced (e.g. a failed job's outputs).
+    #[error("{0}")]
+    Gone(String),
+
+    #[error("{message}")]
+    QuotaExceeded { message: String, reset_at_ms: u64 },
+
+    /// Too many requests, or too many queued prompts, for the caller's key.
+    #[error("{message}")]
+    RateLimited { message: String, retry_at_ms: u64 },
+
+    /// A workflow rule (concurrency or cooldown) refused the job.
+    #[error("{message}")]
+    WorkflowBusy { message: String, retry_at_ms: Option<u64> },
+
+    /// Maintenance mode is on; new jobs are refused until `retry_at_ms` or later.
+    #[error("{message}")]
+    Maintenance { message: String, retry_at_ms: u64 },
+
+    /// The feature needs configuration this instance doesn't have.
+    #[error("{0}")]
+    Unavailable(String),
+
+    #[error("{0}")]
+    Internal(String),
 }
 
 pub type AppResult<T> = Result<T, AppError>;
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) | AppError::InvalidField { .. } | AppError::PromptConstruction(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Gone(_) => StatusCode::GONE,
+            AppError::QuotaExceeded { .. } | AppError::RateLimited { .. } | AppError::WorkflowBusy { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::HttpClient(_) | AppError::JsonSerialization(_) | AppError::ComfyUI(_) => StatusCode::BAD_GATEWAY,
+            AppError::Unavailable(_) | AppError::Maintenance { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            AppError::Config(_) | AppError::WorkflowManagement(_) | AppError::StaticDrivePolling(_) | AppError::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// Stable machine-readable error code.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::HttpClient(_) => "comfyui_unreachable",
+            // Serialization errors come from decoding ComfyUI's responses.
+            AppError::JsonSerialization(_) => "invalid_comfyui_response",
+            AppError::PromptConstruction(_) => "prompt_construction",
+            AppError::ComfyUI(_) => "comfyui_error",
+            AppError::Timeout(_) => "timeout",
+            AppError::Config(_) => "config_error",
+            AppError::WorkflowManagement(_) => "workflow_error",
+            AppError::StaticDrivePolling(_) => "static_drive_error",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::InvalidField { .. } => "invalid_field",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::Gone(_) => "gone",
+            AppError::QuotaExceeded { .. } => "quota_exceeded",
+            AppError::RateLimited { .. } => "rate_limited",
+            AppError::WorkflowBusy { .. } => "workflow_busy",
+            AppError::Maintenance { .. } => "maintenance",
+            AppError::Unavailable(_) => "not_configured",
+            AppError::Internal(_) => "internal",
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let mut error = json!({"code": self.code(), "message": self.to_string()});
+        match &self {
+            AppError::InvalidField { field, .. } => error["field"] = Value::String(field.clone()),
+            AppError::QuotaExceeded { reset_at_ms, .. } => error["reset_at_ms"] = json!(reset_at_ms),
+            AppError::RateLimited { retry_at_ms, .. }
+            | AppError::WorkflowBusy { retry_at_ms: Some(retry_at_ms), .. }
+            | AppError::Maintenance { retry_at_ms, .. } => {
+                error["retry_at_ms"] = json!(retry_at_ms)
+            }
+            _ => {}
+        }
+        let mut response = (status, Json(json!({"error": error}))).into_response();
+        // Maintenance rejections are expected, not failures.
+        if status.is_server_error() && !matches!(self, AppError::Maintenance { .. }) {
+            tracing::error!("{}", self);
+            response.extensions_mut().insert(ReportableError { code: self.code(), message: self.to_string() });
+        }
+        let retry_at_ms = match self {
+            AppError::QuotaExceeded { reset_at_ms, .. } => Some(reset_at_ms),
+            AppError::RateLimited { retry_at_ms, .. } => Some(retry_at_ms),
+            AppError::WorkflowBusy { retry_at_ms, .. } => retry_at_ms,
+            AppError::Maintenance { retry_at_ms, .. } => Some(retry_at_ms),
+            _ => None,
+        };
+        if let Some(retry_at_ms) = retry_at_ms {
+            let retry_after = retry_at_ms.saturating_sub(now_ms()).div_ceil(1000);
+            if let Ok(value) = retry_after.to_string().parse() {
+                response.headers_mut().insert(header::RETRY_AFTER, value);
+            }
+        }
+        response
+    }
+}
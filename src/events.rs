@@ -0,0 +1,105 @@
+//! Proxy-level events, streamed to dashboards over `GET /events`.
+//!
+//! Unlike ComfyUI's websocket (see [`crate::comfyui::ws`]), these describe
+//! the proxy's own view: job state changes, files indexed on the static
+//! drive, and the backend going up or down. Each event carries who may see
+//! it, so one stream can be filtered per API key.
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::jobs::JobState;
+use crate::utils::time::now_ms;
+
+/// Events buffered per subscriber before slow ones start missing events.
+const BUS_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProxyEventKind {
+    /// A job moved to a new state; `previous` is `None` for new jobs.
+    JobState {
+        job_id: String,
+        prompt_id: Option<String>,
+        workflow: Option<String>,
+        state: JobState,
+        previous: Option<JobState>,
+    },
+    /// The static drive indexer picked up a new or changed file.
+    OutputIndexed { path: String, size: u64, modified_ms: u64 },
+    /// ComfyUI became reachable or unreachable.
+    BackendHealth { url: String, reachable: bool, error: Option<String> },
+}
+
+impl ProxyEventKind {
+    /// The `type` tag, as used by `?types=`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProxyEventKind::JobState { .. } => "job_state",
+            ProxyEventKind::OutputIndexed { .. } => "output_indexed",
+            ProxyEventKind::BackendHealth { .. } => "backend_health",
+        }
+    }
+}
+
+/// Every event type, for validating `?types=`.
+pub const EVENT_TYPES: &[&str] = &["job_state", "output_indexed", "backend_health"];
+
+/// Who may receive an event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Audience {
+    Everyone,
+    /// Keys without a tenant only.
+    Admin,
+    /// The owning tenant and keys without a tenant.
+    Tenant(Option<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProxyEvent {
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub kind: ProxyEventKind,
+    #[serde(skip)]
+    pub audience: Audience,
+}
+
+impl ProxyEvent {
+    pub fn now(kind: ProxyEventKind, audience: Audience) -> Self {
+        ProxyEvent { at_ms: now_ms(), kind, audience }
+    }
+
+    pub fn visible_to(&self, tenant: Option<&str>) -> bool {
+        match &self.audience {
+            Audience::Everyone => true,
+            Audience::Admin => tenant.is_none(),
+            Audience::Tenant(owner) => tenant.is_none() || owner.as_deref() == tenant,
+        }
+    }
+}
+
+/// Fan-out of proxy events. Clones share the same channel.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<ProxyEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus { sender: broadcast::channel(BUS_CAPACITY).0 }
+    }
+
+    /// Send `event` to current subscribers; with none it's dropped.
+    pub fn publish(&self, event: ProxyEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ProxyEvent> {
+        self.sender.subscribe()
+    }
+}
@@ -0,0 +1,117 @@
+//! Where the job store keeps jobs beyond its own memory.
+//!
+//! Without a backend, jobs live in one process and are lost on restart.
+//! SQLite (`JOBS_DB`, the `sqlite` feature) keeps them across restarts;
+//! Redis (`JOBS_REDIS_URL`, the `redis` feature) also lets several proxy
+//! replicas share them, each picking up the others' changes through
+//! [`JobBackend::changed_since`] and splitting one-replica work through
+//! [`JobBackend::claim`].
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::auth::keys::key_id;
+use crate::jobs::events::JobEvent;
+use crate::jobs::store::Job;
+
+/// Only [`JobBackend::load`] blocks, and it runs once, when the store is
+/// opened; the rest are awaited without holding the store's lock.
+#[async_trait]
+pub trait JobBackend: Send + Sync {
+    /// Short name for logs.
+    fn name(&self) -> &'static str;
+
+    /// Every stored job.
+    fn load(&self) -> Result<Vec<Job>, String>;
+
+    /// Store `job`'s current state, merged into what's stored (see
+    /// [`Job::merge`]) so replicas saving the same job keep each other's
+    /// events.
+    async fn save(&self, job: &Job) -> Result<(), String>;
+
+    /// Jobs saved (by any process) at or after `since_ms`.
+    async fn changed_since(&self, since_ms: u64) -> Result<Vec<Job>, String>;
+
+    /// Take or renew the lease on `name` for `owner` until `ttl_ms` from
+    /// now, so work that must run once (like downloading outputs) runs on
+    /// one replica. True if `owner` holds it: it was free, expired, or
+    /// already `owner`'s.
+    async fn claim(&self, name: &str, owner: &str, ttl_ms: u64) -> Result<bool, String>;
+}
+
+/// A job as backends serialize it. Grid splits aren't kept: they run on
+/// the replica that queued the job.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: String,
+    pub prompt_id: Option<String>,
+    pub workflow: Option<String>,
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub client: Option<String>,
+    #[serde(default)]
+    pub key_id: Option<String>,
+    /// The raw API key, as older versions stored it; read only, to derive
+    /// `key_id`, and never written back.
+    #[serde(default, skip_serializing)]
+    pub api_key: Option<String>,
+    pub created_at_ms: u64,
+    pub request: Option<Value>,
+    pub expected_outputs: u64,
+    pub events: Vec<JobEvent>,
+    pub node_classes: HashMap<String, String>,
+    pub history_synced: bool,
+    #[serde(default)]
+    pub megapixel_steps: f64,
+    #[serde(default)]
+    pub estimated_cost: Option<f64>,
+    #[serde(default)]
+    pub actual_cost: Option<f64>,
+}
+
+impl From<&Job> for JobRecord {
+    fn from(job: &Job) -> Self {
+        JobRecord {
+            id: job.id.clone(),
+            prompt_id: job.prompt_id.clone(),
+            workflow: job.workflow.clone(),
+            tenant: job.tenant.clone(),
+            client: job.client.clone(),
+            key_id: job.key_id.clone(),
+            api_key: None,
+            created_at_ms: job.created_at_ms,
+            request: job.request.clone(),
+            expected_outputs: job.expected_outputs,
+            events: job.events.clone(),
+            node_classes: job.node_classes.clone(),
+            history_synced: job.history_synced,
+            megapixel_steps: job.megapixel_steps,
+            estimated_cost: job.estimated_cost,
+            actual_cost: job.actual_cost,
+        }
+    }
+}
+
+impl From<JobRecord> for Job {
+    fn from(record: JobRecord) -> Self {
+        Job {
+            id: record.id,
+            prompt_id: record.prompt_id,
+            workflow: record.workflow,
+            created_at_ms: record.created_at_ms,
+            events: record.events,
+            expected_outputs: record.expected_outputs,
+            megapixel_steps: record.megapixel_steps,
+            estimated_cost: record.estimated_cost,
+            actual_cost: record.actual_cost,
+            node_classes: record.node_classes,
+            history_synced: record.history_synced,
+            key_id: record.key_id.or_else(|| record.api_key.as_deref().map(key_id)),
+            tenant: record.tenant,
+            client: record.client,
+            request: record.request,
+            split_grid: None,
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Batch-size awareness for submitted graphs.
+//!
+//! Each SaveImage node writes one file per image in the batch flowing into
+//! it, so a job's expected output count is the sum of the upstream latent
+//! batch sizes over its SaveImage nodes. Batch sizes can also be capped, either
+//! directly or from the GPU's VRAM and the requested resolution.
+use serde_json::Value;
+use std::collections::{HashSet, VecDeque};
+
+use crate::workflow::compose::link_source;
+
+/// Size of the latent batch feeding a node, with its resolution when known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatentBatch {
+    pub node_batch: u64,
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+}
+
+/// Walk upstream from `node_id` to the nearest node with a numeric
+/// `batch_size` input (e.g. EmptyLatentImage).
+pub fn upstream_batch(graph: &Value, node_id: &str) -> Option<LatentBatch> {
+    let mut queue = VecDeque::from([node_id.to_string()]);
+    let mut seen = HashSet::new();
+    while let Some(id) = queue.pop_front() {
+        if !seen.insert(id.clone()) { continue; }
+        let Some(inputs) = graph.get(&id).and_then(|n| n.get("inputs")).and_then(|i| i.as_object()) else { continue };
+        if let Some(batch) = inputs.get("batch_size").and_then(|v| v.as_u64()) {
+            return Some(LatentBatch {
+                node_batch: batch,
+                width: inputs.get("width").and_then(|v| v.as_u64()),
+                height: inputs.get("height").and_then(|v| v.as_u64()),
+            });
+        }
+        // Sorted so the search is deterministic across map orderings.
+        let mut sources: Vec<String> = inputs.values().filter_map(link_source).collect();
+        sources.sort();
+        queue.extend(sources);
+    }
+    None
+}
+
+/// Number of images the graph's SaveImage nodes are expected to write.
+pub fn expected_outputs(graph: &Value) -> u64 {
+    let Some(nodes) = graph.as_object() else { return 0 };
+    nodes.iter()
+        .filter(|(_, n)| n.get("class_type").and_then(|v| v.as_str()) == Some("SaveImage"))
+        .map(|(id, _)| upstream_batch(graph, id).map(|b| b.node_batch).unwrap_or(1))
+        .sum()
+}
+
+/// Upper bound on batch size, either fixed or derived from VRAM.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchLimits {
+    /// Hard cap; takes precedence over the VRAM estimate.
+    pub max_batch_size: Option<u64>,
+    /// Available VRAM in GB, used to estimate a cap when no hard cap is set.
+    pub vram_gb: Option<f64>,
+    /// Approximate VRAM needed per megapixel of batch output.
+    pub gb_per_megapixel: f64,
+}
+
+impl Default for BatchLimits {
+    fn default() -> Self {
+        BatchLimits { max_batch_size: None, vram_gb: None, gb_per_megapixel: 2.0 }
+    }
+}
+
+impl BatchLimits {
+    /// Maximum batch size at the given resolution, if limited.
+    pub fn max_for(&self, width: Option<u64>, height: Option<u64>) -> Option<u64> {
+        if let Some(max) = self.max_batch_size {
+            return Some(max);
+        }
+        let vram = self.vram_gb?;
+        let megapixels = (width.unwrap_or(1024) * height.unwrap_or(1024)) as f64 / 1_000_000.0;
+        let per_image = (megapixels * self.gb_per_megapixel).max(f64::EPSILON);
+        Some(((vram / per_image).floor() as u64).max(1))
+    }
+
+    /// Check every batch-producing node in `graph` against the limit.
+    pub fn check(&self, graph: &Value) -> Result<(), String> {
+        let Some(nodes) = graph.as_object() else { return Ok(()) };
+        for (id, node) in nodes {
+            let Some(inputs) = node.get("inputs").and_then(|i| i.as_object()) else { continue };
+            let Some(batch) = inputs.get("batch_size").and_then(|v| v.as_u64()) else { continue };
+            let width = inputs.get("width").and_then(|v| v.as_u64());
+            let height = inputs.get("height").and_then(|v| v.as_u64());
+            if let Some(max) = self.max_for(width, height) {
+                if batch > max {
+                    return Err(format!(
+                        "batch_size {} on node {} exceeds the maximum of {} for this resolution",
+                        batch, id, max
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+//! Cost model for chargeback on shared GPUs.
+//!
+//! A job's cost is `COST_PER_MEGAPIXEL_STEP` times the sampling work in its
+//! graph (output megapixels × steps × batch, summed over samplers) plus
+//! `COST_PER_GPU_SECOND` times its execution time. The estimate made at
+//! queue time uses the workflow's average execution time so far; the actual
+//! cost uses the job's own.
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::jobs::batch::upstream_batch;
+use crate::jobs::store::Job;
+use crate::jobs::timing::{execution_ms, is_completed};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CostModel {
+    pub per_megapixel_step: Option<f64>,
+    pub per_gpu_second: Option<f64>,
+    /// Unit the rates are in, reported alongside costs.
+    pub currency: String,
+}
+
+impl CostModel {
+    pub fn from_config(config: &Config) -> Self {
+        CostModel {
+            per_megapixel_step: config.cost_per_megapixel_step,
+            per_gpu_second: config.cost_per_gpu_second,
+            currency: config.cost_currency.clone(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.per_megapixel_step.is_some() || self.per_gpu_second.is_some()
+    }
+
+    /// Cost of `megapixel_steps` of sampling and `gpu_ms` of execution.
+    /// `None` when the model is off.
+    pub fn cost(&self, megapixel_steps: f64, gpu_ms: u64) -> Option<f64> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let sampling = self.per_megapixel_step.unwrap_or(0.0) * megapixel_steps;
+        let time = self.per_gpu_second.unwrap_or(0.0) * gpu_ms as f64 / 1000.0;
+        Some(sampling + time)
+    }
+
+    /// Estimated cost of a new `workflow` job, given the jobs run so far.
+    pub fn estimate<'a>(&self, megapixel_steps: f64, workflow: Option<&str>, jobs: impl Iterator<Item = &'a Job>) -> Option<f64> {
+        let gpu_ms = if self.per_gpu_second.is_some() { average_execution_ms(workflow, jobs).unwrap_or(0) } else { 0 };
+        self.cost(megapixel_steps, gpu_ms)
+    }
+
+    /// Cost of a finished job.
+    pub fn actual(&self, job: &Job) -> Option<f64> {
+        self.cost(job.megapixel_steps, execution_ms(job))
+    }
+}
+
+/// Mean execution time of `workflow`'s completed jobs.
+pub fn average_execution_ms<'a>(workflow: Option<&str>, jobs: impl Iterator<Item = &'a Job>) -> Option<u64> {
+    let times: Vec<u64> = jobs
+        .filter(|j| j.workflow.as_deref() == workflow && is_completed(j))
+        .map(execution_ms)
+        .filter(|ms| *ms > 0)
+        .collect();
+    (!times.is_empty()).then(|| times.iter().sum::<u64>() / times.len() as u64)
+}
+
+/// Sampling work in `graph`: for each node with integer `steps` and a
+/// `latent_image` input, the latent's megapixels × batch × steps actually
+/// run (honoring KSamplerAdvanced's `start_at_step`/`end_at_step`).
+/// Samplers whose latent size can't be traced to a node with `width`,
+/// `height`, and `batch_size` (e.g. img2img) are left out.
+pub fn megapixel_steps(graph: &Value) -> f64 {
+    let Some(nodes) = graph.as_object() else { return 0.0 };
+    nodes.iter()
+        .filter_map(|(id, node)| {
+            let inputs = node.get("inputs")?;
+            inputs.get("latent_image")?;
+            let steps = inputs.get("steps")?.as_u64()?;
+            let start = inputs.get("start_at_step").and_then(|v| v.as_u64()).unwrap_or(0);
+            let end = inputs.get("end_at_step").and_then(|v| v.as_u64()).unwrap_or(steps).min(steps);
+            let batch = upstream_batch(graph, id)?;
+            let megapixels = (batch.width? * batch.height?) as f64 / 1_000_000.0;
+            Some(megapixels * batch.node_batch as f64 * end.saturating_sub(start) as f64)
+        })
+        .sum()
+}
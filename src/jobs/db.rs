@@ -0,0 +1,248 @@
+//! SQLite persistence for the job store (the `sqlite` feature).
+//!
+//! One row per job, rewritten whenever the job changes. The timeline and
+//! node classes are stored as JSON; state, timestamps, and output filenames
+//! get their own columns so the database can be queried directly. Saves
+//! merge into the stored row (see [`Job::merge`]) inside a write
+//! transaction, so replicas sharing the file don't drop each other's events.
+use async_trait::async_trait;
+use rusqlite::{params, Connection, ToSql, TransactionBehavior};
+use serde_json::Value;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::auth::keys::key_id;
+use crate::jobs::backend::JobBackend;
+use crate::jobs::store::Job;
+use crate::utils::time::now_ms;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS jobs (
+    id TEXT PRIMARY KEY,
+    prompt_id TEXT,
+    workflow TEXT,
+    tenant TEXT,
+    key_id TEXT,
+    created_at_ms INTEGER NOT NULL,
+    updated_at_ms INTEGER NOT NULL,
+    state TEXT NOT NULL,
+    request TEXT,
+    expected_outputs INTEGER NOT NULL,
+    outputs TEXT NOT NULL,
+    events TEXT NOT NULL,
+    node_classes TEXT NOT NULL,
+    history_synced INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS jobs_created_at ON jobs (created_at_ms);
+CREATE INDEX IF NOT EXISTS jobs_prompt_id ON jobs (prompt_id);
+CREATE TABLE IF NOT EXISTS leases (
+    name TEXT PRIMARY KEY,
+    owner TEXT NOT NULL,
+    expires_at_ms INTEGER NOT NULL
+);
+";
+
+/// Columns added after the first schema, with their types. Older databases
+/// get them on open.
+const ADDED_COLUMNS: &[(&str, &str)] = &[
+    ("megapixel_steps", "REAL NOT NULL DEFAULT 0"),
+    ("estimated_cost", "REAL"),
+    ("actual_cost", "REAL"),
+    // When the row was last written, for replicas syncing changes.
+    ("saved_at_ms", "INTEGER NOT NULL DEFAULT 0"),
+    ("client", "TEXT"),
+    // Replaces the raw `api_key` column of older databases (see `JobDb::open`).
+    ("key_id", "TEXT"),
+];
+
+#[derive(Clone)]
+pub struct JobDb {
+    conn: Arc<Mutex<Connection>>,
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<String, String> {
+    serde_json::to_string(value).map_err(|e| e.to_string())
+}
+
+fn from_json<T: serde::de::DeserializeOwned>(text: &str) -> rusqlite::Result<T> {
+    serde_json::from_str(text).map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+impl JobDb {
+    /// Open (or create) the database at `path`.
+    pub fn open(path: &str) -> Result<Self, String> {
+        let conn = Connection::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+        conn.pragma_update(None, "journal_mode", "WAL").map_err(|e| e.to_string())?;
+        conn.execute_batch(SCHEMA).map_err(|e| format!("Failed to create tables in {}: {}", path, e))?;
+        for (column, kind) in ADDED_COLUMNS {
+            let exists = conn.prepare("SELECT 1 FROM pragma_table_info('jobs') WHERE name = ?1")
+                .and_then(|mut stmt| stmt.exists([column]))
+                .map_err(|e| e.to_string())?;
+            if !exists {
+                conn.execute_batch(&format!("ALTER TABLE jobs ADD COLUMN {} {}", column, kind))
+                    .map_err(|e| format!("Failed to add column {} in {}: {}", column, path, e))?;
+            }
+        }
+        conn.execute_batch("CREATE INDEX IF NOT EXISTS jobs_saved_at ON jobs (saved_at_ms)").map_err(|e| e.to_string())?;
+        scrub_api_keys(&conn).map_err(|e| format!("Failed to remove stored API keys in {}: {}", path, e))?;
+        Ok(JobDb { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    /// Copy the database at `path` to `dest` (which mustn't exist) as a
+    /// single file, including changes still in its write-ahead log.
+    pub fn snapshot(path: &Path, dest: &Path) -> Result<(), String> {
+        let conn = Connection::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        conn.execute("VACUUM INTO ?1", [dest.to_string_lossy()])
+            .map_err(|e| format!("Failed to copy {}: {}", path.display(), e))?;
+        Ok(())
+    }
+
+    /// Every stored job.
+    pub fn load(&self) -> Result<Vec<Job>, String> {
+        self.query("", &[])
+    }
+
+    /// Jobs whose rows were written at or after `since_ms`.
+    pub fn changed_since(&self, since_ms: u64) -> Result<Vec<Job>, String> {
+        self.query("WHERE saved_at_ms >= ?1", &[&(since_ms as i64)])
+    }
+
+    /// See [`JobBackend::claim`].
+    pub fn claim(&self, name: &str, owner: &str, ttl_ms: u64) -> Result<bool, String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let now = now_ms() as i64;
+        let changed = conn.execute(
+            "INSERT INTO leases (name, owner, expires_at_ms) VALUES (?1, ?2, ?3)
+             ON CONFLICT(name) DO UPDATE SET owner = excluded.owner, expires_at_ms = excluded.expires_at_ms
+             WHERE leases.owner = excluded.owner OR leases.expires_at_ms <= ?4",
+            params![name, owner, now + ttl_ms as i64, now],
+        ).map_err(|e| format!("Failed to claim lease {}: {}", name, e))?;
+        Ok(changed > 0)
+    }
+
+    fn query(&self, filter: &str, args: &[&dyn ToSql]) -> Result<Vec<Job>, String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+        query(&conn, filter, args)
+    }
+
+    /// Merge `job` into its stored row, or insert it.
+    pub fn save(&self, job: &Job) -> Result<(), String> {
+        let mut conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(|e| format!("Failed to save job {}: {}", job.id, e))?;
+        let mut merged = job.clone();
+        for stored in query(&tx, "WHERE id = ?1", &[&job.id])? {
+            merged.merge(stored);
+        }
+        let job = &merged;
+        let outputs: Vec<String> = job.outputs().into_iter().map(|o| o.filename).collect();
+        let state = to_json(&job.state())?;
+        let updated_at = job.events.iter().map(|e| e.at_ms).max().unwrap_or(job.created_at_ms);
+        tx.execute(
+            "INSERT OR REPLACE INTO jobs (id, prompt_id, workflow, tenant, key_id, created_at_ms, updated_at_ms, state, request, expected_outputs, outputs, events, node_classes, history_synced,
+                                       megapixel_steps, estimated_cost, actual_cost, saved_at_ms, client)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)",
+            params![
+                job.id,
+                job.prompt_id,
+                job.workflow,
+                job.tenant,
+                job.key_id,
+                job.created_at_ms as i64,
+                updated_at as i64,
+                state.trim_matches('"'),
+                job.request.as_ref().map(to_json).transpose()?,
+                job.expected_outputs as i64,
+                to_json(&outputs)?,
+                to_json(&job.events)?,
+                to_json(&job.node_classes)?,
+                job.history_synced,
+                job.megapixel_steps,
+                job.estimated_cost,
+                job.actual_cost,
+                now_ms() as i64,
+                job.client,
+            ],
+        ).map_err(|e| format!("Failed to save job {}: {}", job.id, e))?;
+        tx.commit().map_err(|e| format!("Failed to save job {}: {}", job.id, e))
+    }
+}
+
+fn query(conn: &Connection, filter: &str, args: &[&dyn ToSql]) -> Result<Vec<Job>, String> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, prompt_id, workflow, tenant, key_id, created_at_ms, request, expected_outputs, events, node_classes, history_synced,
+                megapixel_steps, estimated_cost, actual_cost, client FROM jobs {}",
+        filter,
+    )).map_err(|e| e.to_string())?;
+    let rows = stmt.query_map(args, |row| {
+        let request: Option<String> = row.get(6)?;
+        Ok(Job {
+            id: row.get(0)?,
+            prompt_id: row.get(1)?,
+            workflow: row.get(2)?,
+            tenant: row.get(3)?,
+            client: row.get(14)?,
+            key_id: row.get(4)?,
+            created_at_ms: row.get::<_, i64>(5)? as u64,
+            request: request.as_deref().map(from_json::<Value>).transpose()?,
+            expected_outputs: row.get::<_, i64>(7)? as u64,
+            events: from_json(&row.get::<_, String>(8)?)?,
+            node_classes: from_json(&row.get::<_, String>(9)?)?,
+            history_synced: row.get(10)?,
+            megapixel_steps: row.get(11)?,
+            estimated_cost: row.get(12)?,
+            actual_cost: row.get(13)?,
+            split_grid: None,
+        })
+    }).map_err(|e| e.to_string())?;
+    rows.collect::<rusqlite::Result<Vec<Job>>>().map_err(|e| e.to_string())
+}
+
+/// Older databases stored each job's raw API key in `api_key`: replace it
+/// with its [`key_id`] and drop the column, so the file holds no secrets.
+fn scrub_api_keys(conn: &Connection) -> rusqlite::Result<()> {
+    let legacy = conn.prepare("SELECT 1 FROM pragma_table_info('jobs') WHERE name = 'api_key'")?.exists([])?;
+    if !legacy {
+        return Ok(());
+    }
+    let keys: Vec<(String, String)> = conn.prepare("SELECT id, api_key FROM jobs WHERE api_key IS NOT NULL")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let tx = conn.unchecked_transaction()?;
+    for (id, key) in keys {
+        tx.execute("UPDATE jobs SET key_id = ?1 WHERE id = ?2", params![key_id(&key), id])?;
+    }
+    tx.execute_batch("ALTER TABLE jobs DROP COLUMN api_key")?;
+    tx.commit()
+}
+
+/// Run `f` on the blocking thread pool, off the async runtime.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T, String> + Send + 'static) -> Result<T, String> {
+    tokio::task::spawn_blocking(f).await.map_err(|e| e.to_string())?
+}
+
+#[async_trait]
+impl JobBackend for JobDb {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn load(&self) -> Result<Vec<Job>, String> {
+        JobDb::load(self)
+    }
+
+    async fn save(&self, job: &Job) -> Result<(), String> {
+        let (db, job) = (self.clone(), job.clone());
+        blocking(move || db.save(&job)).await
+    }
+
+    async fn changed_since(&self, since_ms: u64) -> Result<Vec<Job>, String> {
+        let db = self.clone();
+        blocking(move || db.changed_since(since_ms)).await
+    }
+
+    async fn claim(&self, name: &str, owner: &str, ttl_ms: u64) -> Result<bool, String> {
+        let (db, name, owner) = (self.clone(), name.to_string(), owner.to_string());
+        blocking(move || db.claim(&name, &owner, ttl_ms)).await
+    }
+}
@@ -0,0 +1,142 @@
+//! Per-job event timeline.
+//!
+//! Events are appended as a job moves through the proxy and the backend, so
+//! `GET /jobs/:id/events` can show where a stalled or failed generation got to.
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::utils::time::now_ms;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JobEventKind {
+    /// Request accepted by the proxy.
+    Submitted { workflow: Option<String> },
+    /// Job created from an imported ComfyUI history entry rather than
+    /// submitted through the proxy.
+    Imported { prompt_id: String },
+    /// Prompt held by the proxy instance `instance` for fair scheduling
+    /// (`FAIR_QUEUE_DEPTH`) instead of being sent straight away.
+    Held { instance: String },
+    /// Prompt accepted by ComfyUI.
+    SentToBackend { prompt_id: String },
+    /// Backend began executing the prompt.
+    ExecutionStarted,
+    /// Node output was served from ComfyUI's cache.
+    NodeCached { node: String },
+    NodeStarted { node: String },
+    NodeFinished { node: String },
+    OutputSaved {
+        node: String,
+        filename: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        subfolder: Option<String>,
+        /// ComfyUI folder type (`output`, `temp`).
+        #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+        folder_type: Option<String>,
+    },
+    WebhookDelivered { url: String },
+    /// Caption the static drive's `caption` action wrote for the harvested
+    /// copy of output `filename` of `node`.
+    Captioned { node: String, filename: String, caption: String },
+    Completed,
+    Failed { node: Option<String>, error: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JobEvent {
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub kind: JobEventKind,
+}
+
+impl JobEvent {
+    pub fn now(kind: JobEventKind) -> Self {
+        JobEvent { at_ms: now_ms(), kind }
+    }
+
+    pub fn at(at_ms: u64, kind: JobEventKind) -> Self {
+        JobEvent { at_ms, kind }
+    }
+}
+
+/// Derive timeline events from a single ComfyUI history entry
+/// (the value stored under the prompt id in `/history/{prompt_id}`).
+///
+/// Uses `status.messages` for execution milestones and `outputs` for saved
+/// files. Output events carry the completion timestamp when available.
+/// Entries from ComfyUI versions that write no messages count as completed
+/// (or failed, per `status_str`) without timestamps.
+pub fn events_from_history_entry(entry: &Value) -> Vec<JobEvent> {
+    let mut events = Vec::new();
+    let mut finished_at: Option<u64> = None;
+
+    let messages = entry.get("status")
+        .and_then(|s| s.get("messages"))
+        .and_then(|m| m.as_array())
+        .cloned()
+        .unwrap_or_default();
+    for msg in messages.iter() {
+        let Some(pair) = msg.as_array() else { continue };
+        let (Some(name), Some(data)) = (pair.first().and_then(|v| v.as_str()), pair.get(1)) else { continue };
+        let at = data.get("timestamp").and_then(|v| v.as_u64()).unwrap_or(0);
+        match name {
+            "execution_start" => events.push(JobEvent::at(at, JobEventKind::ExecutionStarted)),
+            "execution_cached" => {
+                for node in data.get("nodes").and_then(|v| v.as_array()).into_iter().flatten() {
+                    if let Some(node) = node.as_str() {
+                        events.push(JobEvent::at(at, JobEventKind::NodeCached { node: node.to_string() }));
+                    }
+                }
+            }
+            "execution_success" => finished_at = Some(at),
+            "execution_error" | "execution_interrupted" => {
+                let node = data.get("node_id").and_then(|v| v.as_str()).map(String::from);
+                let error = data.get("exception_message")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or(name)
+                    .trim()
+                    .to_string();
+                events.push(JobEvent::at(at, JobEventKind::Failed { node, error }));
+            }
+            _ => {}
+        }
+    }
+
+    // ComfyUI versions without status messages: an entry in history means
+    // the prompt ran, and `status_str`, when present, says how it ended.
+    if finished_at.is_none() && !events.iter().any(|e| matches!(e.kind, JobEventKind::Failed { .. })) {
+        let status = entry.get("status");
+        let last_at = events.iter().map(|e| e.at_ms).max().unwrap_or(0);
+        match status.and_then(|s| s.get("status_str")).and_then(|v| v.as_str()) {
+            Some("error") => events.push(JobEvent::at(last_at, JobEventKind::Failed { node: None, error: "execution failed".to_string() })),
+            _ if messages.is_empty() && status.and_then(|s| s.get("completed")).and_then(|v| v.as_bool()) != Some(false) => {
+                finished_at = Some(last_at);
+            }
+            _ => {}
+        }
+    }
+
+    let output_at = finished_at.unwrap_or(0);
+    if let Some(outputs) = entry.get("outputs").and_then(|v| v.as_object()) {
+        for (node, out) in outputs {
+            for kind in ["images", "gifs", "videos"] {
+                for item in out.get(kind).and_then(|v| v.as_array()).into_iter().flatten() {
+                    if let Some(filename) = item.get("filename").and_then(|v| v.as_str()) {
+                        let field = |k: &str| item.get(k).and_then(|v| v.as_str()).filter(|s| !s.is_empty()).map(String::from);
+                        events.push(JobEvent::at(output_at, JobEventKind::OutputSaved {
+                            node: node.clone(),
+                            filename: filename.to_string(),
+                            subfolder: field("subfolder"),
+                            folder_type: field("type"),
+                        }));
+                    }
+                }
+            }
+        }
+    }
+    if let Some(at) = finished_at {
+        events.push(JobEvent::at(at, JobEventKind::Completed));
+    }
+    events
+}
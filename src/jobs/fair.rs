@@ -0,0 +1,138 @@
+//! Fair scheduling of prompt submissions across API keys.
+//!
+//! With `FAIR_QUEUE_DEPTH` set, the proxy holds prompts instead of sending
+//! them to ComfyUI straight away, and sends them as ComfyUI's queue drains.
+//! Prompts of workflows with `WORKFLOW_LIMITS` are held the same way, and
+//! sent as their limits allow.
+//! Held prompts are sent round-robin across keys rather than in arrival
+//! order, so one key's 500-image sweep can't starve everyone else: each key
+//! in turn sends up to its `weight` prompts (default 1), oldest first.
+use std::collections::{HashMap, VecDeque};
+
+struct Lane<T> {
+    weight: u32,
+    /// Held prompts as `(job id, prompt)`, oldest first.
+    items: VecDeque<(String, T)>,
+}
+
+pub struct FairQueue<T> {
+    lanes: HashMap<String, Lane<T>>,
+    /// Keys with held prompts; the front key is taking its turn.
+    turns: VecDeque<String>,
+    /// Prompts the front key may still send this turn.
+    credit: u32,
+}
+
+impl<T> Default for FairQueue<T> {
+    fn default() -> Self {
+        FairQueue { lanes: HashMap::new(), turns: VecDeque::new(), credit: 0 }
+    }
+}
+
+impl<T> FairQueue<T> {
+    pub fn new() -> Self {
+        FairQueue::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lanes.values().map(|lane| lane.items.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.turns.is_empty()
+    }
+
+    /// Hold `item` for job `job_id`, behind `key`'s other held prompts. A
+    /// key with nothing held joins the end of the rotation; `weight` (at
+    /// least 1) replaces the key's previous weight.
+    pub fn push(&mut self, key: &str, weight: u32, job_id: String, item: T) {
+        let weight = weight.max(1);
+        let lane = self.lanes.entry(key.to_string()).or_insert_with(|| Lane { weight, items: VecDeque::new() });
+        lane.weight = weight;
+        lane.items.push_back((job_id, item));
+        if lane.items.len() == 1 {
+            self.turns.push_back(key.to_string());
+            if self.turns.len() == 1 {
+                self.credit = weight;
+            }
+        }
+    }
+
+    /// The next prompt to send, as `(job id, prompt)`.
+    pub fn pop(&mut self) -> Option<(String, T)> {
+        let key = self.turns.front()?.clone();
+        let lane = self.lanes.get_mut(&key)?;
+        let next = lane.items.pop_front();
+        self.credit = self.credit.saturating_sub(1);
+        if lane.items.is_empty() {
+            self.lanes.remove(&key);
+            self.turns.pop_front();
+            self.start_turn();
+        } else if self.credit == 0 {
+            self.turns.rotate_left(1);
+            self.start_turn();
+        }
+        next
+    }
+
+    /// The next prompt to send that `sendable` accepts, as `(job id,
+    /// prompt)`. Prompts it refuses keep their place.
+    pub fn pop_where(&mut self, mut sendable: impl FnMut(&T) -> bool) -> Option<(String, T)> {
+        let (position, job_id) = self.schedule().into_iter().enumerate()
+            .find(|(_, (_, item))| sendable(item))
+            .map(|(position, (job_id, _))| (position, job_id.clone()))?;
+        match position {
+            0 => self.pop(),
+            _ => self.remove(&job_id).map(|item| (job_id, item)),
+        }
+    }
+
+    /// Stop holding job `job_id`'s prompt, returning it.
+    pub fn remove(&mut self, job_id: &str) -> Option<T> {
+        let (key, index) = self.lanes.iter()
+            .find_map(|(key, lane)| lane.items.iter().position(|(id, _)| id == job_id).map(|i| (key.clone(), i)))?;
+        let lane = self.lanes.get_mut(&key)?;
+        let (_, item) = lane.items.remove(index)?;
+        if lane.items.is_empty() {
+            self.lanes.remove(&key);
+            let was_front = self.turns.front() == Some(&key);
+            self.turns.retain(|k| *k != key);
+            if was_front {
+                self.start_turn();
+            }
+        }
+        Some(item)
+    }
+
+    /// Held job ids, in the order they will be sent.
+    pub fn order(&self) -> Vec<&str> {
+        self.schedule().into_iter().map(|(job_id, _)| job_id.as_str()).collect()
+    }
+
+    /// Held prompts, in the order they will be sent.
+    fn schedule(&self) -> Vec<&(String, T)> {
+        let mut turns: VecDeque<&str> = self.turns.iter().map(String::as_str).collect();
+        let mut sent: HashMap<&str, usize> = HashMap::new();
+        let mut credit = self.credit;
+        let mut order = Vec::with_capacity(self.len());
+        while let Some(&key) = turns.front() {
+            let lane = &self.lanes[key];
+            let next = sent.entry(key).or_insert(0);
+            order.push(&lane.items[*next]);
+            *next += 1;
+            credit = credit.saturating_sub(1);
+            if *next == lane.items.len() {
+                turns.pop_front();
+                credit = turns.front().map(|k| self.lanes[*k].weight).unwrap_or(0);
+            } else if credit == 0 {
+                turns.rotate_left(1);
+                credit = turns.front().map(|k| self.lanes[*k].weight).unwrap_or(0);
+            }
+        }
+        order
+    }
+
+    fn start_turn(&mut self) {
+        self.credit = self.turns.front().and_then(|key| self.lanes.get(key)).map(|lane| lane.weight).unwrap_or(0);
+    }
+}
@@ -0,0 +1,75 @@
+//! Flattened records for ComfyUI history entries.
+//!
+//! `/history` is keyed by prompt id, and each entry nests its outcome under
+//! `status.messages` and its files under `outputs`. A [`HistoryRecord`]
+//! pulls out what callers usually want (state, timings, error, output
+//! files), one record per prompt.
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::jobs::events::{events_from_history_entry, JobEventKind};
+use crate::jobs::store::{JobState, OutputFile};
+
+#[derive(Debug, Clone, Serialize, PartialEq, utoipa::ToSchema)]
+pub struct HistoryRecord {
+    pub prompt_id: String,
+    /// Proxy job the prompt was submitted as, if known.
+    pub job_id: Option<String>,
+    pub workflow: Option<String>,
+    /// ComfyUI's queue number for the prompt.
+    pub number: Option<u64>,
+    pub state: JobState,
+    pub started_at_ms: Option<u64>,
+    pub finished_at_ms: Option<u64>,
+    pub error: Option<String>,
+    pub outputs: Vec<OutputFile>,
+}
+
+/// Parse the history entry stored under `prompt_id`.
+pub fn parse_history_entry(prompt_id: &str, entry: &Value) -> HistoryRecord {
+    let mut record = HistoryRecord {
+        prompt_id: prompt_id.to_string(),
+        job_id: None,
+        workflow: None,
+        number: entry.get("prompt").and_then(|p| p.get(0)).and_then(|v| v.as_u64()),
+        state: JobState::Running,
+        started_at_ms: None,
+        finished_at_ms: None,
+        error: None,
+        outputs: Vec::new(),
+    };
+    for event in events_from_history_entry(entry) {
+        match event.kind {
+            JobEventKind::ExecutionStarted => record.started_at_ms = Some(event.at_ms),
+            JobEventKind::OutputSaved { node, filename, subfolder, folder_type } => {
+                record.outputs.push(OutputFile { node, filename, subfolder, folder_type, caption: None });
+            }
+            JobEventKind::Completed if record.state != JobState::Failed => {
+                record.state = JobState::Completed;
+                record.finished_at_ms = Some(event.at_ms);
+            }
+            JobEventKind::Failed { error, .. } => {
+                record.state = JobState::Failed;
+                record.finished_at_ms = Some(event.at_ms);
+                record.error = Some(error);
+            }
+            _ => {}
+        }
+    }
+    let completed = entry.get("status").and_then(|s| s.get("completed")).and_then(|v| v.as_bool());
+    if record.state == JobState::Running && completed == Some(true) {
+        record.state = JobState::Completed;
+    }
+    record
+}
+
+/// Records for every entry of a `/history` document, in queue order.
+pub fn history_records(history: &Value) -> Vec<HistoryRecord> {
+    let Some(entries) = history.as_object() else { return Vec::new() };
+    let mut records: Vec<HistoryRecord> = entries.iter()
+        .filter(|(_, entry)| entry.is_object())
+        .map(|(pid, entry)| parse_history_entry(pid, entry))
+        .collect();
+    records.sort_by(|a, b| a.number.cmp(&b.number).then_with(|| a.prompt_id.cmp(&b.prompt_id)));
+    records
+}
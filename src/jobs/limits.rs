@@ -0,0 +1,127 @@
+//! Per-workflow concurrency and cooldown rules.
+//!
+//! VRAM-heavy workflows can be limited to a number of jobs in ComfyUI at a
+//! time, or to one prompt sent per cooldown period. Jobs over a limit are
+//! held by the proxy and sent once the limit allows, instead of piling work
+//! into ComfyUI's queue.
+use std::collections::HashMap;
+
+use crate::config::Config;
+use crate::error::AppError;
+use crate::jobs::store::Job;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WorkflowRule {
+    /// Most jobs sent to ComfyUI and not finished at once.
+    pub max_concurrent: Option<usize>,
+    /// Least time between prompts sent, in milliseconds.
+    pub cooldown_ms: Option<u64>,
+}
+
+/// A job a workflow rule doesn't let through yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkflowBusy {
+    pub message: String,
+    /// When a retry can succeed, if that's known (cooldowns).
+    pub retry_at_ms: Option<u64>,
+}
+
+impl From<WorkflowBusy> for AppError {
+    fn from(b: WorkflowBusy) -> Self {
+        AppError::WorkflowBusy { message: b.message, retry_at_ms: b.retry_at_ms }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WorkflowLimits {
+    rules: HashMap<String, WorkflowRule>,
+}
+
+impl WorkflowLimits {
+    /// Parse `WORKFLOW_LIMITS`: comma-separated `<workflow>:<rule>=<value>`
+    /// entries, where rule is `concurrency` (a count) or `cooldown`
+    /// (seconds), e.g. `video_animatediff:concurrency=1,train_lora:cooldown=30`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut rules: HashMap<String, WorkflowRule> = HashMap::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let parsed = entry.rsplit_once(':')
+                .and_then(|(workflow, rule)| rule.split_once('=').map(|(name, value)| (workflow.trim(), name.trim(), value.trim())));
+            let Some((workflow, name, value)) = parsed.filter(|(w, _, _)| !w.is_empty()) else {
+                return Err(format!("Invalid entry '{}', expected <workflow>:<rule>=<value>", entry));
+            };
+            let rule = rules.entry(workflow.to_string()).or_default();
+            match name {
+                "concurrency" => {
+                    let max = value.parse::<usize>().ok().filter(|n| *n > 0)
+                        .ok_or_else(|| format!("Invalid concurrency '{}' for '{}': expected a positive integer", value, workflow))?;
+                    rule.max_concurrent = Some(max);
+                }
+                "cooldown" => {
+                    let secs = value.trim_end_matches('s').parse::<f64>().ok().filter(|s| *s >= 0.0 && s.is_finite())
+                        .ok_or_else(|| format!("Invalid cooldown '{}' for '{}': expected seconds", value, workflow))?;
+                    rule.cooldown_ms = Some((secs * 1000.0) as u64);
+                }
+                other => return Err(format!("Unknown rule '{}' for '{}': expected concurrency or cooldown", other, workflow)),
+            }
+        }
+        Ok(WorkflowLimits { rules })
+    }
+
+    /// Rules from `WORKFLOW_LIMITS`. An invalid spec is logged and ignored.
+    pub fn from_config(config: &Config) -> Self {
+        let Some(spec) = config.workflow_limits.as_deref() else { return WorkflowLimits::default() };
+        WorkflowLimits::parse(spec).unwrap_or_else(|e| {
+            tracing::error!("Ignoring WORKFLOW_LIMITS: {}", e);
+            WorkflowLimits::default()
+        })
+    }
+
+    pub fn rule(&self, workflow: &str) -> Option<&WorkflowRule> {
+        self.rules.get(workflow)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether a job for `workflow` may be sent to ComfyUI at `now_ms`,
+    /// given the existing `jobs`. Only jobs already sent count: unfinished
+    /// ones take a concurrency slot, and the cooldown runs from the last
+    /// one sent. Callers settle jobs ComfyUI has finished or dropped first,
+    /// so they don't keep their slots.
+    pub fn check<'a>(&self, workflow: &str, jobs: impl Iterator<Item = &'a Job>, now_ms: u64) -> Result<(), WorkflowBusy> {
+        let (mut active, mut last_sent_ms) = (0, None);
+        for job in jobs.filter(|j| j.workflow.as_deref() == Some(workflow)) {
+            let Some(sent_at) = job.sent_at_ms() else { continue };
+            if !job.is_finished() {
+                active += 1;
+            }
+            last_sent_ms = last_sent_ms.max(Some(sent_at));
+        }
+        self.allows(workflow, active, last_sent_ms, now_ms)
+    }
+
+    /// Whether a job for `workflow` may be sent at `now_ms` with `active` of
+    /// its jobs unfinished in ComfyUI, the last sent at `last_sent_ms`.
+    pub fn allows(&self, workflow: &str, active: usize, last_sent_ms: Option<u64>, now_ms: u64) -> Result<(), WorkflowBusy> {
+        let Some(rule) = self.rule(workflow) else { return Ok(()) };
+        if let Some(max) = rule.max_concurrent {
+            if active >= max {
+                return Err(WorkflowBusy {
+                    message: format!("Workflow '{}' allows {} concurrent run(s) and {} are in ComfyUI", workflow, max, active),
+                    retry_at_ms: None,
+                });
+            }
+        }
+        if let (Some(cooldown), Some(last)) = (rule.cooldown_ms, last_sent_ms) {
+            let ready_at = last + cooldown;
+            if now_ms < ready_at {
+                return Err(WorkflowBusy {
+                    message: format!("Workflow '{}' has a {}s cooldown between runs", workflow, cooldown as f64 / 1000.0),
+                    retry_at_ms: Some(ready_at),
+                });
+            }
+        }
+        Ok(())
+    }
+}
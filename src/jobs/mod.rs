@@ -0,0 +1,24 @@
+//! Proxy-side job tracking.
+//!
+//! A job is created for every prompt submitted through `/queue_prompt` and
+//! keeps a timeline of what happened to it in the proxy and in ComfyUI.
+pub mod backend;
+pub mod batch;
+pub mod cost;
+#[cfg(feature = "sqlite")]
+pub mod db;
+pub mod events;
+pub mod fair;
+pub mod history;
+pub mod limits;
+#[cfg(feature = "redis")]
+pub mod redis;
+pub mod store;
+pub mod timing;
+
+pub use backend::{JobBackend, JobRecord};
+pub use events::{JobEvent, JobEventKind};
+pub use fair::FairQueue;
+pub use history::{history_records, HistoryRecord};
+pub use store::{Job, JobState, JobStore, Leases, OutputFile, Refreshed};
+pub use timing::{NodeStat, NodeTiming};
@@ -0,0 +1,155 @@
+//! Redis job backend (the `redis` feature), shared by proxy replicas.
+//!
+//! Each job is a JSON string at `<prefix>:job:<id>`; the sorted set
+//! `<prefix>:jobs` scores job ids by when they were last saved, so replicas
+//! can fetch what changed since their last sync. Leases are
+//! `<prefix>:lease:<name>` keys holding the owner, expiring with the lease.
+//!
+//! Saves are compare-and-set on the job's key: a replica merges its copy
+//! into the stored one (see [`Job::merge`]) and writes the result only if
+//! nobody wrote in between, retrying otherwise, so concurrent saves of the
+//! same job don't drop each other's events.
+use async_trait::async_trait;
+use redis::aio::MultiplexedConnection;
+use redis::{Cmd, Commands, FromRedisValue};
+use tokio::sync::Mutex;
+
+use crate::jobs::backend::{JobBackend, JobRecord};
+use crate::jobs::store::Job;
+use crate::utils::time::now_ms;
+
+/// Set the lease key to the owner unless someone else holds it.
+const CLAIM_SCRIPT: &str = "
+local holder = redis.call('GET', KEYS[1])
+if holder == false or holder == ARGV[1] then
+    redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+    return 1
+end
+return 0
+";
+
+/// Write the job (ARGV[2]) and bump it in the index, if its key still
+/// holds what the writer read (ARGV[1], empty for no job).
+const SAVE_SCRIPT: &str = "
+if (redis.call('GET', KEYS[1]) or '') ~= ARGV[1] then
+    return 0
+end
+redis.call('SET', KEYS[1], ARGV[2])
+redis.call('ZADD', KEYS[2], ARGV[4], ARGV[3])
+return 1
+";
+
+/// Times a save re-reads and re-merges a job that keeps changing under it.
+const SAVE_ATTEMPTS: usize = 5;
+
+pub struct RedisJobs {
+    client: redis::Client,
+    /// Shared async connection, opened on first use and after it drops.
+    conn: Mutex<Option<MultiplexedConnection>>,
+    prefix: String,
+}
+
+fn parse_job(json: &str) -> Result<Job, String> {
+    serde_json::from_str::<JobRecord>(json).map(Job::from).map_err(|e| format!("Invalid job in Redis: {}", e))
+}
+
+impl RedisJobs {
+    /// Connect to the Redis server at `url` (`redis://host:port/db`).
+    pub fn open(url: &str, prefix: &str) -> Result<Self, String> {
+        let client = redis::Client::open(url).map_err(|e| format!("Invalid Redis URL {}: {}", url, e))?;
+        Ok(RedisJobs { client, conn: Mutex::new(None), prefix: prefix.trim_end_matches(':').to_string() })
+    }
+
+    fn job_key(&self, id: &str) -> String {
+        format!("{}:job:{}", self.prefix, id)
+    }
+
+    fn index_key(&self) -> String {
+        format!("{}:jobs", self.prefix)
+    }
+
+    async fn connection(&self) -> Result<MultiplexedConnection, String> {
+        let mut conn = self.conn.lock().await;
+        if let Some(conn) = conn.as_ref() {
+            return Ok(conn.clone());
+        }
+        let fresh = self.client.get_multiplexed_tokio_connection().await
+            .map_err(|e| format!("Failed to connect to Redis: {}", e))?;
+        *conn = Some(fresh.clone());
+        Ok(fresh)
+    }
+
+    /// Run `cmd`, reconnecting once if the connection dropped.
+    async fn query<T: FromRedisValue>(&self, cmd: &Cmd) -> Result<T, String> {
+        let mut conn = self.connection().await?;
+        match cmd.query_async(&mut conn).await {
+            Err(e) if e.is_connection_dropped() || e.is_io_error() => {
+                *self.conn.lock().await = None;
+                let mut conn = self.connection().await?;
+                cmd.query_async(&mut conn).await.map_err(|e| e.to_string())
+            }
+            result => result.map_err(|e| e.to_string()),
+        }
+    }
+
+    async fn fetch(&self, ids: Vec<String>) -> Result<Vec<Job>, String> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let keys: Vec<String> = ids.iter().map(|id| self.job_key(id)).collect();
+        let values: Vec<Option<String>> = self.query(redis::cmd("MGET").arg(&keys)).await?;
+        values.into_iter().flatten().map(|json| parse_job(&json)).collect()
+    }
+}
+
+#[async_trait]
+impl JobBackend for RedisJobs {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    /// Reads over a short-lived blocking connection: this runs once, before
+    /// the proxy serves anything.
+    fn load(&self) -> Result<Vec<Job>, String> {
+        let mut conn = self.client.get_connection().map_err(|e| format!("Failed to connect to Redis: {}", e))?;
+        let ids: Vec<String> = conn.zrange(self.index_key(), 0, -1).map_err(|e| e.to_string())?;
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let keys: Vec<String> = ids.iter().map(|id| self.job_key(id)).collect();
+        let values: Vec<Option<String>> = redis::cmd("MGET").arg(&keys).query(&mut conn).map_err(|e| e.to_string())?;
+        values.into_iter().flatten().map(|json| parse_job(&json)).collect()
+    }
+
+    async fn save(&self, job: &Job) -> Result<(), String> {
+        let key = self.job_key(&job.id);
+        for _ in 0..SAVE_ATTEMPTS {
+            let stored: Option<String> = self.query(redis::cmd("GET").arg(&key)).await
+                .map_err(|e| format!("Failed to save job {}: {}", job.id, e))?;
+            let mut merged = job.clone();
+            if let Some(stored) = &stored {
+                merged.merge(parse_job(stored)?);
+            }
+            let json = serde_json::to_string(&JobRecord::from(&merged)).map_err(|e| e.to_string())?;
+            let saved: i64 = self.query(redis::cmd("EVAL").arg(SAVE_SCRIPT).arg(2).arg(&key).arg(self.index_key())
+                .arg(stored.as_deref().unwrap_or_default()).arg(&json).arg(&job.id).arg(now_ms())).await
+                .map_err(|e| format!("Failed to save job {}: {}", job.id, e))?;
+            if saved == 1 {
+                return Ok(());
+            }
+        }
+        Err(format!("Failed to save job {}: other replicas kept changing it", job.id))
+    }
+
+    async fn changed_since(&self, since_ms: u64) -> Result<Vec<Job>, String> {
+        let ids: Vec<String> = self.query(redis::cmd("ZRANGEBYSCORE").arg(self.index_key()).arg(since_ms).arg("+inf")).await?;
+        self.fetch(ids).await
+    }
+
+    async fn claim(&self, name: &str, owner: &str, ttl_ms: u64) -> Result<bool, String> {
+        let key = format!("{}:lease:{}", self.prefix, name);
+        let claimed: i64 = self.query(redis::cmd("EVAL").arg(CLAIM_SCRIPT).arg(1).arg(&key).arg(owner).arg(ttl_ms.max(1))).await
+            .map_err(|e| format!("Failed to claim lease {}: {}", name, e))?;
+        Ok(claimed == 1)
+    }
+}
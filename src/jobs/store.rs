@@ -0,0 +1,670 @@
+//! In-memory job store keyed by proxy-side job id.
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::{mpsc, oneshot, RwLock};
+
+use crate::api::hooks::LifecycleHooks;
+use crate::config::Config;
+use crate::jobs::backend::JobBackend;
+use crate::events::{Audience, EventBus, ProxyEvent, ProxyEventKind};
+use crate::jobs::batch::expected_outputs;
+use crate::jobs::cost::megapixel_steps;
+use crate::jobs::events::{events_from_history_entry, JobEvent, JobEventKind};
+use crate::utils::grid_split::GridSplit;
+use crate::utils::time::now_ms;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: String,
+    /// ComfyUI prompt id, once the backend has accepted the prompt.
+    pub prompt_id: Option<String>,
+    pub workflow: Option<String>,
+    pub created_at_ms: u64,
+    pub events: Vec<JobEvent>,
+    /// Images the graph's SaveImage nodes should produce (batch-size aware).
+    pub expected_outputs: u64,
+    /// Sampling work in the submitted graph, for the cost model.
+    #[serde(skip)]
+    pub megapixel_steps: f64,
+    /// Cost estimated at queue time, when a cost model is configured.
+    #[serde(skip)]
+    pub estimated_cost: Option<f64>,
+    /// Cost once the job has finished.
+    #[serde(skip)]
+    pub actual_cost: Option<f64>,
+    /// Node id -> class_type for the submitted graph, used to label timings.
+    #[serde(skip)]
+    pub node_classes: HashMap<String, String>,
+    /// Whether the terminal history entry has been folded into `events`.
+    #[serde(skip)]
+    pub history_synced: bool,
+    /// Id of the API key the job was submitted with (see
+    /// [`crate::auth::ApiKey::id`]), for usage accounting. Never the key itself.
+    #[serde(skip)]
+    pub key_id: Option<String>,
+    /// Tenant that owns the job; other tenants can't see it.
+    pub tenant: Option<String>,
+    /// Client app that submitted the job, from its `X-Client` header.
+    pub client: Option<String>,
+    /// Queue payload the job was submitted with (params, overrides).
+    #[serde(skip)]
+    pub request: Option<Value>,
+    /// Grid split to run on the image outputs once the job finishes; taken
+    /// when it runs.
+    #[serde(skip)]
+    pub split_grid: Option<GridSplit>,
+}
+
+/// Coarse lifecycle state of a job, derived from its timeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    /// Accepted by the proxy, not yet accepted by ComfyUI.
+    Submitted,
+    /// Waiting in ComfyUI's queue.
+    Queued,
+    Running,
+    Completed,
+    Failed,
+}
+
+impl JobState {
+    pub fn parse(s: &str) -> Option<Self> {
+        serde_json::from_value(Value::String(s.to_string())).ok()
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, JobState::Completed | JobState::Failed)
+    }
+}
+
+/// A file written by a job, addressable as `/jobs/:id/outputs/:index`.
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct OutputFile {
+    pub node: String,
+    pub filename: String,
+    pub subfolder: Option<String>,
+    pub folder_type: Option<String>,
+    /// From the latest `captioned` event for the output, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+}
+
+impl Job {
+    /// Outputs reported so far, for partial completion of batched jobs.
+    pub fn saved_outputs(&self) -> u64 {
+        self.events.iter().filter(|e| matches!(e.kind, JobEventKind::OutputSaved { .. })).count() as u64
+    }
+
+    /// Saved outputs in the order ComfyUI reported them; the position is the
+    /// output's index.
+    pub fn outputs(&self) -> Vec<OutputFile> {
+        let mut outputs: Vec<OutputFile> = self.events.iter()
+            .filter_map(|e| match &e.kind {
+                JobEventKind::OutputSaved { node, filename, subfolder, folder_type } => Some(OutputFile {
+                    node: node.clone(),
+                    filename: filename.clone(),
+                    subfolder: subfolder.clone(),
+                    folder_type: folder_type.clone(),
+                    caption: None,
+                }),
+                _ => None,
+            })
+            .collect();
+        for event in &self.events {
+            let JobEventKind::Captioned { node, filename, caption } = &event.kind else { continue };
+            for output in outputs.iter_mut().filter(|o| o.node == *node && o.filename == *filename) {
+                output.caption = Some(caption.clone());
+            }
+        }
+        outputs
+    }
+
+    pub fn state(&self) -> JobState {
+        let mut state = JobState::Submitted;
+        for event in &self.events {
+            state = match event.kind {
+                JobEventKind::Failed { .. } => return JobState::Failed,
+                JobEventKind::Completed => JobState::Completed,
+                JobEventKind::SentToBackend { .. } | JobEventKind::Imported { .. } if state == JobState::Submitted => JobState::Queued,
+                JobEventKind::ExecutionStarted
+                | JobEventKind::NodeStarted { .. }
+                | JobEventKind::NodeCached { .. }
+                | JobEventKind::NodeFinished { .. } if state != JobState::Completed => JobState::Running,
+                _ => state,
+            };
+        }
+        state
+    }
+
+    /// When ComfyUI accepted the job's prompt, or when the job was imported
+    /// from its history.
+    pub fn sent_at_ms(&self) -> Option<u64> {
+        self.events.iter().find_map(|e| match e.kind {
+            JobEventKind::SentToBackend { .. } | JobEventKind::Imported { .. } => Some(e.at_ms),
+            _ => None,
+        })
+    }
+
+    /// Whether the job has failed, in the proxy or in ComfyUI.
+    pub fn is_failed(&self) -> bool {
+        self.events.iter().any(|e| matches!(e.kind, JobEventKind::Failed { .. }))
+    }
+
+    /// Whether the job has finished, successfully or not.
+    pub fn is_finished(&self) -> bool {
+        self.is_failed() || self.events.iter().any(|e| matches!(e.kind, JobEventKind::Completed))
+    }
+
+    /// Fold in another copy of this job, saved by another replica or by an
+    /// earlier write: the timeline becomes the union of both, and fields
+    /// only one copy has set are kept.
+    pub fn merge(&mut self, other: Job) {
+        for event in other.events {
+            if !self.events.contains(&event) {
+                self.events.push(event);
+            }
+        }
+        self.events.sort_by_key(|e| e.at_ms);
+        self.prompt_id = self.prompt_id.take().or(other.prompt_id);
+        self.workflow = self.workflow.take().or(other.workflow);
+        self.tenant = self.tenant.take().or(other.tenant);
+        self.client = self.client.take().or(other.client);
+        self.key_id = self.key_id.take().or(other.key_id);
+        self.request = self.request.take().or(other.request);
+        self.estimated_cost = self.estimated_cost.or(other.estimated_cost);
+        self.actual_cost = self.actual_cost.or(other.actual_cost);
+        self.expected_outputs = self.expected_outputs.max(other.expected_outputs);
+        self.megapixel_steps = self.megapixel_steps.max(other.megapixel_steps);
+        for (node, class) in other.node_classes {
+            self.node_classes.entry(node).or_insert(class);
+        }
+        self.history_synced |= other.history_synced;
+    }
+
+    /// Whether a caller scoped to `tenant` may see this job. Unscoped callers
+    /// see every job.
+    pub fn visible_to(&self, tenant: Option<&str>) -> bool {
+        tenant.is_none() || self.tenant.as_deref() == tenant
+    }
+}
+
+/// What [`JobStore::refresh`] picked up.
+#[derive(Debug, Default)]
+pub struct Refreshed {
+    /// How many jobs were read from the backend.
+    pub read: usize,
+    /// Jobs another replica finished syncing (see [`Job::history_synced`]),
+    /// whose usage this replica hasn't counted.
+    pub synced_elsewhere: Vec<Job>,
+}
+
+#[derive(Default)]
+pub struct JobStore {
+    jobs: HashMap<String, Job>,
+    /// Where state changes are announced.
+    events: Option<EventBus>,
+    /// Hooks run on state changes made through this store.
+    hooks: Option<Arc<LifecycleHooks>>,
+    /// Last state announced per job, once there's something to announce to.
+    announced: Mutex<HashMap<String, JobState>>,
+    /// Where changed jobs are written; in memory only without one.
+    backend: Option<Arc<dyn JobBackend>>,
+    /// Queue of writes to the backend, started with the first write.
+    writer: OnceLock<mpsc::UnboundedSender<Write>>,
+    /// Start of the last [`JobStore::refresh`].
+    synced_at_ms: u64,
+}
+
+enum Write {
+    Job(Box<Job>),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Write jobs to `backend` as they arrive, in order, keeping only the
+/// latest copy of a job that changed again before it was written.
+async fn write_jobs(backend: Arc<dyn JobBackend>, mut writes: mpsc::UnboundedReceiver<Write>) {
+    while let Some(first) = writes.recv().await {
+        let mut jobs: Vec<Job> = Vec::new();
+        let mut flushed = Vec::new();
+        let mut next = Some(first);
+        while let Some(write) = next {
+            match write {
+                Write::Job(job) => {
+                    jobs.retain(|j| j.id != job.id);
+                    jobs.push(*job);
+                }
+                Write::Flush(done) => flushed.push(done),
+            }
+            next = writes.try_recv().ok();
+        }
+        for job in &jobs {
+            if let Err(e) = backend.save(job).await {
+                tracing::error!("{}", e);
+            }
+        }
+        for done in flushed {
+            done.send(()).ok();
+        }
+    }
+}
+
+/// Handle for taking leases on the store's backend without holding the
+/// store's lock.
+#[derive(Clone)]
+pub struct Leases(Option<Arc<dyn JobBackend>>);
+
+impl Leases {
+    /// Take or renew the lease on `name` for `owner` (see
+    /// [`JobBackend::claim`]). Without a backend nothing is shared, so the
+    /// caller always holds it; if the backend can't be reached, it doesn't.
+    pub async fn claim(&self, name: &str, owner: &str, ttl_ms: u64) -> bool {
+        let Some(backend) = &self.0 else { return true };
+        backend.claim(name, owner, ttl_ms).await.unwrap_or_else(|e| {
+            tracing::warn!("{}", e);
+            false
+        })
+    }
+
+    /// Run `work` while holding the lease on `name`, renewing it every third
+    /// of `ttl_ms` so work that runs long doesn't outlive it. None if
+    /// another owner holds it, or if a renewal fails midway, in which case
+    /// `work` is dropped where it stands.
+    pub async fn hold<T>(&self, name: &str, owner: &str, ttl_ms: u64, work: impl Future<Output = T>) -> Option<T> {
+        if !self.claim(name, owner, ttl_ms).await {
+            return None;
+        }
+        let renew = async {
+            loop {
+                tokio::time::sleep(std::time::Duration::from_millis((ttl_ms / 3).max(1))).await;
+                if !self.claim(name, owner, ttl_ms).await {
+                    tracing::warn!("Lost lease {}; stopping its work", name);
+                    return;
+                }
+            }
+        };
+        tokio::select! {
+            output = work => Some(output),
+            _ = renew => None,
+        }
+    }
+}
+
+/// The instance holding `job`'s prompt, if it's held and unsent.
+fn unsent_holder(job: &Job) -> Option<&str> {
+    if job.prompt_id.is_some() || job.is_finished() {
+        return None;
+    }
+    job.events.iter().rev().find_map(|e| match &e.kind {
+        JobEventKind::Held { instance } => Some(instance.as_str()),
+        _ => None,
+    })
+}
+
+/// How far back each refresh looks before the previous one, to cover clock
+/// skew between replicas. Re-reading a job is harmless.
+const SYNC_OVERLAP_MS: u64 = 5000;
+
+impl JobStore {
+    pub fn new() -> Self {
+        JobStore::default()
+    }
+
+    /// A store backed by Redis when `JOBS_REDIS_URL` is set, else by
+    /// `JOBS_DB`, else in-memory only. A backend that can't be opened is
+    /// logged and skipped.
+    pub fn from_config(config: &Config) -> Self {
+        let opened = match (config.jobs_redis_url.as_deref(), config.jobs_db.as_deref()) {
+            (Some(url), _) => JobStore::open_redis(url, &config.jobs_redis_prefix),
+            (None, Some(path)) => JobStore::open(path),
+            (None, None) => return JobStore::new(),
+        };
+        match opened {
+            Ok(store) => {
+                let name = store.backend.as_ref().map(|b| b.name()).unwrap_or_default();
+                tracing::info!("Loaded {} jobs from {}", store.jobs.len(), name);
+                store
+            }
+            Err(e) => {
+                tracing::error!("Keeping jobs in memory only: {}", e);
+                JobStore::new()
+            }
+        }
+    }
+
+    /// A store that writes through to `backend`, loaded with the jobs
+    /// already in it. Writes happen in a background task, started by the
+    /// first one, so they need a Tokio runtime.
+    pub fn with_backend(backend: Arc<dyn JobBackend>) -> Result<Self, String> {
+        let started = now_ms();
+        let jobs = backend.load()?.into_iter().map(|job| (job.id.clone(), job)).collect();
+        Ok(JobStore { jobs, backend: Some(backend), synced_at_ms: started, ..JobStore::default() })
+    }
+
+    /// A store persisted to the SQLite database at `path`. Requires the
+    /// `sqlite` feature.
+    #[cfg(feature = "sqlite")]
+    pub fn open(path: &str) -> Result<Self, String> {
+        JobStore::with_backend(Arc::new(crate::jobs::db::JobDb::open(path)?))
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    pub fn open(path: &str) -> Result<Self, String> {
+        Err(format!("JOBS_DB={} requires building with the `sqlite` feature", path))
+    }
+
+    /// A store shared through the Redis server at `url`, under keys starting
+    /// with `prefix`. Requires the `redis` feature.
+    #[cfg(feature = "redis")]
+    pub fn open_redis(url: &str, prefix: &str) -> Result<Self, String> {
+        JobStore::with_backend(Arc::new(crate::jobs::redis::RedisJobs::open(url, prefix)?))
+    }
+
+    #[cfg(not(feature = "redis"))]
+    pub fn open_redis(url: &str, _prefix: &str) -> Result<Self, String> {
+        Err(format!("JOBS_REDIS_URL={} requires building with the `redis` feature", url))
+    }
+
+    /// Whether jobs are written to a backend other processes can read.
+    pub fn has_backend(&self) -> bool {
+        self.backend.is_some()
+    }
+
+    /// Leases on the backend, for work only one replica should do.
+    pub fn leases(&self) -> Leases {
+        Leases(self.backend.clone())
+    }
+
+    /// Pick up jobs other processes saved to the backend since the last
+    /// refresh, merging them into local copies (see [`Job::merge`]), so
+    /// changes not written yet aren't lost. The backend is read without
+    /// holding `store`'s lock.
+    pub async fn refresh(store: &RwLock<JobStore>) -> Refreshed {
+        let (backend, since) = {
+            let store = store.read().await;
+            let Some(backend) = store.backend.clone() else { return Refreshed::default() };
+            (backend, store.synced_at_ms.saturating_sub(SYNC_OVERLAP_MS))
+        };
+        let started = now_ms();
+        let changed = match backend.changed_since(since).await {
+            Ok(changed) => changed,
+            Err(e) => {
+                tracing::warn!("Failed to sync jobs from {}: {}", backend.name(), e);
+                return Refreshed::default();
+            }
+        };
+        let mut store = store.write().await;
+        store.synced_at_ms = store.synced_at_ms.max(started);
+        let mut refreshed = Refreshed { read: changed.len(), synced_elsewhere: Vec::new() };
+        for job in changed {
+            let id = job.id.clone();
+            let was_synced = store.jobs.get(&id).is_some_and(|j| j.history_synced);
+            match store.jobs.get_mut(&id) {
+                Some(local) => local.merge(job),
+                None => {
+                    store.jobs.insert(id.clone(), job);
+                }
+            }
+            if let Some(job) = store.jobs.get(&id).filter(|j| j.history_synced && !was_synced) {
+                refreshed.synced_elsewhere.push(job.clone());
+            }
+            // The replica that made the change ran its hooks.
+            store.announce(&id, false);
+        }
+        refreshed
+    }
+
+    /// Resolves once every write queued so far has reached the backend.
+    /// Doesn't borrow the store, so the caller can release its lock first.
+    pub fn flush(&self) -> impl Future<Output = ()> + 'static {
+        let (done, flushed) = oneshot::channel();
+        if let Some(writer) = self.writer.get() {
+            writer.send(Write::Flush(done)).ok();
+        } else {
+            done.send(()).ok();
+        }
+        async move {
+            flushed.await.ok();
+        }
+    }
+
+    /// Announce job state changes on `bus` from now on. Jobs already in the
+    /// store count as announced.
+    pub fn set_event_bus(&mut self, bus: EventBus) {
+        self.mark_announced();
+        self.events = Some(bus);
+    }
+
+    /// Run `hooks` on state changes made through this store from now on:
+    /// `job_queued` once ComfyUI accepts a job's prompt, and once a job
+    /// finishes however it ends (completed, failed, cancelled, removed from
+    /// the queue), `output_saved` for each of its outputs and then
+    /// `job_completed`. Outputs recorded after that get `output_saved` as
+    /// they come. Changes picked up from other replicas don't run hooks:
+    /// the replica that made them did.
+    pub fn set_hooks(&mut self, hooks: Arc<LifecycleHooks>) {
+        self.mark_announced();
+        self.hooks = Some(hooks);
+    }
+
+    /// Count the states of jobs already in the store as announced.
+    fn mark_announced(&mut self) {
+        let announced = self.announced.get_mut().unwrap_or_else(|e| e.into_inner());
+        for job in self.jobs.values() {
+            announced.entry(job.id.clone()).or_insert_with(|| job.state());
+        }
+    }
+
+    /// Write a job's current state to the backend, if there is one, and
+    /// announce it if it changed. Called by the store's own mutators;
+    /// callers that change a job through [`JobStore::get_mut`] call it
+    /// themselves.
+    pub fn save(&self, id: &str) {
+        self.persist(id);
+        self.announce(id, true);
+    }
+
+    /// Announce a job's state if it differs from the last one announced,
+    /// running the hooks for the change if it was made here (`local`).
+    fn announce(&self, id: &str, local: bool) {
+        if self.events.is_none() && self.hooks.is_none() {
+            return;
+        }
+        let Some(job) = self.jobs.get(id) else { return };
+        let state = job.state();
+        let previous = self.announced.lock().unwrap_or_else(|e| e.into_inner()).insert(id.to_string(), state);
+        if previous == Some(state) {
+            return;
+        }
+        if let Some(bus) = &self.events {
+            bus.publish(ProxyEvent::now(
+                ProxyEventKind::JobState {
+                    job_id: job.id.clone(),
+                    prompt_id: job.prompt_id.clone(),
+                    workflow: job.workflow.clone(),
+                    state,
+                    previous,
+                },
+                Audience::Tenant(job.tenant.clone()),
+            ));
+        }
+        let (Some(hooks), true) = (&self.hooks, local) else { return };
+        // Jobs imported from history never went through the proxy.
+        let imported = job.events.iter().any(|e| matches!(e.kind, JobEventKind::Imported { .. }));
+        let Some(previous) = previous.or((!imported).then_some(JobState::Submitted)) else { return };
+        if previous == JobState::Submitted && job.prompt_id.is_some() {
+            hooks.job_queued(job);
+        }
+        if state.is_terminal() && !previous.is_terminal() {
+            for output in job.outputs() {
+                hooks.output_saved(job, &output);
+            }
+            hooks.job_completed(job);
+        }
+    }
+
+    /// Queue the job to be written to the backend, if there is one. The
+    /// write happens in the background, not under the caller's lock.
+    fn persist(&self, id: &str) {
+        if let (Some(backend), Some(job)) = (&self.backend, self.jobs.get(id)) {
+            let writer = self.writer.get_or_init(|| {
+                let (writer, writes) = mpsc::unbounded_channel();
+                tokio::spawn(write_jobs(backend.clone(), writes));
+                writer
+            });
+            writer.send(Write::Job(Box::new(job.clone()))).ok();
+        }
+    }
+
+    /// Register a new job and record its `submitted` event. Returns the job id.
+    pub fn create(&mut self, workflow: Option<String>) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        let job = Job {
+            id: id.clone(),
+            prompt_id: None,
+            workflow: workflow.clone(),
+            created_at_ms: now_ms(),
+            events: vec![JobEvent::now(JobEventKind::Submitted { workflow })],
+            expected_outputs: 0,
+            megapixel_steps: 0.0,
+            estimated_cost: None,
+            actual_cost: None,
+            node_classes: HashMap::new(),
+            history_synced: false,
+            key_id: None,
+            tenant: None,
+            client: None,
+            request: None,
+            split_grid: None,
+        };
+        self.jobs.insert(id.clone(), job);
+        // Announced on the caller's first save, once owner details are set.
+        self.persist(&id);
+        id
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Job> {
+        self.jobs.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut Job> {
+        self.jobs.get_mut(id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.values()
+    }
+
+    /// Remember the node classes and expected output count of the graph
+    /// submitted for a job.
+    pub fn set_node_classes(&mut self, id: &str, graph: &Value) {
+        if let (Some(job), Some(nodes)) = (self.jobs.get_mut(id), graph.as_object()) {
+            job.expected_outputs = expected_outputs(graph);
+            job.megapixel_steps = megapixel_steps(graph);
+            // Each split image adds its cells after the images themselves.
+            if let Some(split) = job.split_grid {
+                job.expected_outputs += job.expected_outputs * split.tiles() as u64;
+            }
+            job.node_classes = nodes.iter()
+                .filter_map(|(nid, node)| {
+                    node.get("class_type").and_then(|v| v.as_str()).map(|ct| (nid.clone(), ct.to_string()))
+                })
+                .collect();
+            self.save(id);
+        }
+    }
+
+    /// Create a finished job from a ComfyUI history entry, e.g. one produced
+    /// before the proxy was deployed. The timeline and outputs come from the
+    /// entry, and the submitted graph (`prompt[2]`) provides node classes.
+    /// Returns the new job id, or `None` if a job already has `prompt_id`.
+    pub fn import_history_entry(&mut self, prompt_id: &str, entry: &Value, workflow: Option<String>, tenant: Option<String>) -> Option<String> {
+        if self.find_by_prompt_id(prompt_id).is_some() {
+            return None;
+        }
+        let mut events = events_from_history_entry(entry);
+        let created_at_ms = events.iter().map(|e| e.at_ms).filter(|at| *at > 0).min().unwrap_or_else(now_ms);
+        events.insert(0, JobEvent::at(created_at_ms, JobEventKind::Imported { prompt_id: prompt_id.to_string() }));
+        let id = uuid::Uuid::new_v4().to_string();
+        self.jobs.insert(id.clone(), Job {
+            id: id.clone(),
+            prompt_id: Some(prompt_id.to_string()),
+            workflow,
+            created_at_ms,
+            events,
+            expected_outputs: 0,
+            megapixel_steps: 0.0,
+            estimated_cost: None,
+            actual_cost: None,
+            node_classes: HashMap::new(),
+            history_synced: true,
+            key_id: None,
+            tenant,
+            client: None,
+            request: None,
+            split_grid: None,
+        });
+        if let Some(graph) = entry.get("prompt").and_then(|p| p.get(2)) {
+            self.set_node_classes(&id, graph);
+        }
+        if let Some(job) = self.jobs.get_mut(&id) {
+            job.expected_outputs = job.expected_outputs.max(job.saved_outputs());
+        }
+        self.save(&id);
+        Some(id)
+    }
+
+    /// Look up a job by the ComfyUI prompt id it was submitted as.
+    pub fn find_by_prompt_id(&self, prompt_id: &str) -> Option<&Job> {
+        self.jobs.values().find(|j| j.prompt_id.as_deref() == Some(prompt_id))
+    }
+
+    /// The proxy instances holding unsent prompts for fair scheduling.
+    pub fn holders(&self) -> HashSet<String> {
+        self.jobs.values().filter_map(unsent_holder).map(String::from).collect()
+    }
+
+    /// Mark failed the jobs whose prompts `instances` were holding and never
+    /// sent, e.g. because they stopped: held prompts only live in memory.
+    /// Returns how many jobs were failed.
+    pub fn fail_held(&mut self, instances: &[&str], error: &str) -> usize {
+        let lost: Vec<String> = self.jobs.values()
+            .filter(|j| unsent_holder(j).is_some_and(|holder| instances.contains(&holder)))
+            .map(|j| j.id.clone())
+            .collect();
+        for id in &lost {
+            self.record(id, JobEventKind::Failed { node: None, error: error.to_string() });
+        }
+        lost.len()
+    }
+
+    /// Append an event to a job's timeline. Unknown ids are ignored.
+    pub fn record(&mut self, id: &str, kind: JobEventKind) {
+        if let Some(job) = self.jobs.get_mut(id) {
+            if let JobEventKind::SentToBackend { prompt_id } = &kind {
+                job.prompt_id = Some(prompt_id.clone());
+            }
+            let late_output = job.is_finished() && matches!(kind, JobEventKind::OutputSaved { .. });
+            job.events.push(JobEvent::now(kind));
+            self.save(id);
+            if let (Some(hooks), Some(job), true) = (&self.hooks, self.jobs.get(id), late_output) {
+                if let Some(output) = job.outputs().last() {
+                    hooks.output_saved(job, output);
+                }
+            }
+        }
+    }
+
+    /// Merge backend-derived events (e.g. from history) into a job's timeline,
+    /// keeping the timeline ordered by timestamp.
+    pub fn merge_events(&mut self, id: &str, events: Vec<JobEvent>) {
+        if let Some(job) = self.jobs.get_mut(id) {
+            job.events.extend(events);
+            job.events.sort_by_key(|e| e.at_ms);
+            self.save(id);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Per-node execution timing derived from job timelines.
+//!
+//! ComfyUI announces each node as it starts executing (`executing` messages on
+//! its websocket), so a node's duration is the gap until the next node starts,
+//! it reports `executed`, or the prompt finishes — whichever comes first.
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::jobs::events::JobEventKind;
+use crate::jobs::store::Job;
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct NodeTiming {
+    pub node: String,
+    pub class_type: Option<String>,
+    pub started_at_ms: u64,
+    pub duration_ms: u64,
+}
+
+/// Aggregate timing for one node class (or one node id within a workflow).
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct NodeStat {
+    pub key: String,
+    pub class_type: Option<String>,
+    pub runs: u64,
+    pub total_ms: u64,
+    pub avg_ms: u64,
+    pub max_ms: u64,
+}
+
+pub fn is_completed(job: &Job) -> bool {
+    job.events.iter().any(|e| e.kind == JobEventKind::Completed)
+}
+
+/// Time from execution start to the terminal event, or 0 if the job hasn't
+/// finished (or never reported a start).
+pub fn execution_ms(job: &Job) -> u64 {
+    let start = job.events.iter().find(|e| e.kind == JobEventKind::ExecutionStarted).map(|e| e.at_ms);
+    let end = job.events.iter()
+        .filter(|e| matches!(e.kind, JobEventKind::Completed | JobEventKind::Failed { .. }))
+        .map(|e| e.at_ms)
+        .max();
+    match (start, end) {
+        (Some(start), Some(end)) => end.saturating_sub(start),
+        _ => 0,
+    }
+}
+
+/// Compute per-node durations for a job from its `node_started`,
+/// `node_finished`, and terminal events.
+pub fn node_timings(job: &Job) -> Vec<NodeTiming> {
+    let mut events: Vec<_> = job.events.iter().collect();
+    events.sort_by_key(|e| e.at_ms);
+
+    let starts: Vec<(usize, &str, u64)> = events.iter().enumerate()
+        .filter_map(|(i, e)| match &e.kind {
+            JobEventKind::NodeStarted { node } => Some((i, node.as_str(), e.at_ms)),
+            _ => None,
+        })
+        .collect();
+
+    let mut out = Vec::new();
+    for (n, (idx, node, started)) in starts.iter().enumerate() {
+        let next_start = starts.get(n + 1).map(|(_, _, at)| *at);
+        let finished = events[idx + 1..].iter().find_map(|e| match &e.kind {
+            JobEventKind::NodeFinished { node: f } if f == node => Some(e.at_ms),
+            _ => None,
+        });
+        let terminal = events[idx + 1..].iter().find_map(|e| match &e.kind {
+            JobEventKind::Completed | JobEventKind::Failed { .. } => Some(e.at_ms),
+            _ => None,
+        });
+        let end = [next_start, finished, terminal].into_iter().flatten().min();
+        if let Some(end) = end {
+            out.push(NodeTiming {
+                node: node.to_string(),
+                class_type: job.node_classes.get(*node).cloned(),
+                started_at_ms: *started,
+                duration_ms: end.saturating_sub(*started),
+            });
+        }
+    }
+    out
+}
+
+/// Aggregate node timings over completed jobs.
+///
+/// Without a workflow filter, stats are grouped by `class_type`; with one,
+/// they are grouped by node id so individual nodes of that workflow can be
+/// compared. Results are sorted by total time, largest first.
+pub fn aggregate_node_stats<'a>(jobs: impl Iterator<Item = &'a Job>, workflow: Option<&str>) -> Vec<NodeStat> {
+    let mut acc: BTreeMap<String, NodeStat> = BTreeMap::new();
+    for job in jobs.filter(|j| is_completed(j)) {
+        if let Some(wf) = workflow {
+            if job.workflow.as_deref() != Some(wf) { continue; }
+        }
+        for t in node_timings(job) {
+            let key = if workflow.is_some() {
+                t.node.clone()
+            } else {
+                t.class_type.clone().unwrap_or_else(|| format!("node:{}", t.node))
+            };
+            let stat = acc.entry(key.clone()).or_insert_with(|| NodeStat {
+                key,
+                class_type: t.class_type.clone(),
+                runs: 0,
+                total_ms: 0,
+                avg_ms: 0,
+                max_ms: 0,
+            });
+            stat.runs += 1;
+            stat.total_ms += t.duration_ms;
+            stat.max_ms = stat.max_ms.max(t.duration_ms);
+        }
+    }
+    let mut stats: Vec<NodeStat> = acc.into_values()
+        .map(|mut s| { s.avg_ms = s.total_ms / s.runs.max(1); s })
+        .collect();
+    stats.sort_by_key(|s| std::cmp::Reverse(s.total_ms));
+    stats
+}
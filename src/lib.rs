@@ -5,6 +5,12 @@
 //! - `comfyui`: Thin client for ComfyUI REST endpoints.
 //! - `prompt`: Prompt construction helpers with `{{placeholder}}` replacement.
 //! - `workflow`: Loading/saving named workflows in `prompts/`.
+//! - `auth`: API keys and per-key request policies.
+//! - `jobs`: Proxy-side job records and their event timelines.
+//! - `admin`: Backup and restore of proxy state, and maintenance mode.
+//! - `events`: Proxy-level event bus behind `GET /events`.
+//! - `metrics`: Prometheus counters behind `GET /metrics`.
+//! - `reporting`: Optional Sentry reports of panics and 5xx errors.
 //! - `utils`: Background helpers like the static drive poller.
 //! - `config`: Env-driven configuration loader.
 //! - `error`: Common error type and alias.
@@ -15,6 +21,12 @@ pub mod api;
 pub mod comfyui;
 pub mod prompt;
 pub mod workflow;
+pub mod jobs;
+pub mod auth;
+pub mod admin;
+pub mod events;
+pub mod metrics;
+pub mod reporting;
 pub mod utils;
 pub mod config;
 pub mod error;
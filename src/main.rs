@@ -1,61 +1,133 @@
-
-use axum::{
-    routing::{get, post},
-    Router,
-};
 use std::net::SocketAddr;
-use std::sync::Arc;
 use tower_http::cors::CorsLayer;
-use tokio::sync::RwLock;
 
 use comfyui_api_proxy::{
-    comfyui, 
+    comfyui,
     api,
     config,
+    reporting,
     utils,
-    prompt,
-    workflow,
 };
 
+/// Startup preflight attempts while ComfyUI is unreachable.
+const PREFLIGHT_ATTEMPTS: u32 = 6;
+const PREFLIGHT_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(10);
+const TRASH_PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
 #[tokio::main]
 async fn main() {
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
-
     // Load configuration
     config::Config::dotenv_load();
     let config = config::Config::new().expect("Failed to load configuration");
+
+    // Initialize tracing
+    utils::logging::init_logging(&config, "comfyui-api-proxy", utils::logging::Console::Stdout, config.trace_prompt_ops)
+        .expect("Failed to initialize logging");
     config::Config::print_env_vars();
+    let _reporting = reporting::init(&config);
     // Create ComfyUI client
-    let comfyui_client = comfyui::client::ComfyUIClient::new(config.comfyui_url.clone());
-    let static_drive_poller = utils::static_drive_poller::StaticDrivePoller::new(config.static_drive_path.clone());
-
-    tokio::spawn(async move {
-        static_drive_poller.start_polling().await;
-    });
-    let state = Arc::new(api::routes::AppState {
-        prompt_constructor: RwLock::new(prompt::constructor::PromptConstructor::new()),
-        comfyui_client,
-        workflow_manager: RwLock::new(workflow::manager::WorkflowManager::new()),
-        static_drive_poller: Arc::new(utils::static_drive_poller::StaticDrivePoller::new(config.static_drive_path.clone())),
-        prompts_dir: config.prompts_dir.clone(),
-    });
+    let comfyui_client = comfyui::client::ComfyUIClient::from_config(&config);
+    let state = api::routes::build_state(&config, comfyui_client);
+    {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(state.static_drive_poller.interval());
+            loop {
+                interval.tick().await;
+                api::handlers::poll_static_drive(&state).await;
+            }
+        });
+    }
+    {
+        let state = state.clone();
+        tokio::spawn(async move {
+            // Node timings and prompt completion, as ComfyUI reports them.
+            api::handlers::follow_progress(&state).await;
+        });
+    }
+    if let Some(sync) = state.workflow_sync.clone() {
+        sync.spawn_schedule(std::time::Duration::from_secs(config.workflows_sync_interval_secs));
+    }
+    if config.jobs_sync_secs > 0 && state.job_store.read().await.has_backend() {
+        let state = state.clone();
+        let period = std::time::Duration::from_secs(config.jobs_sync_secs);
+        tokio::spawn(async move {
+            // Jobs queued or finished through other replicas sharing the backend.
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                api::handlers::refresh_jobs(&state).await;
+            }
+        });
+    }
+    if state.harvester.is_some() {
+        let state = state.clone();
+        let period = std::time::Duration::from_secs(config.static_drive_poll_secs.max(1));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                api::handlers::harvest_outputs(&state).await;
+            }
+        });
+    }
+    if config.fair_queue_depth.is_some() || config.workflow_limits.is_some() {
+        let state = state.clone();
+        tokio::spawn(async move {
+            // Held prompts go out as ComfyUI's queue drains and workflow limits allow.
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                api::handlers::dispatch_held_prompts(&state).await;
+            }
+        });
+    }
+    if state.output_trash.is_some() {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TRASH_PURGE_INTERVAL);
+            loop {
+                interval.tick().await;
+                api::handlers::purge_trash(&state);
+            }
+        });
+    }
+    if config.backend_health_interval_secs > 0 {
+        let state = state.clone();
+        let period = std::time::Duration::from_secs(config.backend_health_interval_secs);
+        tokio::spawn(async move {
+            // Each detection announces reachability changes on `/events`;
+            // with several backends, queue depths are refreshed too.
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                state.backends.infos(true).await;
+                if state.backends.len() > 1 {
+                    state.backends.check_health().await;
+                }
+            }
+        });
+    }
+    if config.workflow_preflight {
+        let state = state.clone();
+        tokio::spawn(async move {
+            // ComfyUI often starts alongside the proxy; give it a minute.
+            for attempt in 1..=PREFLIGHT_ATTEMPTS {
+                match api::handlers::run_preflight(&state, false).await {
+                    Ok(_) => return,
+                    Err(e) if attempt < PREFLIGHT_ATTEMPTS => {
+                        tracing::debug!("Preflight attempt {} failed: {}", attempt, e);
+                        tokio::time::sleep(PREFLIGHT_RETRY_DELAY).await;
+                    }
+                    Err(e) => tracing::warn!("Workflow preflight skipped: {}", e),
+                }
+            }
+        });
+    }
 
     // Build our application with a route
-    let app = Router::new()
-        .route("/", get(|| async { "ComfyUI API Proxy" }))
-        .route("/queue_prompt", post(api::handlers::queue_prompt))
-        .route("/get_image", get(api::handlers::get_image))
-        .route("/get_history", get(api::handlers::get_history))
-        .route("/history", get(api::handlers::history_friendly))
-        .route("/add_workflow", post(api::handlers::add_workflow))
-        .route("/get_node_info", get(api::handlers::get_node_info))
-        .route("/construct_prompt", post(api::handlers::construct_prompt))
-        .route("/models", get(api::handlers::models_categories))
-        .route("/models/checkpoints", get(api::handlers::models_checkpoints))
-        .route("/models/:category", get(api::handlers::models_in_category))
-        .layer(CorsLayer::permissive())
-        .with_state(state);
+    let app = api::routes::build_router(state)
+        .layer(CorsLayer::permissive());
 
     // Run our application with safe parsing
     let host_str = config.api_host.clone();
@@ -0,0 +1,157 @@
+//! Counters behind `GET /metrics`, in the Prometheus text format.
+//!
+//! Request counts and latencies are per matched route (`/jobs/:id`, not the
+//! raw path), so labels stay bounded. Upstream calls are labelled by the
+//! first segment of the ComfyUI path (`/history/<id>` counts as `/history`);
+//! an upstream error is a request that couldn't be sent or got a 5xx.
+//! Counters are per process and start at zero on restart.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds of the request latency buckets, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Route label for requests no route matched.
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+struct Histogram {
+    /// Observations at or under each of [`LATENCY_BUCKETS`].
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram { buckets: vec![0; LATENCY_BUCKETS.len()], sum: 0.0, count: 0 }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct Upstream {
+    requests: u64,
+    errors: u64,
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    /// By (method, route, status).
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    /// By (method, route).
+    latencies: Mutex<BTreeMap<(String, String), Histogram>>,
+    prompts_queued: AtomicU64,
+    prompts_failed: AtomicU64,
+    /// By where the image came from.
+    images_served: Mutex<BTreeMap<&'static str, u64>>,
+    /// By ComfyUI endpoint.
+    upstream: Mutex<BTreeMap<String, Upstream>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// Count a handled request and its latency.
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        *lock(&self.requests).entry((method.to_string(), route.to_string(), status)).or_insert(0) += 1;
+        lock(&self.latencies).entry((method.to_string(), route.to_string())).or_default().observe(elapsed.as_secs_f64());
+    }
+
+    /// A prompt was accepted by ComfyUI.
+    pub fn prompt_queued(&self) {
+        self.prompts_queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A job failed, either before ComfyUI accepted it or while running.
+    pub fn prompt_failed(&self) {
+        self.prompts_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// An image was sent to a client; `source` is `comfyui`, `job_output`, or `static`.
+    pub fn image_served(&self, source: &'static str) {
+        *lock(&self.images_served).entry(source).or_insert(0) += 1;
+    }
+
+    /// Count a request to ComfyUI at `path`, and whether it failed.
+    pub fn observe_upstream(&self, path: &str, failed: bool) {
+        let mut upstream = lock(&self.upstream);
+        let entry = upstream.entry(upstream_endpoint(path)).or_default();
+        entry.requests += 1;
+        if failed {
+            entry.errors += 1;
+        }
+    }
+
+    /// Everything, in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        header(&mut out, "comfyui_proxy_http_requests_total", "counter", "HTTP requests handled, by route and status.");
+        for ((method, route, status), count) in lock(&self.requests).iter() {
+            let _ = writeln!(out, "comfyui_proxy_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}", escape(method), escape(route), status, count);
+        }
+        header(&mut out, "comfyui_proxy_http_request_duration_seconds", "histogram", "Time to respond to HTTP requests, by route.");
+        for ((method, route), histogram) in lock(&self.latencies).iter() {
+            let labels = format!("method=\"{}\",route=\"{}\"", escape(method), escape(route));
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+                let _ = writeln!(out, "comfyui_proxy_http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, bound, count);
+            }
+            let _ = writeln!(out, "comfyui_proxy_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, histogram.count);
+            let _ = writeln!(out, "comfyui_proxy_http_request_duration_seconds_sum{{{}}} {}", labels, histogram.sum);
+            let _ = writeln!(out, "comfyui_proxy_http_request_duration_seconds_count{{{}}} {}", labels, histogram.count);
+        }
+        header(&mut out, "comfyui_proxy_prompts_queued_total", "counter", "Prompts accepted by ComfyUI.");
+        let _ = writeln!(out, "comfyui_proxy_prompts_queued_total {}", self.prompts_queued.load(Ordering::Relaxed));
+        header(&mut out, "comfyui_proxy_prompts_failed_total", "counter", "Jobs that failed, at submission or while running.");
+        let _ = writeln!(out, "comfyui_proxy_prompts_failed_total {}", self.prompts_failed.load(Ordering::Relaxed));
+        header(&mut out, "comfyui_proxy_images_served_total", "counter", "Images sent to clients, by source.");
+        for (source, count) in lock(&self.images_served).iter() {
+            let _ = writeln!(out, "comfyui_proxy_images_served_total{{source=\"{}\"}} {}", source, count);
+        }
+        let upstream = lock(&self.upstream);
+        header(&mut out, "comfyui_proxy_upstream_requests_total", "counter", "Requests to ComfyUI, by endpoint.");
+        for (endpoint, counts) in upstream.iter() {
+            let _ = writeln!(out, "comfyui_proxy_upstream_requests_total{{endpoint=\"{}\"}} {}", escape(endpoint), counts.requests);
+        }
+        header(&mut out, "comfyui_proxy_upstream_errors_total", "counter", "Requests to ComfyUI that failed to send or got a 5xx, by endpoint.");
+        for (endpoint, counts) in upstream.iter() {
+            let _ = writeln!(out, "comfyui_proxy_upstream_errors_total{{endpoint=\"{}\"}} {}", escape(endpoint), counts.errors);
+        }
+        out
+    }
+}
+
+/// `/history/abc` → `/history`.
+fn upstream_endpoint(path: &str) -> String {
+    let first = path.trim_start_matches('/').split('/').next().unwrap_or_default();
+    format!("/{}", first)
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
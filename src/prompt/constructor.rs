@@ -2,15 +2,53 @@
 //!
 //! Given a JSON `template` and an `inputs` object, recursively walks the
 //! template and replaces any string values of the form `{{ key }}` with
-//! `inputs[key]`, returning a constructed JSON value.
+//! `inputs[key]`, returning a constructed JSON value. Placeholders inside
+//! longer strings (`"a photo of {{subject}} in {{style}} style"`) are
+//! interpolated as text instead; see [`interpolate`].
+//!
+//! Templates may inherit from a stored template with `"extends": "<name>"`
+//! (loaded from `<templates_dir>/<name>.json`). The remaining keys are merged
+//! over the base: objects merge recursively, `null` removes a key, and any
+//! other value replaces it. A `"defaults"` object supplies placeholder values
+//! that `inputs` may override, so children can also change placeholder
+//! defaults without touching nodes.
+use serde::Serialize;
 use serde_json::Value;
+use std::collections::BTreeSet;
 use crate::error::{AppResult, AppError};
+use crate::workflow::manager::is_valid_workflow_name;
+
+/// Bound on `extends` chains, so a long chain of distinct templates can't
+/// recurse arbitrarily deep.
+const MAX_EXTENDS_DEPTH: usize = 16;
+
+pub struct PromptConstructor {
+    templates_dir: Option<String>,
+}
 
-pub struct PromptConstructor;
+/// A placeholder a template expects, with its `defaults` value if any.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Placeholder {
+    pub name: String,
+    pub default: Option<Value>,
+    /// No default, so `inputs` must supply it.
+    pub required: bool,
+}
+
+impl Default for PromptConstructor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl PromptConstructor {
     pub fn new() -> Self {
-        PromptConstructor
+        PromptConstructor { templates_dir: None }
+    }
+
+    /// A constructor that resolves `extends` against templates in `dir`.
+    pub fn with_dir(dir: impl Into<String>) -> Self {
+        PromptConstructor { templates_dir: Some(dir.into()) }
     }
 
     /// Construct a prompt by substituting placeholders inside `template`
@@ -19,25 +57,87 @@ impl PromptConstructor {
         self.validate_template(template)?;
         self.validate_inputs(inputs)?;
 
-        let mut constructed = template.clone();
-        self.replace_placeholders(&mut constructed, inputs)?;
+        let mut constructed = self.resolve_extends(template)?;
+        let mut merged_inputs = match constructed.as_object_mut().and_then(|m| m.remove("defaults")) {
+            Some(Value::Object(defaults)) => defaults,
+            _ => serde_json::Map::new(),
+        };
+        if let Some(inputs) = inputs.as_object() {
+            merged_inputs.extend(inputs.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        self.replace_placeholders(&mut constructed, &Value::Object(merged_inputs))?;
 
         Ok(constructed)
     }
 
+    /// Flatten a template's `extends` chain into a single template.
+    pub fn resolve_extends(&self, template: &Value) -> AppResult<Value> {
+        self.resolve_extends_inner(template, &mut Vec::new())
+    }
+
+    fn resolve_extends_inner(&self, template: &Value, chain: &mut Vec<String>) -> AppResult<Value> {
+        let Some(name) = template.get("extends") else { return Ok(template.clone()) };
+        let name = name.as_str()
+            .ok_or_else(|| AppError::PromptConstruction("'extends' must be a template name".to_string()))?;
+        if chain.iter().any(|n| n == name) {
+            chain.push(name.to_string());
+            return Err(AppError::PromptConstruction(format!("Template inheritance cycle: {}", chain.join(" -> "))));
+        }
+        if chain.len() >= MAX_EXTENDS_DEPTH {
+            chain.push(name.to_string());
+            return Err(AppError::PromptConstruction(format!("Template inheritance deeper than {} levels: {}", MAX_EXTENDS_DEPTH, chain.join(" -> "))));
+        }
+        chain.push(name.to_string());
+        let mut resolved = self.resolve_extends_inner(&self.load_template(name)?, chain)?;
+        let mut overrides = template.clone();
+        if let Some(map) = overrides.as_object_mut() {
+            map.remove("extends");
+        }
+        merge_overrides(&mut resolved, &overrides);
+        Ok(resolved)
+    }
+
+    /// The placeholders `template` (with its `extends` chain) substitutes,
+    /// sorted by name.
+    pub fn extract_placeholders(&self, template: &Value) -> AppResult<Vec<Placeholder>> {
+        let mut resolved = self.resolve_extends(template)?;
+        let defaults = resolved.as_object_mut().and_then(|m| m.remove("defaults")).unwrap_or(Value::Null);
+        let mut names = BTreeSet::new();
+        collect_placeholders(&resolved, &mut names);
+        Ok(names.into_iter()
+            .map(|name| {
+                let default = defaults.get(&name).cloned();
+                Placeholder { required: default.is_none(), name, default }
+            })
+            .collect())
+    }
+
+    fn load_template(&self, name: &str) -> AppResult<Value> {
+        let dir = self.templates_dir.as_deref()
+            .ok_or_else(|| AppError::PromptConstruction(format!("Cannot resolve 'extends: {}' without a templates directory", name)))?;
+        if !is_valid_workflow_name(name) {
+            return Err(AppError::PromptConstruction(format!("Invalid template name '{}'", name)));
+        }
+        let path = format!("{}/{}.json", dir.trim_end_matches('/'), name);
+        let data = std::fs::read_to_string(&path)
+            .map_err(|e| AppError::PromptConstruction(format!("Failed to read template {}: {}", path, e)))?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
     /// TODO: Placeholder for template validation (shape, required fields, etc.).
-    fn validate_template(&self, template: &Value) -> AppResult<()> {
+    fn validate_template(&self, _template: &Value) -> AppResult<()> {
         // Add template validation logic here
         Ok(())
     }
 
     /// TODO: Placeholder for input validation.
-    fn validate_inputs(&self, inputs: &Value) -> AppResult<()> {
+    fn validate_inputs(&self, _inputs: &Value) -> AppResult<()> {
         // Add input validation logic here
         Ok(())
     }
 
-    /// Recursively replace `{{key}}` strings with `inputs[key]`.
+    /// Recursively replace `{{key}}` strings with `inputs[key]`, and
+    /// interpolate placeholders embedded in longer strings.
     fn replace_placeholders(&self, value: &mut Value, inputs: &Value) -> AppResult<()> {
         match value {
             Value::Object(map) => {
@@ -50,18 +150,82 @@ impl PromptConstructor {
                     self.replace_placeholders(v, inputs)?;
                 }
             }
-            Value::String(s) => {
-                if s.starts_with("{{") && s.ends_with("}}") {
-                    let key = s.trim_start_matches("{{").trim_end_matches("}}").trim();
-                    if let Some(replacement) = inputs.get(key) {
-                        *value = replacement.clone();
-                    } else {
-                        return Err(AppError::PromptConstruction(format!("Missing input for placeholder: {}", key)));
-                    }
-                }
+            Value::String(s) if s.contains("{{") => {
+                *value = interpolate(s, inputs)?;
             }
             _ => {}
         }
         Ok(())
     }
 }
+
+/// Substitute the `{{key}}` placeholders in `s` from `inputs`. A string that
+/// is a single placeholder becomes the input value itself, keeping its type;
+/// otherwise each placeholder is replaced by the value as text: strings
+/// as-is, numbers and booleans as written, `null` as nothing, and arrays or
+/// objects as JSON. `{{` without a closing `}}` is left alone.
+pub fn interpolate(s: &str, inputs: &Value) -> AppResult<Value> {
+    let lookup = |key: &str| inputs.get(key)
+        .ok_or_else(|| AppError::PromptConstruction(format!("Missing input for placeholder: {}", key)));
+    if let Some(key) = s.strip_prefix("{{").and_then(|rest| rest.strip_suffix("}}")) {
+        if !key.contains("{{") && !key.contains("}}") {
+            return lookup(key.trim()).cloned();
+        }
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut last = 0;
+    for (start, end) in placeholder_spans(s) {
+        out.push_str(&s[last..start]);
+        match lookup(s[start + 2..end - 2].trim())? {
+            Value::String(text) => out.push_str(text),
+            Value::Null => {}
+            other => out.push_str(&other.to_string()),
+        }
+        last = end;
+    }
+    out.push_str(&s[last..]);
+    Ok(Value::String(out))
+}
+
+/// Byte ranges of the `{{...}}` placeholders in `s`, braces included.
+fn placeholder_spans(s: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut from = 0;
+    while let Some(start) = s[from..].find("{{").map(|i| from + i) {
+        let Some(len) = s[start + 2..].find("}}") else { break };
+        let end = start + 2 + len + 2;
+        spans.push((start, end));
+        from = end;
+    }
+    spans
+}
+
+fn collect_placeholders(value: &Value, names: &mut BTreeSet<String>) {
+    match value {
+        Value::Object(map) => map.values().for_each(|v| collect_placeholders(v, names)),
+        Value::Array(arr) => arr.iter().for_each(|v| collect_placeholders(v, names)),
+        Value::String(s) => {
+            names.extend(placeholder_spans(s).into_iter().map(|(start, end)| s[start + 2..end - 2].trim().to_string()));
+        }
+        _ => {}
+    }
+}
+
+/// Merge `overrides` into `base`: objects merge key by key, `null` removes
+/// the key, and anything else replaces the base value.
+pub fn merge_overrides(base: &mut Value, overrides: &Value) {
+    match (base.as_object_mut(), overrides.as_object()) {
+        (Some(base_map), Some(over_map)) => {
+            for (k, v) in over_map {
+                if v.is_null() {
+                    base_map.remove(k);
+                } else if let Some(existing) = base_map.get_mut(k) {
+                    merge_overrides(existing, v);
+                } else {
+                    base_map.insert(k.clone(), v.clone());
+                }
+            }
+        }
+        _ => *base = overrides.clone(),
+    }
+}
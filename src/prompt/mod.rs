@@ -1,2 +1,9 @@
 pub mod constructor;
 pub mod validator;
+pub mod negative;
+pub mod resolution;
+pub mod seeds;
+pub mod sweep;
+pub mod testing;
+pub mod variation;
+pub mod weights;
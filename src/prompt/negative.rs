@@ -0,0 +1,53 @@
+//! Default negative prompts.
+//!
+//! Most users reuse the same boilerplate negatives, so a global default and
+//! per-workflow overrides can be configured and are applied when a request
+//! leaves `text_negative` unset and the workflow's negative node is empty.
+//!
+//! `NEGATIVE_PROMPTS_FILE` points at JSON of the form
+//! `{"default": "...", "workflows": {"sdxlapi": "..."}}`; `DEFAULT_NEGATIVE_PROMPT`
+//! sets the global default directly and takes precedence over the file's.
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::config::Config;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NegativePrompts {
+    #[serde(default)]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub workflows: HashMap<String, String>,
+}
+
+impl NegativePrompts {
+    /// Build from configuration. An unreadable or malformed file is logged
+    /// and ignored so a typo doesn't keep the server from starting.
+    pub fn from_config(config: &Config) -> Self {
+        let mut prompts = match config.negative_prompts_file.as_deref() {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(data) => serde_json::from_str(&data).unwrap_or_else(|e| {
+                    tracing::warn!("Ignoring invalid negative prompts file {}: {}", path, e);
+                    NegativePrompts::default()
+                }),
+                Err(e) => {
+                    tracing::warn!("Failed to read negative prompts file {}: {}", path, e);
+                    NegativePrompts::default()
+                }
+            },
+            None => NegativePrompts::default(),
+        };
+        if let Some(global) = config.default_negative_prompt.clone() {
+            prompts.default = Some(global);
+        }
+        prompts
+    }
+
+    /// Negative prompt for `workflow`, falling back to the global default.
+    pub fn for_workflow(&self, workflow: Option<&str>) -> Option<&str> {
+        workflow
+            .and_then(|w| self.workflows.get(w))
+            .or(self.default.as_ref())
+            .map(|s| s.as_str())
+    }
+}
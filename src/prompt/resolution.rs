@@ -0,0 +1,193 @@
+//! Width/height validation and snapping.
+//!
+//! Latent sizes must divide evenly by the model's downscale factor; SDXL is
+//! additionally trained on multiples of 64. Catching bad sizes here avoids
+//! tensor-shape errors from deep inside ComfyUI.
+use serde_json::Value;
+
+use crate::utils::prompt_ops::node_order;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelFamily {
+    Sd15,
+    Sdxl,
+    Flux,
+}
+
+impl ModelFamily {
+    /// Detect the family from a checkpoint or UNet file name.
+    pub fn detect(model_name: Option<&str>) -> Self {
+        let name = model_name.unwrap_or_default().to_ascii_lowercase();
+        if name.contains("flux") {
+            ModelFamily::Flux
+        } else if name.contains("xl") {
+            ModelFamily::Sdxl
+        } else {
+            ModelFamily::Sd15
+        }
+    }
+
+    /// Required divisor for width and height.
+    pub fn multiple(&self) -> u64 {
+        match self {
+            ModelFamily::Sd15 => 8,
+            ModelFamily::Sdxl => 64,
+            ModelFamily::Flux => 16,
+        }
+    }
+
+    /// Native training resolution (edge length of a square image).
+    pub fn native_size(&self) -> u64 {
+        match self {
+            ModelFamily::Sd15 => 512,
+            ModelFamily::Sdxl | ModelFamily::Flux => 1024,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ModelFamily::Sd15 => "SD1.5",
+            ModelFamily::Sdxl => "SDXL",
+            ModelFamily::Flux => "Flux",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolutionRules {
+    pub min: u64,
+    pub max: u64,
+    /// Snap invalid sizes instead of rejecting them (per-request override:
+    /// `"snap_resolution": true|false`).
+    pub snap: bool,
+}
+
+impl Default for ResolutionRules {
+    fn default() -> Self {
+        ResolutionRules { min: 64, max: 4096, snap: false }
+    }
+}
+
+/// Model name for family detection: the payload's `ckpt_name` if given,
+/// otherwise the first checkpoint/UNet loader in the graph.
+pub fn model_name_for(payload: &Value, graph: Option<&Value>) -> Option<String> {
+    let from_payload = payload.get("ckpt_name")
+        .or_else(|| payload.get("params").and_then(|p| p.get("ckpt_name")))
+        .and_then(|v| v.as_str());
+    if let Some(name) = from_payload {
+        return Some(name.to_string());
+    }
+    let mut nodes: Vec<(&String, &Value)> = graph?.as_object()?.iter().collect();
+    nodes.sort_by(|a, b| node_order(a.0).cmp(&node_order(b.0)));
+    nodes.into_iter().find_map(|(_, node)| {
+        let inputs = node.get("inputs")?;
+        inputs.get("ckpt_name").or_else(|| inputs.get("unet_name"))?.as_str().map(String::from)
+    })
+}
+
+/// Parse `"16:9"` (or a plain ratio like `"1.5"`) into a width/height ratio.
+pub fn parse_aspect_ratio(s: &str) -> Result<f64, String> {
+    let ratio = match s.split_once(':') {
+        Some((w, h)) => {
+            let w: f64 = w.trim().parse().map_err(|_| format!("Invalid aspect_ratio '{}'", s))?;
+            let h: f64 = h.trim().parse().map_err(|_| format!("Invalid aspect_ratio '{}'", s))?;
+            w / h
+        }
+        None => s.trim().parse().map_err(|_| format!("Invalid aspect_ratio '{}'", s))?,
+    };
+    if !ratio.is_finite() || ratio <= 0.0 {
+        return Err(format!("Invalid aspect_ratio '{}'", s));
+    }
+    Ok(ratio)
+}
+
+/// Dimensions with roughly `base_size`² pixels at `ratio`, rounded to the
+/// family's multiple. Keeping the pixel count near the training size is what
+/// SD1.5/SDXL frontends do for non-square images.
+pub fn dimensions_for_aspect(ratio: f64, base_size: u64, family: ModelFamily) -> (u64, u64) {
+    let area = (base_size as f64).powi(2);
+    let width = (area * ratio).sqrt();
+    let height = area / width;
+    let m = family.multiple() as f64;
+    let round = |v: f64| (((v / m).round() * m) as u64).max(family.multiple());
+    (round(width), round(height))
+}
+
+fn payload_value<'a>(payload: &'a Value, key: &str) -> Option<&'a Value> {
+    payload.get(key).or_else(|| payload.get("params").and_then(|p| p.get(key)))
+}
+
+impl ResolutionRules {
+    /// Resolve `aspect_ratio` (+ optional `base_size`) in `payload` into
+    /// explicit dimensions, or `None` when no aspect ratio was requested.
+    pub fn aspect_dimensions(&self, payload: &Value, graph: Option<&Value>) -> Result<Option<(u64, u64)>, String> {
+        let Some(ratio) = payload_value(payload, "aspect_ratio") else { return Ok(None) };
+        if payload_value(payload, "width").is_some() || payload_value(payload, "height").is_some() {
+            return Err("'aspect_ratio' cannot be combined with 'width'/'height'".to_string());
+        }
+        let ratio = match ratio {
+            Value::String(s) => parse_aspect_ratio(s)?,
+            Value::Number(n) => n.as_f64().filter(|r| *r > 0.0).ok_or("Invalid aspect_ratio")?,
+            _ => return Err("'aspect_ratio' must be a string like \"16:9\"".to_string()),
+        };
+        let family = ModelFamily::detect(model_name_for(payload, graph).as_deref());
+        let base_size = match payload_value(payload, "base_size") {
+            Some(v) => v.as_u64().ok_or("'base_size' must be a positive integer")?,
+            None => family.native_size(),
+        };
+        if base_size < self.min || base_size > self.max {
+            return Err(format!("'base_size' = {} must be between {} and {}", base_size, self.min, self.max));
+        }
+        let (w, h) = dimensions_for_aspect(ratio, base_size, family);
+        let multiple = family.multiple();
+        Ok(Some((self.snap_value(w, multiple), self.snap_value(h, multiple))))
+    }
+
+    fn snap_value(&self, value: u64, multiple: u64) -> u64 {
+        let rounded = (value.saturating_add(multiple / 2) / multiple) * multiple;
+        let min = self.min.div_ceil(multiple) * multiple;
+        let max = (self.max / multiple) * multiple;
+        rounded.clamp(min, max.max(min))
+    }
+
+    fn check_value(&self, field: &str, v: &Value, family: ModelFamily, snap: bool) -> Result<(u64, Option<String>), String> {
+        let value = v.as_u64().ok_or_else(|| format!("'{}' must be a positive integer", field))?;
+        let multiple = family.multiple();
+        let in_bounds = value >= self.min && value <= self.max;
+        let aligned = value % multiple == 0;
+        if in_bounds && aligned {
+            return Ok((value, None));
+        }
+        let problem = if !in_bounds {
+            format!("must be between {} and {}", self.min, self.max)
+        } else {
+            format!("must be a multiple of {} for {}", multiple, family.label())
+        };
+        if !snap {
+            return Err(format!("'{}' = {} {}", field, value, problem));
+        }
+        let snapped = self.snap_value(value, multiple);
+        Ok((snapped, Some(format!("{} {} snapped to {} ({})", field, value, snapped, problem))))
+    }
+
+    /// Validate `width`/`height` in `payload` (top-level and under `params`),
+    /// snapping them in place when enabled. Returns warnings for snapped values.
+    pub fn apply(&self, payload: &mut Value, graph: Option<&Value>) -> Result<Vec<String>, String> {
+        let family = ModelFamily::detect(model_name_for(payload, graph).as_deref());
+        let snap = payload.get("snap_resolution").and_then(|v| v.as_bool()).unwrap_or(self.snap);
+        let mut warnings = Vec::new();
+        for key in ["width", "height"] {
+            if let Some(v) = payload.get(key) {
+                let (value, warning) = self.check_value(key, v, family, snap)?;
+                payload[key] = Value::from(value);
+                warnings.extend(warning);
+            }
+            if let Some(v) = payload.get("params").and_then(|p| p.get(key)) {
+                let (value, warning) = self.check_value(&format!("params.{}", key), v, family, snap)?;
+                payload["params"][key] = Value::from(value);
+                warnings.extend(warning);
+            }
+        }
+        Ok(warnings)
+    }
+}
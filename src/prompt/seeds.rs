@@ -0,0 +1,212 @@
+//! Seed journal.
+//!
+//! Favoriting a job's output records the seeds its graph ran with under a
+//! name, in `SEEDS_FILE`, so a liked image can be reproduced or varied
+//! later: `"seed": "favorite:<name>"` (top level or under `params`) resolves
+//! to the recorded seed. Favorites are scoped per tenant like workflows.
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+
+use crate::config::Config;
+use crate::utils::time::now_ms;
+use crate::workflow::compose::link_source;
+
+/// Prefix of seed values that refer to a favorite.
+pub const FAVORITE_PREFIX: &str = "favorite:";
+
+/// Input names that hold a sampler's seed.
+const SEED_INPUTS: &[&str] = &["seed", "noise_seed"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct FavoriteSeed {
+    pub name: String,
+    /// The first sampler's seed, which `favorite:<name>` resolves to.
+    pub seed: u64,
+    /// Every seed input in the graph, by node id.
+    #[serde(default)]
+    pub seeds: BTreeMap<String, u64>,
+    pub job_id: String,
+    #[serde(default)]
+    pub prompt_id: Option<String>,
+    #[serde(default)]
+    pub workflow: Option<String>,
+    /// Filename of the favorited output.
+    #[serde(default)]
+    pub output: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub saved_at_ms: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SeedsFile {
+    #[serde(default)]
+    favorites: Vec<FavoriteSeed>,
+}
+
+#[derive(Debug, Default)]
+pub struct SeedJournal {
+    path: Option<PathBuf>,
+    favorites: Vec<FavoriteSeed>,
+    /// The file exists but couldn't be loaded, so it's moved aside before
+    /// the journal is first written rather than overwritten.
+    unloaded: bool,
+}
+
+impl SeedJournal {
+    /// Load the journal at `path`. A missing file starts an empty journal; an
+    /// unreadable or malformed one is logged and ignored, and is renamed to
+    /// `<path>.invalid-<ms>` once a favorite is saved, so its contents can
+    /// still be recovered by hand.
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let loaded = match std::fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str::<SeedsFile>(&data).map(|f| f.favorites).map_err(|e| {
+                tracing::warn!("Ignoring invalid seeds file {}: {}", path.display(), e);
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => {
+                tracing::warn!("Failed to read seeds file {}: {}", path.display(), e);
+                Err(())
+            }
+        };
+        let unloaded = loaded.is_err();
+        SeedJournal { path: Some(path), favorites: loaded.unwrap_or_default(), unloaded }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::open(&config.seeds_file)
+    }
+
+    /// `tenant`'s favorites, newest first.
+    pub fn list(&self, tenant: Option<&str>) -> Vec<&FavoriteSeed> {
+        let mut list: Vec<&FavoriteSeed> = self.favorites.iter().filter(|f| f.tenant.as_deref() == tenant).collect();
+        list.sort_by(|a, b| b.saved_at_ms.cmp(&a.saved_at_ms).then_with(|| a.name.cmp(&b.name)));
+        list
+    }
+
+    pub fn get(&self, tenant: Option<&str>, name: &str) -> Option<&FavoriteSeed> {
+        self.favorites.iter().find(|f| f.tenant.as_deref() == tenant && f.name == name)
+    }
+
+    /// Record `favorite`, replacing one of the same name, and write the file.
+    pub fn save(&mut self, favorite: FavoriteSeed) -> Result<(), String> {
+        self.favorites.retain(|f| !(f.tenant == favorite.tenant && f.name == favorite.name));
+        self.favorites.push(favorite);
+        self.write()
+    }
+
+    /// Forget `name`. False if there was no such favorite.
+    pub fn remove(&mut self, tenant: Option<&str>, name: &str) -> Result<bool, String> {
+        let before = self.favorites.len();
+        self.favorites.retain(|f| !(f.tenant.as_deref() == tenant && f.name == name));
+        if self.favorites.len() == before {
+            return Ok(false);
+        }
+        self.write().map(|_| true)
+    }
+
+    /// Replace `"seed": "favorite:<name>"` at the top level and under
+    /// `params` with the favorite's seed. Unknown names are an error.
+    pub fn resolve(&self, payload: &mut Value, tenant: Option<&str>) -> Result<(), String> {
+        let resolve_in = |obj: &mut Value| -> Result<(), String> {
+            let Some(name) = obj.get("seed").and_then(|v| v.as_str()).and_then(|s| s.strip_prefix(FAVORITE_PREFIX)) else { return Ok(()) };
+            let favorite = self.get(tenant, name).ok_or_else(|| format!("Unknown favorite seed '{}'", name))?;
+            obj["seed"] = Value::from(favorite.seed);
+            Ok(())
+        };
+        resolve_in(payload)?;
+        match payload.get_mut("params") {
+            Some(params) => resolve_in(params),
+            None => Ok(()),
+        }
+    }
+
+    fn write(&mut self) -> Result<(), String> {
+        let Some(path) = &self.path else { return Ok(()) };
+        if self.unloaded && path.exists() {
+            let mut aside = path.clone().into_os_string();
+            aside.push(format!(".invalid-{}", now_ms()));
+            std::fs::rename(path, &aside)
+                .map_err(|e| format!("Refusing to overwrite unreadable seeds file {} (failed to move it aside: {})", path.display(), e))?;
+            tracing::warn!("Moved unreadable seeds file {} to {}", path.display(), aside.to_string_lossy());
+        }
+        self.unloaded = false;
+        let data = serde_json::to_string_pretty(&SeedsFile { favorites: self.favorites.clone() }).map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        // Write then rename so a crash never leaves a truncated journal.
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, data).map_err(|e| format!("Failed to write {}: {}", tmp.display(), e))?;
+        std::fs::rename(&tmp, path).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// Integer seed inputs in an API-format graph, by node id.
+pub fn seeds_in_graph(graph: &Value) -> BTreeMap<String, u64> {
+    let mut seeds = BTreeMap::new();
+    let Some(nodes) = graph.as_object() else { return seeds };
+    for (id, node) in nodes {
+        let Some(inputs) = node.get("inputs") else { continue };
+        if let Some(seed) = SEED_INPUTS.iter().find_map(|k| inputs.get(*k).and_then(|v| v.as_u64())) {
+            seeds.insert(id.clone(), seed);
+        }
+    }
+    seeds
+}
+
+/// Whether `node` is a sampler (KSampler, KSamplerAdvanced, SamplerCustom, ...).
+fn is_sampler(node: &Value) -> bool {
+    node.get("class_type").and_then(|v| v.as_str()).is_some_and(|ct| ct.contains("Sampler"))
+}
+
+/// Whether a sampler feeds `node_id`, directly or through other nodes.
+fn has_sampler_upstream(graph: &Value, node_id: &str) -> bool {
+    let mut stack = vec![node_id.to_string()];
+    let mut seen = HashSet::new();
+    while let Some(id) = stack.pop() {
+        if !seen.insert(id.clone()) {
+            continue;
+        }
+        let Some(inputs) = graph.get(&id).and_then(|n| n.get("inputs")).and_then(|i| i.as_object()) else { continue };
+        for source in inputs.values().filter_map(link_source) {
+            if graph.get(&source).is_some_and(is_sampler) {
+                return true;
+            }
+            stack.push(source);
+        }
+    }
+    false
+}
+
+/// The seed a favorite resolves to: that of the first sampling stage of
+/// `graph`, the one that sets the image's composition. Each entry of `seeds`
+/// (from [`seeds_in_graph`]) belongs to the sampler it's on, or to the
+/// sampler it's linked into (e.g. a `RandomNoise` node's `noise_seed`).
+/// Refiners, detailers, and other samplers fed by a sampler come later. When
+/// several samplers start the graph, or no seed belongs to a sampler, the
+/// lowest-numbered node wins.
+pub fn primary_seed(graph: &Value, seeds: &BTreeMap<String, u64>) -> Option<u64> {
+    let id_order = |id: &str| (id.parse::<u64>().unwrap_or(u64::MAX), id.to_string());
+    let lowest = |a: &(&String, &u64), b: &(&String, &u64)| id_order(a.0).cmp(&id_order(b.0));
+    let nodes = graph.as_object();
+    let sampler_of = |id: &str| -> Option<String> {
+        if graph.get(id).is_some_and(is_sampler) {
+            return Some(id.to_string());
+        }
+        nodes?.iter()
+            .filter(|(_, node)| is_sampler(node))
+            .find(|(_, node)| {
+                node.get("inputs").and_then(|i| i.as_object())
+                    .is_some_and(|inputs| inputs.values().any(|v| link_source(v).as_deref() == Some(id)))
+            })
+            .map(|(sampler, _)| sampler.clone())
+    };
+    let first_stage = seeds.iter()
+        .filter(|(id, _)| sampler_of(id).is_some_and(|sampler| !has_sampler_upstream(graph, &sampler)))
+        .min_by(lowest);
+    first_stage.or_else(|| seeds.iter().min_by(lowest)).map(|(_, seed)| *seed)
+}
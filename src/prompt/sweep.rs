@@ -0,0 +1,120 @@
+//! Parameter sweeps (grid search).
+//!
+//! A sweep is a queue payload whose params may list several values, e.g.
+//! `"cfg": [5, 7, 9]`, or ask for several seeds with `"seed": {"count": 4}`
+//! (consecutive from `start`, random by default). [`expand_sweep`] turns it
+//! into one payload per combination, the Cartesian product of every swept
+//! param, each tagged with its values through `filename_suffix` so its
+//! outputs are named e.g. `Derivata_cfg-7_seed-1234_00001_.png`.
+use serde_json::{Map, Value};
+
+use crate::utils::prompt_build::{MAX_QUEUE_BATCH, TOP_LEVEL_PARAM_KEYS};
+
+/// Longest a string value may run in a filename tag.
+const MAX_TAG_VALUE_LEN: usize = 24;
+
+/// One combination of a sweep.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepPoint {
+    /// The queue payload with every swept param set to one value.
+    pub payload: Value,
+    /// The swept params' values in this combination.
+    pub values: Map<String, Value>,
+    /// Filename tag for the combination, e.g. `cfg-7_seed-1234`.
+    pub tag: String,
+}
+
+/// Expand `payload` into one payload per combination of its swept params,
+/// in order with the first swept param (in `TOP_LEVEL_PARAM_KEYS` order)
+/// varying slowest. At least one param must be swept, and the product is
+/// capped like a batch.
+pub fn expand_sweep(payload: &Value) -> Result<Vec<SweepPoint>, String> {
+    let obj = payload.as_object().ok_or("Sweep body must be an object")?;
+    let params = obj.get("params").and_then(|p| p.as_object());
+    // (param, whether it's under `params`, its values)
+    let mut axes: Vec<(&str, bool, Vec<Value>)> = Vec::new();
+    for key in TOP_LEVEL_PARAM_KEYS {
+        let top = obj.get(*key).map(|v| axis_values(key, v)).transpose()?.flatten();
+        let nested = params.and_then(|p| p.get(*key)).map(|v| axis_values(key, v)).transpose()?.flatten();
+        match (top, nested) {
+            (Some(_), Some(_)) => return Err(format!("'{}' is swept both at the top level and under 'params'", key)),
+            (Some(values), None) => axes.push((key, false, values)),
+            (None, Some(values)) => axes.push((key, true, values)),
+            (None, None) => {}
+        }
+    }
+    if axes.is_empty() {
+        return Err("Nothing to sweep: give a param a list of values, e.g. \"cfg\": [5, 7], or \"seed\": {\"count\": 4}".to_string());
+    }
+    let total = axes.iter()
+        .try_fold(1usize, |n, (_, _, values)| n.checked_mul(values.len()))
+        .filter(|n| *n <= MAX_QUEUE_BATCH)
+        .ok_or_else(|| format!("Sweep expands to more than {} prompts", MAX_QUEUE_BATCH))?;
+    let base_suffix = obj.get("filename_suffix").and_then(|v| v.as_str()).filter(|s| !s.is_empty());
+
+    let mut points = Vec::with_capacity(total);
+    for index in 0..total {
+        let mut payload = payload.clone();
+        let mut values = Map::new();
+        let mut tags = Vec::new();
+        let mut rest = index;
+        // The last axis varies fastest.
+        let mut picks = vec![0; axes.len()];
+        for (pick, (_, _, axis)) in picks.iter_mut().zip(&axes).rev() {
+            *pick = rest % axis.len();
+            rest /= axis.len();
+        }
+        for ((key, nested, axis), pick) in axes.iter().zip(picks) {
+            let value = axis[pick].clone();
+            let target = if *nested { &mut payload["params"] } else { &mut payload };
+            target[*key] = value.clone();
+            tags.push(format!("{}-{}", key, tag_value(&value)));
+            values.insert(key.to_string(), value);
+        }
+        let tag = tags.join("_");
+        payload["filename_suffix"] = Value::String(match base_suffix {
+            Some(base) => format!("{}_{}", base, tag),
+            None => tag.clone(),
+        });
+        points.push(SweepPoint { payload, values, tag });
+    }
+    Ok(points)
+}
+
+/// The values a swept param takes, or `None` if `value` is a plain value.
+fn axis_values(key: &str, value: &Value) -> Result<Option<Vec<Value>>, String> {
+    match value {
+        Value::Array(values) if values.is_empty() => Err(format!("'{}' must list at least one value", key)),
+        Value::Array(values) => Ok(Some(values.clone())),
+        Value::Object(spec) if spec.contains_key("count") => {
+            if key != "seed" {
+                return Err(format!("Only 'seed' takes {{\"count\": n}}; list the values of '{}' instead", key));
+            }
+            let count = spec["count"].as_u64().filter(|n| (1..=MAX_QUEUE_BATCH as u64).contains(n))
+                .ok_or_else(|| format!("'seed.count' must be between 1 and {}", MAX_QUEUE_BATCH))?;
+            let start = match spec.get("start") {
+                None => (uuid::Uuid::new_v4().as_u128() as u64) >> 14,
+                Some(v) => v.as_u64().ok_or("'seed.start' must be a non-negative integer")?,
+            };
+            Ok(Some((0..count).map(|i| Value::from(start.saturating_add(i))).collect()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// `value` as it appears in a filename tag: numbers as written, strings
+/// reduced to alphanumerics, `.` and `-`.
+fn tag_value(value: &Value) -> String {
+    let text = match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    let mut tag = String::new();
+    for c in text.chars() {
+        let c = if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '-' };
+        if !(c == '-' && tag.ends_with('-')) {
+            tag.push(c);
+        }
+    }
+    tag.trim_matches('-').chars().take(MAX_TAG_VALUE_LEN).collect()
+}
@@ -0,0 +1,147 @@
+//! Golden tests for prompt templates.
+//!
+//! [`assert_renders_to`] checks one rendering from Rust tests.
+//! `comfyctl template test <dir>` runs every fixture in a directory, each a
+//! JSON file of the form:
+//!
+//! ```json
+//! {"template": "portrait" | {...}, "inputs": {...}, "expected": {...}}
+//! ```
+//!
+//! A string `template` names a stored template, resolved like `extends`.
+//! `expected` is the golden output; `comfyctl template test --update`
+//! rewrites it with the current rendering after an intended change.
+use serde_json::{json, Value};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::error::AppResult;
+use crate::prompt::constructor::PromptConstructor;
+
+/// A fixture file.
+#[derive(Debug, Clone)]
+pub struct GoldenCase {
+    pub path: PathBuf,
+    pub template: Value,
+    pub inputs: Value,
+    /// Missing until the fixture is first rendered with `--update`.
+    pub expected: Option<Value>,
+}
+
+/// One place a rendering differs from the golden output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    /// JSON pointer to the differing value (`""` for the root).
+    pub path: String,
+    /// `None` when the key is missing on that side.
+    pub expected: Option<Value>,
+    pub actual: Option<Value>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |v: &Option<Value>| v.as_ref().map(Value::to_string).unwrap_or_else(|| "<missing>".to_string());
+        let path = if self.path.is_empty() { "/" } else { &self.path };
+        write!(f, "{}: expected {}, got {}", path, show(&self.expected), show(&self.actual))
+    }
+}
+
+impl GoldenCase {
+    /// Fixture name: the file stem.
+    pub fn name(&self) -> String {
+        self.path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let doc: Value = serde_json::from_str(&data).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let template = match doc.get("template") {
+            Some(Value::String(name)) => json!({"extends": name}),
+            Some(template @ Value::Object(_)) => template.clone(),
+            _ => return Err(format!("{}: 'template' must be a template name or object", path.display())),
+        };
+        Ok(GoldenCase {
+            path: path.to_path_buf(),
+            template,
+            inputs: doc.get("inputs").cloned().unwrap_or_else(|| json!({})),
+            expected: doc.get("expected").cloned(),
+        })
+    }
+
+    pub fn render(&self, constructor: &PromptConstructor) -> AppResult<Value> {
+        constructor.construct_prompt(&self.template, &self.inputs)
+    }
+
+    /// Replace the fixture's `expected` with `rendered`, keeping its other keys.
+    pub fn write_expected(&self, rendered: &Value) -> Result<(), String> {
+        let data = std::fs::read_to_string(&self.path).map_err(|e| format!("Failed to read {}: {}", self.path.display(), e))?;
+        let mut doc: Value = serde_json::from_str(&data).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        doc["expected"] = rendered.clone();
+        let text = serde_json::to_string_pretty(&doc).map_err(|e| e.to_string())?;
+        std::fs::write(&self.path, text + "\n").map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))
+    }
+}
+
+/// Every `*.json` fixture directly under `dir`, by file name. A fixture that
+/// can't be loaded doesn't stop the others: each gets its own result, so a
+/// run can report every broken one. Only an unreadable `dir` is an error.
+pub fn load_cases(dir: &Path) -> Result<Vec<Result<GoldenCase, String>>, String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    let mut paths: Vec<PathBuf> = entries.flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && p.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    Ok(paths.iter().map(|p| GoldenCase::load(p)).collect())
+}
+
+/// Where `actual` differs from `expected`, depth first. Objects are
+/// compared key by key and arrays element by element; anything else must be
+/// equal.
+pub fn diff_json(expected: &Value, actual: &Value) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+    diff_at(String::new(), Some(expected), Some(actual), &mut mismatches);
+    mismatches
+}
+
+fn diff_at(path: String, expected: Option<&Value>, actual: Option<&Value>, out: &mut Vec<Mismatch>) {
+    match (expected, actual) {
+        (Some(Value::Object(a)), Some(Value::Object(b))) => {
+            let mut keys: Vec<&String> = a.keys().chain(b.keys().filter(|k| !a.contains_key(*k))).collect();
+            keys.sort();
+            for key in keys {
+                let escaped = key.replace('~', "~0").replace('/', "~1");
+                diff_at(format!("{}/{}", path, escaped), a.get(key), b.get(key), out);
+            }
+        }
+        (Some(Value::Array(a)), Some(Value::Array(b))) => {
+            for i in 0..a.len().max(b.len()) {
+                diff_at(format!("{}/{}", path, i), a.get(i), b.get(i), out);
+            }
+        }
+        (a, b) if a == b => {}
+        (a, b) => out.push(Mismatch { path, expected: a.cloned(), actual: b.cloned() }),
+    }
+}
+
+/// Render `template` with `inputs` using `constructor` and compare it to
+/// `expected`, describing every difference on failure.
+pub fn check_renders_to(constructor: &PromptConstructor, template: &Value, inputs: &Value, expected: &Value) -> Result<(), String> {
+    let rendered = constructor.construct_prompt(template, inputs).map_err(|e| format!("render failed: {}", e))?;
+    let mismatches = diff_json(expected, &rendered);
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+    let lines: Vec<String> = mismatches.iter().map(|m| format!("  {}", m)).collect();
+    Err(format!("rendered template differs from expected:\n{}", lines.join("\n")))
+}
+
+/// Panic unless `template` rendered with `inputs` equals `expected`. For
+/// templates that `extends` stored ones, use [`check_renders_to`] with a
+/// [`PromptConstructor::with_dir`].
+#[track_caller]
+pub fn assert_renders_to(template: &Value, inputs: &Value, expected: &Value) {
+    if let Err(message) = check_renders_to(&PromptConstructor::new(), template, inputs, expected) {
+        panic!("{}", message);
+    }
+}
@@ -0,0 +1,91 @@
+//! Validation for free-text prompt parameters.
+//!
+//! Text params end up in CLIPTextEncode nodes, where empty strings, stray
+//! control characters, or enormous inputs fail late (or silently produce
+//! garbage). These checks run before any graph is built.
+use serde_json::Value;
+use std::fmt;
+
+/// Text params routed into CLIPTextEncode nodes.
+pub const TEXT_PARAM_KEYS: &[&str] = &["text", "text_positive", "text_negative"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextLimits {
+    pub max_chars: usize,
+    /// Optional budget on the rough token estimate from [`estimate_tokens`].
+    pub max_tokens: Option<usize>,
+}
+
+impl Default for TextLimits {
+    fn default() -> Self {
+        TextLimits { max_chars: 4000, max_tokens: None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextValidationError {
+    pub field: String,
+    pub reason: String,
+}
+
+impl fmt::Display for TextValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid '{}': {}", self.field, self.reason)
+    }
+}
+
+impl std::error::Error for TextValidationError {}
+
+/// Rough token estimate: each run of alphanumerics counts as one token and
+/// every other non-whitespace character (punctuation, weighting parens)
+/// counts as its own token. Close enough to CLIP's BPE for budgeting.
+pub fn estimate_tokens(text: &str) -> usize {
+    let mut count = 0;
+    let mut in_word = false;
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            if !in_word { count += 1; in_word = true; }
+        } else {
+            in_word = false;
+            if !c.is_whitespace() { count += 1; }
+        }
+    }
+    count
+}
+
+/// Validate a single text value for `field`.
+pub fn validate_prompt_text(field: &str, value: &Value, limits: &TextLimits) -> Result<(), TextValidationError> {
+    let err = |reason: String| TextValidationError { field: field.to_string(), reason };
+    let text = value.as_str().ok_or_else(|| err("must be a string".to_string()))?;
+    if text.trim().is_empty() {
+        return Err(err("must not be empty".to_string()));
+    }
+    if let Some(c) = text.chars().find(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t')) {
+        return Err(err(format!("contains control character U+{:04X}", c as u32)));
+    }
+    let chars = text.chars().count();
+    if chars > limits.max_chars {
+        return Err(err(format!("is {} characters, exceeding the limit of {}", chars, limits.max_chars)));
+    }
+    if let Some(max_tokens) = limits.max_tokens {
+        let tokens = estimate_tokens(text);
+        if tokens > max_tokens {
+            return Err(err(format!("is ~{} tokens, exceeding the limit of {}", tokens, max_tokens)));
+        }
+    }
+    Ok(())
+}
+
+/// Validate text params in a queue payload, both top-level and under `params`.
+/// Field names in errors use the `params.` prefix for nested values.
+pub fn validate_text_params(payload: &Value, limits: &TextLimits) -> Result<(), TextValidationError> {
+    for key in TEXT_PARAM_KEYS {
+        if let Some(v) = payload.get(*key) {
+            validate_prompt_text(key, v, limits)?;
+        }
+        if let Some(v) = payload.get("params").and_then(|p| p.get(*key)) {
+            validate_prompt_text(&format!("params.{}", key), v, limits)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,103 @@
+//! Named denoise presets for image-to-image workflows.
+//!
+//! `"variation": "subtle" | "moderate" | "strong"` picks a denoise strength
+//! for samplers that start from an encoded image, so callers don't need to
+//! know what denoise does. Each preset covers a range; the midpoint is used.
+use serde_json::Value;
+use std::collections::HashSet;
+
+use crate::workflow::compose::link_source;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variation {
+    Subtle,
+    Moderate,
+    Strong,
+}
+
+impl Variation {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "subtle" => Some(Variation::Subtle),
+            "moderate" => Some(Variation::Moderate),
+            "strong" => Some(Variation::Strong),
+            _ => None,
+        }
+    }
+
+    /// Denoise range the preset stands for.
+    pub fn range(&self) -> (f64, f64) {
+        match self {
+            Variation::Subtle => (0.2, 0.35),
+            Variation::Moderate => (0.4, 0.6),
+            Variation::Strong => (0.65, 0.85),
+        }
+    }
+
+    pub fn denoise(&self) -> f64 {
+        let (lo, hi) = self.range();
+        ((lo + hi) / 2.0 * 1000.0).round() / 1000.0
+    }
+}
+
+/// Whether the latent feeding sampler `node_id` comes from an encoded image
+/// (VAEEncode and friends) rather than an empty latent. Only latent inputs
+/// are followed upstream.
+fn starts_from_image(graph: &Value, node_id: &str) -> bool {
+    let latent_inputs = |id: &str| -> Vec<String> {
+        graph.get(id).and_then(|n| n.get("inputs")).and_then(|i| i.as_object())
+            .map(|inputs| inputs.iter()
+                .filter(|(name, _)| *name == "latent_image" || name.starts_with("samples"))
+                .filter_map(|(_, v)| link_source(v))
+                .collect())
+            .unwrap_or_default()
+    };
+    let mut stack = latent_inputs(node_id);
+    let mut seen = HashSet::new();
+    while let Some(id) = stack.pop() {
+        if !seen.insert(id.clone()) { continue; }
+        let class = graph.get(&id).and_then(|n| n.get("class_type")).and_then(|v| v.as_str()).unwrap_or_default();
+        if class.starts_with("VAEEncode") {
+            return true;
+        }
+        stack.extend(latent_inputs(&id));
+    }
+    false
+}
+
+/// Sampler nodes with a `denoise` input whose latent starts from an image,
+/// sorted by id.
+pub fn img2img_samplers(graph: &Value) -> Vec<String> {
+    let Some(nodes) = graph.as_object() else { return Vec::new() };
+    let mut ids: Vec<String> = nodes.iter()
+        .filter(|(_, n)| n.get("inputs").and_then(|i| i.get("denoise")).is_some())
+        .filter(|(id, _)| starts_from_image(graph, id))
+        .map(|(id, _)| id.clone())
+        .collect();
+    ids.sort();
+    ids
+}
+
+/// Apply the payload's `variation` preset (top-level or under `params`) to the
+/// graph's img2img samplers. Returns the denoise used, or `None` when no
+/// preset was requested.
+pub fn apply_variation(payload: &Value, graph: &mut Value) -> Result<Option<f64>, String> {
+    let value = payload.get("variation").or_else(|| payload.get("params").and_then(|p| p.get("variation")));
+    let Some(value) = value else { return Ok(None) };
+    let variation = value.as_str().and_then(Variation::parse)
+        .ok_or("'variation' must be one of \"subtle\", \"moderate\", \"strong\"")?;
+    let has_denoise = payload.get("denoise").is_some()
+        || payload.get("params").and_then(|p| p.get("denoise")).is_some();
+    if has_denoise {
+        return Err("'variation' cannot be combined with 'denoise'".to_string());
+    }
+    let samplers = img2img_samplers(graph);
+    if samplers.is_empty() {
+        return Err("'variation' requires an image-to-image workflow (a sampler fed by VAEEncode)".to_string());
+    }
+    let denoise = variation.denoise();
+    for id in samplers {
+        graph[&id]["inputs"]["denoise"] = Value::from(denoise);
+    }
+    Ok(Some(denoise))
+}
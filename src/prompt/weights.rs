@@ -0,0 +1,233 @@
+//! Parsing and normalization of prompt emphasis syntax.
+//!
+//! Both A1111 and ComfyUI read `(word)` as ×1.1 emphasis (nesting multiplies)
+//! and `(word:1.3)` as an explicit weight. A1111 additionally reads `[word]`
+//! as ÷1.1, which ComfyUI treats as literal brackets. Normalizing rewrites a
+//! prompt into flat, explicit `(text:weight)` groups that mean the same thing
+//! to either frontend.
+use serde_json::Value;
+
+use crate::prompt::validator::TEXT_PARAM_KEYS;
+
+const EMPHASIS: f64 = 1.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeightSyntax {
+    A1111,
+    Comfy,
+}
+
+impl WeightSyntax {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "a1111" | "auto1111" | "webui" => Some(WeightSyntax::A1111),
+            "comfy" | "comfyui" => Some(WeightSyntax::Comfy),
+            _ => None,
+        }
+    }
+
+    fn square_brackets(&self) -> bool {
+        matches!(self, WeightSyntax::A1111)
+    }
+}
+
+/// A run of prompt text with its effective weight.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightedSpan {
+    pub text: String,
+    pub weight: f64,
+}
+
+enum Token {
+    Text(String),
+    OpenRound,
+    OpenSquare,
+    CloseRound,
+    CloseSquare,
+    /// `:1.2)` closing an explicit-weight group.
+    Weight(f64),
+}
+
+/// `:` followed by a number and `)` closes an explicit-weight group.
+fn explicit_weight(chars: &[char], i: usize) -> Option<(f64, usize)> {
+    let mut j = i + 1;
+    while j < chars.len() && chars[j].is_whitespace() { j += 1; }
+    let start = j;
+    while j < chars.len() && (chars[j].is_ascii_digit() || matches!(chars[j], '.' | '+' | '-')) { j += 1; }
+    let number: String = chars[start..j].iter().collect();
+    while j < chars.len() && chars[j].is_whitespace() { j += 1; }
+    if chars.get(j) != Some(&')') { return None; }
+    number.parse().ok().map(|w| (w, j + 1))
+}
+
+fn tokenize(text: &str, syntax: WeightSyntax) -> Vec<Token> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut buf = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let token = match c {
+            '\\' if i + 1 < chars.len() => {
+                buf.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            '(' => Token::OpenRound,
+            ')' => Token::CloseRound,
+            '[' if syntax.square_brackets() => Token::OpenSquare,
+            ']' if syntax.square_brackets() => Token::CloseSquare,
+            ':' => match explicit_weight(&chars, i) {
+                Some((w, next)) => {
+                    if !buf.is_empty() { tokens.push(Token::Text(std::mem::take(&mut buf))); }
+                    tokens.push(Token::Weight(w));
+                    i = next;
+                    continue;
+                }
+                None => {
+                    buf.push(c);
+                    i += 1;
+                    continue;
+                }
+            },
+            _ => {
+                buf.push(c);
+                i += 1;
+                continue;
+            }
+        };
+        if !buf.is_empty() { tokens.push(Token::Text(std::mem::take(&mut buf))); }
+        tokens.push(token);
+        i += 1;
+    }
+    if !buf.is_empty() { tokens.push(Token::Text(buf)); }
+    tokens
+}
+
+fn multiply(spans: &mut [WeightedSpan], from: usize, factor: f64) {
+    for span in &mut spans[from..] {
+        span.weight *= factor;
+    }
+}
+
+/// Parse `text` into weighted spans. Unbalanced brackets are applied to the
+/// end of the prompt (as A1111 does); stray closers are kept as text.
+pub fn parse(text: &str, syntax: WeightSyntax) -> Vec<WeightedSpan> {
+    let mut spans: Vec<WeightedSpan> = Vec::new();
+    let mut round: Vec<usize> = Vec::new();
+    let mut square: Vec<usize> = Vec::new();
+    let literal = |spans: &mut Vec<WeightedSpan>, s: &str| spans.push(WeightedSpan { text: s.to_string(), weight: 1.0 });
+
+    for token in tokenize(text, syntax) {
+        match token {
+            Token::Text(s) => literal(&mut spans, &s),
+            Token::OpenRound => round.push(spans.len()),
+            Token::OpenSquare => square.push(spans.len()),
+            Token::CloseRound => match round.pop() {
+                Some(start) => multiply(&mut spans, start, EMPHASIS),
+                None => literal(&mut spans, ")"),
+            },
+            Token::CloseSquare => match square.pop() {
+                Some(start) => multiply(&mut spans, start, 1.0 / EMPHASIS),
+                None => literal(&mut spans, "]"),
+            },
+            Token::Weight(w) => match round.pop() {
+                Some(start) => multiply(&mut spans, start, w),
+                None => literal(&mut spans, &format!(":{})", w)),
+            },
+        }
+    }
+    for start in round {
+        multiply(&mut spans, start, EMPHASIS);
+    }
+    for start in square {
+        multiply(&mut spans, start, 1.0 / EMPHASIS);
+    }
+
+    // Merge neighbours that ended up with the same weight.
+    let mut merged: Vec<WeightedSpan> = Vec::new();
+    for span in spans {
+        let span = WeightedSpan { weight: round_weight(span.weight), ..span };
+        match merged.last_mut() {
+            Some(last) if last.weight == span.weight => last.text.push_str(&span.text),
+            _ => merged.push(span),
+        }
+    }
+    merged
+}
+
+fn round_weight(w: f64) -> f64 {
+    (w * 100.0).round() / 100.0
+}
+
+fn format_weight(w: f64) -> String {
+    let s = format!("{:.2}", w);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+fn escape(text: &str, syntax: WeightSyntax) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        let special = matches!(c, '(' | ')' | '\\') || (syntax.square_brackets() && matches!(c, '[' | ']'));
+        if special { out.push('\\'); }
+        out.push(c);
+    }
+    out
+}
+
+/// Render spans with explicit `(text:weight)` groups for `syntax`.
+pub fn render(spans: &[WeightedSpan], syntax: WeightSyntax) -> String {
+    let mut out = String::new();
+    for span in spans {
+        let text = escape(&span.text, syntax);
+        if span.weight == 1.0 || span.text.trim().is_empty() {
+            out.push_str(&text);
+            continue;
+        }
+        // Keep surrounding whitespace outside the group.
+        let trimmed = text.trim();
+        let lead = &text[..text.len() - text.trim_start().len()];
+        let trail = &text[text.trim_end().len()..];
+        out.push_str(&format!("{}({}:{}){}", lead, trimmed, format_weight(span.weight), trail));
+    }
+    out
+}
+
+/// Rewrite `text` written in `from` syntax into normalized `to` syntax.
+pub fn convert(text: &str, from: WeightSyntax, to: WeightSyntax) -> String {
+    render(&parse(text, from), to)
+}
+
+/// Normalize text params when the payload asks for it.
+///
+/// `"normalize_weights": true` reads the prompts as A1111 syntax (a superset
+/// of ComfyUI's); a string such as `"comfy"` picks the source syntax instead.
+/// Output is always ComfyUI syntax. Returns whether anything was rewritten.
+pub fn apply_weight_normalization(payload: &mut Value) -> Result<bool, String> {
+    let from = match payload.get("normalize_weights") {
+        None | Some(Value::Bool(false)) | Some(Value::Null) => return Ok(false),
+        Some(Value::Bool(true)) => WeightSyntax::A1111,
+        Some(Value::String(s)) => WeightSyntax::parse(s)
+            .ok_or_else(|| format!("Unknown weight syntax '{}' (expected 'a1111' or 'comfy')", s))?,
+        Some(_) => return Err("'normalize_weights' must be a boolean or syntax name".to_string()),
+    };
+    let mut changed = false;
+    let mut rewrite = |obj: &mut serde_json::Map<String, Value>| {
+        for key in TEXT_PARAM_KEYS {
+            if let Some(Value::String(s)) = obj.get(*key) {
+                let normalized = convert(s, from, WeightSyntax::Comfy);
+                if &normalized != s {
+                    obj.insert(key.to_string(), Value::String(normalized));
+                    changed = true;
+                }
+            }
+        }
+    };
+    if let Some(obj) = payload.as_object_mut() {
+        rewrite(obj);
+    }
+    if let Some(obj) = payload.get_mut("params").and_then(|p| p.as_object_mut()) {
+        rewrite(obj);
+    }
+    Ok(changed)
+}
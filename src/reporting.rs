@@ -0,0 +1,134 @@
+//! Error reporting for proxies running unattended.
+//!
+//! Built with the `sentry` feature and given a `SENTRY_DSN`, the proxy sends
+//! handler panics and 5xx errors to Sentry, tagged with the request's
+//! method, route, API key name, tenant, and `X-Client`. Otherwise every
+//! function here is a no-op and errors are only logged.
+use axum::response::{IntoResponse, Response};
+use std::any::Any;
+use std::future::Future;
+
+use crate::config::Config;
+use crate::error::AppError;
+
+/// What an error report says about the request that failed.
+#[derive(Debug, Clone, Default)]
+pub struct RequestContext {
+    pub method: String,
+    /// Matched route, e.g. `/jobs/:id`, rather than the raw path.
+    pub route: String,
+    pub key_name: Option<String>,
+    pub tenant: Option<String>,
+    pub client: Option<String>,
+}
+
+/// Marks a response whose error should be reported: `AppError`s that render
+/// as 5xx, except maintenance rejections.
+#[derive(Debug, Clone)]
+pub struct ReportableError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Keeps the reporting client alive; dropping it flushes pending reports.
+pub struct ReportingGuard {
+    #[cfg(feature = "sentry")]
+    _client: Option<sentry::ClientInitGuard>,
+}
+
+/// Start reporting to `SENTRY_DSN`. Hold the guard for the life of the
+/// process.
+pub fn init(config: &Config) -> ReportingGuard {
+    #[cfg(feature = "sentry")]
+    {
+        let client = config.sentry_dsn.as_deref().map(|dsn| {
+            let client = sentry::init((dsn, sentry::ClientOptions {
+                release: sentry::release_name!(),
+                environment: config.sentry_environment.clone().map(Into::into),
+                ..Default::default()
+            }));
+            if client.is_enabled() {
+                tracing::info!("Reporting panics and 5xx errors to Sentry");
+            } else {
+                tracing::error!("SENTRY_DSN is invalid; errors won't be reported");
+            }
+            client
+        });
+        ReportingGuard { _client: client }
+    }
+    #[cfg(not(feature = "sentry"))]
+    {
+        if config.sentry_dsn.is_some() {
+            tracing::error!("SENTRY_DSN needs a build with --features sentry; ignoring it");
+        }
+        ReportingGuard {}
+    }
+}
+
+/// Whether reports are being sent anywhere.
+pub fn is_enabled() -> bool {
+    #[cfg(feature = "sentry")]
+    {
+        sentry::Hub::main().client().map(|c| c.is_enabled()).unwrap_or(false)
+    }
+    #[cfg(not(feature = "sentry"))]
+    {
+        false
+    }
+}
+
+/// Run `handler` with `context` attached to anything it reports: a panic
+/// while it runs, or a [`ReportableError`] in its response.
+pub async fn reported<F>(context: RequestContext, handler: F) -> Response
+where
+    F: Future<Output = Response>,
+{
+    #[cfg(feature = "sentry")]
+    {
+        use sentry::SentryFutureExt;
+
+        let hub = std::sync::Arc::new(sentry::Hub::new_from_top(sentry::Hub::main()));
+        hub.configure_scope(|scope| {
+            scope.set_tag("http.method", &context.method);
+            scope.set_tag("route", &context.route);
+            if let Some(name) = &context.key_name {
+                scope.set_tag("api_key", name);
+            }
+            if let Some(tenant) = &context.tenant {
+                scope.set_tag("tenant", tenant);
+            }
+            if let Some(client) = &context.client {
+                scope.set_tag("client", client);
+            }
+            scope.set_transaction(Some(&format!("{} {}", context.method, context.route)));
+        });
+        let response = handler.bind_hub(hub.clone()).await;
+        if let Some(error) = response.extensions().get::<ReportableError>() {
+            hub.configure_scope(|scope| {
+                scope.set_tag("error.code", error.code);
+                scope.set_tag("http.status_code", response.status().as_u16());
+            });
+            hub.capture_message(&error.message, sentry::Level::Error);
+        }
+        response
+    }
+    #[cfg(not(feature = "sentry"))]
+    {
+        let _ = context;
+        handler.await
+    }
+}
+
+/// Response for a handler that panicked, for `CatchPanicLayer`: a 500 with
+/// the usual error body. The panic itself is reported by the panic hook,
+/// with the request's context when it ran inside [`reported`].
+pub fn panic_response(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let detail = panic.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+    tracing::error!("Handler panicked: {}", detail);
+    let mut response = AppError::Internal("Internal server error".to_string()).into_response();
+    // Already reported by the panic hook.
+    response.extensions_mut().remove::<ReportableError>();
+    response
+}
@@ -0,0 +1,168 @@
+//! Assembling a job's frame outputs into one animation.
+//!
+//! GIF and APNG are encoded in-process. Animated WebP and MP4 shell out to
+//! `ffmpeg` and are only available when built with the `ffmpeg` feature.
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::imageops::FilterType;
+use image::{Delay, Frame, RgbaImage};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnimationFormat {
+    Gif,
+    Apng,
+    Webp,
+    Mp4,
+}
+
+impl AnimationFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "gif" => Some(AnimationFormat::Gif),
+            "apng" | "png" => Some(AnimationFormat::Apng),
+            "webp" => Some(AnimationFormat::Webp),
+            "mp4" => Some(AnimationFormat::Mp4),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "png",
+            AnimationFormat::Webp => "webp",
+            AnimationFormat::Mp4 => "mp4",
+        }
+    }
+
+    /// Whether this build can produce the format.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            AnimationFormat::Gif | AnimationFormat::Apng => true,
+            AnimationFormat::Webp | AnimationFormat::Mp4 => cfg!(feature = "ffmpeg"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnimationOptions {
+    pub format: AnimationFormat,
+    /// Frames per second.
+    pub fps: u32,
+    /// Loop forever; otherwise play once. MP4 ignores this.
+    pub looped: bool,
+}
+
+/// Decode `frames` (encoded images) into RGBA, scaling any that differ to
+/// the first frame's size.
+pub fn decode_frames(frames: &[Vec<u8>]) -> Result<Vec<RgbaImage>, String> {
+    let mut decoded: Vec<RgbaImage> = Vec::with_capacity(frames.len());
+    for (i, data) in frames.iter().enumerate() {
+        let image = image::load_from_memory(data).map_err(|e| format!("Failed to decode frame {}: {}", i, e))?;
+        let image = match decoded.first() {
+            Some(first) if first.dimensions() != (image.width(), image.height()) => {
+                image.resize_exact(first.width(), first.height(), FilterType::Triangle)
+            }
+            _ => image,
+        };
+        decoded.push(image.to_rgba8());
+    }
+    Ok(decoded)
+}
+
+/// Encode `frames` (encoded images, in display order) as one animation.
+pub fn assemble(frames: &[Vec<u8>], options: AnimationOptions) -> Result<Vec<u8>, String> {
+    if frames.len() < 2 {
+        return Err(format!("An animation needs at least 2 frames, got {}", frames.len()));
+    }
+    if !options.format.is_supported() {
+        return Err(format!("{} output requires building with the `ffmpeg` feature", options.format.extension()));
+    }
+    let fps = options.fps.max(1);
+    let frames = decode_frames(frames)?;
+    match options.format {
+        AnimationFormat::Gif => encode_gif(frames, fps, options.looped),
+        AnimationFormat::Apng => encode_apng(&frames, fps, options.looped),
+        #[cfg(feature = "ffmpeg")]
+        AnimationFormat::Webp | AnimationFormat::Mp4 => ffmpeg::encode(&frames, fps, options),
+        #[cfg(not(feature = "ffmpeg"))]
+        AnimationFormat::Webp | AnimationFormat::Mp4 => unreachable!("checked by is_supported"),
+    }
+}
+
+fn encode_gif(frames: Vec<RgbaImage>, fps: u32, looped: bool) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    {
+        let mut encoder = GifEncoder::new_with_speed(&mut out, 10);
+        // Without a repeat extension, a GIF plays once.
+        if looped {
+            encoder.set_repeat(Repeat::Infinite).map_err(|e| e.to_string())?;
+        }
+        let delay = Delay::from_numer_denom_ms(1000, fps);
+        encoder.encode_frames(frames.into_iter().map(|f| Frame::from_parts(f, 0, 0, delay)))
+            .map_err(|e| format!("Failed to encode GIF: {}", e))?;
+    }
+    Ok(out)
+}
+
+fn encode_apng(frames: &[RgbaImage], fps: u32, looped: bool) -> Result<Vec<u8>, String> {
+    let (width, height) = frames[0].dimensions();
+    let err = |e: png::EncodingError| format!("Failed to encode APNG: {}", e);
+    let mut out = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut out, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        // 0 plays forever.
+        encoder.set_animated(frames.len() as u32, if looped { 0 } else { 1 }).map_err(err)?;
+        encoder.set_frame_delay(1, fps.min(u16::MAX as u32) as u16).map_err(err)?;
+        let mut writer = encoder.write_header().map_err(err)?;
+        for frame in frames {
+            writer.write_image_data(frame.as_raw()).map_err(err)?;
+        }
+        writer.finish().map_err(err)?;
+    }
+    Ok(out)
+}
+
+#[cfg(feature = "ffmpeg")]
+mod ffmpeg {
+    use super::{AnimationFormat, AnimationOptions};
+    use image::RgbaImage;
+    use std::process::Command;
+
+    /// Write the frames as PNGs to a scratch directory and let ffmpeg encode them.
+    pub fn encode(frames: &[RgbaImage], fps: u32, options: AnimationOptions) -> Result<Vec<u8>, String> {
+        let dir = std::env::temp_dir().join(format!("comfyui_anim_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let result = run(&dir, frames, fps, options);
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    }
+
+    fn run(dir: &std::path::Path, frames: &[RgbaImage], fps: u32, options: AnimationOptions) -> Result<Vec<u8>, String> {
+        for (i, frame) in frames.iter().enumerate() {
+            let path = dir.join(format!("frame_{:05}.png", i));
+            frame.save(&path).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        }
+        let output = dir.join(format!("out.{}", options.format.extension()));
+        let mut cmd = Command::new("ffmpeg");
+        cmd.args(["-y", "-loglevel", "error", "-framerate", &fps.to_string(), "-i"])
+            .arg(dir.join("frame_%05d.png"));
+        match options.format {
+            AnimationFormat::Mp4 => {
+                // yuv420p needs even dimensions.
+                cmd.args(["-c:v", "libx264", "-pix_fmt", "yuv420p", "-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2"]);
+            }
+            _ => {
+                cmd.args(["-c:v", "libwebp", "-quality", "90", "-loop", if options.looped { "0" } else { "1" }]);
+            }
+        }
+        let result = cmd.arg(&output).output().map_err(|e| format!("Failed to run ffmpeg: {}", e))?;
+        if !result.status.success() {
+            return Err(format!("ffmpeg failed: {}", String::from_utf8_lossy(&result.stderr).trim()));
+        }
+        std::fs::read(&output).map_err(|e| format!("Failed to read {}: {}", output.display(), e))
+    }
+}
@@ -0,0 +1,99 @@
+//! Batch input files for `comfyctl prompt batch`.
+//!
+//! Each CSV row or JSONL line describes one prompt. Columns (or keys) are
+//! params and payload options as in a `/queue_prompt` body, e.g. `seed`,
+//! `text_positive`, or `filename_prefix`; dotted names such as
+//! `2.inputs.seed` are `--set` paths. Empty CSV cells leave a column unset
+//! for that row.
+use serde_json::{Map, Value};
+use std::path::Path;
+
+use crate::utils::prompt_ops::parse_value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchFormat {
+    Csv,
+    Jsonl,
+}
+
+impl BatchFormat {
+    /// The format of `path` by extension: `.csv`, or `.jsonl`/`.ndjson`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "csv" => Some(BatchFormat::Csv),
+            "jsonl" | "ndjson" => Some(BatchFormat::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+/// One queue payload per row of `data`, in order. Errors name the row
+/// (counting from 1, after the CSV header).
+pub fn read_batch_rows(data: &str, format: BatchFormat) -> Result<Vec<Value>, String> {
+    match format {
+        BatchFormat::Csv => read_csv(data),
+        BatchFormat::Jsonl => read_jsonl(data),
+    }
+}
+
+fn read_csv(data: &str) -> Result<Vec<Value>, String> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::Headers).from_reader(data.as_bytes());
+    let headers = reader.headers().map_err(|e| format!("Invalid CSV header: {}", e))?.clone();
+    if let Some(empty) = headers.iter().position(str::is_empty) {
+        return Err(format!("CSV column {} has no name", empty + 1));
+    }
+    let mut rows = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| format!("row {}: {}", i + 1, e))?;
+        let mut row = Map::new();
+        for (column, cell) in headers.iter().zip(record.iter()) {
+            if cell.is_empty() {
+                continue;
+            }
+            // Prompt text stays text even when it looks like a number.
+            let value = if column.starts_with("text") { Value::String(cell.to_string()) } else { parse_value(cell) };
+            row.insert(column.to_string(), value);
+        }
+        rows.push(into_payload(row));
+    }
+    Ok(rows)
+}
+
+fn read_jsonl(data: &str) -> Result<Vec<Value>, String> {
+    let mut rows = Vec::new();
+    for line in data.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let row = match serde_json::from_str::<Value>(line) {
+            Ok(Value::Object(row)) => row,
+            Ok(_) => return Err(format!("row {}: expected a JSON object", rows.len() + 1)),
+            Err(e) => return Err(format!("row {}: {}", rows.len() + 1, e)),
+        };
+        rows.push(into_payload(row));
+    }
+    Ok(rows)
+}
+
+/// Move dotted keys into `sets` as `path=value` strings.
+fn into_payload(row: Map<String, Value>) -> Value {
+    let mut payload = Map::new();
+    let mut sets = Vec::new();
+    for (key, value) in row {
+        if key.contains('.') {
+            let value = match value {
+                Value::String(s) => s,
+                other => other.to_string(),
+            };
+            sets.push(Value::String(format!("{}={}", key, value)));
+        } else {
+            payload.insert(key, value);
+        }
+    }
+    if !sets.is_empty() {
+        match payload.get_mut("sets").and_then(|v| v.as_array_mut()) {
+            Some(existing) => existing.extend(sets),
+            None => {
+                payload.insert("sets".to_string(), Value::Array(sets));
+            }
+        }
+    }
+    Value::Object(payload)
+}
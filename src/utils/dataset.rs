@@ -0,0 +1,167 @@
+//! Training datasets built from the static drive, for
+//! `comfyctl outputs export`.
+//!
+//! Selected images are copied out of the drive with one caption each, in a
+//! layout LoRA training tools read:
+//! - `kohya`: `<out>/<repeats>_<concept>/<image>` with `<image>.txt` beside
+//!   it, the folder convention of kohya-ss/sd-scripts and tools built on it.
+//! - `flat`: `<out>/<image>` and `<image>.txt`.
+//! - `imagefolder`: `<out>/<image>` plus one `metadata.jsonl` of
+//!   `{"file_name", "text"}` lines, as Hugging Face `datasets` and the
+//!   diffusers training scripts read.
+//!
+//! An image's caption is the one the `caption` drive action recorded, else
+//! its `.txt` sidecar, else the `text_positive` it was queued with (from the
+//! harvested `generation.json`).
+use serde_json::json;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::utils::drive_actions::{sidecar_path, FileRecord};
+use crate::utils::harvest::sidecar_for;
+
+/// Written by the `imagefolder` format.
+pub const METADATA_FILE: &str = "metadata.jsonl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatasetFormat {
+    Kohya,
+    Flat,
+    ImageFolder,
+}
+
+impl DatasetFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "kohya" => Some(DatasetFormat::Kohya),
+            "flat" => Some(DatasetFormat::Flat),
+            "imagefolder" | "hf" => Some(DatasetFormat::ImageFolder),
+            _ => None,
+        }
+    }
+}
+
+/// How an export is laid out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatasetLayout {
+    pub format: DatasetFormat,
+    /// Times each image is repeated per epoch (`kohya` only).
+    pub repeats: u32,
+    /// Concept folder name (`kohya` only).
+    pub concept: String,
+    /// Token put at the start of every caption, e.g. a LoRA trigger word.
+    pub trigger: Option<String>,
+}
+
+/// One image to export: its path under the drive root and its caption.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatasetItem {
+    pub path: String,
+    pub caption: String,
+}
+
+/// Whether training tools can read the file at `path` as an image.
+pub fn is_training_image(path: &str) -> bool {
+    let extension = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    matches!(extension.as_str(), "png" | "jpg" | "jpeg" | "webp")
+}
+
+/// Caption for `record`, a file under `root`: see the module docs.
+pub fn caption_for(root: &Path, record: &FileRecord) -> Option<String> {
+    let caption = record.caption.clone()
+        .or_else(|| std::fs::read_to_string(root.join(sidecar_path(&record.path))).ok())
+        .or_else(|| {
+            let sidecar = sidecar_for(root, &record.path)?;
+            sidecar["request"]["text_positive"].as_str().map(String::from)
+        })?;
+    Some(caption.trim().to_string()).filter(|c| !c.is_empty())
+}
+
+/// Whether `caption` has every one of `tags`, ignoring case: each tag's
+/// words must appear together in order, so `portrait` matches
+/// `1girl, portrait, smile` and `red hair` matches `a woman with red hair`.
+pub fn has_tags(caption: &str, tags: &[String]) -> bool {
+    let caption = words(caption);
+    tags.iter().all(|tag| {
+        let tag = words(tag);
+        !tag.is_empty() && caption.windows(tag.len()).any(|w| w == tag.as_slice())
+    })
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// File name for the image at drive path `path`: the path with its
+/// separators replaced, so outputs of different prompts don't collide.
+/// Paths can still map to the same name (`a_b/c.png` and `a/b_c.png`);
+/// [`export_dataset`] tells those apart.
+pub fn dataset_file_name(path: &str) -> String {
+    path.trim_start_matches('/').replace(['/', '\\'], "_")
+}
+
+/// `name`, or `name` with `_2`, `_3`, ... before its extension, whichever
+/// stem isn't in `used` yet (ignoring case, for case-insensitive
+/// filesystems). Stems rather than names must differ, since each image's
+/// caption goes in `<stem>.txt`.
+fn unique_file_name(name: &str, used: &mut HashSet<String>) -> String {
+    let path = Path::new(name);
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let mut candidate = stem.clone();
+    let mut n = 1;
+    while !used.insert(candidate.to_lowercase()) {
+        n += 1;
+        candidate = format!("{}_{}", stem, n);
+    }
+    format!("{}{}", candidate, extension)
+}
+
+/// `caption` with `trigger` in front, unless it's already there.
+pub fn with_trigger(caption: &str, trigger: Option<&str>) -> String {
+    match trigger.map(str::trim).filter(|t| !t.is_empty()) {
+        Some(trigger) if !words(caption).starts_with(&words(trigger)) => format!("{}, {}", trigger, caption),
+        _ => caption.to_string(),
+    }
+}
+
+/// Copy `items` (paths under `root`) into `out` in `layout`'s format,
+/// returning the image files written. Images whose names (or caption
+/// files) would clash get a `_2`, `_3`, ... suffix.
+pub fn export_dataset(root: &Path, items: &[DatasetItem], out: &Path, layout: &DatasetLayout) -> Result<Vec<PathBuf>, String> {
+    let dir = match layout.format {
+        DatasetFormat::Kohya => out.join(format!("{}_{}", layout.repeats.max(1), layout.concept)),
+        DatasetFormat::Flat | DatasetFormat::ImageFolder => out.to_path_buf(),
+    };
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let mut metadata = Vec::new();
+    let mut written = Vec::with_capacity(items.len());
+    let mut used = HashSet::new();
+    for item in items {
+        let name = unique_file_name(&dataset_file_name(&item.path), &mut used);
+        let target = dir.join(&name);
+        std::fs::copy(root.join(&item.path), &target)
+            .map_err(|e| format!("Failed to copy {}: {}", item.path, e))?;
+        let caption = with_trigger(&item.caption, layout.trigger.as_deref());
+        match layout.format {
+            DatasetFormat::ImageFolder => {
+                writeln!(metadata, "{}", json!({"file_name": name, "text": caption})).map_err(|e| e.to_string())?;
+            }
+            DatasetFormat::Kohya | DatasetFormat::Flat => {
+                let caption_file = target.with_extension("txt");
+                std::fs::write(&caption_file, caption)
+                    .map_err(|e| format!("Failed to write {}: {}", caption_file.display(), e))?;
+            }
+        }
+        written.push(target);
+    }
+    if layout.format == DatasetFormat::ImageFolder {
+        let path = dir.join(METADATA_FILE);
+        std::fs::write(&path, metadata).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+    Ok(written)
+}
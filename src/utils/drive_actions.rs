@@ -0,0 +1,263 @@
+//! Actions the static drive poller runs on each new file.
+//!
+//! `STATIC_DRIVE_ACTIONS` lists them in order; each sees what the earlier
+//! ones recorded, so e.g. `metadata,webhook` delivers the extracted metadata.
+//!
+//! - `thumbnail`: writes a downscaled PNG to `.thumbnails/<path>.png`.
+//! - `metadata`: reads PNG text chunks (ComfyUI stores `prompt` and
+//!   `workflow` there) into the file's index record.
+//! - `caption`: sends images to `CAPTION_URL` and stores the returned
+//!   caption in the record (and a `.txt` sidecar with `CAPTION_SIDECAR`).
+//! - `s3`: uploads the file to `S3_BUCKET` under `S3_PREFIX` + its path.
+//! - `webhook`: POSTs `{"event": "file_added", "file": <record>}` to
+//!   `STATIC_DRIVE_WEBHOOK_URL`.
+use flate2::read::ZlibDecoder;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::utils::s3::S3Target;
+
+/// Directory under the drive root where thumbnails are written.
+pub const THUMBNAIL_DIR: &str = ".thumbnails";
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "webp"];
+
+/// What the poller knows about a file under the drive.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FileRecord {
+    /// Path relative to the drive root, `/`-separated.
+    pub path: String,
+    pub size: u64,
+    pub modified_ms: u64,
+    pub processed_at_ms: u64,
+    /// PNG text chunks; JSON-valued chunks are parsed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Map<String, Value>>,
+    /// Thumbnail path relative to the drive root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
+    /// Caption returned by the `caption` action.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s3_url: Option<String>,
+    /// Where the `webhook` action delivered the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook_url: Option<String>,
+    /// First action failure; later actions are skipped for the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum DriveAction {
+    Thumbnail { max_size: u32 },
+    Metadata,
+    Caption { url: String, api_key: Option<String>, sidecar: bool },
+    S3(S3Target),
+    Webhook { url: String },
+}
+
+/// The configured action list. Unknown names and actions missing required
+/// settings are logged and left out.
+pub fn actions_from_config(config: &Config) -> Vec<DriveAction> {
+    config.static_drive_actions.iter()
+        .filter_map(|name| {
+            let action = match name.as_str() {
+                "thumbnail" => Ok(DriveAction::Thumbnail { max_size: config.thumbnail_size }),
+                "metadata" => Ok(DriveAction::Metadata),
+                "caption" => config.caption_url.clone()
+                    .map(|url| DriveAction::Caption { url, api_key: config.caption_api_key.clone(), sidecar: config.caption_sidecar })
+                    .ok_or_else(|| "the caption action requires CAPTION_URL".to_string()),
+                "s3" => S3Target::from_config(config).map(DriveAction::S3),
+                "webhook" => config.static_drive_webhook_url.clone()
+                    .map(|url| DriveAction::Webhook { url })
+                    .ok_or_else(|| "the webhook action requires STATIC_DRIVE_WEBHOOK_URL".to_string()),
+                other => Err(format!("unknown action '{}'", other)),
+            };
+            action.map_err(|e| tracing::error!("Ignoring STATIC_DRIVE_ACTIONS entry: {}", e)).ok()
+        })
+        .collect()
+}
+
+fn extension(path: &str) -> String {
+    Path::new(path).extension().map(|e| e.to_string_lossy().to_ascii_lowercase()).unwrap_or_default()
+}
+
+/// Whether `path` is an image or video, by extension.
+pub fn is_media(path: &str) -> bool {
+    let kind = content_type(path);
+    kind.starts_with("image/") || kind.starts_with("video/")
+}
+
+fn content_type(path: &str) -> &'static str {
+    match extension(path).as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "webp" => "image/webp",
+        "gif" => "image/gif",
+        "mp4" => "video/mp4",
+        "json" => "application/json",
+        "txt" => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+fn parse_text_value(text: &str) -> Value {
+    serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string()))
+}
+
+fn inflate(data: &[u8]) -> Option<String> {
+    let mut out = String::new();
+    ZlibDecoder::new(data).read_to_string(&mut out).ok()?;
+    Some(out)
+}
+
+/// Text chunks (`tEXt`, `zTXt`, `iTXt`) of a PNG, keyed by keyword. Returns
+/// `None` if `data` isn't a PNG.
+pub fn png_text_chunks(data: &[u8]) -> Option<Map<String, Value>> {
+    let mut rest = data.strip_prefix(PNG_SIGNATURE)?;
+    let mut chunks = Map::new();
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes(rest[..4].try_into().ok()?) as usize;
+        let kind = &rest[4..8];
+        let body = rest.get(8..8 + len)?;
+        rest = rest.get(12 + len..)?;
+        let Some(nul) = body.iter().position(|b| *b == 0) else { continue };
+        let keyword = String::from_utf8_lossy(&body[..nul]).to_string();
+        let after = &body[nul + 1..];
+        let text = match kind {
+            b"tEXt" => Some(String::from_utf8_lossy(after).to_string()),
+            b"zTXt" => after.get(1..).and_then(inflate),
+            b"iTXt" => {
+                // compression flag, method, language\0, translated keyword\0, text
+                let (flag, fields) = (after.first().copied(), after.get(2..).unwrap_or_default());
+                let mut parts = fields.splitn(3, |b| *b == 0);
+                let text = parts.nth(2).unwrap_or_default();
+                if flag == Some(1) { inflate(text) } else { Some(String::from_utf8_lossy(text).to_string()) }
+            }
+            b"IEND" => break,
+            _ => None,
+        };
+        if let Some(text) = text {
+            chunks.insert(keyword, parse_text_value(&text));
+        }
+    }
+    Some(chunks)
+}
+
+/// The caption in a caption endpoint's response: a JSON object's `caption`,
+/// `text`, or `generated_text` (also inside a one-element array, as Hugging
+/// Face returns it), or else the whole body as plain text.
+pub fn caption_from_response(body: &str) -> Option<String> {
+    let caption = match serde_json::from_str::<Value>(body) {
+        Ok(Value::String(text)) => text,
+        Ok(value @ (Value::Object(_) | Value::Array(_))) => {
+            let object = value.as_array().and_then(|a| a.first()).unwrap_or(&value);
+            ["caption", "text", "generated_text"].iter()
+                .find_map(|key| object.get(key).and_then(|v| v.as_str()))?
+                .to_string()
+        }
+        _ => body.to_string(),
+    };
+    Some(caption.trim().to_string()).filter(|c| !c.is_empty())
+}
+
+/// Caption sidecar for the image at `path`: the same path with a `.txt`
+/// extension, as captioned training datasets expect.
+pub fn sidecar_path(path: &str) -> String {
+    Path::new(path).with_extension("txt").to_string_lossy().to_string()
+}
+
+/// Write a PNG thumbnail of `source` no larger than `max_size` on either side.
+pub fn write_thumbnail(source: &Path, target: &Path, max_size: u32) -> Result<(), String> {
+    let image = image::open(source).map_err(|e| format!("Failed to decode {}: {}", source.display(), e))?;
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    image.thumbnail(max_size, max_size)
+        .save_with_format(target, image::ImageFormat::Png)
+        .map_err(|e| format!("Failed to write {}: {}", target.display(), e))
+}
+
+impl DriveAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DriveAction::Thumbnail { .. } => "thumbnail",
+            DriveAction::Metadata => "metadata",
+            DriveAction::Caption { .. } => "caption",
+            DriveAction::S3(_) => "s3",
+            DriveAction::Webhook { .. } => "webhook",
+        }
+    }
+
+    /// Run the action on `record` (a file under `root`), filling in what it
+    /// produces. Files the action doesn't apply to are left alone.
+    pub async fn run(&self, root: &Path, http: &reqwest::Client, record: &mut FileRecord) -> Result<(), String> {
+        let source = root.join(&record.path);
+        match self {
+            DriveAction::Thumbnail { max_size } => {
+                if !IMAGE_EXTENSIONS.contains(&extension(&record.path).as_str()) {
+                    return Ok(());
+                }
+                let rel = format!("{}/{}.png", THUMBNAIL_DIR, record.path);
+                let target: PathBuf = root.join(&rel);
+                let max_size = *max_size;
+                tokio::task::spawn_blocking(move || write_thumbnail(&source, &target, max_size))
+                    .await
+                    .map_err(|e| e.to_string())??;
+                record.thumbnail = Some(rel);
+            }
+            DriveAction::Metadata => {
+                if extension(&record.path) != "png" {
+                    return Ok(());
+                }
+                let data = tokio::fs::read(&source).await.map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+                record.metadata = png_text_chunks(&data).filter(|m| !m.is_empty());
+            }
+            DriveAction::Caption { url, api_key, sidecar } => {
+                if !IMAGE_EXTENSIONS.contains(&extension(&record.path).as_str()) {
+                    return Ok(());
+                }
+                let data = tokio::fs::read(&source).await.map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+                let filename = Path::new(&record.path).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+                let form = reqwest::multipart::Form::new()
+                    .part("image", reqwest::multipart::Part::bytes(data).file_name(filename))
+                    .text("path", record.path.clone());
+                let mut request = http.post(url).multipart(form);
+                if let Some(key) = api_key {
+                    request = request.bearer_auth(key);
+                }
+                let response = request.send().await.map_err(|e| format!("Caption request to {} failed: {}", url, e))?;
+                if !response.status().is_success() {
+                    return Err(format!("Caption endpoint {} returned {}", url, response.status()));
+                }
+                let body = response.text().await.map_err(|e| format!("Failed to read caption from {}: {}", url, e))?;
+                let caption = caption_from_response(&body).ok_or_else(|| format!("Caption endpoint {} returned no caption", url))?;
+                if *sidecar {
+                    let target = root.join(sidecar_path(&record.path));
+                    tokio::fs::write(&target, &caption).await.map_err(|e| format!("Failed to write {}: {}", target.display(), e))?;
+                }
+                record.caption = Some(caption);
+            }
+            DriveAction::S3(target) => {
+                let data = tokio::fs::read(&source).await.map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+                record.s3_url = Some(target.put_object(http, &record.path, data, content_type(&record.path)).await?);
+            }
+            DriveAction::Webhook { url } => {
+                let body = serde_json::json!({"event": "file_added", "file": record});
+                let response = http.post(url).json(&body).send().await
+                    .map_err(|e| format!("Webhook to {} failed: {}", url, e))?;
+                if !response.status().is_success() {
+                    return Err(format!("Webhook to {} returned {}", url, response.status()));
+                }
+                record.webhook_url = Some(url.clone());
+            }
+        }
+        Ok(())
+    }
+}
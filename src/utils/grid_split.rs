@@ -0,0 +1,69 @@
+//! Slicing grid images into their cells.
+//!
+//! Some workflows save a whole batch as one grid image. A queue payload's
+//! `"split_grid": {"rows", "cols"}` cuts each image output into its cells
+//! once the job finishes, and registers every cell as a job output.
+use image::ImageOutputFormat;
+use serde_json::Value;
+
+/// Largest accepted `rows` or `cols`.
+pub const MAX_GRID_SIDE: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GridSplit {
+    pub rows: u32,
+    pub cols: u32,
+}
+
+impl GridSplit {
+    /// The payload's `split_grid`, if any.
+    pub fn from_payload(payload: &Value) -> Result<Option<Self>, String> {
+        let Some(spec) = payload.get("split_grid") else { return Ok(None) };
+        let side = |key: &str| -> Result<u32, String> {
+            spec.get(key)
+                .and_then(|v| v.as_u64())
+                .filter(|n| (1..=MAX_GRID_SIDE as u64).contains(n))
+                .map(|n| n as u32)
+                .ok_or_else(|| format!("'split_grid.{}' must be an integer from 1 to {}", key, MAX_GRID_SIDE))
+        };
+        let split = GridSplit { rows: side("rows")?, cols: side("cols")? };
+        if split.tiles() < 2 {
+            return Err("'split_grid' must have more than one cell".to_string());
+        }
+        Ok(Some(split))
+    }
+
+    pub fn tiles(&self) -> u32 {
+        self.rows * self.cols
+    }
+
+    /// Cut `data` (an encoded image) into PNG cells, row by row. Cell edges
+    /// are rounded so every pixel lands in exactly one cell.
+    pub fn split(&self, data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+        let image = image::load_from_memory(data).map_err(|e| format!("Failed to decode grid image: {}", e))?;
+        let (width, height) = (image.width(), image.height());
+        if width < self.cols || height < self.rows {
+            return Err(format!("A {}x{} image can't be split into {} rows and {} columns", width, height, self.rows, self.cols));
+        }
+        let edge = |i: u32, total: u32, parts: u32| (i as u64 * total as u64 / parts as u64) as u32;
+        let mut cells = Vec::with_capacity(self.tiles() as usize);
+        for row in 0..self.rows {
+            let (y0, y1) = (edge(row, height, self.rows), edge(row + 1, height, self.rows));
+            for col in 0..self.cols {
+                let (x0, x1) = (edge(col, width, self.cols), edge(col + 1, width, self.cols));
+                let mut out = Vec::new();
+                image.crop_imm(x0, y0, x1 - x0, y1 - y0)
+                    .write_to(&mut std::io::Cursor::new(&mut out), ImageOutputFormat::Png)
+                    .map_err(|e| format!("Failed to encode grid cell: {}", e))?;
+                cells.push(out);
+            }
+        }
+        Ok(cells)
+    }
+}
+
+/// Filename for the cell at `row`/`col` of grid image `filename`.
+pub fn cell_filename(filename: &str, row: u32, col: u32) -> String {
+    let stem = filename.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(filename);
+    format!("{}_r{}c{}.png", stem, row + 1, col + 1)
+}
@@ -0,0 +1,119 @@
+//! Copy finished jobs' outputs from ComfyUI onto the static drive
+//! (`HARVEST_OUTPUTS`).
+//!
+//! Each completed job's images land in `images/<prompt_id>/` under the drive
+//! root, next to a `generation.json` sidecar with what produced them: the
+//! queue request, the prompt graph ComfyUI ran, and where each file came
+//! from. The sidecar is written last, so its presence marks the job done.
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Directory under the drive root that harvested outputs go in.
+pub const HARVEST_DIR: &str = "images";
+/// Sidecar written next to each job's harvested outputs.
+pub const SIDECAR_FILE: &str = "generation.json";
+/// How far behind the present [`OutputHarvester::advance`] keeps its
+/// watermark, for jobs that reach the store after they were created (e.g.
+/// from another replica).
+pub const SETTLE_MS: u64 = 60 * 60 * 1000;
+
+pub struct OutputHarvester {
+    root: PathBuf,
+    /// Jobs created before this aren't checked: at first, so enabling the
+    /// harvester doesn't copy the whole history; then, as a watermark below
+    /// which every job is done.
+    since_ms: AtomicU64,
+    /// Job ids already harvested (or failed) since the watermark, with when
+    /// they were created, so they aren't checked again.
+    done: Mutex<HashMap<String, u64>>,
+}
+
+impl OutputHarvester {
+    pub fn new(root: impl Into<PathBuf>, since_ms: u64) -> Self {
+        OutputHarvester { root: root.into(), since_ms: AtomicU64::new(since_ms), done: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn since_ms(&self) -> u64 {
+        self.since_ms.load(Ordering::Relaxed)
+    }
+
+    /// Raise the watermark to `since_ms` (never past [`SETTLE_MS`] ago, nor
+    /// back), forgetting the done jobs created before it.
+    pub fn advance(&self, since_ms: u64, now_ms: u64) {
+        let since_ms = since_ms.min(now_ms.saturating_sub(SETTLE_MS));
+        let since_ms = self.since_ms.fetch_max(since_ms, Ordering::Relaxed).max(since_ms);
+        if let Ok(mut done) = self.done.lock() {
+            done.retain(|_, created_at_ms| *created_at_ms >= since_ms);
+        }
+    }
+
+    /// Where `prompt_id`'s outputs go.
+    pub fn dir_for(&self, prompt_id: &str) -> PathBuf {
+        self.root.join(HARVEST_DIR).join(prompt_id)
+    }
+
+    pub fn is_done(&self, job_id: &str) -> bool {
+        self.done.lock().map(|done| done.contains_key(job_id)).unwrap_or(false)
+    }
+
+    /// Stop checking `job_id`, created at `created_at_ms`.
+    pub fn mark_done(&self, job_id: &str, created_at_ms: u64) {
+        if let Ok(mut done) = self.done.lock() {
+            done.insert(job_id.to_string(), created_at_ms);
+        }
+    }
+
+    /// How many done jobs are remembered.
+    pub fn done_len(&self) -> usize {
+        self.done.lock().map(|done| done.len()).unwrap_or(0)
+    }
+
+    /// Whether `prompt_id`'s sidecar exists, e.g. from before a restart.
+    pub fn is_harvested(&self, prompt_id: &str) -> bool {
+        self.dir_for(prompt_id).join(SIDECAR_FILE).is_file()
+    }
+
+    /// Write `files` (name, contents) and then the sidecar under
+    /// `prompt_id`'s directory. Returns the paths written, relative to the
+    /// drive root.
+    pub fn store(&self, prompt_id: &str, files: &[(String, Vec<u8>)], sidecar: &Value) -> Result<Vec<String>, String> {
+        if !is_plain_name(prompt_id) {
+            return Err(format!("Invalid prompt id '{}'", prompt_id));
+        }
+        let dir = self.dir_for(prompt_id);
+        std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let mut written = Vec::new();
+        for (name, data) in files {
+            if !is_plain_name(name) {
+                return Err(format!("Invalid output filename '{}'", name));
+            }
+            let path = dir.join(name);
+            std::fs::write(&path, data).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+            written.push(format!("{}/{}/{}", HARVEST_DIR, prompt_id, name));
+        }
+        let data = serde_json::to_vec_pretty(sidecar).map_err(|e| e.to_string())?;
+        let path = dir.join(SIDECAR_FILE);
+        std::fs::write(&path, data).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        Ok(written)
+    }
+}
+
+/// The sidecar of the job that produced `path` (relative to the drive
+/// `root`), for files harvested into `images/<prompt_id>/`.
+pub fn sidecar_for(root: &Path, path: &str) -> Option<Value> {
+    let mut parts = path.split('/');
+    let (Some(HARVEST_DIR), Some(prompt_id), Some(_), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else { return None };
+    if !is_plain_name(prompt_id) {
+        return None;
+    }
+    let data = std::fs::read(root.join(HARVEST_DIR).join(prompt_id).join(SIDECAR_FILE)).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+/// A single non-hidden path component.
+fn is_plain_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && Path::new(name).file_name().is_some_and(|n| n == name)
+}
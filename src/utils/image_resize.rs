@@ -0,0 +1,103 @@
+//! Resizing uploaded source images to a workflow's dimensions.
+//!
+//! img2img and inpainting workflows fail deep inside ComfyUI when the loaded
+//! image doesn't match the latent size the rest of the graph expects, so
+//! `/upload_image` can bring images to an exact size before forwarding them.
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+use serde::Serialize;
+
+use crate::config::Config;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizeMode {
+    /// Scale to cover the target and center-crop the overflow.
+    Crop,
+    /// Scale to fit inside the target and pad the rest with black.
+    Pad,
+    /// Scale each side independently, ignoring the aspect ratio.
+    Stretch,
+}
+
+impl ResizeMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "crop" => Some(ResizeMode::Crop),
+            "pad" => Some(ResizeMode::Pad),
+            "stretch" => Some(ResizeMode::Stretch),
+            _ => None,
+        }
+    }
+}
+
+/// Server-wide resize settings for uploads.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResizeDefaults {
+    /// Resize every upload this way unless the request opts out.
+    pub mode: Option<ResizeMode>,
+    /// Target size when the request names neither a size nor a workflow.
+    pub size: Option<(u32, u32)>,
+}
+
+impl ResizeDefaults {
+    pub fn from_config(config: &Config) -> Self {
+        let mode = config.upload_resize.as_deref().and_then(|m| {
+            let mode = ResizeMode::parse(m);
+            if mode.is_none() {
+                tracing::error!("Ignoring UPLOAD_RESIZE '{}': expected crop, pad, or stretch", m);
+            }
+            mode
+        });
+        ResizeDefaults { mode, size: config.upload_resize_width.zip(config.upload_resize_height) }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ResizedImage {
+    pub bytes: Vec<u8>,
+    /// The upload's filename, with a `.png` extension when it was re-encoded as PNG.
+    pub filename: String,
+    pub from: (u32, u32),
+    pub to: (u32, u32),
+}
+
+fn is_jpeg(filename: &str) -> bool {
+    let lower = filename.to_ascii_lowercase();
+    lower.ends_with(".jpg") || lower.ends_with(".jpeg")
+}
+
+/// Bring `data` to exactly `width`x`height`. JPEGs stay JPEGs; everything
+/// else is written as PNG. Images already at the target size are returned
+/// unchanged.
+pub fn resize_upload(data: &[u8], filename: &str, width: u32, height: u32, mode: ResizeMode) -> Result<ResizedImage, String> {
+    let image = image::load_from_memory(data).map_err(|e| format!("Failed to decode '{}': {}", filename, e))?;
+    let from = (image.width(), image.height());
+    if from == (width, height) {
+        return Ok(ResizedImage { bytes: data.to_vec(), filename: filename.to_string(), from, to: from });
+    }
+
+    let resized = match mode {
+        ResizeMode::Crop => image.resize_to_fill(width, height, FilterType::Lanczos3),
+        ResizeMode::Stretch => image.resize_exact(width, height, FilterType::Lanczos3),
+        ResizeMode::Pad => {
+            let scaled = image.resize(width, height, FilterType::Lanczos3).to_rgba8();
+            let mut canvas = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+            let x = (width - scaled.width()) / 2;
+            let y = (height - scaled.height()) / 2;
+            image::imageops::overlay(&mut canvas, &scaled, x as i64, y as i64);
+            DynamicImage::ImageRgba8(canvas)
+        }
+    };
+
+    let mut bytes = Vec::new();
+    let (resized, format, filename) = if is_jpeg(filename) {
+        (DynamicImage::ImageRgb8(resized.to_rgb8()), ImageOutputFormat::Jpeg(95), filename.to_string())
+    } else {
+        let stem = filename.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(filename);
+        (resized, ImageOutputFormat::Png, format!("{}.png", stem))
+    };
+    resized.write_to(&mut std::io::Cursor::new(&mut bytes), format)
+        .map_err(|e| format!("Failed to encode '{}': {}", filename, e))?;
+    Ok(ResizedImage { bytes, filename, from, to: (width, height) })
+}
@@ -0,0 +1,127 @@
+//! Logging setup shared by the server and `comfyctl`.
+//!
+//! `LOG_FORMAT` picks human-readable text or one JSON object per line,
+//! `LOG_LEVEL` sets the filter when `RUST_LOG` is unset, and `LOG_DIR`
+//! additionally writes logs to a rolling file there (`<app>.<date>.log`,
+//! rotated per `LOG_ROTATION`).
+use tracing::Subscriber;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::config::Config;
+use crate::utils::prompt_ops::TRACE_TARGET;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// `LOG_ROTATION` as a [`Rotation`]: `hourly`, `daily`, or `never`.
+pub fn parse_rotation(s: &str) -> Option<Rotation> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "hourly" => Some(Rotation::HOURLY),
+        "daily" => Some(Rotation::DAILY),
+        "never" => Some(Rotation::NEVER),
+        _ => None,
+    }
+}
+
+/// Where console logs go: the server logs to stdout, `comfyctl` to stderr
+/// so its output stays pipeable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    Stdout,
+    Stderr,
+}
+
+/// Install the global subscriber for `app` (also the log file's name).
+/// Bad settings are reported as warnings once logging is up rather than
+/// failing startup; a log file that can't be opened leaves console logging.
+/// Fails only if a global subscriber is already installed.
+pub fn init_logging(config: &Config, app: &str, console: Console, trace_prompt_ops: bool) -> Result<(), String> {
+    let mut warnings = Vec::new();
+    let format = LogFormat::parse(&config.log_format).unwrap_or_else(|| {
+        warnings.push(format!("Unknown LOG_FORMAT '{}', using text", config.log_format));
+        LogFormat::Text
+    });
+    let filter = log_filter(config.log_level.as_deref(), trace_prompt_ops).unwrap_or_else(|e| {
+        warnings.push(e);
+        log_filter(None, trace_prompt_ops).unwrap_or_default()
+    });
+    let console_layer = match console {
+        Console::Stdout => fmt_layer(format, std::io::stdout, true),
+        Console::Stderr => fmt_layer(format, std::io::stderr, true),
+    };
+    let file_layer = config.log_dir.as_deref().and_then(|dir| {
+        let rotation = parse_rotation(&config.log_rotation).unwrap_or_else(|| {
+            warnings.push(format!("Unknown LOG_ROTATION '{}', rotating daily", config.log_rotation));
+            Rotation::DAILY
+        });
+        let appender = RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(app)
+            .filename_suffix("log")
+            .build(dir);
+        match appender {
+            Ok(appender) => Some(fmt_layer(format, appender, false)),
+            Err(e) => {
+                warnings.push(format!("Not logging to {}: {}", dir, e));
+                None
+            }
+        }
+    });
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(console_layer)
+        .with(file_layer)
+        .try_init()
+        .map_err(|e| format!("Logging was already initialized: {}", e))?;
+    for warning in warnings {
+        tracing::warn!("{}", warning);
+    }
+    Ok(())
+}
+
+/// Log filter from `RUST_LOG`, or `level` (`LOG_LEVEL` directives such as
+/// `info` or `info,comfyui_api_proxy=debug`) when it's unset, with
+/// [`TRACE_TARGET`] raised to debug when `trace_prompt_ops` is set.
+pub fn log_filter(level: Option<&str>, trace_prompt_ops: bool) -> Result<EnvFilter, String> {
+    let filter = match level.filter(|_| std::env::var_os(EnvFilter::DEFAULT_ENV).is_none()) {
+        Some(level) => EnvFilter::try_new(level).map_err(|e| format!("Invalid LOG_LEVEL '{}': {}", level, e))?,
+        None => EnvFilter::from_default_env(),
+    };
+    if !trace_prompt_ops {
+        return Ok(filter);
+    }
+    Ok(match format!("{}=debug", TRACE_TARGET).parse() {
+        Ok(directive) => filter.add_directive(directive),
+        Err(_) => filter,
+    })
+}
+
+fn fmt_layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer).with_ansi(ansi);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
@@ -1,3 +1,14 @@
 pub mod static_drive_poller;
+pub mod animation;
+pub mod dataset;
+pub mod drive_actions;
+pub mod grid_split;
+pub mod harvest;
+pub mod image_resize;
+pub mod s3;
 pub mod prompt_ops;
 pub mod prompt_build;
+pub mod time;
+pub mod logging;
+pub mod batch_file;
+pub mod zip_stream;
@@ -1,39 +1,243 @@
 use serde_json::{json, Value};
 use tokio::fs;
 
-use crate::utils::prompt_ops::{apply_params_map, apply_set_path, ensure_filename_prefix, parse_set_pairs};
+use crate::error::{AppError, AppResult};
+use crate::prompt::constructor::PromptConstructor;
+use crate::workflow::bypass::{apply_groups, bypass_nodes, mute_nodes, select_nodes, toggle_stage};
+use crate::workflow::manager::{is_valid_workflow_name, WorkflowManager};
+use crate::utils::prompt_ops::{apply_default_negative, apply_params_map, apply_set_path, ensure_filename_prefix, init_image_ids, inject_vae_loader, parse_set_pairs, TitlePatterns};
 
-pub async fn resolve_prompt_root_from_payload(payload: &Value, prompts_dir: &str) -> Result<Value, String> {
+/// Resolve the `{"prompt": {...}}` body for a queue payload.
+///
+/// Uses `payload.prompt` when present, otherwise loads `payload.workflow` from
+/// `prompts_dir`, falling back to `default_workflow` when neither is given.
+/// Stored workflows that `extends` another are flattened, then `$include`
+/// directives are expanded, then `{{placeholders}}` are filled from the
+/// payload's `inputs` over the workflow's `defaults` (which are removed, as
+/// ComfyUI would take them for a node). A workflow that doesn't exist is
+/// [`AppError::NotFound`]; a `workflow` that isn't a plain name (see
+/// [`is_valid_workflow_name`]) is rejected before any path is built.
+pub async fn resolve_prompt_root_from_payload(payload: &Value, prompts_dir: &str, default_workflow: Option<&str>) -> AppResult<Value> {
     if let Some(prompt) = payload.get("prompt").cloned() {
         return Ok(json!({"prompt": prompt}));
     }
-    let workflow_name = payload.get("workflow")
-        .and_then(|v| v.as_str())
-        .ok_or("Either 'prompt' or 'workflow' must be provided")?;
+    let workflow_name = payload.get("workflow").and_then(|v| v.as_str());
+    if let Some(name) = workflow_name.filter(|n| !is_valid_workflow_name(n)) {
+        return Err(AppError::InvalidField { field: "workflow".to_string(), message: format!("Invalid workflow name '{}'", name) });
+    }
+    let workflow_name = workflow_name
+        .or(default_workflow)
+        .ok_or_else(|| AppError::BadRequest("Either 'prompt' or 'workflow' must be provided (and no DEFAULT_WORKFLOW is configured)".to_string()))?;
     let workflow_path = format!("{}/{}.json", prompts_dir.trim_end_matches('/'), workflow_name);
     let workflow_content = fs::read_to_string(&workflow_path)
         .await
-        .map_err(|e| format!("Failed to read workflow file: {}", e))?;
-    let wf: Value = serde_json::from_str(&workflow_content)
-        .map_err(|e| format!("Failed to parse workflow JSON: {}", e))?;
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => AppError::NotFound(format!("Workflow '{}' not found", workflow_name)),
+            _ => AppError::WorkflowManagement(format!("Failed to read workflow file: {}", e)),
+        })?;
+    let mut wf: Value = serde_json::from_str(&workflow_content)
+        .map_err(|e| AppError::WorkflowManagement(format!("Failed to parse workflow JSON: {}", e)))?;
+    if wf.get("extends").is_some() {
+        wf = PromptConstructor::with_dir(prompts_dir).resolve_extends(&wf)?;
+    }
+    WorkflowManager::with_dir(prompts_dir).expand_includes(&mut wf).map_err(AppError::WorkflowManagement)?;
+    let inputs = payload.get("inputs").cloned().unwrap_or_else(|| json!({}));
+    if !inputs.is_object() {
+        return Err(AppError::InvalidField { field: "inputs".to_string(), message: "'inputs' must be an object of placeholder values".to_string() });
+    }
+    let wf = PromptConstructor::new().construct_prompt(&wf, &inputs)?;
     Ok(if wf.get("prompt").is_some() { wf } else { json!({"prompt": wf}) })
 }
 
-pub fn apply_overrides_from_payload(root: &mut Value, payload: &Value) -> Result<(), String> {
+/// Params accepted at the top level of a queue payload as well as under `params`.
+pub const TOP_LEVEL_PARAM_KEYS: &[&str] = &[
+    "seed","steps","cfg","sampler_name","scheduler","denoise",
+    "width","height","batch_size","ckpt_name","text","text_positive","text_negative",
+    "upscale_method","upscale_by","vae_name","clip_skip","image"
+];
+
+/// Most prompts one `/queue_batch` request may queue.
+pub const MAX_QUEUE_BATCH: usize = 100;
+
+/// Expand a `/queue_batch` body into one queue payload per prompt.
+///
+/// The body is either an array of payloads or an object whose `prompts`
+/// array holds them; the object's other keys are shared settings each
+/// prompt is merged over. A prompt's keys replace shared ones, except that
+/// `params` and `groups` merge key by key and `sets` are appended, so
+/// `{"workflow": "sdxl", "steps": 30, "prompts": [{"seed": 1}, {"seed": 2}]}`
+/// queues one workflow with two seeds.
+pub fn expand_queue_batch(body: &Value) -> Result<Vec<Value>, String> {
+    let (shared, items) = match body {
+        Value::Array(items) => (serde_json::Map::new(), items),
+        Value::Object(obj) => match obj.get("prompts") {
+            Some(Value::Array(items)) => {
+                let mut shared = obj.clone();
+                shared.remove("prompts");
+                (shared, items)
+            }
+            _ => return Err("Body must be an array of prompts or an object with a 'prompts' array".to_string()),
+        },
+        _ => return Err("Body must be an array of prompts or an object with a 'prompts' array".to_string()),
+    };
+    if items.is_empty() {
+        return Err("At least one prompt is required".to_string());
+    }
+    if items.len() > MAX_QUEUE_BATCH {
+        return Err(format!("At most {} prompts can be queued per batch", MAX_QUEUE_BATCH));
+    }
+    items.iter().enumerate().map(|(i, item)| {
+        let item = item.as_object().ok_or_else(|| format!("prompts[{}] must be an object", i))?;
+        let mut merged = shared.clone();
+        for (k, v) in item {
+            match (k.as_str(), merged.get_mut(k), v) {
+                ("params" | "groups", Some(Value::Object(base)), Value::Object(over)) => {
+                    base.extend(over.iter().map(|(k, v)| (k.clone(), v.clone())));
+                }
+                ("sets", Some(Value::Array(base)), Value::Array(over)) => base.extend(over.iter().cloned()),
+                _ => { merged.insert(k.clone(), v.clone()); }
+            }
+        }
+        // Top-level params win over `params`, so a prompt's `params` must
+        // also displace shared top-level keys of the same name.
+        if let Some(params) = item.get("params").and_then(|p| p.as_object()) {
+            for k in params.keys().filter(|k| TOP_LEVEL_PARAM_KEYS.contains(&k.as_str()) && !item.contains_key(*k)) {
+                merged.remove(k);
+            }
+        }
+        Ok(Value::Object(merged))
+    }).collect()
+}
+
+/// Payload keys that change the graph without being node params.
+const GRAPH_OPTION_KEYS: &[&str] = &["variation", "detail_faces", "disable_nodes"];
+
+/// Whether the payload asks for any param, `sets`, or graph option overrides.
+pub fn payload_has_overrides(payload: &Value) -> bool {
+    payload.get("params").is_some()
+        || payload.get("sets").is_some()
+        || TOP_LEVEL_PARAM_KEYS.iter().chain(GRAPH_OPTION_KEYS).any(|k| payload.get(*k).is_some())
+}
+
+/// Resolve UI groups for submission using the payload's
+/// `"groups": {"<title>": true|false}`. Always call this before submitting a
+/// stored workflow: it also drops nodes the UI had muted or bypassed.
+/// Returns warnings for unknown group names.
+pub fn apply_group_toggles(root: &mut Value, payload: &Value) -> Result<Vec<String>, String> {
+    let overrides: Vec<(String, bool)> = match payload.get("groups") {
+        None => Vec::new(),
+        Some(Value::Object(map)) => map.iter()
+            .map(|(k, v)| v.as_bool().map(|on| (k.clone(), on)).ok_or_else(|| format!("groups.{} must be a boolean", k)))
+            .collect::<Result<_, _>>()?,
+        Some(_) => return Err("'groups' must be an object of group title -> boolean".to_string()),
+    };
+    match root.get_mut("prompt") {
+        Some(graph) => Ok(apply_groups(graph, &overrides)),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Remove the nodes selected by the payload's `disable_nodes` (node ids,
+/// `title:<title>`, `class:<class_type>`, or `group:<title>`), bypassing them by default or
+/// muting them with `"disable_mode": "mute"`. Returns warnings for selectors
+/// that matched nothing and for dependent nodes removed along the way.
+pub fn apply_disable_nodes(root: &mut Value, payload: &Value) -> Result<Vec<String>, String> {
+    let Some(value) = payload.get("disable_nodes") else { return Ok(Vec::new()) };
+    let selectors: Vec<&str> = value.as_array()
+        .and_then(|a| a.iter().map(|v| v.as_str()).collect::<Option<Vec<_>>>())
+        .ok_or("'disable_nodes' must be an array of strings")?;
+    let mute = match payload.get("disable_mode").and_then(|v| v.as_str()) {
+        None | Some("bypass") => false,
+        Some("mute") => true,
+        Some(other) => return Err(format!("Unknown disable_mode '{}' (expected 'bypass' or 'mute')", other)),
+    };
+    let graph = root.get_mut("prompt").ok_or("Missing 'prompt' in body")?;
+    let mut warnings = Vec::new();
+    let mut ids = Vec::new();
+    for selector in selectors {
+        let matched = select_nodes(graph, selector);
+        if matched.is_empty() {
+            warnings.push(format!("disable_nodes: '{}' matched no node", selector));
+        }
+        ids.extend(matched);
+    }
+    ids.sort();
+    ids.dedup();
+    let removed = if mute { mute_nodes(graph, &ids) } else { bypass_nodes(graph, &ids) };
+    let dependents: Vec<&str> = removed.iter().filter(|id| !ids.contains(id)).map(String::as_str).collect();
+    if !dependents.is_empty() {
+        warnings.push(format!("disable_nodes: also removed dependent nodes {}", dependents.join(", ")));
+    }
+    Ok(warnings)
+}
+
+/// Switch the face-detailer stage (nodes matching `selectors`) on or off per
+/// the payload's `detail_faces`. Returns the ids of bypassed nodes.
+pub fn apply_detail_faces(root: &mut Value, payload: &Value, selectors: &[String]) -> Result<Vec<String>, String> {
+    let Some(value) = payload.get("detail_faces") else { return Ok(Vec::new()) };
+    let enabled = value.as_bool().ok_or("'detail_faces' must be a boolean")?;
+    let graph = root.get_mut("prompt").ok_or("Missing 'prompt' in body")?;
+    toggle_stage(graph, selectors, enabled).map_err(|e| format!("'detail_faces': {}", e))
+}
+
+/// Split a combined `"text": "positive <delimiter> negative"` param into
+/// `text_positive`/`text_negative`, at the top level and under `params`.
+/// The payload's `text_delimiter` overrides `delimiter`. Explicit
+/// `text_positive`/`text_negative` keys win over the split halves. Returns
+/// whether any text was split.
+pub fn split_combined_text(payload: &mut Value, delimiter: &str) -> Result<bool, String> {
+    let delimiter = match payload.get("text_delimiter") {
+        None => delimiter.to_string(),
+        Some(Value::String(d)) if !d.is_empty() => d.clone(),
+        Some(_) => return Err("'text_delimiter' must be a non-empty string".to_string()),
+    };
+    let split = |obj: &mut serde_json::Map<String, Value>| {
+        let Some((pos, neg)) = obj.get("text").and_then(|v| v.as_str()).and_then(|t| t.split_once(delimiter.as_str())) else {
+            return false;
+        };
+        let (pos, neg) = (pos.trim().to_string(), neg.trim().to_string());
+        obj.remove("text");
+        obj.entry("text_positive").or_insert(Value::String(pos));
+        if !neg.is_empty() {
+            obj.entry("text_negative").or_insert(Value::String(neg));
+        }
+        true
+    };
+    let mut changed = payload.as_object_mut().map(split).unwrap_or(false);
+    if let Some(params) = payload.get_mut("params").and_then(|p| p.as_object_mut()) {
+        changed |= split(params);
+    }
+    Ok(changed)
+}
+
+/// Apply `params`, top-level params, and `sets` from a queue payload.
+/// Returns warnings for params that matched no node input and `sets` paths
+/// that couldn't be applied. `patterns` locate titled text nodes.
+///
+/// With `"inject_vae": true`, a `vae_name` param adds a VAELoader (rewiring
+/// VAE inputs to it) when the graph has none.
+pub fn apply_overrides_from_payload(root: &mut Value, payload: &Value, patterns: &TitlePatterns) -> Result<Vec<String>, String> {
+    let mut warnings = Vec::new();
     // Merge params from `params` and convenient top-level keys
     let mut params_obj = serde_json::Map::new();
     if let Some(params) = payload.get("params").and_then(|v| v.as_object()) {
         for (k, v) in params.iter() { params_obj.insert(k.clone(), v.clone()); }
     }
-    let top_keys = [
-        "seed","steps","cfg","sampler_name","scheduler","denoise",
-        "width","height","batch_size","ckpt_name","text","text_positive","text_negative"
-    ];
-    for k in top_keys.iter() {
+    for k in TOP_LEVEL_PARAM_KEYS.iter() {
         if let Some(v) = payload.get(*k) { params_obj.insert((*k).to_string(), v.clone()); }
     }
+    if let Some(graph) = root.get("prompt").filter(|_| params_obj.contains_key("image")) {
+        init_image_ids(graph)?;
+    }
     if !params_obj.is_empty() {
-        if let Some(graph) = root.get_mut("prompt") { apply_params_map(graph, &Value::Object(params_obj)); }
+        if let Some(graph) = root.get_mut("prompt") {
+            if let (Some(true), Some(vae_name)) = (payload.get("inject_vae").and_then(|v| v.as_bool()), params_obj.get("vae_name")) {
+                inject_vae_loader(graph, vae_name);
+            }
+            for key in apply_params_map(graph, &Value::Object(params_obj), patterns) {
+                warnings.push(format!("param '{}' matched no node input", key));
+            }
+        }
     }
 
     if let Some(sets) = payload.get("sets").and_then(|v| v.as_array()) {
@@ -45,11 +249,25 @@ pub fn apply_overrides_from_payload(root: &mut Value, payload: &Value) -> Result
                     let graph = root.get_mut("prompt").ok_or("Missing 'prompt' in body")?;
                     apply_set_path(graph, &path, new_val.clone())
                 };
-                if !applied_to_graph { let _ = apply_set_path(root, &path, new_val); }
+                if !applied_to_graph && !apply_set_path(root, &path, new_val) {
+                    warnings.push(format!("could not apply set to path: {}", path.join(".")));
+                }
             }
         }
     }
-    Ok(())
+    Ok(warnings)
+}
+
+/// Apply a default negative prompt unless the payload supplies `text_negative`
+/// (top-level or under `params`). Only fills an empty negative node.
+pub fn apply_default_negative_from_payload(root: &mut Value, payload: &Value, negative: Option<&str>, patterns: &TitlePatterns) {
+    let Some(negative) = negative else { return };
+    let supplied = payload.get("text_negative").is_some()
+        || payload.get("params").and_then(|p| p.get("text_negative")).is_some();
+    if supplied { return; }
+    if let Some(graph) = root.get_mut("prompt") {
+        apply_default_negative(graph, negative, patterns);
+    }
 }
 
 pub fn ensure_defaults_on_root(root: &mut Value, filename_prefix: Option<&str>) {
@@ -1,4 +1,14 @@
-use serde_json::{json, Map, Value};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+
+use crate::config::Config;
+use crate::jobs::batch::{upstream_batch, LatentBatch};
+use crate::workflow::compose::{add_node, link_source};
+
+/// Tracing target for parameter-routing decisions. Enable with
+/// `TRACE_PROMPT_OPS=true` (server), `comfyctl --debug`, or
+/// `RUST_LOG=prompt_ops=debug`.
+pub const TRACE_TARGET: &str = "prompt_ops";
 
 pub fn parse_set_pairs(items: &[String]) -> Result<Vec<(Vec<String>, Value)>, String> {
     let mut out = Vec::new();
@@ -65,8 +75,21 @@ pub fn ensure_filename_prefix(graph: &mut Value, default_prefix: &str) {
     }
 }
 
-// Known parameter keys we support mapping into node inputs dynamically.
-const KNOWN_PARAM_KEYS: &[&str] = &[
+/// Append `_<suffix>` to every SaveImage node's `filename_prefix`, e.g. to
+/// tell a sweep's outputs apart. Run after defaults are applied.
+pub fn append_filename_suffix(graph: &mut Value, suffix: &str) {
+    let Some(nodes) = graph.as_object_mut() else { return };
+    for node in nodes.values_mut() {
+        if node.get("class_type").and_then(|v| v.as_str()) != Some("SaveImage") { continue; }
+        let Some(inputs) = node.get_mut("inputs").and_then(|v| v.as_object_mut()) else { continue };
+        let current = inputs.get("filename_prefix").and_then(|v| v.as_str()).unwrap_or("");
+        let prefix = if current.is_empty() { suffix.to_string() } else { format!("{}_{}", current, suffix) };
+        inputs.insert("filename_prefix".to_string(), Value::String(prefix));
+    }
+}
+
+/// Known parameter keys we support mapping into node inputs dynamically.
+pub const KNOWN_PARAM_KEYS: &[&str] = &[
     "seed",
     "steps",
     "cfg",
@@ -78,6 +101,8 @@ const KNOWN_PARAM_KEYS: &[&str] = &[
     "batch_size",
     "ckpt_name",
     "text",
+    "upscale_method",
+    "vae_name",
 ];
 
 /// Apply a params object to the prompt graph by matching keys to node input names.
@@ -87,14 +112,42 @@ const KNOWN_PARAM_KEYS: &[&str] = &[
 /// - Special case for `text`: applies to all nodes with `inputs.text` (common for
 ///   CLIPTextEncode). If the caller wants different values per text node, they can
 ///   still use explicit `sets` paths.
-pub fn apply_params_map(graph: &mut Value, params: &Value) {
-    let obj = match params.as_object() { Some(o) => o, None => return };
+/// - `text_positive`/`text_negative` go to the nodes picked by
+///   [`resolve_text_targets`].
+/// - `upscale_by` is routed by [`apply_upscale_by`], `clip_skip` by
+///   [`apply_clip_skip`], and `image` (an img2img init image) by
+///   [`apply_init_image`].
+///
+/// Returns the supported keys that matched no node input, so callers can warn
+/// instead of dropping them silently.
+pub fn apply_params_map(graph: &mut Value, params: &Value, patterns: &TitlePatterns) -> Vec<String> {
+    let obj = match params.as_object() { Some(o) => o, None => return Vec::new() };
+    let _span = tracing::debug_span!(target: TRACE_TARGET, "apply_params_map").entered();
+    let mut unapplied = Vec::new();
 
     // Handle specialized text mapping first (positive/negative)
     let text_pos = obj.get("text_positive").cloned();
     let text_neg = obj.get("text_negative").cloned();
     if text_pos.is_some() || text_neg.is_some() {
-        apply_text_pos_neg(graph, text_pos.as_ref(), text_neg.as_ref());
+        let (pos_ok, neg_ok) = apply_text_pos_neg(graph, text_pos.as_ref(), text_neg.as_ref(), patterns);
+        if text_pos.is_some() && !pos_ok { unapplied.push("text_positive".to_string()); }
+        if text_neg.is_some() && !neg_ok { unapplied.push("text_negative".to_string()); }
+    }
+    if let Some(by) = obj.get("upscale_by") {
+        if by.as_f64().map(|f| apply_upscale_by(graph, f)).unwrap_or(0) == 0 {
+            unapplied.push("upscale_by".to_string());
+        }
+    }
+    if let Some(skip) = obj.get("clip_skip") {
+        if !skip.as_i64().map(|n| apply_clip_skip(graph, n)).unwrap_or(false) {
+            unapplied.push("clip_skip".to_string());
+        }
+    }
+    if let Some(image) = obj.get("image") {
+        // Ambiguous graphs are rejected before params are applied (see `apply_overrides_from_payload`).
+        if image.as_str().and_then(|name| apply_init_image(graph, name).ok()).unwrap_or(0) == 0 {
+            unapplied.push("image".to_string());
+        }
     }
 
     // Extract only known keys with values (excluding specialized keys above)
@@ -104,81 +157,442 @@ pub fn apply_params_map(graph: &mut Value, params: &Value) {
             kvs.push((k, v));
         }
     }
-    if kvs.is_empty() { return; }
+    if kvs.is_empty() { return unapplied; }
 
+    let mut matched: Vec<Vec<String>> = vec![Vec::new(); kvs.len()];
     if let Some(nodes) = graph.as_object_mut() {
-        for (_id, node) in nodes.iter_mut() {
+        for (id, node) in nodes.iter_mut() {
             if let Some(inputs) = node.get_mut("inputs").and_then(|v| v.as_object_mut()) {
-                for (k, v) in kvs.iter() {
+                for (i, (k, v)) in kvs.iter().enumerate() {
                     if inputs.contains_key(*k) {
                         inputs.insert((*k).to_string(), (*v).clone());
+                        matched[i].push(id.clone());
                     }
                 }
             }
         }
     }
+    for ((k, _), mut nodes) in kvs.iter().zip(matched) {
+        if nodes.is_empty() {
+            tracing::debug!(target: TRACE_TARGET, param = %k, "no node has this input");
+            unapplied.push(k.to_string());
+        } else {
+            nodes.sort();
+            tracing::debug!(target: TRACE_TARGET, param = %k, nodes = ?nodes, "applied param");
+        }
+    }
+    unapplied
 }
 
-fn apply_text_pos_neg(graph: &mut Value, text_pos: Option<&Value>, text_neg: Option<&Value>) {
-    // Strategy:
-    // 1) Prefer to locate a KSampler node and follow its `positive`/`negative` inputs to CLIPTextEncode nodes.
-    // 2) If not resolvable, apply to all CLIPTextEncode nodes, in order: first gets positive, second gets negative (if provided).
-    // 3) If only one text provided, apply that one where possible and leave others untouched.
+/// Case-insensitive `_meta.title` substrings that mark a workflow's positive
+/// and negative text nodes (e.g. "Positive Prompt").
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TitlePatterns {
+    pub positive: Vec<String>,
+    pub negative: Vec<String>,
+}
+
+impl Default for TitlePatterns {
+    fn default() -> Self {
+        TitlePatterns { positive: vec!["positive".to_string()], negative: vec!["negative".to_string()] }
+    }
+}
+
+impl TitlePatterns {
+    /// Build from comma-separated lists; unset or empty lists keep the default.
+    pub fn from_lists(positive: Option<&str>, negative: Option<&str>) -> Self {
+        let parse = |list: Option<&str>| -> Option<Vec<String>> {
+            let items: Vec<String> = list?.split(',')
+                .map(|p| p.trim().to_lowercase())
+                .filter(|p| !p.is_empty())
+                .collect();
+            (!items.is_empty()).then_some(items)
+        };
+        let default = TitlePatterns::default();
+        TitlePatterns {
+            positive: parse(positive).unwrap_or(default.positive),
+            negative: parse(negative).unwrap_or(default.negative),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::from_lists(config.positive_title_patterns.as_deref(), config.negative_title_patterns.as_deref())
+    }
 
+    /// Lowest-id text node whose title matches one of `patterns`. Negative
+    /// patterns win when a title matches both (e.g. "Non-positive").
+    fn find(&self, graph: &Value, negative: bool) -> Option<String> {
+        let mut ids: Vec<&String> = graph.as_object()?.iter()
+            .filter(|(_, node)| node.get("inputs").and_then(|i| i.get("text")).is_some())
+            .filter(|(_, node)| {
+                let Some(title) = node.get("_meta").and_then(|m| m.get("title")).and_then(|t| t.as_str()) else { return false };
+                let title = title.to_lowercase();
+                let matches = |patterns: &[String]| patterns.iter().any(|p| title.contains(p.as_str()));
+                if negative { matches(&self.negative) } else { matches(&self.positive) && !matches(&self.negative) }
+            })
+            .map(|(id, _)| id)
+            .collect();
+        ids.sort_by(|a, b| node_order(a).cmp(&node_order(b)));
+        ids.first().map(|id| id.to_string())
+    }
+}
+
+/// Resolve which nodes receive positive and negative prompt text.
+///
+/// Prefers following the first KSampler's `positive`/`negative` links to their
+/// source nodes. When a link can't be resolved, uses the text node whose
+/// `_meta.title` matches `patterns`, then falls back to CLIPTextEncode nodes
+/// sorted by id: the first is positive, the second negative.
+pub fn resolve_text_targets(graph: &Value, patterns: &TitlePatterns) -> (Option<String>, Option<String>) {
     let ksampler_id = find_first_node_id_by_class(graph, "KSampler");
+    tracing::debug!(target: TRACE_TARGET, ksampler = ?ksampler_id, "chose KSampler for text routing");
+    let linked = |input_name: &str| {
+        ksampler_id.as_ref()
+            .and_then(|ks_id| source_node_id_from_ksampler_input(graph, ks_id, input_name))
+            .filter(|src| graph.get(src).and_then(|n| n.get("inputs")).map(|i| i.is_object()).unwrap_or(false))
+    };
+    let clip_nodes = collect_clip_textencode_ids(graph);
+    let resolve = |input_name: &str, negative: bool| {
+        if let Some(id) = linked(input_name) {
+            tracing::debug!(target: TRACE_TARGET, input = input_name, node = %id, "text target from KSampler link");
+            return Some(id);
+        }
+        if let Some(id) = patterns.find(graph, negative) {
+            tracing::debug!(target: TRACE_TARGET, input = input_name, node = %id, "text target from _meta.title");
+            return Some(id);
+        }
+        let fallback = clip_nodes.get(usize::from(negative)).cloned();
+        tracing::debug!(
+            target: TRACE_TARGET, input = input_name, node = ?fallback, clip_nodes = ?clip_nodes,
+            "KSampler link and titles unresolved; falling back to CLIPTextEncode by id order"
+        );
+        fallback
+    };
+    (resolve("positive", false), resolve("negative", true))
+}
 
-    let mut applied_pos = false;
-    let mut applied_neg = false;
+/// Returns whether the positive and negative texts found a node.
+fn apply_text_pos_neg(graph: &mut Value, text_pos: Option<&Value>, text_neg: Option<&Value>, patterns: &TitlePatterns) -> (bool, bool) {
+    let _span = tracing::debug_span!(target: TRACE_TARGET, "apply_text_pos_neg").entered();
+    // If only one text is provided, apply that one and leave the other node untouched.
+    let (pos_id, neg_id) = resolve_text_targets(graph, patterns);
+    let mut applied = (false, false);
+    if let (Some(v), Some(id)) = (text_pos, pos_id) {
+        applied.0 = set_node_text(graph, &id, v);
+        tracing::debug!(target: TRACE_TARGET, node = %id, applied = applied.0, "text_positive");
+    }
+    if let (Some(v), Some(id)) = (text_neg, neg_id) {
+        applied.1 = set_node_text(graph, &id, v);
+        tracing::debug!(target: TRACE_TARGET, node = %id, applied = applied.1, "text_negative");
+    }
+    applied
+}
 
-    if let Some(ref ks_id) = ksampler_id {
-        if let Some(v) = text_pos {
-            if let Some(src) = source_node_id_from_ksampler_input(graph, ks_id, "positive") {
-                applied_pos = set_node_text(graph, &src, v);
-            }
+/// Scale factor of an upscale model, read from names like
+/// `4x-UltraSharp.pth` or `RealESRGAN_x2plus.pth`.
+pub fn upscale_model_factor(model_name: &str) -> Option<f64> {
+    model_name.to_ascii_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .find_map(|token| {
+            let digits = token.strip_suffix('x')
+                .or_else(|| token.strip_prefix('x').map(|t| t.trim_end_matches(|c: char| c.is_ascii_alphabetic())))?;
+            digits.parse::<u32>().ok().filter(|n| *n > 0).map(f64::from)
+        })
+}
+
+/// Inputs the image or latent being upscaled flows through.
+const UPSCALED_INPUTS: &[&str] = &["samples", "latent_image", "image", "pixels"];
+
+/// Size, relative to the workflow's base size, of what `node_id` outputs
+/// once every upscale stage has been set for `factor`: a stage brings its
+/// output to `factor`, an ImageUpscaleWithModel multiplies by its model's
+/// factor, and anything else passes its input's size on. `seen` guards
+/// against cycles.
+fn scale_after(graph: &Value, node_id: &str, factor: f64, seen: &mut HashSet<String>) -> f64 {
+    let Some(node) = graph.get(node_id).filter(|_| seen.insert(node_id.to_string())) else { return 1.0 };
+    let Some(inputs) = node.get("inputs") else { return 1.0 };
+    let class_type = node.get("class_type").and_then(|v| v.as_str());
+    let source = UPSCALED_INPUTS.iter().find_map(|name| inputs.get(*name).and_then(link_source));
+    if inputs.get("scale_by").is_some() {
+        return factor;
+    }
+    if class_type == Some("LatentUpscale") && source.as_deref().and_then(|src| upstream_batch(graph, src)).is_some_and(|b| b.width.is_some() && b.height.is_some()) {
+        return factor;
+    }
+    let before = source.map(|src| scale_after(graph, &src, factor, seen)).unwrap_or(1.0);
+    if class_type == Some("ImageUpscaleWithModel") {
+        return before * model_factor(graph, inputs).unwrap_or(1.0);
+    }
+    before
+}
+
+/// Factor of the model an ImageUpscaleWithModel with `inputs` loads, when
+/// its loader's model name gives one.
+fn model_factor(graph: &Value, inputs: &Value) -> Option<f64> {
+    let loader = link_source(inputs.get("upscale_model")?)?;
+    upscale_model_factor(graph.get(&loader)?.get("inputs")?.get("model_name")?.as_str()?)
+}
+
+/// Bring the workflow's output to `factor` × its base size, however many
+/// upscale stages it has:
+///
+/// - `scale_by` nodes (LatentUpscaleBy, ImageScaleBy) get what's left of
+///   `factor` after the stages and upscale models before them, so the first
+///   stage scales by `factor` (divided by the model's own factor when it
+///   resizes an ImageUpscaleWithModel output) and later ones by 1.
+/// - LatentUpscale gets `width`/`height` of the base latent × `factor`,
+///   rounded to a multiple of 8.
+///
+/// Returns how many nodes were updated.
+pub fn apply_upscale_by(graph: &mut Value, factor: f64) -> usize {
+    let Some(nodes) = graph.as_object() else { return 0 };
+    let mut updates: Vec<(String, &str, Value)> = Vec::new();
+    for (id, node) in nodes {
+        let Some(inputs) = node.get("inputs") else { continue };
+        if inputs.get("scale_by").is_some() {
+            let source = UPSCALED_INPUTS.iter().find_map(|name| inputs.get(*name).and_then(link_source));
+            let before = source.map(|src| scale_after(graph, &src, factor, &mut HashSet::new())).unwrap_or(1.0);
+            let scale = factor / before;
+            updates.push((id.clone(), "scale_by", Value::from((scale * 1000.0).round() / 1000.0)));
+        } else if node.get("class_type").and_then(|v| v.as_str()) == Some("LatentUpscale") {
+            let Some(src) = inputs.get("samples").and_then(link_source) else { continue };
+            let Some(LatentBatch { width: Some(w), height: Some(h), .. }) = upstream_batch(graph, &src) else { continue };
+            let scaled = |v: u64| ((((v as f64 * factor) / 8.0).round() as u64) * 8).max(8);
+            updates.push((id.clone(), "width", Value::from(scaled(w))));
+            updates.push((id.clone(), "height", Value::from(scaled(h))));
+        }
+    }
+    let mut touched: Vec<String> = updates.iter().map(|(id, _, _)| id.clone()).collect();
+    touched.dedup();
+    for (id, input, value) in updates {
+        graph[&id]["inputs"][input] = value;
+    }
+    tracing::debug!(target: TRACE_TARGET, factor, nodes = ?touched, "upscale_by");
+    touched.len()
+}
+
+/// Add a VAELoader for `vae_name` and point every `vae` input at it, unless
+/// the graph already has a VAELoader. Returns the new node's id.
+pub fn inject_vae_loader(graph: &mut Value, vae_name: &Value) -> Option<String> {
+    let nodes = graph.as_object_mut()?;
+    let has_loader = nodes.values().any(|n| n.get("class_type").and_then(|v| v.as_str()) == Some("VAELoader"));
+    if has_loader {
+        return None;
+    }
+    let loader = json!({"class_type": "VAELoader", "inputs": {"vae_name": vae_name}, "_meta": {"title": "Load VAE"}});
+    let id = add_node(nodes, loader);
+    for node in nodes.values_mut() {
+        if let Some(vae) = node.get_mut("inputs").and_then(|i| i.get_mut("vae")).filter(|v| link_source(v).is_some()) {
+            *vae = json!([id, 0]);
         }
-        if let Some(v) = text_neg {
-            if let Some(src) = source_node_id_from_ksampler_input(graph, ks_id, "negative") {
-                applied_neg = set_node_text(graph, &src, v);
+    }
+    tracing::debug!(target: TRACE_TARGET, node = %id, "injected VAELoader");
+    Some(id)
+}
+
+/// Checkpoint loader classes whose second output (slot 1) is the CLIP model.
+const CHECKPOINT_LOADER_CLASSES: &[&str] = &["CheckpointLoaderSimple", "CheckpointLoader"];
+
+/// Ids of the nodes that load an input image by name (`LoadImage`,
+/// `LoadImageMask`, and the like), in numeric id order. Nodes whose `image`
+/// input is a link are skipped.
+pub fn load_image_ids(graph: &Value) -> Vec<String> {
+    let Some(nodes) = graph.as_object() else { return Vec::new() };
+    let mut ids: Vec<String> = nodes.iter()
+        .filter(|(_, n)| n.get("class_type").and_then(|v| v.as_str()).map(|ct| ct.starts_with("LoadImage")).unwrap_or(false))
+        .filter(|(_, n)| n.get("inputs").and_then(|i| i.get("image")).map(|v| v.is_string()).unwrap_or(false))
+        .map(|(id, _)| id.clone())
+        .collect();
+    ids.sort_by(|a, b| node_order(a).cmp(&node_order(b)));
+    ids
+}
+
+/// Sort key putting node ids in numeric order ("9" before "10"), with
+/// non-numeric ids last, by name.
+pub(crate) fn node_order(id: &str) -> (u64, &str) {
+    (id.parse().unwrap_or(u64::MAX), id)
+}
+
+/// Titles (case-insensitive) that mark an image loader as the init image.
+pub const INIT_IMAGE_TITLES: &[&str] = &["init image", "init"];
+
+/// The image loaders an init image goes to: the only one, the ones titled
+/// as in [`INIT_IMAGE_TITLES`], or else the one whose image is encoded by a
+/// `VAEEncode` (directly or through other nodes). Empty when the graph has
+/// no loader; an error when several could be the init image, since the rest
+/// (masks, ControlNet hints, IP-Adapter references) must keep their images.
+pub fn init_image_ids(graph: &Value) -> Result<Vec<String>, String> {
+    let loaders = load_image_ids(graph);
+    if loaders.len() <= 1 {
+        return Ok(loaders);
+    }
+    let titled: Vec<String> = loaders.iter()
+        .filter(|id| graph[id.as_str()].get("_meta").and_then(|m| m.get("title")).and_then(|t| t.as_str())
+            .is_some_and(|title| INIT_IMAGE_TITLES.iter().any(|known| title.trim().eq_ignore_ascii_case(known))))
+        .cloned()
+        .collect();
+    if !titled.is_empty() {
+        return Ok(titled);
+    }
+    let encoded: Vec<String> = loaders.iter().filter(|id| feeds_vae_encode(graph, id)).cloned().collect();
+    match encoded.len() {
+        1 => Ok(encoded),
+        n => Err(format!(
+            "The workflow has {} image loaders ({}) and {} of them feed a VAEEncode; title the init image's loader 'Init Image'",
+            loaders.len(), loaders.join(", "), if n == 0 { "none".to_string() } else { n.to_string() },
+        )),
+    }
+}
+
+/// Whether node `id`'s outputs reach a `VAEEncode*` node.
+fn feeds_vae_encode(graph: &Value, id: &str) -> bool {
+    let Some(nodes) = graph.as_object() else { return false };
+    let mut seen = std::collections::HashSet::from([id.to_string()]);
+    let mut frontier = vec![id.to_string()];
+    while let Some(current) = frontier.pop() {
+        for (next, node) in nodes {
+            let reads_current = node.get("inputs").and_then(|i| i.as_object())
+                .is_some_and(|inputs| inputs.values().any(|v| v.get(0).and_then(|s| s.as_str()) == Some(current.as_str())));
+            if !reads_current || !seen.insert(next.clone()) {
+                continue;
+            }
+            if node.get("class_type").and_then(|v| v.as_str()).is_some_and(|ct| ct.starts_with("VAEEncode")) {
+                return true;
             }
+            frontier.push(next.clone());
         }
     }
+    false
+}
 
-    // Fallback to applying on CLIPTextEncode nodes in encounter order
-    if (!applied_pos && text_pos.is_some()) || (!applied_neg && text_neg.is_some()) {
-        let mut clip_nodes = collect_clip_textencode_ids(graph);
+/// Point the init image loaders (see [`init_image_ids`]) at `image`, a file
+/// in ComfyUI's input folder such as an `/upload_image` result
+/// (`subfolder/name` when it has a subfolder). Returns how many nodes were
+/// updated.
+pub fn apply_init_image(graph: &mut Value, image: &str) -> Result<usize, String> {
+    let ids = init_image_ids(graph)?;
+    for id in &ids {
+        graph[id]["inputs"]["image"] = Value::String(image.to_string());
+    }
+    tracing::debug!(target: TRACE_TARGET, image, nodes = ?ids, "init image");
+    Ok(ids.len())
+}
 
-        if let Some(v) = text_pos {
-            if !applied_pos {
-                if let Some(first) = clip_nodes.get(0) {
-                    let _ = set_node_text(graph, first, v);
-                }
+/// Set CLIP skip on every CLIPSetLastLayer node. `skip` follows A1111's
+/// convention (2 skips the last layer) and may also be given as ComfyUI's
+/// negative `stop_at_clip_layer`.
+///
+/// When the graph has no CLIPSetLastLayer, one is inserted after the first
+/// checkpoint loader and every input reading the loader's CLIP output is
+/// pointed at it. Returns `false` when there's nowhere to apply it.
+pub fn apply_clip_skip(graph: &mut Value, skip: i64) -> bool {
+    if skip == 0 { return false; }
+    let layer = -skip.abs();
+    let Some(nodes) = graph.as_object_mut() else { return false };
+    let mut applied = false;
+    for node in nodes.values_mut() {
+        if node.get("class_type").and_then(|v| v.as_str()) != Some("CLIPSetLastLayer") { continue; }
+        if let Some(inputs) = node.get_mut("inputs").and_then(|i| i.as_object_mut()) {
+            inputs.insert("stop_at_clip_layer".to_string(), Value::from(layer));
+            applied = true;
+        }
+    }
+    if applied {
+        return true;
+    }
+
+    let mut loaders: Vec<&String> = nodes.iter()
+        .filter(|(_, n)| n.get("class_type").and_then(|v| v.as_str()).map(|ct| CHECKPOINT_LOADER_CLASSES.contains(&ct)).unwrap_or(false))
+        .map(|(id, _)| id)
+        .collect();
+    loaders.sort_by(|a, b| node_order(a).cmp(&node_order(b)));
+    let Some(loader) = loaders.first().map(|id| id.to_string()) else { return false };
+    let clip_output = json!([loader, 1]);
+    let is_clip_output = |v: &Value| link_source(v).as_deref() == Some(loader.as_str()) && v[1] == 1;
+    let node = json!({"class_type": "CLIPSetLastLayer", "inputs": {"clip": clip_output, "stop_at_clip_layer": layer}, "_meta": {"title": "CLIP Set Last Layer"}});
+    let id = add_node(nodes, node);
+    for (node_id, node) in nodes.iter_mut() {
+        if *node_id == id { continue; }
+        let Some(inputs) = node.get_mut("inputs").and_then(|i| i.as_object_mut()) else { continue };
+        for value in inputs.values_mut() {
+            if is_clip_output(value) {
+                *value = json!([id, 0]);
             }
         }
-        if let Some(v) = text_neg {
-            if !applied_neg {
-                if let Some(second) = clip_nodes.get(1) {
-                    let _ = set_node_text(graph, second, v);
-                }
+    }
+    tracing::debug!(target: TRACE_TARGET, node = %id, loader = %loader, "injected CLIPSetLastLayer");
+    true
+}
+
+/// Latent-creating node classes that carry the generation resolution.
+const LATENT_IMAGE_CLASSES: &[&str] = &["EmptyLatentImage", "EmptySD3LatentImage"];
+
+/// Set `width`/`height` on every empty-latent node. Returns how many nodes
+/// were updated.
+pub fn set_latent_dimensions(graph: &mut Value, width: u64, height: u64) -> usize {
+    let mut count = 0;
+    if let Some(nodes) = graph.as_object_mut() {
+        for (_id, node) in nodes.iter_mut() {
+            let is_latent = node.get("class_type")
+                .and_then(|v| v.as_str())
+                .map(|ct| LATENT_IMAGE_CLASSES.contains(&ct))
+                .unwrap_or(false);
+            if !is_latent { continue; }
+            if let Some(inputs) = node.get_mut("inputs").and_then(|v| v.as_object_mut()) {
+                inputs.insert("width".to_string(), Value::from(width));
+                inputs.insert("height".to_string(), Value::from(height));
+                count += 1;
             }
         }
     }
+    count
 }
 
-fn find_first_node_id_by_class(graph: &Value, class_type: &str) -> Option<String> {
-    graph.as_object()?.iter().find_map(|(id, node)| {
-        node.get("class_type")
-            .and_then(|ct| ct.as_str())
-            .filter(|ct| *ct == class_type)
-            .map(|_| id.clone())
+/// `width`/`height` of the first empty-latent node (in numeric id order) that sets both
+/// literally, i.e. the size the workflow generates at.
+pub fn latent_dimensions(graph: &Value) -> Option<(u64, u64)> {
+    let mut nodes: Vec<(&String, &Value)> = graph.as_object()?.iter().collect();
+    nodes.sort_by(|a, b| node_order(a.0).cmp(&node_order(b.0)));
+    nodes.into_iter().find_map(|(_, node)| {
+        let class_type = node.get("class_type")?.as_str()?;
+        if !LATENT_IMAGE_CLASSES.contains(&class_type) {
+            return None;
+        }
+        let inputs = node.get("inputs")?;
+        Some((inputs.get("width")?.as_u64()?, inputs.get("height")?.as_u64()?))
     })
 }
 
+/// Fill the negative prompt node with `negative` if its text is empty.
+/// Returns `true` when the default was applied.
+pub fn apply_default_negative(graph: &mut Value, negative: &str, patterns: &TitlePatterns) -> bool {
+    let (_, Some(neg_id)) = resolve_text_targets(graph, patterns) else { return false };
+    let current = graph.get(&neg_id)
+        .and_then(|n| n.get("inputs"))
+        .and_then(|i| i.get("text"));
+    let is_empty = match current {
+        None => true,
+        Some(Value::String(s)) => s.trim().is_empty(),
+        // Linked text inputs (e.g. from a string node) are left alone.
+        Some(_) => false,
+    };
+    is_empty && set_node_text(graph, &neg_id, &Value::String(negative.to_string()))
+}
+
+fn find_first_node_id_by_class(graph: &Value, class_type: &str) -> Option<String> {
+    graph.as_object()?.iter()
+        .filter(|(_, node)| node.get("class_type").and_then(|ct| ct.as_str()) == Some(class_type))
+        .map(|(id, _)| id)
+        .min_by(|a, b| node_order(a).cmp(&node_order(b)))
+        .cloned()
+}
+
 fn source_node_id_from_ksampler_input(graph: &Value, ksampler_id: &str, input_name: &str) -> Option<String> {
     let node = graph.get(ksampler_id)?;
     let inputs = node.get("inputs")?.as_object()?;
     let source = inputs.get(input_name)?;
     if let Some(arr) = source.as_array() {
-        if let Some(idv) = arr.get(0) {
+        if let Some(idv) = arr.first() {
             if let Some(s) = idv.as_str() { return Some(s.to_string()); }
             if let Some(n) = idv.as_i64() { return Some(n.to_string()); }
         }
@@ -207,6 +621,6 @@ fn collect_clip_textencode_ids(graph: &Value) -> Vec<String> {
                 .map(|_| id.clone())
         })
         .collect();
-    ids.sort();
+    ids.sort_by(|a, b| node_order(a).cmp(&node_order(b)));
     ids
 }
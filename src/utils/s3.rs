@@ -0,0 +1,162 @@
+//! Minimal S3 client for the static drive's `s3` action.
+//!
+//! Only `PutObject` is needed, so requests are signed here with AWS
+//! Signature Version 4 rather than pulling in the AWS SDK. Works with AWS and
+//! S3-compatible stores (MinIO, R2) via `S3_ENDPOINT`.
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+use crate::utils::time::{civil_date, now_ms};
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone)]
+pub struct S3Target {
+    pub bucket: String,
+    pub region: String,
+    /// Custom endpoint (path-style URLs); AWS virtual-hosted URLs when unset.
+    pub endpoint: Option<String>,
+    /// Prepended to each object key.
+    pub prefix: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+/// Hex-encoded SHA-256 of `data`.
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encode a URI path, leaving `/` and RFC 3986 unreserved characters.
+fn encode_path(path: &str) -> String {
+    let mut out = String::new();
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => out.push(b as char),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+/// `Authorization` header for a request with no query string. `headers`
+/// are the headers to sign (must include `host` and `x-amz-date`); names are
+/// lowercased and sorted here. `amz_date` is `YYYYMMDDTHHMMSSZ`.
+#[allow(clippy::too_many_arguments)]
+pub fn authorization(
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    payload_hash: &str,
+    amz_date: &str,
+    region: &str,
+    access_key_id: &str,
+    secret_access_key: &str,
+) -> String {
+    let mut headers: Vec<(String, String)> = headers.iter()
+        .map(|(k, v)| (k.to_ascii_lowercase(), v.trim().to_string()))
+        .collect();
+    headers.sort();
+    let canonical_headers: String = headers.iter().map(|(k, v)| format!("{}:{}\n", k, v)).collect();
+    let signed_headers = headers.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>().join(";");
+    let canonical_request = format!(
+        "{}\n{}\n\n{}\n{}\n{}",
+        method, encode_path(path), canonical_headers, signed_headers, payload_hash
+    );
+
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, sha256_hex(canonical_request.as_bytes()));
+    let key = hmac(format!("AWS4{}", secret_access_key).as_bytes(), date);
+    let key = hmac(&key, region);
+    let key = hmac(&key, "s3");
+    let key = hmac(&key, "aws4_request");
+    let signature = hex::encode(hmac(&key, &string_to_sign));
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key_id, scope, signed_headers, signature
+    )
+}
+
+/// `YYYYMMDDTHHMMSSZ` for a Unix timestamp in milliseconds.
+pub fn amz_date(ms: u64) -> String {
+    let (y, m, d) = civil_date(ms);
+    let secs = (ms / 1000) % 86_400;
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", y, m, d, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+impl S3Target {
+    /// Settings from `S3_*` and the standard `AWS_*` credential variables.
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let bucket = config.s3_bucket.clone().ok_or("the s3 action requires S3_BUCKET")?;
+        let access_key_id = config.aws_access_key_id.clone().ok_or("the s3 action requires AWS_ACCESS_KEY_ID")?;
+        let secret_access_key = config.aws_secret_access_key.clone().ok_or("the s3 action requires AWS_SECRET_ACCESS_KEY")?;
+        Ok(S3Target {
+            bucket,
+            region: config.s3_region.clone(),
+            endpoint: config.s3_endpoint.clone().map(|e| e.trim_end_matches('/').to_string()),
+            prefix: config.s3_prefix.clone().unwrap_or_default(),
+            access_key_id,
+            secret_access_key,
+            session_token: config.aws_session_token.clone(),
+        })
+    }
+
+    /// Host and path of the object stored under `key` (after the prefix).
+    fn location(&self, key: &str) -> (String, String, String) {
+        let key = format!("{}{}", self.prefix, key);
+        match &self.endpoint {
+            Some(endpoint) => {
+                let (scheme, host) = endpoint.split_once("://").unwrap_or(("https", endpoint));
+                (scheme.to_string(), host.to_string(), format!("/{}/{}", self.bucket, key))
+            }
+            None => ("https".to_string(), format!("{}.s3.{}.amazonaws.com", self.bucket, self.region), format!("/{}", key)),
+        }
+    }
+
+    /// URL of the object stored under `key` (after the prefix).
+    pub fn object_url(&self, key: &str) -> String {
+        let (scheme, host, path) = self.location(key);
+        format!("{}://{}{}", scheme, host, encode_path(&path))
+    }
+
+    /// Upload `body` as `key` and return the object URL.
+    pub async fn put_object(&self, http: &reqwest::Client, key: &str, body: Vec<u8>, content_type: &str) -> Result<String, String> {
+        let (_, host, path) = self.location(key);
+        let url = self.object_url(key);
+        let payload_hash = sha256_hex(&body);
+        let date = amz_date(now_ms());
+        let mut headers = vec![
+            ("host", host.as_str()),
+            ("content-type", content_type),
+            ("x-amz-content-sha256", payload_hash.as_str()),
+            ("x-amz-date", date.as_str()),
+        ];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token.as_str()));
+        }
+        let auth = authorization("PUT", &path, &headers, &payload_hash, &date, &self.region, &self.access_key_id, &self.secret_access_key);
+
+        let mut request = http.put(&url).header("authorization", auth).body(body);
+        // reqwest sets Host itself from the URL.
+        for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+            request = request.header(*name, *value);
+        }
+        let response = request.send().await.map_err(|e| format!("S3 upload to {} failed: {}", url, e))?;
+        if response.status().is_success() {
+            Ok(url)
+        } else {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            Err(format!("S3 upload to {} failed. Status: {}, Body: {}", url, status, body))
+        }
+    }
+}
@@ -1,35 +1,242 @@
-//! Background poller for a local static directory.
+//! Background indexer for a local static directory.
 //!
-//! Intended as a scaffold for reacting to newly created files under
-//! `STATIC_DRIVE_PATH`. Currently it iterates directory entries on an
-//! interval but performs no side effects.
+//! Every `STATIC_DRIVE_POLL_SECS` it scans `STATIC_DRIVE_PATH` for files that
+//! are new or changed since the last scan, reads their PNG metadata (the
+//! prompt ComfyUI embeds), and runs them through the `STATIC_DRIVE_ACTIONS`
+//! pipeline (see [`crate::utils::drive_actions`]). Files that are gone are
+//! dropped from the index.
+//!
+//! The index is kept in memory for `GET /static/index` and mirrored to
+//! `.drive_index.json` under the drive, so a restart doesn't process files
+//! again. Hidden entries (names starting with `.`, including the index and
+//! thumbnails) are never scanned. A file whose pipeline failed is not
+//! retried until it changes.
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use tokio::sync::{Mutex, RwLock};
 use tokio::time::{self, Duration};
-use std::path::Path;
-use tokio::fs;
+
+use crate::config::Config;
+use crate::events::{Audience, EventBus, ProxyEvent, ProxyEventKind};
+use crate::utils::drive_actions::{actions_from_config, DriveAction, FileRecord};
+use crate::utils::time::now_ms;
+
+/// Index file under the drive root.
+pub const INDEX_FILE: &str = ".drive_index.json";
+/// Files modified more recently than this may still be being written.
+const SETTLE_MS: u64 = 2000;
 
 pub struct StaticDrivePoller {
     path: String,
+    interval: Duration,
+    actions: Vec<DriveAction>,
+    http: reqwest::Client,
+    /// Relative path -> record, loaded from the index file on first poll.
+    index: RwLock<Option<BTreeMap<String, FileRecord>>>,
+    /// Held for a whole scan so two polls don't process the same file.
+    scanning: Mutex<()>,
+    events: Option<EventBus>,
 }
 
 impl StaticDrivePoller {
     pub fn new(path: String) -> Self {
-        Self { path }
+        Self::with_actions(path, Vec::new())
+    }
+
+    pub fn with_actions(path: String, mut actions: Vec<DriveAction>) -> Self {
+        // Metadata is always read; see `poll_drive`.
+        actions.retain(|a| !matches!(a, DriveAction::Metadata));
+        Self {
+            path,
+            interval: Duration::from_secs(5),
+            actions,
+            http: reqwest::Client::new(),
+            index: RwLock::new(None),
+            scanning: Mutex::new(()),
+            events: None,
+        }
+    }
+
+    /// Announce indexed files on `bus`.
+    pub fn set_event_bus(&mut self, bus: EventBus) {
+        self.events = Some(bus);
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        let mut poller = Self::with_actions(config.static_drive_path.clone(), actions_from_config(config));
+        poller.interval = Duration::from_secs(config.static_drive_poll_secs.max(1));
+        if let Ok(http) = reqwest::Client::builder().user_agent(config.user_agent.clone()).build() {
+            poller.http = http;
+        }
+        poller
+    }
+
+    /// Time between scans, `STATIC_DRIVE_POLL_SECS`.
+    pub fn interval(&self) -> Duration {
+        self.interval
     }
 
     pub async fn start_polling(&self) {
-        let mut interval = time::interval(Duration::from_secs(5));
+        let mut interval = time::interval(self.interval);
         loop {
             interval.tick().await;
             self.poll_drive().await;
         }
     }
 
-    async fn poll_drive(&self) {
-        let path = Path::new(&self.path);
-        if let Ok(entries) = fs::read_dir(path).await {
-            // Process new files here
-            // You might want to move processed files to a different directory
-            // or update a database with the new file information
+    /// Records of every indexed file, by relative path.
+    pub async fn records(&self) -> Vec<FileRecord> {
+        self.load().await;
+        self.index.read().await.iter().flat_map(|index| index.values().cloned()).collect()
+    }
+
+    /// Indexed files modified at or after `since` (Unix ms) whose path starts
+    /// with `prefix`, oldest first, so a client can pass the last
+    /// `modified_ms` it saw as the next `since`.
+    pub async fn list(&self, since: u64, prefix: Option<&str>) -> Vec<FileRecord> {
+        let mut records: Vec<FileRecord> = self.records().await.into_iter()
+            .filter(|r| r.modified_ms >= since)
+            .filter(|r| prefix.is_none_or(|p| r.path.starts_with(p)))
+            .collect();
+        records.sort_by(|a, b| a.modified_ms.cmp(&b.modified_ms).then_with(|| a.path.cmp(&b.path)));
+        records
+    }
+
+    /// Drop indexed files whose path starts with `prefix`, e.g. files moved
+    /// off the drive, returning how many were dropped.
+    pub async fn forget(&self, prefix: &str) -> usize {
+        let mut index = self.index.write().await;
+        let index = index.get_or_insert_with(|| self.load_index());
+        let before = index.len();
+        index.retain(|rel, _| !rel.starts_with(prefix));
+        let removed = before - index.len();
+        if removed > 0 {
+            self.save_index(index).await;
+        }
+        removed
+    }
+
+    /// The drive's root directory.
+    pub fn root(&self) -> &Path {
+        Path::new(&self.path)
+    }
+
+    fn index_path(&self) -> PathBuf {
+        Path::new(&self.path).join(INDEX_FILE)
+    }
+
+    /// Load the index file if it hasn't been yet; the write lock is only
+    /// taken the first time.
+    async fn load(&self) {
+        if self.index.read().await.is_some() {
+            return;
+        }
+        let mut index = self.index.write().await;
+        if index.is_none() {
+            *index = Some(self.load_index());
+        }
+    }
+
+    fn load_index(&self) -> BTreeMap<String, FileRecord> {
+        std::fs::read(self.index_path()).ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    /// Scan once: index and process new or changed files, and forget files
+    /// that are gone. Returns the records of the processed files.
+    ///
+    /// The scan and the action pipeline run against a snapshot of the index,
+    /// so `records`, `list` and `forget` aren't blocked while files are
+    /// hashed or uploaded; the results are merged in afterwards.
+    pub async fn poll_drive(&self) -> Vec<FileRecord> {
+        let root = PathBuf::from(&self.path);
+        if !root.is_dir() {
+            return Vec::new();
+        }
+        let _scanning = self.scanning.lock().await;
+        self.load().await;
+        let known: BTreeMap<String, (u64, u64)> = self.index.read().await.iter()
+            .flat_map(|index| index.iter().map(|(rel, r)| (rel.clone(), (r.size, r.modified_ms))))
+            .collect();
+
+        let files = scan(&root);
+        let present: HashSet<String> = files.iter().map(|(rel, _, _)| rel.clone()).collect();
+
+        let now = now_ms();
+        let mut processed = Vec::new();
+        for (rel, size, modified_ms) in files {
+            let unchanged = known.get(&rel) == Some(&(size, modified_ms));
+            if unchanged || now.saturating_sub(modified_ms) < SETTLE_MS {
+                continue;
+            }
+            tracing::info!("New file on static drive: {}", rel);
+            let mut record = FileRecord { path: rel.clone(), size, modified_ms, ..Default::default() };
+            for action in std::iter::once(&DriveAction::Metadata).chain(&self.actions) {
+                if let Err(e) = action.run(&root, &self.http, &mut record).await {
+                    tracing::warn!("Static drive action '{}' failed for {}: {}", action.name(), rel, e);
+                    record.error = Some(format!("{}: {}", action.name(), e));
+                    break;
+                }
+            }
+            record.processed_at_ms = now_ms();
+            if let Some(bus) = &self.events {
+                let kind = ProxyEventKind::OutputIndexed { path: rel.clone(), size, modified_ms };
+                // The drive holds every tenant's files.
+                bus.publish(ProxyEvent::now(kind, Audience::Admin));
+            }
+            processed.push(record);
+        }
+
+        let mut index = self.index.write().await;
+        let index = index.get_or_insert_with(|| self.load_index());
+        let before = index.len();
+        index.retain(|rel, _| present.contains(rel));
+        let removed = before - index.len();
+        for record in &processed {
+            index.insert(record.path.clone(), record.clone());
+        }
+        if !processed.is_empty() || removed > 0 {
+            self.save_index(index).await;
+        }
+        processed
+    }
+
+    async fn save_index(&self, index: &BTreeMap<String, FileRecord>) {
+        match serde_json::to_vec_pretty(index) {
+            Ok(data) => {
+                if let Err(e) = tokio::fs::write(self.index_path(), data).await {
+                    tracing::warn!("Failed to write {}: {}", self.index_path().display(), e);
+                }
+            }
+            Err(e) => tracing::warn!("Failed to serialize static drive index: {}", e),
+        }
+    }
+}
+
+/// `(relative path, size, modified ms)` of every non-hidden file under `root`.
+fn scan(root: &Path) -> Vec<(String, u64, u64)> {
+    let mut files = Vec::new();
+    let mut stack = vec![root.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else { continue };
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            let Ok(meta) = entry.metadata() else { continue };
+            if meta.is_dir() {
+                stack.push(path);
+            } else if let Ok(rel) = path.strip_prefix(root) {
+                let modified_ms = meta.modified().ok()
+                    .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|d| d.as_millis() as u64)
+                    .unwrap_or(0);
+                files.push((rel.to_string_lossy().replace('\\', "/"), meta.len(), modified_ms));
+            }
         }
     }
+    files.sort();
+    files
 }
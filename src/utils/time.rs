@@ -0,0 +1,73 @@
+//! Small time helpers shared by job tracking and indexing.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Milliseconds since the Unix epoch.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+const DAY_MS: u64 = 86_400_000;
+
+/// Milliseconds in an age like `45s`, `30m`, `12h`, `1d`, or `2w`.
+pub fn parse_age(age: &str) -> Option<u64> {
+    let age = age.trim();
+    let unit_ms = match age.chars().last()? {
+        's' => 1000,
+        'm' => 60_000,
+        'h' => 3_600_000,
+        'd' => DAY_MS,
+        'w' => 7 * DAY_MS,
+        _ => return None,
+    };
+    let count: u64 = age[..age.len() - 1].parse().ok()?;
+    count.checked_mul(unit_ms)
+}
+
+/// UTC calendar date `(year, month, day)` for a Unix-epoch day number.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Howard Hinnant's days-to-civil algorithm.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
+/// Unix-epoch day number of a UTC calendar date.
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let m = m as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// UTC `(year, month, day)` containing the timestamp.
+pub fn civil_date(ms: u64) -> (i64, u32, u32) {
+    civil_from_days((ms / DAY_MS) as i64)
+}
+
+/// Start and end (exclusive) of the UTC day containing `ms`.
+pub fn day_bounds(ms: u64) -> (u64, u64) {
+    let start = ms / DAY_MS * DAY_MS;
+    (start, start + DAY_MS)
+}
+
+/// Start and end (exclusive) of the UTC calendar month containing `ms`.
+pub fn month_bounds(ms: u64) -> (u64, u64) {
+    let (y, m, _) = civil_date(ms);
+    let (ny, nm) = if m == 12 { (y + 1, 1) } else { (y, m + 1) };
+    let start = days_from_civil(y, m, 1) as u64 * DAY_MS;
+    let end = days_from_civil(ny, nm, 1) as u64 * DAY_MS;
+    (start, end)
+}
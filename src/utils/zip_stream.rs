@@ -0,0 +1,120 @@
+//! ZIP archives written entry by entry, for streaming downloads.
+//!
+//! Entries are stored uncompressed: outputs are PNG, JPEG, WebP, or video,
+//! which don't shrink further. Each entry's size and CRC are known before
+//! its header is written, so the archive never needs to seek back and each
+//! chunk can be sent as soon as it's ready. Archives are limited to what
+//! plain (non-ZIP64) ZIP allows: 65535 entries and 4 GiB.
+use crate::utils::time::civil_date;
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+/// 1.0: stored entries only.
+const VERSION: u16 = 10;
+/// Bit 11: names are UTF-8.
+const UTF8_NAMES: u16 = 1 << 11;
+
+struct CentralEntry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+pub struct ZipStream {
+    /// Last-modified time of every entry, as MS-DOS (time, date).
+    modified: (u16, u16),
+    offset: u64,
+    entries: Vec<CentralEntry>,
+}
+
+impl ZipStream {
+    /// A new archive whose entries are dated `now_ms`.
+    pub fn new(now_ms: u64) -> Self {
+        ZipStream { modified: dos_datetime(now_ms), offset: 0, entries: Vec::new() }
+    }
+
+    /// The bytes of one entry: its local header followed by `data`.
+    pub fn entry(&mut self, name: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+        let size = u32::try_from(data.len()).map_err(|_| format!("'{}' is too large for a ZIP archive", name))?;
+        let offset = u32::try_from(self.offset).map_err(|_| "Archive is too large for ZIP".to_string())?;
+        if self.entries.len() >= u16::MAX as usize {
+            return Err("Too many files for a ZIP archive".to_string());
+        }
+        let crc = crc32fast::hash(data);
+        let mut out = Vec::with_capacity(30 + name.len() + data.len());
+        put32(&mut out, LOCAL_HEADER);
+        put16(&mut out, VERSION);
+        put16(&mut out, UTF8_NAMES);
+        put16(&mut out, 0); // stored
+        put16(&mut out, self.modified.0);
+        put16(&mut out, self.modified.1);
+        put32(&mut out, crc);
+        put32(&mut out, size);
+        put32(&mut out, size);
+        put16(&mut out, name.len() as u16);
+        put16(&mut out, 0);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(data);
+        self.offset += out.len() as u64;
+        self.entries.push(CentralEntry { name: name.to_string(), crc, size, offset });
+        Ok(out)
+    }
+
+    /// The central directory, which ends the archive.
+    pub fn finish(self) -> Result<Vec<u8>, String> {
+        let start = u32::try_from(self.offset).map_err(|_| "Archive is too large for ZIP".to_string())?;
+        let mut out = Vec::new();
+        for entry in &self.entries {
+            put32(&mut out, CENTRAL_HEADER);
+            put16(&mut out, VERSION); // made by
+            put16(&mut out, VERSION); // needed
+            put16(&mut out, UTF8_NAMES);
+            put16(&mut out, 0);
+            put16(&mut out, self.modified.0);
+            put16(&mut out, self.modified.1);
+            put32(&mut out, entry.crc);
+            put32(&mut out, entry.size);
+            put32(&mut out, entry.size);
+            put16(&mut out, entry.name.len() as u16);
+            put16(&mut out, 0); // extra field
+            put16(&mut out, 0); // comment
+            put16(&mut out, 0); // disk
+            put16(&mut out, 0); // internal attributes
+            put32(&mut out, 0); // external attributes
+            put32(&mut out, entry.offset);
+            out.extend_from_slice(entry.name.as_bytes());
+        }
+        let size = out.len() as u32;
+        put32(&mut out, END_OF_CENTRAL_DIRECTORY);
+        put16(&mut out, 0);
+        put16(&mut out, 0);
+        put16(&mut out, self.entries.len() as u16);
+        put16(&mut out, self.entries.len() as u16);
+        put32(&mut out, size);
+        put32(&mut out, start);
+        put16(&mut out, 0);
+        Ok(out)
+    }
+}
+
+fn put16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// `ms` (UTC) as MS-DOS time and date; DOS dates start in 1980.
+fn dos_datetime(ms: u64) -> (u16, u16) {
+    let (year, month, day) = civil_date(ms);
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let secs = ms / 1000 % 86_400;
+    let time = ((secs / 3600) << 11) | ((secs / 60 % 60) << 5) | (secs % 60 / 2);
+    let date = (((year - 1980).min(127) as u32) << 9) | (month << 5) | day;
+    (time as u16, date as u16)
+}
@@ -0,0 +1,175 @@
+//! Portable workflow bundles for sharing workflows between proxy instances.
+//!
+//! A bundle is a `.tar.gz` with:
+//!
+//! - `manifest.json`: name, node count, and what the workflow needs on the
+//!   receiving side (custom node classes and model files).
+//! - `workflow.json`: the API graph, with `$include` snippets already expanded
+//!   so the bundle doesn't depend on the source instance's snippets.
+//! - `defaults.json`: the values the workflow uses for tunable params
+//!   (seed, steps, cfg, ...) when a request doesn't override them.
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::io::Read;
+
+use crate::utils::prompt_ops::KNOWN_PARAM_KEYS;
+use crate::utils::time::now_ms;
+
+const MANIFEST_PATH: &str = "manifest.json";
+const WORKFLOW_PATH: &str = "workflow.json";
+const DEFAULTS_PATH: &str = "defaults.json";
+const BUNDLE_VERSION: u32 = 1;
+/// Largest a bundle may be once decompressed. Real bundles are a few
+/// hundred KB; the cap keeps a small upload from inflating without bound.
+pub const MAX_BUNDLE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Loader inputs that name model files, and the ComfyUI model folder each
+/// one reads from.
+const MODEL_INPUTS: &[(&str, &str)] = &[
+    ("ckpt_name", "checkpoints"),
+    ("vae_name", "vae"),
+    ("lora_name", "loras"),
+    ("control_net_name", "controlnet"),
+    ("clip_name", "clip"),
+    ("clip_name1", "clip"),
+    ("clip_name2", "clip"),
+    ("unet_name", "diffusion_models"),
+    ("upscale_model", "upscale_models"),
+    ("model_name", "upscale_models"),
+    ("style_model_name", "style_models"),
+    ("clip_vision", "clip_vision"),
+];
+
+/// A model file the workflow loads.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ModelRef {
+    /// ComfyUI model folder, e.g. `checkpoints` or `loras`.
+    pub category: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub version: u32,
+    pub name: String,
+    pub created_at_ms: u64,
+    pub node_count: usize,
+    /// Every node class the graph uses, sorted.
+    pub nodes: Vec<String>,
+    pub models: Vec<ModelRef>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bundle {
+    pub manifest: BundleManifest,
+    pub workflow: Value,
+    pub defaults: Value,
+}
+
+/// Node classes used by `graph`, sorted and deduplicated.
+pub fn required_nodes(graph: &Value) -> Vec<String> {
+    let classes: BTreeSet<String> = graph.as_object().into_iter().flatten()
+        .filter_map(|(_, n)| n.get("class_type").and_then(|v| v.as_str()).map(String::from))
+        .collect();
+    classes.into_iter().collect()
+}
+
+/// Model files loaded by `graph`, sorted and deduplicated.
+pub fn required_models(graph: &Value) -> Vec<ModelRef> {
+    let mut models = BTreeSet::new();
+    for node in graph.as_object().into_iter().flatten().map(|(_, n)| n) {
+        let Some(inputs) = node.get("inputs").and_then(|i| i.as_object()) else { continue };
+        for (input, category) in MODEL_INPUTS {
+            if let Some(name) = inputs.get(*input).and_then(|v| v.as_str()).filter(|s| !s.is_empty()) {
+                models.insert(ModelRef { category: category.to_string(), name: name.to_string() });
+            }
+        }
+    }
+    models.into_iter().collect()
+}
+
+/// Values `graph` uses for each tunable param, taken from the lowest node id
+/// that has the input as a literal.
+pub fn param_defaults(graph: &Value) -> Value {
+    let Some(nodes) = graph.as_object() else { return Value::Object(Map::new()) };
+    let mut ids: Vec<&String> = nodes.keys().collect();
+    ids.sort_by(|a, b| a.parse::<u64>().ok().cmp(&b.parse::<u64>().ok()).then_with(|| a.cmp(b)));
+    let mut defaults = Map::new();
+    for key in KNOWN_PARAM_KEYS {
+        let value = ids.iter()
+            .filter_map(|id| nodes[*id].get("inputs").and_then(|i| i.get(*key)))
+            .find(|v| !v.is_array() && !v.is_object());
+        if let Some(value) = value {
+            defaults.insert(key.to_string(), value.clone());
+        }
+    }
+    Value::Object(defaults)
+}
+
+fn append_json<W: std::io::Write>(tar: &mut tar::Builder<W>, path: &str, value: &impl Serialize) -> Result<(), String> {
+    let data = serde_json::to_vec_pretty(value).map_err(|e| e.to_string())?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(now_ms() / 1000);
+    header.set_cksum();
+    tar.append_data(&mut header, path, data.as_slice()).map_err(|e| format!("Failed to add {} to bundle: {}", path, e))
+}
+
+/// Package workflow `name` (an API graph) as a bundle archive.
+pub fn write_bundle(name: &str, graph: &Value) -> Result<(BundleManifest, Vec<u8>), String> {
+    let manifest = BundleManifest {
+        version: BUNDLE_VERSION,
+        name: name.to_string(),
+        created_at_ms: now_ms(),
+        node_count: graph.as_object().map(|o| o.len()).unwrap_or(0),
+        nodes: required_nodes(graph),
+        models: required_models(graph),
+    };
+    let mut tar = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    append_json(&mut tar, MANIFEST_PATH, &manifest)?;
+    append_json(&mut tar, WORKFLOW_PATH, graph)?;
+    append_json(&mut tar, DEFAULTS_PATH, &param_defaults(graph))?;
+    let encoder = tar.into_inner().map_err(|e| format!("Failed to write bundle: {}", e))?;
+    let bytes = encoder.finish().map_err(|e| format!("Failed to write bundle: {}", e))?;
+    Ok((manifest, bytes))
+}
+
+/// Unpack a bundle archive. Entries other than the manifest, workflow, and
+/// defaults are ignored. Archives over [`MAX_BUNDLE_SIZE`] decompressed are
+/// rejected.
+pub fn read_bundle(bytes: &[u8]) -> Result<Bundle, String> {
+    let mut tar_bytes = Vec::new();
+    GzDecoder::new(bytes).take(MAX_BUNDLE_SIZE + 1).read_to_end(&mut tar_bytes).map_err(|e| format!("Invalid bundle: {}", e))?;
+    if tar_bytes.len() as u64 > MAX_BUNDLE_SIZE {
+        return Err(format!("Bundle is larger than {} MB decompressed", MAX_BUNDLE_SIZE / (1024 * 1024)));
+    }
+    let mut archive = tar::Archive::new(tar_bytes.as_slice());
+    let (mut manifest, mut workflow, mut defaults) = (None, None, None);
+    let entries = archive.entries().map_err(|e| format!("Invalid bundle: {}", e))?;
+    for entry in entries {
+        let mut entry = entry.map_err(|e| format!("Invalid bundle: {}", e))?;
+        let path = entry.path().map_err(|e| format!("Invalid bundle: {}", e))?.to_string_lossy().to_string();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).map_err(|e| format!("Failed to read {} from bundle: {}", path, e))?;
+        let parse = |what: &str| serde_json::from_slice::<Value>(&data).map_err(|e| format!("Invalid {} in bundle: {}", what, e));
+        match path.as_str() {
+            MANIFEST_PATH => manifest = Some(parse(MANIFEST_PATH)?),
+            WORKFLOW_PATH => workflow = Some(parse(WORKFLOW_PATH)?),
+            DEFAULTS_PATH => defaults = Some(parse(DEFAULTS_PATH)?),
+            _ => {}
+        }
+    }
+    let manifest: BundleManifest = serde_json::from_value(manifest.ok_or("Bundle has no manifest.json")?)
+        .map_err(|e| format!("Invalid manifest.json in bundle: {}", e))?;
+    if manifest.version > BUNDLE_VERSION {
+        return Err(format!("Bundle version {} is newer than supported ({})", manifest.version, BUNDLE_VERSION));
+    }
+    Ok(Bundle {
+        manifest,
+        workflow: workflow.ok_or("Bundle has no workflow.json")?,
+        defaults: defaults.unwrap_or_else(|| Value::Object(Map::new())),
+    })
+}
@@ -0,0 +1,153 @@
+//! Removing nodes from API graphs the way the UI's bypass does.
+//!
+//! API graphs carry no mute/bypass state, so a disabled node has to be taken
+//! out of the graph. Consumers of its main output are rewired to the input it
+//! would pass through (e.g. a detailer's `image`); consumers that can't be
+//! rewired are removed as well, like muting them. Muting removes a node and
+//! everything downstream of it.
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+use crate::workflow::compose::link_source;
+
+/// Input names that carry a node's main data, in the order they are tried
+/// when picking what a bypassed node passes through.
+const PASSTHROUGH_INPUTS: &[&str] = &[
+    "image", "images", "pixels", "samples", "latent_image", "latent", "model", "conditioning", "clip",
+];
+
+/// UI group titles a node belonged to, as recorded by the converter.
+fn node_groups(node: &Value) -> Vec<&str> {
+    node.get("_meta").and_then(|m| m.get("groups")).and_then(|g| g.as_array())
+        .map(|a| a.iter().filter_map(|v| v.as_str()).collect())
+        .unwrap_or_default()
+}
+
+fn in_group(node: &Value, group: &str) -> bool {
+    node_groups(node).iter().any(|g| g.trim().eq_ignore_ascii_case(group.trim()))
+}
+
+/// Node ids matching `selector`: a node id, `title:<title>` (case-insensitive
+/// `_meta.title`), `class:<class_type>`, or `group:<group title>`. Sorted by id.
+pub fn select_nodes(graph: &Value, selector: &str) -> Vec<String> {
+    let Some(nodes) = graph.as_object() else { return Vec::new() };
+    let mut ids: Vec<String> = if let Some(group) = selector.strip_prefix("group:") {
+        nodes.iter().filter(|(_, n)| in_group(n, group)).map(|(id, _)| id.clone()).collect()
+    } else if let Some(title) = selector.strip_prefix("title:") {
+        nodes.iter()
+            .filter(|(_, n)| {
+                n.get("_meta").and_then(|m| m.get("title")).and_then(|t| t.as_str())
+                    .map(|t| t.trim().eq_ignore_ascii_case(title.trim()))
+                    .unwrap_or(false)
+            })
+            .map(|(id, _)| id.clone())
+            .collect()
+    } else if let Some(class) = selector.strip_prefix("class:") {
+        nodes.iter()
+            .filter(|(_, n)| n.get("class_type").and_then(|v| v.as_str()) == Some(class.trim()))
+            .map(|(id, _)| id.clone())
+            .collect()
+    } else {
+        nodes.contains_key(selector).then(|| selector.to_string()).into_iter().collect()
+    };
+    ids.sort();
+    ids
+}
+
+/// The link a bypassed `node` forwards from its first output, if any.
+fn passthrough(node: &Value) -> Option<Value> {
+    let inputs = node.get("inputs")?.as_object()?;
+    let preferred = PASSTHROUGH_INPUTS.iter().find_map(|name| inputs.get(*name).filter(|v| link_source(v).is_some()));
+    if let Some(link) = preferred {
+        return Some(link.clone());
+    }
+    let mut links = inputs.values().filter(|v| link_source(v).is_some());
+    match (links.next(), links.next()) {
+        (Some(only), None) => Some(only.clone()),
+        _ => None,
+    }
+}
+
+/// Bypass the nodes in `ids` and return every node removed, including
+/// consumers that couldn't be rewired. Unknown ids are ignored.
+pub fn bypass_nodes(graph: &mut Value, ids: &[String]) -> Vec<String> {
+    remove_nodes(graph, ids, true)
+}
+
+/// Mute the nodes in `ids`: remove them and everything downstream of them.
+/// Returns every node removed.
+pub fn mute_nodes(graph: &mut Value, ids: &[String]) -> Vec<String> {
+    remove_nodes(graph, ids, false)
+}
+
+fn remove_nodes(graph: &mut Value, ids: &[String], bypass: bool) -> Vec<String> {
+    let mut removed = BTreeSet::new();
+    let mut pending: Vec<(String, bool)> = ids.iter().map(|id| (id.clone(), bypass)).collect();
+    while let Some((id, rewire)) = pending.pop() {
+        let Some(nodes) = graph.as_object_mut() else { break };
+        let Some(node) = nodes.remove(&id) else { continue };
+        removed.insert(id.clone());
+        let forward = if rewire { passthrough(&node) } else { None };
+        for (consumer_id, consumer) in nodes.iter_mut() {
+            let Some(inputs) = consumer.get_mut("inputs").and_then(|i| i.as_object_mut()) else { continue };
+            for value in inputs.values_mut() {
+                if link_source(value).as_deref() != Some(id.as_str()) { continue; }
+                match (&forward, value[1].as_i64()) {
+                    (Some(link), Some(0)) => *value = link.clone(),
+                    _ => pending.push((consumer_id.clone(), bypass && ids.contains(consumer_id))),
+                }
+            }
+        }
+    }
+    removed.into_iter().collect()
+}
+
+/// Turn an optional workflow stage on or off. The stage is the set of nodes
+/// matching `selectors`; enabling leaves them in place, disabling bypasses
+/// them. Returns the removed node ids, or an error when no node matches.
+pub fn toggle_stage(graph: &mut Value, selectors: &[String], enabled: bool) -> Result<Vec<String>, String> {
+    let mut ids: Vec<String> = selectors.iter().flat_map(|s| select_nodes(graph, s)).collect();
+    ids.sort();
+    ids.dedup();
+    if ids.is_empty() {
+        return Err(format!("workflow has no nodes matching {}", selectors.join(", ")));
+    }
+    Ok(if enabled { Vec::new() } else { bypass_nodes(graph, &ids) })
+}
+
+/// Enable or disable UI groups for one submission.
+///
+/// `overrides` maps group titles to on/off. Nodes the UI had muted or
+/// bypassed (`_meta.mode`) stay disabled unless one of their groups is turned
+/// on; nodes in a group turned off are bypassed. Disabled nodes are removed
+/// and `_meta.mode` is cleared from the rest, so the result is safe to submit.
+/// Returns warnings for group names the graph doesn't have.
+pub fn apply_groups(graph: &mut Value, overrides: &[(String, bool)]) -> Vec<String> {
+    let Some(nodes) = graph.as_object_mut() else { return Vec::new() };
+    let warnings = overrides.iter()
+        .filter(|(group, _)| !nodes.values().any(|n| in_group(n, group)))
+        .map(|(group, _)| format!("group '{}' not found in workflow", group))
+        .collect();
+    let setting = |node: &Value| -> Option<bool> {
+        let states: Vec<bool> = overrides.iter().filter(|(g, _)| in_group(node, g)).map(|(_, on)| *on).collect();
+        if states.contains(&false) { Some(false) } else if states.contains(&true) { Some(true) } else { None }
+    };
+
+    let mut mute = Vec::new();
+    let mut bypass = Vec::new();
+    for (id, node) in nodes.iter_mut() {
+        let mode = node.get("_meta").and_then(|m| m.get("mode")).and_then(|v| v.as_str()).map(String::from);
+        match (setting(node), mode.as_deref()) {
+            (Some(false), Some("mute")) | (None, Some("mute")) => mute.push(id.clone()),
+            (Some(false), _) | (None, Some(_)) => bypass.push(id.clone()),
+            _ => {
+                if let Some(meta) = node.get_mut("_meta").and_then(|m| m.as_object_mut()) {
+                    meta.remove("mode");
+                }
+            }
+        }
+    }
+    bypass_nodes(graph, &bypass);
+    mute_nodes(graph, &mute);
+    warnings
+}
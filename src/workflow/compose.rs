@@ -0,0 +1,99 @@
+//! Merging API graphs without node id collisions.
+//!
+//! API graphs reference other nodes by id (`"input": ["<id>", <slot>]`), so
+//! combining two graphs means renumbering one of them and rewriting its links
+//! to match. This is the building block for `$include` snippets and other
+//! graph stitching.
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// Old id -> new id for the nodes of a merged graph.
+pub type IdMap = HashMap<String, String>;
+
+/// Source node id of a link value (`["<id>", <slot>]`), if `v` is one.
+pub fn link_source(v: &Value) -> Option<String> {
+    let arr = v.as_array()?;
+    if arr.len() != 2 || !arr[1].is_i64() { return None; }
+    arr[0].as_str().map(String::from).or_else(|| arr[0].as_i64().map(|n| n.to_string()))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// Nodes of `b` that must keep a specific id in the merged graph.
+    pub pinned: IdMap,
+    /// Ids `b` links to without defining, mapped to nodes of `a`.
+    pub external: IdMap,
+}
+
+fn next_free_id(a: &Map<String, Value>, opts: &MergeOptions) -> u64 {
+    a.keys().chain(opts.pinned.values())
+        .filter_map(|k| k.parse::<u64>().ok())
+        .max()
+        .map(|n| n + 1)
+        .unwrap_or(1)
+}
+
+/// Add `node` to `graph` under the next free numeric id and return that id.
+pub fn add_node(graph: &mut Map<String, Value>, node: Value) -> String {
+    let id = next_free_id(graph, &MergeOptions::default()).to_string();
+    graph.insert(id.clone(), node);
+    id
+}
+
+/// Merge `b` into `a` in place and return the id assigned to each node of `b`.
+///
+/// Unpinned nodes of `b` are renumbered, in id order, past the highest
+/// numeric id in `a`. Links inside `b` are rewritten to the new ids; links to
+/// ids `b` doesn't define must be listed in `opts.external`.
+pub fn merge_into(a: &mut Map<String, Value>, b: Map<String, Value>, opts: &MergeOptions) -> Result<IdMap, String> {
+    let mut next = next_free_id(a, opts);
+    let mut ids: Vec<&String> = b.keys().collect();
+    ids.sort_by_key(|id| (id.parse::<u64>().unwrap_or(u64::MAX), (*id).clone()));
+
+    let mut id_map = IdMap::new();
+    for id in ids {
+        let new_id = match opts.pinned.get(id) {
+            Some(target) => target.clone(),
+            None => {
+                let id = next.to_string();
+                next += 1;
+                id
+            }
+        };
+        if a.contains_key(&new_id) {
+            return Err(format!("node id '{}' already exists in the target graph", new_id));
+        }
+        id_map.insert(id.clone(), new_id);
+    }
+
+    let mut links = id_map.clone();
+    for (external, target) in &opts.external {
+        if !b.contains_key(external) {
+            links.insert(external.clone(), target.clone());
+        }
+    }
+    let mut renumbered = Vec::with_capacity(b.len());
+    for (id, mut node) in b {
+        if let Some(inputs) = node.get_mut("inputs").and_then(|v| v.as_object_mut()) {
+            for (input, value) in inputs.iter_mut() {
+                let Some(source) = link_source(value) else { continue };
+                let target = links.get(&source).ok_or_else(|| {
+                    format!("node {} input '{}' links to '{}', which is neither in the merged graph nor mapped", id, input, source)
+                })?;
+                value[0] = Value::String(target.clone());
+            }
+        }
+        renumbered.push((id_map[&id].clone(), node));
+    }
+    a.extend(renumbered);
+    Ok(id_map)
+}
+
+/// Merge two API graphs, renumbering `b`'s nodes so they don't collide with
+/// `a`'s. Returns the merged graph and `b`'s id mapping.
+pub fn merge_graphs(a: &Value, b: &Value) -> Result<(Value, IdMap), String> {
+    let mut merged = a.as_object().cloned().ok_or("first graph is not an object")?;
+    let b = b.as_object().cloned().ok_or("second graph is not an object")?;
+    let id_map = merge_into(&mut merged, b, &MergeOptions::default())?;
+    Ok((Value::Object(merged), id_map))
+}
@@ -0,0 +1,369 @@
+//! Conversion between ComfyUI workflow formats.
+//!
+//! The web UI exports graphs as `{"nodes": [...], "links": [...]}` with
+//! positional `widgets_values`, while `/prompt` expects the API format
+//! `{"<id>": {"class_type", "inputs"}}`. This module detects which format a
+//! document is in and converts UI exports into API graphs.
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+/// Shape of a workflow document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkflowFormat {
+    /// API prompt graph, optionally wrapped as `{"prompt": {...}}`.
+    Api,
+    /// UI export with `nodes` and `links` arrays.
+    Ui,
+    Unknown,
+}
+
+impl WorkflowFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkflowFormat::Api => "api",
+            WorkflowFormat::Ui => "ui",
+            WorkflowFormat::Unknown => "unknown",
+        }
+    }
+}
+
+// Stored workflows may wrap either format as `{"prompt": ...}`.
+fn unwrap_prompt(doc: &Value) -> &Value {
+    match doc.get("prompt") {
+        Some(inner) if inner.is_object() => inner,
+        _ => doc,
+    }
+}
+
+/// Detect whether `doc` is an API graph or a UI export.
+pub fn detect_format(doc: &Value) -> WorkflowFormat {
+    let inner = unwrap_prompt(doc);
+    if inner.get("nodes").map(|v| v.is_array()).unwrap_or(false)
+        && inner.get("links").map(|v| v.is_array()).unwrap_or(false)
+    {
+        return WorkflowFormat::Ui;
+    }
+    if crate::utils::prompt_build::is_probably_graph(inner) {
+        WorkflowFormat::Api
+    } else {
+        WorkflowFormat::Unknown
+    }
+}
+
+/// Normalize an uploaded document into a bare API graph, converting UI
+/// exports when necessary. Returns the graph and the detected source format.
+pub fn to_api_graph(doc: &Value) -> Result<(Value, WorkflowFormat), String> {
+    to_api_graph_with(doc, None)
+}
+
+/// [`to_api_graph`], reading widget layouts of nodes this module doesn't know
+/// from ComfyUI's `/object_info` document when given.
+pub fn to_api_graph_with(doc: &Value, object_info: Option<&Value>) -> Result<(Value, WorkflowFormat), String> {
+    let inner = unwrap_prompt(doc);
+    match detect_format(doc) {
+        WorkflowFormat::Api => Ok((inner.clone(), WorkflowFormat::Api)),
+        WorkflowFormat::Ui => Ok((ui_to_api_with(inner, object_info)?, WorkflowFormat::Ui)),
+        WorkflowFormat::Unknown => Err("Document is neither an API prompt graph nor a UI workflow export".to_string()),
+    }
+}
+
+// Node types that only exist in the editor and never reach the backend.
+const UI_ONLY_TYPES: &[&str] = &["Note", "MarkdownNote", "PrimitiveNode", "Reroute"];
+
+// Widget layouts for common core nodes. `None` marks frontend-only widgets
+// (e.g. `control_after_generate`) that occupy a slot in `widgets_values` but
+// are not backend inputs.
+fn known_widget_names(class_type: &str) -> Option<&'static [Option<&'static str>]> {
+    const CONTROL: Option<&str> = None;
+    let names: &'static [Option<&'static str>] = match class_type {
+        "KSampler" => &[Some("seed"), CONTROL, Some("steps"), Some("cfg"), Some("sampler_name"), Some("scheduler"), Some("denoise")],
+        "KSamplerAdvanced" => &[
+            Some("add_noise"), Some("noise_seed"), CONTROL, Some("steps"), Some("cfg"), Some("sampler_name"),
+            Some("scheduler"), Some("start_at_step"), Some("end_at_step"), Some("return_with_leftover_noise"),
+        ],
+        "CLIPTextEncode" => &[Some("text")],
+        "EmptyLatentImage" | "EmptySD3LatentImage" => &[Some("width"), Some("height"), Some("batch_size")],
+        "CheckpointLoaderSimple" => &[Some("ckpt_name")],
+        "SaveImage" => &[Some("filename_prefix")],
+        "VAELoader" => &[Some("vae_name")],
+        "LoraLoader" => &[Some("lora_name"), Some("strength_model"), Some("strength_clip")],
+        "CLIPSetLastLayer" => &[Some("stop_at_clip_layer")],
+        "LoadImage" => &[Some("image"), CONTROL],
+        "LatentUpscale" | "ImageScale" => &[Some("upscale_method"), Some("width"), Some("height"), Some("crop")],
+        "LatentUpscaleBy" | "ImageScaleBy" => &[Some("upscale_method"), Some("scale_by")],
+        "UpscaleModelLoader" => &[Some("model_name")],
+        "UNETLoader" => &[Some("unet_name"), Some("weight_dtype")],
+        "CLIPLoader" => &[Some("clip_name"), Some("type")],
+        "DualCLIPLoader" => &[Some("clip_name1"), Some("clip_name2"), Some("type")],
+        "FluxGuidance" => &[Some("guidance")],
+        "RandomNoise" => &[Some("noise_seed"), CONTROL],
+        "KSamplerSelect" => &[Some("sampler_name")],
+        "BasicScheduler" => &[Some("scheduler"), Some("steps"), Some("denoise")],
+        "ControlNetLoader" => &[Some("control_net_name")],
+        "ControlNetApply" => &[Some("strength")],
+        "VAEDecode" | "VAEEncode" | "PreviewImage" => &[],
+        _ => return None,
+    };
+    Some(names)
+}
+
+// Widget names advertised by newer frontends on the node's `inputs` entries.
+fn widget_names_from_inputs(node: &Value) -> Vec<String> {
+    node.get("inputs")
+        .and_then(|v| v.as_array())
+        .map(|arr| {
+            arr.iter()
+                .filter_map(|i| i.get("widget").and_then(|w| w.get("name")).and_then(|n| n.as_str()))
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Widget layout from a node definition in `/object_info`. Only definitions
+// with `input_order` qualify; the input maps themselves are unordered here.
+fn widget_names_from_object_info(info: &Value, class_type: &str) -> Option<Vec<Option<String>>> {
+    let definition = info.get(class_type)?;
+    let order = definition.get("input_order")?;
+    let mut names = Vec::new();
+    for section in ["required", "optional"] {
+        let Some(inputs) = order.get(section).and_then(|v| v.as_array()) else { continue };
+        for name in inputs.iter().filter_map(|n| n.as_str()) {
+            let Some(spec) = definition.pointer(&format!("/input/{}/{}", section, name)) else { continue };
+            let config = spec.get(1);
+            let flag = |key: &str| config.and_then(|c| c.get(key)).and_then(|v| v.as_bool()).unwrap_or(false);
+            match spec.get(0) {
+                Some(Value::Array(_)) => names.push(Some(name.to_string())),
+                Some(Value::String(kind)) if kind == "INT" => {
+                    names.push(Some(name.to_string()));
+                    // The editor adds its control widget after seeds.
+                    if flag("control_after_generate") || name == "seed" || name == "noise_seed" {
+                        names.push(None);
+                    }
+                }
+                Some(Value::String(kind)) if matches!(kind.as_str(), "FLOAT" | "STRING" | "BOOLEAN" | "COMBO") => {
+                    names.push(Some(name.to_string()));
+                }
+                _ => continue,
+            }
+            if flag("image_upload") {
+                names.push(None);
+            }
+        }
+    }
+    Some(names)
+}
+
+fn node_id_string(v: &Value) -> Option<String> {
+    v.as_i64().map(|n| n.to_string()).or_else(|| v.as_str().map(String::from))
+}
+
+/// Convert a UI export (`nodes`/`links`) into an API prompt graph.
+///
+/// Links through `Reroute` nodes are followed to their origin, values held by
+/// `PrimitiveNode`s are inlined into their targets, and notes are dropped.
+/// Nodes inside UI groups record the group titles in `_meta.groups`. Muted
+/// (`mode == 2`) and bypassed (`mode == 4`) nodes are omitted, unless they
+/// belong to a group: those are kept with `_meta.mode` (`"mute"`/`"bypass"`)
+/// so the group can be enabled per request (see [`crate::workflow::bypass::apply_groups`]).
+pub fn ui_to_api(ui: &Value) -> Result<Value, String> {
+    ui_to_api_with(ui, None)
+}
+
+/// [`ui_to_api`], mapping the `widgets_values` of nodes without a built-in
+/// layout through their `/object_info` definitions when `object_info` is given.
+pub fn ui_to_api_with(ui: &Value, object_info: Option<&Value>) -> Result<Value, String> {
+    let nodes = ui.get("nodes").and_then(|v| v.as_array()).ok_or("UI workflow is missing a 'nodes' array")?;
+    let links = ui.get("links").and_then(|v| v.as_array()).ok_or("UI workflow is missing a 'links' array")?;
+
+    let mut by_id: HashMap<String, &Value> = HashMap::new();
+    for node in nodes {
+        let id = node.get("id").and_then(node_id_string).ok_or("UI node without an 'id'")?;
+        by_id.insert(id, node);
+    }
+
+    // link id -> (origin node id, origin slot)
+    let mut link_origin: HashMap<i64, (String, i64)> = HashMap::new();
+    for link in links {
+        let (id, from, slot) = if let Some(arr) = link.as_array() {
+            (arr.first().and_then(|v| v.as_i64()), arr.get(1).and_then(node_id_string), arr.get(2).and_then(|v| v.as_i64()))
+        } else {
+            (
+                link.get("id").and_then(|v| v.as_i64()),
+                link.get("origin_id").and_then(node_id_string),
+                link.get("origin_slot").and_then(|v| v.as_i64()),
+            )
+        };
+        if let (Some(id), Some(from), Some(slot)) = (id, from, slot) {
+            link_origin.insert(id, (from, slot));
+        }
+    }
+
+    let groups: Vec<UiGroup> = ui.get("groups").and_then(|v| v.as_array())
+        .map(|a| a.iter().filter_map(UiGroup::parse).collect())
+        .unwrap_or_default();
+
+    let mut out = Map::new();
+    for node in nodes {
+        let id = node.get("id").and_then(node_id_string).unwrap_or_default();
+        let class_type = node.get("type").and_then(|v| v.as_str()).ok_or_else(|| format!("Node {} has no 'type'", id))?;
+        if UI_ONLY_TYPES.contains(&class_type) {
+            continue;
+        }
+        let node_groups: Vec<&str> = groups.iter().filter(|g| g.contains(node)).map(|g| g.title.as_str()).collect();
+        let disabled = match node.get("mode").and_then(|v| v.as_i64()).unwrap_or(0) {
+            2 => Some("mute"),
+            4 => Some("bypass"),
+            _ => None,
+        };
+        if disabled.is_some() && node_groups.is_empty() {
+            continue;
+        }
+
+        let mut inputs = Map::new();
+
+        // Widget values first; linked inputs below override converted widgets.
+        let values: Vec<Value> = node.get("widgets_values").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+        if !values.is_empty() {
+            let names: Vec<Option<String>> = match known_widget_names(class_type) {
+                Some(known) => known.iter().map(|n| n.map(String::from)).collect(),
+                None => match object_info.and_then(|info| widget_names_from_object_info(info, class_type)) {
+                    Some(names) => names,
+                    None => widget_names_from_inputs(node).into_iter().map(Some).collect(),
+                },
+            };
+            if names.is_empty() {
+                return Err(format!("Unknown widget layout for node {} ({}); export it in API format instead", id, class_type));
+            }
+            for (name, value) in names.iter().zip(values.iter()) {
+                if let Some(name) = name {
+                    inputs.insert(name.clone(), value.clone());
+                }
+            }
+        }
+
+        if let Some(arr) = node.get("inputs").and_then(|v| v.as_array()) {
+            for input in arr {
+                let Some(name) = input.get("name").and_then(|v| v.as_str()) else { continue };
+                let Some(link_id) = input.get("link").and_then(|v| v.as_i64()) else { continue };
+                match resolve_link(link_id, &link_origin, &by_id)? {
+                    Some(Resolved::Link(from, slot)) => { inputs.insert(name.to_string(), json!([from, slot])); }
+                    Some(Resolved::Value(v)) => { inputs.insert(name.to_string(), v); }
+                    None => {}
+                }
+            }
+        }
+
+        let title = node.get("title").and_then(|v| v.as_str()).unwrap_or(class_type);
+        let mut meta = json!({"title": title});
+        if !node_groups.is_empty() {
+            meta["groups"] = json!(node_groups);
+        }
+        if let Some(mode) = disabled {
+            meta["mode"] = json!(mode);
+        }
+        out.insert(id, json!({
+            "inputs": inputs,
+            "class_type": class_type,
+            "_meta": meta,
+        }));
+    }
+
+    if out.is_empty() {
+        return Err("UI workflow contains no executable nodes".to_string());
+    }
+    Ok(Value::Object(out))
+}
+
+/// A titled UI group and its canvas rectangle.
+struct UiGroup {
+    title: String,
+    bounding: [f64; 4],
+}
+
+impl UiGroup {
+    fn parse(group: &Value) -> Option<Self> {
+        let title = group.get("title")?.as_str()?.to_string();
+        let b = group.get("bounding")?.as_array()?;
+        let n = |i: usize| b.get(i).and_then(|v| v.as_f64());
+        Some(UiGroup { title, bounding: [n(0)?, n(1)?, n(2)?, n(3)?] })
+    }
+
+    /// Whether `node`'s position lies inside the group, as the editor decides
+    /// membership when a group is moved.
+    fn contains(&self, node: &Value) -> bool {
+        let pos = node.get("pos");
+        let coord = |i: usize, key: &str| pos.and_then(|p| p.get(i).or_else(|| p.get(key))).and_then(|v| v.as_f64());
+        let (Some(x), Some(y)) = (coord(0, "0"), coord(1, "1")) else { return false };
+        let [gx, gy, gw, gh] = self.bounding;
+        x >= gx && x <= gx + gw && y >= gy && y <= gy + gh
+    }
+}
+
+enum Resolved {
+    Link(String, i64),
+    Value(Value),
+}
+
+fn resolve_link(
+    link_id: i64,
+    link_origin: &HashMap<i64, (String, i64)>,
+    by_id: &HashMap<String, &Value>,
+) -> Result<Option<Resolved>, String> {
+    let mut current = link_id;
+    // Bounded walk to guard against reroute cycles in malformed exports.
+    for _ in 0..64 {
+        let Some((from, slot)) = link_origin.get(&current) else {
+            return Err(format!("Link {} references an unknown origin", current));
+        };
+        let Some(origin) = by_id.get(from) else {
+            return Err(format!("Link {} originates from missing node {}", current, from));
+        };
+        match origin.get("type").and_then(|v| v.as_str()) {
+            Some("Reroute") => {
+                let upstream = origin.get("inputs")
+                    .and_then(|v| v.as_array())
+                    .and_then(|a| a.first())
+                    .and_then(|i| i.get("link"))
+                    .and_then(|v| v.as_i64());
+                match upstream {
+                    Some(l) => current = l,
+                    None => return Ok(None),
+                }
+            }
+            Some("PrimitiveNode") => {
+                let value = origin.get("widgets_values").and_then(|v| v.as_array()).and_then(|a| a.first()).cloned();
+                return Ok(value.map(Resolved::Value));
+            }
+            _ => return Ok(Some(Resolved::Link(from.clone(), *slot))),
+        }
+    }
+    Err(format!("Link {} could not be resolved (reroute cycle?)", link_id))
+}
+
+/// Check that every link in an API graph points at an existing node.
+pub fn validate_api_graph(graph: &Value) -> Result<(), String> {
+    let nodes = graph.as_object().ok_or("Prompt graph must be a JSON object")?;
+    if nodes.is_empty() {
+        return Err("Prompt graph has no nodes".to_string());
+    }
+    for (id, node) in nodes {
+        if node.get("class_type").and_then(|v| v.as_str()).is_none() {
+            return Err(format!("Node {} is missing 'class_type'", id));
+        }
+        if let Some(inputs) = node.get("inputs").and_then(|v| v.as_object()) {
+            for (name, v) in inputs {
+                if let Some(arr) = v.as_array() {
+                    if arr.len() == 2 && arr[1].is_i64() {
+                        if let Some(src) = arr.first().and_then(node_id_string) {
+                            if !nodes.contains_key(&src) {
+                                return Err(format!("Node {} input '{}' references missing node {}", id, name, src));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
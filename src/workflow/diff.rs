@@ -0,0 +1,59 @@
+//! Input-level differences between two API graphs.
+//!
+//! Used to report what request params and overrides actually changed in a
+//! workflow, since a param that matches no node input is otherwise silent.
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, Serialize, PartialEq, utoipa::ToSchema)]
+pub struct InputChange {
+    pub node: String,
+    pub class_type: Option<String>,
+    pub input: String,
+    /// Previous value; `None` when the input (or node) was added.
+    pub old: Option<Value>,
+    /// New value; `None` when the input (or node) was removed.
+    pub new: Option<Value>,
+}
+
+fn inputs_of<'a>(graph: &'a Value, id: &str) -> Option<&'a Map<String, Value>> {
+    graph.get(id)?.get("inputs")?.as_object()
+}
+
+fn class_of(graph: &Value, id: &str) -> Option<String> {
+    graph.get(id)?.get("class_type")?.as_str().map(String::from)
+}
+
+/// Every node input whose value differs between `before` and `after`,
+/// ordered by node id (numerically where possible) and input name.
+pub fn diff_inputs(before: &Value, after: &Value) -> Vec<InputChange> {
+    let mut ids: Vec<&String> = before.as_object().into_iter().flatten()
+        .chain(after.as_object().into_iter().flatten())
+        .map(|(k, _)| k)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    ids.sort_by_key(|id| (id.parse::<u64>().unwrap_or(u64::MAX), (*id).clone()));
+
+    let empty = Map::new();
+    let mut changes = Vec::new();
+    for id in ids {
+        let old_inputs = inputs_of(before, id).unwrap_or(&empty);
+        let new_inputs = inputs_of(after, id).unwrap_or(&empty);
+        let names: BTreeSet<&String> = old_inputs.keys().chain(new_inputs.keys()).collect();
+        for name in names {
+            let (old, new) = (old_inputs.get(name), new_inputs.get(name));
+            if old != new {
+                changes.push(InputChange {
+                    node: id.clone(),
+                    class_type: class_of(after, id).or_else(|| class_of(before, id)),
+                    input: name.clone(),
+                    old: old.cloned(),
+                    new: new.cloned(),
+                });
+            }
+        }
+    }
+    changes
+}
@@ -0,0 +1,91 @@
+//! `$include` directives for splicing reusable subgraphs into workflows.
+//!
+//! A stored API graph may contain entries of the form
+//! `"<any key>": {"$include": "snippets/upscale_chain.json", "remap": {...}}`.
+//! At load time each directive is replaced by the snippet's nodes:
+//!
+//! - Snippet node ids named in `remap` take the given id, so host nodes can
+//!   link to them (e.g. the chain's final node).
+//! - Other snippet nodes are renumbered past the host's highest numeric id.
+//! - `remap` keys that are *not* snippet nodes are external inputs: snippet
+//!   links to them are rewired to the given host node.
+//!
+//! Include paths are relative to the prompts directory, and snippets may
+//! include further snippets.
+use serde_json::{Map, Value};
+
+use crate::workflow::compose::{merge_into, IdMap, MergeOptions};
+
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+fn is_include(node: &Value) -> bool {
+    node.get("$include").is_some()
+}
+
+fn graph_mut(doc: &mut Value) -> Option<&mut Map<String, Value>> {
+    if doc.get("prompt").map(|p| p.is_object()).unwrap_or(false) {
+        doc.get_mut("prompt")?.as_object_mut()
+    } else {
+        doc.as_object_mut()
+    }
+}
+
+fn read_snippet(base_dir: &str, path: &str) -> Result<Value, String> {
+    if path.starts_with('/') || path.split(['/', '\\']).any(|part| part == "..") {
+        return Err(format!("Invalid include path '{}'", path));
+    }
+    let full = format!("{}/{}", base_dir.trim_end_matches('/'), path);
+    let data = std::fs::read_to_string(&full).map_err(|e| format!("Failed to read include {}: {}", full, e))?;
+    serde_json::from_str(&data).map_err(|e| format!("Failed to parse include {}: {}", full, e))
+}
+
+fn remap_targets(directive: &Value) -> Result<IdMap, String> {
+    let Some(remap) = directive.get("remap") else { return Ok(IdMap::new()) };
+    let remap = remap.as_object().ok_or("'remap' must be an object")?;
+    remap.iter()
+        .map(|(k, v)| {
+            let target = v.as_str().map(String::from)
+                .or_else(|| v.as_i64().map(|n| n.to_string()))
+                .ok_or_else(|| format!("remap target for '{}' must be a node id", k))?;
+            Ok((k.clone(), target))
+        })
+        .collect()
+}
+
+fn expand(doc: &mut Value, base_dir: &str, depth: usize) -> Result<usize, String> {
+    let Some(graph) = graph_mut(doc) else { return Ok(0) };
+    let mut keys: Vec<String> = graph.iter().filter(|(_, n)| is_include(n)).map(|(k, _)| k.clone()).collect();
+    if keys.is_empty() {
+        return Ok(0);
+    }
+    if depth >= MAX_INCLUDE_DEPTH {
+        return Err(format!("Includes nested deeper than {} levels", MAX_INCLUDE_DEPTH));
+    }
+    keys.sort();
+    let mut count = 0;
+    for key in keys {
+        let directive = graph.remove(&key).unwrap_or_default();
+        let path = directive.get("$include").and_then(|v| v.as_str())
+            .ok_or_else(|| format!("'$include' in '{}' must be a path", key))?;
+        let mut snippet = read_snippet(base_dir, path)?;
+        count += expand(&mut snippet, base_dir, depth + 1)?;
+        let snippet = graph_mut(&mut snippet).map(std::mem::take)
+            .ok_or_else(|| format!("Include {} is not an API graph", path))?;
+        // `remap` mixes pinned snippet ids and external inputs; merge_into
+        // ignores external entries for ids the snippet defines.
+        let remap = remap_targets(&directive)?;
+        let opts = MergeOptions {
+            pinned: remap.iter().filter(|(k, _)| snippet.contains_key(*k)).map(|(k, v)| (k.clone(), v.clone())).collect(),
+            external: remap,
+        };
+        merge_into(graph, snippet, &opts).map_err(|e| format!("{}: {}", path, e))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Replace every `$include` directive in `doc` (a graph, optionally wrapped
+/// in `{"prompt": ...}`) with its snippet. Returns how many were expanded.
+pub fn expand_includes(doc: &mut Value, base_dir: &str) -> Result<usize, String> {
+    expand(doc, base_dir, 0)
+}
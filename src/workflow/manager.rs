@@ -1,33 +1,89 @@
 //! Minimal in-memory and file-backed workflow manager.
 //!
 //! Responsibilities:
-//! - Load/save workflows from `prompts/<name>.json`.
+//! - Load/save workflows from `<prompts_dir>/<name>.json` (default `prompts/`);
+//!   tenants get their own `<prompts_dir>/<tenant>/` namespace.
+//! - List and delete the stored workflows.
+//! - Expand `$include` snippet directives when loading (see [`crate::workflow::include`]).
 //! - Keep track of the last selected workflow.
 //! - Store arbitrary node metadata (if provided programmatically).
+use serde::Serialize;
 use serde_json::Value;
 use std::collections::HashMap;
 
+use crate::workflow::include::expand_includes;
+
 #[derive(Clone)]
 pub struct WorkflowManager {
     workflow: Value,
     workflows: HashMap<String, Value>,
     nodes: HashMap<String, Value>,
+    prompts_dir: String,
+}
+
+/// Workflow names map directly to file names, so only allow alphanumerics,
+/// `_` and `-` to keep them inside the prompts directory.
+pub fn is_valid_workflow_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Workflow directory for `tenant`: `<prompts_dir>/<tenant>`, or
+/// `prompts_dir` itself when unscoped.
+pub fn tenant_dir(prompts_dir: &str, tenant: Option<&str>) -> String {
+    match tenant {
+        Some(t) => format!("{}/{}", prompts_dir.trim_end_matches('/'), t),
+        None => prompts_dir.to_string(),
+    }
+}
+
+/// Directory to load workflow `name` from: the tenant's own directory when
+/// it has the workflow, otherwise the shared `prompts_dir`.
+pub fn resolve_workflow_dir(prompts_dir: &str, tenant: Option<&str>, name: Option<&str>) -> String {
+    let scoped = tenant_dir(prompts_dir, tenant);
+    match name {
+        Some(n) if tenant.is_some() && !std::path::Path::new(&format!("{}/{}.json", scoped, n)).exists() => prompts_dir.to_string(),
+        _ => scoped,
+    }
+}
+
+/// A stored workflow file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WorkflowInfo {
+    pub name: String,
+    pub size: u64,
+    pub modified_ms: u64,
+}
+
+impl Default for WorkflowManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl WorkflowManager {
     pub fn new() -> Self {
+        Self::with_dir("prompts")
+    }
+
+    /// Create a manager that reads and writes workflows under `prompts_dir`.
+    pub fn with_dir(prompts_dir: impl Into<String>) -> Self {
         WorkflowManager {
             workflow: Value::Null,
             workflows: HashMap::new(),
             nodes: HashMap::new(),
+            prompts_dir: prompts_dir.into(),
         }
     }
 
+    fn workflow_path(&self, name: &str) -> String {
+        format!("{}/{}.json", self.prompts_dir.trim_end_matches('/'), name)
+    }
+
     pub async fn add_workflow(&mut self, name: Option<String>, workflow: Option<Value>) -> Result<(), String> {
         match (name, workflow) {
             (Some(name), Some(workflow)) => {
                 // Save the provided workflow under the given name
-                let file_path = format!("prompts/{}.json", name);
+                let file_path = self.workflow_path(&name);
                 let workflow_content = serde_json::to_string_pretty(&workflow)
                     .map_err(|e| format!("Failed to serialize workflow: {}", e))?;
                 
@@ -51,6 +107,52 @@ impl WorkflowManager {
         }
     }
 
+    /// Workflows stored directly under the prompts directory (not snippets or
+    /// tenant namespaces), sorted by name.
+    pub async fn list_workflows(&self) -> Result<Vec<WorkflowInfo>, String> {
+        let mut entries = match tokio::fs::read_dir(&self.prompts_dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read {}: {}", self.prompts_dir, e)),
+        };
+        let mut workflows = Vec::new();
+        while let Some(entry) = entries.next_entry().await.map_err(|e| format!("Failed to read {}: {}", self.prompts_dir, e))? {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let Some(name) = file_name.strip_suffix(".json").filter(|n| is_valid_workflow_name(n)) else { continue };
+            let Ok(meta) = entry.metadata().await else { continue };
+            if !meta.is_file() {
+                continue;
+            }
+            workflows.push(WorkflowInfo {
+                name: name.to_string(),
+                size: meta.len(),
+                modified_ms: meta.modified().ok()
+                    .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|d| d.as_millis() as u64)
+                    .unwrap_or(0),
+            });
+        }
+        workflows.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(workflows)
+    }
+
+    pub fn has_workflow(&self, name: &str) -> bool {
+        std::path::Path::new(&self.workflow_path(name)).is_file()
+    }
+
+    /// Delete workflow `name`. Returns `false` if it didn't exist.
+    pub async fn delete_workflow(&mut self, name: &str) -> Result<bool, String> {
+        let file_path = self.workflow_path(name);
+        match tokio::fs::remove_file(&file_path).await {
+            Ok(()) => {
+                self.workflows.remove(name);
+                Ok(true)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(format!("Failed to delete {}: {}", file_path, e)),
+        }
+    }
+
     pub async fn add_node(&mut self, node_type: String, node_info: Value) {
         self.nodes.insert(node_type, node_info);
     }
@@ -58,14 +160,21 @@ impl WorkflowManager {
     pub fn get_node_info(&self, node_type: &str) -> Option<Value> {
         self.nodes.get(node_type).cloned()
     }
+    /// Splice `$include` snippets (paths relative to the prompts directory)
+    /// into `workflow`. Returns how many directives were expanded.
+    pub fn expand_includes(&self, workflow: &mut Value) -> Result<usize, String> {
+        expand_includes(workflow, &self.prompts_dir)
+    }
+
     pub async fn load_workflow(&self, name: &str) -> Result<Value, String> {
-        let file_path = format!("prompts/{}.json", name);
+        let file_path = self.workflow_path(name);
         let workflow_content = tokio::fs::read_to_string(&file_path)
             .await
             .map_err(|e| format!("Failed to read file {}: {}", file_path, e))?;
 
-        let workflow: Value = serde_json::from_str(&workflow_content)
+        let mut workflow: Value = serde_json::from_str(&workflow_content)
             .map_err(|e| format!("Failed to parse JSON from {}: {}", file_path, e))?;
+        self.expand_includes(&mut workflow)?;
 
         tokio::fs::write("workflow.json", &workflow_content)
             .await
@@ -1,3 +1,12 @@
 pub mod manager;
+pub mod convert;
+pub mod compose;
+pub mod diff;
+pub mod include;
+pub mod bypass;
+pub mod bundle;
+pub mod preflight;
+pub mod sync;
+pub mod validate;
 
-pub use manager::WorkflowManager;
\ No newline at end of file
+pub use manager::WorkflowManager;
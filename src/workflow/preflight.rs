@@ -0,0 +1,56 @@
+//! Checking every stored workflow against the live backend.
+//!
+//! A preflight resolves each shared workflow in the prompts directory the
+//! way `/queue_prompt` would (`extends`, `$include`, UI conversion, muted
+//! groups dropped) and validates it against ComfyUI's `/object_info`, so a
+//! missing custom node or model file shows up before anyone queues it.
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::utils::prompt_build::resolve_prompt_root_from_payload;
+use crate::workflow::bypass::apply_groups;
+use crate::workflow::convert::to_api_graph;
+use crate::workflow::manager::WorkflowManager;
+use crate::workflow::validate::{validate_against_object_info, NodeIssue};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkflowReport {
+    pub name: String,
+    pub valid: bool,
+    pub errors: Vec<NodeIssue>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PreflightReport {
+    pub checked_at_ms: u64,
+    /// Whether every workflow passed.
+    pub ready: bool,
+    pub total: usize,
+    /// Names of the workflows that didn't.
+    pub failing: Vec<String>,
+    pub workflows: Vec<WorkflowReport>,
+}
+
+/// The graph stored workflow `name` queues by default.
+async fn resolve_graph(prompts_dir: &str, name: &str) -> Result<Value, String> {
+    let root = resolve_prompt_root_from_payload(&json!({"workflow": name}), prompts_dir, None).await
+        .map_err(|e| e.to_string())?;
+    let (mut graph, _) = to_api_graph(&root)?;
+    apply_groups(&mut graph, &[]);
+    Ok(graph)
+}
+
+/// Check every shared workflow in `prompts_dir` against `object_info`.
+/// Workflows that can't be loaded fail with an `invalid_workflow` error.
+pub async fn run_preflight(prompts_dir: &str, object_info: &Value, now_ms: u64) -> Result<PreflightReport, String> {
+    let mut workflows = Vec::new();
+    for info in WorkflowManager::with_dir(prompts_dir).list_workflows().await? {
+        let errors = match resolve_graph(prompts_dir, &info.name).await {
+            Ok(graph) => validate_against_object_info(&graph, object_info),
+            Err(message) => vec![NodeIssue { node: String::new(), class_type: None, input: None, code: "invalid_workflow", message }],
+        };
+        workflows.push(WorkflowReport { name: info.name, valid: errors.is_empty(), errors });
+    }
+    let failing: Vec<String> = workflows.iter().filter(|w| !w.valid).map(|w| w.name.clone()).collect();
+    Ok(PreflightReport { checked_at_ms: now_ms, ready: failing.is_empty(), total: workflows.len(), failing, workflows })
+}
@@ -0,0 +1,156 @@
+//! Keep the prompts directory in sync with a git repository.
+//!
+//! When `WORKFLOWS_GIT_URL` is set, the prompts directory is a checkout of
+//! that repository: the first sync clones it (the directory must be missing
+//! or empty), later ones fast-forward it. Workflows are read from disk per
+//! request, so the next request after a sync sees the new files.
+//!
+//! Syncs shell out to the `git` CLI, so credentials come from the usual
+//! places (SSH agent, credential helper, or a token in the URL). A token in
+//! the URL is masked (see [`redact_url`]) wherever the URL is logged or
+//! returned, including git's own error messages.
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+use crate::config::Config;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncAction {
+    Cloned,
+    Pulled,
+}
+
+/// Result of one sync.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SyncOutcome {
+    pub action: SyncAction,
+    /// Commit checked out after the sync.
+    pub revision: String,
+    /// Commit checked out before the sync, if there was a checkout.
+    pub previous: Option<String>,
+    /// Whether the checkout moved.
+    pub changed: bool,
+}
+
+/// A git remote mirrored into a directory. Concurrent syncs are serialized.
+pub struct WorkflowSync {
+    url: String,
+    branch: Option<String>,
+    dir: PathBuf,
+    lock: Mutex<()>,
+}
+
+async fn git(cwd: Option<&Path>, args: &[&str]) -> Result<String, String> {
+    let mut cmd = Command::new("git");
+    if let Some(cwd) = cwd {
+        cmd.arg("-C").arg(cwd);
+    }
+    // Fail instead of hanging on a credential prompt.
+    cmd.args(args).env("GIT_TERMINAL_PROMPT", "0");
+    let output = cmd.output().await.map_err(|e| format!("Failed to run git: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("git {} failed: {}", args.first().copied().unwrap_or_default(), stderr.trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The `user:password@` part of a `scheme://` URL, if it has one.
+fn userinfo(url: &str) -> Option<&str> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    authority.rfind('@').map(|at| &authority[..at])
+}
+
+/// `url` with any credentials replaced by `***`, for logs and errors:
+/// `https://token@host/repo.git` becomes `https://***@host/repo.git`.
+pub fn redact_url(url: &str) -> String {
+    redact(url, url)
+}
+
+/// `text` with the credentials of `url` in it replaced by `***`.
+fn redact(text: &str, url: &str) -> String {
+    match userinfo(url).filter(|u| !u.is_empty()) {
+        Some(secret) => text.replace(secret, "***"),
+        None => text.to_string(),
+    }
+}
+
+impl WorkflowSync {
+    pub fn new(url: impl Into<String>, branch: Option<String>, dir: impl Into<PathBuf>) -> Self {
+        WorkflowSync { url: url.into(), branch, dir: dir.into(), lock: Mutex::new(()) }
+    }
+
+    /// Sync settings from `config`, or `None` if `WORKFLOWS_GIT_URL` is unset.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let url = config.workflows_git_url.clone()?;
+        Some(WorkflowSync::new(url, config.workflows_git_branch.clone(), &config.prompts_dir))
+    }
+
+    /// The remote's URL, with credentials masked.
+    pub fn url(&self) -> String {
+        redact_url(&self.url)
+    }
+
+    /// Clone the repository into the directory, or fast-forward the existing
+    /// checkout. Errors never contain the URL's credentials.
+    pub async fn sync(&self) -> Result<SyncOutcome, String> {
+        let _guard = self.lock.lock().await;
+        self.sync_locked().await.map_err(|e| redact(&e, &self.url))
+    }
+
+    async fn sync_locked(&self) -> Result<SyncOutcome, String> {
+        if self.dir.join(".git").exists() {
+            let previous = git(Some(&self.dir), &["rev-parse", "HEAD"]).await.ok();
+            match &self.branch {
+                Some(branch) => git(Some(&self.dir), &["pull", "--ff-only", "origin", branch]).await?,
+                None => git(Some(&self.dir), &["pull", "--ff-only"]).await?,
+            };
+            let revision = git(Some(&self.dir), &["rev-parse", "HEAD"]).await?;
+            let changed = previous.as_deref() != Some(revision.as_str());
+            return Ok(SyncOutcome { action: SyncAction::Pulled, revision, previous, changed });
+        }
+
+        let occupied = std::fs::read_dir(&self.dir).map(|mut d| d.next().is_some()).unwrap_or(false);
+        if occupied {
+            return Err(format!(
+                "{} is not empty and not a git checkout; move its contents aside to sync from {}",
+                self.dir.display(), redact_url(&self.url)
+            ));
+        }
+        let dir = self.dir.to_string_lossy().to_string();
+        let mut args = vec!["clone", "--quiet"];
+        if let Some(branch) = &self.branch {
+            args.extend(["--branch", branch.as_str()]);
+        }
+        args.extend([self.url.as_str(), dir.as_str()]);
+        git(None, &args).await?;
+        let revision = git(Some(&self.dir), &["rev-parse", "HEAD"]).await?;
+        Ok(SyncOutcome { action: SyncAction::Cloned, revision, previous: None, changed: true })
+    }
+
+    /// Sync now and then every `interval`, logging failures. A zero interval
+    /// syncs once.
+    pub fn spawn_schedule(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            loop {
+                match self.sync().await {
+                    Ok(outcome) if outcome.changed => {
+                        tracing::info!("Workflows synced from {} at {}", self.url(), outcome.revision);
+                    }
+                    Ok(_) => tracing::debug!("Workflows already up to date with {}", self.url()),
+                    Err(e) => tracing::warn!("Workflow sync failed: {}", e),
+                }
+                if interval.is_zero() {
+                    break;
+                }
+                tokio::time::sleep(interval).await;
+            }
+        });
+    }
+}
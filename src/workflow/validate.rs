@@ -0,0 +1,153 @@
+//! Checking an API graph against ComfyUI's node definitions.
+//!
+//! [`validate_against_object_info`] catches what ComfyUI would reject when
+//! the prompt is queued (unknown classes, missing or mistyped inputs, links
+//! to nodes that don't exist) and reports every problem at once, per node.
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct NodeIssue {
+    pub node: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
+    /// `missing_class_type`, `unknown_class_type`, `missing_input`,
+    /// `dangling_link`, `invalid_output`, `type_mismatch`, `expected_link`,
+    /// `invalid_value`, `out_of_range`, `invalid_option`, or (for a whole
+    /// workflow that can't be loaded, with an empty `node`) `invalid_workflow`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// `(source node, output slot)` of a link value.
+fn link(v: &Value) -> Option<(String, u64)> {
+    let arr = v.as_array()?;
+    if arr.len() != 2 { return None; }
+    let slot = arr[1].as_u64()?;
+    let source = arr[0].as_str().map(String::from).or_else(|| arr[0].as_u64().map(|n| n.to_string()))?;
+    Some((source, slot))
+}
+
+/// An input's expected type, combo options, and config.
+type InputSpec<'a> = (String, Option<&'a Vec<Value>>, Option<&'a Map<String, Value>>);
+
+/// Expected type and options of an input spec (`["INT", {...}]`,
+/// `[["a", "b"], {...}]`, or `["COMBO", {"options": [...]}]`).
+fn input_type(spec: &Value) -> InputSpec<'_> {
+    let config = spec.get(1).and_then(|c| c.as_object());
+    match spec.get(0) {
+        Some(Value::Array(options)) => ("COMBO".to_string(), Some(options), config),
+        Some(Value::String(kind)) => {
+            let options = config.and_then(|c| c.get("options")).and_then(|o| o.as_array());
+            (kind.clone(), options, config)
+        }
+        _ => ("*".to_string(), None, config),
+    }
+}
+
+fn types_match(expected: &str, actual: &str) -> bool {
+    let any = |t: &str| t == "*" || t.is_empty();
+    any(expected) || any(actual) || expected.split(',').any(|e| actual.split(',').any(|a| e.trim() == a.trim()))
+}
+
+/// Problem with a literal `value` for an input of type `kind`, if any.
+fn check_value(kind: &str, value: &Value, options: Option<&Vec<Value>>, config: Option<&Map<String, Value>>) -> Option<(&'static str, String)> {
+    if let Some(options) = options {
+        if kind == "COMBO" || !options.is_empty() {
+            return (!options.contains(value)).then(|| {
+                let shown: Vec<String> = options.iter().take(10).map(|o| o.to_string()).collect();
+                let more = if options.len() > 10 { ", ..." } else { "" };
+                ("invalid_option", format!("{} is not one of [{}{}]", value, shown.join(", "), more))
+            });
+        }
+    }
+    let valid = match kind {
+        "INT" => value.is_i64() || value.is_u64(),
+        "FLOAT" => value.is_number(),
+        "STRING" => value.is_string(),
+        "BOOLEAN" => value.is_boolean(),
+        "COMBO" | "*" => true,
+        _ => return Some(("expected_link", format!("expects a {} link, got {}", kind, value))),
+    };
+    if !valid {
+        return Some(("invalid_value", format!("expects {}, got {}", kind, value)));
+    }
+    let number = value.as_f64()?;
+    let bound = |key: &str| config.and_then(|c| c.get(key)).and_then(|v| v.as_f64());
+    match (bound("min"), bound("max")) {
+        (Some(min), _) if number < min => Some(("out_of_range", format!("{} is below the minimum {}", value, min))),
+        (_, Some(max)) if number > max => Some(("out_of_range", format!("{} is above the maximum {}", value, max))),
+        _ => None,
+    }
+}
+
+/// Every problem ComfyUI would find in `graph` given its `/object_info`
+/// document `info`. An empty list means the graph should queue.
+pub fn validate_against_object_info(graph: &Value, info: &Value) -> Vec<NodeIssue> {
+    let Some(nodes) = graph.as_object() else {
+        return vec![NodeIssue {
+            node: String::new(),
+            class_type: None,
+            input: None,
+            code: "invalid_value",
+            message: "Prompt graph must be a JSON object".to_string(),
+        }];
+    };
+    let mut issues = Vec::new();
+    let mut ids: Vec<&String> = nodes.keys().collect();
+    ids.sort_by_key(|id| (id.parse::<u64>().unwrap_or(u64::MAX), id.to_string()));
+    for id in ids {
+        let node = &nodes[id];
+        let class_type = node.get("class_type").and_then(|v| v.as_str());
+        let mut issue = |input: Option<&str>, code: &'static str, message: String| issues.push(NodeIssue {
+            node: id.clone(),
+            class_type: class_type.map(String::from),
+            input: input.map(String::from),
+            code,
+            message,
+        });
+        let Some(class_type) = class_type else {
+            issue(None, "missing_class_type", "Node has no 'class_type'".to_string());
+            continue;
+        };
+        let Some(definition) = info.get(class_type) else {
+            issue(None, "unknown_class_type", format!("'{}' is not installed in ComfyUI", class_type));
+            continue;
+        };
+        let empty = Map::new();
+        let inputs = node.get("inputs").and_then(|v| v.as_object()).unwrap_or(&empty);
+        for (section, required) in [("required", true), ("optional", false)] {
+            let Some(specs) = definition.pointer(&format!("/input/{}", section)).and_then(|v| v.as_object()) else { continue };
+            for (name, spec) in specs {
+                let (kind, options, config) = input_type(spec);
+                let Some(value) = inputs.get(name) else {
+                    if required {
+                        issue(Some(name), "missing_input", format!("Required input '{}' ({}) is missing", name, kind));
+                    }
+                    continue;
+                };
+                if let Some((source, slot)) = link(value) {
+                    let Some(source_node) = nodes.get(&source) else {
+                        issue(Some(name), "dangling_link", format!("Links to node {}, which doesn't exist", source));
+                        continue;
+                    };
+                    let source_class = source_node.get("class_type").and_then(|v| v.as_str()).unwrap_or_default();
+                    // Unknown sources are reported on their own node.
+                    let Some(outputs) = info.get(source_class).and_then(|d| d.get("output")).and_then(|o| o.as_array()) else { continue };
+                    match outputs.get(slot as usize).and_then(|o| o.as_str()) {
+                        None => issue(Some(name), "invalid_output", format!("Node {} ({}) has no output {}", source, source_class, slot)),
+                        Some(actual) if !types_match(&kind, actual) => {
+                            issue(Some(name), "type_mismatch", format!("Expects {}, but node {} output {} is {}", kind, source, slot, actual));
+                        }
+                        Some(_) => {}
+                    }
+                } else if let Some((code, message)) = check_value(&kind, value, options, config) {
+                    issue(Some(name), code, format!("'{}' {}", name, message));
+                }
+            }
+        }
+    }
+    issues
+}
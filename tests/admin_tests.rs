@@ -0,0 +1,114 @@
+use comfyui_api_proxy::admin::backup::{restore_backup, write_backup, BackupSources};
+use serde_json::json;
+
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("{}_{}", name, uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_backup_round_trip() {
+    let src = temp_dir("backup_src");
+    std::fs::create_dir_all(src.join("prompts/team-a")).unwrap();
+    std::fs::write(src.join("prompts/sdxl.json"), r#"{"3": {}}"#).unwrap();
+    std::fs::write(src.join("prompts/team-a/flux.json"), r#"{"4": {}}"#).unwrap();
+    std::fs::write(src.join("keys.json"), r#"{"keys": []}"#).unwrap();
+    let sources = BackupSources {
+        prompts_dir: src.join("prompts"),
+        negative_prompts_file: None,
+        api_keys_file: Some(src.join("keys.json")),
+        seeds_file: None,
+        jobs_db: None,
+        drive_index: None,
+    };
+    let history = json!({"p1": {"outputs": {}}});
+    let archive = src.join("backup.tar.gz");
+    let manifest = write_backup(&archive, &sources, Some(&history)).unwrap();
+    assert_eq!(manifest.prompts, vec!["sdxl.json", "team-a/flux.json"]);
+    assert!(manifest.api_keys);
+    assert_eq!(manifest.history_entries, Some(1));
+
+    let dst = temp_dir("backup_dst");
+    let targets = BackupSources {
+        prompts_dir: dst.join("prompts"),
+        negative_prompts_file: None,
+        api_keys_file: None,
+        seeds_file: None,
+        jobs_db: None,
+        drive_index: None,
+    };
+    let restored = restore_backup(&archive, &targets, false).unwrap();
+    assert_eq!(std::fs::read_to_string(dst.join("prompts/team-a/flux.json")).unwrap(), r#"{"4": {}}"#);
+    assert_eq!(restored.skipped, vec!["config/api_keys.json"]);
+    assert_eq!(restored.history, Some(history));
+
+    // A changed file is only replaced with `overwrite`.
+    std::fs::write(dst.join("prompts/sdxl.json"), "{}").unwrap();
+    assert!(restore_backup(&archive, &targets, false).unwrap_err().contains("sdxl.json"));
+    restore_backup(&archive, &targets, true).unwrap();
+    assert_eq!(std::fs::read_to_string(dst.join("prompts/sdxl.json")).unwrap(), r#"{"3": {}}"#);
+
+    std::fs::remove_dir_all(&src).ok();
+    std::fs::remove_dir_all(&dst).ok();
+}
+
+#[test]
+fn test_backup_includes_drive_index() {
+    let src = temp_dir("backup_src");
+    std::fs::write(src.join(".drive_index.json"), r#"{"images/a.png": {"caption": "a fox"}}"#).unwrap();
+    let sources = BackupSources {
+        prompts_dir: src.join("prompts"),
+        negative_prompts_file: None,
+        api_keys_file: None,
+        seeds_file: None,
+        jobs_db: None,
+        drive_index: Some(src.join(".drive_index.json")),
+    };
+    let archive = src.join("backup.tar.gz");
+    assert!(write_backup(&archive, &sources, None).unwrap().drive_index);
+
+    let dst = temp_dir("backup_dst");
+    let targets = BackupSources { drive_index: Some(dst.join("drive/.drive_index.json")), ..sources };
+    restore_backup(&archive, &targets, false).unwrap();
+    assert_eq!(std::fs::read_to_string(dst.join("drive/.drive_index.json")).unwrap(), r#"{"images/a.png": {"caption": "a fox"}}"#);
+    std::fs::remove_dir_all(&src).ok();
+    std::fs::remove_dir_all(&dst).ok();
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_backup_includes_job_db() {
+    use comfyui_api_proxy::jobs::JobStore;
+
+    let src = temp_dir("backup_src");
+    let db = src.join("jobs.db");
+    let id = {
+        let mut store = JobStore::open(&db.to_string_lossy()).unwrap();
+        let id = store.create(Some("sdxlapi".to_string()));
+        store.flush().await;
+        id
+    };
+    let sources = BackupSources {
+        prompts_dir: src.join("prompts"),
+        negative_prompts_file: None,
+        api_keys_file: None,
+        seeds_file: None,
+        jobs_db: Some(db.clone()),
+        drive_index: None,
+    };
+    let archive = src.join("backup.tar.gz");
+    assert!(write_backup(&archive, &sources, None).unwrap().jobs_db);
+
+    // A stale write-ahead log next to the target is dropped, not replayed.
+    let dst = temp_dir("backup_dst");
+    let restored_db = dst.join("jobs.db");
+    std::fs::write(dst.join("jobs.db-wal"), b"stale").unwrap();
+    let targets = BackupSources { jobs_db: Some(restored_db.clone()), ..sources };
+    restore_backup(&archive, &targets, false).unwrap();
+    assert!(!dst.join("jobs.db-wal").exists());
+    let store = JobStore::open(&restored_db.to_string_lossy()).unwrap();
+    assert_eq!(store.get(&id).unwrap().workflow.as_deref(), Some("sdxlapi"));
+    std::fs::remove_dir_all(&src).ok();
+    std::fs::remove_dir_all(&dst).ok();
+}
@@ -14,7 +14,7 @@ use tower::ServiceExt;
 async fn test_root_endpoint() {
     let config = Config::new().expect("Failed to load configuration");
     let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
-    let app = routes::setup_routes(comfyui_client).await;
+    let app = routes::setup_routes(comfyui_client);
 
     let response = app
         .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
@@ -30,7 +30,7 @@ async fn test_root_endpoint() {
 async fn test_queue_prompt() {
     let config = Config::new().expect("Failed to load configuration");
     let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
-    let app = setup_routes(config, comfyui_client);
+    let app = routes::setup_routes(comfyui_client);
 
     let test_prompt = json!({
         "prompt": "test prompt",
@@ -51,4 +51,935 @@ async fn test_queue_prompt() {
 
     assert_eq!(response.status(), StatusCode::OK);
     // You might want to add more assertions here based on the expected response
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn test_queue_prompt_rejects_empty_text() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let payload = json!({"workflow": "sdxlapi", "text_positive": ""});
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/queue_prompt")
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["error"]["code"], "invalid_field");
+    assert_eq!(v["error"]["field"], "text_positive");
+}
+
+#[tokio::test]
+async fn test_missing_workflow_is_structured_404() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let payload = json!({"workflow": "no_such_workflow_anywhere"});
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/queue_prompt?dry_run=true")
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["error"]["code"], "not_found");
+    assert!(v["error"]["message"].as_str().unwrap().contains("no_such_workflow_anywhere"));
+}
+
+#[tokio::test]
+async fn test_queue_prompt_dry_run_reports_changes() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let payload = json!({
+        "prompt": {
+            "3": {"class_type": "KSampler", "inputs": {"seed": 1, "steps": 20, "model": ["4", 0]}},
+            "4": {"class_type": "CheckpointLoaderSimple", "inputs": {"ckpt_name": "v1-5.safetensors"}}
+        },
+        "seed": 42
+    });
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/queue_prompt?dry_run=true")
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["dry_run"], true);
+    assert_eq!(v["prompt"]["3"]["inputs"]["seed"], 42);
+    assert_eq!(v["changes"][0], json!({"node": "3", "class_type": "KSampler", "input": "seed", "old": 1, "new": 42}));
+}
+
+#[tokio::test]
+async fn test_queue_prompt_fills_stored_template() {
+    let dir = std::env::temp_dir().join(format!("prompts_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let base = json!({
+        "defaults": {"steps": 20, "seed": 1},
+        "3": {"class_type": "KSampler", "inputs": {"seed": "{{seed}}", "steps": "{{steps}}", "model": ["4", 0]}},
+        "4": {"class_type": "CheckpointLoaderSimple", "inputs": {"ckpt_name": "{{ckpt}}"}}
+    });
+    std::fs::write(dir.join("base.json"), base.to_string()).unwrap();
+    std::fs::write(dir.join("child.json"), json!({"extends": "base", "defaults": {"steps": 40}}).to_string()).unwrap();
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.prompts_dir = dir.to_string_lossy().to_string();
+    let app = routes::build_router(routes::build_state(&config, ComfyUIClient::new(config.comfyui_url.clone())));
+    let dry_run = |payload: serde_json::Value| Request::builder()
+        .method("POST")
+        .uri("/queue_prompt?dry_run=true")
+        .header("Content-Type", "application/json")
+        .body(Body::from(payload.to_string()))
+        .unwrap();
+
+    let response = app.clone().oneshot(dry_run(json!({"workflow": "child", "inputs": {"ckpt": "v1-5.safetensors", "seed": 7}}))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(v["prompt"].get("defaults").is_none());
+    assert_eq!(v["prompt"]["3"]["inputs"]["steps"], 40);
+    assert_eq!(v["prompt"]["3"]["inputs"]["seed"], 7);
+    assert_eq!(v["prompt"]["4"]["inputs"]["ckpt_name"], "v1-5.safetensors");
+
+    // A placeholder with no default needs an input.
+    let response = app.clone().oneshot(dry_run(json!({"workflow": "child"}))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(v["error"]["message"].as_str().unwrap().contains("ckpt"), "{}", v);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn test_queue_batch_validates_every_prompt() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let graph = json!({"3": {"class_type": "KSampler", "inputs": {"seed": 1, "steps": 20}}});
+    let post = |uri: &str, body: serde_json::Value| Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+
+    let body = json!({"prompt": graph, "steps": 30, "prompts": [{"seed": 7}, {"seed": 8}]});
+    let response = app.clone().oneshot(post("/queue_batch?dry_run=true", body)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["prompts"][0]["prompt"]["3"]["inputs"], json!({"seed": 7, "steps": 30}));
+    assert_eq!(v["prompts"][1]["prompt"]["3"]["inputs"], json!({"seed": 8, "steps": 30}));
+
+    // One bad prompt rejects the batch before anything is queued.
+    let body = json!({"prompt": graph, "prompts": [{"seed": 7}, {"text_delimiter": ""}]});
+    let response = app.clone().oneshot(post("/queue_batch", body)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["error"]["field"], "prompts[1]");
+
+    let response = app.oneshot(post("/queue_batch", json!({"prompts": []}))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_sweep_tags_each_combination() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let graph = json!({
+        "3": {"class_type": "KSampler", "inputs": {"seed": 1, "cfg": 8}},
+        "9": {"class_type": "SaveImage", "inputs": {"filename_prefix": "run"}}
+    });
+    let post = |uri: &str, body: serde_json::Value| Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+
+    let body = json!({"prompt": graph, "cfg": [5, 7], "seed": {"count": 2, "start": 40}});
+    let response = app.clone().oneshot(post("/sweep?dry_run=true", body)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["prompts"].as_array().unwrap().len(), 4);
+    assert_eq!(v["prompts"][1]["prompt"]["3"]["inputs"], json!({"seed": 40, "cfg": 7}));
+    assert_eq!(v["prompts"][1]["prompt"]["9"]["inputs"]["filename_prefix"], "run_seed-40_cfg-7");
+    assert_eq!(v["combinations"][2], json!({"values": {"seed": 41, "cfg": 5}, "tag": "seed-41_cfg-5"}));
+
+    let response = app.oneshot(post("/sweep", json!({"prompt": graph, "cfg": 7}))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_img2img_points_load_image_at_the_init_image() {
+    use base64::Engine;
+
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let mut png = Vec::new();
+    image::RgbImage::new(2, 2)
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .unwrap();
+    let init_image = format!("data:image/png;base64,{}", base64::engine::general_purpose::STANDARD.encode(&png));
+    let graph = json!({
+        "1": {"class_type": "LoadImage", "inputs": {"image": "example.png"}},
+        "3": {"class_type": "KSampler", "inputs": {"seed": 1, "denoise": 1.0}}
+    });
+    let post = |uri: &str, body: serde_json::Value| Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let error_field = |bytes: &[u8]| serde_json::from_slice::<serde_json::Value>(bytes).unwrap()["error"]["field"].clone();
+
+    let body = json!({"prompt": graph, "denoise": 0.55, "init_image": init_image, "init_image_name": "photo.png"});
+    let response = app.clone().oneshot(post("/img2img?dry_run=true", body)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["prompt"]["1"]["inputs"]["image"], "photo.png");
+    assert_eq!(v["prompt"]["3"]["inputs"]["denoise"], 0.55);
+
+    let body = json!({"prompt": graph, "init_image": "not base64!"});
+    let response = app.clone().oneshot(post("/img2img", body)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(error_field(&hyper::body::to_bytes(response.into_body()).await.unwrap()), "init_image");
+
+    let txt2img = json!({"3": {"class_type": "KSampler", "inputs": {"seed": 1}}});
+    let body = json!({"prompt": txt2img, "init_image": init_image});
+    let response = app.oneshot(post("/img2img", body)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(error_field(&hyper::body::to_bytes(response.into_body()).await.unwrap()), "workflow");
+}
+
+#[tokio::test]
+async fn test_plugin_routes_share_state_and_middleware() {
+    use axum::{extract::{Extension, State}, routing::get, Router};
+    use comfyui_api_proxy::api::{middleware::ClientName, routes::AppState};
+    use std::sync::Arc;
+
+    async fn whoami(State(state): State<Arc<AppState>>, client: Option<Extension<ClientName>>) -> String {
+        let jobs = state.job_store.read().await.iter().count();
+        format!("{} {}", client.map(|c| c.0.0.clone()).unwrap_or_default(), jobs)
+    }
+
+    let config = Config::new().expect("Failed to load configuration");
+    let state = AppState::new(&config, ComfyUIClient::new(config.comfyui_url.clone()));
+    let app = routes::build_router_with(state, Router::new().route("/plugins/whoami", get(whoami)));
+
+    let request = |client: &str| Request::builder().uri("/plugins/whoami").header("X-Client", client).body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request("storyboard/2.1")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&bytes[..], b"storyboard/2.1 0");
+    // The built-in X-Client validation applies to plugin routes too.
+    let response = app.clone().oneshot(request("   ")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    // Built-in routes are still there.
+    let response = app.oneshot(Request::builder().uri("/jobs").body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_jobs_record_x_client() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let queue = |client: &str| Request::builder()
+        .method("POST")
+        .uri("/queue_prompt")
+        .header("Content-Type", "application/json")
+        .header("X-Client", client)
+        .body(Body::from(json!({"prompt": {"3": {"class_type": "KSampler", "inputs": {"seed": 1}}}}).to_string()))
+        .unwrap();
+    let response = app.clone().oneshot(queue("   ")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // The job is recorded whether or not ComfyUI accepts the prompt.
+    app.clone().oneshot(queue("storyboard-ui/2.1")).await.unwrap();
+    let request = Request::builder().uri("/jobs?client=storyboard-ui/2.1").body(Body::empty()).unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["total"], 1);
+    assert_eq!(v["jobs"][0]["client"], "storyboard-ui/2.1");
+}
+
+#[tokio::test]
+async fn test_unknown_favorite_seed_is_rejected() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let payload = json!({
+        "prompt": {"3": {"class_type": "KSampler", "inputs": {"seed": 1, "steps": 20}}},
+        "params": {"seed": "favorite:no-such-favorite"}
+    });
+    let request = Request::builder()
+        .method("POST")
+        .uri("/queue_prompt?dry_run=true")
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["error"]["field"], "seed");
+
+    let request = Request::builder().method("POST").uri("/jobs/missing/favorite").body(Body::empty()).unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_dry_run_flag_in_payload() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let post = |payload: serde_json::Value| {
+        Request::builder()
+            .method("POST")
+            .uri("/queue_prompt")
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+            .unwrap()
+    };
+    let graph = json!({"3": {"class_type": "KSampler", "inputs": {"seed": 1, "steps": 20}}});
+
+    // No ComfyUI is needed: the graph is built and returned, never sent.
+    let response = app.clone().oneshot(post(json!({"prompt": graph, "steps": 8, "dry_run": true}))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["dry_run"], true);
+    assert_eq!(v["prompt"]["3"]["inputs"]["steps"], 8);
+
+    let response = app.oneshot(post(json!({"prompt": graph, "dry_run": "yes"}))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_dry_run_lists_applied_overrides() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let payload = json!({
+        "prompt": {"3": {"class_type": "KSampler", "inputs": {"seed": 1, "steps": 20}}},
+        "params": {"denoise": 0.5},
+        "sets": ["3.inputs.steps=30"]
+    });
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/queue_prompt?dry_run=true")
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    // `denoise` matched no input, so only the `sets` override shows up.
+    assert_eq!(v["applied"], json!([{"node": "3", "class_type": "KSampler", "input": "steps", "old": 20, "new": 30}]));
+}
+
+#[tokio::test]
+async fn test_job_status_unknown_job() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let response = app.clone()
+        .oneshot(Request::builder().uri("/jobs/missing?wait=5").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app
+        .oneshot(Request::builder().uri("/jobs/missing?wait=soon").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_list_jobs_empty() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let response = app.clone()
+        .oneshot(Request::builder().uri("/jobs?state=running").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v, json!({"total": 0, "jobs": []}));
+
+    let response = app
+        .oneshot(Request::builder().uri("/jobs?state=sleeping").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_import_jobs_then_list() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let dump = json!({
+        "workflow": "legacy",
+        "history": {
+            "p1": {
+                "status": {"completed": true, "messages": [["execution_success", {"prompt_id": "p1", "timestamp": 50}]]},
+                "outputs": {"9": {"images": [{"filename": "old.png", "subfolder": "", "type": "output"}]}}
+            },
+            "junk": 5
+        }
+    });
+    let request = Request::builder()
+        .method("POST")
+        .uri("/jobs/import")
+        .header("content-type", "application/json")
+        .body(Body::from(dump.to_string()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["imported"], 1);
+    assert_eq!(v["invalid"], json!(["junk"]));
+    let job_id = v["jobs"]["p1"].as_str().unwrap().to_string();
+
+    let response = app.clone()
+        .oneshot(Request::builder().uri("/jobs?state=completed").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["total"], 1);
+    assert_eq!(v["jobs"][0]["job_id"], job_id.as_str());
+    assert_eq!(v["jobs"][0]["workflow"], "legacy");
+    assert_eq!(v["jobs"][0]["outputs"]["files"][0]["filename"], "old.png");
+    assert_eq!(v["jobs"][0]["outputs"]["files"][0]["url"], format!("/jobs/{}/outputs/0", job_id));
+
+    // The imported job was created at its history timestamp.
+    let response = app
+        .oneshot(Request::builder().uri("/jobs?status=completed&since=51").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["total"], 0);
+}
+
+#[tokio::test]
+async fn test_cancel_and_rerun_finished_imported_job() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let dump = json!({"history": {"p1": {"status": {"completed": true}, "outputs": {}}}});
+    let request = Request::builder()
+        .method("POST")
+        .uri("/jobs/import")
+        .header("content-type", "application/json")
+        .body(Body::from(dump.to_string()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let job_id = v["jobs"]["p1"].as_str().unwrap().to_string();
+
+    let post = |uri: String| Request::builder().method("POST").uri(uri).body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(post(format!("/jobs/{}/cancel", job_id))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    // Imported jobs have no request to repeat.
+    let response = app.clone().oneshot(post(format!("/jobs/{}/rerun", job_id))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+
+    let response = app.clone().oneshot(post("/jobs/missing/cancel".to_string())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = app.oneshot(post("/jobs/missing/rerun".to_string())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_list_jobs_field_selection() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let dump = json!({
+        "p1": {
+            "status": {"completed": true, "messages": [["execution_success", {"prompt_id": "p1", "timestamp": 50}]]},
+            "outputs": {"9": {"images": [{"filename": "a.png", "subfolder": "", "type": "output"}]}}
+        }
+    });
+    let request = Request::builder()
+        .method("POST")
+        .uri("/jobs/import")
+        .header("content-type", "application/json")
+        .body(Body::from(dump.to_string()))
+        .unwrap();
+    assert_eq!(app.clone().oneshot(request).await.unwrap().status(), StatusCode::OK);
+
+    let response = app.clone()
+        .oneshot(Request::builder().uri("/jobs?fields=prompt_id,outputs.files.filename,outputs.files").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["total"], 1);
+    assert_eq!(v["jobs"][0]["prompt_id"], "p1");
+    // A whole-value path wins over a narrower one.
+    assert_eq!(v["jobs"][0]["outputs"]["files"][0]["node"], "9");
+    assert!(v["jobs"][0].get("state").is_none());
+
+    let response = app
+        .oneshot(Request::builder().uri("/jobs?fields=prompt_id,,state").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["error"]["field"], "fields");
+}
+
+#[tokio::test]
+async fn test_workflow_bundle_export_and_conflict() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let response = app.clone()
+        .oneshot(Request::builder().uri("/workflows/sdxlapi/bundle").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "application/gzip");
+    let bundle = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+    // Importing over the existing workflow needs overwrite=true.
+    let request = Request::builder()
+        .method("POST")
+        .uri("/workflows/bundle")
+        .body(Body::from(bundle))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = app
+        .oneshot(Request::builder().uri("/workflows/missing/bundle").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_workflow_sync_requires_git_url() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let request = Request::builder().method("POST").uri("/workflows/sync").body(Body::empty()).unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn test_maintenance_mode_rejects_new_jobs() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+    let post = |uri: &str, body: serde_json::Value| {
+        Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    let request = post("/admin/maintenance", json!({"enabled": true, "message": "Upgrading ComfyUI", "retry_after_secs": 120}));
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["enabled"], true);
+    assert_eq!(v["in_flight"], 0);
+
+    let response = app.clone().oneshot(post("/queue_prompt", json!({"prompt": {}}))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let retry_after: u64 = response.headers()["retry-after"].to_str().unwrap().parse().unwrap();
+    assert!((119..=120).contains(&retry_after));
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["error"]["code"], "maintenance");
+    assert_eq!(v["error"]["message"], "Upgrading ComfyUI");
+
+    // Reads keep working.
+    let response = app.clone()
+        .oneshot(Request::builder().uri("/jobs").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app.clone().oneshot(post("/admin/maintenance", json!({"enabled": false}))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .oneshot(Request::builder().uri("/admin/maintenance").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["enabled"], false);
+}
+
+#[tokio::test]
+async fn test_upload_image_requires_image_field() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let body = "--XBOUNDARY\r\nContent-Disposition: form-data; name=\"overwrite\"\r\n\r\ntrue\r\n--XBOUNDARY--\r\n";
+    let request = Request::builder()
+        .method("POST")
+        .uri("/upload_image")
+        .header("content-type", "multipart/form-data; boundary=XBOUNDARY")
+        .body(Body::from(body))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_inputs_listing_and_delete_requires_input_dir() {
+    let config = Config::new().expect("Failed to load configuration");
+    let comfyui_client = ComfyUIClient::new(config.comfyui_url.clone());
+    let app = routes::setup_routes(comfyui_client);
+
+    let response = app.clone()
+        .oneshot(Request::builder().uri("/inputs").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v, json!({"total": 0, "inputs": []}));
+
+    let request = Request::builder().method("DELETE").uri("/inputs/photo.png").body(Body::empty()).unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[test]
+fn test_input_library_survives_restart() {
+    use comfyui_api_proxy::comfyui::inputs::{list_inputs, InputLibrary};
+    use comfyui_api_proxy::comfyui::types::UploadedImage;
+
+    let dir = std::env::temp_dir().join(format!("inputs_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("photo.png"), b"png").unwrap();
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.comfyui_input_dir = Some(dir.to_string_lossy().to_string());
+    config.inputs_file = None;
+
+    let uploaded: UploadedImage = serde_json::from_value(json!({"name": "photo.png", "subfolder": "", "type": "input"})).unwrap();
+    InputLibrary::from_config(&config).record(&uploaded, 3, 1_000, Some("team-a".to_string()));
+
+    // The upload keeps its tenant after a restart, and the library file
+    // isn't listed as an input.
+    let mut library = InputLibrary::from_config(&config);
+    let inputs = list_inputs(&library, Some(&dir));
+    assert_eq!(inputs.len(), 1);
+    assert!(inputs[0].tracked);
+    assert_eq!(inputs[0].tenant.as_deref(), Some("team-a"));
+    assert!(inputs[0].visible_to(Some("team-a")));
+    assert!(!inputs[0].visible_to(Some("team-b")));
+
+    library.remove("photo.png");
+    assert!(InputLibrary::from_config(&config).get("photo.png").is_none());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn test_workflow_crud() {
+    let dir = std::env::temp_dir().join(format!("prompts_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.prompts_dir = dir.to_string_lossy().to_string();
+    let app = routes::build_router(routes::build_state(&config, ComfyUIClient::new(config.comfyui_url.clone())));
+    let graph = std::fs::read_to_string("prompts/sdxlapi.json").unwrap();
+
+    let put = |body: String| Request::builder()
+        .method("PUT")
+        .uri("/workflows/portrait")
+        .header("content-type", "application/json")
+        .body(Body::from(body))
+        .unwrap();
+    let response = app.clone().oneshot(put(graph.clone())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = app.clone().oneshot(put(graph.clone())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app.clone()
+        .oneshot(Request::builder().uri("/workflows").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["total"], 1);
+    assert_eq!(v["workflows"][0]["name"], "portrait");
+
+    let response = app.clone()
+        .oneshot(Request::builder().uri("/workflows/portrait").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let stored: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(stored, serde_json::from_str::<serde_json::Value>(&graph).unwrap());
+
+    let delete = || Request::builder().method("DELETE").uri("/workflows/portrait").body(Body::empty()).unwrap();
+    assert_eq!(app.clone().oneshot(delete()).await.unwrap().status(), StatusCode::OK);
+    assert_eq!(app.oneshot(delete()).await.unwrap().status(), StatusCode::NOT_FOUND);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn test_workflow_etag_and_if_none_match() {
+    let dir = std::env::temp_dir().join(format!("prompts_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("portrait.json"), r#"{"1": {"class_type": "A", "inputs": {}}}"#).unwrap();
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.prompts_dir = dir.to_string_lossy().to_string();
+    let app = routes::build_router(routes::build_state(&config, ComfyUIClient::new(config.comfyui_url.clone())));
+
+    let get = |etag: Option<&str>| {
+        let mut request = Request::builder().uri("/workflows/portrait");
+        if let Some(etag) = etag {
+            request = request.header("if-none-match", etag);
+        }
+        request.body(Body::empty()).unwrap()
+    };
+    let response = app.clone().oneshot(get(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+    assert!(etag.starts_with('"') && etag.ends_with('"'));
+
+    let response = app.clone().oneshot(get(Some(&etag))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()["etag"], etag.as_str());
+    assert!(hyper::body::to_bytes(response.into_body()).await.unwrap().is_empty());
+    let response = app.clone().oneshot(get(Some(&format!("\"other\", W/{}", etag)))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    std::fs::write(dir.join("portrait.json"), r#"{"1": {"class_type": "B", "inputs": {}}}"#).unwrap();
+    let response = app.clone().oneshot(get(Some(&etag))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_ne!(response.headers()["etag"], etag.as_str());
+
+    // Errors aren't tagged.
+    let response = app
+        .oneshot(Request::builder().uri("/workflows/missing").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(response.headers().get("etag").is_none());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn test_static_files_are_served_without_traversal() {
+    let dir = std::env::temp_dir().join(format!("static_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(dir.join("images")).unwrap();
+    std::fs::write(dir.join("images/out 1.png"), b"\x89PNG fake").unwrap();
+    std::fs::write(dir.join(".drive_index.json"), b"{}").unwrap();
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.static_drive_path = dir.to_string_lossy().to_string();
+    let app = routes::build_router(routes::build_state(&config, ComfyUIClient::new(config.comfyui_url.clone())));
+    let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+    let response = app.clone().oneshot(get("/static/images/out%201.png")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "image/png");
+    let etag = response.headers()["etag"].clone();
+    let last_modified = response.headers()["last-modified"].clone();
+    assert_eq!(response.headers()["cache-control"], "private, no-cache");
+    assert_eq!(&hyper::body::to_bytes(response.into_body()).await.unwrap()[..], b"\x89PNG fake");
+
+    // Cached copies are revalidated by tag, and ranges are served.
+    let request = |header: &str, value: &str| Request::builder().uri("/static/images/out%201.png").header(header, value).body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request("If-None-Match", etag.to_str().unwrap())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()["etag"], etag);
+    assert_eq!(response.headers()["last-modified"], last_modified);
+    assert_eq!(response.headers()["cache-control"], "private, no-cache");
+    let response = app.clone().oneshot(request("Range", "bytes=1-3")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()["content-range"], "bytes 1-3/9");
+    assert_eq!(&hyper::body::to_bytes(response.into_body()).await.unwrap()[..], b"PNG");
+
+    for uri in ["/static/images/../../etc/passwd", "/static/images/%2E%2E/%2E%2E/etc/passwd", "/static/.drive_index.json", "/static/images", "/static/images/missing.png"] {
+        let response = app.clone().oneshot(get(uri)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", uri);
+    }
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn test_metrics_count_requests_images_and_upstream_errors() {
+    let dir = std::env::temp_dir().join(format!("static_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(dir.join("images")).unwrap();
+    std::fs::write(dir.join("images/a.png"), b"\x89PNG fake").unwrap();
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.static_drive_path = dir.to_string_lossy().to_string();
+    let app = routes::build_router(routes::build_state(&config, ComfyUIClient::builder("http://127.0.0.1:9").retries(0).build()));
+    let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+    assert_eq!(app.clone().oneshot(get("/static/images/a.png")).await.unwrap().status(), StatusCode::OK);
+    assert_eq!(app.clone().oneshot(get("/static/images/b.png")).await.unwrap().status(), StatusCode::NOT_FOUND);
+    assert_eq!(app.clone().oneshot(get("/get_image?filename=x.png")).await.unwrap().status(), StatusCode::BAD_GATEWAY);
+
+    let response = app.oneshot(get("/metrics")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/plain"));
+    let body = String::from_utf8(hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap();
+    for line in [
+        "comfyui_proxy_http_requests_total{method=\"GET\",route=\"/static/*path\",status=\"200\"} 1",
+        "comfyui_proxy_http_requests_total{method=\"GET\",route=\"/static/*path\",status=\"404\"} 1",
+        "comfyui_proxy_http_request_duration_seconds_count{method=\"GET\",route=\"/get_image\"} 1",
+        "comfyui_proxy_images_served_total{source=\"static\"} 1",
+        "comfyui_proxy_upstream_requests_total{endpoint=\"/view\"} 1",
+        "comfyui_proxy_upstream_errors_total{endpoint=\"/view\"} 1",
+        "comfyui_proxy_prompts_queued_total 0",
+        "# TYPE comfyui_proxy_http_request_duration_seconds histogram",
+    ] {
+        assert!(body.lines().any(|l| l == line), "missing {:?} in\n{}", line, body);
+    }
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn test_openapi_spec_and_docs_are_public() {
+    fn refs<'a>(value: &'a serde_json::Value, out: &mut Vec<&'a str>) {
+        match value {
+            serde_json::Value::Object(map) => {
+                if let Some(r) = map.get("$ref").and_then(|r| r.as_str()) {
+                    out.push(r);
+                }
+                map.values().for_each(|v| refs(v, out));
+            }
+            serde_json::Value::Array(items) => items.iter().for_each(|v| refs(v, out)),
+            _ => {}
+        }
+    }
+
+    let keys = std::env::temp_dir().join(format!("api_keys_{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&keys, json!({"keys": [{"key": "secret"}]}).to_string()).unwrap();
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.api_keys_file = Some(keys.to_string_lossy().to_string());
+    let app = routes::build_router(routes::build_state(&config, ComfyUIClient::new(config.comfyui_url.clone())));
+    std::fs::remove_file(&keys).ok();
+    let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+    let response = app.clone().oneshot(get("/openapi.json")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let spec: serde_json::Value = serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+    for path in ["/queue_prompt", "/workflows", "/workflows/{name}", "/models/{category}", "/history", "/jobs/{id}"] {
+        assert!(spec["paths"].get(path).is_some(), "{} is not documented", path);
+    }
+    let mut found = Vec::new();
+    refs(&spec, &mut found);
+    assert!(!found.is_empty());
+    for r in found {
+        let name = r.strip_prefix("#/components/schemas/").expect("local schema ref");
+        assert!(spec["components"]["schemas"].get(name).is_some(), "dangling {}", r);
+    }
+
+    let docs = app.clone().oneshot(get("/docs")).await.unwrap();
+    assert_eq!(docs.status(), StatusCode::OK);
+    let html = hyper::body::to_bytes(docs.into_body()).await.unwrap();
+    assert!(String::from_utf8_lossy(&html).contains("/openapi.json"));
+
+    // The UI's assets are served by the proxy, not a CDN.
+    for (file, content_type) in [("swagger-ui-bundle.js", "text/javascript"), ("swagger-ui.css", "text/css")] {
+        assert!(String::from_utf8_lossy(&html).contains(&format!("/docs/{}", file)));
+        let asset = app.clone().oneshot(get(&format!("/docs/{}", file))).await.unwrap();
+        assert_eq!(asset.status(), StatusCode::OK);
+        assert_eq!(asset.headers()["content-type"], content_type);
+        assert!(!hyper::body::to_bytes(asset.into_body()).await.unwrap().is_empty());
+    }
+    let missing = app.oneshot(get("/docs/swagger-ui.js.map")).await.unwrap();
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_server_errors_are_marked_for_reporting() {
+    use axum::response::IntoResponse;
+    use comfyui_api_proxy::error::AppError;
+    use comfyui_api_proxy::reporting::{panic_response, ReportableError};
+
+    let response = AppError::ComfyUI("boom".to_string()).into_response();
+    let reported = response.extensions().get::<ReportableError>().unwrap();
+    assert_eq!((reported.code, reported.message.as_str()), ("comfyui_error", "ComfyUI error: boom"));
+    assert!(AppError::NotFound("x".to_string()).into_response().extensions().get::<ReportableError>().is_none());
+    let maintenance = AppError::Maintenance { message: "later".to_string(), retry_at_ms: 0 }.into_response();
+    assert!(maintenance.extensions().get::<ReportableError>().is_none());
+
+    // A panic renders like any internal error; the panic hook reports it.
+    let response = panic_response(Box::new("index out of bounds"));
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(response.extensions().get::<ReportableError>().is_none());
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["error"]["code"], "internal");
+}
@@ -0,0 +1,365 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use comfyui_api_proxy::{
+    api::routes,
+    auth::{ApiKey, ApiKeys, KeyPolicy, OnExceed},
+    config::Config,
+    comfyui::client::ComfyUIClient,
+};
+use serde_json::json;
+use tower::ServiceExt;
+
+fn graph() -> serde_json::Value {
+    json!({
+        "3": {"class_type": "KSampler", "inputs": {"steps": 60, "seed": 1}},
+        "4": {"class_type": "CheckpointLoaderSimple", "inputs": {"ckpt_name": "sd_xl_base_1.0.safetensors"}},
+        "5": {"class_type": "EmptyLatentImage", "inputs": {"width": 2048, "height": 1024, "batch_size": 1}},
+        "9": {"class_type": "SaveImage", "inputs": {"filename_prefix": "ComfyUI", "images": ["8", 0]}}
+    })
+}
+
+#[test]
+fn test_policy_rejects_or_clamps() {
+    let reject = KeyPolicy { max_steps: Some(30), ..Default::default() };
+    let err = reject.enforce(&mut graph()).unwrap_err();
+    assert!(err.contains("steps 60"), "{}", err);
+
+    let clamp = KeyPolicy {
+        max_steps: Some(30),
+        max_resolution: Some(1500),
+        filename_prefix: Some("alice/".to_string()),
+        on_exceed: OnExceed::Clamp,
+        ..Default::default()
+    };
+    let mut g = graph();
+    let warnings = clamp.enforce(&mut g).unwrap();
+    assert_eq!(warnings.len(), 2);
+    assert_eq!(g["3"]["inputs"]["steps"], 30);
+    assert_eq!(g["5"]["inputs"]["width"], 1496);
+    assert_eq!(g["5"]["inputs"]["height"], 1024);
+    assert_eq!(g["9"]["inputs"]["filename_prefix"], "alice/ComfyUI");
+
+    let checkpoints = KeyPolicy { allowed_checkpoints: Some(vec!["v1-5.safetensors".to_string()]), ..Default::default() };
+    assert!(checkpoints.enforce(&mut graph()).is_err());
+}
+
+#[test]
+fn test_filename_prefix_stays_in_its_folder() {
+    use comfyui_api_proxy::auth::policy::enforce_filename_prefix;
+
+    let mut g = graph();
+    g["10"] = json!({"class_type": "VHS_VideoCombine", "inputs": {"filename_prefix": "clip"}});
+    g["11"] = json!({"class_type": "PreviewImage", "inputs": {"images": ["8", 0]}});
+    enforce_filename_prefix(&mut g, "team-a/").unwrap();
+    assert_eq!(g["9"]["inputs"]["filename_prefix"], "team-a/ComfyUI");
+    assert_eq!(g["10"]["inputs"]["filename_prefix"], "team-a/clip");
+    assert!(g["11"]["inputs"].get("filename_prefix").is_none());
+    // Already prefixed values are kept.
+    enforce_filename_prefix(&mut g, "team-a/").unwrap();
+    assert_eq!(g["9"]["inputs"]["filename_prefix"], "team-a/ComfyUI");
+
+    for escape in ["team-a/../team-b/x", "../team-b/x", "team-a/sub/..\\..\\x"] {
+        let mut g = graph();
+        g["10"] = json!({"class_type": "SaveAnimatedWEBP", "inputs": {"filename_prefix": escape}});
+        let err = enforce_filename_prefix(&mut g, "team-a/").unwrap_err();
+        assert!(err.starts_with("node 10"), "{}", err);
+    }
+    let mut linked = graph();
+    linked["9"]["inputs"]["filename_prefix"] = json!(["12", 0]);
+    assert!(enforce_filename_prefix(&mut linked, "team-a/").is_err());
+}
+
+#[test]
+fn test_api_key_policy_deserializes() {
+    let key: ApiKey = serde_json::from_value(json!({
+        "key": "secret",
+        "name": "alice",
+        "policy": {"max_steps": 40, "on_exceed": "clamp"}
+    })).unwrap();
+    assert_eq!(key.policy.max_steps, Some(40));
+    assert_eq!(key.policy.on_exceed, OnExceed::Clamp);
+    let keys = ApiKeys::from_keys(vec![key]);
+    assert!(keys.is_enabled());
+    assert_eq!(keys.lookup("secret").map(|k| k.label()), Some("alice"));
+    assert!(keys.lookup("other").is_none());
+}
+
+#[tokio::test]
+async fn test_requests_require_api_key_when_configured() {
+    let path = std::env::temp_dir().join(format!("api_keys_{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, json!({"keys": [{"key": "secret"}]}).to_string()).unwrap();
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.api_keys_file = Some(path.to_string_lossy().to_string());
+    let state = routes::build_state(&config, ComfyUIClient::new(config.comfyui_url.clone()));
+    let app = routes::build_router(state);
+    std::fs::remove_file(&path).ok();
+
+    let request = |uri: &str, key: Option<&str>| {
+        let mut builder = Request::builder().uri(uri);
+        if let Some(key) = key {
+            builder = builder.header("X-API-Key", key);
+        }
+        builder.body(Body::empty()).unwrap()
+    };
+    let health = app.clone().oneshot(request("/", None)).await.unwrap();
+    assert_eq!(health.status(), StatusCode::OK);
+    let missing = app.clone().oneshot(request("/stats/nodes", None)).await.unwrap();
+    assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+    let wrong = app.clone().oneshot(request("/stats/nodes", Some("nope"))).await.unwrap();
+    assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+    let ok = app.oneshot(request("/stats/nodes", Some("secret"))).await.unwrap();
+    assert_eq!(ok.status(), StatusCode::OK);
+}
+
+#[test]
+fn test_quota_periods_and_reset() {
+    use comfyui_api_proxy::auth::quota::{Period, Quota, Usage, UsageTracker};
+    use comfyui_api_proxy::utils::time::{civil_date, month_bounds};
+
+    // 2024-02-29T12:00:00Z
+    let now = 1_709_208_000_000;
+    assert_eq!(civil_date(now), (2024, 2, 29));
+    let (start, end) = month_bounds(now);
+    assert_eq!(civil_date(start), (2024, 2, 1));
+    assert_eq!(civil_date(end), (2024, 3, 1));
+
+    let quota = Quota { daily_images: Some(4), monthly_gpu_seconds: Some(100), ..Default::default() };
+    let mut tracker = UsageTracker::new();
+    tracker.record("k", now, Usage { images: 3, gpu_ms: 20_000, cost: 0.0 });
+    assert!(quota.check(&tracker, "k", now).is_ok());
+
+    tracker.record("k", now + 1000, Usage { images: 1, gpu_ms: 20_000, cost: 0.0 });
+    let err = quota.check(&tracker, "k", now + 2000).unwrap_err();
+    assert!(err.message.contains("daily image quota"));
+    assert_eq!(err.reset_at_ms, Period::Day.bounds(now).1);
+
+    // Next day: daily counters reset, monthly usage carries over.
+    let tomorrow = now + 86_400_000;
+    assert!(quota.check(&tracker, "k", tomorrow).is_ok());
+    assert_eq!(tracker.usage("k", Period::Month, tomorrow).gpu_ms, 0, "March starts a new month");
+    assert_eq!(tracker.usage("k", Period::Month, now).gpu_ms, 40_000);
+}
+
+#[test]
+fn test_tenant_namespaces() {
+    use comfyui_api_proxy::jobs::JobStore;
+    use comfyui_api_proxy::workflow::manager::{resolve_workflow_dir, tenant_dir};
+
+    let root = std::env::temp_dir().join(format!("prompts_{}", uuid::Uuid::new_v4()));
+    let root_str = root.to_string_lossy().to_string();
+    std::fs::create_dir_all(root.join("team-a")).unwrap();
+    std::fs::write(root.join("team-a").join("mine.json"), "{}").unwrap();
+
+    assert_eq!(tenant_dir(&root_str, Some("team-a")), format!("{}/team-a", root_str));
+    assert_eq!(resolve_workflow_dir(&root_str, Some("team-a"), Some("mine")), format!("{}/team-a", root_str));
+    // Shared workflows stay readable to tenants that don't override them.
+    assert_eq!(resolve_workflow_dir(&root_str, Some("team-a"), Some("sdxlapi")), root_str);
+    assert_eq!(resolve_workflow_dir(&root_str, None, Some("mine")), root_str);
+    std::fs::remove_dir_all(&root).ok();
+
+    let mut store = JobStore::new();
+    let id = store.create(None);
+    store.get_mut(&id).unwrap().tenant = Some("team-a".to_string());
+    let job = store.get(&id).unwrap();
+    assert!(job.visible_to(Some("team-a")));
+    assert!(job.visible_to(None));
+    assert!(!job.visible_to(Some("team-b")));
+
+    let keys = ApiKeys::from_keys(vec![
+        serde_json::from_value(json!({"key": "a", "tenant": "team-a"})).unwrap(),
+        serde_json::from_value(json!({"key": "b", "tenant": "../etc"})).unwrap(),
+    ]);
+    assert_eq!(keys.lookup("a").and_then(|k| k.tenant()), Some("team-a"));
+    assert!(keys.lookup("b").is_none());
+}
+
+#[tokio::test]
+async fn test_prompt_events_hidden_from_other_tenants() {
+    let path = std::env::temp_dir().join(format!("api_keys_{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, json!({"keys": [{"key": "a", "tenant": "team-a"}]}).to_string()).unwrap();
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.api_keys_file = Some(path.to_string_lossy().to_string());
+    let state = routes::build_state(&config, ComfyUIClient::new(config.comfyui_url.clone()));
+    let app = routes::build_router(state);
+    std::fs::remove_file(&path).ok();
+
+    let request = Request::builder().uri("/events/someone-elses-prompt").header("X-API-Key", "a").body(Body::empty()).unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_queue_delete_hidden_from_other_tenants() {
+    let path = std::env::temp_dir().join(format!("api_keys_{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, json!({"keys": [{"key": "a", "tenant": "team-a"}]}).to_string()).unwrap();
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.api_keys_file = Some(path.to_string_lossy().to_string());
+    let state = routes::build_state(&config, ComfyUIClient::new(config.comfyui_url.clone()));
+    let app = routes::build_router(state);
+    std::fs::remove_file(&path).ok();
+
+    let request = Request::builder()
+        .method("DELETE")
+        .uri("/queue/someone-elses-prompt")
+        .header("X-API-Key", "a")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_queue_cannot_load_other_tenants_workflows() {
+    let prompts_dir = std::env::temp_dir().join(format!("prompts_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(prompts_dir.join("team-b")).unwrap();
+    let secret = json!({"3": {"class_type": "KSampler", "inputs": {"steps": 20, "seed": 1}}});
+    std::fs::write(prompts_dir.join("team-b/secret.json"), secret.to_string()).unwrap();
+    let path = std::env::temp_dir().join(format!("api_keys_{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, json!({"keys": [{"key": "a", "tenant": "team-a"}, {"key": "b", "tenant": "team-b"}]}).to_string()).unwrap();
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.api_keys_file = Some(path.to_string_lossy().to_string());
+    config.prompts_dir = prompts_dir.to_string_lossy().to_string();
+    let state = routes::build_state(&config, ComfyUIClient::new(config.comfyui_url.clone()));
+    let app = routes::build_router(state);
+    std::fs::remove_file(&path).ok();
+
+    let dry_run = |key: &str, workflow: &str| Request::builder()
+        .method("POST")
+        .uri("/queue_prompt?dry_run=true")
+        .header("X-API-Key", key)
+        .header("Content-Type", "application/json")
+        .body(Body::from(json!({"workflow": workflow}).to_string()))
+        .unwrap();
+    assert_eq!(app.clone().oneshot(dry_run("b", "secret")).await.unwrap().status(), StatusCode::OK);
+    assert_eq!(app.clone().oneshot(dry_run("a", "secret")).await.unwrap().status(), StatusCode::NOT_FOUND);
+    for name in ["team-b/secret", "../team-b/secret", "./team-b/secret"] {
+        let response = app.clone().oneshot(dry_run("a", name)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", name);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(v["error"]["field"], "workflow");
+    }
+    std::fs::remove_dir_all(&prompts_dir).ok();
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_usage_survives_restart() {
+    use comfyui_api_proxy::auth::keys::key_id;
+    use comfyui_api_proxy::jobs::{JobEventKind, JobStore};
+
+    let db = std::env::temp_dir().join(format!("jobs_{}.db", uuid::Uuid::new_v4()));
+    let db = db.to_string_lossy().to_string();
+    {
+        let mut store = JobStore::open(&db).unwrap();
+        let id = store.create(None);
+        store.get_mut(&id).unwrap().key_id = Some(key_id("a"));
+        store.record(&id, JobEventKind::SentToBackend { prompt_id: "p1".to_string() });
+        store.record(&id, JobEventKind::ExecutionStarted);
+        for filename in ["a.png", "b.png"] {
+            store.record(&id, JobEventKind::OutputSaved {
+                node: "9".to_string(),
+                filename: filename.to_string(),
+                subfolder: None,
+                folder_type: Some("output".to_string()),
+            });
+        }
+        store.record(&id, JobEventKind::Completed);
+        store.get_mut(&id).unwrap().history_synced = true;
+        store.save(&id);
+        store.flush().await;
+    }
+
+    let path = std::env::temp_dir().join(format!("api_keys_{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, json!({"keys": [{"key": "a", "quota": {"daily_images": 2}}]}).to_string()).unwrap();
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.api_keys_file = Some(path.to_string_lossy().to_string());
+    config.jobs_db = Some(db.clone());
+    let app = routes::build_router(routes::build_state(&config, ComfyUIClient::new(config.comfyui_url.clone())));
+    std::fs::remove_file(&path).ok();
+
+    let response = app.clone().oneshot(Request::builder().uri("/usage").header("X-API-Key", "a").body(Body::empty()).unwrap()).await.unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v["day"]["images"], 2);
+    assert_eq!(v["month"]["images"], 2);
+    let queue = Request::builder()
+        .method("POST")
+        .uri("/queue_prompt")
+        .header("X-API-Key", "a")
+        .header("content-type", "application/json")
+        .body(Body::from(json!({"prompt": graph()}).to_string()))
+        .unwrap();
+    assert_eq!(app.oneshot(queue).await.unwrap().status(), StatusCode::TOO_MANY_REQUESTS);
+    for suffix in ["", "-wal", "-shm"] {
+        std::fs::remove_file(format!("{}{}", db, suffix)).ok();
+    }
+}
+
+#[test]
+fn test_rate_limiter_refills_per_key() {
+    use comfyui_api_proxy::auth::{RateLimit, RateLimiter};
+
+    let limiter = RateLimiter::new();
+    let limit = RateLimit { requests_per_minute: Some(60), burst: Some(2), max_queued: None };
+    let now = 1_000_000;
+    assert!(limiter.check("a", &limit, now).is_ok());
+    assert!(limiter.check("a", &limit, now).is_ok());
+    let err = limiter.check("a", &limit, now).unwrap_err();
+    assert_eq!(err.retry_at_ms, now + 1000, "one request per second refills");
+    assert!(limiter.check("b", &limit, now).is_ok(), "buckets are per key");
+    assert!(limiter.check("a", &limit, now + 1000).is_ok());
+
+    let unlimited = RateLimit::default();
+    for _ in 0..100 {
+        assert!(limiter.check("c", &unlimited, now).is_ok());
+    }
+    let defaults = RateLimit { requests_per_minute: Some(10), burst: None, max_queued: Some(3) };
+    let own = RateLimit { max_queued: Some(1), ..Default::default() }.or(defaults);
+    assert_eq!(own, RateLimit { requests_per_minute: Some(10), burst: None, max_queued: Some(1) });
+}
+
+#[tokio::test]
+async fn test_rate_limits_and_queued_caps_return_429() {
+    let path = std::env::temp_dir().join(format!("api_keys_{}.json", uuid::Uuid::new_v4()));
+    let keys = json!({"keys": [
+        {"key": "slow", "rate_limit": {"requests_per_minute": 1}},
+        {"key": "busy", "rate_limit": {"max_queued": 1}},
+    ]});
+    std::fs::write(&path, keys.to_string()).unwrap();
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.api_keys_file = Some(path.to_string_lossy().to_string());
+    let state = routes::build_state(&config, ComfyUIClient::new(config.comfyui_url.clone()));
+    let app = routes::build_router(state.clone());
+    std::fs::remove_file(&path).ok();
+
+    let request = |key: &str| Request::builder().uri("/stats/nodes").header("X-API-Key", key).body(Body::empty()).unwrap();
+    assert_eq!(app.clone().oneshot(request("slow")).await.unwrap().status(), StatusCode::OK);
+    let limited = app.clone().oneshot(request("slow")).await.unwrap();
+    assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = limited.headers()["retry-after"].to_str().unwrap().parse().unwrap();
+    assert!((59..=60).contains(&retry_after));
+    let body = hyper::body::to_bytes(limited.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"]["code"], "rate_limited");
+    assert_eq!(app.clone().oneshot(request("busy")).await.unwrap().status(), StatusCode::OK);
+
+    // "busy" already has an unfinished job.
+    {
+        let mut jobs = state.job_store.write().await;
+        let id = jobs.create(None);
+        jobs.get_mut(&id).unwrap().key_id = Some(comfyui_api_proxy::auth::keys::key_id("busy"));
+    }
+    let queue = Request::builder()
+        .method("POST")
+        .uri("/queue_prompt")
+        .header("X-API-Key", "busy")
+        .header("content-type", "application/json")
+        .body(Body::from(json!({"prompt": graph()}).to_string()))
+        .unwrap();
+    let response = app.oneshot(queue).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key("retry-after"));
+}
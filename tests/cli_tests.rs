@@ -0,0 +1,139 @@
+//! `comfyctl` run as a process against stand-in ComfyUI servers.
+use axum::{http::StatusCode, routing::post, Json, Router};
+use serde_json::json;
+use std::path::PathBuf;
+use std::process::Output;
+
+/// A stand-in ComfyUI whose `/prompt` answers `status` with `body`. Returns
+/// its URL.
+fn spawn_comfyui(status: StatusCode, body: serde_json::Value) -> String {
+    let app = Router::new().route("/prompt", post(move || {
+        let body = body.clone();
+        async move { (status, Json(body)) }
+    }));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+    url
+}
+
+/// A one-node API graph in a temporary file.
+fn graph_file() -> PathBuf {
+    let path = std::env::temp_dir().join(format!("graph_{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, json!({"3": {"class_type": "KSampler", "inputs": {"seed": 1}}}).to_string()).unwrap();
+    path
+}
+
+/// Run `comfyctl --comfyui-url <url> prompt queue --file <graph> <args>`.
+async fn queue(url: &str, args: &[&str]) -> Output {
+    let graph = graph_file();
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_comfyctl"))
+        .args(["--comfyui-url", url, "prompt", "queue", "--file"])
+        .arg(&graph)
+        .args(args)
+        .env("COMFYUI_RETRIES", "0")
+        .output()
+        .await
+        .unwrap();
+    std::fs::remove_file(&graph).ok();
+    output
+}
+
+#[tokio::test]
+async fn test_queue_exit_codes() {
+    let queued = spawn_comfyui(StatusCode::OK, json!({"prompt_id": "p1", "number": 1, "node_errors": {}}));
+    assert_eq!(queue(&queued, &[]).await.status.code(), Some(0));
+
+    // ComfyUI refusing the prompt is a validation failure.
+    let rejected = spawn_comfyui(StatusCode::BAD_REQUEST, json!({"error": {"message": "Prompt outputs failed validation"}, "node_errors": {}}));
+    let output = queue(&rejected, &[]).await;
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("failed validation"));
+
+    // ComfyUI failing on its side, or not answering at all, is not.
+    let broken = spawn_comfyui(StatusCode::INTERNAL_SERVER_ERROR, json!({}));
+    assert_eq!(queue(&broken, &[]).await.status.code(), Some(6));
+    assert_eq!(queue("http://127.0.0.1:9", &[]).await.status.code(), Some(3));
+
+    // Bad arguments are usage errors.
+    assert_eq!(queue(&queued, &["--group", "no-state"]).await.status.code(), Some(2));
+}
+
+#[tokio::test]
+async fn test_queue_output_formats() {
+    let url = spawn_comfyui(StatusCode::OK, json!({"prompt_id": "p1", "number": 3, "node_errors": {}}));
+    let stdout = |output: Output| {
+        assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert_eq!(stdout(queue(&url, &[]).await), "prompt_id=p1 number=3\n");
+    assert_eq!(stdout(queue(&url, &["--output", "text"]).await), "prompt_id=p1 number=3\n");
+    let printed: serde_json::Value = serde_json::from_str(&stdout(queue(&url, &["--output", "json"]).await)).unwrap();
+    assert_eq!(printed, json!({"prompt_id": "p1", "number": 3, "node_errors": {}}));
+    assert_eq!(stdout(queue(&url, &["--json"]).await), stdout(queue(&url, &["--output", "json"]).await));
+    // --quiet leaves only the prompt id, for scripts.
+    assert_eq!(stdout(queue(&url, &["--quiet"]).await), "p1\n");
+    assert_eq!(stdout(queue(&url, &["-q"]).await), "p1\n");
+    assert_eq!(queue(&url, &["--quiet", "--output", "json"]).await.status.code(), Some(2));
+}
+
+#[tokio::test]
+async fn test_outputs_export_filters() {
+    use comfyui_api_proxy::utils::harvest::OutputHarvester;
+    use comfyui_api_proxy::utils::static_drive_poller::StaticDrivePoller;
+    use std::time::{Duration, SystemTime};
+
+    let root = std::env::temp_dir().join(format!("drive_{}", uuid::Uuid::new_v4()));
+    let harvester = OutputHarvester::new(&root, 0);
+    let harvest = |prompt_id: &str, workflow: &str, text: &str| {
+        harvester.store(prompt_id, &[("out.png".to_string(), b"png".to_vec())], &json!({"workflow": workflow, "request": {"text_positive": text}})).unwrap();
+    };
+    harvest("fox", "portrait", "a red fox");
+    harvest("hill", "landscape", "a green hill");
+    harvest("old", "portrait", "an old fox");
+    std::fs::create_dir_all(root.join("loose")).unwrap();
+    std::fs::write(root.join("loose/uncaptioned.png"), b"png").unwrap();
+    // Settled a minute ago, except the old output, from three days ago.
+    for entry in ["images/fox", "images/hill", "images/old", "loose"].iter().flat_map(|d| std::fs::read_dir(root.join(d)).unwrap()) {
+        let path = entry.unwrap().path();
+        let age = if path.starts_with(root.join("images/old")) { 3 * 24 * 3600 } else { 60 };
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(SystemTime::now() - Duration::from_secs(age)).unwrap();
+    }
+    StaticDrivePoller::new(root.to_string_lossy().to_string()).poll_drive().await;
+
+    let export = |out: &str, args: &[&str]| {
+        let out = root.join(out);
+        let command = tokio::process::Command::new(env!("CARGO_BIN_EXE_comfyctl"))
+            .args(["outputs", "export", "--format", "flat", "--out"])
+            .arg(&out)
+            .args(args)
+            .env("STATIC_DRIVE_PATH", &root)
+            .output();
+        async move {
+            let output = command.await.unwrap();
+            let mut files: Vec<String> = std::fs::read_dir(&out).map(|entries| entries.map(|e| e.unwrap().file_name().to_string_lossy().to_string()).collect()).unwrap_or_default();
+            files.sort();
+            (output, files)
+        }
+    };
+
+    // Everything captioned, skipping (and counting) the uncaptioned file.
+    let (output, files) = export("all", &[]).await;
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Skipped 1 outputs without a caption"));
+    assert_eq!(files, vec!["images_fox_out.png", "images_fox_out.txt", "images_hill_out.png", "images_hill_out.txt", "images_old_out.png", "images_old_out.txt"]);
+    assert_eq!(std::fs::read_to_string(root.join("all/images_fox_out.txt")).unwrap(), "a red fox");
+
+    let (_, files) = export("portrait", &["--workflow", "portrait"]).await;
+    assert_eq!(files, vec!["images_fox_out.png", "images_fox_out.txt", "images_old_out.png", "images_old_out.txt"]);
+    let (_, files) = export("recent", &["--workflow", "portrait", "--since", "1d"]).await;
+    assert_eq!(files, vec!["images_fox_out.png", "images_fox_out.txt"]);
+
+    // Nothing matching is an error, as is a bad --since.
+    let (output, _) = export("none", &["--workflow", "video"]).await;
+    assert_eq!(output.status.code(), Some(1));
+    let (output, _) = export("bad", &["--since", "soon"]).await;
+    assert_eq!(output.status.code(), Some(2));
+    std::fs::remove_dir_all(&root).ok();
+}
@@ -1,6 +1,10 @@
 use serde_json::json;
 
 use comfyui_api_proxy::comfyui::client::ComfyUIClient;
+use comfyui_api_proxy::comfyui::backend::BackendInfo;
+use comfyui_api_proxy::comfyui::object_info::{model_categories_from_object_info, models_from_object_info, node_schema};
+use comfyui_api_proxy::comfyui::types::{parse_history, unwrap_history, HistoryEntry, ModelList, PromptResult, QueuePromptResponse, QueueStatus, UploadedImage};
+use comfyui_api_proxy::comfyui::ws::{ws_url, ProgressEvent};
 
 
 #[tokio::test]
@@ -72,3 +76,1238 @@ async fn test_get_history() {
     assert!(result.is_ok());
     // Add more specific assertions based on the expected response
 }
+
+#[test]
+fn test_ws_url() {
+    assert_eq!(ws_url("http://localhost:8188/", "abc"), "ws://localhost:8188/ws?clientId=abc");
+    assert_eq!(ws_url("https://comfy.example", "abc"), "wss://comfy.example/ws?clientId=abc");
+}
+
+#[test]
+fn test_parse_progress_events() {
+    let executing = ProgressEvent::parse(r#"{"type":"executing","data":{"node":"3","prompt_id":"p1"}}"#);
+    assert_eq!(executing, Some(ProgressEvent::Executing { prompt_id: Some("p1".into()), node: Some("3".into()) }));
+
+    let done = ProgressEvent::parse(r#"{"type":"executing","data":{"node":null,"prompt_id":"p1"}}"#);
+    assert_eq!(done, Some(ProgressEvent::Executing { prompt_id: Some("p1".into()), node: None }));
+
+    let progress = ProgressEvent::parse(r#"{"type":"progress","data":{"value":4,"max":20,"node":"3","prompt_id":"p1"}}"#);
+    assert_eq!(progress, Some(ProgressEvent::Progress { prompt_id: Some("p1".into()), node: Some("3".into()), value: 4, max: 20 }));
+
+    let executed = ProgressEvent::parse(
+        r#"{"type":"executed","data":{"node":"9","prompt_id":"p1","output":{"images":[{"filename":"a.png"}]}}}"#,
+    ).unwrap();
+    assert_eq!(executed.prompt_id(), Some("p1"));
+    match executed {
+        ProgressEvent::Executed { node, output, .. } => {
+            assert_eq!(node, "9");
+            assert_eq!(output, json!({"images": [{"filename": "a.png"}]}));
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+
+    let error = ProgressEvent::parse(
+        r#"{"type":"execution_error","data":{"prompt_id":"p1","node_id":"3","node_type":"KSampler","exception_message":"OOM"}}"#,
+    );
+    assert_eq!(error, Some(ProgressEvent::ExecutionError {
+        prompt_id: "p1".into(),
+        node: Some("3".into()),
+        node_type: Some("KSampler".into()),
+        message: "OOM".into(),
+    }));
+
+    let status = ProgressEvent::parse(r#"{"type":"status","data":{"status":{"exec_info":{"queue_remaining":2}}}}"#);
+    assert_eq!(status, Some(ProgressEvent::Status { queue_remaining: Some(2) }));
+    assert!(matches!(ProgressEvent::parse(r#"{"type":"crystools.monitor","data":{}}"#), Some(ProgressEvent::Other { .. })));
+    assert_eq!(ProgressEvent::parse("not json"), None);
+}
+
+#[test]
+fn test_prompt_result_from_history_entry() {
+    let ok = json!({
+        "status": {"status_str": "success", "completed": true, "messages": []},
+        "outputs": {"9": {"images": [{"filename": "a.png", "subfolder": "", "type": "output"}]}}
+    });
+    let result = PromptResult::from_history_entry("p1", &ok).unwrap();
+    assert!(result.success);
+    assert_eq!(result.error, None);
+    assert_eq!(result.images.len(), 1);
+    assert_eq!(result.images[0].filename, "a.png");
+    assert_eq!(result.images[0].subfolder, None);
+    assert_eq!(result.images[0].folder_type.as_deref(), Some("output"));
+
+    let failed = json!({
+        "status": {"status_str": "error", "completed": false, "messages": [
+            ["execution_error", {"node_id": "3", "exception_message": "CUDA out of memory\n"}]
+        ]},
+        "outputs": {}
+    });
+    let result = PromptResult::from_history_entry("p2", &failed).unwrap();
+    assert!(!result.success);
+    assert_eq!(result.error.as_deref(), Some("CUDA out of memory"));
+    assert!(result.images.is_empty());
+
+    assert!(PromptResult::from_history_entry("p3", &json!({"outputs": "not a map"})).is_err());
+}
+
+#[test]
+fn test_typed_responses() {
+    let queued: QueuePromptResponse = serde_json::from_value(json!({
+        "prompt_id": "p1", "number": 4, "node_errors": {}, "extra_field": true
+    })).unwrap();
+    assert_eq!(queued.prompt_id, "p1");
+    assert_eq!(queued.number, Some(4));
+    assert_eq!(queued.extra["extra_field"], true);
+
+    let entry: HistoryEntry = serde_json::from_value(json!({
+        "prompt": [12, "p1", {"9": {"class_type": "SaveImage", "inputs": {}}}, {}, ["9"]],
+        "outputs": {
+            "9": {"images": [{"filename": "a.png", "subfolder": "", "type": "output"}]},
+            "12": {"gifs": [{"filename": "b.webp", "subfolder": "anim", "type": "output"}], "text": ["hi"]}
+        },
+        "status": {"status_str": "success", "completed": true, "messages": [["execution_start", {"timestamp": 1}]]}
+    })).unwrap();
+    assert_eq!(entry.number(), Some(12));
+    assert!(!entry.is_error());
+    let images = entry.images();
+    assert_eq!(images.iter().map(|i| (i.node.as_str(), i.filename.as_str())).collect::<Vec<_>>(), vec![("12", "b.webp"), ("9", "a.png")]);
+    assert_eq!(images[0].subfolder.as_deref(), Some("anim"));
+    assert_eq!(images[1].subfolder, None);
+
+    let old: ModelList = serde_json::from_value(json!(["a.safetensors", "b.ckpt"])).unwrap();
+    let new: ModelList = serde_json::from_value(json!([{"name": "a.safetensors", "pathIndex": 0}])).unwrap();
+    assert_eq!(old.names, vec!["a.safetensors", "b.ckpt"]);
+    assert_eq!(new.names, vec!["a.safetensors"]);
+
+    let uploaded: UploadedImage = serde_json::from_value(json!({"name": "mask (1).png", "subfolder": "", "type": "input"})).unwrap();
+    assert_eq!(uploaded.name, "mask (1).png");
+    assert_eq!(uploaded.subfolder, None);
+    assert_eq!(uploaded.folder_type.as_deref(), Some("input"));
+
+    let queue: QueueStatus = serde_json::from_value(json!({
+        "queue_running": [[3, "p3", {"9": {}}, {}, ["9"]]],
+        "queue_pending": [[4, "p4", {}, {}, []], [5, "p5", {}, {}, []]]
+    })).unwrap();
+    assert_eq!(queue.running[0].number, Some(3));
+    assert!(queue.is_running("p3"));
+    assert!(queue.is_pending("p5"));
+    assert!(!queue.is_pending("p3"));
+}
+
+#[test]
+fn test_input_library_merges_folder() {
+    use comfyui_api_proxy::comfyui::inputs::{list_inputs, InputLibrary};
+
+    let dir = std::env::temp_dir().join(format!("inputs_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(dir.join("masks")).unwrap();
+    std::fs::write(dir.join("photo.png"), b"1234").unwrap();
+    std::fs::write(dir.join("masks/m.png"), b"12").unwrap();
+
+    let mut library = InputLibrary::new();
+    let uploaded: UploadedImage = serde_json::from_value(json!({"name": "photo.png", "subfolder": "", "type": "input"})).unwrap();
+    library.record(&uploaded, 4, u64::MAX, Some("team-a".to_string()));
+    let gone: UploadedImage = serde_json::from_value(json!({"name": "deleted.png", "type": "input"})).unwrap();
+    library.record(&gone, 1, 1, None);
+
+    let images = list_inputs(&library, Some(&dir));
+    let paths: Vec<String> = images.iter().map(|i| i.path()).collect();
+    assert_eq!(paths, vec!["photo.png", "masks/m.png"]);
+    assert!(images[0].tracked && images[0].visible_to(Some("team-a")));
+    assert!(!images[1].tracked && !images[1].visible_to(Some("team-a")));
+
+    // Without the folder, only tracked uploads are known.
+    assert_eq!(list_inputs(&library, None).len(), 2);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_node_schema_from_object_info() {
+    let info = json!({
+        "KSampler": {
+            "input": {
+                "required": {
+                    "seed": ["INT", {"default": 0, "min": 0, "max": 18446744073709551615u64}],
+                    "sampler_name": [["euler", "dpmpp_2m"], {}],
+                    "scheduler": ["COMBO", {"options": ["normal", "karras"], "default": "normal"}],
+                    "model": ["MODEL"]
+                },
+                "optional": {"denoise": ["FLOAT", {"default": 1.0, "step": 0.01}]}
+            },
+            "output": ["LATENT"],
+            "output_name": ["samples"],
+            "display_name": "KSampler",
+            "category": "sampling"
+        }
+    });
+    let schema = node_schema(&info, "KSampler").unwrap();
+    let inputs = schema["inputs"].as_array().unwrap();
+    let input = |name: &str| inputs.iter().find(|i| i["name"] == name).unwrap().clone();
+    assert_eq!(input("seed")["type"], "INT");
+    assert_eq!(input("seed")["default"], 0);
+    assert_eq!(input("sampler_name")["type"], "COMBO");
+    assert_eq!(input("sampler_name")["options"], json!(["euler", "dpmpp_2m"]));
+    assert_eq!(input("scheduler")["options"], json!(["normal", "karras"]));
+    assert_eq!(input("model")["required"], true);
+    assert_eq!(input("denoise")["required"], false);
+    assert_eq!(schema["outputs"], json!([{"type": "LATENT", "name": "samples"}]));
+    assert_eq!(schema["category"], "sampling");
+    assert!(node_schema(&info, "Missing").is_none());
+}
+
+#[test]
+fn test_backend_detection_and_compat() {
+    let stats = json!({
+        "system": {"os": "posix", "python_version": "3.11.9", "comfyui_version": "0.3.27", "pytorch_version": "2.5.1"},
+        "devices": [{"name": "cuda:0 NVIDIA GeForce RTX 4090", "type": "cuda", "vram_total": 25393692672u64, "vram_free": 20000000000u64}]
+    });
+    let history = json!({"abc": {"prompt": [1, "abc", {}, {}, []], "outputs": {}, "status": {"status_str": "success"}}});
+    let info = BackendInfo::from_probes("http://comfy:8188", Some(&stats), true, 1000);
+    assert_eq!(info.version.as_deref(), Some("0.3.27"));
+    assert_eq!(info.devices[0].device_type.as_deref(), Some("cuda"));
+    assert!(info.features.models && info.features.system_stats);
+
+    // An old build: no /system_stats or /models, wrapped history without status.
+    let wrapped = json!({"history": {"abc": {"prompt": [1, "abc", {}, {}, []], "outputs": {}}}});
+    let old = BackendInfo::from_probes("http://comfy:8188", None, false, 1000);
+    assert_eq!(old.version, None);
+    assert!(!old.features.system_stats && !old.features.models);
+    assert!(unwrap_history(wrapped).get("abc").is_some());
+    assert_eq!(unwrap_history(history.clone()), history);
+
+    // One unreadable entry doesn't hide the others.
+    let mixed = json!({
+        "abc": {"prompt": [1, "abc", {}, {}, []], "outputs": {"9": {"images": [{"filename": "a.png"}]}}},
+        "bad": {"prompt": [2, "bad", {}, {}, []], "outputs": {"9": {"images": "not a list"}}},
+    });
+    let parsed = parse_history(mixed);
+    assert_eq!(parsed.keys().collect::<Vec<_>>(), vec!["abc"]);
+    assert_eq!(parsed["abc"].images()[0].filename, "a.png");
+
+    let object_info = json!({
+        "CheckpointLoaderSimple": {"input": {"required": {"ckpt_name": [["sd15.safetensors", "sdxl.safetensors"]]}}},
+        "LoraLoader": {"input": {"required": {"lora_name": ["COMBO", {"options": ["detail.safetensors"]}]}}}
+    });
+    assert_eq!(models_from_object_info(&object_info, "checkpoints").unwrap(), vec!["sd15.safetensors", "sdxl.safetensors"]);
+    assert_eq!(models_from_object_info(&object_info, "loras").unwrap(), vec!["detail.safetensors"]);
+    assert!(models_from_object_info(&object_info, "vae").is_none());
+    assert_eq!(model_categories_from_object_info(&object_info), vec!["checkpoints", "loras"]);
+}
+
+#[cfg(feature = "fixtures")]
+#[tokio::test]
+async fn test_record_and_replay_fixtures() {
+    use axum::{routing::{get, post}, Json, Router};
+    use comfyui_api_proxy::comfyui::fixtures::{FixtureMode, Fixtures};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // A stand-in ComfyUI whose history fills in on the second poll.
+    let polls = Arc::new(AtomicUsize::new(0));
+    let app = Router::new()
+        .route("/prompt", post(|| async { Json(json!({"prompt_id": "p1", "number": 1, "node_errors": {}})) }))
+        .route("/history/p1", get(move || async move {
+            match polls.fetch_add(1, Ordering::SeqCst) {
+                0 => Json(json!({})),
+                _ => Json(json!({"p1": {"prompt": [1, "p1", {}, {}, []], "outputs": {}, "status": {"status_str": "success", "completed": true}}})),
+            }
+        }))
+        .route("/view", get(|| async { ([("content-type", "image/png")], vec![0x89, b'P', b'N', b'G', 0, 1, 2]) }));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+
+    let dir = std::env::temp_dir().join(format!("fixtures_{}", uuid::Uuid::new_v4()));
+    let recorder = ComfyUIClient::new(url).with_fixtures(Fixtures::new(&dir, FixtureMode::Record));
+    assert_eq!(recorder.queue_prompt(json!({"prompt": {}})).await.unwrap().prompt_id, "p1");
+    assert!(recorder.get_prompt_history("p1").await.unwrap().is_none());
+    assert!(recorder.get_prompt_history("p1").await.unwrap().is_some());
+    let image = recorder.get_image("out.png").await.unwrap();
+
+    // Nothing listens here; every answer comes from the recordings.
+    let replayer = ComfyUIClient::new("http://127.0.0.1:9".to_string()).with_fixtures(Fixtures::new(&dir, FixtureMode::Replay));
+    assert_eq!(replayer.queue_prompt(json!({"prompt": {"other": 1}})).await.unwrap().prompt_id, "p1");
+    assert!(replayer.get_prompt_history("p1").await.unwrap().is_none());
+    let result = replayer.wait_for_completion("p1", std::time::Duration::from_secs(5)).await.unwrap();
+    assert!(result.success);
+    assert_eq!(replayer.get_image("out.png").await.unwrap(), image);
+    assert!(replayer.get_queue().await.is_err());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn test_client_retries_transient_failures_and_times_out() {
+    use axum::{http::StatusCode, routing::get, Json, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // A flaky ComfyUI: the first two /queue requests get a 503, /prompt
+    // always does, and /history never answers.
+    let calls = Arc::new(AtomicUsize::new(0));
+    let posts = Arc::new(AtomicUsize::new(0));
+    let (queue_calls, prompt_posts) = (calls.clone(), posts.clone());
+    let app = Router::new()
+        .route("/queue", get(move || async move {
+            match queue_calls.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(StatusCode::SERVICE_UNAVAILABLE),
+                _ => Ok(Json(json!({"queue_running": [], "queue_pending": []}))),
+            }
+        }))
+        .route("/prompt", axum::routing::post(move || async move {
+            prompt_posts.fetch_add(1, Ordering::SeqCst);
+            StatusCode::SERVICE_UNAVAILABLE
+        }))
+        .route("/history", get(|| async {
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            Json(json!({}))
+        }));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+
+    let client = ComfyUIClient::builder(url.clone())
+        .timeout(Duration::from_millis(300))
+        .retries(2)
+        .backoff(Duration::from_millis(10))
+        .build();
+    assert!(client.get_queue().await.is_ok());
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    // A POST that reached ComfyUI isn't repeated.
+    assert!(client.queue_prompt(json!({"prompt": {}})).await.is_err());
+    assert_eq!(posts.load(Ordering::SeqCst), 1);
+
+    let started = std::time::Instant::now();
+    assert!(client.get_history().await.is_err());
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    calls.store(0, Ordering::SeqCst);
+    let no_retries = ComfyUIClient::builder(url).retries(0).build();
+    assert!(no_retries.get_queue().await.is_err());
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // POSTs that couldn't connect are only retried when opted in; the
+    // backoff between attempts shows whether they were.
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let down = format!("http://{}", closed.local_addr().unwrap());
+    drop(closed);
+    let builder = ComfyUIClient::builder(down).retries(2).backoff(Duration::from_millis(200));
+    let started = std::time::Instant::now();
+    assert!(builder.clone().build().queue_prompt(json!({"prompt": {}})).await.is_err());
+    assert!(started.elapsed() < Duration::from_millis(200));
+    let started = std::time::Instant::now();
+    assert!(builder.retry_posts(true).build().queue_prompt(json!({"prompt": {}})).await.is_err());
+    assert!(started.elapsed() >= Duration::from_millis(600));
+}
+
+#[tokio::test]
+async fn test_client_sends_user_agent() {
+    use axum::{http::HeaderMap, routing::get, Json, Router};
+    use comfyui_api_proxy::config::DEFAULT_USER_AGENT;
+
+    // Echo the User-Agent back as the queue.
+    let app = Router::new().route("/queue", get(|headers: HeaderMap| async move {
+        let agent = headers.get("user-agent").and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+        Json(json!({"queue_running": [], "queue_pending": [[0, agent, {}, {}, []]]}))
+    }));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+
+    let client = ComfyUIClient::new(url.clone());
+    assert!(DEFAULT_USER_AGENT.starts_with("comfyui-api-proxy/"));
+    assert_eq!(client.get_queue().await.unwrap().pending[0].prompt_id, DEFAULT_USER_AGENT);
+    let client = ComfyUIClient::builder(url).user_agent("studio-proxy/1.0").build();
+    assert_eq!(client.get_queue().await.unwrap().pending[0].prompt_id, "studio-proxy/1.0");
+}
+
+/// A stand-in ComfyUI that queues every prompt as `prompt_id` and reports
+/// `pending` prompts waiting. Returns its URL.
+fn spawn_backend(prompt_id: &'static str, pending: usize) -> String {
+    use axum::{routing::{get, post}, Json, Router};
+
+    let queue: Vec<serde_json::Value> = (0..pending).map(|i| json!([i, format!("{}-waiting-{}", prompt_id, i), {}, {}, []])).collect();
+    let app = Router::new()
+        .route("/prompt", post(move || async move { Json(json!({"prompt_id": prompt_id, "number": 1, "node_errors": {}})) }))
+        .route("/queue", get(move || {
+            let queue = queue.clone();
+            async move { Json(json!({"queue_running": [], "queue_pending": queue})) }
+        }).post(|| async { Json(json!({})) }))
+        .route("/history", get(move || async move { Json(json!({format!("{}-old", prompt_id): {"outputs": {}}})) }))
+        .route("/history/:id", get(move |axum::extract::Path(id): axum::extract::Path<String>| async move {
+            match id == prompt_id {
+                true => Json(json!({id.clone(): {"outputs": {}, "status": {"status_str": "success", "completed": true}}})),
+                false => Json(json!({})),
+            }
+        }))
+        .route("/view", get(move || async move { prompt_id.as_bytes().to_vec() }));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+    url
+}
+
+#[tokio::test]
+async fn test_backend_pool_balances_and_sticks_to_backends() {
+    use comfyui_api_proxy::comfyui::backend::BackendPool;
+
+    let busy = ComfyUIClient::builder(spawn_backend("busy", 2)).retries(0).build();
+    let idle = ComfyUIClient::builder(spawn_backend("idle", 0)).retries(0).build();
+    let pool = BackendPool::new(busy, vec![idle.clone()]);
+    pool.check_health().await;
+    let loads = pool.loads();
+    assert_eq!((loads[0].queue_depth, loads[1].queue_depth), (2, 0));
+
+    // The idle backend gets the prompt, and follow-ups for it go there too.
+    let queued = pool.queue_prompt(json!({"prompt": {}})).await.unwrap();
+    assert_eq!(queued.prompt_id, "idle");
+    assert_eq!(pool.url_for("idle").as_deref(), Some(idle.base_url()));
+    assert!(pool.get_prompt_history("idle").await.unwrap().is_some());
+    assert_eq!(pool.get_image_in(Some("idle"), "out.png", None, None).await.unwrap(), b"idle");
+    // Images without a known prompt come from the first backend that has them.
+    assert_eq!(pool.get_image_in(None, "out.png", None, None).await.unwrap(), b"busy");
+
+    // Listings are merged across backends.
+    assert_eq!(pool.get_queue().await.unwrap().pending.len(), 2);
+    let history = pool.get_history_raw().await.unwrap();
+    assert!(history.get("busy-old").is_some() && history.get("idle-old").is_some());
+
+    // A backend refusing connections is skipped and taken out of rotation.
+    let down = ComfyUIClient::builder("http://127.0.0.1:9").retries(0).build();
+    let pool = BackendPool::new(down, vec![idle]);
+    assert_eq!(pool.queue_prompt(json!({"prompt": {}})).await.unwrap().prompt_id, "idle");
+    assert!(!pool.loads()[0].healthy);
+    assert!(pool.get_queue().await.is_ok());
+
+    // Clearing goes on past a backend that can't be reached, and reports it.
+    let failed = pool.clear_queue().await.unwrap();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].0, "http://127.0.0.1:9");
+}
+
+#[tokio::test]
+async fn test_backend_pool_forgets_old_finished_prompts() {
+    use axum::{routing::get, Json, Router};
+    use comfyui_api_proxy::comfyui::backend::BackendPool;
+
+    // A backend whose history holds more finished prompts than are kept.
+    let history: serde_json::Map<String, serde_json::Value> = (0..1001).map(|i| (format!("p{:04}", i), json!({"outputs": {}}))).collect();
+    let app = Router::new().route("/history", get(move || {
+        let history = history.clone();
+        async move { Json(serde_json::Value::Object(history)) }
+    }));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+
+    let idle = ComfyUIClient::builder(spawn_backend("idle", 0)).retries(0).build();
+    let pool = BackendPool::new(idle.clone(), vec![ComfyUIClient::builder(url.clone()).retries(0).build()]);
+    pool.get_history_raw().await.unwrap();
+    assert_eq!(pool.url_for("p0000"), None);
+    assert_eq!(pool.url_for("p1000").as_deref(), Some(url.as_str()));
+
+    // A queued prompt is remembered, and kept once it has left the queue.
+    pool.queue_prompt(json!({"prompt": {}})).await.unwrap();
+    pool.check_health().await;
+    assert_eq!(pool.url_for("idle").as_deref(), Some(idle.base_url()));
+}
+
+#[tokio::test]
+async fn test_lifecycle_hooks_follow_a_job() {
+    use axum::{body::Body, http::Request};
+    use comfyui_api_proxy::{api::routes, config::Config, jobs::JobEventKind};
+    use tower::ServiceExt;
+
+    let config = Config::new().expect("Failed to load configuration");
+    let client = ComfyUIClient::builder(spawn_backend("hooked", 0)).retries(0).build();
+    let state = routes::build_state(&config, client);
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let queued = tx.clone();
+    state.hooks.on_job_queued(move |job| {
+        let tx = queued.clone();
+        async move { tx.send(format!("queued {}", job.prompt_id.unwrap_or_default())).unwrap(); }
+    });
+    let completed = tx.clone();
+    state.hooks.on_job_completed(move |job| {
+        let tx = completed.clone();
+        async move { tx.send(format!("completed {:?}", job.state())).unwrap(); }
+    });
+    state.hooks.on_output_saved(move |_, output| {
+        let tx = tx.clone();
+        async move { tx.send(format!("saved {}", output.filename)).unwrap(); }
+    });
+    let app = routes::build_router(state.clone());
+
+    let body = json!({"prompt": {"3": {"class_type": "KSampler", "inputs": {"seed": 1}}}});
+    let response = app.clone().oneshot(Request::builder()
+        .method("POST")
+        .uri("/queue_prompt")
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()).await.unwrap();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    let job_id = v["job_id"].as_str().expect("queued").to_string();
+    assert_eq!(rx.recv().await.unwrap(), "queued hooked");
+
+    // Polling the job picks up its finished history.
+    app.oneshot(Request::builder().uri(format!("/jobs/{}", job_id)).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), "completed Completed");
+
+    // Hooks follow the job however it changes: a job failing on the way,
+    // and outputs added once it has finished.
+    let other = state.job_store.write().await.create(None);
+    state.job_store.write().await.record(&other, JobEventKind::Failed { node: None, error: "Removed from queue".to_string() });
+    assert_eq!(rx.recv().await.unwrap(), "completed Failed");
+    state.job_store.write().await.record(&job_id, JobEventKind::OutputSaved {
+        node: "9:cell".to_string(),
+        filename: "cell.png".to_string(),
+        subfolder: None,
+        folder_type: Some("output".to_string()),
+    });
+    assert_eq!(rx.recv().await.unwrap(), "saved cell.png");
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_rerun_queues_the_request_again() {
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use comfyui_api_proxy::{api::routes, config::Config};
+    use tower::ServiceExt;
+
+    let config = Config::new().expect("Failed to load configuration");
+    let client = ComfyUIClient::builder(spawn_backend("again", 0)).retries(0).build();
+    let state = routes::build_state(&config, client);
+    let app = routes::build_router(state.clone());
+    let post = |uri: String, body: serde_json::Value| {
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(Request::builder().method("POST").uri(uri).header("Content-Type", "application/json")
+                .body(Body::from(body.to_string())).unwrap()).await.unwrap();
+            let status = response.status();
+            let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            (status, serde_json::from_slice::<serde_json::Value>(&bytes).unwrap())
+        }
+    };
+
+    let body = json!({"prompt": {"3": {"class_type": "KSampler", "inputs": {"seed": 1}}}, "text": "a lighthouse"});
+    let (_, queued) = post("/queue_prompt".to_string(), body.clone()).await;
+    let job_id = queued["job_id"].as_str().unwrap().to_string();
+    let (status, rerun) = post(format!("/jobs/{}/rerun", job_id), json!(null)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(rerun["rerun_of"], job_id.as_str());
+    assert_eq!(rerun["prompt_id"], "again");
+    let rerun_id = rerun["job_id"].as_str().unwrap();
+    assert_ne!(rerun_id, job_id);
+    assert_eq!(state.job_store.read().await.get(rerun_id).unwrap().request.as_ref(), Some(&body));
+
+    // A request over today's text limits is refused, as /queue_prompt would.
+    let too_long = json!({"prompt": {}, "text": "x".repeat(config.max_prompt_chars + 1)});
+    state.job_store.write().await.get_mut(&job_id).unwrap().request = Some(too_long);
+    let (status, _) = post(format!("/jobs/{}/rerun", job_id), json!(null)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+/// A stand-in ComfyUI that queues every prompt as `prompt_id`, finishes it
+/// with one saved image, and plays `messages` on its websocket once the
+/// returned sender fires. `/hook` accepts webhook posts and `/caption`
+/// captions every image "a red fox". Returns its URL.
+fn spawn_ws_backend(prompt_id: &'static str, messages: Vec<serde_json::Value>) -> (String, tokio::sync::oneshot::Sender<()>) {
+    use axum::{extract::ws::{Message, WebSocketUpgrade}, routing::{get, post}, Json, Router};
+    use std::sync::{Arc, Mutex};
+
+    let (play, played) = tokio::sync::oneshot::channel::<()>();
+    let played = Arc::new(Mutex::new(Some(played)));
+    let history = move || async move {
+        Json(json!({prompt_id: {
+            "outputs": {"9": {"images": [{"filename": "out.png", "subfolder": "", "type": "output"}]}},
+            "status": {"status_str": "success", "completed": true}
+        }}))
+    };
+    let app = Router::new()
+        .route("/prompt", post(move || async move { Json(json!({"prompt_id": prompt_id, "number": 1, "node_errors": {}})) }))
+        .route("/queue", get(|| async { Json(json!({"queue_running": [], "queue_pending": []})) }))
+        .route("/history", get(history))
+        .route("/history/:id", get(history))
+        .route("/view", get(|| async { b"pixels".to_vec() }))
+        .route("/hook", post(|| async { "ok" }))
+        .route("/caption", post(|| async { Json(json!({"caption": "a red fox"})) }))
+        .route("/ws", get(move |ws: WebSocketUpgrade| {
+            let (played, messages) = (played.lock().unwrap().take(), messages.clone());
+            async move {
+                ws.on_upgrade(move |mut socket| async move {
+                    let Some(played) = played else { return };
+                    played.await.ok();
+                    for message in messages {
+                        if socket.send(Message::Text(message.to_string())).await.is_err() {
+                            return;
+                        }
+                    }
+                    // Stay open so the hub doesn't reconnect.
+                    while socket.recv().await.is_some() {}
+                })
+            }
+        }));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+    (url, play)
+}
+
+#[tokio::test]
+async fn test_generate_runs_the_default_workflow() {
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use comfyui_api_proxy::{api::routes, config::Config};
+    use tower::ServiceExt;
+
+    let (url, _play) = spawn_ws_backend("generated", Vec::new());
+    let prompts = std::env::temp_dir().join(format!("prompts_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&prompts).unwrap();
+    let graph = json!({
+        "3": {"class_type": "KSampler", "inputs": {"seed": 1}},
+        "6": {"class_type": "CLIPTextEncode", "inputs": {"text": ""}},
+        "9": {"class_type": "SaveImage", "inputs": {}}
+    });
+    std::fs::write(prompts.join("quick.json"), graph.to_string()).unwrap();
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.prompts_dir = prompts.to_string_lossy().to_string();
+    let generate = |config: &Config, body: serde_json::Value| {
+        let state = routes::build_state(config, ComfyUIClient::builder(url.clone()).retries(0).build());
+        async move {
+            let response = routes::build_router(state.clone()).oneshot(Request::builder().method("POST").uri("/generate")
+                .header("Content-Type", "application/json").body(Body::from(body.to_string())).unwrap()).await.unwrap();
+            let status = response.status();
+            let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            (status, serde_json::from_slice::<serde_json::Value>(&bytes).unwrap(), state)
+        }
+    };
+
+    // Without DEFAULT_WORKFLOW there is nothing to drive.
+    let (status, _, _) = generate(&config, json!({"prompt": "a lighthouse"})).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+    config.default_workflow = Some("quick".to_string());
+    let (status, v, state) = generate(&config, json!({"prompt": "a lighthouse", "negative_prompt": "fog", "seed": 42, "width": 640})).await;
+    assert_eq!(status, StatusCode::OK, "{}", v);
+    assert_eq!(v["prompt_id"], "generated");
+    assert_eq!(v["seed"], 42);
+    assert_eq!(v["images"][0]["filename"], "out.png");
+    assert_eq!(v["images"][0]["url"], "/get_image?filename=out.png");
+    let job = state.job_store.read().await.get(v["job_id"].as_str().unwrap()).cloned().unwrap();
+    assert_eq!(job.workflow.as_deref(), Some("quick"));
+    assert_eq!(job.request, Some(json!({"workflow": "quick", "text_positive": "a lighthouse", "text_negative": "fog", "seed": 42, "width": 640})));
+
+    let (status, _, _) = generate(&config, json!({"prompt": "a lighthouse", "width": "wide"})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    std::fs::remove_dir_all(&prompts).ok();
+}
+
+#[tokio::test]
+async fn test_job_timeline_follows_the_websocket() {
+    use axum::{body::Body, http::Request};
+    use comfyui_api_proxy::{api::{handlers, routes}, config::Config};
+    use tower::ServiceExt;
+
+    let executing = |node: Option<&str>| json!({"type": "executing", "data": {"prompt_id": "traced", "node": node}});
+    let (url, play) = spawn_ws_backend("traced", vec![
+        json!({"type": "execution_start", "data": {"prompt_id": "traced"}}),
+        executing(Some("3")),
+        executing(Some("9")),
+        json!({"type": "executed", "data": {"prompt_id": "traced", "node": "9", "output": {}}}),
+        executing(None),
+    ]);
+    let drive = std::env::temp_dir().join(format!("drive_{}", uuid::Uuid::new_v4()));
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.static_drive_path = drive.to_string_lossy().to_string();
+    config.harvest_outputs = true;
+    config.static_drive_actions = vec!["caption".to_string(), "webhook".to_string()];
+    config.static_drive_webhook_url = Some(format!("{}/hook", url));
+    config.caption_url = Some(format!("{}/caption", url));
+    let state = routes::build_state(&config, ComfyUIClient::builder(url.clone()).retries(0).build());
+    let follower = state.clone();
+    tokio::spawn(async move { handlers::follow_progress(&follower).await });
+    let app = routes::build_router(state.clone());
+    let call = |method: &str, uri: &str, body: serde_json::Value| {
+        let app = app.clone();
+        let request = Request::builder().method(method).uri(uri).header("Content-Type", "application/json")
+            .body(Body::from(body.to_string())).unwrap();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        }
+    };
+
+    let body = json!({"prompt": {"3": {"class_type": "KSampler", "inputs": {"seed": 1}}, "9": {"class_type": "SaveImage", "inputs": {}}}});
+    let job_id = call("POST", "/queue_prompt", body).await["job_id"].as_str().unwrap().to_string();
+    play.send(()).unwrap();
+    // The follower finishes the job on its own; nothing polls it.
+    for _ in 0..100 {
+        if state.job_store.read().await.get(&job_id).unwrap().is_finished() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    let timeline = call("GET", &format!("/jobs/{}/events", job_id), json!(null)).await;
+    let kinds: Vec<(String, Option<String>)> = timeline["events"].as_array().unwrap().iter()
+        .map(|e| (e["event"].as_str().unwrap().to_string(), e["node"].as_str().map(String::from)))
+        .collect();
+    for expected in [("node_started", Some("3")), ("node_started", Some("9")), ("node_finished", Some("9")), ("completed", None)] {
+        assert!(kinds.contains(&(expected.0.to_string(), expected.1.map(String::from))), "{:?} missing from {:?}", expected, kinds);
+    }
+
+    // Harvested outputs delivered by the drive webhook show up too, and
+    // their captions land on the job's outputs.
+    assert_eq!(handlers::harvest_outputs(&state).await, 1);
+    let past = std::time::SystemTime::now() - std::time::Duration::from_secs(60);
+    for file in ["out.png", "generation.json"] {
+        std::fs::File::options().write(true).open(drive.join("images/traced").join(file)).unwrap().set_modified(past).unwrap();
+    }
+    assert_eq!(handlers::poll_static_drive(&state).await.len(), 2);
+    let timeline = call("GET", &format!("/jobs/{}/events", job_id), json!(null)).await;
+    let delivered: Vec<&serde_json::Value> = timeline["events"].as_array().unwrap().iter()
+        .filter(|e| e["event"] == "webhook_delivered")
+        .collect();
+    assert_eq!(delivered.len(), 1);
+    assert_eq!(delivered[0]["url"], format!("{}/hook", url));
+    let job = call("GET", &format!("/jobs/{}", job_id), json!(null)).await;
+    assert_eq!(job["outputs"]["files"][0]["caption"], "a red fox");
+    std::fs::remove_dir_all(&drive).ok();
+}
+
+#[tokio::test]
+async fn test_node_timings_come_from_the_websocket() {
+    use axum::{body::Body, http::Request};
+    use comfyui_api_proxy::{api::{handlers, routes}, config::Config};
+    use tower::ServiceExt;
+
+    // No `executed` messages: each node runs until the next starts or the prompt ends.
+    let executing = |node: Option<&str>| json!({"type": "executing", "data": {"prompt_id": "timed", "node": node}});
+    let (url, play) = spawn_ws_backend("timed", vec![executing(Some("3")), executing(Some("9")), executing(None)]);
+    let config = Config::new().expect("Failed to load configuration");
+    let state = routes::build_state(&config, ComfyUIClient::builder(url).retries(0).build());
+    let follower = state.clone();
+    tokio::spawn(async move { handlers::follow_progress(&follower).await });
+    let app = routes::build_router(state.clone());
+    let call = |method: &str, uri: &str, body: serde_json::Value| {
+        let app = app.clone();
+        let request = Request::builder().method(method).uri(uri).header("Content-Type", "application/json")
+            .body(Body::from(body.to_string())).unwrap();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        }
+    };
+
+    let body = json!({"prompt": {"3": {"class_type": "KSampler", "inputs": {"seed": 1}}, "9": {"class_type": "SaveImage", "inputs": {}}}});
+    let job_id = call("POST", "/queue_prompt", body).await["job_id"].as_str().unwrap().to_string();
+    play.send(()).unwrap();
+    for _ in 0..100 {
+        if state.job_store.read().await.get(&job_id).unwrap().is_finished() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+
+    let timeline = call("GET", &format!("/jobs/{}/events", job_id), json!(null)).await;
+    let timed: Vec<(&str, &str)> = timeline["node_timings"].as_array().unwrap().iter()
+        .map(|t| (t["node"].as_str().unwrap(), t["class_type"].as_str().unwrap()))
+        .collect();
+    assert_eq!(timed, vec![("3", "KSampler"), ("9", "SaveImage")]);
+    let stats = call("GET", "/stats/nodes", json!(null)).await;
+    let mut classes: Vec<&str> = stats["nodes"].as_array().unwrap().iter().map(|s| s["key"].as_str().unwrap()).collect();
+    classes.sort();
+    assert_eq!(classes, vec!["KSampler", "SaveImage"]);
+    assert!(stats["nodes"].as_array().unwrap().iter().all(|s| s["runs"] == 1));
+}
+
+#[tokio::test]
+async fn test_fair_queue_holds_prompts_while_comfyui_is_busy() {
+    use axum::{body::Body, http::Request};
+    use comfyui_api_proxy::{api::routes, config::Config};
+    use tower::ServiceExt;
+
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.fair_queue_depth = Some(1);
+    // One prompt is already waiting, so the queue is full.
+    let client = ComfyUIClient::builder(spawn_backend("fair", 1)).retries(0).build();
+    let app = routes::build_router(routes::build_state(&config, client));
+    let call = |method: &str, uri: &str, body: serde_json::Value| {
+        let app = app.clone();
+        let request = Request::builder().method(method).uri(uri).header("Content-Type", "application/json")
+            .body(Body::from(body.to_string())).unwrap();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        }
+    };
+
+    let body = json!({"prompt": {"3": {"class_type": "KSampler", "inputs": {"seed": 1}}}});
+    let queued = call("POST", "/queue_prompt", body.clone()).await;
+    assert_eq!(queued["held"], true);
+    assert_eq!(queued["position"], 0);
+    assert!(queued.get("prompt_id").is_none());
+    let job_id = queued["job_id"].as_str().unwrap().to_string();
+
+    let queue = call("GET", "/queue", json!(null)).await;
+    assert_eq!(queue["held"][0]["job_id"], job_id.as_str());
+    assert_eq!(call("GET", &format!("/jobs/{}", job_id), json!(null)).await["state"], "submitted");
+
+    let cancelled = call("POST", &format!("/jobs/{}/cancel", job_id), json!(null)).await;
+    assert_eq!(cancelled["action"], "dequeued");
+    assert_eq!(call("GET", "/queue", json!(null)).await["held"], json!([]));
+    assert_eq!(call("GET", &format!("/jobs/{}", job_id), json!(null)).await["state"], "failed");
+    let timeline = call("GET", &format!("/jobs/{}/events", job_id), json!(null)).await;
+    assert!(timeline["events"].as_array().unwrap().iter().any(|e| e["event"] == "held" && e["instance"] == config.instance_id.as_str()));
+
+    // Held prompts can be dropped through the queue endpoints too, by job id.
+    let second = call("POST", "/queue_prompt", body.clone()).await["job_id"].as_str().unwrap().to_string();
+    let third = call("POST", "/queue_prompt", body).await["job_id"].as_str().unwrap().to_string();
+    assert_eq!(call("DELETE", &format!("/queue/{}", second), json!(null)).await["deleted"], second.as_str());
+    assert_eq!(call("POST", "/interrupt", json!({"prompt_id": third})).await["released"], third.as_str());
+    assert_eq!(call("GET", "/queue", json!(null)).await["held"], json!([]));
+    assert_eq!(call("GET", &format!("/jobs/{}", third), json!(null)).await["state"], "failed");
+}
+
+#[tokio::test]
+async fn test_workflow_limits_hold_jobs_until_comfyui_finishes_one() {
+    use axum::{body::Body, extract::Path, http::Request, routing::{get, post}, Json, Router};
+    use comfyui_api_proxy::{api::routes, config::Config};
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+
+    // Sent prompts run until they're marked finished, which moves them to history.
+    let running: Arc<Mutex<Vec<String>>> = Arc::default();
+    let finished: Arc<Mutex<Vec<String>>> = Arc::default();
+    let sent = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let backend = Router::new()
+        .route("/prompt", post({
+            let running = running.clone();
+            move || async move {
+                let prompt_id = format!("limited-{}", sent.fetch_add(1, std::sync::atomic::Ordering::SeqCst));
+                running.lock().unwrap().push(prompt_id.clone());
+                Json(json!({"prompt_id": prompt_id, "number": 1, "node_errors": {}}))
+            }
+        }))
+        .route("/queue", get({
+            let (running, finished) = (running.clone(), finished.clone());
+            move || async move {
+                let finished = finished.lock().unwrap();
+                let items: Vec<_> = running.lock().unwrap().iter().filter(|id| !finished.contains(id)).map(|id| json!([0, id, {}, {}, []])).collect();
+                Json(json!({"queue_running": items, "queue_pending": []}))
+            }
+        }))
+        .route("/history/:id", get({
+            let finished = finished.clone();
+            move |Path(id): Path<String>| async move {
+                match finished.lock().unwrap().contains(&id) {
+                    true => Json(json!({id: {"outputs": {}, "status": {"status_str": "success", "completed": true}}})),
+                    false => Json(json!({})),
+                }
+            }
+        }));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(backend.into_make_service()));
+
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.workflow_limits = Some("video:concurrency=1".to_string());
+    let state = routes::build_state(&config, ComfyUIClient::builder(url).retries(0).build());
+    let app = routes::build_router(state.clone());
+    let call = |method: &str, uri: &str, body: serde_json::Value| {
+        let app = app.clone();
+        let request = Request::builder().method(method).uri(uri).header("Content-Type", "application/json")
+            .body(Body::from(body.to_string())).unwrap();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        }
+    };
+
+    let body = json!({"workflow": "video", "prompt": {"3": {"class_type": "KSampler", "inputs": {"seed": 1}}}});
+    let first = call("POST", "/queue_prompt", body.clone()).await;
+    assert_eq!(first["prompt_id"], "limited-0");
+    assert!(first.get("held").is_none());
+
+    // The second waits for the first instead of being rejected.
+    let second = call("POST", "/queue_prompt", body.clone()).await;
+    assert_eq!(second["held"], true);
+    assert_eq!(second["position"], 0);
+    assert!(second["reason"].as_str().unwrap().contains("allows 1 concurrent run"));
+    let second_id = second["job_id"].as_str().unwrap().to_string();
+    comfyui_api_proxy::api::handlers::dispatch_held_prompts(&state).await;
+    assert_eq!(call("GET", &format!("/jobs/{}", second_id), json!(null)).await["state"], "submitted");
+
+    // Once ComfyUI has finished the first, its slot goes to the second.
+    finished.lock().unwrap().push("limited-0".to_string());
+    comfyui_api_proxy::api::handlers::dispatch_held_prompts(&state).await;
+    let first_job = call("GET", &format!("/jobs/{}", first["job_id"].as_str().unwrap()), json!(null)).await;
+    assert_eq!(first_job["state"], "completed");
+    assert_eq!(call("GET", &format!("/jobs/{}", second_id), json!(null)).await["state"], "queued");
+
+    // A prompt ComfyUI dropped without running fails and frees its slot too.
+    running.lock().unwrap().retain(|id| id != "limited-1");
+    let third = call("POST", "/queue_prompt", body).await;
+    assert_eq!(third["prompt_id"], "limited-2");
+    let second_job = call("GET", &format!("/jobs/{}", second_id), json!(null)).await;
+    assert_eq!(second_job["state"], "failed");
+    let timeline = call("GET", &format!("/jobs/{}/events", second_id), json!(null)).await;
+    assert!(timeline["events"].as_array().unwrap().iter().any(|e| e["event"] == "failed" && e["error"].as_str().unwrap().contains("no longer has the prompt")));
+}
+
+#[tokio::test]
+async fn test_prompts_comfyui_lost_stop_counting_as_queued() {
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use comfyui_api_proxy::{api::routes, config::Config, jobs::JobEventKind};
+    use tower::ServiceExt;
+
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.max_queued_per_key = Some(1);
+    let state = routes::build_state(&config, ComfyUIClient::builder(spawn_backend("fresh", 0)).retries(0).build());
+    let app = routes::build_router(state.clone());
+
+    // Sent before ComfyUI restarted: it's neither queued nor in history now.
+    let lost = {
+        let mut jobs = state.job_store.write().await;
+        let id = jobs.create(None);
+        jobs.record(&id, JobEventKind::SentToBackend { prompt_id: "gone".to_string() });
+        jobs.get_mut(&id).unwrap().events.iter_mut().for_each(|e| e.at_ms -= 1_000);
+        id
+    };
+    let request = Request::builder().method("POST").uri("/queue_prompt").header("Content-Type", "application/json")
+        .body(Body::from(json!({"prompt": {"3": {"class_type": "KSampler", "inputs": {"seed": 1}}}}).to_string())).unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let jobs = state.job_store.read().await;
+    assert!(jobs.get(&lost).unwrap().is_failed());
+}
+
+#[tokio::test]
+async fn test_job_list_syncs_finished_jobs_from_one_history_call() {
+    use axum::{body::Body, http::Request};
+    use comfyui_api_proxy::{api::routes, config::Config, jobs::JobEventKind};
+    use tower::ServiceExt;
+
+    // The backend's full `/history` has "listed-old"; `/history/<id>` doesn't.
+    let config = Config::new().expect("Failed to load configuration");
+    let state = routes::build_state(&config, ComfyUIClient::builder(spawn_backend("listed", 0)).retries(0).build());
+    let app = routes::build_router(state.clone());
+    let (finished, running) = {
+        let mut jobs = state.job_store.write().await;
+        let finished = jobs.create(None);
+        jobs.record(&finished, JobEventKind::SentToBackend { prompt_id: "listed-old".to_string() });
+        let running = jobs.create(None);
+        jobs.record(&running, JobEventKind::SentToBackend { prompt_id: "listed-new".to_string() });
+        (finished, running)
+    };
+    let response = app.oneshot(Request::builder().uri("/jobs").body(Body::empty()).unwrap()).await.unwrap();
+    assert!(response.status().is_success());
+    let jobs = state.job_store.read().await;
+    assert!(jobs.get(&finished).unwrap().history_synced);
+    assert!(!jobs.get(&running).unwrap().history_synced);
+}
+
+#[tokio::test]
+async fn test_outputs_zip_bundles_a_prompts_files() {
+    use axum::{body::Body, http::{Request, StatusCode}, routing::get, Json, Router};
+    use comfyui_api_proxy::{api::routes, config::Config};
+    use std::collections::HashMap;
+    use tower::ServiceExt;
+
+    let backend = Router::new()
+        .route("/history/:id", get(|| async {
+            Json(json!({"zipped": {"outputs": {
+                "9": {"images": [
+                    {"filename": "a.png", "subfolder": "", "type": "output"},
+                    {"filename": "b.png", "subfolder": "set", "type": "output"},
+                    {"filename": "preview.png", "subfolder": "", "type": "temp"}
+                ]}
+            }}}))
+        }))
+        .route("/view", get(|axum::extract::Query(q): axum::extract::Query<HashMap<String, String>>| async move {
+            format!("data of {}", q["filename"]).into_bytes()
+        }));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(backend.into_make_service()));
+
+    let config = Config::new().expect("Failed to load configuration");
+    let state = routes::build_state(&config, ComfyUIClient::builder(url).retries(0).build());
+    let app = routes::build_router(state);
+
+    let response = app.clone().oneshot(Request::builder().uri("/outputs/zipped.zip").body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "application/zip");
+    let zip = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert!(zip.starts_with(b"PK\x03\x04"));
+    // The end-of-central-directory record counts the entries; previews are left out.
+    let end = &zip[zip.len() - 22..];
+    assert!(end.starts_with(b"PK\x05\x06"));
+    assert_eq!(u16::from_le_bytes([end[10], end[11]]), 2);
+    let text = String::from_utf8_lossy(&zip);
+    assert!(text.contains("a.pngdata of a.png"));
+    assert!(text.contains("set/b.pngdata of b.png"));
+    assert!(!text.contains("preview.png"));
+
+    let response = app.oneshot(Request::builder().uri("/outputs/zipped").body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_deleted_outputs_go_to_the_trash_and_come_back() {
+    use axum::{body::Body, http::{Request, StatusCode}, routing::get, Json, Router};
+    use comfyui_api_proxy::{api::routes, comfyui::trash::OutputTrash, config::Config};
+    use tower::ServiceExt;
+
+    let backend = Router::new().route("/history/:id", get(|| async {
+        Json(json!({"binned": {"outputs": {"9": {"images": [
+            {"filename": "a.png", "subfolder": "", "type": "output"},
+            {"filename": "b.png", "subfolder": "set", "type": "output"},
+            {"filename": "preview.png", "subfolder": "", "type": "temp"}
+        ]}}}}))
+    }));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(backend.into_make_service()));
+    let dir = std::env::temp_dir().join(format!("outputs_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(dir.join("set")).unwrap();
+    std::fs::write(dir.join("a.png"), b"a").unwrap();
+    std::fs::write(dir.join("set/b.png"), b"b").unwrap();
+
+    let mut config = Config::new().expect("Failed to load configuration");
+    config.comfyui_output_dir = Some(dir.to_string_lossy().to_string());
+    let app = routes::build_router(routes::build_state(&config, ComfyUIClient::builder(url).retries(0).build()));
+    let call = |method: &str, uri: &str| {
+        let app = app.clone();
+        let request = Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            (status, serde_json::from_slice::<serde_json::Value>(&bytes).unwrap_or_default())
+        }
+    };
+
+    let (status, body) = call("DELETE", "/outputs/binned").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["trashed"]["files"], json!(["a.png", "set/b.png"]));
+    assert!(!dir.join("a.png").exists());
+    assert!(dir.join(".trash/binned/files/set/b.png").is_file());
+    assert_eq!(call("DELETE", "/outputs/binned").await.0, StatusCode::CONFLICT);
+    assert_eq!(call("GET", "/outputs/binned.zip").await.0, StatusCode::GONE);
+    let (_, trash) = call("GET", "/outputs/trash").await;
+    assert_eq!(trash["trash"][0]["prompt_id"], "binned");
+
+    let (status, body) = call("POST", "/outputs/binned/restore").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["restored"]["files"].as_array().unwrap().len(), 2);
+    assert_eq!(std::fs::read(dir.join("set/b.png")).unwrap(), b"b");
+    assert_eq!(call("GET", "/outputs/trash").await.1["total"], 0);
+    assert_eq!(call("POST", "/outputs/binned/restore").await.0, StatusCode::NOT_FOUND);
+
+    // Past the retention window, trashed files are gone for good.
+    let trash = OutputTrash::new(&dir, 0);
+    trash.trash("binned", None, &["a.png".to_string()], None, None, 1000).unwrap();
+    assert!(trash.restore("binned", 1000).is_err());
+    assert!(trash.list().is_empty());
+    assert!(matches!(trash.trash("../set", None, &["a.png".to_string()], None, None, 1000), Err(comfyui_api_proxy::error::AppError::BadRequest(_))));
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_trash_takes_harvested_copies_and_finishes_partial_restores() {
+    use comfyui_api_proxy::comfyui::trash::OutputTrash;
+
+    let root = std::env::temp_dir().join(format!("trash_{}", uuid::Uuid::new_v4()));
+    let (gpu0, gpu1, drive) = (root.join("gpu0"), root.join("gpu1"), root.join("drive"));
+    for dir in [&gpu0, &gpu1, &drive.join("images/p1")] {
+        std::fs::create_dir_all(dir).unwrap();
+    }
+    // Both backends have an output by that name; only the second ran the prompt.
+    std::fs::write(gpu0.join("out.png"), b"gpu0").unwrap();
+    std::fs::write(gpu1.join("out.png"), b"gpu1").unwrap();
+    std::fs::write(drive.join("images/p1/out.png"), b"copy").unwrap();
+    let trash = OutputTrash::for_backends(vec![gpu0.clone(), gpu1.clone()], 60_000).with_drive(&drive);
+
+    let entry = trash.trash("p1", Some(1), &["out.png".to_string()], None, None, 1000).unwrap();
+    assert_eq!(entry.drive_files, ["images/p1/out.png"]);
+    assert!(gpu0.join("out.png").is_file());
+    assert!(!gpu1.join("out.png").exists());
+    assert!(drive.join(".trash/p1/images/p1/out.png").is_file());
+    assert!(!drive.join("images/p1").exists());
+    let missing = trash.trash("p2", Some(0), &["gone.png".to_string()], None, None, 1000).unwrap_err();
+    assert!(missing.to_string().contains(&gpu0.display().to_string()));
+
+    // One file made it back before the restore failed; retrying finishes it.
+    std::fs::rename(gpu1.join(".trash/p1/files/out.png"), gpu1.join("out.png")).unwrap();
+    let restored = trash.restore("p1", 2000).unwrap();
+    assert_eq!(restored.files, ["out.png"]);
+    assert_eq!(std::fs::read(gpu1.join("out.png")).unwrap(), b"gpu1");
+    assert_eq!(std::fs::read(drive.join("images/p1/out.png")).unwrap(), b"copy");
+    assert!(trash.get("p1").is_none());
+    assert!(!drive.join(".trash/p1").exists());
+    std::fs::remove_dir_all(&root).ok();
+}
+
+#[tokio::test]
+async fn test_get_image_streams_with_image_headers() {
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use comfyui_api_proxy::{api::routes, config::Config};
+    use tower::ServiceExt;
+
+    let config = Config::new().expect("Failed to load configuration");
+    let client = ComfyUIClient::builder(spawn_backend("pixels", 0)).retries(0).build();
+    let app = routes::build_router(routes::build_state(&config, client));
+    let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+    // The backend sends application/octet-stream; the extension wins.
+    let response = app.clone().oneshot(get("/get_image?filename=out.png")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "image/png");
+    assert_eq!(response.headers()["content-length"], "6");
+    assert_eq!(response.headers()["cache-control"], "private, no-cache");
+    assert_eq!(&hyper::body::to_bytes(response.into_body()).await.unwrap()[..], b"pixels");
+
+    let response = app.oneshot(get("/get_image?filename=photo.JPG&type=input")).await.unwrap();
+    assert_eq!(response.headers()["content-type"], "image/jpeg");
+    assert_eq!(response.headers()["cache-control"], "private, no-cache");
+}
+
+#[tokio::test]
+async fn test_get_image_serves_ranges_and_revalidates() {
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use comfyui_api_proxy::{api::routes, config::Config};
+    use tower::ServiceExt;
+
+    let config = Config::new().expect("Failed to load configuration");
+    let client = ComfyUIClient::builder(spawn_backend("pixels", 0)).retries(0).build();
+    let app = routes::build_router(routes::build_state(&config, client));
+    let get = |headers: &[(&str, &str)]| {
+        let mut request = Request::builder().uri("/get_image?filename=clip.mp4");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(Body::empty()).unwrap()
+    };
+
+    let response = app.clone().oneshot(get(&[])).await.unwrap();
+    assert_eq!(response.headers()["accept-ranges"], "bytes");
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+
+    let response = app.clone().oneshot(get(&[("Range", "bytes=1-3")])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()["content-type"], "video/mp4");
+    assert_eq!(response.headers()["content-range"], "bytes 1-3/6");
+    assert_eq!(response.headers()["content-length"], "3");
+    assert_eq!(&hyper::body::to_bytes(response.into_body()).await.unwrap()[..], b"ixe");
+    let response = app.clone().oneshot(get(&[("Range", "bytes=-2")])).await.unwrap();
+    assert_eq!(&hyper::body::to_bytes(response.into_body()).await.unwrap()[..], b"ls");
+
+    let response = app.clone().oneshot(get(&[("Range", "bytes=6-")])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(response.headers()["content-range"], "bytes */6");
+    // A stale If-Range gets the whole file.
+    let response = app.clone().oneshot(get(&[("Range", "bytes=1-3"), ("If-Range", "\"other\"")])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app.oneshot(get(&[("If-None-Match", &etag)])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()["etag"], etag.as_str());
+    assert!(hyper::body::to_bytes(response.into_body()).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_get_image_passes_ranges_on_to_comfyui() {
+    use axum::{body::Body, http::{HeaderMap, Request, StatusCode}, response::IntoResponse, routing::get, Router};
+    use comfyui_api_proxy::{api::routes, config::Config};
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+
+    // A /view that answers ranges itself, and remembers which it was asked for.
+    let asked = Arc::new(Mutex::new(Vec::new()));
+    let seen = asked.clone();
+    let backend = Router::new().route("/view", get(move |headers: HeaderMap| {
+        let range = headers.get("range").map(|v| v.to_str().unwrap().to_string());
+        seen.lock().unwrap().push(range.clone());
+        async move {
+            match range.as_deref() {
+                Some("bytes=1-3") => (StatusCode::PARTIAL_CONTENT, [("content-range", "bytes 1-3/6")], b"ixe".to_vec()).into_response(),
+                Some(_) => (StatusCode::RANGE_NOT_SATISFIABLE, [("content-range", "bytes */6")], Vec::new()).into_response(),
+                None => b"pixels".to_vec().into_response(),
+            }
+        }
+    }));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(backend.into_make_service()));
+
+    let config = Config::new().expect("Failed to load configuration");
+    let app = routes::build_router(routes::build_state(&config, ComfyUIClient::builder(url).retries(0).build()));
+    let get = |headers: &[(&str, &str)]| {
+        let mut request = Request::builder().uri("/get_image?filename=clip.mp4");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(Body::empty()).unwrap()
+    };
+
+    let response = app.clone().oneshot(get(&[("Range", "bytes=1-3")])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()["content-range"], "bytes 1-3/6");
+    assert_eq!(response.headers()["content-length"], "3");
+    assert!(response.headers().contains_key("etag"));
+    assert_eq!(&hyper::body::to_bytes(response.into_body()).await.unwrap()[..], b"ixe");
+    assert_eq!(*asked.lock().unwrap(), vec![Some("bytes=1-3".to_string())]);
+
+    // ComfyUI's 416 is settled against the whole file.
+    asked.lock().unwrap().clear();
+    let response = app.clone().oneshot(get(&[("Range", "bytes=6-")])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(response.headers()["content-range"], "bytes */6");
+    assert_eq!(*asked.lock().unwrap(), vec![Some("bytes=6-".to_string()), None]);
+
+    // With If-Range, the whole file is fetched and the tag checked here.
+    asked.lock().unwrap().clear();
+    let response = app.oneshot(get(&[("Range", "bytes=1-3"), ("If-Range", "\"other\"")])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(&hyper::body::to_bytes(response.into_body()).await.unwrap()[..], b"pixels");
+    assert_eq!(*asked.lock().unwrap(), vec![None]);
+}
+
+#[tokio::test]
+async fn test_comfyctl_wait_follows_progress_and_downloads_outputs() {
+    let executing = |node: Option<&str>| json!({"type": "executing", "data": {"prompt_id": "watched", "node": node}});
+    let (url, play) = spawn_ws_backend("watched", vec![
+        json!({"type": "execution_start", "data": {"prompt_id": "watched"}}),
+        executing(Some("3")),
+        json!({"type": "progress", "data": {"prompt_id": "watched", "node": "3", "value": 5, "max": 10}}),
+        executing(None),
+    ]);
+    play.send(()).unwrap();
+    let dir = std::env::temp_dir().join(format!("watch_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let graph = dir.join("graph.json");
+    std::fs::write(&graph, json!({"3": {"class_type": "KSampler", "inputs": {"seed": 1}}}).to_string()).unwrap();
+    let out_dir = dir.join("out");
+
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_comfyctl"))
+        .args(["--comfyui-url", &url, "prompt", "queue", "--wait", "--timeout", "10", "--file"])
+        .arg(&graph)
+        .arg("--out-dir")
+        .arg(&out_dir)
+        .output()
+        .await
+        .unwrap();
+    let (stdout, stderr) = (String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+    assert_eq!(output.status.code(), Some(0), "{}", stderr);
+    // Progress came over the websocket rather than from polling history.
+    assert!(!stderr.contains("polling history"), "{}", stderr);
+    assert!(stderr.contains("node 3 [###############...............] 5/10"), "{}", stderr);
+    assert!(stdout.starts_with("prompt_id=watched"), "{}", stdout);
+    assert!(stdout.contains(&format!("Saved {} (6 bytes)", out_dir.join("out.png").display())), "{}", stdout);
+    assert_eq!(std::fs::read(out_dir.join("out.png")).unwrap(), b"pixels");
+    std::fs::remove_dir_all(&dir).ok();
+}
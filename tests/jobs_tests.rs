@@ -0,0 +1,754 @@
+use comfyui_api_proxy::jobs::events::events_from_history_entry;
+use comfyui_api_proxy::jobs::{history_records, JobEvent, JobEventKind, JobState, JobStore};
+use serde_json::json;
+
+#[test]
+fn test_job_store_records_timeline() {
+    let mut store = JobStore::new();
+    let id = store.create(Some("sdxlapi".to_string()));
+    store.record(&id, JobEventKind::SentToBackend { prompt_id: "abc".to_string() });
+
+    let job = store.get(&id).unwrap();
+    assert_eq!(job.prompt_id.as_deref(), Some("abc"));
+    assert_eq!(job.events.len(), 2);
+    assert!(matches!(job.events[0].kind, JobEventKind::Submitted { .. }));
+    assert!(store.find_by_prompt_id("abc").is_some());
+}
+
+#[test]
+fn test_job_store_fails_prompts_lost_before_sending() {
+    let mut store = JobStore::new();
+    let sent = store.create(None);
+    store.record(&sent, JobEventKind::SentToBackend { prompt_id: "abc".to_string() });
+    let mine = store.create(None);
+    store.record(&mine, JobEventKind::Held { instance: "a".to_string() });
+    let theirs = store.create(None);
+    store.record(&theirs, JobEventKind::Held { instance: "b".to_string() });
+
+    assert_eq!(store.holders(), ["a".to_string(), "b".to_string()].into_iter().collect());
+    // Other replicas may still send their own held prompts.
+    assert_eq!(store.fail_held(&["a"], "restarted"), 1);
+    assert_eq!(store.get(&mine).unwrap().state(), JobState::Failed);
+    assert_eq!(store.get(&theirs).unwrap().state(), JobState::Submitted);
+    assert_eq!(store.holders(), ["b".to_string()].into_iter().collect());
+    assert_eq!(store.get(&sent).unwrap().state(), JobState::Queued);
+}
+
+#[test]
+fn test_events_from_history_entry() {
+    let entry = json!({
+        "outputs": {
+            "9": {"images": [{"filename": "Derivata_00001_.png", "subfolder": "", "type": "output"}]}
+        },
+        "status": {
+            "status_str": "success",
+            "completed": true,
+            "messages": [
+                ["execution_start", {"prompt_id": "abc", "timestamp": 1000}],
+                ["execution_cached", {"nodes": ["4"], "prompt_id": "abc", "timestamp": 1001}],
+                ["execution_success", {"prompt_id": "abc", "timestamp": 5000}]
+            ]
+        }
+    });
+    let events = events_from_history_entry(&entry);
+    let kinds: Vec<_> = events.iter().map(|e| e.kind.clone()).collect();
+    assert_eq!(kinds, vec![
+        JobEventKind::ExecutionStarted,
+        JobEventKind::NodeCached { node: "4".to_string() },
+        JobEventKind::OutputSaved {
+            node: "9".to_string(),
+            filename: "Derivata_00001_.png".to_string(),
+            subfolder: None,
+            folder_type: Some("output".to_string()),
+        },
+        JobEventKind::Completed,
+    ]);
+    assert_eq!(events.last().unwrap().at_ms, 5000);
+}
+
+#[test]
+fn test_events_from_legacy_history_entry() {
+    // Older ComfyUI: no status at all, or a status without messages.
+    let entry = json!({"outputs": {"9": {"images": [{"filename": "old.png", "type": "output"}]}}});
+    let kinds: Vec<_> = events_from_history_entry(&entry).into_iter().map(|e| e.kind).collect();
+    assert_eq!(kinds.last(), Some(&JobEventKind::Completed));
+
+    let entry = json!({"outputs": {}, "status": {"status_str": "error", "completed": false}});
+    let kinds: Vec<_> = events_from_history_entry(&entry).into_iter().map(|e| e.kind).collect();
+    assert_eq!(kinds, vec![JobEventKind::Failed { node: None, error: "execution failed".to_string() }]);
+}
+
+#[test]
+fn test_node_timings_and_aggregate() {
+    use comfyui_api_proxy::jobs::timing::{aggregate_node_stats, node_timings};
+    use comfyui_api_proxy::jobs::JobEvent;
+
+    let mut store = JobStore::new();
+    let id = store.create(Some("sdxlapi".to_string()));
+    store.set_node_classes(&id, &json!({
+        "2": {"class_type": "KSampler", "inputs": {}},
+        "6": {"class_type": "VAEDecode", "inputs": {}}
+    }));
+    store.merge_events(&id, vec![
+        JobEvent::at(1_000, JobEventKind::NodeStarted { node: "2".to_string() }),
+        JobEvent::at(4_000, JobEventKind::NodeStarted { node: "6".to_string() }),
+        JobEvent::at(4_500, JobEventKind::Completed),
+    ]);
+
+    let timings = node_timings(store.get(&id).unwrap());
+    assert_eq!(timings.len(), 2);
+    assert_eq!(timings[0].duration_ms, 3_000);
+    assert_eq!(timings[0].class_type.as_deref(), Some("KSampler"));
+    assert_eq!(timings[1].duration_ms, 500);
+
+    let stats = aggregate_node_stats(store.iter(), None);
+    assert_eq!(stats[0].key, "KSampler");
+    assert_eq!(stats[0].total_ms, 3_000);
+}
+
+#[test]
+fn test_expected_outputs_and_batch_limits() {
+    use comfyui_api_proxy::jobs::batch::{expected_outputs, BatchLimits};
+
+    let data = std::fs::read_to_string("prompts/sdxlapi.json").unwrap();
+    let mut graph: serde_json::Value = serde_json::from_str(&data).unwrap();
+    assert_eq!(expected_outputs(&graph), 1);
+    graph["5"]["inputs"]["batch_size"] = json!(4);
+    assert_eq!(expected_outputs(&graph), 4);
+
+    let fixed = BatchLimits { max_batch_size: Some(2), ..BatchLimits::default() };
+    assert!(fixed.check(&graph).is_err());
+
+    // 24 GB at ~1 MP (1024x1024) and 2 GB/MP allows 11 images.
+    let vram = BatchLimits { vram_gb: Some(24.0), ..BatchLimits::default() };
+    assert_eq!(vram.max_for(Some(1024), Some(1024)), Some(11));
+    assert!(vram.check(&graph).is_ok());
+}
+
+#[test]
+fn test_job_outputs_in_reported_order() {
+    use comfyui_api_proxy::jobs::OutputFile;
+
+    let mut store = JobStore::new();
+    let id = store.create(None);
+    let entry = json!({
+        "outputs": {
+            "9": {"images": [
+                {"filename": "a_00001_.png", "subfolder": "team", "type": "output"},
+                {"filename": "a_00002_.png", "subfolder": "team", "type": "output"}
+            ]}
+        },
+        "status": {"messages": [["execution_success", {"timestamp": 10}]]}
+    });
+    store.merge_events(&id, events_from_history_entry(&entry));
+    let job = store.get(&id).unwrap();
+    assert!(job.is_finished() && !job.is_failed());
+    assert_eq!(job.outputs()[1], OutputFile {
+        node: "9".to_string(),
+        filename: "a_00002_.png".to_string(),
+        subfolder: Some("team".to_string()),
+        folder_type: Some("output".to_string()),
+        caption: None,
+    });
+
+    store.record(&id, JobEventKind::Captioned { node: "9".to_string(), filename: "a_00002_.png".to_string(), caption: "a red fox".to_string() });
+    let outputs = store.get(&id).unwrap().outputs();
+    assert_eq!(outputs[1].caption.as_deref(), Some("a red fox"));
+    assert_eq!(outputs[0].caption, None);
+
+    store.record(&id, JobEventKind::Failed { node: None, error: "boom".to_string() });
+    assert!(store.get(&id).unwrap().is_failed());
+}
+
+#[test]
+fn test_job_state_follows_timeline() {
+    use comfyui_api_proxy::jobs::JobState;
+
+    let mut store = JobStore::new();
+    let id = store.create(None);
+    assert_eq!(store.get(&id).unwrap().state(), JobState::Submitted);
+    store.record(&id, JobEventKind::SentToBackend { prompt_id: "p".to_string() });
+    assert_eq!(store.get(&id).unwrap().state(), JobState::Queued);
+    store.record(&id, JobEventKind::ExecutionStarted);
+    assert_eq!(store.get(&id).unwrap().state(), JobState::Running);
+    store.record(&id, JobEventKind::Completed);
+    assert_eq!(store.get(&id).unwrap().state(), JobState::Completed);
+    assert!(JobState::Completed.is_terminal());
+    assert_eq!(JobState::parse("running"), Some(JobState::Running));
+    assert_eq!(JobState::parse("done"), None);
+}
+
+#[test]
+fn test_history_records_flatten_entries() {
+    let history = json!({
+        "p2": {
+            "prompt": [7, "p2", {}, {}, []],
+            "status": {"status_str": "error", "completed": false, "messages": [
+                ["execution_start", {"prompt_id": "p2", "timestamp": 200}],
+                ["execution_error", {"prompt_id": "p2", "node_id": "3", "exception_message": "OOM ", "timestamp": 250}]
+            ]},
+            "outputs": {}
+        },
+        "p1": {
+            "prompt": [6, "p1", {}, {}, []],
+            "status": {"status_str": "success", "completed": true, "messages": [
+                ["execution_start", {"prompt_id": "p1", "timestamp": 100}],
+                ["execution_success", {"prompt_id": "p1", "timestamp": 150}]
+            ]},
+            "outputs": {"9": {"images": [{"filename": "a.png", "subfolder": "", "type": "output"}]}}
+        }
+    });
+    let records = history_records(&history);
+    assert_eq!(records.iter().map(|r| r.prompt_id.as_str()).collect::<Vec<_>>(), vec!["p1", "p2"]);
+
+    assert_eq!(records[0].state, JobState::Completed);
+    assert_eq!((records[0].started_at_ms, records[0].finished_at_ms), (Some(100), Some(150)));
+    assert_eq!(records[0].outputs[0].filename, "a.png");
+    assert_eq!(records[0].outputs[0].subfolder, None);
+
+    assert_eq!(records[1].state, JobState::Failed);
+    assert_eq!(records[1].error.as_deref(), Some("OOM"));
+    assert_eq!(records[1].finished_at_ms, Some(250));
+}
+
+#[test]
+fn test_import_history_entry() {
+    let entry = json!({
+        "prompt": [3, "old", {"9": {"class_type": "SaveImage", "inputs": {"images": ["8", 0]}}}, {}, ["9"]],
+        "status": {"completed": true, "messages": [
+            ["execution_start", {"prompt_id": "old", "timestamp": 100}],
+            ["execution_success", {"prompt_id": "old", "timestamp": 150}]
+        ]},
+        "outputs": {"9": {"images": [{"filename": "a.png", "type": "output"}, {"filename": "b.png", "type": "output"}]}}
+    });
+    let mut store = JobStore::new();
+    let id = store.import_history_entry("old", &entry, Some("sdxl".to_string()), None).unwrap();
+    let job = store.get(&id).unwrap();
+    assert_eq!(job.prompt_id.as_deref(), Some("old"));
+    assert_eq!(job.created_at_ms, 100);
+    assert_eq!(job.state(), JobState::Completed);
+    assert_eq!(job.outputs().len(), 2);
+    assert_eq!(job.expected_outputs, 2);
+    assert!(job.history_synced);
+
+    // Importing the same prompt again is a no-op.
+    assert_eq!(store.import_history_entry("old", &entry, None, None), None);
+}
+
+#[test]
+fn test_assemble_frames_into_animation() {
+    use comfyui_api_proxy::utils::animation::{assemble, AnimationFormat, AnimationOptions};
+    use image::AnimationDecoder;
+
+    let frame = |shade: u8, size: u32| {
+        let mut data = Vec::new();
+        image::RgbImage::from_pixel(size, size, image::Rgb([shade, shade, shade]))
+            .write_to(&mut std::io::Cursor::new(&mut data), image::ImageOutputFormat::Png)
+            .unwrap();
+        data
+    };
+    // The odd-sized last frame is scaled to match the first.
+    let frames = vec![frame(0, 8), frame(128, 8), frame(255, 4)];
+
+    let gif = assemble(&frames, AnimationOptions { format: AnimationFormat::Gif, fps: 10, looped: true }).unwrap();
+    let decoded = image::codecs::gif::GifDecoder::new(std::io::Cursor::new(&gif)).unwrap()
+        .into_frames().collect_frames().unwrap();
+    assert_eq!(decoded.len(), 3);
+    assert_eq!(decoded[2].buffer().dimensions(), (8, 8));
+    assert_eq!(decoded[0].delay().numer_denom_ms(), (100, 1));
+
+    let apng = assemble(&frames, AnimationOptions { format: AnimationFormat::Apng, fps: 4, looped: false }).unwrap();
+    let reader = png::Decoder::new(std::io::Cursor::new(&apng)).read_info().unwrap();
+    let control = reader.info().animation_control.unwrap();
+    assert_eq!((control.num_frames, control.num_plays), (3, 1));
+
+    assert!(assemble(&frames[..1], AnimationOptions { format: AnimationFormat::Gif, fps: 8, looped: true }).is_err());
+    assert_eq!(AnimationFormat::parse("MP4"), Some(AnimationFormat::Mp4));
+    assert_eq!(AnimationFormat::Mp4.is_supported(), cfg!(feature = "ffmpeg"));
+}
+
+#[test]
+fn test_split_grid_into_cells() {
+    use comfyui_api_proxy::utils::grid_split::{cell_filename, GridSplit};
+
+    // A 2x2 grid of 5x3 cells, each a different shade.
+    let grid = image::RgbImage::from_fn(10, 6, |x, y| {
+        let shade = ((y / 3) * 2 + x / 5) as u8 * 60;
+        image::Rgb([shade, shade, shade])
+    });
+    let mut data = Vec::new();
+    grid.write_to(&mut std::io::Cursor::new(&mut data), image::ImageOutputFormat::Png).unwrap();
+
+    let split = GridSplit::from_payload(&json!({"split_grid": {"rows": 2, "cols": 2}})).unwrap().unwrap();
+    let cells = split.split(&data).unwrap();
+    assert_eq!(cells.len(), 4);
+    for (i, cell) in cells.iter().enumerate() {
+        let cell = image::load_from_memory(cell).unwrap().to_rgb8();
+        assert_eq!(cell.dimensions(), (5, 3));
+        assert!(cell.pixels().all(|p| p.0 == [i as u8 * 60; 3]));
+    }
+    assert_eq!(cell_filename("Derivata_00001_.png", 1, 0), "Derivata_00001__r2c1.png");
+
+    assert_eq!(GridSplit::from_payload(&json!({})).unwrap(), None);
+    assert!(GridSplit::from_payload(&json!({"split_grid": {"rows": 1, "cols": 1}})).is_err());
+    assert!(GridSplit::from_payload(&json!({"split_grid": {"rows": 2}})).is_err());
+    assert!(GridSplit { rows: 16, cols: 16 }.split(&data).is_err());
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_job_store_persists_to_sqlite() {
+    let path = std::env::temp_dir().join(format!("jobs_{}.db", uuid::Uuid::new_v4()));
+    let path = path.to_string_lossy().to_string();
+    let id = {
+        let mut store = JobStore::open(&path).unwrap();
+        let id = store.create(Some("sdxlapi".to_string()));
+        store.get_mut(&id).unwrap().request = Some(json!({"params": {"seed": 7}}));
+        store.save(&id);
+        store.record(&id, JobEventKind::SentToBackend { prompt_id: "abc".to_string() });
+        store.record(&id, JobEventKind::OutputSaved {
+            node: "9".to_string(),
+            filename: "out.png".to_string(),
+            subfolder: None,
+            folder_type: Some("output".to_string()),
+        });
+        store.flush().await;
+        id
+    };
+
+    let store = JobStore::open(&path).unwrap();
+    let job = store.get(&id).unwrap();
+    assert_eq!(job.prompt_id.as_deref(), Some("abc"));
+    assert_eq!(job.workflow.as_deref(), Some("sdxlapi"));
+    assert_eq!(job.request, Some(json!({"params": {"seed": 7}})));
+    assert_eq!(job.events.len(), 3);
+    assert_eq!(job.outputs()[0].filename, "out.png");
+    assert_eq!(job.state(), JobState::Queued);
+    for suffix in ["", "-wal", "-shm"] {
+        std::fs::remove_file(format!("{}{}", path, suffix)).ok();
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_sqlite_replaces_stored_api_keys_with_ids() {
+    use comfyui_api_proxy::auth::keys::key_id;
+
+    let path = std::env::temp_dir().join(format!("jobs_{}.db", uuid::Uuid::new_v4()));
+    let path = path.to_string_lossy().to_string();
+    let id = {
+        let mut store = JobStore::open(&path).unwrap();
+        let id = store.create(None);
+        store.flush().await;
+        id
+    };
+    // A database from before key ids, holding the raw key.
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    conn.execute_batch("ALTER TABLE jobs ADD COLUMN api_key TEXT").unwrap();
+    conn.execute("UPDATE jobs SET api_key = 'secret', key_id = NULL WHERE id = ?1", [&id]).unwrap();
+    drop(conn);
+
+    let store = JobStore::open(&path).unwrap();
+    assert_eq!(store.get(&id).unwrap().key_id, Some(key_id("secret")));
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let columns: Vec<String> = conn.prepare("SELECT name FROM pragma_table_info('jobs')").unwrap()
+        .query_map([], |row| row.get(0)).unwrap()
+        .collect::<Result<_, _>>().unwrap();
+    assert!(columns.contains(&"key_id".to_string()));
+    assert!(!columns.contains(&"api_key".to_string()));
+    for suffix in ["", "-wal", "-shm"] {
+        std::fs::remove_file(format!("{}{}", path, suffix)).ok();
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_sqlite_saves_merge_replica_changes() {
+    let path = std::env::temp_dir().join(format!("jobs_{}.db", uuid::Uuid::new_v4()));
+    let path = path.to_string_lossy().to_string();
+    let mut a = JobStore::open(&path).unwrap();
+    let id = a.create(None);
+    a.flush().await;
+    let mut b = JobStore::open(&path).unwrap();
+    a.record(&id, JobEventKind::SentToBackend { prompt_id: "abc".to_string() });
+    b.record(&id, JobEventKind::WebhookDelivered { url: "http://hook".to_string() });
+    a.flush().await;
+    b.flush().await;
+
+    let job = JobStore::open(&path).unwrap().get(&id).cloned().unwrap();
+    assert_eq!(job.events.len(), 3);
+    assert_eq!(job.prompt_id.as_deref(), Some("abc"));
+    for suffix in ["", "-wal", "-shm"] {
+        std::fs::remove_file(format!("{}{}", path, suffix)).ok();
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_sqlite_leases_go_to_one_replica() {
+    let path = std::env::temp_dir().join(format!("jobs_{}.db", uuid::Uuid::new_v4()));
+    let path = path.to_string_lossy().to_string();
+    let a = JobStore::open(&path).unwrap().leases();
+    let b = JobStore::open(&path).unwrap().leases();
+    assert!(a.claim("harvest", "a", 60_000).await);
+    assert!(!b.claim("harvest", "b", 60_000).await);
+    // Renewing keeps it; other leases are independent.
+    assert!(a.claim("harvest", "a", 60_000).await);
+    assert!(b.claim("other", "b", 60_000).await);
+    // An expired lease can be taken over.
+    assert!(a.claim("short", "a", 0).await);
+    assert!(b.claim("short", "b", 60_000).await);
+    assert!(!a.claim("short", "a", 60_000).await);
+    // Without a backend there's no one to share with.
+    assert!(JobStore::new().leases().claim("harvest", "c", 60_000).await);
+    for suffix in ["", "-wal", "-shm"] {
+        std::fs::remove_file(format!("{}{}", path, suffix)).ok();
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_sqlite_leases_are_renewed_while_held() {
+    let path = std::env::temp_dir().join(format!("jobs_{}.db", uuid::Uuid::new_v4()));
+    let path = path.to_string_lossy().to_string();
+    let a = JobStore::open(&path).unwrap().leases();
+    let b = JobStore::open(&path).unwrap().leases();
+    // Work outlasting the TTL keeps the lease, and no one else gets it.
+    let work = async {
+        tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+        "done"
+    };
+    let other = async {
+        tokio::time::sleep(std::time::Duration::from_millis(400)).await;
+        (b.claim("harvest", "b", 200).await, b.hold("harvest", "b", 200, async {}).await)
+    };
+    let (held, (claimed, ran)) = tokio::join!(a.hold("harvest", "a", 200, work), other);
+    assert_eq!(held, Some("done"));
+    assert!(!claimed);
+    assert_eq!(ran, None);
+    for suffix in ["", "-wal", "-shm"] {
+        std::fs::remove_file(format!("{}{}", path, suffix)).ok();
+    }
+}
+
+/// Runs against the Redis server at `REDIS_TEST_URL`, when set.
+#[cfg(feature = "redis")]
+#[tokio::test]
+async fn test_redis_shares_jobs_and_leases() {
+    let Ok(url) = std::env::var("REDIS_TEST_URL") else { return };
+    let prefix = format!("test-{}", uuid::Uuid::new_v4());
+    let mut a = JobStore::open_redis(&url, &prefix).unwrap();
+    let id = a.create(Some("sdxlapi".to_string()));
+    a.flush().await;
+    let mut b = JobStore::open_redis(&url, &prefix).unwrap();
+    a.record(&id, JobEventKind::SentToBackend { prompt_id: "abc".to_string() });
+    b.record(&id, JobEventKind::WebhookDelivered { url: "http://hook".to_string() });
+    a.flush().await;
+    b.flush().await;
+    let job = JobStore::open_redis(&url, &prefix).unwrap().get(&id).cloned().unwrap();
+    assert_eq!(job.events.len(), 3);
+    assert_eq!(job.prompt_id.as_deref(), Some("abc"));
+
+    let (a, b) = (a.leases(), b.leases());
+    assert!(a.claim("harvest", "a", 60_000).await);
+    assert!(!b.claim("harvest", "b", 60_000).await);
+    assert!(a.claim("harvest", "a", 60_000).await);
+    assert!(a.claim("short", "a", 1).await);
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    assert!(b.claim("short", "b", 60_000).await);
+    assert!(!a.claim("short", "a", 60_000).await);
+}
+
+#[test]
+fn test_workflow_limits() {
+    use comfyui_api_proxy::jobs::limits::WorkflowLimits;
+
+    let limits = WorkflowLimits::parse("video:concurrency=1, train:cooldown=30s, train:concurrency=2").unwrap();
+    assert_eq!(limits.rule("train").unwrap().cooldown_ms, Some(30_000));
+    assert_eq!(limits.rule("train").unwrap().max_concurrent, Some(2));
+    assert!(WorkflowLimits::parse("video:concurrency=0").is_err());
+    assert!(WorkflowLimits::parse("video:parallel=1").is_err());
+    assert!(WorkflowLimits::parse("concurrency=1").is_err());
+
+    let mut store = JobStore::new();
+    let other = store.create(Some("sdxl".to_string()));
+    let now = store.get(&other).unwrap().created_at_ms;
+    assert!(limits.check("video", store.iter(), now).is_ok());
+
+    // An unfinished run blocks the next one until it completes.
+    let video = store.create(Some("video".to_string()));
+    store.record(&video, JobEventKind::SentToBackend { prompt_id: "v1".to_string() });
+    let busy = limits.check("video", store.iter(), now).unwrap_err();
+    assert_eq!(busy.retry_at_ms, None);
+    store.record(&video, JobEventKind::Completed);
+    assert!(limits.check("video", store.iter(), now).is_ok());
+
+    // Cooldowns run from the last submission, finished or not.
+    let train = store.create(Some("train".to_string()));
+    store.record(&train, JobEventKind::SentToBackend { prompt_id: "t1".to_string() });
+    store.record(&train, JobEventKind::Completed);
+    let started = store.get(&train).unwrap().sent_at_ms().unwrap();
+    let busy = limits.check("train", store.iter(), started + 1_000).unwrap_err();
+    assert_eq!(busy.retry_at_ms, Some(started + 30_000));
+    assert!(limits.check("train", store.iter(), started + 30_000).is_ok());
+
+    // Jobs that never reached ComfyUI don't count, held or failed.
+    let rejected = store.create(Some("video".to_string()));
+    store.record(&rejected, JobEventKind::Failed { node: None, error: "bad params".to_string() });
+    let held = store.create(Some("video".to_string()));
+    store.record(&held, JobEventKind::Held { instance: "a".to_string() });
+    assert!(limits.check("video", store.iter(), now).is_ok());
+    assert!(limits.allows("video", 1, None, now).is_err());
+    assert!(limits.allows("sdxl", 5, Some(now), now).is_ok());
+}
+
+#[test]
+fn test_cost_model() {
+    use comfyui_api_proxy::jobs::cost::{megapixel_steps, CostModel};
+
+    let graph = json!({
+        "3": {"class_type": "KSampler", "inputs": {"steps": 20, "latent_image": ["5", 0], "model": ["4", 0]}},
+        "5": {"class_type": "EmptyLatentImage", "inputs": {"width": 1000, "height": 500, "batch_size": 2}},
+        "6": {"class_type": "KSamplerAdvanced", "inputs": {"steps": 30, "start_at_step": 20, "end_at_step": 10000, "latent_image": ["3", 0]}},
+        "9": {"class_type": "SaveImage", "inputs": {"images": ["8", 0]}}
+    });
+    // 0.5 MP × 2 images × (20 + 10) steps.
+    assert!((megapixel_steps(&graph) - 30.0).abs() < 1e-9);
+
+    let model = CostModel { per_megapixel_step: Some(0.01), per_gpu_second: Some(0.002), currency: "USD".to_string() };
+    assert!((model.cost(30.0, 10_000).unwrap() - 0.32).abs() < 1e-9);
+    assert_eq!(CostModel::default().cost(30.0, 10_000), None);
+
+    // Estimates use the workflow's average execution time so far.
+    let mut store = JobStore::new();
+    let id = store.create(Some("sdxl".to_string()));
+    let entry = json!({"status": {"completed": true, "messages": [
+        ["execution_start", {"prompt_id": "p", "timestamp": 1_000}],
+        ["execution_success", {"prompt_id": "p", "timestamp": 5_000}]
+    ]}});
+    store.merge_events(&id, events_from_history_entry(&entry));
+    let estimate = model.estimate(0.0, Some("sdxl"), store.iter()).unwrap();
+    assert!((estimate - 0.008).abs() < 1e-9);
+    assert_eq!(model.estimate(0.0, Some("other"), store.iter()), Some(0.0));
+    assert!((model.actual(store.get(&id).unwrap()).unwrap() - 0.008).abs() < 1e-9);
+}
+
+#[test]
+fn test_job_state_changes_are_announced() {
+    use comfyui_api_proxy::events::{EventBus, ProxyEventKind};
+
+    let bus = EventBus::new();
+    let mut receiver = bus.subscribe();
+    let mut store = JobStore::new();
+    store.set_event_bus(bus);
+
+    let id = store.create(Some("sdxlapi".to_string()));
+    // Owner details are filled in before the first announcement.
+    store.get_mut(&id).unwrap().tenant = Some("acme".to_string());
+    store.save(&id);
+    store.record(&id, JobEventKind::SentToBackend { prompt_id: "p1".to_string() });
+    // Same state again: nothing new to announce.
+    store.save(&id);
+    store.record(&id, JobEventKind::Failed { node: None, error: "boom".to_string() });
+
+    let mut events = Vec::new();
+    while let Ok(event) = receiver.try_recv() {
+        events.push(event);
+    }
+    let states: Vec<(JobState, Option<JobState>)> = events.iter()
+        .map(|e| match &e.kind {
+            ProxyEventKind::JobState { state, previous, .. } => (*state, *previous),
+            other => panic!("unexpected event {:?}", other),
+        })
+        .collect();
+    assert_eq!(states, vec![
+        (JobState::Submitted, None),
+        (JobState::Queued, Some(JobState::Submitted)),
+        (JobState::Failed, Some(JobState::Queued)),
+    ]);
+    assert!(events.iter().all(|e| e.visible_to(None) && e.visible_to(Some("acme")) && !e.visible_to(Some("other"))));
+    let json = serde_json::to_value(&events[2]).unwrap();
+    assert_eq!(json["type"], "job_state");
+    assert_eq!(json["prompt_id"], "p1");
+    assert_eq!(json["state"], "failed");
+}
+
+#[tokio::test]
+async fn test_replicas_share_jobs_through_backend() {
+    use async_trait::async_trait;
+    use comfyui_api_proxy::jobs::{Job, JobBackend, JobRecord};
+    use comfyui_api_proxy::utils::time::now_ms;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::RwLock;
+
+    /// Job id -> (saved at, JSON record).
+    type Saved = HashMap<String, (u64, String)>;
+
+    /// Stands in for Redis.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Saved>>);
+
+    impl Shared {
+        fn jobs(&self, since_ms: u64) -> Vec<Job> {
+            self.0.lock().unwrap().values()
+                .filter(|(at, _)| *at >= since_ms)
+                .map(|(_, json)| Job::from(serde_json::from_str::<JobRecord>(json).unwrap()))
+                .collect()
+        }
+    }
+
+    #[async_trait]
+    impl JobBackend for Shared {
+        fn name(&self) -> &'static str {
+            "shared"
+        }
+        fn load(&self) -> Result<Vec<Job>, String> {
+            Ok(self.jobs(0))
+        }
+        async fn save(&self, job: &Job) -> Result<(), String> {
+            let mut saved = self.0.lock().unwrap();
+            let mut job = job.clone();
+            if let Some((_, json)) = saved.get(&job.id) {
+                job.merge(Job::from(serde_json::from_str::<JobRecord>(json).unwrap()));
+            }
+            let json = serde_json::to_string(&JobRecord::from(&job)).unwrap();
+            saved.insert(job.id.clone(), (now_ms(), json));
+            Ok(())
+        }
+        async fn changed_since(&self, since_ms: u64) -> Result<Vec<Job>, String> {
+            Ok(self.jobs(since_ms))
+        }
+        async fn claim(&self, _name: &str, _owner: &str, _ttl_ms: u64) -> Result<bool, String> {
+            Ok(true)
+        }
+    }
+
+    let shared = Shared::default();
+    let a = RwLock::new(JobStore::with_backend(Arc::new(shared.clone())).unwrap());
+    let b = RwLock::new(JobStore::with_backend(Arc::new(shared.clone())).unwrap());
+    assert!(a.read().await.has_backend());
+
+    let id = {
+        let mut a = a.write().await;
+        let id = a.create(Some("sdxlapi".to_string()));
+        a.get_mut(&id).unwrap().tenant = Some("acme".to_string());
+        a.save(&id);
+        a.record(&id, JobEventKind::SentToBackend { prompt_id: "p1".to_string() });
+        id
+    };
+    a.read().await.flush().await;
+
+    assert!(b.read().await.get(&id).is_none());
+    assert!(JobStore::refresh(&b).await.read >= 1);
+    let job = b.read().await.get(&id).cloned().unwrap();
+    assert_eq!(job.state(), JobState::Queued);
+    assert_eq!(job.tenant.as_deref(), Some("acme"));
+    assert_eq!(job.prompt_id.as_deref(), Some("p1"));
+
+    // Changes flow the other way too, and saving the same job on both
+    // replicas keeps both changes.
+    b.write().await.record(&id, JobEventKind::Failed { node: None, error: "boom".to_string() });
+    a.write().await.record(&id, JobEventKind::WebhookDelivered { url: "http://hook".to_string() });
+    b.read().await.flush().await;
+    a.read().await.flush().await;
+    JobStore::refresh(&a).await;
+    let job = a.read().await.get(&id).cloned().unwrap();
+    assert_eq!(job.state(), JobState::Failed);
+    assert!(job.events.iter().any(|e| matches!(e.kind, JobEventKind::WebhookDelivered { .. })));
+
+    // A new replica loads everything.
+    let c = JobStore::with_backend(Arc::new(shared)).unwrap();
+    assert_eq!(c.get(&id).unwrap().events, job.events);
+
+    // A job another replica finished syncing is reported once, for its usage.
+    {
+        let mut b = b.write().await;
+        b.get_mut(&id).unwrap().history_synced = true;
+        b.save(&id);
+    }
+    b.read().await.flush().await;
+    let refreshed = JobStore::refresh(&a).await;
+    assert_eq!(refreshed.synced_elsewhere.iter().map(|j| j.id.as_str()).collect::<Vec<_>>(), vec![id.as_str()]);
+    assert!(JobStore::refresh(&a).await.synced_elsewhere.is_empty());
+}
+
+#[test]
+fn test_job_records_hold_key_ids_not_keys() {
+    use comfyui_api_proxy::auth::keys::key_id;
+    use comfyui_api_proxy::jobs::{Job, JobRecord};
+
+    // Records written before key ids carried the raw key.
+    let mut store = JobStore::new();
+    let id = store.create(None);
+    let mut legacy = serde_json::to_value(JobRecord::from(store.get(&id).unwrap())).unwrap();
+    legacy["api_key"] = json!("secret");
+    legacy.as_object_mut().unwrap().remove("key_id");
+    let job = Job::from(serde_json::from_value::<JobRecord>(legacy).unwrap());
+    assert_eq!(job.key_id, Some(key_id("secret")));
+
+    let written = serde_json::to_string(&JobRecord::from(&job)).unwrap();
+    assert!(!written.contains("secret") && !written.contains("api_key"), "{}", written);
+}
+
+#[test]
+fn test_merging_job_copies_keeps_both_timelines() {
+    let mut store = JobStore::new();
+    let id = store.create(Some("sdxlapi".to_string()));
+    let mut theirs = store.get(&id).cloned().unwrap();
+    store.record(&id, JobEventKind::SentToBackend { prompt_id: "p1".to_string() });
+    theirs.events.push(JobEvent::at(theirs.created_at_ms + 1, JobEventKind::Failed { node: None, error: "boom".to_string() }));
+    theirs.tenant = Some("acme".to_string());
+
+    let mut job = store.get(&id).cloned().unwrap();
+    job.merge(theirs.clone());
+    job.merge(theirs);
+    assert_eq!(job.events.len(), 3);
+    assert_eq!(job.prompt_id.as_deref(), Some("p1"));
+    assert_eq!(job.tenant.as_deref(), Some("acme"));
+    assert_eq!(job.state(), JobState::Failed);
+}
+
+#[test]
+fn test_fair_queue_takes_turns_across_keys() {
+    use comfyui_api_proxy::jobs::FairQueue;
+
+    let mut queue = FairQueue::new();
+    for i in 0..4 {
+        queue.push("sweeper", 1, format!("s{}", i), ());
+    }
+    queue.push("alice", 1, "a0".to_string(), ());
+    queue.push("bob", 2, "b0".to_string(), ());
+    queue.push("bob", 2, "b1".to_string(), ());
+    queue.push("bob", 2, "b2".to_string(), ());
+    let expected = ["s0", "a0", "b0", "b1", "s1", "b2", "s2", "s3"];
+    assert_eq!(queue.order(), expected);
+
+    // Cancelled prompts leave the rotation without disturbing it.
+    assert!(queue.remove("a0").is_some());
+    assert!(queue.remove("a0").is_none());
+    let mut sent = Vec::new();
+    while let Some((job_id, ())) = queue.pop() {
+        sent.push(job_id);
+    }
+    assert_eq!(sent, ["s0", "b0", "b1", "s1", "b2", "s2", "s3"]);
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn test_fair_queue_skips_prompts_that_cant_be_sent() {
+    use comfyui_api_proxy::jobs::FairQueue;
+
+    let mut queue = FairQueue::new();
+    queue.push("alice", 1, "a0".to_string(), "video");
+    queue.push("alice", 1, "a1".to_string(), "sdxl");
+    queue.push("bob", 1, "b0".to_string(), "video");
+    queue.push("bob", 1, "b1".to_string(), "sdxl");
+
+    // Blocked prompts keep their place for later.
+    assert_eq!(queue.pop_where(|w| *w != "video"), Some(("a1".to_string(), "sdxl")));
+    assert_eq!(queue.pop_where(|w| *w != "video"), Some(("b1".to_string(), "sdxl")));
+    assert_eq!(queue.pop_where(|w| *w != "video"), None);
+    assert_eq!(queue.order(), ["a0", "b0"]);
+    assert_eq!(queue.pop_where(|_| true), Some(("a0".to_string(), "video")));
+}
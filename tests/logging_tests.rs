@@ -0,0 +1,27 @@
+//! Logging settings and subscriber setup.
+#[test]
+fn test_logging_settings_parse() {
+    use comfyui_api_proxy::utils::logging::{log_filter, parse_rotation, LogFormat};
+
+    assert_eq!(LogFormat::parse("JSON"), Some(LogFormat::Json));
+    assert_eq!(LogFormat::parse(" text "), Some(LogFormat::Text));
+    assert_eq!(LogFormat::parse("yaml"), None);
+    assert!(parse_rotation("hourly").is_some());
+    assert!(parse_rotation("weekly").is_none());
+
+    assert!(log_filter(Some("info,comfyui_api_proxy=debug"), true).is_ok());
+    if std::env::var_os("RUST_LOG").is_none() {
+        assert!(log_filter(Some("info,=[bad"), false).unwrap_err().contains("LOG_LEVEL"));
+    }
+}
+
+#[test]
+fn test_init_logging_once() {
+    use comfyui_api_proxy::config::Config;
+    use comfyui_api_proxy::utils::logging::{init_logging, Console};
+
+    let config = Config::new().expect("Failed to load configuration");
+    assert!(init_logging(&config, "logging_tests", Console::Stderr, false).is_ok());
+    let err = init_logging(&config, "logging_tests", Console::Stderr, false).unwrap_err();
+    assert!(err.contains("already initialized"));
+}
@@ -3,7 +3,7 @@ use serde_json::json;
 
 #[test]
 fn test_construct_prompt() {
-    let mut constructor = PromptConstructor::new();
+    let constructor = PromptConstructor::new();
     let template = json!({
         "node1": {
             "inputs": {
@@ -31,4 +31,772 @@ fn test_construct_prompt() {
             }
         })
     );
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_placeholders_inside_strings() {
+    let constructor = PromptConstructor::new();
+    let template = json!({
+        "6": {"inputs": {"text": "a photo of {{subject}} in {{ style }} style, {{steps}} steps{{suffix}}"}},
+        "3": {"inputs": {"seed": "{{seed}}", "note": "tags: {{tags}}, hires: {{hires}}", "raw": "{{ unclosed"}}
+    });
+    let inputs = json!({"subject": "a fox", "style": "watercolor", "steps": 30, "suffix": null, "seed": 7, "tags": ["a", "b"], "hires": true});
+    let out = constructor.construct_prompt(&template, &inputs).unwrap();
+    assert_eq!(out["6"]["inputs"]["text"], "a photo of a fox in watercolor style, 30 steps");
+    assert_eq!(out["3"]["inputs"]["seed"], 7);
+    assert_eq!(out["3"]["inputs"]["note"], r#"tags: ["a","b"], hires: true"#);
+    assert_eq!(out["3"]["inputs"]["raw"], "{{ unclosed");
+
+    let err = constructor.construct_prompt(&json!({"text": "{{a}} and {{b}}"}), &json!({"a": 1})).unwrap_err();
+    assert!(err.to_string().contains("placeholder: b"));
+}
+
+#[test]
+fn test_validate_text_params() {
+    use comfyui_api_proxy::prompt::validator::{validate_text_params, TextLimits};
+
+    let limits = TextLimits { max_chars: 20, max_tokens: None };
+    assert!(validate_text_params(&json!({"text_positive": "misty forest"}), &limits).is_ok());
+
+    let err = validate_text_params(&json!({"text_negative": "   "}), &limits).unwrap_err();
+    assert_eq!(err.field, "text_negative");
+
+    let err = validate_text_params(&json!({"params": {"text": "bad\u{0007}bell"}}), &limits).unwrap_err();
+    assert_eq!(err.field, "params.text");
+
+    let err = validate_text_params(&json!({"text_positive": "a".repeat(21)}), &limits).unwrap_err();
+    assert!(err.reason.contains("exceeding"));
+}
+
+#[test]
+fn test_apply_default_negative_only_fills_empty_node() {
+    use comfyui_api_proxy::utils::prompt_ops::{apply_default_negative, TitlePatterns};
+
+    let mut graph = json!({
+        "1": {"class_type": "CLIPTextEncode", "inputs": {"text": "forest", "clip": ["4", 1]}},
+        "2": {"class_type": "KSampler", "inputs": {"positive": ["1", 0], "negative": ["3", 0]}},
+        "3": {"class_type": "CLIPTextEncode", "inputs": {"text": "", "clip": ["4", 1]}}
+    });
+    assert!(apply_default_negative(&mut graph, "blurry", &TitlePatterns::default()));
+    assert_eq!(graph["3"]["inputs"]["text"], "blurry");
+    assert_eq!(graph["1"]["inputs"]["text"], "forest");
+
+    assert!(!apply_default_negative(&mut graph, "lowres", &TitlePatterns::default()));
+    assert_eq!(graph["3"]["inputs"]["text"], "blurry");
+}
+
+#[test]
+fn test_resolution_rules() {
+    use comfyui_api_proxy::prompt::resolution::ResolutionRules;
+
+    let rules = ResolutionRules::default();
+    let mut payload = json!({"width": 1000, "ckpt_name": "SDXL/sd_xl_base_1.0.safetensors"});
+    assert!(rules.apply(&mut payload, None).is_err());
+
+    let mut payload = json!({"params": {"width": 1000, "height": 760}, "snap_resolution": true, "ckpt_name": "sd_xl_base.safetensors"});
+    let warnings = rules.apply(&mut payload, None).unwrap();
+    assert_eq!(payload["params"]["width"], 1024);
+    assert_eq!(payload["params"]["height"], 768);
+    assert_eq!(warnings.len(), 2);
+
+    // SD1.5 only needs multiples of 8
+    let mut payload = json!({"width": 520, "height": 512});
+    assert!(rules.apply(&mut payload, None).unwrap().is_empty());
+
+    // Huge values snap down to the maximum instead of overflowing
+    let mut payload = json!({"width": u64::MAX, "snap_resolution": true});
+    rules.apply(&mut payload, None).unwrap();
+    assert_eq!(payload["width"], 4096);
+}
+
+#[test]
+fn test_aspect_ratio_dimensions() {
+    use comfyui_api_proxy::prompt::resolution::{dimensions_for_aspect, ModelFamily, ResolutionRules};
+
+    assert_eq!(dimensions_for_aspect(16.0 / 9.0, 1024, ModelFamily::Sdxl), (1344, 768));
+    assert_eq!(dimensions_for_aspect(1.0, 512, ModelFamily::Sd15), (512, 512));
+
+    let rules = ResolutionRules::default();
+    let payload = json!({"aspect_ratio": "2:3", "ckpt_name": "sd_xl_base.safetensors"});
+    assert_eq!(rules.aspect_dimensions(&payload, None).unwrap(), Some((832, 1280)));
+
+    let conflicting = json!({"aspect_ratio": "1:1", "width": 512});
+    assert!(rules.aspect_dimensions(&conflicting, None).is_err());
+
+    for base_size in [0, 32, 8192, u64::MAX] {
+        let payload = json!({"aspect_ratio": "16:9", "base_size": base_size});
+        assert!(rules.aspect_dimensions(&payload, None).is_err(), "base_size {} accepted", base_size);
+    }
+}
+
+#[test]
+fn test_weight_normalization() {
+    use comfyui_api_proxy::prompt::weights::{apply_weight_normalization, convert, parse, WeightSyntax};
+
+    let spans = parse("a ((cat)) [dog]", WeightSyntax::A1111);
+    assert_eq!(spans.len(), 4);
+    assert_eq!(spans[1].weight, 1.21);
+    assert_eq!(spans[3].weight, 0.91);
+
+    assert_eq!(convert("((cat)), [dog]", WeightSyntax::A1111, WeightSyntax::Comfy), "(cat:1.21), (dog:0.91)");
+    assert_eq!(convert("(red (hat:1.5)) \\(x\\)", WeightSyntax::A1111, WeightSyntax::Comfy), "(red:1.1) (hat:1.65) \\(x\\)");
+    // ComfyUI has no [..] de-emphasis; converting to A1111 escapes the brackets.
+    assert_eq!(convert("[note] (cat:1.2)", WeightSyntax::Comfy, WeightSyntax::A1111), "\\[note\\] (cat:1.2)");
+
+    let mut payload = json!({"normalize_weights": true, "params": {"text_positive": "((sky))"}});
+    assert!(apply_weight_normalization(&mut payload).unwrap());
+    assert_eq!(payload["params"]["text_positive"], "(sky:1.21)");
+}
+
+#[test]
+fn test_template_extends() {
+    let dir = std::env::temp_dir().join(format!("templates_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("base.json"), json!({
+        "defaults": {"steps": 20, "text": "a cat"},
+        "3": {"class_type": "KSampler", "inputs": {"steps": "{{steps}}", "cfg": 7}},
+        "6": {"class_type": "CLIPTextEncode", "inputs": {"text": "{{text}}"}},
+        "9": {"class_type": "PreviewImage", "inputs": {}}
+    }).to_string()).unwrap();
+    std::fs::write(dir.join("loop_a.json"), json!({"extends": "loop_b"}).to_string()).unwrap();
+    std::fs::write(dir.join("loop_b.json"), json!({"extends": "loop_a"}).to_string()).unwrap();
+
+    let constructor = PromptConstructor::with_dir(dir.to_string_lossy());
+    let child = json!({
+        "extends": "base",
+        "defaults": {"steps": 40},
+        "3": {"inputs": {"cfg": 5}},
+        "9": null
+    });
+    let out = constructor.construct_prompt(&child, &json!({"text": "a dog"})).unwrap();
+    assert_eq!(out, json!({
+        "3": {"class_type": "KSampler", "inputs": {"steps": 40, "cfg": 5}},
+        "6": {"class_type": "CLIPTextEncode", "inputs": {"text": "a dog"}}
+    }));
+
+    let err = constructor.construct_prompt(&json!({"extends": "loop_a"}), &json!({})).unwrap_err();
+    assert!(err.to_string().contains("cycle: loop_a -> loop_b -> loop_a"), "{}", err);
+
+    // A long chain without a cycle is reported as too deep, not as a cycle.
+    for level in 0..20 {
+        std::fs::write(dir.join(format!("deep_{}.json", level)), json!({"extends": format!("deep_{}", level + 1)}).to_string()).unwrap();
+    }
+    let err = constructor.construct_prompt(&json!({"extends": "deep_0"}), &json!({})).unwrap_err();
+    assert!(err.to_string().contains("deeper than 16 levels"), "{}", err);
+    assert!(!err.to_string().contains("cycle"), "{}", err);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_extract_placeholders() {
+    let dir = std::env::temp_dir().join(format!("templates_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("base.json"), json!({
+        "defaults": {"steps": 20},
+        "3": {"inputs": {"steps": "{{steps}}", "seed": "{{seed}}"}}
+    }).to_string()).unwrap();
+
+    let constructor = PromptConstructor::with_dir(dir.to_string_lossy());
+    let child = json!({
+        "extends": "base",
+        "defaults": {"style": "watercolor"},
+        "6": {"inputs": {"text": "a photo of {{ subject }} in {{style}} style, {{subject}}"}}
+    });
+    let params = constructor.extract_placeholders(&child).unwrap();
+    let summary: Vec<(&str, Option<serde_json::Value>, bool)> = params.iter()
+        .map(|p| (p.name.as_str(), p.default.clone(), p.required))
+        .collect();
+    assert_eq!(summary, vec![
+        ("seed", None, true),
+        ("steps", Some(json!(20)), false),
+        ("style", Some(json!("watercolor")), false),
+        ("subject", None, true),
+    ]);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_unapplied_params_are_reported() {
+    use comfyui_api_proxy::utils::prompt_build::apply_overrides_from_payload;
+    use comfyui_api_proxy::utils::prompt_ops::TitlePatterns;
+
+    let mut root = json!({"prompt": {
+        "3": {"class_type": "KSampler", "inputs": {"seed": 1, "steps": 20}}
+    }});
+    let payload = json!({"seed": 7, "params": {"denoise": 0.4}, "sets": ["9.inputs.cfg=5"]});
+    let warnings = apply_overrides_from_payload(&mut root, &payload, &TitlePatterns::default()).unwrap();
+    assert_eq!(root["prompt"]["3"]["inputs"]["seed"], 7);
+    assert_eq!(warnings, vec![
+        "param 'denoise' matched no node input".to_string(),
+        "could not apply set to path: 9.inputs.cfg".to_string(),
+    ]);
+}
+
+#[test]
+fn test_text_targets_prefer_titles_over_id_order() {
+    use comfyui_api_proxy::utils::prompt_ops::{resolve_text_targets, TitlePatterns};
+
+    // No KSampler links to follow; id order alone would pick 6 as positive.
+    let graph = json!({
+        "6": {"class_type": "CLIPTextEncode", "_meta": {"title": "Negative Prompt"}, "inputs": {"text": ""}},
+        "7": {"class_type": "CLIPTextEncode", "_meta": {"title": "Positive Prompt"}, "inputs": {"text": ""}}
+    });
+    let (pos, neg) = resolve_text_targets(&graph, &TitlePatterns::default());
+    assert_eq!((pos.as_deref(), neg.as_deref()), (Some("7"), Some("6")));
+
+    let patterns = TitlePatterns::from_lists(Some("Prompt+, main"), Some("prompt-"));
+    let graph = json!({
+        "1": {"class_type": "CLIPTextEncode", "_meta": {"title": "Prompt-"}, "inputs": {"text": ""}},
+        "2": {"class_type": "CLIPTextEncode", "_meta": {"title": "Main"}, "inputs": {"text": ""}}
+    });
+    let (pos, neg) = resolve_text_targets(&graph, &patterns);
+    assert_eq!((pos.as_deref(), neg.as_deref()), (Some("2"), Some("1")));
+
+    // Ids are compared as numbers: 9 comes before 10.
+    let graph = json!({
+        "10": {"class_type": "CLIPTextEncode", "_meta": {"title": "Positive (refiner)"}, "inputs": {"text": ""}},
+        "9": {"class_type": "CLIPTextEncode", "_meta": {"title": "Positive"}, "inputs": {"text": ""}}
+    });
+    let (pos, _) = resolve_text_targets(&graph, &TitlePatterns::default());
+    assert_eq!(pos.as_deref(), Some("9"));
+
+    // Untitled encoders, and the KSampler whose links are followed, too.
+    let graph = json!({
+        "10": {"class_type": "CLIPTextEncode", "inputs": {"text": ""}},
+        "9": {"class_type": "CLIPTextEncode", "inputs": {"text": ""}}
+    });
+    let (pos, neg) = resolve_text_targets(&graph, &TitlePatterns::default());
+    assert_eq!((pos.as_deref(), neg.as_deref()), (Some("9"), Some("10")));
+    let graph = json!({
+        "10": {"class_type": "KSampler", "inputs": {"positive": ["12", 0], "negative": ["13", 0]}},
+        "9": {"class_type": "KSampler", "inputs": {"positive": ["6", 0], "negative": ["7", 0]}},
+        "6": {"class_type": "CLIPTextEncode", "inputs": {"text": ""}},
+        "7": {"class_type": "CLIPTextEncode", "inputs": {"text": ""}},
+        "12": {"class_type": "CLIPTextEncode", "inputs": {"text": ""}},
+        "13": {"class_type": "CLIPTextEncode", "inputs": {"text": ""}}
+    });
+    let (pos, neg) = resolve_text_targets(&graph, &TitlePatterns::default());
+    assert_eq!((pos.as_deref(), neg.as_deref()), (Some("6"), Some("7")));
+}
+
+#[test]
+fn test_model_name_for_uses_numeric_node_order() {
+    use comfyui_api_proxy::prompt::resolution::model_name_for;
+
+    let graph = json!({
+        "10": {"class_type": "CheckpointLoaderSimple", "inputs": {"ckpt_name": "refiner.safetensors"}},
+        "9": {"class_type": "CheckpointLoaderSimple", "inputs": {"ckpt_name": "base.safetensors"}}
+    });
+    assert_eq!(model_name_for(&json!({}), Some(&graph)).as_deref(), Some("base.safetensors"));
+    assert_eq!(model_name_for(&json!({"ckpt_name": "x"}), Some(&graph)).as_deref(), Some("x"));
+}
+
+#[test]
+fn test_split_combined_text() {
+    use comfyui_api_proxy::utils::prompt_build::split_combined_text;
+
+    let mut payload = json!({"text": "beautiful forest ### blurry, lowres"});
+    assert!(split_combined_text(&mut payload, "###").unwrap());
+    assert_eq!(payload, json!({"text_positive": "beautiful forest", "text_negative": "blurry, lowres"}));
+
+    let mut payload = json!({"params": {"text": "cat | dog", "text_negative": "ugly"}, "text_delimiter": "|"});
+    assert!(split_combined_text(&mut payload, "###").unwrap());
+    assert_eq!(payload["params"], json!({"text_positive": "cat", "text_negative": "ugly"}));
+
+    let mut payload = json!({"text": "no delimiter here"});
+    assert!(!split_combined_text(&mut payload, "###").unwrap());
+    assert_eq!(payload["text"], "no delimiter here");
+}
+
+#[test]
+fn test_variation_presets_target_img2img_sampler() {
+    use comfyui_api_proxy::prompt::variation::apply_variation;
+
+    let mut graph = json!({
+        "1": {"class_type": "LoadImage", "inputs": {"image": "in.png"}},
+        "2": {"class_type": "VAEEncode", "inputs": {"pixels": ["1", 0], "vae": ["4", 2]}},
+        "3": {"class_type": "KSampler", "inputs": {"latent_image": ["2", 0], "denoise": 1.0}},
+        "5": {"class_type": "EmptyLatentImage", "inputs": {"width": 512, "height": 512, "batch_size": 1}},
+        "6": {"class_type": "KSampler", "inputs": {"latent_image": ["5", 0], "denoise": 1.0}}
+    });
+    assert_eq!(apply_variation(&json!({"variation": "subtle"}), &mut graph).unwrap(), Some(0.275));
+    assert_eq!(graph["3"]["inputs"]["denoise"], 0.275);
+    assert_eq!(graph["6"]["inputs"]["denoise"], 1.0);
+
+    assert_eq!(apply_variation(&json!({}), &mut graph).unwrap(), None);
+    assert!(apply_variation(&json!({"variation": "wild"}), &mut graph).is_err());
+    assert!(apply_variation(&json!({"variation": "strong", "denoise": 0.3}), &mut graph).is_err());
+
+    let mut txt2img = json!({"6": graph["6"].clone(), "5": graph["5"].clone()});
+    assert!(apply_variation(&json!({"params": {"variation": "strong"}}), &mut txt2img).is_err());
+}
+
+#[test]
+fn test_upscale_params_route_to_upscale_nodes() {
+    use comfyui_api_proxy::utils::prompt_ops::{apply_params_map, upscale_model_factor, TitlePatterns};
+
+    assert_eq!(upscale_model_factor("4x-UltraSharp.pth"), Some(4.0));
+    assert_eq!(upscale_model_factor("RealESRGAN_x2plus.pth"), Some(2.0));
+    assert_eq!(upscale_model_factor("sharpen.pth"), None);
+
+    let mut graph = json!({
+        "5": {"class_type": "EmptyLatentImage", "inputs": {"width": 512, "height": 768, "batch_size": 1}},
+        "10": {"class_type": "LatentUpscale", "inputs": {"samples": ["5", 0], "upscale_method": "nearest-exact", "width": 1024, "height": 1024, "crop": "disabled"}},
+        "20": {"class_type": "UpscaleModelLoader", "inputs": {"model_name": "4x-UltraSharp.pth"}},
+        "21": {"class_type": "ImageUpscaleWithModel", "inputs": {"upscale_model": ["20", 0], "image": ["8", 0]}},
+        "22": {"class_type": "ImageScaleBy", "inputs": {"image": ["21", 0], "upscale_method": "lanczos", "scale_by": 0.5}}
+    });
+    let params = json!({"upscale_method": "bilinear", "upscale_by": 1.5});
+    let unapplied = apply_params_map(&mut graph, &params, &TitlePatterns::default());
+    assert!(unapplied.is_empty());
+    assert_eq!(graph["10"]["inputs"]["width"], 768);
+    assert_eq!(graph["10"]["inputs"]["height"], 1152);
+    assert_eq!(graph["10"]["inputs"]["upscale_method"], "bilinear");
+    assert_eq!(graph["22"]["inputs"]["scale_by"], 0.375);
+    assert_eq!(graph["22"]["inputs"]["upscale_method"], "bilinear");
+
+    // A two-stage workflow is brought to 2× overall, not 2× per stage.
+    let mut graph = json!({
+        "5": {"class_type": "EmptyLatentImage", "inputs": {"width": 512, "height": 512, "batch_size": 1}},
+        "3": {"class_type": "KSampler", "inputs": {"latent_image": ["5", 0]}},
+        "10": {"class_type": "LatentUpscaleBy", "inputs": {"samples": ["3", 0], "upscale_method": "nearest-exact", "scale_by": 1.5}},
+        "11": {"class_type": "KSampler", "inputs": {"latent_image": ["10", 0]}},
+        "12": {"class_type": "VAEDecode", "inputs": {"samples": ["11", 0]}},
+        "20": {"class_type": "UpscaleModelLoader", "inputs": {"model_name": "4x-UltraSharp.pth"}},
+        "21": {"class_type": "ImageUpscaleWithModel", "inputs": {"upscale_model": ["20", 0], "image": ["12", 0]}},
+        "22": {"class_type": "ImageScaleBy", "inputs": {"image": ["21", 0], "upscale_method": "lanczos", "scale_by": 0.5}}
+    });
+    apply_params_map(&mut graph, &json!({"upscale_by": 2.0}), &TitlePatterns::default());
+    assert_eq!(graph["10"]["inputs"]["scale_by"], 2.0);
+    assert_eq!(graph["22"]["inputs"]["scale_by"], 0.25);
+
+    let mut plain = json!({"3": {"class_type": "KSampler", "inputs": {"seed": 1}}});
+    assert_eq!(apply_params_map(&mut plain, &params, &TitlePatterns::default()), vec!["upscale_by", "upscale_method"]);
+}
+
+#[test]
+fn test_vae_name_sets_or_injects_loader() {
+    use comfyui_api_proxy::utils::prompt_build::apply_overrides_from_payload;
+    use comfyui_api_proxy::utils::prompt_ops::TitlePatterns;
+
+    let graph = json!({
+        "4": {"class_type": "CheckpointLoaderSimple", "inputs": {"ckpt_name": "model.safetensors"}},
+        "8": {"class_type": "VAEDecode", "inputs": {"samples": ["3", 0], "vae": ["4", 2]}}
+    });
+    let mut root = json!({"prompt": graph.clone()});
+    let warnings = apply_overrides_from_payload(&mut root, &json!({"vae_name": "vae.safetensors"}), &TitlePatterns::default()).unwrap();
+    assert_eq!(warnings, vec!["param 'vae_name' matched no node input".to_string()]);
+
+    let payload = json!({"vae_name": "vae.safetensors", "inject_vae": true});
+    let warnings = apply_overrides_from_payload(&mut root, &payload, &TitlePatterns::default()).unwrap();
+    assert!(warnings.is_empty());
+    assert_eq!(root["prompt"]["9"]["class_type"], "VAELoader");
+    assert_eq!(root["prompt"]["9"]["inputs"]["vae_name"], "vae.safetensors");
+    assert_eq!(root["prompt"]["8"]["inputs"]["vae"], json!(["9", 0]));
+
+    // An existing loader is reused, not duplicated.
+    let payload = json!({"vae_name": "other.safetensors", "inject_vae": true});
+    apply_overrides_from_payload(&mut root, &payload, &TitlePatterns::default()).unwrap();
+    assert_eq!(root["prompt"].as_object().unwrap().len(), 3);
+    assert_eq!(root["prompt"]["9"]["inputs"]["vae_name"], "other.safetensors");
+}
+
+#[test]
+fn test_clip_skip_injects_set_last_layer() {
+    use comfyui_api_proxy::utils::prompt_ops::apply_clip_skip;
+
+    let mut graph = json!({
+        "4": {"class_type": "CheckpointLoaderSimple", "inputs": {"ckpt_name": "anime.safetensors"}},
+        "6": {"class_type": "CLIPTextEncode", "inputs": {"text": "cat", "clip": ["4", 1]}},
+        "7": {"class_type": "CLIPTextEncode", "inputs": {"text": "", "clip": ["4", 1]}},
+        "3": {"class_type": "KSampler", "inputs": {"model": ["4", 0]}}
+    });
+    assert!(apply_clip_skip(&mut graph, 2));
+    assert_eq!(graph["8"]["class_type"], "CLIPSetLastLayer");
+    assert_eq!(graph["8"]["inputs"], json!({"clip": ["4", 1], "stop_at_clip_layer": -2}));
+    assert_eq!(graph["6"]["inputs"]["clip"], json!(["8", 0]));
+    assert_eq!(graph["7"]["inputs"]["clip"], json!(["8", 0]));
+    assert_eq!(graph["3"]["inputs"]["model"], json!(["4", 0]));
+
+    // Existing nodes are updated in place.
+    assert!(apply_clip_skip(&mut graph, -1));
+    assert_eq!(graph["8"]["inputs"]["stop_at_clip_layer"], -1);
+    assert_eq!(graph.as_object().unwrap().len(), 5);
+
+    let mut no_loader = json!({"3": {"class_type": "KSampler", "inputs": {}}});
+    assert!(!apply_clip_skip(&mut no_loader, 2));
+}
+
+#[test]
+fn test_image_param_sets_load_image_nodes() {
+    use comfyui_api_proxy::utils::prompt_ops::{apply_params_map, TitlePatterns};
+
+    let mut graph = json!({
+        "10": {"class_type": "LoadImage", "inputs": {"image": "example.png", "upload": "image"}},
+        "11": {"class_type": "ImageScale", "inputs": {"image": ["10", 0], "width": 512}},
+        "3": {"class_type": "KSampler", "inputs": {"denoise": 1.0}}
+    });
+    let params = json!({"image": "uploads/photo.png", "denoise": 0.6});
+    let unapplied = apply_params_map(&mut graph, &params, &TitlePatterns::default());
+    assert!(unapplied.is_empty());
+    assert_eq!(graph["10"]["inputs"]["image"], "uploads/photo.png");
+    // Linked image inputs are left alone.
+    assert_eq!(graph["11"]["inputs"]["image"], json!(["10", 0]));
+    assert_eq!(graph["3"]["inputs"]["denoise"], 0.6);
+
+    let mut txt2img = json!({"3": {"class_type": "KSampler", "inputs": {"denoise": 1.0}}});
+    assert_eq!(apply_params_map(&mut txt2img, &params, &TitlePatterns::default()), vec!["image"]);
+}
+
+#[test]
+fn test_init_image_goes_to_one_loader() {
+    use comfyui_api_proxy::utils::prompt_build::apply_overrides_from_payload;
+    use comfyui_api_proxy::utils::prompt_ops::{init_image_ids, load_image_ids, TitlePatterns};
+
+    // The photo is encoded; the ControlNet hint only goes through a preprocessor.
+    let graph = json!({
+        "9": {"class_type": "LoadImage", "inputs": {"image": "photo.png"}},
+        "10": {"class_type": "LoadImage", "inputs": {"image": "pose.png"}},
+        "12": {"class_type": "ImageScale", "inputs": {"image": ["9", 0]}},
+        "13": {"class_type": "VAEEncode", "inputs": {"pixels": ["12", 0]}},
+        "14": {"class_type": "OpenposePreprocessor", "inputs": {"image": ["10", 0]}}
+    });
+    assert_eq!(load_image_ids(&graph), ["9", "10"]);
+    assert_eq!(init_image_ids(&graph).unwrap(), ["9"]);
+    let mut root = json!({"prompt": graph.clone()});
+    apply_overrides_from_payload(&mut root, &json!({"image": "upload.png"}), &TitlePatterns::default()).unwrap();
+    assert_eq!(root["prompt"]["9"]["inputs"]["image"], "upload.png");
+    assert_eq!(root["prompt"]["10"]["inputs"]["image"], "pose.png");
+
+    // A title settles it; without one, two loaders and no encoder are ambiguous.
+    let mut titled = graph.clone();
+    titled["10"]["_meta"] = json!({"title": "Init Image"});
+    assert_eq!(init_image_ids(&titled).unwrap(), ["10"]);
+    let mut ambiguous = graph;
+    ambiguous.as_object_mut().unwrap().remove("13");
+    assert!(init_image_ids(&ambiguous).unwrap_err().contains("9, 10"));
+    let mut root = json!({"prompt": ambiguous});
+    assert!(apply_overrides_from_payload(&mut root, &json!({"image": "upload.png"}), &TitlePatterns::default()).is_err());
+}
+
+#[test]
+fn test_disable_nodes_bypass_and_mute() {
+    use comfyui_api_proxy::utils::prompt_build::apply_disable_nodes;
+
+    let graph = json!({
+        "3": {"class_type": "KSampler", "inputs": {"latent_image": ["5", 0]}},
+        "5": {"class_type": "EmptyLatentImage", "inputs": {"width": 512, "height": 512, "batch_size": 1}},
+        "8": {"class_type": "VAEDecode", "inputs": {"samples": ["10", 0], "vae": ["4", 2]}},
+        "9": {"class_type": "SaveImage", "inputs": {"images": ["8", 0]}},
+        "10": {"class_type": "LatentUpscale", "_meta": {"title": "Upscale"}, "inputs": {"samples": ["3", 0], "width": 1024, "height": 1024}}
+    });
+
+    let mut root = json!({"prompt": graph.clone()});
+    let payload = json!({"disable_nodes": ["title:Upscale", "99"]});
+    let warnings = apply_disable_nodes(&mut root, &payload).unwrap();
+    assert_eq!(warnings, vec!["disable_nodes: '99' matched no node".to_string()]);
+    assert!(root["prompt"].get("10").is_none());
+    assert_eq!(root["prompt"]["8"]["inputs"]["samples"], json!(["3", 0]));
+
+    let mut root = json!({"prompt": graph});
+    let payload = json!({"disable_nodes": ["10"], "disable_mode": "mute"});
+    let warnings = apply_disable_nodes(&mut root, &payload).unwrap();
+    assert_eq!(warnings, vec!["disable_nodes: also removed dependent nodes 8, 9".to_string()]);
+    let mut left: Vec<&String> = root["prompt"].as_object().unwrap().keys().collect();
+    left.sort();
+    assert_eq!(left, vec!["3", "5"]);
+
+    assert!(apply_disable_nodes(&mut root, &json!({"disable_nodes": "10"})).is_err());
+}
+
+#[test]
+fn test_upload_resized_to_workflow_latent() {
+    use comfyui_api_proxy::utils::image_resize::{resize_upload, ResizeMode};
+    use comfyui_api_proxy::utils::prompt_ops::latent_dimensions;
+
+    let graph = json!({
+        "3": {"class_type": "KSampler", "inputs": {"latent_image": ["5", 0]}},
+        "5": {"class_type": "EmptyLatentImage", "inputs": {"width": 64, "height": 32, "batch_size": 1}}
+    });
+    let (width, height) = latent_dimensions(&graph).unwrap();
+    assert_eq!((width, height), (64, 32));
+    assert_eq!(latent_dimensions(&json!({"1": {"class_type": "LoadImage", "inputs": {}}})), None);
+    let graph = json!({
+        "10": {"class_type": "EmptyLatentImage", "inputs": {"width": 128, "height": 128}},
+        "9": {"class_type": "EmptyLatentImage", "inputs": {"width": 64, "height": 32}}
+    });
+    assert_eq!(latent_dimensions(&graph), Some((64, 32)));
+
+    let mut source = Vec::new();
+    image::RgbImage::from_pixel(30, 30, image::Rgb([255, 255, 255]))
+        .write_to(&mut std::io::Cursor::new(&mut source), image::ImageOutputFormat::Jpeg(90))
+        .unwrap();
+
+    let padded = resize_upload(&source, "photo.jpg", width as u32, height as u32, ResizeMode::Pad).unwrap();
+    assert_eq!((padded.from, padded.to), ((30, 30), (64, 32)));
+    assert_eq!(padded.filename, "photo.jpg");
+    let decoded = image::load_from_memory(&padded.bytes).unwrap().to_rgb8();
+    assert_eq!(decoded.dimensions(), (64, 32));
+    // Centered 32x32 image with black bars on both sides.
+    assert!(decoded.get_pixel(2, 16)[0] < 30);
+    assert!(decoded.get_pixel(32, 16)[0] > 220);
+
+    let cropped = resize_upload(&source, "photo.webp", 64, 32, ResizeMode::Crop).unwrap();
+    assert_eq!(cropped.filename, "photo.png");
+    let decoded = image::load_from_memory_with_format(&cropped.bytes, image::ImageFormat::Png).unwrap();
+    assert_eq!((decoded.width(), decoded.height()), (64, 32));
+    assert!(decoded.to_rgb8().pixels().all(|p| p[0] > 220));
+
+    let same = resize_upload(&source, "photo.jpg", 30, 30, ResizeMode::Stretch).unwrap();
+    assert_eq!(same.bytes, source);
+}
+
+#[test]
+fn test_golden_render_helpers() {
+    use comfyui_api_proxy::prompt::testing::{assert_renders_to, diff_json, load_cases, Mismatch};
+
+    let template = json!({"defaults": {"steps": 20}, "3": {"inputs": {"seed": "{{seed}}", "steps": "{{steps}}"}}});
+    assert_renders_to(&template, &json!({"seed": 7}), &json!({"3": {"inputs": {"seed": 7, "steps": 20}}}));
+
+    let mismatches = diff_json(&json!({"a": [1, 2], "b/c": true}), &json!({"a": [1, 3, 4], "d": null}));
+    assert_eq!(mismatches.iter().map(|m| m.path.as_str()).collect::<Vec<_>>(), vec!["/a/1", "/a/2", "/b~1c", "/d"]);
+    assert_eq!(mismatches[0], Mismatch { path: "/a/1".to_string(), expected: Some(json!(2)), actual: Some(json!(3)) });
+    assert_eq!(mismatches[2].to_string(), "/b~1c: expected true, got <missing>");
+
+    // The shipped fixtures stay in sync with the constructor.
+    let constructor = PromptConstructor::with_dir("prompts");
+    for case in load_cases(std::path::Path::new("prompts/tests")).unwrap() {
+        let case = case.unwrap();
+        assert_eq!(case.render(&constructor).unwrap(), case.expected.clone().unwrap(), "{}", case.name());
+    }
+}
+
+#[test]
+fn test_load_cases_reports_every_bad_fixture() {
+    use comfyui_api_proxy::prompt::testing::load_cases;
+
+    let dir = std::env::temp_dir().join(format!("golden_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a_broken.json"), "{not json").unwrap();
+    std::fs::write(dir.join("b_good.json"), json!({"template": {"3": {}}, "expected": {"3": {}}}).to_string()).unwrap();
+    std::fs::write(dir.join("c_no_template.json"), json!({"inputs": {}}).to_string()).unwrap();
+
+    let cases = load_cases(&dir).unwrap();
+    assert_eq!(cases.len(), 3);
+    assert!(cases[0].as_ref().unwrap_err().contains("a_broken.json"));
+    assert_eq!(cases[1].as_ref().unwrap().name(), "b_good");
+    assert!(cases[2].as_ref().unwrap_err().contains("'template' must be"));
+    assert!(load_cases(&dir.join("missing")).is_err());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+#[should_panic(expected = "/3/inputs/seed: expected 8, got 7")]
+fn test_assert_renders_to_reports_differences() {
+    comfyui_api_proxy::prompt::testing::assert_renders_to(
+        &json!({"3": {"inputs": {"seed": "{{seed}}"}}}),
+        &json!({"seed": 7}),
+        &json!({"3": {"inputs": {"seed": 8}}}),
+    );
+}
+
+#[test]
+fn test_seed_journal_resolves_favorites() {
+    use comfyui_api_proxy::prompt::seeds::{primary_seed, seeds_in_graph, FavoriteSeed, SeedJournal};
+
+    let graph = json!({
+        "10": {"class_type": "FaceDetailer", "inputs": {"seed": 7}},
+        "3": {"class_type": "KSampler", "inputs": {"seed": 42, "steps": 20}},
+        "4": {"class_type": "CheckpointLoaderSimple", "inputs": {"ckpt_name": "sdxl.safetensors"}},
+    });
+    let seeds = seeds_in_graph(&graph);
+    assert_eq!(seeds.len(), 2);
+    assert_eq!(primary_seed(&graph, &seeds), Some(42));
+
+    // The first sampling stage wins over a refiner with a lower id, and a
+    // RandomNoise seed counts for the sampler it feeds.
+    let staged = json!({
+        "2": {"class_type": "KSamplerAdvanced", "inputs": {"noise_seed": 9, "latent_image": ["8", 0]}},
+        "8": {"class_type": "SamplerCustomAdvanced", "inputs": {"noise": ["12", 0], "latent_image": ["5", 0]}},
+        "12": {"class_type": "RandomNoise", "inputs": {"noise_seed": 42}},
+        "5": {"class_type": "EmptyLatentImage", "inputs": {"width": 512, "height": 512, "batch_size": 1}}
+    });
+    assert_eq!(primary_seed(&staged, &seeds_in_graph(&staged)), Some(42));
+
+    let path = std::env::temp_dir().join(format!("seeds_{}.json", uuid::Uuid::new_v4()));
+    let mut journal = SeedJournal::open(&path);
+    journal.save(FavoriteSeed {
+        name: "sunset".to_string(),
+        seed: 42,
+        seeds,
+        job_id: "job-1".to_string(),
+        prompt_id: Some("p1".to_string()),
+        workflow: Some("sdxlapi".to_string()),
+        output: Some("a.png".to_string()),
+        tenant: None,
+        saved_at_ms: 1,
+    }).unwrap();
+
+    // Saved favorites survive a reload and resolve at the top level and in params.
+    let journal = SeedJournal::open(&path);
+    let mut payload = json!({"seed": "favorite:sunset", "params": {"seed": "favorite:sunset", "steps": 30}});
+    journal.resolve(&mut payload, None).unwrap();
+    assert_eq!(payload["seed"], 42);
+    assert_eq!(payload["params"]["seed"], 42);
+    // Favorites are per tenant.
+    assert!(journal.resolve(&mut json!({"seed": "favorite:sunset"}), Some("team-a")).unwrap_err().contains("sunset"));
+
+    let mut journal = journal;
+    assert!(journal.remove(None, "sunset").unwrap());
+    assert!(!journal.remove(None, "sunset").unwrap());
+    assert!(SeedJournal::open(&path).list(None).is_empty());
+    std::fs::remove_file(&path).ok();
+
+    // A malformed journal is moved aside, not overwritten, on the next save.
+    let dir = std::env::temp_dir().join(format!("seeds_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("seeds.json");
+    std::fs::write(&path, "{\"favorites\": [oops").unwrap();
+    let mut journal = SeedJournal::open(&path);
+    assert!(journal.list(None).is_empty());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"favorites\": [oops");
+    journal.save(FavoriteSeed {
+        name: "dawn".to_string(),
+        seed: 1,
+        seeds: Default::default(),
+        job_id: "job-2".to_string(),
+        prompt_id: None,
+        workflow: None,
+        output: None,
+        tenant: None,
+        saved_at_ms: 2,
+    }).unwrap();
+    let aside: Vec<_> = std::fs::read_dir(&dir).unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with("seeds.json.invalid-"))
+        .collect();
+    assert_eq!(aside.len(), 1);
+    assert_eq!(std::fs::read_to_string(dir.join(&aside[0])).unwrap(), "{\"favorites\": [oops");
+    assert_eq!(SeedJournal::open(&path).list(None)[0].name, "dawn");
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_expand_queue_batch_merges_shared_settings() {
+    use comfyui_api_proxy::utils::prompt_build::{expand_queue_batch, MAX_QUEUE_BATCH};
+
+    let body = json!({
+        "workflow": "sdxlapi",
+        "seed": 5,
+        "params": {"steps": 30, "cfg": 7},
+        "sets": ["3.inputs.denoise=0.5"],
+        "prompts": [
+            {"seed": 1},
+            {"params": {"seed": 2, "cfg": 4}, "sets": ["3.inputs.denoise=0.7"]},
+            {"workflow": "flux"}
+        ]
+    });
+    let specs = expand_queue_batch(&body).unwrap();
+    assert_eq!(specs.len(), 3);
+    assert_eq!(specs[0], json!({"workflow": "sdxlapi", "seed": 1, "params": {"steps": 30, "cfg": 7}, "sets": ["3.inputs.denoise=0.5"]}));
+    // A prompt's params displace the shared top-level seed and merge into the shared params.
+    assert_eq!(specs[1].get("seed"), None);
+    assert_eq!(specs[1]["params"], json!({"steps": 30, "cfg": 4, "seed": 2}));
+    assert_eq!(specs[1]["sets"], json!(["3.inputs.denoise=0.5", "3.inputs.denoise=0.7"]));
+    assert_eq!(specs[2]["workflow"], "flux");
+    assert_eq!(specs[2]["seed"], 5);
+
+    // A bare array has no shared settings.
+    assert_eq!(expand_queue_batch(&json!([{"workflow": "a"}])).unwrap(), vec![json!({"workflow": "a"})]);
+    assert!(expand_queue_batch(&json!({"prompts": []})).is_err());
+    assert!(expand_queue_batch(&json!({"workflow": "a"})).is_err());
+    assert!(expand_queue_batch(&json!([1])).unwrap_err().contains("prompts[0]"));
+    assert!(expand_queue_batch(&json!(vec![json!({}); MAX_QUEUE_BATCH + 1])).is_err());
+}
+
+#[test]
+fn test_expand_sweep_takes_the_cartesian_product() {
+    use comfyui_api_proxy::prompt::sweep::expand_sweep;
+
+    let points = expand_sweep(&json!({
+        "workflow": "sdxlapi",
+        "cfg": [5, 7.5],
+        "params": {"sampler_name": ["euler", "dpmpp 2m"], "steps": 20},
+        "seed": {"count": 2, "start": 10}
+    })).unwrap();
+    let tags: Vec<&str> = points.iter().map(|p| p.tag.as_str()).collect();
+    assert_eq!(tags, [
+        "seed-10_cfg-5_sampler_name-euler", "seed-10_cfg-5_sampler_name-dpmpp-2m",
+        "seed-10_cfg-7.5_sampler_name-euler", "seed-10_cfg-7.5_sampler_name-dpmpp-2m",
+        "seed-11_cfg-5_sampler_name-euler", "seed-11_cfg-5_sampler_name-dpmpp-2m",
+        "seed-11_cfg-7.5_sampler_name-euler", "seed-11_cfg-7.5_sampler_name-dpmpp-2m",
+    ]);
+    assert_eq!(points[1].payload, json!({
+        "workflow": "sdxlapi",
+        "cfg": 5,
+        "params": {"sampler_name": "dpmpp 2m", "steps": 20},
+        "seed": 10,
+        "filename_suffix": "seed-10_cfg-5_sampler_name-dpmpp-2m"
+    }));
+    assert_eq!(points[1].values, json!({"seed": 10, "cfg": 5, "sampler_name": "dpmpp 2m"}).as_object().unwrap().clone());
+
+    assert!(expand_sweep(&json!({"workflow": "sdxlapi", "cfg": 7})).is_err());
+    assert!(expand_sweep(&json!({"cfg": []})).is_err());
+    assert!(expand_sweep(&json!({"cfg": {"count": 3}})).is_err());
+    assert!(expand_sweep(&json!({"cfg": [1, 2], "params": {"cfg": [3]}})).is_err());
+    let too_many: Vec<u64> = (0..11).collect();
+    assert!(expand_sweep(&json!({"cfg": too_many, "steps": too_many})).unwrap_err().contains("more than"));
+}
+
+#[test]
+fn test_read_batch_rows_from_csv_and_jsonl() {
+    use comfyui_api_proxy::utils::batch_file::{read_batch_rows, BatchFormat};
+    use std::path::Path;
+
+    assert_eq!(BatchFormat::from_path(Path::new("jobs.CSV")), Some(BatchFormat::Csv));
+    assert_eq!(BatchFormat::from_path(Path::new("jobs.ndjson")), Some(BatchFormat::Jsonl));
+    assert_eq!(BatchFormat::from_path(Path::new("jobs.txt")), None);
+
+    let csv = "seed, text_positive ,3.inputs.steps,filename_prefix\n5,\"a cat, sitting\",12,cats\n6,123,,\n";
+    let rows = read_batch_rows(csv, BatchFormat::Csv).unwrap();
+    assert_eq!(rows, vec![
+        json!({"seed": 5, "text_positive": "a cat, sitting", "filename_prefix": "cats", "sets": ["3.inputs.steps=12"]}),
+        // Empty cells are unset; text stays text.
+        json!({"seed": 6, "text_positive": "123"}),
+    ]);
+    assert!(read_batch_rows("seed,,cfg\n1,2,3\n", BatchFormat::Csv).unwrap_err().contains("column 2"));
+    assert!(read_batch_rows("seed,cfg\n1,2,3\n", BatchFormat::Csv).unwrap_err().starts_with("row 1"));
+
+    let jsonl = "{\"seed\": \"favorite:sunset\", \"groups\": {\"Refiner\": false}}\n\n{\"4.inputs.ckpt_name\": \"b.safetensors\", \"sets\": [\"3.inputs.cfg=4\"]}\n";
+    let rows = read_batch_rows(jsonl, BatchFormat::Jsonl).unwrap();
+    assert_eq!(rows[0], json!({"seed": "favorite:sunset", "groups": {"Refiner": false}}));
+    assert_eq!(rows[1], json!({"sets": ["3.inputs.cfg=4", "4.inputs.ckpt_name=b.safetensors"]}));
+    assert!(read_batch_rows("{}\n[1]\n", BatchFormat::Jsonl).unwrap_err().starts_with("row 2"));
+}
+
+#[tokio::test]
+async fn test_payload_without_prompt_or_workflow_uses_default_workflow() {
+    use comfyui_api_proxy::utils::prompt_build::resolve_prompt_root_from_payload;
+
+    let dir = std::env::temp_dir().join(format!("prompts_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("portrait.json"), json!({"3": {"class_type": "KSampler", "inputs": {"seed": 1}}}).to_string()).unwrap();
+    std::fs::write(dir.join("landscape.json"), json!({"4": {"class_type": "KSampler", "inputs": {"seed": 2}}}).to_string()).unwrap();
+    let prompts_dir = dir.to_string_lossy().to_string();
+
+    let text_only = json!({"text_positive": "a lighthouse"});
+    let root = resolve_prompt_root_from_payload(&text_only, &prompts_dir, Some("portrait")).await.unwrap();
+    assert_eq!(root["prompt"]["3"]["class_type"], "KSampler");
+    // Without DEFAULT_WORKFLOW the payload is refused.
+    let err = resolve_prompt_root_from_payload(&text_only, &prompts_dir, None).await.unwrap_err();
+    assert!(err.to_string().contains("DEFAULT_WORKFLOW"), "{}", err);
+
+    // A named workflow or an inline prompt takes precedence over the default.
+    let named = json!({"workflow": "landscape"});
+    let root = resolve_prompt_root_from_payload(&named, &prompts_dir, Some("portrait")).await.unwrap();
+    assert!(root["prompt"].get("4").is_some() && root["prompt"].get("3").is_none());
+    let inline = json!({"prompt": {"9": {"class_type": "SaveImage", "inputs": {}}}});
+    let root = resolve_prompt_root_from_payload(&inline, &prompts_dir, Some("portrait")).await.unwrap();
+    assert_eq!(root["prompt"]["9"]["class_type"], "SaveImage");
+    std::fs::remove_dir_all(&dir).ok();
+}